@@ -0,0 +1,169 @@
+//! Engine overhead benchmarks
+//!
+//! Every step runs on `NoopAgent`, which answers instantly, so these measure
+//! only the engine: scheduling and dispatch on large DAGs, the scheduling
+//! strategies, and fanning events out to handlers and subscribers.
+//!
+//! ```bash
+//! cargo bench --bench engine
+//! cargo bench --bench engine -- strategies   # one group
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pipeline::agent::noop::NoopAgent;
+use pipeline::core::config::PipelineConfig;
+use pipeline::{ExecutionEngine, Pipeline, SchedulingStrategy};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+/// Shape of a generated pipeline
+#[derive(Clone, Copy)]
+enum Shape {
+    /// Each step depends on the one before it
+    Chain,
+    /// Independent steps joined by a final step
+    FanIn,
+    /// Layers of `width` steps, each depending on the whole layer before
+    Layered { width: usize },
+}
+
+/// Pipeline of `steps` steps with the given shape
+fn pipeline(shape: Shape, steps: usize) -> Pipeline {
+    let mut yaml = String::from("name: \"Bench\"\nsteps:\n");
+    for i in 0..steps {
+        let depends_on: Vec<usize> = match shape {
+            Shape::Chain => i.checked_sub(1).into_iter().collect(),
+            Shape::FanIn if i + 1 == steps => (0..i).collect(),
+            Shape::FanIn => vec![],
+            Shape::Layered { width } => {
+                let layer = i / width;
+                if layer == 0 {
+                    vec![]
+                } else {
+                    ((layer - 1) * width..layer * width).collect()
+                }
+            }
+        };
+        writeln!(yaml, "  - id: \"s{i}\"\n    name: \"Step {i}\"\n    prompt: \"Do {i}\"").unwrap();
+        if !depends_on.is_empty() {
+            let ids: Vec<String> = depends_on.iter().map(|d| format!("\"s{d}\"")).collect();
+            writeln!(yaml, "    depends_on: [{}]", ids.join(", ")).unwrap();
+        }
+        yaml.push_str("    termination:\n      success_pattern: \"DONE\"\n");
+    }
+    let config = PipelineConfig::from_yaml(&yaml).expect("generated pipeline is valid");
+    Pipeline::from_config(&config)
+}
+
+/// Time `iters` runs of fresh copies of `template`, leaving the setup of
+/// each engine (by `build`) out of the measurement
+async fn time_runs<F>(iters: u64, template: &Pipeline, build: F) -> Duration
+where
+    F: Fn() -> ExecutionEngine<NoopAgent>,
+{
+    let mut total = Duration::ZERO;
+    for _ in 0..iters {
+        let engine = build();
+        // Handlers are registered by a spawned task; give it time to run
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let mut pipeline = template.clone();
+        let start = Instant::now();
+        engine.execute(&mut pipeline).await.expect("pipeline succeeds");
+        total += start.elapsed();
+    }
+    total
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap()
+}
+
+/// Dispatch overhead per step as DAGs grow to thousands of steps
+fn large_dags(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("large_dags");
+    group.sample_size(10);
+    for steps in [100, 1000, 2000] {
+        group.throughput(Throughput::Elements(steps as u64));
+        for (name, shape) in [
+            ("chain", Shape::Chain),
+            ("fan_in", Shape::FanIn),
+            ("layered", Shape::Layered { width: 10 }),
+        ] {
+            let template = pipeline(shape, steps);
+            group.bench_with_input(BenchmarkId::new(name, steps), &template, |b, template| {
+                b.to_async(&rt).iter_custom(|iters| {
+                    time_runs(iters, template, || {
+                        ExecutionEngine::new(NoopAgent::new(), SchedulingStrategy::Sequential)
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// The scheduling strategies on a wide pipeline, where most steps are ready
+/// at once
+fn strategies(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("strategies");
+    group.sample_size(10);
+    let steps = 1000;
+    let template = pipeline(Shape::Layered { width: 100 }, steps);
+    group.throughput(Throughput::Elements(steps as u64));
+    for (name, strategy) in [
+        ("sequential", SchedulingStrategy::Sequential),
+        ("parallel", SchedulingStrategy::Parallel),
+        ("limited_parallel_8", SchedulingStrategy::LimitedParallel(8)),
+    ] {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &strategy, |b, &strategy| {
+            b.to_async(&rt)
+                .iter_custom(|iters| time_runs(iters, &template, || ExecutionEngine::new(NoopAgent::new(), strategy)))
+        });
+    }
+    group.finish();
+}
+
+/// Cost of delivering every event to more handlers and subscribers
+fn event_fan_out(c: &mut Criterion) {
+    let rt = runtime();
+    let mut group = c.benchmark_group("event_fan_out");
+    group.sample_size(10);
+    let steps = 200;
+    let template = pipeline(Shape::Chain, steps);
+    group.throughput(Throughput::Elements(steps as u64));
+    for listeners in [0, 1, 8, 32] {
+        group.bench_with_input(BenchmarkId::new("handlers", listeners), &listeners, |b, &listeners| {
+            b.to_async(&rt).iter_custom(|iters| {
+                time_runs(iters, &template, move || {
+                    let engine = ExecutionEngine::new(NoopAgent::new(), SchedulingStrategy::Sequential);
+                    for _ in 0..listeners {
+                        engine.add_event_handler(|event| {
+                            std::hint::black_box(event);
+                        });
+                    }
+                    engine
+                })
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("subscribers", listeners), &listeners, |b, &listeners| {
+            b.to_async(&rt).iter_custom(|iters| {
+                time_runs(iters, &template, move || {
+                    let engine = ExecutionEngine::new(NoopAgent::new(), SchedulingStrategy::Sequential);
+                    for _ in 0..listeners {
+                        // Dropping the receiver unsubscribes, so drain it instead
+                        let mut events = engine.subscribe_filtered(|_| true);
+                        tokio::spawn(async move { while events.recv().await.is_some() {} });
+                    }
+                    engine
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, large_dags, strategies, event_fan_out);
+criterion_main!(benches);
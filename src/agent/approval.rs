@@ -0,0 +1,342 @@
+//! Operator approval of agent tool calls
+//!
+//! Pi executes tools on its own, so pi-peline cannot block a tool call before
+//! it starts. Instead, `ToolApprovalCallback` watches for completed tool call
+//! announcements (`ToolcallEnd`) whose tool is covered by a `ToolApprovalPolicy`,
+//! asks a `ToolApprover` for a decision and, on denial, requests that the
+//! running agent be aborted. The engine then retries the step with an
+//! instruction not to use the denied tool.
+
+use crate::agent::pi_events::AssistantMessageEvent;
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Set of tool names that require operator approval
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ToolApprovalPolicy {
+    tools: HashSet<String>,
+}
+
+impl ToolApprovalPolicy {
+    /// Create a policy requiring approval for the given tool names
+    pub fn new<I, S>(tools: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            tools: tools.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Policy covering pi's tools that change the workspace
+    ///
+    /// `bash` is included since its commands can do anything.
+    pub fn read_only() -> Self {
+        Self::new(["write", "edit", "bash"])
+    }
+
+    /// Check whether a tool requires approval
+    pub fn requires_approval(&self, tool_name: &str) -> bool {
+        self.tools.contains(tool_name)
+    }
+
+    /// Check whether the policy covers no tools at all
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+}
+
+/// Decides whether a tool call may proceed
+pub trait ToolApprover: Send + Sync {
+    /// Return `true` to allow the tool call, `false` to deny it
+    fn approve(&self, tool_name: &str, arguments: &Value) -> bool;
+
+    /// Who makes the decisions, recorded with each of them
+    fn name(&self) -> String;
+}
+
+/// Approver that denies every call, enforcing a policy without asking
+pub struct DenyAll;
+
+impl ToolApprover for DenyAll {
+    fn approve(&self, _tool_name: &str, _arguments: &Value) -> bool {
+        false
+    }
+
+    fn name(&self) -> String {
+        "deny-all".to_string()
+    }
+}
+
+/// Approver for steps that must not change the workspace: allows `bash`
+/// commands that only read (see `is_read_only_command`) and denies
+/// everything else
+pub struct ReadOnlyApprover;
+
+impl ToolApprover for ReadOnlyApprover {
+    fn approve(&self, tool_name: &str, arguments: &Value) -> bool {
+        tool_name == "bash"
+            && arguments
+                .get("command")
+                .and_then(Value::as_str)
+                .is_some_and(is_read_only_command)
+    }
+
+    fn name(&self) -> String {
+        "read-only".to_string()
+    }
+}
+
+/// Commands that only read, whatever their arguments
+const READ_ONLY_COMMANDS: &[&str] = &[
+    "cat", "cut", "du", "echo", "file", "grep", "head", "ls", "nl", "pwd", "rg", "stat", "tail", "wc", "which",
+];
+
+/// Git subcommands that only read
+const READ_ONLY_GIT: &[&str] = &["blame", "diff", "grep", "log", "ls-files", "rev-parse", "show", "status"];
+
+/// Whether a shell command only reads: every command in it (split at
+/// pipes, `;`, `&&` and `||`) is a known reading command, `find` without
+/// actions, or a reading `git` subcommand, and nothing is redirected to a
+/// file, substituted or run in the background
+///
+/// Anything not recognized counts as writing.
+pub fn is_read_only_command(command: &str) -> bool {
+    if command.contains(['>', '`', '\n']) || command.contains("$(") {
+        return false;
+    }
+    command
+        .split("&&")
+        .flat_map(|part| part.split("||"))
+        .flat_map(|part| part.split([';', '|', '&']))
+        .all(|part| {
+            let words: Vec<&str> = part.split_whitespace().collect();
+            match words.as_slice() {
+                [] => false,
+                ["find", args @ ..] => !args
+                    .iter()
+                    .any(|arg| ["-delete", "-exec", "-execdir", "-ok", "-okdir"].contains(arg) || arg.starts_with("-fprint")),
+                ["git", subcommand, args @ ..] => {
+                    READ_ONLY_GIT.contains(subcommand) && !args.iter().any(|arg| arg.starts_with("--output"))
+                }
+                [program, ..] => READ_ONLY_COMMANDS.contains(program),
+            }
+        })
+}
+
+/// A recorded approval decision (audit trail entry)
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalDecision {
+    /// Name of the tool the agent wanted to run
+    pub tool_name: String,
+
+    /// Arguments of the tool call
+    pub arguments: Value,
+
+    /// Whether the operator allowed the call
+    pub approved: bool,
+
+    /// Who decided (see `ToolApprover::name`)
+    pub approver: String,
+
+    /// When the decision was made
+    pub decided_at: DateTime<Utc>,
+}
+
+/// Progress callback that gates tool calls through a `ToolApprover`
+///
+/// All events are forwarded to the optional inner callback. Once a tool call
+/// is denied, `abort_reason()` reports the denial so the agent client stops
+/// the running subprocess.
+pub struct ToolApprovalCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    policy: &'a ToolApprovalPolicy,
+    approver: &'a dyn ToolApprover,
+    decisions: Mutex<Vec<ApprovalDecision>>,
+    denied_tool: Mutex<Option<String>>,
+}
+
+impl<'a> ToolApprovalCallback<'a> {
+    /// Create a new approval callback wrapping an optional inner callback
+    pub fn new(
+        inner: Option<&'a dyn ProgressCallback>,
+        policy: &'a ToolApprovalPolicy,
+        approver: &'a dyn ToolApprover,
+    ) -> Self {
+        Self {
+            inner,
+            policy,
+            approver,
+            decisions: Mutex::new(Vec::new()),
+            denied_tool: Mutex::new(None),
+        }
+    }
+
+    /// Get all decisions made so far
+    pub fn decisions(&self) -> Vec<ApprovalDecision> {
+        self.decisions.lock().unwrap().clone()
+    }
+
+    /// Get the name of the tool that was denied (if any)
+    pub fn denied_tool(&self) -> Option<String> {
+        self.denied_tool.lock().unwrap().clone()
+    }
+}
+
+impl ProgressCallback for ToolApprovalCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        if let PiJsonEvent::MessageUpdate {
+            assistant_message_event: Some(AssistantMessageEvent::ToolcallEnd { tool_call, .. }),
+            ..
+        } = event
+        {
+            if self.policy.requires_approval(&tool_call.name) && self.denied_tool().is_none() {
+                let approved = self.approver.approve(&tool_call.name, &tool_call.arguments);
+                self.decisions.lock().unwrap().push(ApprovalDecision {
+                    tool_name: tool_call.name.clone(),
+                    arguments: tool_call.arguments.clone(),
+                    approved,
+                    approver: self.approver.name(),
+                    decided_at: Utc::now(),
+                });
+                if !approved {
+                    *self.denied_tool.lock().unwrap() = Some(tool_call.name.clone());
+                }
+            }
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.denied_tool()
+            .map(|tool| format!("Tool call '{}' denied by operator", tool))
+            .or_else(|| self.inner.and_then(|inner| inner.abort_reason()))
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::pi_events::{Message, ToolCall};
+    use serde_json::json;
+
+    struct FixedApprover(bool);
+
+    impl ToolApprover for FixedApprover {
+        fn approve(&self, _tool_name: &str, _arguments: &Value) -> bool {
+            self.0
+        }
+
+        fn name(&self) -> String {
+            "fixed".to_string()
+        }
+    }
+
+    fn toolcall_end(tool_name: &str) -> PiJsonEvent {
+        PiJsonEvent::MessageUpdate {
+            assistant_message_event: Some(AssistantMessageEvent::ToolcallEnd {
+                content_index: 0,
+                tool_call: ToolCall {
+                    tool_type: "toolCall".to_string(),
+                    id: "call_1".to_string(),
+                    name: tool_name.to_string(),
+                    arguments: json!({"command": "rm -rf target"}),
+                },
+                partial: Message {
+                    role: "assistant".to_string(),
+                    usage: None,
+                    content: vec![],
+                },
+            }),
+            message: None,
+        }
+    }
+
+    #[test]
+    fn test_denied_tool_requests_abort() {
+        let policy = ToolApprovalPolicy::new(["bash"]);
+        let approver = FixedApprover(false);
+        let callback = ToolApprovalCallback::new(None, &policy, &approver);
+
+        callback.on_event(&toolcall_end("bash"));
+
+        assert_eq!(callback.denied_tool(), Some("bash".to_string()));
+        assert!(callback.abort_reason().unwrap().contains("bash"));
+        let decisions = callback.decisions();
+        assert_eq!(decisions.len(), 1);
+        assert!(!decisions[0].approved);
+        assert_eq!(decisions[0].approver, "fixed");
+    }
+
+    #[test]
+    fn test_tools_outside_policy_are_not_reviewed() {
+        let policy = ToolApprovalPolicy::new(["bash"]);
+        let approver = FixedApprover(false);
+        let callback = ToolApprovalCallback::new(None, &policy, &approver);
+
+        callback.on_event(&toolcall_end("read"));
+
+        assert!(callback.decisions().is_empty());
+        assert!(callback.abort_reason().is_none());
+    }
+
+    #[test]
+    fn test_read_only_commands() {
+        for command in [
+            "ls -la src",
+            "cat Cargo.toml | head -20",
+            "git status && git diff HEAD~1 -- src",
+            "rg -n 'fn main' src || echo none",
+            "find . -name '*.rs'",
+        ] {
+            assert!(is_read_only_command(command), "{}", command);
+        }
+        for command in [
+            "rm -rf target",
+            "echo hi > notes.txt",
+            "cat $(rm -rf target)",
+            "ls; touch x",
+            "ls & rm x",
+            "find . -name '*.tmp' -delete",
+            "git commit -am wip",
+            "git diff --output=patch.diff",
+            "sed -i s/a/b/ file",
+            "",
+        ] {
+            assert!(!is_read_only_command(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_read_only_approver_allows_reading_bash_only() {
+        let approve = |tool: &str, command: &str| ReadOnlyApprover.approve(tool, &json!({ "command": command }));
+        assert!(approve("bash", "git log --oneline"));
+        assert!(!approve("bash", "cargo build"));
+        assert!(!approve("write", "ls"));
+        assert!(!approve("edit", "ls"));
+    }
+
+    #[test]
+    fn test_approved_tool_is_recorded() {
+        let policy = ToolApprovalPolicy::new(["write", "bash"]);
+        let approver = FixedApprover(true);
+        let callback = ToolApprovalCallback::new(None, &policy, &approver);
+
+        callback.on_event(&toolcall_end("write"));
+
+        assert!(callback.abort_reason().is_none());
+        assert!(callback.decisions()[0].approved);
+    }
+}
@@ -11,6 +11,15 @@ pub struct AgentClientConfig {
 
     /// Timeout for requests in seconds
     pub timeout_secs: u64,
+
+    /// Extra arguments passed to pi
+    pub args: Vec<String>,
+
+    /// Fail on the first event type this version does not understand
+    pub strict_events: bool,
+
+    /// Longest pi event line kept in full, in bytes
+    pub max_event_bytes: usize,
 }
 
 impl Default for AgentClientConfig {
@@ -18,6 +27,9 @@ impl Default for AgentClientConfig {
         Self {
             endpoint: None,
             timeout_secs: 10800,
+            args: Vec::new(),
+            strict_events: false,
+            max_event_bytes: crate::agent::event_reader::DEFAULT_MAX_EVENT_BYTES,
         }
     }
 }
@@ -39,6 +51,21 @@ impl AgentClientConfig {
         self.timeout_secs = timeout_secs;
         self
     }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn with_strict_events(mut self, strict_events: bool) -> Self {
+        self.strict_events = strict_events;
+        self
+    }
+
+    pub fn with_max_event_bytes(mut self, max_event_bytes: usize) -> Self {
+        self.max_event_bytes = max_event_bytes;
+        self
+    }
 }
 
 
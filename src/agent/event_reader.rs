@@ -0,0 +1,219 @@
+//! Length-aware reading of pi's JSON event lines
+//!
+//! Pi writes one JSON event per line, and a single event can be huge (a tool
+//! that printed a whole log file). `EventLineReader` reads lines
+//! incrementally and keeps at most `max_bytes` of each, plus a short tail,
+//! so an oversized event costs bounded memory. Oversized tool events are
+//! rebuilt from what was kept, with the result replaced by a marker; other
+//! oversized events are skipped.
+
+use crate::agent::PiJsonEvent;
+use regex::Regex;
+use serde_json::Value;
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// Default limit on the size of a single event line
+pub const DEFAULT_MAX_EVENT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Bytes kept from the end of an oversized line (fields after the result)
+const TAIL_BYTES: usize = 4096;
+
+/// Bytes of a truncated tool result shown before the marker
+const RESULT_PREVIEW_BYTES: usize = 2048;
+
+/// One line of output, possibly cut short
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventLine {
+    /// The line, or its first `max_bytes` if it was longer
+    pub text: String,
+    /// The last bytes of an oversized line; empty otherwise
+    pub tail: String,
+    /// Length of the whole line in bytes
+    pub total_bytes: usize,
+}
+
+impl EventLine {
+    /// Whether part of the line was dropped
+    pub fn is_truncated(&self) -> bool {
+        self.total_bytes > self.text.len()
+    }
+
+    /// Rebuild a tool event from a truncated line, with its result replaced
+    /// by a preview and a truncation marker
+    ///
+    /// Returns `None` for other event types, which can't be rebuilt from
+    /// part of their JSON.
+    pub fn truncated_event(&self) -> Option<PiJsonEvent> {
+        let event_type = string_field(&self.text, "type")?;
+        let tool_call_id = string_field(&self.text, "toolCallId").unwrap_or_default();
+        let tool_name = string_field(&self.text, "toolName").unwrap_or_default();
+
+        match event_type.as_str() {
+            "tool_execution_end" => Some(PiJsonEvent::ToolExecutionEnd {
+                tool_call_id,
+                tool_name,
+                result: self.result_marker("result"),
+                is_error: is_error_regex().is_match(&self.tail),
+            }),
+            "tool_execution_update" => Some(PiJsonEvent::ToolExecutionUpdate {
+                tool_call_id,
+                tool_name,
+                args: Value::Null,
+                partial_result: self.result_marker("partialResult"),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The start of the named result field followed by a truncation marker
+    fn result_marker(&self, field: &str) -> Value {
+        let start = self
+            .text
+            .find(&format!("\"{}\":", field))
+            .map_or(self.text.len(), |at| at + field.len() + 3);
+        let mut end = (start + RESULT_PREVIEW_BYTES).min(self.text.len());
+        while !self.text.is_char_boundary(end) {
+            end -= 1;
+        }
+        Value::String(format!(
+            "{}\n[... truncated: event was {} bytes, over the {} byte limit ...]",
+            &self.text[start..end],
+            self.total_bytes,
+            self.text.len()
+        ))
+    }
+}
+
+/// Reads lines from pi's stdout, keeping at most `max_bytes` of each
+pub struct EventLineReader<R> {
+    inner: R,
+    max_bytes: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> EventLineReader<R> {
+    /// Read lines from `inner`, cutting each at `max_bytes`
+    pub fn new(inner: R, max_bytes: usize) -> Self {
+        Self { inner, max_bytes }
+    }
+
+    /// Next line without its line ending, or `None` at end of output
+    pub async fn next_line(&mut self) -> std::io::Result<Option<EventLine>> {
+        let mut head = Vec::new();
+        let mut tail = Vec::new();
+        let mut total_bytes = 0;
+        let mut read_any = false;
+
+        loop {
+            let buf = self.inner.fill_buf().await?;
+            if buf.is_empty() {
+                if !read_any {
+                    return Ok(None);
+                }
+                break;
+            }
+            read_any = true;
+
+            let newline = buf.iter().position(|&b| b == b'\n');
+            let chunk = &buf[..newline.unwrap_or(buf.len())];
+            total_bytes += chunk.len();
+
+            let room = self.max_bytes.saturating_sub(head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..room]);
+            if total_bytes > self.max_bytes {
+                tail.extend_from_slice(chunk);
+                if tail.len() > 2 * TAIL_BYTES {
+                    tail.drain(..tail.len() - TAIL_BYTES);
+                }
+            }
+
+            let consumed = newline.map_or(buf.len(), |at| at + 1);
+            self.inner.consume(consumed);
+            if newline.is_some() {
+                break;
+            }
+        }
+
+        if head.last() == Some(&b'\r') && total_bytes == head.len() {
+            head.pop();
+            total_bytes -= 1;
+        }
+        let tail = &tail[tail.len().saturating_sub(TAIL_BYTES)..];
+        Ok(Some(EventLine {
+            text: String::from_utf8_lossy(&head).into_owned(),
+            tail: String::from_utf8_lossy(tail).into_owned(),
+            total_bytes,
+        }))
+    }
+}
+
+/// First string value of `key` in (possibly incomplete) JSON text
+fn string_field(text: &str, key: &str) -> Option<String> {
+    let pattern = format!(r#""{}"\s*:\s*"((?:[^"\\]|\\.)*)""#, regex::escape(key));
+    let captures = Regex::new(&pattern).ok()?.captures(text)?;
+    serde_json::from_str(&format!("\"{}\"", &captures[1])).ok()
+}
+
+fn is_error_regex() -> &'static Regex {
+    static IS_ERROR: OnceLock<Regex> = OnceLock::new();
+    IS_ERROR.get_or_init(|| Regex::new(r#""isError"\s*:\s*true"#).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read_all(input: &[u8], max_bytes: usize) -> Vec<EventLine> {
+        let mut reader = EventLineReader::new(input, max_bytes);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            lines.push(line);
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_reads_lines_within_limit() {
+        let lines = read_all(b"{\"type\":\"agent_start\"}\r\n\n{\"type\":\"agent_end\"}", 64).await;
+        let texts: Vec<_> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, vec![r#"{"type":"agent_start"}"#, "", r#"{"type":"agent_end"}"#]);
+        assert!(lines.iter().all(|l| !l.is_truncated()));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_tool_result_is_truncated_with_marker() {
+        let output = "x".repeat(100_000);
+        let event = format!(
+            r#"{{"type":"tool_execution_end","toolCallId":"call_1","toolName":"bash","result":{{"content":[{{"type":"text","text":"{}"}}]}},"isError":true}}"#,
+            output
+        );
+        let input = format!("{}\n{{\"type\":\"agent_end\",\"messages\":[]}}\n", event);
+        let lines = read_all(input.as_bytes(), 1024).await;
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].is_truncated());
+        assert_eq!(lines[0].text.len(), 1024);
+        assert_eq!(lines[0].total_bytes, event.len());
+        assert!(!lines[1].is_truncated());
+
+        match lines[0].truncated_event() {
+            Some(PiJsonEvent::ToolExecutionEnd { tool_call_id, tool_name, result, is_error }) => {
+                assert_eq!(tool_call_id, "call_1");
+                assert_eq!(tool_name, "bash");
+                assert!(is_error);
+                let result = result.as_str().unwrap();
+                assert!(result.starts_with(r#"{"content":"#), "{}", result);
+                assert!(result.contains(&format!("truncated: event was {} bytes", event.len())));
+            }
+            other => panic!("expected tool_execution_end, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_other_oversized_events_are_not_rebuilt() {
+        let input = format!(r#"{{"type":"message_end","message":{{"content":"{}"}}}}"#, "y".repeat(5000));
+        let lines = read_all(input.as_bytes(), 100).await;
+        assert!(lines[0].is_truncated());
+        assert!(lines[0].truncated_event().is_none());
+    }
+}
@@ -0,0 +1,469 @@
+//! Failure injection for chaos-testing pipelines
+//!
+//! `FaultyAgent` wraps another `AgentExecutor` and, at configurable rates,
+//! injects one of the failures seen in practice into a run's event stream:
+//! the agent going quiet until the caller's timeout fires, stream events
+//! lost as unparseable, the stream ending early, or pi exiting with a
+//! non-zero status partway through. Callbacks see exactly the events that
+//! got through, and the response text is rebuilt from them, so users can
+//! check that their termination, retry and failure-handler configuration
+//! copes with flaky runs.
+//!
+//! Faults need a stream, so the wrapped agent always runs in streaming mode.
+
+use crate::agent::pi_events::AssistantMessageEvent;
+use crate::agent::{AgentExecutor, AgentError, AgentResponse, PiJsonEvent, ProgressCallback};
+use async_trait::async_trait;
+use std::str::FromStr;
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+/// Chance of each event being lost in a run with a `Malformed` fault
+const MALFORMED_LOSS_RATE: f64 = 0.25;
+
+/// Chance of the stream stopping at each event in a run with a `Timeout`,
+/// `Truncate` or `Exit` fault
+const CUT_RATE: f64 = 0.125;
+
+/// Fault rates (each 0.0 to 1.0) for a `FaultyAgent`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Stop the stream and hang until the caller's timeout fires, as when
+    /// the agent stops responding
+    pub timeout_rate: f64,
+
+    /// Lose stream events, as when they fail to parse
+    pub malformed_rate: f64,
+
+    /// Stop the stream before the agent finishes, as when it ends early
+    pub truncate_rate: f64,
+
+    /// Stop the stream and fail, as when pi exits with a non-zero status
+    pub exit_rate: f64,
+
+    /// Seed for reproducible fault sequences
+    pub seed: Option<u64>,
+}
+
+/// A single injected fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Timeout,
+    Malformed,
+    Truncate,
+    Exit,
+}
+
+impl ChaosConfig {
+    /// Use the same rate for every fault kind
+    pub fn uniform(rate: f64) -> Self {
+        Self {
+            timeout_rate: rate,
+            malformed_rate: rate,
+            truncate_rate: rate,
+            exit_rate: rate,
+            seed: None,
+        }
+    }
+
+    /// Set the random seed
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    fn total_rate(&self) -> f64 {
+        self.timeout_rate + self.malformed_rate + self.truncate_rate + self.exit_rate
+    }
+
+    /// Pick the fault (if any) for a uniform random roll in `[0, 1)`
+    fn fault_for(&self, roll: f64) -> Option<Fault> {
+        let mut threshold = 0.0;
+        for (rate, fault) in [
+            (self.timeout_rate, Fault::Timeout),
+            (self.malformed_rate, Fault::Malformed),
+            (self.truncate_rate, Fault::Truncate),
+            (self.exit_rate, Fault::Exit),
+        ] {
+            threshold += rate;
+            if roll < threshold {
+                return Some(fault);
+            }
+        }
+        None
+    }
+}
+
+/// Parses either a single rate applied to every fault (`0.2`) or a
+/// comma-separated list of `kind=rate` pairs (`timeout=0.1,exit=0.05`).
+impl FromStr for ChaosConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_rate = |value: &str| -> Result<f64, String> {
+            let rate: f64 = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid chaos rate: {}", value))?;
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("Chaos rate must be between 0 and 1: {}", value));
+            }
+            Ok(rate)
+        };
+
+        let config = if s.contains('=') {
+            let mut config = ChaosConfig::default();
+            for pair in s.split(',') {
+                let (kind, value) = pair
+                    .split_once('=')
+                    .ok_or_else(|| format!("Invalid chaos setting: {}", pair))?;
+                let rate = parse_rate(value)?;
+                match kind.trim() {
+                    "timeout" => config.timeout_rate = rate,
+                    "malformed" => config.malformed_rate = rate,
+                    "truncate" => config.truncate_rate = rate,
+                    "exit" => config.exit_rate = rate,
+                    other => {
+                        return Err(format!(
+                            "Unknown chaos fault '{}' (expected timeout, malformed, truncate or exit)",
+                            other
+                        ))
+                    }
+                }
+            }
+            config
+        } else {
+            ChaosConfig::uniform(parse_rate(s)?)
+        };
+
+        if config.total_rate() > 1.0 {
+            return Err("Chaos rates must add up to at most 1".to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// Agent wrapper that randomly injects failures
+pub struct FaultyAgent<A> {
+    inner: A,
+    config: ChaosConfig,
+    rng: Mutex<u64>,
+}
+
+impl<A: AgentExecutor> FaultyAgent<A> {
+    /// Wrap an agent with the given fault rates
+    pub fn new(inner: A, config: ChaosConfig) -> Self {
+        let seed = config.seed.unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or_default()
+        });
+        Self {
+            inner,
+            config,
+            rng: Mutex::new(seed),
+        }
+    }
+
+    /// Next pseudo-random number in `[0, 1)`
+    fn next_f64(&self) -> f64 {
+        next_f64(&mut self.rng.lock().unwrap())
+    }
+
+    /// Run the wrapped agent with a fault injected into its event stream
+    async fn run_with_fault(
+        &self,
+        prompt: &str,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, AgentError> {
+        let fault = self.config.fault_for(self.next_f64());
+        let Some(fault) = fault else {
+            return self.inner.execute_streaming(prompt, callback).await;
+        };
+        warn!("Chaos: injecting {:?} fault", fault);
+
+        let seed = self.next_f64().to_bits();
+        let stream = FaultyStream::new(callback, fault, seed);
+        let mut response = self.inner.execute_streaming(prompt, Some(&stream)).await?;
+        if stream.lost_text() {
+            response.content = stream.text();
+        }
+
+        match fault {
+            // Nothing more arrives; the caller's timeout ends the run
+            Fault::Timeout => std::future::pending().await,
+            Fault::Exit => Err(AgentError::Api(
+                "pi exited with non-zero status: Some(1) (injected by --chaos)".to_string(),
+            )),
+            Fault::Truncate => {
+                response.done = false;
+                Ok(response)
+            }
+            Fault::Malformed => Ok(response),
+        }
+    }
+}
+
+/// Next pseudo-random number in `[0, 1)` from a SplitMix64 state
+fn next_f64(state: &mut u64) -> f64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Callback standing between the wrapped agent and the caller's callback,
+/// passing on only the events a fault lets through
+struct FaultyStream<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    fault: Fault,
+    state: Mutex<StreamState>,
+}
+
+#[derive(Default)]
+struct StreamState {
+    rng: u64,
+    /// The stream has stopped; later events are lost
+    cut: bool,
+    /// Text of the events passed on
+    text: String,
+    /// Some text was lost
+    lost_text: bool,
+}
+
+impl<'a> FaultyStream<'a> {
+    fn new(inner: Option<&'a dyn ProgressCallback>, fault: Fault, seed: u64) -> Self {
+        Self {
+            inner,
+            fault,
+            state: Mutex::new(StreamState { rng: seed, ..Default::default() }),
+        }
+    }
+
+    fn text(&self) -> String {
+        self.state.lock().unwrap().text.clone()
+    }
+
+    fn lost_text(&self) -> bool {
+        self.state.lock().unwrap().lost_text
+    }
+
+    /// Whether the fault loses `event`
+    fn loses(&self, state: &mut StreamState, event: &PiJsonEvent) -> bool {
+        if state.cut {
+            return true;
+        }
+        let roll = next_f64(&mut state.rng);
+        match self.fault {
+            Fault::Malformed => roll < MALFORMED_LOSS_RATE,
+            // A stream that stops never gets to the agent's end
+            _ => {
+                state.cut = roll < CUT_RATE || matches!(event, PiJsonEvent::AgentEnd);
+                state.cut
+            }
+        }
+    }
+}
+
+impl ProgressCallback for FaultyStream<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        let delta = match event {
+            PiJsonEvent::MessageUpdate {
+                assistant_message_event: Some(AssistantMessageEvent::TextDelta { delta, .. }),
+                ..
+            } => Some(delta.as_str()),
+            _ => None,
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if self.loses(&mut state, event) {
+                debug!("Chaos: losing {:?} event", self.fault);
+                state.lost_text |= delta.is_some();
+                return;
+            }
+            state.text.push_str(delta.unwrap_or_default());
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.inner.and_then(|inner| inner.abort_reason())
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[async_trait]
+impl<A: AgentExecutor> AgentExecutor for FaultyAgent<A> {
+    async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+        self.run_with_fault(prompt, None).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        prompt: &str,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.run_with_fault(prompt, callback).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Streams the prompt back one word at a time
+    struct EchoAgent;
+
+    #[async_trait]
+    impl AgentExecutor for EchoAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            self.execute_streaming(prompt, None).await
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            callback: Option<&dyn ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            if let Some(callback) = callback {
+                callback.on_event(&PiJsonEvent::AgentStart);
+                for word in prompt.split_inclusive(' ') {
+                    callback.on_event(&PiJsonEvent::MessageUpdate {
+                        assistant_message_event: Some(AssistantMessageEvent::TextDelta {
+                            content_index: 0,
+                            delta: word.to_string(),
+                        }),
+                        message: None,
+                    });
+                }
+                callback.on_event(&PiJsonEvent::AgentEnd);
+            }
+            Ok(AgentResponse::new(prompt.to_string()))
+        }
+    }
+
+    /// Collects the text and end events it is given
+    #[derive(Default)]
+    struct Transcript {
+        text: Mutex<String>,
+        ended: Mutex<bool>,
+    }
+
+    impl ProgressCallback for Transcript {
+        fn on_event(&self, event: &PiJsonEvent) {
+            match event {
+                PiJsonEvent::MessageUpdate {
+                    assistant_message_event: Some(AssistantMessageEvent::TextDelta { delta, .. }),
+                    ..
+                } => self.text.lock().unwrap().push_str(delta),
+                PiJsonEvent::AgentEnd => *self.ended.lock().unwrap() = true,
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_chaos_config() {
+        assert_eq!("0.1".parse::<ChaosConfig>().unwrap(), ChaosConfig::uniform(0.1));
+
+        let config: ChaosConfig = "timeout=0.2, exit=0.05".parse().unwrap();
+        assert_eq!(config.timeout_rate, 0.2);
+        assert_eq!(config.exit_rate, 0.05);
+        assert_eq!(config.truncate_rate, 0.0);
+
+        assert!("1.5".parse::<ChaosConfig>().is_err());
+        assert!("0.5".parse::<ChaosConfig>().is_err()); // 4 x 0.5 > 1
+        assert!("explode=0.1".parse::<ChaosConfig>().is_err());
+    }
+
+    #[test]
+    fn test_fault_selection() {
+        let config: ChaosConfig = "timeout=0.25,truncate=0.25".parse().unwrap();
+        assert_eq!(config.fault_for(0.1), Some(Fault::Timeout));
+        assert_eq!(config.fault_for(0.3), Some(Fault::Truncate));
+        assert_eq!(config.fault_for(0.6), None);
+    }
+
+    #[tokio::test]
+    async fn test_zero_rates_pass_through() {
+        let agent = FaultyAgent::new(EchoAgent, ChaosConfig::default());
+        for _ in 0..20 {
+            assert_eq!(agent.execute("✓ DONE").await.unwrap().content, "✓ DONE");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_faults_are_injected() {
+        let agent = FaultyAgent::new(EchoAgent, ChaosConfig { exit_rate: 1.0, ..Default::default() });
+        assert!(matches!(agent.execute("hi").await, Err(AgentError::Api(_))));
+
+        let agent = FaultyAgent::new(EchoAgent, ChaosConfig { truncate_rate: 1.0, ..Default::default() }.with_seed(7));
+        let response = agent.execute("some long output ✓ DONE").await.unwrap();
+        assert!("some long output ✓ DONE".starts_with(&response.content));
+        assert!(!response.done);
+    }
+
+    #[tokio::test]
+    async fn test_faults_reach_the_callback() {
+        let prompt = "one two three four five six seven eight nine ten ✓ DONE";
+
+        for seed in 0..10 {
+            let config = ChaosConfig { truncate_rate: 1.0, ..Default::default() }.with_seed(seed);
+            let transcript = Transcript::default();
+            let response = FaultyAgent::new(EchoAgent, config)
+                .execute_streaming(prompt, Some(&transcript))
+                .await
+                .unwrap();
+            assert!(!*transcript.ended.lock().unwrap());
+            assert_eq!(response.content, *transcript.text.lock().unwrap());
+            assert!(prompt.starts_with(&response.content));
+        }
+
+        let mut lost_some = false;
+        for seed in 0..10 {
+            let config = ChaosConfig { malformed_rate: 1.0, ..Default::default() }.with_seed(seed);
+            let transcript = Transcript::default();
+            let response = FaultyAgent::new(EchoAgent, config)
+                .execute_streaming(prompt, Some(&transcript))
+                .await
+                .unwrap();
+            assert_eq!(response.content, *transcript.text.lock().unwrap());
+            lost_some |= response.content != prompt;
+        }
+        assert!(lost_some);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_stalls_until_the_callers_timeout() {
+        let agent = FaultyAgent::new(EchoAgent, ChaosConfig { timeout_rate: 1.0, ..Default::default() });
+        let run = tokio::time::timeout(std::time::Duration::from_millis(50), agent.execute("hi ✓ DONE"));
+        assert!(run.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_seed_is_reproducible() {
+        let config = ChaosConfig::uniform(0.2).with_seed(42);
+        let a = FaultyAgent::new(EchoAgent, config);
+        let b = FaultyAgent::new(EchoAgent, config);
+        // Timeouts stall, so give each run a deadline
+        async fn run(agent: &FaultyAgent<EchoAgent>) -> Option<Option<(String, bool)>> {
+            tokio::time::timeout(std::time::Duration::from_millis(10), agent.execute("a b c d e f g h"))
+                .await
+                .map(|result| result.map(|r| (r.content, r.done)).ok())
+                .ok()
+        }
+        for _ in 0..20 {
+            assert_eq!(run(&a).await, run(&b).await);
+        }
+    }
+}
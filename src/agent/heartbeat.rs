@@ -0,0 +1,68 @@
+//! Keepalive tracking for the agent's event stream
+//!
+//! A long-thinking agent can go quiet for a while without being stuck.
+//! `HeartbeatCallback` records when the last event arrived so the engine can
+//! report the silence ("no events for 45s") well before the step timeout
+//! kills the agent.
+
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Progress callback that remembers when the agent last emitted an event
+///
+/// All events are forwarded to the optional inner callback.
+pub struct HeartbeatCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    last_event: Mutex<Instant>,
+}
+
+impl<'a> HeartbeatCallback<'a> {
+    /// Create a new tracker wrapping an optional inner callback
+    ///
+    /// The silence is measured from creation until the first event arrives.
+    pub fn new(inner: Option<&'a dyn ProgressCallback>) -> Self {
+        Self {
+            inner,
+            last_event: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Time since the last event
+    pub fn silence(&self) -> Duration {
+        self.last_event.lock().unwrap().elapsed()
+    }
+}
+
+impl ProgressCallback for HeartbeatCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        *self.last_event.lock().unwrap() = Instant::now();
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.inner.and_then(|inner| inner.abort_reason())
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_resets_silence() {
+        let heartbeat = HeartbeatCallback::new(None);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(heartbeat.silence() >= Duration::from_millis(20));
+
+        heartbeat.on_event(&PiJsonEvent::AgentStart);
+        assert!(heartbeat.silence() < Duration::from_millis(20));
+    }
+}
@@ -5,13 +5,31 @@ pub mod response;
 pub mod subprocess_client;
 pub mod streaming;
 pub mod pi_events;
+pub mod approval;
+pub mod write_tracking;
+pub mod heartbeat;
+pub mod usage;
+pub mod unknown_events;
+pub mod text_chunks;
+pub mod step_log;
+pub mod event_reader;
+pub mod faulty;
+pub mod noop;
 
 use async_trait::async_trait;
 pub use client::{AgentClientConfig};
 pub use response::{AgentResponse, AgentError};
-pub use subprocess_client::PiSubprocessClient;
+pub use subprocess_client::{PiSubprocessClient, STEP_ENV, STEP_ENV_PASSTHROUGH, STEP_SESSION, STEP_WORKING_DIR};
 pub use pi_events::PiJsonEvent;
-pub use streaming::ProgressCallback;
+pub use streaming::{BufferedCallback, ProgressCallback};
+pub use approval::{ApprovalDecision, DenyAll, ReadOnlyApprover, ToolApprovalPolicy, ToolApprover, ToolApprovalCallback};
+pub use write_tracking::WriteTrackingCallback;
+pub use heartbeat::HeartbeatCallback;
+pub use usage::UsageTrackingCallback;
+pub use unknown_events::UnknownEventCallback;
+pub use text_chunks::TextChunkCallback;
+pub use step_log::StepLogCallback;
+pub use faulty::{ChaosConfig, FaultyAgent};
 
 /// Trait for agent execution - allows for different implementations
 #[async_trait]
@@ -71,6 +89,21 @@ pub trait AgentExecutor: Send + Sync {
     ) -> Result<AgentResponse, AgentError>;
 }
 
+#[async_trait]
+impl<T: AgentExecutor + ?Sized> AgentExecutor for Box<T> {
+    async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+        (**self).execute(prompt).await
+    }
+
+    async fn execute_streaming(
+        &self,
+        prompt: &str,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, AgentError> {
+        (**self).execute_streaming(prompt, callback).await
+    }
+}
+
 /// Pi agent client that calls pi CLI as a subprocess
 #[derive(Debug, Clone)]
 pub struct PiAgentClient {
@@ -85,13 +118,18 @@ impl PiAgentClient {
     /// * `config` - Configuration for the agent client
     ///
     /// The `config.endpoint` field is used as the path to the pi executable.
-    /// If not provided, defaults to "pi" (assuming it's on PATH).
+    /// If not provided, defaults to "pi" (assuming it's on PATH). Any
+    /// `config.args` are passed to pi on every call, and
+    /// `config.strict_events` makes unknown pi events fail the call.
+    /// Event lines over `config.max_event_bytes` are truncated.
     pub fn new(config: AgentClientConfig) -> Self {
         let pi_path = config
             .endpoint
             .unwrap_or_else(|| "pi".to_string());
-        let subprocess_client =
-            PiSubprocessClient::new(pi_path, config.timeout_secs);
+        let subprocess_client = PiSubprocessClient::new(pi_path, config.timeout_secs)
+            .with_args(config.args)
+            .with_strict_events(config.strict_events)
+            .with_max_event_bytes(config.max_event_bytes);
         Self {
             subprocess_client,
         }
@@ -158,6 +196,6 @@ mod tests {
         let callback = NoopCallback;
 
         // This won't work without pi installed, but we're testing compilation
-        let _ = client.execute_streaming("test", Some(&callback));
+        let _future = client.execute_streaming("test", Some(&callback));
     }
 }
@@ -0,0 +1,58 @@
+//! An agent that answers instantly without running anything
+//!
+//! `NoopAgent` replies to every prompt with the same text, so a run with it
+//! costs only the engine's own scheduling and event dispatch. The benchmarks
+//! under `benches/` use it to measure that overhead on large pipelines.
+
+use crate::agent::{AgentExecutor, AgentError, AgentResponse, ProgressCallback};
+use async_trait::async_trait;
+
+/// Reply used unless `with_response` sets another
+const DEFAULT_RESPONSE: &str = "DONE";
+
+/// Agent that replies to every prompt immediately with a fixed response
+#[derive(Debug, Clone)]
+pub struct NoopAgent {
+    response: String,
+}
+
+impl NoopAgent {
+    /// Agent that replies `DONE`
+    pub fn new() -> Self {
+        Self {
+            response: DEFAULT_RESPONSE.to_string(),
+        }
+    }
+
+    /// Reply with `response` instead
+    pub fn with_response(mut self, response: impl Into<String>) -> Self {
+        self.response = response.into();
+        self
+    }
+}
+
+impl Default for NoopAgent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl AgentExecutor for NoopAgent {
+    async fn execute(&self, _prompt: &str) -> Result<AgentResponse, AgentError> {
+        Ok(AgentResponse {
+            content: self.response.clone(),
+            done: true,
+            usage: None,
+        })
+    }
+
+    /// Sends no events, so the terminal stays quiet however many steps run
+    async fn execute_streaming(
+        &self,
+        prompt: &str,
+        _callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, AgentError> {
+        self.execute(prompt).await
+    }
+}
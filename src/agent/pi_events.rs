@@ -73,6 +73,57 @@ pub enum PiJsonEvent {
         result: Value,
         is_error: bool,
     },
+
+    /// Event of a type this version does not know, kept as sent
+    ///
+    /// Produced by `PiJsonEvent::parse` when a newer pi adds event types.
+    #[serde(skip)]
+    Unknown { raw: Value },
+}
+
+/// Event types `PiJsonEvent` understands
+const KNOWN_EVENT_TYPES: &[&str] = &[
+    "agent_start",
+    "agent_end",
+    "turn_start",
+    "turn_end",
+    "message_start",
+    "message_end",
+    "message_update",
+    "session",
+    "tool_execution_start",
+    "tool_execution_update",
+    "tool_execution_end",
+];
+
+impl PiJsonEvent {
+    /// Parse one line of pi's JSON output
+    ///
+    /// Lines whose `type` is not a known event type become
+    /// `PiJsonEvent::Unknown` instead of an error, so output from newer pi
+    /// versions is preserved. Malformed known events are still errors.
+    pub fn parse(line: &str) -> Result<Self, serde_json::Error> {
+        let raw: Value = serde_json::from_str(line)?;
+        let known = raw
+            .get("type")
+            .and_then(Value::as_str)
+            .is_some_and(|event_type| KNOWN_EVENT_TYPES.contains(&event_type));
+        if known {
+            serde_json::from_value(raw)
+        } else {
+            Ok(PiJsonEvent::Unknown { raw })
+        }
+    }
+
+    /// The `type` of an unknown event (`"?"` when it has none)
+    pub fn unknown_type(&self) -> Option<&str> {
+        match self {
+            PiJsonEvent::Unknown { raw } => {
+                Some(raw.get("type").and_then(Value::as_str).unwrap_or("?"))
+            }
+            _ => None,
+        }
+    }
 }
 
 /// Nested assistant message event from MessageUpdate
@@ -154,6 +205,33 @@ pub enum AssistantMessageEvent {
 pub struct Message {
     pub role: String,
     pub content: Vec<Value>,
+
+    /// Tokens and cost spent on the message (assistant messages only)
+    #[serde(default)]
+    pub usage: Option<Box<Usage>>,
+}
+
+/// Token usage reported with an assistant message
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Usage {
+    pub input: u64,
+    pub output: u64,
+    pub cache_read: u64,
+    pub cache_write: u64,
+    pub total_tokens: u64,
+    pub cost: UsageCost,
+}
+
+/// Cost of a message in USD, as computed by pi from the model's pricing
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UsageCost {
+    pub input: f64,
+    pub output: f64,
+    pub cache_read: f64,
+    pub cache_write: f64,
+    pub total: f64,
 }
 
 /// Tool call object
@@ -170,6 +248,28 @@ pub struct ToolCall {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_keeps_unknown_events() {
+        let line = r#"{"type":"compaction_start","reason":"overflow"}"#;
+        let event = PiJsonEvent::parse(line).unwrap();
+        assert_eq!(event.unknown_type(), Some("compaction_start"));
+        assert_eq!(
+            event,
+            PiJsonEvent::Unknown {
+                raw: serde_json::json!({"type": "compaction_start", "reason": "overflow"})
+            }
+        );
+
+        let untyped = PiJsonEvent::parse(r#"{"hello":1}"#).unwrap();
+        assert_eq!(untyped.unknown_type(), Some("?"));
+
+        assert_eq!(PiJsonEvent::parse(r#"{"type":"agent_start"}"#).unwrap(), PiJsonEvent::AgentStart);
+        assert_eq!(PiJsonEvent::parse(r#"{"type":"agent_start"}"#).unwrap().unknown_type(), None);
+        // A known type with missing fields is still an error, not an unknown event
+        assert!(PiJsonEvent::parse(r#"{"type":"session"}"#).is_err());
+        assert!(PiJsonEvent::parse("not json").is_err());
+    }
+
     #[test]
     fn test_parse_agent_start() {
         let json = r#"{"type":"agent_start"}"#;
@@ -977,7 +1077,7 @@ mod tests {
                     Some(AssistantMessageEvent::ToolcallEnd {
                         content_index,
                         tool_call,
-                        partial,
+                        partial: _,
                     }) => {
                         assert_eq!(content_index, 1);
                         assert_eq!(tool_call.name, "bash");
@@ -1012,6 +1112,7 @@ mod tests {
             } => {
                 assert_eq!(tool_call_id, "call_test123");
                 assert_eq!(tool_name, "bash");
+                assert_eq!(args["command"], "ls -la");
             }
             _ => panic!("Expected ToolExecutionStart, got: {:?}", event),
         }
@@ -1077,7 +1178,7 @@ mod tests {
             } => {
                 assert_eq!(tool_call_id, "call_test123");
                 assert_eq!(tool_name, "bash");
-                assert_eq!(is_error, false);
+                assert!(!is_error);
             }
             _ => panic!("Expected ToolExecutionEnd, got: {:?}", event),
         }
@@ -14,6 +14,9 @@ pub enum AgentError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+
+    #[error("Aborted: {0}")]
+    Aborted(String),
 }
 
 /// Response from the agent
@@ -0,0 +1,178 @@
+//! Per-step log files
+//!
+//! `StepLogCallback` appends what the agent streams during a step to the
+//! step's log: its text as it arrives, and each tool call with its
+//! arguments and full result. Each attempt starts with a header line. Once
+//! the log would grow past its size limit it is moved to `<name>.1`,
+//! replacing the previous one, and a new log is started.
+
+use crate::agent::pi_events::AssistantMessageEvent;
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use serde_json::Value;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+struct LogFile {
+    file: File,
+    size: u64,
+}
+
+/// Progress callback writing the agent's output to a step log
+///
+/// All events are forwarded to the optional inner callback. Output that
+/// can't be written is skipped with a debug message, so a full disk
+/// doesn't stop the step.
+pub struct StepLogCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    path: PathBuf,
+    max_bytes: u64,
+    log: Mutex<LogFile>,
+}
+
+impl<'a> StepLogCallback<'a> {
+    /// Append to the log at `path`, creating it and its directory if
+    /// needed, starting with a header for `attempt`
+    pub fn open(
+        path: &Path,
+        attempt: usize,
+        max_bytes: u64,
+        inner: Option<&'a dyn ProgressCallback>,
+    ) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+        let size = file.metadata()?.len();
+        let callback = Self {
+            inner,
+            path: path.to_path_buf(),
+            max_bytes,
+            log: Mutex::new(LogFile { file, size }),
+        };
+        let separator = if size > 0 { "\n" } else { "" };
+        callback.write(&format!(
+            "{}=== attempt {} at {} ===\n",
+            separator,
+            attempt,
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ))?;
+        Ok(callback)
+    }
+
+    fn write(&self, text: &str) -> std::io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        if log.size > 0 && log.size + text.len() as u64 > self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            std::fs::rename(&self.path, &rotated)?;
+            log.file = File::create(&self.path)?;
+            log.size = 0;
+        }
+        log.file.write_all(text.as_bytes())?;
+        log.size += text.len() as u64;
+        Ok(())
+    }
+}
+
+/// Text of a tool result's content, or the result as JSON if it has none
+fn result_text(result: &Value) -> String {
+    let texts: Vec<&str> = result
+        .get("content")
+        .and_then(Value::as_array)
+        .map(|content| content.iter().filter_map(|item| item.get("text")?.as_str()).collect())
+        .unwrap_or_default();
+    if texts.is_empty() {
+        result.to_string()
+    } else {
+        texts.join("\n")
+    }
+}
+
+impl ProgressCallback for StepLogCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        let text = match event {
+            PiJsonEvent::MessageUpdate {
+                assistant_message_event: Some(AssistantMessageEvent::TextDelta { delta, .. }),
+                ..
+            } => Some(delta.clone()),
+            PiJsonEvent::ToolExecutionStart { tool_name, args, .. } => {
+                Some(format!("\n[tool] {} {}\n", tool_name, args))
+            }
+            PiJsonEvent::ToolExecutionEnd {
+                tool_name,
+                result,
+                is_error,
+                ..
+            } => {
+                let outcome = if *is_error { "failed" } else { "done" };
+                let text = result_text(result);
+                let newline = if text.ends_with('\n') { "" } else { "\n" };
+                Some(format!("[tool] {} {}\n{}{}", tool_name, outcome, text, newline))
+            }
+            _ => None,
+        };
+        if let Some(text) = text {
+            if let Err(e) = self.write(&text) {
+                tracing::debug!("Could not write to step log {}: {}", self.path.display(), e);
+            }
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.inner.and_then(|inner| inner.abort_reason())
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(line: &str) -> PiJsonEvent {
+        PiJsonEvent::parse(line).unwrap()
+    }
+
+    #[test]
+    fn test_logs_text_and_tool_calls_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/build.log");
+
+        let callback = StepLogCallback::open(&path, 1, 1024, None).unwrap();
+        for line in [
+            r#"{"type":"agent_start"}"#,
+            r#"{"type":"message_update","assistantMessageEvent":{"type":"text_delta","contentIndex":0,"delta":"Building"}}"#,
+            r#"{"type":"tool_execution_start","toolCallId":"c1","toolName":"bash","args":{"command":"cargo build"}}"#,
+            r#"{"type":"tool_execution_end","toolCallId":"c1","toolName":"bash","result":{"content":[{"type":"text","text":"Compiling\nFinished"}]},"isError":false}"#,
+            r#"{"type":"message_update","assistantMessageEvent":{"type":"text_delta","contentIndex":0,"delta":"DONE"}}"#,
+        ] {
+            callback.on_event(&event(line));
+        }
+        let log = std::fs::read_to_string(&path).unwrap();
+        let body = log.split_once("===\n").unwrap().1;
+        assert!(log.starts_with("=== attempt 1 at "), "{}", log);
+        assert_eq!(
+            body,
+            "Building\n[tool] bash {\"command\":\"cargo build\"}\n[tool] bash done\nCompiling\nFinished\nDONE"
+        );
+
+        // A later attempt appends; past the limit the log moves to `.1`
+        let callback = StepLogCallback::open(&path, 2, 1024, None).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().contains("DONE\n=== attempt 2 at "));
+        let delta = format!(
+            r#"{{"type":"message_update","assistantMessageEvent":{{"type":"text_delta","contentIndex":0,"delta":"{}"}}}}"#,
+            "x".repeat(1000)
+        );
+        callback.on_event(&event(&delta));
+        assert!(std::fs::read_to_string(dir.path().join("run/build.log.1")).unwrap().contains("DONE"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x".repeat(1000));
+    }
+}
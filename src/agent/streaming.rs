@@ -18,6 +18,10 @@
 //! - `AgentEnd` - Execution finished
 //! - Tool call events (Start, Delta, End, etc.)
 //!
+//! `BufferedCallback` keeps the last lines of the agent's text so that an
+//! interrupted step can show where the agent was; wrappers pass
+//! `get_context_lines` on from their inner callback.
+//!
 //! # Example
 //!
 //! ```no_run
@@ -55,7 +59,13 @@
 //! # }
 //! ```
 
+use crate::agent::pi_events::AssistantMessageEvent;
 use crate::agent::PiJsonEvent;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Lines of agent text a `BufferedCallback` keeps by default
+pub const DEFAULT_CONTEXT_LINES: usize = 20;
 
 /// Callback for processing events as they arrive from streaming execution
 ///
@@ -85,6 +95,97 @@ pub trait ProgressCallback: Send + Sync {
     /// This is called for every valid JSON line parsed from the subprocess,
     /// including events like `AgentStart`, `TextDelta`, `TextEnd`, `AgentEnd`, etc.
     fn on_event(&self, event: &PiJsonEvent);
+
+    /// Reason for stopping the running agent, if the callback requests it
+    ///
+    /// Checked after every event. When this returns `Some`, the agent client
+    /// terminates the subprocess and returns `AgentError::Aborted`.
+    fn abort_reason(&self) -> Option<String> {
+        None
+    }
+
+    /// The last lines of agent text, if the callback keeps them
+    ///
+    /// Used to show what the agent was doing when a step is interrupted.
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        None
+    }
+}
+
+/// Progress callback that keeps the last lines of the agent's text
+///
+/// All events are forwarded to the optional inner callback. A line still
+/// being written counts as the last one.
+pub struct BufferedCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    max_lines: usize,
+    text: Mutex<TextTail>,
+}
+
+/// Complete lines kept so far and the line being written
+#[derive(Default)]
+struct TextTail {
+    lines: VecDeque<String>,
+    partial: String,
+}
+
+impl<'a> BufferedCallback<'a> {
+    /// Create a buffer of `DEFAULT_CONTEXT_LINES` lines wrapping an
+    /// optional inner callback
+    pub fn new(inner: Option<&'a dyn ProgressCallback>) -> Self {
+        Self::with_max_lines(inner, DEFAULT_CONTEXT_LINES)
+    }
+
+    /// Create a buffer keeping at most `max_lines` lines
+    pub fn with_max_lines(inner: Option<&'a dyn ProgressCallback>, max_lines: usize) -> Self {
+        Self {
+            inner,
+            max_lines,
+            text: Mutex::new(TextTail::default()),
+        }
+    }
+}
+
+impl ProgressCallback for BufferedCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        if let PiJsonEvent::MessageUpdate {
+            assistant_message_event: Some(AssistantMessageEvent::TextDelta { delta, .. }),
+            ..
+        } = event
+        {
+            let mut text = self.text.lock().unwrap();
+            let mut pieces = delta.split('\n');
+            // The first piece continues the line being written
+            if let Some(first) = pieces.next() {
+                text.partial.push_str(first);
+            }
+            for piece in pieces {
+                let line = std::mem::replace(&mut text.partial, piece.to_string());
+                text.lines.push_back(line);
+                if text.lines.len() > self.max_lines {
+                    text.lines.pop_front();
+                }
+            }
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.inner.and_then(|inner| inner.abort_reason())
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        let text = self.text.lock().unwrap();
+        let mut lines: Vec<String> = text.lines.iter().cloned().collect();
+        if !text.partial.is_empty() {
+            lines.push(text.partial.clone());
+        }
+        let skip = lines.len().saturating_sub(self.max_lines);
+        Some(lines.split_off(skip))
+    }
 }
 
 /// No-op callback that does nothing (for backward compatibility)
@@ -172,6 +273,34 @@ mod tests {
         takes_callback(&test); // Should compile
     }
 
+    #[test]
+    fn test_buffered_callback_keeps_last_lines() {
+        let delta = |text: &str| {
+            let json = serde_json::json!({
+                "type": "message_update",
+                "assistantMessageEvent": {"type": "text_delta", "contentIndex": 0, "delta": text}
+            });
+            serde_json::from_value::<PiJsonEvent>(json).unwrap()
+        };
+        let inner = TestCallback::new();
+        let buffered = BufferedCallback::with_max_lines(Some(&inner), 3);
+        assert_eq!(buffered.get_context_lines(), Some(vec![]));
+
+        for text in ["Reading src/", "lib.rs\nFound 3 ", "issues\n1. a\n", "2. b\n3. c"] {
+            buffered.on_event(&delta(text));
+        }
+        assert_eq!(
+            buffered.get_context_lines(),
+            Some(vec!["1. a".to_string(), "2. b".to_string(), "3. c".to_string()])
+        );
+        assert_eq!(inner.get_events().len(), 4);
+
+        // Wrappers pass the lines on
+        let outer = crate::agent::HeartbeatCallback::new(Some(&buffered));
+        assert_eq!(outer.get_context_lines().unwrap().last().unwrap(), "3. c");
+        assert_eq!(NoopCallback.get_context_lines(), None);
+    }
+
     #[test]
     fn test_progress_callback_as_option() {
         // Test that callback can be passed as Option<&dyn ProgressCallback>
@@ -2,13 +2,21 @@
 
 use crate::agent::{AgentError, PiJsonEvent, AgentResponse};
 use crate::agent::pi_events::AssistantMessageEvent;
+use crate::agent::event_reader::{EventLineReader, DEFAULT_MAX_EVENT_BYTES};
 use crate::agent::streaming::ProgressCallback;
+use std::path::PathBuf;
 use std::time::Duration;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::BufReader;
 use tokio::process::Command;
 use tokio::time::timeout;
 use tracing::{debug, warn};
 
+/// Extra spawn attempts after a transient OS error
+const SPAWN_RETRIES: u32 = 3;
+
+/// Delay before the first spawn retry, doubled for each later one
+const SPAWN_BACKOFF: Duration = Duration::from_millis(100);
+
 /// Client for executing Pi CLI as a subprocess
 #[derive(Debug, Clone)]
 pub struct PiSubprocessClient {
@@ -17,6 +25,15 @@ pub struct PiSubprocessClient {
 
     /// Timeout for command execution in seconds
     timeout_secs: u64,
+
+    /// Extra arguments passed to pi before the prompt
+    args: Vec<String>,
+
+    /// Fail on the first event type this version does not understand
+    strict_events: bool,
+
+    /// Longest event line kept in full, in bytes
+    max_event_bytes: usize,
 }
 
 impl PiSubprocessClient {
@@ -29,9 +46,33 @@ impl PiSubprocessClient {
         Self {
             pi_path,
             timeout_secs,
+            args: Vec::new(),
+            strict_events: false,
+            max_event_bytes: DEFAULT_MAX_EVENT_BYTES,
         }
     }
 
+    /// Pass extra arguments to pi (e.g. `--provider openai`)
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Fail with an error on unknown event types instead of passing them on
+    pub fn with_strict_events(mut self, strict_events: bool) -> Self {
+        self.strict_events = strict_events;
+        self
+    }
+
+    /// Cut event lines longer than this many bytes
+    ///
+    /// Oversized tool events keep their IDs with the result truncated;
+    /// other oversized events are skipped.
+    pub fn with_max_event_bytes(mut self, max_event_bytes: usize) -> Self {
+        self.max_event_bytes = max_event_bytes;
+        self
+    }
+
     /// Get the pi executable path
     #[cfg(test)]
     pub fn pi_path(&self) -> &str {
@@ -44,8 +85,10 @@ impl PiSubprocessClient {
     ///
     /// # Streaming Behavior
     /// - Subprocess spawns with JSON mode for event-based streaming
-    /// - stdout is read line-by-line in real-time
-    /// - Each valid JSON line is parsed as `PiJsonEvent`
+    /// - stdout is read line-by-line in real-time, keeping at most
+    ///   `max_event_bytes` of each line
+    /// - Each valid JSON line is parsed as `PiJsonEvent`; oversized tool
+    ///   events get their result truncated with a marker
     /// - Text deltas are accumulated into final content buffer
     /// - Callback is invoked for each parsed event (if provided)
     /// - Malformed JSON lines are logged but don't crash parsing
@@ -62,6 +105,7 @@ impl PiSubprocessClient {
     /// - The pi executable cannot be spawned
     /// - pi exits with a non-zero status
     /// - The command times out
+    /// - The callback requests an abort (`AgentError::Aborted`)
     ///
     /// # Example
     /// ```no_run
@@ -96,13 +140,9 @@ impl PiSubprocessClient {
         let timeout_duration = Duration::from_secs(self.timeout_secs);
 
         // Spawn pi in JSON mode with streaming
-        let mut child = Command::new(&self.pi_path)
-            .args(["--mode", "json", "--print"])
-            .arg(prompt)
-            .stdout(std::process::Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .map_err(|e| AgentError::Internal(format!("Failed to spawn pi subprocess: {}", e)))?;
+        let mut command = self.command("json", prompt);
+        command.stdout(std::process::Stdio::piped());
+        let mut child = spawn_with_retry(|| command.spawn()).await?;
 
         // Get stdout handle
         let stdout = child
@@ -110,8 +150,7 @@ impl PiSubprocessClient {
             .take()
             .ok_or_else(|| AgentError::Internal("Failed to get stdout handle".to_string()))?;
 
-        let reader = BufReader::new(stdout);
-        let mut lines = reader.lines();
+        let mut lines = EventLineReader::new(BufReader::new(stdout), self.max_event_bytes);
 
         let mut accumulated_text = String::new();
 
@@ -123,29 +162,53 @@ impl PiSubprocessClient {
 
             match line_result {
                 Ok(Some(line)) => {
-                    if line.is_empty() {
+                    let parsed = if line.is_truncated() {
+                        match line.truncated_event() {
+                            Some(event) => Ok(event),
+                            None => {
+                                warn!(
+                                    "Skipping {} byte event from pi (over the {} byte limit)",
+                                    line.total_bytes, self.max_event_bytes
+                                );
+                                continue;
+                            }
+                        }
+                    } else if line.text.is_empty() {
                         continue;
-                    }
+                    } else {
+                        PiJsonEvent::parse(&line.text)
+                    };
 
                     // Parse the line as a JSON event
-                    match serde_json::from_str::<PiJsonEvent>(&line) {
+                    match parsed {
                         Ok(event) => {
                             debug!("Parsed event: {:?}", event);
 
+                            // Pi's schema has drifted; stop before text goes missing unnoticed
+                            if let (Some(event_type), true) = (event.unknown_type(), self.strict_events) {
+                                let _ = child.kill().await;
+                                return Err(AgentError::Api(format!(
+                                    "pi sent unknown event type '{}' (--strict-events)",
+                                    event_type
+                                )));
+                            }
+
                             // Handle different event types
                             match &event {
-                                PiJsonEvent::MessageUpdate { assistant_message_event, .. } => {
+                                PiJsonEvent::MessageUpdate { assistant_message_event: Some(assistant_event), .. } => {
                                     // Extract text from nested MessageUpdate events
-                                    if let Some(assistant_event) = assistant_message_event {
-                                        match assistant_event {
-                                            AssistantMessageEvent::TextDelta { delta, .. } => {
-                                                accumulated_text.push_str(delta);
-                                            }
-                                            AssistantMessageEvent::TextEnd { content, .. } => {
-                                                debug!("Received text_end event with content: {:?}", content);
-                                            }
-                                            _ => {}
+                                    match assistant_event {
+                                        AssistantMessageEvent::TextDelta { delta, .. } => {
+                                            accumulated_text.push_str(delta);
+                                        }
+                                        AssistantMessageEvent::TextEnd { content, .. } => {
+                                            // The client doesn't know the step's telemetry level, so only the size is logged
+                                            debug!(
+                                                "Received text_end event ({} bytes)",
+                                                content.as_ref().map_or(0, String::len)
+                                            );
                                         }
+                                        _ => {}
                                     }
                                 }
                                 PiJsonEvent::Session { .. } => {
@@ -157,11 +220,18 @@ impl PiSubprocessClient {
                             // Call callback if provided
                             if let Some(cb) = callback {
                                 cb.on_event(&event);
+
+                                // Stop the agent if the callback asks for it
+                                if let Some(reason) = cb.abort_reason() {
+                                    warn!("Aborting pi subprocess: {}", reason);
+                                    let _ = child.kill().await;
+                                    return Err(AgentError::Aborted(reason));
+                                }
                             }
                         }
                         Err(e) => {
                             // Log malformed JSON but continue processing
-                            warn!("Failed to parse JSON line: {} - Line: {}", e, line);
+                            warn!("Failed to parse JSON line: {} - Line: {}", e, line.text);
                         }
                     }
                 }
@@ -223,20 +293,16 @@ impl PiSubprocessClient {
         let timeout_duration = Duration::from_secs(self.timeout_secs);
 
         // Spawn pi in text/print mode
-        let result = timeout(
-            timeout_duration,
-            Command::new(&self.pi_path)
-                .args(["--mode", "text", "--print"])
-                .arg(prompt)
-                .kill_on_drop(true)
-                .output(),
-        )
-        .await
-        .map_err(|_| AgentError::Timeout(self.timeout_secs))?;
-
-        let output = result.map_err(|e| {
-            AgentError::Internal(format!("Failed to execute pi subprocess: {}", e))
-        })?;
+        let mut command = self.command("text", prompt);
+        command
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped());
+        let child = spawn_with_retry(|| command.spawn()).await?;
+
+        let output = timeout(timeout_duration, child.wait_with_output())
+            .await
+            .map_err(|_| AgentError::Timeout(self.timeout_secs))?
+            .map_err(|e| AgentError::Internal(format!("Failed to execute pi subprocess: {}", e)))?;
 
         // Check exit code
         if !output.status.success() {
@@ -263,6 +329,119 @@ impl PiSubprocessClient {
 
         Ok(content)
     }
+
+    /// Command running pi on `prompt` in the given output mode, with the
+    /// running step's environment and working directory
+    fn command(&self, mode: &str, prompt: &str) -> Command {
+        let mut command = Command::new(&self.pi_path);
+        command.args(["--mode", mode, "--print"]).args(&self.args);
+        if let Some(session) = step_session() {
+            command.arg("--session").arg(session);
+        }
+        command.arg(prompt).kill_on_drop(true);
+        if let Some(names) = step_env_passthrough() {
+            command.env_clear();
+            for name in names {
+                if let Some(value) = std::env::var_os(&name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(step_env());
+        if let Some(dir) = step_working_dir() {
+            command.current_dir(dir);
+        }
+        command
+    }
+}
+
+tokio::task_local! {
+    /// Environment variables set on pi processes spawned by the running step
+    /// (e.g. its scratch directory)
+    pub static STEP_ENV: Vec<(String, String)>;
+
+    /// Variables of our environment that pi processes spawned by the running
+    /// step inherit, if not all of them
+    pub static STEP_ENV_PASSTHROUGH: Option<Vec<String>>;
+
+    /// Directory pi processes spawned by the running step start in, if not
+    /// the pipeline's own
+    pub static STEP_WORKING_DIR: Option<PathBuf>;
+
+    /// Session file pi processes spawned by the running step continue, so
+    /// that a follow-up reply reaches the same conversation
+    pub static STEP_SESSION: Option<PathBuf>;
+}
+
+fn step_env() -> Vec<(String, String)> {
+    STEP_ENV.try_with(Clone::clone).unwrap_or_default()
+}
+
+fn step_env_passthrough() -> Option<Vec<String>> {
+    STEP_ENV_PASSTHROUGH.try_with(Clone::clone).ok().flatten()
+}
+
+fn step_working_dir() -> Option<PathBuf> {
+    STEP_WORKING_DIR.try_with(Clone::clone).ok().flatten()
+}
+
+fn step_session() -> Option<PathBuf> {
+    STEP_SESSION.try_with(Clone::clone).ok().flatten()
+}
+
+/// Spawn a process, retrying with exponential backoff while the OS reports
+/// a transient error
+async fn spawn_with_retry<T>(mut spawn: impl FnMut() -> std::io::Result<T>) -> Result<T, AgentError> {
+    let mut delay = SPAWN_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match spawn() {
+            Ok(child) => return Ok(child),
+            Err(e) if attempt <= SPAWN_RETRIES && is_transient_spawn_error(&e) => {
+                debug!(
+                    "Spawning pi failed ({}), retrying in {}ms (attempt {} of {})",
+                    e,
+                    delay.as_millis(),
+                    attempt + 1,
+                    SPAWN_RETRIES + 1
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) if attempt > 1 => {
+                return Err(AgentError::Internal(format!(
+                    "Failed to spawn pi subprocess after {} attempts: {}",
+                    attempt, e
+                )))
+            }
+            Err(e) => return Err(AgentError::Internal(format!("Failed to spawn pi subprocess: {}", e))),
+        }
+    }
+}
+
+/// Whether a spawn error may go away on its own (resource exhaustion, a file
+/// briefly locked by another process such as a virus scanner)
+fn is_transient_spawn_error(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    if matches!(
+        error.kind(),
+        ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::OutOfMemory | ErrorKind::ResourceBusy
+    ) {
+        return true;
+    }
+
+    // EAGAIN, ENOMEM, ETXTBSY (binary still being written)
+    #[cfg(unix)]
+    let transient: &[i32] = &[11, 12, 26];
+    // ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    #[cfg(windows)]
+    let transient: &[i32] = &[5, 32, 33];
+    #[cfg(not(any(unix, windows)))]
+    let transient: &[i32] = &[];
+
+    error.raw_os_error().is_some_and(|code| transient.contains(&code))
 }
 
 #[cfg(test)]
@@ -371,6 +550,41 @@ mod tests {
         let _ = result;
     }
 
+    #[tokio::test]
+    async fn test_strict_events_fail_on_unknown_event_types() {
+        let prompt = r#"prompt
+{"type":"agent_start"}
+{"type":"compaction_start"}
+{"type":"agent_end"}"#;
+
+        // By default unknown events reach the callback as they were sent
+        let events = Arc::new(Mutex::new(Vec::new()));
+        struct TestCallback {
+            events: Arc<Mutex<Vec<PiJsonEvent>>>,
+        }
+        impl ProgressCallback for TestCallback {
+            fn on_event(&self, event: &PiJsonEvent) {
+                self.events.lock().unwrap().push(event.clone());
+            }
+        }
+        let callback = TestCallback { events: Arc::clone(&events) };
+        let client = PiSubprocessClient::new("echo".to_string(), 5);
+        assert!(client.execute_streaming(prompt, Some(&callback)).await.is_ok());
+        let unknown: Vec<_> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| event.unknown_type().map(str::to_string))
+            .collect();
+        assert_eq!(unknown, vec!["compaction_start"]);
+
+        let strict = PiSubprocessClient::new("echo".to_string(), 5).with_strict_events(true);
+        match strict.execute_streaming(prompt, None).await {
+            Err(AgentError::Api(msg)) => assert!(msg.contains("compaction_start"), "{}", msg),
+            other => panic!("Expected AgentError::Api, got {:?}", other),
+        }
+    }
+
     #[tokio::test]
     async fn test_malformed_json_handling() {
         // Test that the parser handles empty output gracefully
@@ -381,4 +595,117 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap().content, "");
     }
+
+    #[tokio::test]
+    async fn test_spawn_retries_transient_errors() {
+        let mut calls = 0;
+        let result = spawn_with_retry(|| {
+            calls += 1;
+            match calls {
+                1 | 2 => Err(std::io::Error::from(std::io::ErrorKind::WouldBlock)),
+                _ => Ok("spawned"),
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "spawned");
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result = spawn_with_retry(|| -> std::io::Result<()> {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::OutOfMemory))
+        })
+        .await;
+        assert_eq!(calls, SPAWN_RETRIES + 1);
+        match result {
+            Err(AgentError::Internal(msg)) => assert!(msg.contains("after 4 attempts"), "{}", msg),
+            other => panic!("Expected AgentError::Internal, got {:?}", other),
+        }
+
+        // A missing binary is not worth retrying
+        let mut calls = 0;
+        let result = spawn_with_retry(|| -> std::io::Result<()> {
+            calls += 1;
+            Err(std::io::Error::from(std::io::ErrorKind::NotFound))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pi_starts_in_step_working_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = tempfile::tempdir().unwrap();
+        let fake_pi = bin.path().join("pi");
+        std::fs::write(&fake_pi, "#!/bin/sh\npwd\n").unwrap();
+        std::fs::set_permissions(&fake_pi, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let client = PiSubprocessClient::new(fake_pi.to_string_lossy().into_owned(), 5);
+
+        let workspace = tempfile::tempdir().unwrap();
+        let output = STEP_WORKING_DIR
+            .scope(Some(workspace.path().to_path_buf()), client.execute("Where am I?"))
+            .await
+            .unwrap();
+        assert_eq!(
+            PathBuf::from(output.trim()).canonicalize().unwrap(),
+            workspace.path().canonicalize().unwrap()
+        );
+
+        // Outside a step, pi starts where the pipeline runs
+        let output = client.execute("Where am I?").await.unwrap();
+        assert_eq!(PathBuf::from(output.trim()), std::env::current_dir().unwrap());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pi_continues_step_session() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bin = tempfile::tempdir().unwrap();
+        let fake_pi = bin.path().join("pi");
+        std::fs::write(&fake_pi, "#!/bin/sh\necho \"$@\"\n").unwrap();
+        std::fs::set_permissions(&fake_pi, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let client = PiSubprocessClient::new(fake_pi.to_string_lossy().into_owned(), 5);
+
+        let output = STEP_SESSION
+            .scope(Some(PathBuf::from("/tmp/step/session.jsonl")), client.execute("Use Postgres"))
+            .await
+            .unwrap();
+        assert_eq!(output.trim(), "--mode text --print --session /tmp/step/session.jsonl Use Postgres");
+
+        let output = client.execute("Hello").await.unwrap();
+        assert_eq!(output.trim(), "--mode text --print Hello");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_pi_gets_configured_environment() {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::env::set_var("PIPELINE_TEST_ENV_KEPT", "kept");
+        std::env::set_var("PIPELINE_TEST_ENV_DROPPED", "dropped");
+        let bin = tempfile::tempdir().unwrap();
+        let fake_pi = bin.path().join("pi");
+        let script = "#!/bin/sh\necho \"$GREETING|$REGION|$PIPELINE_TEST_ENV_KEPT|$PIPELINE_TEST_ENV_DROPPED\"\n";
+        std::fs::write(&fake_pi, script).unwrap();
+        std::fs::set_permissions(&fake_pi, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let client = PiSubprocessClient::new(fake_pi.to_string_lossy().into_owned(), 5);
+        let step_env = || vec![("GREETING".to_string(), "hello".to_string()), ("REGION".to_string(), "us".to_string())];
+
+        // Everything is inherited unless a passthrough list is given
+        let output = STEP_ENV.scope(step_env(), client.execute("Env?")).await.unwrap();
+        assert_eq!(output.trim(), "hello|us|kept|dropped");
+
+        // The step's variables are set whatever it passes through
+        let passthrough = Some(vec!["PIPELINE_TEST_ENV_KEPT".to_string()]);
+        let run = STEP_ENV_PASSTHROUGH.scope(passthrough, client.execute("Env?"));
+        let output = STEP_ENV.scope(step_env(), run).await.unwrap();
+        assert_eq!(output.trim(), "hello|us|kept|");
+
+        let output = STEP_ENV_PASSTHROUGH.scope(Some(vec![]), client.execute("Env?")).await.unwrap();
+        assert_eq!(output.trim(), "|||");
+    }
 }
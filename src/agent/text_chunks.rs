@@ -0,0 +1,72 @@
+//! Forwarding of agent text as it arrives
+//!
+//! Progress callbacks run synchronously inside the agent client, so
+//! `TextChunkCallback` hands each text delta to a channel; the engine turns
+//! what it receives into `StepOutputChunk` events for its handlers.
+
+use crate::agent::pi_events::AssistantMessageEvent;
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Progress callback that sends the agent's text deltas to a channel
+///
+/// All events are forwarded to the optional inner callback. Deltas sent
+/// after the receiver is gone are dropped.
+pub struct TextChunkCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    sender: UnboundedSender<String>,
+}
+
+impl<'a> TextChunkCallback<'a> {
+    /// Create a forwarder wrapping an optional inner callback
+    pub fn new(inner: Option<&'a dyn ProgressCallback>, sender: UnboundedSender<String>) -> Self {
+        Self { inner, sender }
+    }
+}
+
+impl ProgressCallback for TextChunkCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        if let PiJsonEvent::MessageUpdate {
+            assistant_message_event: Some(AssistantMessageEvent::TextDelta { delta, .. }),
+            ..
+        } = event
+        {
+            let _ = self.sender.send(delta.clone());
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.inner.and_then(|inner| inner.abort_reason())
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwards_text_deltas_only() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let callback = TextChunkCallback::new(None, sender);
+        for line in [
+            r#"{"type":"agent_start"}"#,
+            r#"{"type":"message_update","assistantMessageEvent":{"type":"thinking_delta","contentIndex":0,"delta":"hmm"}}"#,
+            r#"{"type":"message_update","assistantMessageEvent":{"type":"text_delta","contentIndex":0,"delta":"Hello"}}"#,
+            r#"{"type":"message_update","assistantMessageEvent":{"type":"text_delta","contentIndex":0,"delta":", world"}}"#,
+        ] {
+            callback.on_event(&PiJsonEvent::parse(line).unwrap());
+        }
+
+        assert_eq!(receiver.try_recv().unwrap(), "Hello");
+        assert_eq!(receiver.try_recv().unwrap(), ", world");
+        assert!(receiver.try_recv().is_err());
+    }
+}
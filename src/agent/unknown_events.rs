@@ -0,0 +1,85 @@
+//! Tracking of pi events this version does not understand
+//!
+//! Pi's JSON schema grows with new releases. Events of unknown types reach
+//! callbacks as `PiJsonEvent::Unknown`; `UnknownEventCallback` counts them by
+//! type so the engine can report them once per step instead of per line.
+
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// Progress callback that counts unknown events by type
+///
+/// All events are forwarded to the optional inner callback.
+pub struct UnknownEventCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    counts: Mutex<BTreeMap<String, usize>>,
+}
+
+impl<'a> UnknownEventCallback<'a> {
+    /// Create a new tracker wrapping an optional inner callback
+    pub fn new(inner: Option<&'a dyn ProgressCallback>) -> Self {
+        Self {
+            inner,
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Unknown event types seen so far with their counts, sorted by type
+    pub fn counts(&self) -> Vec<(String, usize)> {
+        self.counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(event_type, count)| (event_type.clone(), *count))
+            .collect()
+    }
+}
+
+impl ProgressCallback for UnknownEventCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        if let Some(event_type) = event.unknown_type() {
+            *self
+                .counts
+                .lock()
+                .unwrap()
+                .entry(event_type.to_string())
+                .or_default() += 1;
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        self.inner.and_then(|inner| inner.abort_reason())
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_unknown_events_by_type() {
+        let tracker = UnknownEventCallback::new(None);
+        for line in [
+            r#"{"type":"compaction_start"}"#,
+            r#"{"type":"agent_start"}"#,
+            r#"{"type":"retry"}"#,
+            r#"{"type":"compaction_start"}"#,
+        ] {
+            tracker.on_event(&PiJsonEvent::parse(line).unwrap());
+        }
+
+        assert_eq!(
+            tracker.counts(),
+            vec![("compaction_start".to_string(), 2), ("retry".to_string(), 1)]
+        );
+    }
+}
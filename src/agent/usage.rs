@@ -0,0 +1,117 @@
+//! Cost tracking from the usage pi reports
+//!
+//! Every assistant turn ends with a `turn_end` event whose message carries
+//! the tokens used and their cost. `UsageTrackingCallback` sums those so the
+//! engine can attribute spending to steps and stop an agent that overruns
+//! its budget.
+
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use std::sync::Mutex;
+
+/// Progress callback that totals the cost of the agent's turns
+///
+/// All events are forwarded to the optional inner callback. When a limit is
+/// set and exceeded, `abort_reason()` asks the agent client to stop.
+pub struct UsageTrackingCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    limit_usd: Option<f64>,
+    cost_usd: Mutex<f64>,
+    tokens: Mutex<u64>,
+}
+
+impl<'a> UsageTrackingCallback<'a> {
+    /// Create a new tracker wrapping an optional inner callback
+    pub fn new(inner: Option<&'a dyn ProgressCallback>, limit_usd: Option<f64>) -> Self {
+        Self {
+            inner,
+            limit_usd,
+            cost_usd: Mutex::new(0.0),
+            tokens: Mutex::new(0),
+        }
+    }
+
+    /// Cost of the turns so far, in USD
+    pub fn cost_usd(&self) -> f64 {
+        *self.cost_usd.lock().unwrap()
+    }
+
+    /// Tokens used by the turns so far
+    pub fn tokens(&self) -> u64 {
+        *self.tokens.lock().unwrap()
+    }
+}
+
+impl ProgressCallback for UsageTrackingCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        if let PiJsonEvent::TurnEnd {
+            message: Some(message),
+            ..
+        } = event
+        {
+            if let Some(usage) = &message.usage {
+                *self.cost_usd.lock().unwrap() += usage.cost.total;
+                *self.tokens.lock().unwrap() += usage.total_tokens;
+            }
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        match self.limit_usd {
+            Some(limit) if self.cost_usd() > limit => Some(format!(
+                "Agent spent ${:.2}, exceeding the remaining budget of ${:.2}",
+                self.cost_usd(),
+                limit
+            )),
+            _ => self.inner.and_then(|inner| inner.abort_reason()),
+        }
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn_end(cost: f64, tokens: u64) -> PiJsonEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "turn_end",
+            "message": {
+                "role": "assistant",
+                "content": [],
+                "usage": {"totalTokens": tokens, "cost": {"total": cost}}
+            },
+            "toolResults": []
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sums_turn_costs() {
+        let tracker = UsageTrackingCallback::new(None, None);
+        tracker.on_event(&turn_end(0.25, 1000));
+        tracker.on_event(&PiJsonEvent::AgentEnd);
+        tracker.on_event(&turn_end(0.5, 3000));
+
+        assert!((tracker.cost_usd() - 0.75).abs() < 1e-9);
+        assert_eq!(tracker.tokens(), 4000);
+        assert!(tracker.abort_reason().is_none());
+    }
+
+    #[test]
+    fn test_aborts_over_limit() {
+        let tracker = UsageTrackingCallback::new(None, Some(1.0));
+        tracker.on_event(&turn_end(0.75, 1000));
+        assert!(tracker.abort_reason().is_none());
+
+        tracker.on_event(&turn_end(0.5, 1000));
+        let reason = tracker.abort_reason().unwrap();
+        assert!(reason.contains("$1.25"), "{}", reason);
+    }
+}
@@ -0,0 +1,167 @@
+//! Tracking of file writes performed by the agent
+//!
+//! Pi reports every tool call it makes (`ToolcallEnd`). `WriteTrackingCallback`
+//! sums the bytes passed to the `write` and `edit` tools so the engine can
+//! report how much a step wrote and stop agents that run away generating files.
+
+use crate::agent::pi_events::AssistantMessageEvent;
+use crate::agent::{PiJsonEvent, ProgressCallback};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+/// Number of bytes a tool call writes to disk (0 for non-writing tools)
+///
+/// For `write` this is the full file content; for `edit` it is the
+/// replacement text.
+pub fn bytes_written_by(tool_name: &str, arguments: &Value) -> u64 {
+    let text_len = |value: &Value, key: &str| {
+        value.get(key).and_then(Value::as_str).map_or(0, |s| s.len() as u64)
+    };
+
+    match tool_name {
+        "write" => text_len(arguments, "content"),
+        "edit" => match arguments.get("edits").and_then(Value::as_array) {
+            Some(edits) => edits.iter().map(|edit| text_len(edit, "newText")).sum(),
+            None => text_len(arguments, "newText"),
+        },
+        _ => 0,
+    }
+}
+
+/// Progress callback that totals bytes written by the agent
+///
+/// All events are forwarded to the optional inner callback. When a limit is
+/// set and exceeded, `abort_reason()` asks the agent client to stop.
+pub struct WriteTrackingCallback<'a> {
+    inner: Option<&'a dyn ProgressCallback>,
+    max_bytes: Option<u64>,
+    bytes: Mutex<u64>,
+    files: Mutex<HashSet<String>>,
+}
+
+impl<'a> WriteTrackingCallback<'a> {
+    /// Create a new tracker wrapping an optional inner callback
+    pub fn new(inner: Option<&'a dyn ProgressCallback>, max_bytes: Option<u64>) -> Self {
+        Self {
+            inner,
+            max_bytes,
+            bytes: Mutex::new(0),
+            files: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Total bytes written so far
+    pub fn bytes_written(&self) -> u64 {
+        *self.bytes.lock().unwrap()
+    }
+
+    /// Number of distinct files written so far
+    pub fn files_written(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    /// Paths written so far, sorted
+    pub fn paths_written(&self) -> Vec<String> {
+        let mut paths: Vec<_> = self.files.lock().unwrap().iter().cloned().collect();
+        paths.sort();
+        paths
+    }
+}
+
+impl ProgressCallback for WriteTrackingCallback<'_> {
+    fn on_event(&self, event: &PiJsonEvent) {
+        if let PiJsonEvent::MessageUpdate {
+            assistant_message_event: Some(AssistantMessageEvent::ToolcallEnd { tool_call, .. }),
+            ..
+        } = event
+        {
+            let bytes = bytes_written_by(&tool_call.name, &tool_call.arguments);
+            if bytes > 0 {
+                *self.bytes.lock().unwrap() += bytes;
+                if let Some(path) = tool_call.arguments.get("path").and_then(Value::as_str) {
+                    self.files.lock().unwrap().insert(path.to_string());
+                }
+            }
+        }
+
+        if let Some(inner) = self.inner {
+            inner.on_event(event);
+        }
+    }
+
+    fn abort_reason(&self) -> Option<String> {
+        match self.max_bytes {
+            Some(max) if self.bytes_written() > max => Some(format!(
+                "Agent wrote {} bytes, exceeding max_write_bytes of {}",
+                self.bytes_written(),
+                max
+            )),
+            _ => self.inner.and_then(|inner| inner.abort_reason()),
+        }
+    }
+
+    fn get_context_lines(&self) -> Option<Vec<String>> {
+        self.inner.and_then(|inner| inner.get_context_lines())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::pi_events::{Message, ToolCall};
+    use serde_json::json;
+
+    fn tool_call_end(name: &str, arguments: Value) -> PiJsonEvent {
+        PiJsonEvent::MessageUpdate {
+            assistant_message_event: Some(AssistantMessageEvent::ToolcallEnd {
+                content_index: 0,
+                tool_call: ToolCall {
+                    tool_type: "toolCall".to_string(),
+                    id: "call_1".to_string(),
+                    name: name.to_string(),
+                    arguments,
+                },
+                partial: Message {
+                    role: "assistant".to_string(),
+                    usage: None,
+                    content: vec![],
+                },
+            }),
+            message: None,
+        }
+    }
+
+    #[test]
+    fn test_bytes_written_by_tool() {
+        assert_eq!(bytes_written_by("write", &json!({"path": "a", "content": "hello"})), 5);
+        assert_eq!(bytes_written_by("edit", &json!({"path": "a", "oldText": "x", "newText": "abc"})), 3);
+        assert_eq!(
+            bytes_written_by("edit", &json!({"path": "a", "edits": [{"newText": "ab"}, {"newText": "cd"}]})),
+            4
+        );
+        assert_eq!(bytes_written_by("bash", &json!({"command": "echo hi > a"})), 0);
+    }
+
+    #[test]
+    fn test_tracks_bytes_and_files() {
+        let tracker = WriteTrackingCallback::new(None, None);
+        tracker.on_event(&tool_call_end("write", json!({"path": "a.rs", "content": "12345"})));
+        tracker.on_event(&tool_call_end("edit", json!({"path": "a.rs", "oldText": "1", "newText": "99"})));
+        tracker.on_event(&tool_call_end("read", json!({"path": "b.rs"})));
+
+        assert_eq!(tracker.bytes_written(), 7);
+        assert_eq!(tracker.files_written(), 1);
+        assert!(tracker.abort_reason().is_none());
+    }
+
+    #[test]
+    fn test_aborts_over_limit() {
+        let tracker = WriteTrackingCallback::new(None, Some(4));
+        tracker.on_event(&tool_call_end("write", json!({"path": "a", "content": "1234"})));
+        assert!(tracker.abort_reason().is_none());
+
+        tracker.on_event(&tool_call_end("write", json!({"path": "b", "content": "5"})));
+        assert!(tracker.abort_reason().unwrap().contains("max_write_bytes"));
+    }
+}
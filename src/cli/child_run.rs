@@ -0,0 +1,61 @@
+//! Runs started as child `pipeline run` processes
+//!
+//! `pipeline serve` and `pipeline scheduler` start each run as a separate
+//! process of this binary on the same data directory, so it saves to
+//! history, takes locks and sends webhooks as it would from the command
+//! line. The run's execution ID is chosen up front so it can be followed
+//! before the process has written anything.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use uuid::Uuid;
+
+/// A run to start: what to run, with what, and where its output goes
+#[derive(Debug, Clone)]
+pub struct ChildRun<'a> {
+    /// This binary
+    pub program: &'a Path,
+    pub data_dir: &'a Path,
+    pub file: &'a Path,
+    pub execution_id: Uuid,
+    pub variables: &'a BTreeMap<String, String>,
+    /// Setting overrides by dotted path, as `--set` takes them
+    pub overrides: &'a BTreeMap<String, String>,
+    /// File the process's stdout and stderr are written to
+    pub output: PathBuf,
+}
+
+impl ChildRun<'_> {
+    /// Start the process, passing `extra` arguments to `pipeline run` after
+    /// the rest
+    pub fn spawn(&self, extra: &[&std::ffi::OsStr]) -> Result<tokio::process::Child> {
+        std::fs::create_dir_all(self.output.parent().unwrap_or(Path::new("")))?;
+        let output = std::fs::File::create(&self.output)?;
+
+        let mut command = tokio::process::Command::new(self.program);
+        command
+            .arg("--data-dir")
+            .arg(self.data_dir)
+            .arg("run")
+            .arg("--file")
+            .arg(self.file)
+            .arg("--execution-id")
+            .arg(self.execution_id.to_string());
+        for (key, value) in self.variables {
+            command.arg("--variable").arg(format!("{}={}", key, value));
+        }
+        for (path, value) in self.overrides {
+            command.arg("--set").arg(format!("{}={}", path, value));
+        }
+        command
+            .args(extra)
+            .stdin(Stdio::null())
+            .stdout(output.try_clone()?)
+            .stderr(output);
+        command
+            .spawn()
+            .with_context(|| format!("Failed to run {}", self.program.display()))
+    }
+}
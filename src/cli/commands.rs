@@ -1,10 +1,16 @@
 //! CLI command definitions
 
-use clap::Args;
-use crate::execution::SchedulingStrategy;
+use chrono::{DateTime, Utc};
+use clap::{Args, Subcommand};
+use crate::agent::ChaosConfig;
+use crate::cli::graph::GraphFormat;
+use crate::cli::report::ReportFormat;
+use crate::core::config::Priority;
+use crate::core::ExecutionStatus;
+use crate::execution::{SchedulingStrategy, DEFAULT_MAX_PARALLEL};
 
 /// Run a pipeline
-#[derive(Debug, Args, Clone)]
+#[derive(Debug, Args, Clone, Default)]
 pub struct RunCommand {
     /// Path to pipeline YAML file
     #[arg(short, long)]
@@ -14,9 +20,22 @@ pub struct RunCommand {
     #[arg(long, value_parser = parse_key_value)]
     pub variable: Vec<(String, String)>,
 
-    /// Scheduling strategy
-    #[arg(long, value_enum, default_value_t = SchedulingStrategyArg::Sequential)]
-    pub strategy: SchedulingStrategyArg,
+    /// Override a pipeline setting (path=value, e.g. steps.test.timeout_secs=600)
+    #[arg(long = "set", value_parser = parse_key_value)]
+    pub set: Vec<(String, String)>,
+
+    /// Scheduling strategy (default: `strategy` from the user config, else sequential)
+    #[arg(long, value_enum)]
+    pub strategy: Option<SchedulingStrategyArg>,
+
+    /// Most steps running at once with the parallel strategies (default:
+    /// `max_parallel` from the pipeline, else 4 for parallel-limited)
+    #[arg(long, value_name = "N", value_parser = parse_max_parallel)]
+    pub max_parallel: Option<usize>,
+
+    /// Priority against other runs on this machine (default: `priority` from the pipeline, else normal)
+    #[arg(long, value_enum)]
+    pub priority: Option<PriorityArg>,
 
     /// Don't save execution to history
     #[arg(long)]
@@ -26,9 +45,90 @@ pub struct RunCommand {
     #[arg(long)]
     pub from_step: Option<String>,
 
+    /// Run steps that declare `inputs` even when their inputs are unchanged
+    #[arg(long)]
+    pub force: bool,
+
+    /// Show agent thinking (reasoning output)
+    #[arg(long)]
+    pub show_thinking: bool,
+
+    /// Render at most this many bytes of agent text per second (excess is skipped)
+    #[arg(long, value_name = "BYTES_PER_SEC")]
+    pub max_output_rate: Option<usize>,
+
+    /// Follow the run in a terminal dashboard (keys: p pause, s skip, c cancel)
+    #[cfg(feature = "tui")]
+    #[arg(long, conflicts_with = "confirm_tools")]
+    pub tui: bool,
+
+    /// Ask for confirmation before the agent runs these tools (e.g. write,bash)
+    #[arg(long, value_delimiter = ',')]
+    pub confirm_tools: Vec<String>,
+
+    /// Seconds the operator gets to retry, skip, reroute or abort a failed step in an attended run (0 turns the prompt off; default: 30)
+    #[arg(long, value_name = "SECS")]
+    pub failure_prompt_secs: Option<u64>,
+
+    /// Exit with an error if any warnings were raised during the run
+    #[arg(long)]
+    pub fail_on_warnings: bool,
+
+    /// Run every step in pi's text mode instead of JSON streaming
+    #[arg(long)]
+    pub no_streaming: bool,
+
+    /// Fail a step when pi sends an event type this version does not understand
+    #[arg(long)]
+    pub strict_events: bool,
+
+    /// Append every execution event to this file as a line of JSON
+    #[arg(long, value_name = "PATH")]
+    pub event_log: Option<std::path::PathBuf>,
+
+    /// Use this execution ID instead of a new one (set by `pipeline serve`)
+    #[arg(long, hide = true)]
+    pub execution_id: Option<uuid::Uuid>,
+
+    /// Truncate pi events longer than this many bytes, e.g. huge tool results (default: 16 MiB)
+    #[arg(long, value_name = "BYTES")]
+    pub max_event_bytes: Option<usize>,
+
+    /// Ask each step's agent for a plan instead of doing the work
+    #[arg(long)]
+    pub plan: bool,
+
+    /// Inject agent failures for testing (e.g. 0.1 or timeout=0.1,truncate=0.2)
+    #[arg(long)]
+    pub chaos: Option<ChaosConfig>,
+
+    /// Seed for --chaos so a failure sequence can be reproduced
+    #[arg(long, requires = "chaos")]
+    pub chaos_seed: Option<u64>,
+
+    /// Seed of the run, `{{ run.seed }}` (default: random); pass the seed
+    /// `history <ID>` shows to re-run with the same one
+    #[arg(long)]
+    pub seed: Option<u32>,
+}
+
+/// Resume a run that stopped before finishing
+#[derive(Debug, Args, Clone)]
+pub struct ResumeCommand {
+    /// Execution ID (or a unique prefix) of the run to resume
+    pub execution_id: String,
+
+    /// Scheduling strategy (default: `strategy` from the user config, else sequential)
+    #[arg(long, value_enum)]
+    pub strategy: Option<SchedulingStrategyArg>,
+
     /// Show agent thinking (reasoning output)
     #[arg(long)]
     pub show_thinking: bool,
+
+    /// Append every execution event to this file as a line of JSON
+    #[arg(long, value_name = "PATH")]
+    pub event_log: Option<std::path::PathBuf>,
 }
 
 /// Validate a pipeline configuration
@@ -41,6 +141,61 @@ pub struct ValidateCommand {
     /// Output in JSON format
     #[arg(long)]
     pub json: bool,
+
+    /// Reject keys that are not pipeline settings (e.g. a misspelled
+    /// `termination:`) and prompts referencing undefined variables
+    #[arg(long)]
+    pub strict: bool,
+}
+
+/// Print the JSON Schema of pipeline files
+#[derive(Debug, Args, Clone)]
+pub struct SchemaCommand {
+    /// Write the schema to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+/// Estimate the cost and duration of a run
+#[derive(Debug, Args, Clone)]
+pub struct EstimateCommand {
+    /// Path to pipeline YAML file
+    pub file: String,
+
+    /// Variable overrides (key=value)
+    #[arg(long, value_parser = parse_key_value)]
+    pub variable: Vec<(String, String)>,
+
+    /// Override a pipeline setting (path=value, e.g. steps.test.timeout_secs=600)
+    #[arg(long = "set", value_parser = parse_key_value)]
+    pub set: Vec<(String, String)>,
+
+    /// Number of recent runs to base the estimate on
+    #[arg(long, default_value_t = crate::cli::estimate::DEFAULT_HISTORY_RUNS)]
+    pub runs: usize,
+
+    /// Output in JSON format
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Draw the dependency and routing graph of a pipeline
+#[derive(Debug, Args, Clone)]
+pub struct GraphCommand {
+    /// Path to pipeline YAML file
+    pub file: String,
+
+    /// Graph format
+    #[arg(long, value_enum, default_value_t = GraphFormat::Ascii)]
+    pub format: GraphFormat,
+
+    /// Show the status of each step in this execution (ID or unique prefix)
+    #[arg(long, value_name = "ID")]
+    pub execution: Option<String>,
+
+    /// Write the graph to a file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
 }
 
 /// List available pipelines
@@ -55,9 +210,15 @@ pub struct ListCommand {
     pub json: bool,
 }
 
+/// Lines of each step's output `history --verbose` shows unless told otherwise
+pub const DEFAULT_MAX_OUTPUT_LINES: usize = 200;
+
 /// Show execution history
 #[derive(Debug, Args, Clone)]
 pub struct HistoryCommand {
+    /// Execution to show (ID or a unique prefix of it)
+    pub id: Option<String>,
+
     /// Pipeline name to filter by
     #[arg(short, long)]
     pub pipeline: Option<String>,
@@ -70,6 +231,14 @@ pub struct HistoryCommand {
     #[arg(long)]
     pub verbose: bool,
 
+    /// Most lines of each step's output to show with --verbose (0: all)
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_MAX_OUTPUT_LINES)]
+    pub max_output_lines: usize,
+
+    /// Show only these steps' details (repeatable; implies --verbose)
+    #[arg(long = "step", value_name = "ID", requires = "id")]
+    pub steps: Vec<String>,
+
     /// Output in JSON format
     #[arg(long)]
     pub json: bool,
@@ -77,10 +246,327 @@ pub struct HistoryCommand {
     /// Show executions for a specific execution ID
     #[arg(long)]
     pub execution_id: Option<String>,
+
+    /// Show the hashes of each step's prompt template and rendered prompt
+    #[arg(long, requires = "id")]
+    pub prompts: bool,
+
+    /// List the artifacts the run's steps collected
+    #[arg(long, requires = "id")]
+    pub artifacts: bool,
+
+    /// Copy the run's artifacts into this directory, checking their hashes
+    #[arg(long, value_name = "DIR", requires = "id")]
+    pub extract: Option<std::path::PathBuf>,
+
+    #[command(subcommand)]
+    pub action: Option<HistoryAction>,
+}
+
+/// History subcommands
+#[derive(Debug, Subcommand, Clone)]
+pub enum HistoryAction {
+    /// Show where a run's files live (and optionally open them)
+    Open(HistoryOpenCommand),
+
+    /// List webhook notifications that could not be delivered
+    Undelivered(HistoryUndeliveredCommand),
+
+    /// Find executions by status, start time and step text
+    Search(HistorySearchCommand),
+}
+
+/// Locate the files produced by a run
+#[derive(Debug, Args, Clone)]
+pub struct HistoryOpenCommand {
+    /// Execution ID (or a unique prefix of it)
+    pub execution_id: String,
+
+    /// Launch the system file opener on the run directory
+    #[arg(long)]
+    pub open: bool,
+}
+
+/// List undelivered webhook notifications
+#[derive(Debug, Args, Clone)]
+pub struct HistoryUndeliveredCommand {
+    /// Number of recent notifications to show
+    #[arg(short, long, default_value_t = 20)]
+    pub limit: usize,
+
+    /// Output in JSON format, including the payloads
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Search execution history
+#[derive(Debug, Args, Clone)]
+pub struct HistorySearchCommand {
+    /// Only executions with this status (repeat for any of several)
+    #[arg(long)]
+    pub status: Vec<ExecutionStatus>,
+
+    /// Only executions started within this long (`30m`, `12h`, `7d`, `2w`)
+    /// or since this date (`2024-05-14`, RFC 3339)
+    #[arg(long, value_parser = parse_since)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only executions with a step whose ID, output or error contains this
+    /// text (ignoring case)
+    #[arg(long)]
+    pub contains: Option<String>,
+
+    /// Pipeline name to filter by
+    #[arg(short, long)]
+    pub pipeline: Option<String>,
+
+    /// Number of matching executions to show
+    #[arg(short, long, default_value_t = 10)]
+    pub limit: usize,
+
+    /// Output in JSON format
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Show aggregate metrics of past runs
+#[derive(Debug, Args, Clone)]
+pub struct StatsCommand {
+    /// Pipeline name to filter by
+    #[arg(short, long)]
+    pub pipeline: Option<String>,
+
+    /// Only runs started within this long (`30m`, `12h`, `7d`, `2w`) or
+    /// since this date (`2024-05-14`, RFC 3339)
+    #[arg(long, value_parser = parse_since)]
+    pub since: Option<DateTime<Utc>>,
+
+    /// Output in JSON format
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Follow the events of a run as they happen
+#[derive(Debug, Args, Clone)]
+pub struct TailCommand {
+    /// Execution ID (or a unique prefix of it); defaults to the most recent run
+    pub execution_id: Option<String>,
+
+    /// Number of earlier events to show before following
+    #[arg(short = 'n', long, default_value_t = 20)]
+    pub lines: usize,
+}
+
+/// Show or follow the step logs of a run
+#[derive(Debug, Args, Clone)]
+pub struct LogsCommand {
+    /// Execution ID (or a unique prefix of it)
+    pub execution_id: String,
+
+    /// Step to show; without it, the run's step logs are listed
+    pub step_id: Option<String>,
+
+    /// Show only the last N lines
+    #[arg(short = 'n', long)]
+    pub lines: Option<usize>,
+
+    /// Keep printing the step's output as it is written
+    #[arg(short, long, requires = "step_id")]
+    pub follow: bool,
+
+    /// Directory holding the logs (default: .pipeline/logs)
+    #[arg(long)]
+    pub dir: Option<std::path::PathBuf>,
+}
+
+/// Generate a report of a run
+#[derive(Debug, Args, Clone)]
+pub struct ReportCommand {
+    /// Execution ID (or a unique prefix of it)
+    pub execution_id: String,
+
+    /// Report format
+    #[arg(long, value_enum, default_value_t = ReportFormat::Md)]
+    pub format: ReportFormat,
+
+    /// Write the report to a file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+/// Compare two runs of a pipeline
+#[derive(Debug, Args, Clone)]
+pub struct DiffCommand {
+    /// Earlier execution ID (or a unique prefix of it)
+    pub execution_a: String,
+
+    /// Later execution ID (or a unique prefix of it)
+    pub execution_b: String,
+
+    /// Lines of unchanged output shown around each change
+    #[arg(long, default_value_t = crate::cli::diff::DEFAULT_CONTEXT_LINES)]
+    pub context: usize,
+}
+
+/// Cancel a run in progress in another process
+#[derive(Debug, Args, Clone)]
+pub struct CancelCommand {
+    /// Execution ID (or a unique prefix of it)
+    pub execution_id: String,
+
+    /// Start no more steps, but let the running ones finish and be recorded
+    #[arg(long)]
+    pub after_current: bool,
+}
+
+/// Release a pipeline's run lock
+#[derive(Debug, Args, Clone)]
+pub struct UnlockCommand {
+    /// Pipeline name whose lock should be removed
+    pub pipeline: String,
+}
+
+/// Cut some steps and their dependencies out into a standalone pipeline
+#[derive(Debug, Args, Clone)]
+pub struct ExtractCommand {
+    /// Path to pipeline YAML file
+    pub file: String,
+
+    /// Steps to keep (comma-separated); their dependencies come along
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub steps: Vec<String>,
+
+    /// Write the pipeline to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<String>,
+}
+
+/// Serve the local web UI
+#[cfg(feature = "ui")]
+#[derive(Debug, Args, Clone)]
+pub struct UiCommand {
+    /// Port to listen on (localhost only)
+    #[arg(short, long, default_value_t = 8765)]
+    pub port: u16,
+
+    /// Show this execution (ID or unique prefix) instead of the most recent run
+    pub execution_id: Option<String>,
+}
+
+/// Serve the HTTP API
+#[cfg(feature = "serve")]
+#[derive(Debug, Args, Clone)]
+pub struct ServeCommand {
+    /// Address to listen on; anything but localhost requires --token-env
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+
+    /// Port to listen on
+    #[arg(short, long, default_value_t = 8766)]
+    pub port: u16,
+
+    /// Environment variable holding the bearer token requests must send
+    #[arg(long, value_name = "VAR")]
+    pub token_env: Option<String>,
+
+    /// YAML file of trigger rules that start runs for signed webhooks
+    #[arg(long, value_name = "FILE")]
+    pub triggers: Option<std::path::PathBuf>,
+}
+
+/// Read or change user-level defaults
+#[derive(Debug, Args, Clone)]
+pub struct ConfigCommand {
+    #[command(subcommand)]
+    pub action: ConfigAction,
+}
+
+/// Config subcommands
+#[derive(Debug, Subcommand, Clone)]
+pub enum ConfigAction {
+    /// Print a setting, or every setting that is set
+    Get {
+        /// Setting name (pi_path, strategy, color, data_dir, model)
+        key: Option<String>,
+    },
+
+    /// Change a setting
+    Set {
+        /// Setting name
+        key: String,
+        /// New value
+        value: String,
+    },
+
+    /// Remove a setting so the built-in default applies
+    Unset {
+        /// Setting name
+        key: String,
+    },
+
+    /// Print the location of the config file
+    Path,
+}
+
+/// Run pipelines on cron schedules
+#[derive(Debug, Args, Clone)]
+pub struct SchedulerCommand {
+    #[command(subcommand)]
+    pub action: SchedulerAction,
+}
+
+/// Scheduler subcommands
+#[derive(Debug, Subcommand, Clone)]
+pub enum SchedulerAction {
+    /// Start runs as schedules come due, until stopped
+    Run {
+        /// YAML file of schedules
+        #[arg(short, long, default_value = "schedules.yaml")]
+        file: std::path::PathBuf,
+    },
+
+    /// Show each schedule's next due time and last run
+    List {
+        /// YAML file of schedules
+        #[arg(short, long, default_value = "schedules.yaml")]
+        file: std::path::PathBuf,
+    },
+}
+
+/// Hand work between pipelines through named queues
+#[derive(Debug, Args, Clone)]
+pub struct QueueCommand {
+    #[command(subcommand)]
+    pub action: QueueAction,
+}
+
+/// Queue subcommands
+#[derive(Debug, Subcommand, Clone)]
+pub enum QueueAction {
+    /// Add items to the back of a queue
+    Push {
+        /// Queue name
+        queue: String,
+        /// Items to add (default: one per non-empty line of stdin)
+        items: Vec<String>,
+    },
+
+    /// Take items from the front of a queue and print them, one per line
+    Pop {
+        /// Queue name
+        queue: String,
+        /// Most items to take
+        #[arg(short, long, default_value_t = 1)]
+        batch: usize,
+    },
+
+    /// Show the queues with items waiting
+    List,
 }
 
 /// Scheduling strategy argument
-#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum SchedulingStrategyArg {
     Sequential,
     Parallel,
@@ -93,12 +579,62 @@ impl From<SchedulingStrategyArg> for SchedulingStrategy {
         match arg {
             SchedulingStrategyArg::Sequential => SchedulingStrategy::Sequential,
             SchedulingStrategyArg::Parallel => SchedulingStrategy::Parallel,
-            SchedulingStrategyArg::ParallelLimited => SchedulingStrategy::LimitedParallel(4),
+            SchedulingStrategyArg::ParallelLimited => SchedulingStrategy::LimitedParallel(DEFAULT_MAX_PARALLEL),
+        }
+    }
+}
+
+/// Run priority argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PriorityArg {
+    Low,
+    Normal,
+    High,
+}
+
+impl From<PriorityArg> for Priority {
+    fn from(arg: PriorityArg) -> Self {
+        match arg {
+            PriorityArg::Low => Priority::Low,
+            PriorityArg::Normal => Priority::Normal,
+            PriorityArg::High => Priority::High,
         }
     }
 }
 
 /// Parse key=value pairs
+/// Parse a `--since` value: an age back from now (`30m`, `12h`, `7d`, `2w`),
+/// a date (midnight UTC) or an RFC 3339 time
+pub fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    let s = s.trim();
+    if let Some(unit) = s.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        if let Ok(amount) = s[..s.len() - 1].parse::<i64>() {
+            let age = match unit {
+                'm' => chrono::Duration::minutes(amount),
+                'h' => chrono::Duration::hours(amount),
+                'd' => chrono::Duration::days(amount),
+                'w' => chrono::Duration::weeks(amount),
+                _ => return Err(format!("Unknown unit '{}' in {} (expected m, h, d or w)", unit, s)),
+            };
+            return Ok(Utc::now() - age);
+        }
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| format!("Invalid time: {} (expected an age like 7d, a date or an RFC 3339 time)", s))
+}
+
+fn parse_max_parallel(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 pub fn parse_key_value(s: &str) -> Result<(String, String), String> {
     let parts: Vec<&str> = s.splitn(2, '=').collect();
     if parts.len() != 2 {
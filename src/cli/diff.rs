@@ -0,0 +1,321 @@
+//! Comparison of two runs of a pipeline
+//!
+//! Built from the execution summaries and step results in history: changes
+//! in status, attempts and duration per step, and a unified diff of the
+//! outputs that were kept.
+
+use crate::cli::output::{format_duration, style};
+use crate::persistence::{ExecutionSummary, StepResult};
+use chrono::{DateTime, Utc};
+
+/// Lines of unchanged output shown around each change
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+/// Largest outputs (lines before × lines after) diffed line by line; bigger
+/// ones are shown as entirely replaced
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Render the differences between run `a` and run `b`
+pub fn render_diff(
+    a: (&ExecutionSummary, &[StepResult]),
+    b: (&ExecutionSummary, &[StepResult]),
+    context: usize,
+) -> String {
+    let ((a, a_steps), (b, b_steps)) = (a, b);
+    let (a_id, b_id) = (short_id(a), short_id(b));
+    let mut out = String::new();
+
+    out.push_str(&format!(
+        "{} {} → {}\n",
+        style(&b.pipeline_name).bold(),
+        style(&a_id).dim(),
+        style(&b_id).dim()
+    ));
+    if a.status != b.status {
+        out.push_str(&format!(
+            "  Status: {:?} → {}\n",
+            a.status,
+            style(format!("{:?}", b.status)).yellow()
+        ));
+    } else {
+        out.push_str(&format!("  Status: {:?}\n", b.status));
+    }
+    out.push_str(&format!(
+        "  Duration: {}\n",
+        compare_durations(elapsed(Some(a.started_at), a.completed_at), elapsed(Some(b.started_at), b.completed_at))
+    ));
+
+    // Steps of run a in order, then the ones only run b has
+    let mut step_ids: Vec<&str> = a_steps.iter().map(|s| s.step_id.as_str()).collect();
+    for step in b_steps {
+        if !step_ids.contains(&step.step_id.as_str()) {
+            step_ids.push(&step.step_id);
+        }
+    }
+
+    out.push_str("\nSteps:\n");
+    let mut changed_outputs = Vec::new();
+    for step_id in step_ids {
+        let before = a_steps.iter().find(|s| s.step_id == step_id);
+        let after = b_steps.iter().find(|s| s.step_id == step_id);
+
+        let status = match (before, after) {
+            (Some(x), Some(y)) if x.status == y.status => x.status.clone(),
+            (x, y) => format!(
+                "{} → {}",
+                x.map_or("-", |s| s.status.as_str()),
+                style(y.map_or("-", |s| s.status.as_str())).yellow()
+            ),
+        };
+        let attempts = match (before.map(|s| s.attempts), after.map(|s| s.attempts)) {
+            (Some(x), Some(y)) if x == y => x.to_string(),
+            (x, y) => format!("{} → {}", count(x), count(y)),
+        };
+        let duration = compare_durations(
+            before.and_then(|s| elapsed(s.started_at, s.finished_at)),
+            after.and_then(|s| elapsed(s.started_at, s.finished_at)),
+        );
+        out.push_str(&format!(
+            "  {}  {}, attempts {}, {}\n",
+            style(step_id).bold(),
+            status,
+            attempts,
+            duration
+        ));
+
+        let outputs = (before.and_then(|s| s.output.as_deref()), after.and_then(|s| s.output.as_deref()));
+        if let (Some(x), Some(y)) = outputs {
+            if let Some(diff) = unified_diff(x, y, context) {
+                changed_outputs.push((step_id, diff));
+            }
+        }
+    }
+
+    for (step_id, diff) in changed_outputs {
+        out.push_str(&format!("\n{}\n", style(format!("--- {} ({})", step_id, a_id)).bold()));
+        out.push_str(&format!("{}\n", style(format!("+++ {} ({})", step_id, b_id)).bold()));
+        for line in diff.lines() {
+            let line = match line.chars().next() {
+                Some('-') => style(line).red(),
+                Some('+') => style(line).green(),
+                Some('@') => style(line).cyan(),
+                _ => style(line),
+            };
+            out.push_str(&format!("{}\n", line));
+        }
+    }
+
+    out
+}
+
+fn short_id(summary: &ExecutionSummary) -> String {
+    summary.execution_id.to_string()[..8].to_string()
+}
+
+fn count(value: Option<usize>) -> String {
+    value.map_or("-".to_string(), |v| v.to_string())
+}
+
+fn elapsed(start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Option<std::time::Duration> {
+    end?.signed_duration_since(start?).to_std().ok()
+}
+
+/// "1m 15s → 1m 2s (-13s)", or a single duration when they are equal
+fn compare_durations(before: Option<std::time::Duration>, after: Option<std::time::Duration>) -> String {
+    let show = |d: Option<std::time::Duration>| d.map_or("-".to_string(), format_duration);
+    match (before, after) {
+        (Some(x), Some(y)) if x.as_secs() == y.as_secs() => format_duration(y),
+        (Some(x), Some(y)) => {
+            let delta = if y > x {
+                format!("+{}", format_duration(y - x))
+            } else {
+                format!("-{}", format_duration(x - y))
+            };
+            format!("{} → {} ({})", format_duration(x), format_duration(y), delta)
+        }
+        (x, y) => format!("{} → {}", show(x), show(y)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Line-by-line edit from `a` to `b` along their longest common subsequence
+fn line_edits<'a>(a: &[&'a str], b: &[&'a str]) -> Vec<Line<'a>> {
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        return a.iter().map(|l| Line::Removed(l)).chain(b.iter().map(|l| Line::Added(l))).collect();
+    }
+
+    // lcs[i][j]: longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = Vec::new();
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            edits.push(Line::Same(a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            edits.push(Line::Removed(a[i]));
+            i += 1;
+        } else {
+            edits.push(Line::Added(b[j]));
+            j += 1;
+        }
+    }
+    edits
+}
+
+/// Unified diff hunks (without the `---`/`+++` header) turning `before`
+/// into `after`, or `None` when they are the same
+pub fn unified_diff(before: &str, after: &str, context: usize) -> Option<String> {
+    if before == after {
+        return None;
+    }
+    let a: Vec<&str> = before.lines().collect();
+    let b: Vec<&str> = after.lines().collect();
+    let edits = line_edits(&a, &b);
+
+    // Group changes closer than twice the context into one hunk
+    let changes: Vec<usize> = (0..edits.len()).filter(|&i| !matches!(edits[i], Line::Same(_))).collect();
+    if changes.is_empty() {
+        // Only line endings differ
+        return None;
+    }
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // Line numbers in `before` and `after` at each edit
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut old, mut new) = (0, 0);
+    for edit in &edits {
+        positions.push((old, new));
+        match edit {
+            Line::Same(_) => {
+                old += 1;
+                new += 1;
+            }
+            Line::Removed(_) => old += 1,
+            Line::Added(_) => new += 1,
+        }
+    }
+
+    let mut out = String::new();
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_len = hunk.iter().filter(|e| !matches!(e, Line::Added(_))).count();
+        let new_len = hunk.iter().filter(|e| !matches!(e, Line::Removed(_))).count();
+        let (old_start, new_start) = positions[start];
+        // An empty side starts at the line before it, as in `diff -u`
+        let old_start = if old_len == 0 { old_start } else { old_start + 1 };
+        let new_start = if new_len == 0 { new_start } else { new_start + 1 };
+        out.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_len, new_start, new_len));
+        for edit in hunk {
+            match edit {
+                Line::Same(line) => out.push_str(&format!(" {}\n", line)),
+                Line::Removed(line) => out.push_str(&format!("-{}\n", line)),
+                Line::Added(line) => out.push_str(&format!("+{}\n", line)),
+            }
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ExecutionStatus;
+    use uuid::Uuid;
+
+    fn summary(status: ExecutionStatus, secs: i64) -> ExecutionSummary {
+        let started = Utc::now();
+        ExecutionSummary {
+            execution_id: Uuid::new_v4(),
+            pipeline_name: "Feature".to_string(),
+            status,
+            started_at: started,
+            completed_at: Some(started + chrono::Duration::seconds(secs)),
+            progress: 1.0,
+            completed_steps: 2,
+            total_steps: 2,
+            bytes_written: 0,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        }
+    }
+
+    fn result(step_id: &str, status: &str, attempts: usize, secs: i64, output: &str) -> StepResult {
+        let started = Utc::now();
+        StepResult {
+            step_id: step_id.to_string(),
+            status: status.to_string(),
+            output: Some(output.to_string()),
+            error: None,
+            attempts,
+            started_at: Some(started),
+            finished_at: Some(started + chrono::Duration::seconds(secs)),
+            tokens: 0,
+            cost_usd: 0.0,
+            input_digest: None,
+        }
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", 3), None);
+
+        let before = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let after = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n";
+        assert_eq!(
+            unified_diff(before, after, 1).unwrap(),
+            "@@ -2,3 +2,3 @@\n 2\n-3\n+three\n 4\n@@ -10,1 +10,2 @@\n 10\n+11\n"
+        );
+        assert_eq!(unified_diff("", "new\n", 3).unwrap(), "@@ -0,0 +1,1 @@\n+new\n");
+    }
+
+    #[test]
+    fn test_render_diff() {
+        let a = summary(ExecutionStatus::Failed, 200);
+        let b = summary(ExecutionStatus::Completed, 125);
+        let a_steps = vec![
+            result("plan", "completed", 1, 75, "Plan:\n1. Parse\n2. Test\n"),
+            result("code", "failed", 3, 120, "error"),
+        ];
+        let b_steps = vec![
+            result("plan", "completed", 1, 62, "Plan:\n1. Parse\n2. Lint\n3. Test\n"),
+            result("code", "completed", 1, 60, "error"),
+            result("review", "completed", 1, 3, "LGTM"),
+        ];
+
+        let diff = console::strip_ansi_codes(&render_diff((&a, &a_steps), (&b, &b_steps), 3)).into_owned();
+        assert!(diff.contains("  Status: Failed → Completed\n"));
+        assert!(diff.contains("  Duration: 3m 20s → 2m 5s (-1m 15s)\n"));
+        assert!(diff.contains("  plan  completed, attempts 1, 1m 15s → 1m 2s (-13s)\n"));
+        assert!(diff.contains("  code  failed → completed, attempts 3 → 1, 2m 0s → 1m 0s (-1m 0s)\n"));
+        assert!(diff.contains("  review  - → completed, attempts - → 1, - → 3s\n"));
+        assert!(diff.contains(" 1. Parse\n-2. Test\n+2. Lint\n+3. Test\n"));
+        assert!(!diff.contains("--- code"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Rough cost and time estimates of a run, before running it
+//!
+//! Steps that ran before are estimated from their step records in recent
+//! runs of the pipeline: the mean cost and duration per run. Steps without
+//! history get their cost from the cost per prompt token that steps on the
+//! same model (or, failing that, any model) had, and the mean duration of
+//! the steps with history. Prompt tokens are counted as bytes / 4.
+
+use crate::cli::output::{format_cost, format_duration};
+use crate::core::Pipeline;
+use crate::persistence::StepRecord;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Recent runs whose step records feed the estimate, by default
+pub const DEFAULT_HISTORY_RUNS: usize = 10;
+
+/// Model label of steps that don't pick one
+pub const DEFAULT_MODEL: &str = "default";
+
+/// Model that pi arguments select with `--model`
+pub fn model_from_args(args: &[String]) -> Option<String> {
+    args.iter().enumerate().find_map(|(i, arg)| match arg.strip_prefix("--model=") {
+        Some(model) => Some(model.to_string()),
+        None if arg == "--model" => args.get(i + 1).cloned(),
+        None => None,
+    })
+}
+
+/// What a step cost and took in past runs, on average
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepHistory {
+    /// Runs in which the step finished
+    pub runs: usize,
+    pub cost_usd: f64,
+    pub duration: Duration,
+}
+
+/// Average the step records of past runs per step
+///
+/// Only steps that finished (completed or failed) count; a step skipped or
+/// cancelled in a run says little about what it costs.
+pub fn step_history(runs: &[Vec<StepRecord>]) -> HashMap<String, StepHistory> {
+    let mut totals: HashMap<String, (usize, f64, Duration)> = HashMap::new();
+    for record in runs.iter().flatten() {
+        let (Some(started), Some(finished)) = (record.started_at, record.finished_at) else {
+            continue;
+        };
+        if !matches!(record.status.as_str(), "completed" | "failed") {
+            continue;
+        }
+        let total = totals.entry(record.step_id.clone()).or_default();
+        total.0 += 1;
+        total.1 += record.cost_usd;
+        total.2 += (finished - started).to_std().unwrap_or_default();
+    }
+    totals
+        .into_iter()
+        .map(|(step_id, (runs, cost, duration))| {
+            let history = StepHistory {
+                runs,
+                cost_usd: cost / runs as f64,
+                duration: duration / runs as u32,
+            };
+            (step_id, history)
+        })
+        .collect()
+}
+
+/// Estimate of one step
+#[derive(Debug, Clone, Serialize)]
+pub struct StepEstimate {
+    pub step_id: String,
+    pub model: String,
+    pub prompt_tokens: usize,
+    /// Past runs the estimate is based on; 0 means it was extrapolated
+    pub history_runs: usize,
+    /// `None` when nothing to extrapolate from
+    pub cost_usd: Option<f64>,
+    pub duration_secs: Option<f64>,
+}
+
+/// Estimate of a whole run
+#[derive(Debug, Clone, Serialize)]
+pub struct Estimate {
+    pub pipeline_name: String,
+    pub steps: Vec<StepEstimate>,
+    /// Sum of the steps' costs that could be estimated; `None` if none could
+    pub cost_usd: Option<f64>,
+    /// Time with steps run one after another
+    pub sequential_secs: Option<f64>,
+    /// Time with independent steps run in parallel (the critical path)
+    pub parallel_secs: Option<f64>,
+    /// Steps never seen in history
+    pub without_history: Vec<String>,
+}
+
+/// Estimate a run of `pipeline` from the history of its steps
+///
+/// `models` maps step IDs to the model they run on; steps not in it use
+/// [`DEFAULT_MODEL`].
+pub fn estimate(pipeline: &Pipeline, models: &HashMap<String, String>, history: &HashMap<String, StepHistory>) -> Estimate {
+    let order = pipeline.execution_order();
+    let model_of = |id: &str| models.get(id).cloned().unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    let prompt_tokens: HashMap<&str, usize> = order
+        .iter()
+        .filter_map(|id| pipeline.step(id))
+        .map(|step| (step.id.as_str(), step.render_with_variables(&pipeline.variables).len().div_ceil(4)))
+        .collect();
+
+    // Cost per prompt token of the steps with history, per model and overall
+    let mut rates: HashMap<String, (f64, usize)> = HashMap::new();
+    let mut overall = (0.0, 0);
+    let mut known_durations = Vec::new();
+    for id in order {
+        if let Some(past) = history.get(id) {
+            let tokens = prompt_tokens.get(id.as_str()).copied().unwrap_or_default();
+            let rate = rates.entry(model_of(id)).or_default();
+            rate.0 += past.cost_usd;
+            rate.1 += tokens;
+            overall.0 += past.cost_usd;
+            overall.1 += tokens;
+            known_durations.push(past.duration);
+        }
+    }
+    let per_token = |(cost, tokens): (f64, usize)| (tokens > 0).then(|| cost / tokens as f64);
+    let typical_duration = (!known_durations.is_empty())
+        .then(|| known_durations.iter().sum::<Duration>() / known_durations.len() as u32);
+
+    let mut steps = Vec::new();
+    for id in order {
+        let model = model_of(id);
+        let tokens = prompt_tokens.get(id.as_str()).copied().unwrap_or_default();
+        let step = match history.get(id) {
+            Some(past) => StepEstimate {
+                step_id: id.clone(),
+                model,
+                prompt_tokens: tokens,
+                history_runs: past.runs,
+                cost_usd: Some(past.cost_usd),
+                duration_secs: Some(past.duration.as_secs_f64()),
+            },
+            None => {
+                let rate = rates.get(&model).copied().and_then(per_token).or_else(|| per_token(overall));
+                StepEstimate {
+                    step_id: id.clone(),
+                    model,
+                    prompt_tokens: tokens,
+                    history_runs: 0,
+                    cost_usd: rate.map(|rate| rate * tokens as f64),
+                    duration_secs: typical_duration.map(|d| d.as_secs_f64()),
+                }
+            }
+        };
+        steps.push(step);
+    }
+
+    // A step finishes after its slowest dependency (or the fastest of its
+    // alternatives, if that is later), plus its own time
+    let secs: HashMap<&str, f64> = steps
+        .iter()
+        .map(|s| (s.step_id.as_str(), s.duration_secs.unwrap_or_default()))
+        .collect();
+    let mut finish: HashMap<&str, f64> = HashMap::new();
+    for id in order {
+        let start = pipeline
+            .step(id)
+            .map(|step| {
+                let finished = |deps: &[String]| deps.iter().filter_map(|dep| finish.get(dep.as_str()).copied()).collect::<Vec<_>>();
+                let all = finished(&step.dependencies).into_iter().fold(0.0, f64::max);
+                let any = finished(&step.any_dependencies).into_iter().reduce(f64::min).unwrap_or_default();
+                all.max(any)
+            })
+            .unwrap_or_default();
+        finish.insert(id.as_str(), start + secs[id.as_str()]);
+    }
+
+    let costs: Vec<f64> = steps.iter().filter_map(|s| s.cost_usd).collect();
+    let timed = typical_duration.is_some();
+    Estimate {
+        pipeline_name: pipeline.name.clone(),
+        cost_usd: (!costs.is_empty()).then(|| costs.iter().fold(0.0, |total, cost| total + cost)),
+        sequential_secs: timed.then(|| secs.values().fold(0.0, |total, secs| total + secs)),
+        parallel_secs: timed.then(|| finish.values().copied().fold(0.0, f64::max)),
+        without_history: steps.iter().filter(|s| s.history_runs == 0).map(|s| s.step_id.clone()).collect(),
+        steps,
+    }
+}
+
+/// Render an estimate as a table with totals
+pub fn format_estimate(estimate: &Estimate) -> String {
+    let unknown = || "?".to_string();
+    let rows: Vec<[String; 6]> = estimate
+        .steps
+        .iter()
+        .map(|step| {
+            let approx = if step.history_runs == 0 { "~" } else { "" };
+            [
+                step.step_id.clone(),
+                step.model.clone(),
+                step.prompt_tokens.to_string(),
+                step.history_runs.to_string(),
+                step.cost_usd.map_or_else(unknown, |cost| format!("{}{}", approx, format_cost(cost))),
+                step.duration_secs
+                    .map_or_else(unknown, |secs| format!("{}{}", approx, format_duration(Duration::from_secs_f64(secs)))),
+            ]
+        })
+        .collect();
+    let header = ["Step", "Model", "Prompt tokens", "Runs", "Cost", "Time"];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| rows.iter().map(|row| row[col].len()).chain([header[col].len()]).max().unwrap_or_default())
+        .collect();
+    let line = |cells: &[String]| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        format!("  {}\n", cells.join("  ").trim_end())
+    };
+
+    let mut out = line(&header.map(str::to_string));
+    for row in &rows {
+        out.push_str(&line(row));
+    }
+    out.push_str(&format!("\n  Estimated cost: {}\n", estimate.cost_usd.map_or_else(unknown, format_cost)));
+    match (estimate.sequential_secs, estimate.parallel_secs) {
+        (Some(sequential), Some(parallel)) => out.push_str(&format!(
+            "  Estimated time: {} sequential, {} with independent steps in parallel\n",
+            format_duration(Duration::from_secs_f64(sequential)),
+            format_duration(Duration::from_secs_f64(parallel))
+        )),
+        _ => out.push_str("  Estimated time: ?\n"),
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::PipelineConfig;
+    use chrono::{TimeZone, Utc};
+
+    fn record(step_id: &str, status: &str, cost_usd: f64, secs: i64) -> StepRecord {
+        let started = Utc.with_ymd_and_hms(2024, 5, 14, 12, 0, 0).unwrap();
+        StepRecord {
+            step_id: step_id.to_string(),
+            status: status.to_string(),
+            depends_on: vec![],
+            depends_on_any: vec![],
+            attempts: 1,
+            started_at: Some(started),
+            finished_at: Some(started + chrono::Duration::seconds(secs)),
+            bytes_written: 0,
+            error: None,
+            agent_attempts: vec![],
+            budget_category: None,
+            cost_usd,
+            tokens: 0,
+            prompt_hashes: vec![],
+            input_digest: None,
+        }
+    }
+
+    #[test]
+    fn test_model_from_args() {
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(model_from_args(&args(&["--provider", "x", "--model", "m1"])), Some("m1".to_string()));
+        assert_eq!(model_from_args(&args(&["--model=m2"])), Some("m2".to_string()));
+        assert_eq!(model_from_args(&args(&["--provider", "x"])), None);
+    }
+
+    #[test]
+    fn test_step_history_averages_finished_steps() {
+        let runs = vec![
+            vec![record("build", "completed", 1.0, 60), record("test", "cancelled", 5.0, 1)],
+            vec![record("build", "failed", 3.0, 120), record("test", "completed", 0.5, 30)],
+        ];
+        let history = step_history(&runs);
+        assert_eq!(
+            history["build"],
+            StepHistory { runs: 2, cost_usd: 2.0, duration: Duration::from_secs(90) }
+        );
+        assert_eq!(history["test"].runs, 1);
+    }
+
+    #[test]
+    fn test_estimate_extrapolates_steps_without_history() {
+        let yaml = r#"
+name: "Test Pipeline"
+variables:
+  target: "the service"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build {{ target }} now"
+  - id: "lint"
+    name: "Lint"
+    prompt: "Lint everything in the repository"
+  - id: "deploy"
+    name: "Deploy"
+    prompt: "Deploy"
+    depends_on: ["build", "lint"]
+"#;
+        let pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let history = step_history(&[vec![record("build", "completed", 0.5, 60), record("deploy", "completed", 0.1, 20)]]);
+        let models = HashMap::from([("lint".to_string(), "big".to_string())]);
+        let estimate = estimate(&pipeline, &models, &history);
+
+        let step = |id: &str| estimate.steps.iter().find(|s| s.step_id == id).unwrap();
+        // "Build the service now" is 21 bytes
+        assert_eq!(step("build").prompt_tokens, 6);
+        assert_eq!(step("build").cost_usd, Some(0.5));
+        assert_eq!(step("lint").history_runs, 0);
+        assert_eq!(step("lint").model, "big");
+        // No history on its model: the overall rate of $0.6 per 8 tokens
+        let lint_tokens = step("lint").prompt_tokens as f64;
+        assert!((step("lint").cost_usd.unwrap() - 0.6 / 8.0 * lint_tokens).abs() < 1e-9);
+        assert_eq!(step("lint").duration_secs, Some(40.0));
+        assert_eq!(estimate.without_history, vec!["lint"]);
+
+        assert_eq!(estimate.sequential_secs, Some(120.0));
+        assert_eq!(estimate.parallel_secs, Some(80.0));
+        assert!(format_estimate(&estimate).contains("Estimated time: 2m 0s sequential, 1m 20s"));
+    }
+
+    #[test]
+    fn test_estimate_without_any_history_is_unknown() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+"#;
+        let pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let estimate = estimate(&pipeline, &HashMap::new(), &HashMap::new());
+        assert_eq!((estimate.steps[0].cost_usd, estimate.steps[0].duration_secs), (None, None));
+        assert_eq!((estimate.cost_usd, estimate.parallel_secs), (None, None));
+        let table = format_estimate(&estimate);
+        assert!(table.contains("build  default  2              0     ?     ?"), "{}", table);
+        assert!(table.contains("Estimated cost: ?"), "{}", table);
+    }
+}
@@ -0,0 +1,322 @@
+//! Dependency and routing graph of a pipeline
+//!
+//! Drawn from the pipeline config: `depends_on` and `depends_on_any` edges
+//! point from a dependency to the step that waits for it, while termination
+//! and continuation edges point from a step to the step its output routes
+//! to. Each kind of edge gets its own color. Statuses loaded from history
+//! can be overlaid on the steps.
+
+use crate::core::config::{ContinuationAction, PipelineConfig};
+use crate::cli::output::style;
+use std::collections::HashMap;
+
+/// Graph output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT (render with `dot -Tsvg`)
+    Dot,
+    /// Mermaid (renders inline on GitHub and GitLab)
+    Mermaid,
+    /// Plain text, one step per line with its edges below it
+    Ascii,
+}
+
+/// How two steps are connected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// `depends_on`: the dependency has to complete first
+    DependsOn,
+    /// `depends_on_any`: one of the alternatives has to complete first
+    DependsOnAny,
+    /// `termination.on_success`
+    OnSuccess,
+    /// `termination.on_failure`
+    OnFailure,
+    /// `continuation` with `action: route`
+    Route,
+    /// `continuation` with `action: retry` (a loop on the step)
+    Retry,
+}
+
+impl EdgeKind {
+    fn label(&self) -> Option<&'static str> {
+        match self {
+            EdgeKind::DependsOn => None,
+            EdgeKind::DependsOnAny => Some("any of"),
+            EdgeKind::OnSuccess => Some("on success"),
+            EdgeKind::OnFailure => Some("on failure"),
+            EdgeKind::Route => Some("continue"),
+            EdgeKind::Retry => Some("retry"),
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            EdgeKind::DependsOn => "#555555",
+            EdgeKind::DependsOnAny => "#999999",
+            EdgeKind::OnSuccess => "#2e7d32",
+            EdgeKind::OnFailure => "#c62828",
+            EdgeKind::Route | EdgeKind::Retry => "#ef6c00",
+        }
+    }
+
+    fn dashed(&self) -> bool {
+        !matches!(self, EdgeKind::DependsOn | EdgeKind::OnSuccess | EdgeKind::OnFailure)
+    }
+}
+
+/// An edge between two steps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+/// Edges of a pipeline, grouped by step in the order of the config
+pub fn edges(config: &PipelineConfig) -> Vec<Edge> {
+    let edge = |from: &str, to: &str, kind| Edge { from: from.to_string(), to: to.to_string(), kind };
+    let mut edges = Vec::new();
+    for step in &config.steps {
+        for dep in &step.depends_on {
+            edges.push(edge(dep, &step.id, EdgeKind::DependsOn));
+        }
+        for dep in &step.depends_on_any {
+            edges.push(edge(dep, &step.id, EdgeKind::DependsOnAny));
+        }
+        if let Some(termination) = &step.termination {
+            if let Some(target) = &termination.on_success {
+                edges.push(edge(&step.id, target, EdgeKind::OnSuccess));
+            }
+            if let Some(target) = &termination.on_failure {
+                edges.push(edge(&step.id, target, EdgeKind::OnFailure));
+            }
+        }
+        if let Some(continuation) = &step.continuation {
+            match (continuation.action, &continuation.target) {
+                (ContinuationAction::Route, Some(target)) => edges.push(edge(&step.id, target, EdgeKind::Route)),
+                (ContinuationAction::Retry, _) => edges.push(edge(&step.id, &step.id, EdgeKind::Retry)),
+                (ContinuationAction::Route, None) => {}
+            }
+        }
+    }
+    edges
+}
+
+/// Render the graph of a pipeline, with the status of each step in
+/// `statuses` (keyed by step ID) shown on its node
+pub fn render(config: &PipelineConfig, format: GraphFormat, statuses: &HashMap<String, String>) -> String {
+    let edges = edges(config);
+    match format {
+        GraphFormat::Dot => dot(config, &edges, statuses),
+        GraphFormat::Mermaid => mermaid(config, &edges, statuses),
+        GraphFormat::Ascii => ascii(config, &edges, statuses),
+    }
+}
+
+/// Fill color of a step with the given status
+fn status_color(status: &str) -> Option<&'static str> {
+    match status {
+        "completed" => Some("#c8e6c9"),
+        "failed" => Some("#ffcdd2"),
+        "running" | "retrying" => Some("#bbdefb"),
+        "cancelled" | "blocked" => Some("#ffe0b2"),
+        "skipped" => Some("#eeeeee"),
+        _ => None,
+    }
+}
+
+fn node_label(id: &str, status: Option<&String>) -> String {
+    match status {
+        Some(status) => format!("{} ({})", id, status),
+        None => id.to_string(),
+    }
+}
+
+fn dot(config: &PipelineConfig, edges: &[Edge], statuses: &HashMap<String, String>) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+
+    let mut out = format!("digraph {} {{\n", quote(&config.name));
+    out.push_str("    rankdir=TB;\n    node [shape=box, style=\"rounded,filled\", fillcolor=white];\n");
+    for step in &config.steps {
+        let status = statuses.get(&step.id);
+        let fill = status
+            .and_then(|s| status_color(s))
+            .map(|color| format!(", fillcolor={}", quote(color)))
+            .unwrap_or_default();
+        out.push_str(&format!("    {} [label={}{}];\n", quote(&step.id), quote(&node_label(&step.id, status)), fill));
+    }
+    for edge in edges {
+        let mut attrs = vec![format!("color={}", quote(edge.kind.color()))];
+        if edge.kind.dashed() {
+            attrs.push("style=dashed".to_string());
+        }
+        if let Some(label) = edge.kind.label() {
+            attrs.push(format!("label={}", quote(label)));
+            attrs.push(format!("fontcolor={}", quote(edge.kind.color())));
+        }
+        out.push_str(&format!("    {} -> {} [{}];\n", quote(&edge.from), quote(&edge.to), attrs.join(", ")));
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn mermaid(config: &PipelineConfig, edges: &[Edge], statuses: &HashMap<String, String>) -> String {
+    use crate::cli::report::node_id;
+
+    let mut out = String::from("graph TD\n");
+    for step in &config.steps {
+        let label = node_label(&step.id, statuses.get(&step.id)).replace('"', "#quot;");
+        out.push_str(&format!("    {}[\"{}\"]\n", node_id(&step.id), label));
+    }
+    for edge in edges {
+        let arrow = if edge.kind.dashed() { "-.->" } else { "-->" };
+        let label = edge.kind.label().map(|l| format!("|{}|", l)).unwrap_or_default();
+        out.push_str(&format!("    {} {}{} {}\n", node_id(&edge.from), arrow, label, node_id(&edge.to)));
+    }
+    // Link styles go by the order the edges were declared in
+    for (index, edge) in edges.iter().enumerate() {
+        out.push_str(&format!("    linkStyle {} stroke:{},color:{}\n", index, edge.kind.color(), edge.kind.color()));
+    }
+
+    let mut classes: Vec<&str> = config
+        .steps
+        .iter()
+        .filter_map(|step| statuses.get(&step.id).map(String::as_str))
+        .filter(|status| status_color(status).is_some())
+        .collect();
+    classes.sort_unstable();
+    classes.dedup();
+    for status in classes {
+        out.push_str(&format!("    classDef {} fill:{}\n", status, status_color(status).unwrap_or_default()));
+        let members: Vec<String> = config
+            .steps
+            .iter()
+            .filter(|step| statuses.get(&step.id).map(String::as_str) == Some(status))
+            .map(|step| node_id(&step.id))
+            .collect();
+        out.push_str(&format!("    class {} {}\n", members.join(","), status));
+    }
+    out
+}
+
+fn ascii(config: &PipelineConfig, edges: &[Edge], statuses: &HashMap<String, String>) -> String {
+    let mut out = String::new();
+    for step in &config.steps {
+        match statuses.get(&step.id) {
+            Some(status) => {
+                let status = match status.as_str() {
+                    "completed" => style(status).green(),
+                    "failed" => style(status).red(),
+                    "cancelled" | "blocked" => style(status).yellow(),
+                    _ => style(status).dim(),
+                };
+                out.push_str(&format!("{} [{}]\n", style(&step.id).bold(), status));
+            }
+            None => out.push_str(&format!("{}\n", style(&step.id).bold())),
+        }
+
+        let waits_for: Vec<&str> = edges
+            .iter()
+            .filter(|e| e.to == step.id && e.kind == EdgeKind::DependsOn)
+            .map(|e| e.from.as_str())
+            .collect();
+        if !waits_for.is_empty() {
+            out.push_str(&format!("  <- {}\n", waits_for.join(", ")));
+        }
+        let any_of: Vec<&str> = edges
+            .iter()
+            .filter(|e| e.to == step.id && e.kind == EdgeKind::DependsOnAny)
+            .map(|e| e.from.as_str())
+            .collect();
+        if !any_of.is_empty() {
+            out.push_str(&format!("  {}\n", style(format!("<- any of {}", any_of.join(" | "))).dim()));
+        }
+        for edge in edges.iter().filter(|e| e.from == step.id && e.kind != EdgeKind::DependsOn && e.kind != EdgeKind::DependsOnAny) {
+            let line = format!("-> {} ({})", edge.to, edge.kind.label().unwrap_or_default());
+            let line = match edge.kind {
+                EdgeKind::OnSuccess => style(line).green(),
+                EdgeKind::OnFailure => style(line).red(),
+                _ => style(line).yellow(),
+            };
+            out.push_str(&format!("  {}\n", line));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+name: "Review"
+steps:
+  - id: plan
+    name: Plan
+    prompt: Plan it
+  - id: code
+    name: Code
+    prompt: Write it
+    depends_on: [plan]
+    continuation:
+      pattern: MORE
+  - id: review
+    name: Review
+    prompt: Review it
+    depends_on: [code]
+    termination:
+      success_pattern: APPROVED
+      on_failure: code
+    continuation:
+      pattern: REPLAN
+      action: route
+      target: plan
+"#;
+
+    #[test]
+    fn test_edges_cover_dependencies_and_routes() {
+        let config = PipelineConfig::from_yaml(YAML).unwrap();
+        let edges = edges(&config);
+        let kinds: Vec<(&str, &str, EdgeKind)> = edges
+            .iter()
+            .map(|e| (e.from.as_str(), e.to.as_str(), e.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("plan", "code", EdgeKind::DependsOn),
+                ("code", "code", EdgeKind::Retry),
+                ("code", "review", EdgeKind::DependsOn),
+                ("review", "code", EdgeKind::OnFailure),
+                ("review", "plan", EdgeKind::Route),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_formats() {
+        let config = PipelineConfig::from_yaml(YAML).unwrap();
+        let statuses = HashMap::from([
+            ("plan".to_string(), "completed".to_string()),
+            ("code".to_string(), "failed".to_string()),
+        ]);
+
+        let dot = render(&config, GraphFormat::Dot, &statuses);
+        assert!(dot.starts_with("digraph \"Review\" {\n"));
+        assert!(dot.contains("\"plan\" [label=\"plan (completed)\", fillcolor=\"#c8e6c9\"];"));
+        assert!(dot.contains("\"review\" [label=\"review\"];"));
+        assert!(dot.contains("\"plan\" -> \"code\" [color=\"#555555\"];"));
+        assert!(dot.contains("\"review\" -> \"code\" [color=\"#c62828\", label=\"on failure\""));
+
+        let mermaid = render(&config, GraphFormat::Mermaid, &statuses);
+        assert!(mermaid.contains("    review -->|on failure| code\n"));
+        assert!(mermaid.contains("    review -.->|continue| plan\n"));
+        assert!(mermaid.contains("    linkStyle 3 stroke:#c62828,color:#c62828\n"));
+        assert!(mermaid.contains("    class code failed\n"));
+
+        let ascii = console::strip_ansi_codes(&render(&config, GraphFormat::Ascii, &statuses)).into_owned();
+        assert!(ascii.contains("code [failed]\n  <- plan\n  -> code (retry)\n"));
+        assert!(ascii.contains("review\n  <- code\n  -> code (on failure)\n  -> plan (continue)\n"));
+    }
+}
@@ -0,0 +1,231 @@
+//! Interactive operator prompts for attended runs
+
+use crate::agent::ToolApprover;
+use crate::cli::terminal_output::TerminalOutputCallback;
+use crate::execution::{FailureAction, Interruption, StepFailure, Steering, SteeringAction};
+use console::style;
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Print a question and read a single line answer from stdin
+///
+/// Returns `None` if stdin is closed or cannot be read.
+pub fn ask(question: &str) -> Option<String> {
+    print!("{} ", question);
+    let _ = io::stdout().flush();
+    stdin_reader().lock().unwrap().read_line(None).ok().flatten()
+}
+
+/// Like `ask`, but gives up at `deadline`
+///
+/// Returns `None` if no answer came in time or stdin is closed.
+pub fn ask_until(question: &str, deadline: Instant) -> Option<String> {
+    print!("{} ", question);
+    let _ = io::stdout().flush();
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    match stdin_reader().lock().unwrap().read_line(Some(timeout)) {
+        Ok(answer) => answer,
+        Err(RecvTimeoutError::Timeout) => {
+            println!();
+            None
+        }
+        Err(RecvTimeoutError::Disconnected) => None,
+    }
+}
+
+/// Reads stdin lines on a thread of its own, one per request
+///
+/// A question that stops waiting leaves its read outstanding, and the next
+/// question takes over that read instead of starting another; nothing reads
+/// stdin while no question is asked (an editor may be using the terminal).
+struct StdinReader {
+    requests: mpsc::Sender<()>,
+    lines: Receiver<Option<String>>,
+    outstanding: bool,
+}
+
+impl StdinReader {
+    fn read_line(&mut self, timeout: Option<Duration>) -> Result<Option<String>, RecvTimeoutError> {
+        if !self.outstanding {
+            self.requests.send(()).map_err(|_| RecvTimeoutError::Disconnected)?;
+            self.outstanding = true;
+        }
+        let line = match timeout {
+            Some(timeout) => self.lines.recv_timeout(timeout)?,
+            None => self.lines.recv().map_err(|_| RecvTimeoutError::Disconnected)?,
+        };
+        self.outstanding = false;
+        Ok(line)
+    }
+}
+
+fn stdin_reader() -> &'static Mutex<StdinReader> {
+    static READER: OnceLock<Mutex<StdinReader>> = OnceLock::new();
+    READER.get_or_init(|| {
+        let (requests, pending) = mpsc::channel::<()>();
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for () in pending {
+                let mut answer = String::new();
+                let line = match io::stdin().lock().read_line(&mut answer) {
+                    Ok(0) | Err(_) => None,
+                    Ok(_) => Some(answer.trim().to_string()),
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(StdinReader {
+            requests,
+            lines,
+            outstanding: false,
+        })
+    })
+}
+
+/// Tool approver that asks the operator on the terminal
+///
+/// Anything other than an explicit "y"/"yes" denies the tool call, so a
+/// closed stdin never silently approves.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerminalToolApprover;
+
+impl ToolApprover for TerminalToolApprover {
+    fn approve(&self, tool_name: &str, arguments: &Value) -> bool {
+        println!();
+        println!(
+            "{} Agent wants to run {}",
+            style("?").yellow().bold(),
+            TerminalOutputCallback::format_tool_call(tool_name, arguments)
+        );
+        matches!(
+            ask("  Allow? [y/N]").as_deref().map(str::to_lowercase).as_deref(),
+            Some("y") | Some("yes")
+        )
+    }
+
+    fn name(&self) -> String {
+        match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+            Ok(user) if !user.is_empty() => format!("terminal:{}", user),
+            _ => "terminal".to_string(),
+        }
+    }
+}
+
+/// Lines of an interrupted step's output shown when it left no recent lines
+const INTERRUPTED_OUTPUT_LINES: usize = 20;
+
+/// Steering that shows the operator what an interrupted step was doing and
+/// asks whether to retry it, skip it or abort the run
+///
+/// A closed stdin aborts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TerminalSteering;
+
+impl Steering for TerminalSteering {
+    fn steer(&self, interruption: &Interruption) -> SteeringAction {
+        println!();
+        println!(
+            "{} Interrupted {}",
+            style("⏸").yellow().bold(),
+            style(&interruption.step_id).bold()
+        );
+
+        let mut lines: Vec<&str> = interruption.recent_lines.iter().map(String::as_str).collect();
+        if lines.is_empty() {
+            let output: Vec<&str> = interruption.accumulated_output.lines().collect();
+            lines = output[output.len().saturating_sub(INTERRUPTED_OUTPUT_LINES)..].to_vec();
+        }
+        for line in lines {
+            println!("  {}", style(line).dim());
+        }
+
+        loop {
+            match ask("  [r]etry, [s]kip or [a]bort?").as_deref().map(str::to_lowercase).as_deref() {
+                Some("r") | Some("retry") => return SteeringAction::Retry,
+                Some("s") | Some("skip") => return SteeringAction::Skip,
+                Some("a") | Some("abort") | None => return SteeringAction::Abort,
+                Some(_) => continue,
+            }
+        }
+    }
+
+    fn steer_failure(&self, failure: &StepFailure, timeout: Duration) -> FailureAction {
+        println!();
+        println!(
+            "{} {} failed after {} attempt{}: {}",
+            style("✗").red().bold(),
+            style(&failure.step_id).bold(),
+            failure.attempts,
+            if failure.attempts == 1 { "" } else { "s" },
+            failure.error
+        );
+        let default = match &failure.on_failure {
+            Some(handler) => format!("route to {}", handler),
+            None => "fail the step".to_string(),
+        };
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            let question = format!(
+                "  [r]etry, [e]dit prompt and retry, [s]kip, r[o]ute, [a]bort? ({} in {}s)",
+                default,
+                deadline.saturating_duration_since(Instant::now()).as_secs()
+            );
+            match ask_until(&question, deadline).as_deref().map(str::to_lowercase).as_deref() {
+                Some("r") | Some("retry") => return FailureAction::Retry,
+                Some("e") | Some("edit") => match edit_prompt(&failure.prompt_template) {
+                    Ok(prompt) => return FailureAction::RetryWithPrompt(prompt),
+                    Err(e) => println!("  {}", style(format!("Could not edit the prompt: {}", e)).red()),
+                },
+                Some("s") | Some("skip") => return FailureAction::Skip,
+                Some("o") | Some("route") => {
+                    let question = match &failure.on_failure {
+                        Some(handler) => format!("  Route to step [{}]:", handler),
+                        None => "  Route to step:".to_string(),
+                    };
+                    match ask_until(&question, deadline) {
+                        Some(target) if !target.is_empty() => return FailureAction::Route(target),
+                        Some(_) if failure.on_failure.is_some() => return FailureAction::Default,
+                        Some(_) => continue,
+                        None => return FailureAction::Default,
+                    }
+                }
+                Some("a") | Some("abort") => return FailureAction::Abort,
+                Some(_) => continue,
+                None => return FailureAction::Default,
+            }
+        }
+    }
+}
+
+/// Let the operator edit a prompt in `$VISUAL` or `$EDITOR` (default: vi)
+fn edit_prompt(prompt: &str) -> io::Result<String> {
+    let mut file = tempfile::Builder::new().prefix("pipeline-prompt-").suffix(".md").tempfile()?;
+    file.write_all(prompt.as_bytes())?;
+    file.flush()?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Through the shell, so editors given with arguments ("code --wait") work
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(file.path())
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", editor, status)));
+    }
+
+    let edited = std::fs::read_to_string(file.path())?;
+    if edited.trim().is_empty() {
+        return Err(io::Error::other("the prompt is empty"));
+    }
+    Ok(edited)
+}
@@ -1,11 +1,32 @@
 //! Command-line interface
 
+pub mod child_run;
 pub mod commands;
+pub mod diff;
+pub mod estimate;
+pub mod graph;
+pub mod interactive;
 pub mod output;
+pub mod report;
+pub mod scheduler;
 pub mod terminal_output;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod user_config;
+pub mod webhook;
+#[cfg(feature = "serve")]
+pub mod serve;
+#[cfg(feature = "ui")]
+pub mod ui;
+#[cfg(feature = "serve")]
+pub mod worktrees;
 
 use clap::{Parser, Subcommand};
-use commands::{RunCommand, ValidateCommand, ListCommand, HistoryCommand};
+use commands::{RunCommand, ResumeCommand, ValidateCommand, ListCommand, HistoryCommand, StatsCommand, TailCommand, LogsCommand, ReportCommand, DiffCommand, CancelCommand, UnlockCommand, ConfigCommand, SchedulerCommand, QueueCommand, ExtractCommand, SchemaCommand, EstimateCommand, GraphCommand};
+#[cfg(feature = "serve")]
+use commands::ServeCommand;
+#[cfg(feature = "ui")]
+use commands::UiCommand;
 
 /// Pipeline CI/CD tool powered by Pi agent
 #[derive(Debug, Parser, Clone)]
@@ -28,6 +49,19 @@ pub struct Cli {
     /// Enable streaming output
     #[arg(short, long, global = true)]
     pub stream: bool,
+
+    /// Show timestamps in the local timezone instead of UTC
+    #[arg(long, global = true)]
+    pub local_time: bool,
+
+    /// Show timestamps relative to now (e.g. "3m ago", "yesterday 14:02")
+    #[arg(long, global = true)]
+    pub relative_time: bool,
+
+    /// Keep history and run files in this directory (overrides
+    /// PIPELINE_DATA_DIR and the user config's data_dir)
+    #[arg(long, global = true, value_name = "DIR")]
+    pub data_dir: Option<std::path::PathBuf>,
 }
 
 /// Available commands
@@ -36,14 +70,67 @@ pub enum Command {
     /// Run a pipeline
     Run(RunCommand),
 
+    /// Resume a run that crashed or was interrupted, skipping completed steps
+    Resume(ResumeCommand),
+
     /// Validate a pipeline configuration
     Validate(ValidateCommand),
 
+    /// Write a pipeline with only some steps and their dependencies
+    Extract(ExtractCommand),
+
+    /// Print the JSON Schema of pipeline files (for editor completion and checks)
+    Schema(SchemaCommand),
+
+    /// Estimate what a run will cost and take, from the history of its steps
+    Estimate(EstimateCommand),
+
+    /// Draw the dependency and routing graph of a pipeline (DOT, Mermaid or text)
+    Graph(GraphCommand),
+
     /// List available pipelines
     List(ListCommand),
 
     /// Show execution history
     History(HistoryCommand),
+
+    /// Show success rates, durations, retries and spending per pipeline
+    Stats(StatsCommand),
+
+    /// Stream the events of a run, following it until it finishes
+    Tail(TailCommand),
+
+    /// Show or follow the log of each step of a run
+    Logs(LogsCommand),
+
+    /// Generate a shareable report of a run
+    Report(ReportCommand),
+
+    /// Compare two runs of a pipeline: statuses, attempts, durations and outputs
+    Diff(DiffCommand),
+
+    /// Cancel a run in progress, now or once its running steps finish
+    Cancel(CancelCommand),
+
+    /// Remove a stale run lock left by an interrupted run
+    Unlock(UnlockCommand),
+
+    /// Read or change user-level defaults (~/.config/pipeline/config.toml)
+    Config(ConfigCommand),
+
+    /// Run pipelines on cron schedules
+    Scheduler(SchedulerCommand),
+
+    /// Push to, take from or list the named queues `queue:` steps use
+    Queue(QueueCommand),
+
+    /// Follow a run in a local web page
+    #[cfg(feature = "ui")]
+    Ui(UiCommand),
+
+    /// Start runs and follow their events over HTTP
+    #[cfg(feature = "serve")]
+    Serve(ServeCommand),
 }
 
 impl Cli {
@@ -51,4 +138,12 @@ impl Cli {
     pub fn from_args() -> Self {
         Self::parse()
     }
+
+    /// Timestamp format selected by the global flags
+    pub fn timestamp_format(&self) -> output::TimestampFormat {
+        output::TimestampFormat {
+            local: self.local_time,
+            relative: self.relative_time,
+        }
+    }
 }
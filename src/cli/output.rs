@@ -2,9 +2,10 @@
 
 use crate::{
     core::{ExecutionStatus},
-    persistence::ExecutionSummary,
+    persistence::{ExecutionSummary, PipelineStats},
     execution::ContinueAction,
 };
+use chrono::{DateTime, Datelike, Local, TimeZone, Utc};
 use console::Emoji;
 
 // Re-export style
@@ -18,6 +19,33 @@ pub static INFO: Emoji<'_, '_> = Emoji("ℹ️  ", "i ");
 pub static WARN: Emoji<'_, '_> = Emoji("⚠️  ", "!");
 pub static ROCKET: Emoji<'_, '_> = Emoji("🚀 ", "> ");
 
+/// Format a duration as e.g. "45s", "3m 12s" or "1h 2m 3s"
+///
+/// Durations under a second are shown in milliseconds ("350ms").
+pub fn format_duration(duration: std::time::Duration) -> String {
+    format_duration_with(duration, 3)
+}
+
+/// Format a duration with at most `units` of hours, minutes and seconds
+///
+/// The largest non-zero unit comes first, so with 2 units 3723s is "1h 2m"
+/// and 72s is "1m 12s".
+pub fn format_duration_with(duration: std::time::Duration, units: usize) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        return format!("{}ms", duration.as_millis());
+    }
+
+    let parts = [(secs / 3600, "h"), ((secs % 3600) / 60, "m"), (secs % 60, "s")];
+    let first = parts.iter().position(|(value, _)| *value > 0).unwrap_or(2);
+    parts[first..]
+        .iter()
+        .take(units.max(1))
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Format an execution status for display
 pub fn format_status(status: ExecutionStatus) -> String {
     match status {
@@ -25,22 +53,79 @@ pub fn format_status(status: ExecutionStatus) -> String {
         ExecutionStatus::Running => style("RUNNING").yellow().to_string(),
         ExecutionStatus::Completed => style("COMPLETED").green().to_string(),
         ExecutionStatus::Failed => style("FAILED").red().to_string(),
+        ExecutionStatus::PartiallyCompleted => style("PARTIAL").yellow().to_string(),
         ExecutionStatus::Cancelled => style("CANCELLED").yellow().to_string(),
         ExecutionStatus::Paused => style("PAUSED").blue().to_string(),
+        ExecutionStatus::Orphaned => style("ORPHANED").red().to_string(),
+    }
+}
+
+/// How timestamps are rendered in CLI output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimestampFormat {
+    /// Convert to the local timezone instead of UTC
+    pub local: bool,
+
+    /// Use relative forms such as "3m ago" or "yesterday 14:02"
+    pub relative: bool,
+}
+
+impl TimestampFormat {
+    /// Format a timestamp relative to the current time
+    pub fn format(&self, ts: DateTime<Utc>) -> String {
+        self.format_at(ts, Utc::now())
+    }
+
+    /// Format a timestamp relative to `now`
+    pub fn format_at(&self, ts: DateTime<Utc>, now: DateTime<Utc>) -> String {
+        if self.local {
+            self.render(ts.with_timezone(&Local), now.with_timezone(&Local))
+        } else {
+            self.render(ts, now)
+        }
+    }
+
+    fn render<Tz: TimeZone>(&self, ts: DateTime<Tz>, now: DateTime<Tz>) -> String
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        if !self.relative {
+            return ts.to_rfc3339();
+        }
+
+        let elapsed = now.clone().signed_duration_since(ts.clone());
+        let days_apart = now.date_naive().num_days_from_ce() - ts.date_naive().num_days_from_ce();
+
+        if elapsed.num_seconds() < 0 {
+            ts.format("%Y-%m-%d %H:%M").to_string()
+        } else if elapsed.num_seconds() < 60 {
+            "just now".to_string()
+        } else if elapsed.num_minutes() < 60 {
+            format!("{}m ago", elapsed.num_minutes())
+        } else if days_apart == 0 {
+            format!("{}h ago", elapsed.num_hours())
+        } else if days_apart == 1 {
+            ts.format("yesterday %H:%M").to_string()
+        } else if days_apart < 7 {
+            ts.format("%a %H:%M").to_string()
+        } else {
+            ts.format("%Y-%m-%d %H:%M").to_string()
+        }
     }
 }
 
 /// Format execution summary for display
-pub fn format_execution_summary(summary: &ExecutionSummary) -> String {
+pub fn format_execution_summary(summary: &ExecutionSummary, times: &TimestampFormat) -> String {
     let status_icon = match summary.status {
         ExecutionStatus::Completed => CHECK,
         ExecutionStatus::Failed => CROSS,
+        ExecutionStatus::PartiallyCompleted => WARN,
         ExecutionStatus::Running => SPINNER,
         _ => INFO,
     };
 
     format!(
-        "{} {} - {} - {} ({}/{}) - {}",
+        "{} {} - {} - {} ({}/{}) - {} - {}",
         status_icon,
         style(&summary.execution_id.to_string()[..8]).dim(),
         style(&summary.pipeline_name).bold(),
@@ -48,7 +133,8 @@ pub fn format_execution_summary(summary: &ExecutionSummary) -> String {
         summary.completed_steps,
         summary.total_steps,
         style(format!("{:.0}%", summary.progress * 100.0))
-            .cyan()
+            .cyan(),
+        style(times.format(summary.started_at)).dim()
     )
 }
 
@@ -66,6 +152,12 @@ pub fn format_execution_event(
             style(pipeline_name).bold(),
             style(&execution_id.to_string()[..8]).dim()
         ),
+        crate::execution::ExecutionEvent::PipelineResumed { completed_steps } => format!(
+            "{} Resuming after {} completed step(s): {}",
+            INFO,
+            completed_steps.len(),
+            style(completed_steps.join(", ")).dim()
+        ),
         crate::execution::ExecutionEvent::StepStarted { step_id, attempt } => {
             if *attempt > 1 {
                 format!(
@@ -79,6 +171,7 @@ pub fn format_execution_event(
                 format!("{} {}", SPINNER, style(step_id).cyan())
             }
         }
+        crate::execution::ExecutionEvent::StepOutputChunk { delta, .. } => delta.clone(),
         crate::execution::ExecutionEvent::StepOutput { step_id, output } => {
             format!("{} Output from {}:\n{}", INFO, style(step_id).dim(), output)
         }
@@ -124,6 +217,173 @@ pub fn format_execution_event(
             style(from_step).dim(),
             style(to_step).cyan()
         ),
+        crate::execution::ExecutionEvent::ToolCallReviewed {
+            step_id,
+            tool_name,
+            approved,
+        } => {
+            if *approved {
+                format!("{} {}: {} allowed", INFO, style(step_id).dim(), style(tool_name).cyan())
+            } else {
+                format!("{} {}: {} denied", WARN, style(step_id).dim(), style(tool_name).red())
+            }
+        }
+        crate::execution::ExecutionEvent::StepFilesWritten {
+            step_id,
+            bytes,
+            files,
+        } => format!(
+            "{} {}: wrote {} file{} ({})",
+            INFO,
+            style(step_id).dim(),
+            files,
+            if *files == 1 { "" } else { "s" },
+            format_bytes(*bytes)
+        ),
+        crate::execution::ExecutionEvent::PromptPreview { step_id, attempt, prompt } => {
+            let mut text = format!(
+                "{} {}: prompt (attempt {})",
+                INFO,
+                style(step_id).dim(),
+                attempt
+            );
+            for line in prompt.lines() {
+                text.push_str(&format!("\n  {} {}", style("│").dim(), line));
+            }
+            text
+        }
+        crate::execution::ExecutionEvent::RunOnceStepSkipped { step_id, from_step } => format!(
+            "{} {}: already ran (run_once), skipping route from {}",
+            INFO,
+            style(step_id).dim(),
+            style(from_step).cyan()
+        ),
+        crate::execution::ExecutionEvent::StepReused { step_id, from_execution } => format!(
+            "{} {}: inputs unchanged, reusing output of run {}",
+            INFO,
+            style(step_id).dim(),
+            style(&from_execution.to_string()[..8]).cyan()
+        ),
+        crate::execution::ExecutionEvent::StepSkipped { step_id, reason } => format!(
+            "{} {}: skipped ({})",
+            INFO,
+            style(step_id).dim(),
+            style(reason).dim()
+        ),
+        crate::execution::ExecutionEvent::StepWaiting { step_id, condition } => format!(
+            "{} {}: waiting for {}",
+            INFO,
+            style(step_id).dim(),
+            style(condition).cyan()
+        ),
+        crate::execution::ExecutionEvent::StepHeartbeatMissed { step_id, silent_secs } => format!(
+            "{} {}: agent thinking (no events {}s)",
+            SPINNER,
+            style(step_id).dim(),
+            silent_secs
+        ),
+        crate::execution::ExecutionEvent::PollAttempt { step_id, attempt, passed } => {
+            if *passed {
+                format!("{} {}: check passed (attempt {})", INFO, style(step_id).dim(), attempt)
+            } else {
+                format!("{} {}: check not passing yet (attempt {})", SPINNER, style(step_id).dim(), attempt)
+            }
+        }
+        crate::execution::ExecutionEvent::SubPipelineStarted {
+            step_id,
+            execution_id,
+            pipeline_name,
+        } => format!(
+            "{} {}: running pipeline {} ({})",
+            SPINNER,
+            style(step_id).dim(),
+            style(pipeline_name).bold(),
+            style(&execution_id.to_string()[..8]).dim()
+        ),
+        crate::execution::ExecutionEvent::SubPipelineStepFinished {
+            step_id,
+            child_step_id,
+            error,
+        } => match error {
+            None => format!("{} {} › {}", CHECK, style(step_id).dim(), style(child_step_id).green()),
+            Some(error) => format!(
+                "{} {} › {}: {}",
+                CROSS,
+                style(step_id).dim(),
+                style(child_step_id).red(),
+                style(error).dim()
+            ),
+        },
+        crate::execution::ExecutionEvent::StepCost {
+            step_id,
+            cost_usd,
+            tokens,
+            budget,
+        } => {
+            let mut line = format!(
+                "{} {}: {} ({} tokens)",
+                INFO,
+                style(step_id).dim(),
+                format_cost(*cost_usd),
+                format_tokens(*tokens)
+            );
+            if let Some(budget) = budget {
+                line.push_str(&format!(
+                    ", {} {} of {}",
+                    style(&budget.category).cyan(),
+                    format_cost(budget.spent_usd),
+                    format_cost(budget.limit_usd)
+                ));
+            }
+            line
+        }
+        crate::execution::ExecutionEvent::AgentFailover {
+            step_id,
+            from,
+            to,
+            error,
+        } => format!(
+            "{} {}: agent {} failed ({}), failing over to {}",
+            WARN,
+            style(step_id).yellow(),
+            style(from).dim(),
+            style(error).dim(),
+            style(to).cyan()
+        ),
+        crate::execution::ExecutionEvent::ValueRemembered { step_id, key, value } => format!(
+            "{} {}: remembered {} = {}",
+            INFO,
+            style(step_id).dim(),
+            style(format!("memory.{}", key)).cyan(),
+            value
+        ),
+        crate::execution::ExecutionEvent::Warning {
+            scope,
+            code,
+            message,
+        } => format!(
+            "{} {} [{}] {}",
+            WARN,
+            style(scope).yellow(),
+            style(code.as_str()).dim(),
+            message
+        ),
+        crate::execution::ExecutionEvent::PipelineTimedOut {
+            limit_secs,
+            cancelled_steps,
+        } => {
+            let cancelled = if cancelled_steps.is_empty() {
+                String::new()
+            } else {
+                format!("; cancelled {}", cancelled_steps.join(", "))
+            };
+            format!(
+                "{} Pipeline ran longer than {}{}",
+                CROSS,
+                style(format_duration(std::time::Duration::from_secs(*limit_secs))).red(),
+                cancelled
+            )
+        }
         crate::execution::ExecutionEvent::PipelineCompleted {
             execution_id,
             status,
@@ -131,6 +391,7 @@ pub fn format_execution_event(
             let status_str = match status {
                 ExecutionStatus::Completed => format!("{} completed", style("successfully").green()),
                 ExecutionStatus::Failed => style("failed").red().to_string(),
+                ExecutionStatus::PartiallyCompleted => format!("{} with failed steps", style("finished").yellow()),
                 _ => format!("{:?}", status),
             };
             format!(
@@ -143,6 +404,91 @@ pub fn format_execution_event(
     }
 }
 
+/// Format a byte count for display (e.g. "12.4 KB")
+pub fn format_bytes(bytes: u64) -> String {
+    format_bytes_with(bytes, 1)
+}
+
+/// Format a byte count with `decimals` digits after the point
+///
+/// Counts under 1 KB are always shown exactly ("512 B").
+pub fn format_bytes_with(bytes: u64, decimals: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.*} {}", decimals, value, UNITS[unit])
+    }
+}
+
+/// Format a token count for display (e.g. "950", "12.3k", "1.2M")
+pub fn format_tokens(tokens: u64) -> String {
+    match tokens {
+        0..=999 => tokens.to_string(),
+        1_000..=999_999 => format!("{:.1}k", tokens as f64 / 1_000.0),
+        _ => format!("{:.1}M", tokens as f64 / 1_000_000.0),
+    }
+}
+
+/// Format a cost in USD for display (e.g. "$1.50")
+pub fn format_cost(usd: f64) -> String {
+    format_cost_with(usd, 2)
+}
+
+/// Format a cost in USD with `decimals` digits after the point
+///
+/// Costs too small to show at that precision read "<$0.01" rather than
+/// "$0.00", so spending is never hidden.
+pub fn format_cost_with(usd: f64, decimals: usize) -> String {
+    let smallest = 10f64.powi(-(decimals as i32));
+    if usd > 0.0 && usd < smallest / 2.0 {
+        format!("<${:.*}", decimals, smallest)
+    } else {
+        format!("${:.*}", decimals, usd)
+    }
+}
+
+/// Render per-pipeline metrics as a table, one row per pipeline
+pub fn format_stats(stats: &[PipelineStats]) -> String {
+    let rows: Vec<[String; 7]> = stats
+        .iter()
+        .map(|pipeline| {
+            [
+                pipeline.pipeline_name.clone(),
+                pipeline.runs.to_string(),
+                format!("{:.0}%", pipeline.success_rate * 100.0),
+                pipeline.avg_duration_secs.map_or_else(
+                    || "?".to_string(),
+                    |secs| format_duration_with(std::time::Duration::from_secs_f64(secs), 2),
+                ),
+                format!("{:.1}", pipeline.avg_retries),
+                format_tokens(pipeline.tokens),
+                format_cost(pipeline.cost_usd),
+            ]
+        })
+        .collect();
+    let header = ["Pipeline", "Runs", "Success", "Avg time", "Avg retries", "Tokens", "Cost"];
+    let widths: Vec<usize> = (0..header.len())
+        .map(|col| rows.iter().map(|row| row[col].len()).chain([header[col].len()]).max().unwrap_or_default())
+        .collect();
+    let line = |cells: &[String]| {
+        let cells: Vec<String> = cells.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        format!("  {}\n", cells.join("  ").trim_end())
+    };
+
+    let mut out = line(&header.map(str::to_string));
+    for row in &rows {
+        out.push_str(&line(row));
+    }
+    out
+}
+
 /// Format step output with truncation
 pub fn format_output(output: &str, max_lines: usize) -> String {
     let lines: Vec<&str> = output.lines().collect();
@@ -159,3 +505,108 @@ pub fn format_output(output: &str, max_lines: usize) -> String {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn relative_utc() -> TimestampFormat {
+        TimestampFormat {
+            local: false,
+            relative: true,
+        }
+    }
+
+    #[test]
+    fn test_format_durations() {
+        use std::time::Duration;
+        assert_eq!(format_duration(Duration::from_millis(350)), "350ms");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(192)), "3m 12s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 2m 3s");
+        assert_eq!(format_duration_with(Duration::from_secs(3723), 2), "1h 2m");
+        assert_eq!(format_duration_with(Duration::from_secs(3723), 1), "1h");
+        assert_eq!(format_duration_with(Duration::from_secs(72), 2), "1m 12s");
+    }
+
+    #[test]
+    fn test_format_sizes_tokens_and_costs() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(12_698), "12.4 KB");
+        assert_eq!(format_bytes_with(12_698, 2), "12.40 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MB");
+
+        assert_eq!(format_tokens(950), "950");
+        assert_eq!(format_tokens(12_345), "12.3k");
+        assert_eq!(format_tokens(1_234_567), "1.2M");
+
+        assert_eq!(format_cost(1.5), "$1.50");
+        assert_eq!(format_cost(0.0), "$0.00");
+        assert_eq!(format_cost(0.001), "<$0.01");
+        assert_eq!(format_cost_with(0.001, 4), "$0.0010");
+    }
+
+    #[test]
+    fn test_format_stats_table() {
+        let stats = vec![
+            PipelineStats {
+                pipeline_name: "Release".to_string(),
+                runs: 4,
+                succeeded: 3,
+                success_rate: 0.75,
+                avg_duration_secs: Some(192.4),
+                avg_retries: 0.5,
+                tokens: 48_200,
+                cost_usd: 1.5,
+            },
+            PipelineStats {
+                pipeline_name: "Docs".to_string(),
+                runs: 1,
+                succeeded: 0,
+                success_rate: 0.0,
+                avg_duration_secs: None,
+                avg_retries: 0.0,
+                tokens: 0,
+                cost_usd: 0.0,
+            },
+        ];
+        assert_eq!(
+            format_stats(&stats),
+            "  Pipeline  Runs  Success  Avg time  Avg retries  Tokens  Cost\n\
+             \x20 Release   4     75%      3m 12s    0.5          48.2k   $1.50\n\
+             \x20 Docs      1     0%       ?         0.0          0       $0.00\n"
+        );
+    }
+
+    #[test]
+    fn test_absolute_utc_is_rfc3339() {
+        let ts = Utc.with_ymd_and_hms(2024, 3, 5, 14, 2, 0).unwrap();
+        assert_eq!(
+            TimestampFormat::default().format_at(ts, ts),
+            "2024-03-05T14:02:00+00:00"
+        );
+    }
+
+    #[test]
+    fn test_relative_forms() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 16, 30, 0).unwrap();
+        let fmt = relative_utc();
+
+        assert_eq!(fmt.format_at(now - Duration::seconds(20), now), "just now");
+        assert_eq!(fmt.format_at(now - Duration::minutes(3), now), "3m ago");
+        assert_eq!(fmt.format_at(now - Duration::hours(2), now), "2h ago");
+        assert_eq!(
+            fmt.format_at(Utc.with_ymd_and_hms(2024, 3, 4, 14, 2, 0).unwrap(), now),
+            "yesterday 14:02"
+        );
+        assert_eq!(
+            fmt.format_at(Utc.with_ymd_and_hms(2024, 3, 1, 9, 15, 0).unwrap(), now),
+            "Fri 09:15"
+        );
+        assert_eq!(
+            fmt.format_at(Utc.with_ymd_and_hms(2024, 2, 1, 9, 15, 0).unwrap(), now),
+            "2024-02-01 09:15"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Shareable reports of a finished run
+//!
+//! Built from the execution summary in history and the step records and
+//! outputs saved in the run directory.
+
+use crate::cli::output::{format_bytes, format_cost, format_duration, TimestampFormat};
+use crate::persistence::{ExecutionSummary, StepRecord};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+/// Report output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReportFormat {
+    /// Markdown (renders on GitHub, GitLab and most wikis)
+    Md,
+}
+
+/// Render a run as a Markdown document
+pub fn markdown_report(
+    summary: &ExecutionSummary,
+    steps: &[StepRecord],
+    outputs: &HashMap<String, String>,
+    times: &TimestampFormat,
+) -> String {
+    let mut md = String::new();
+
+    md.push_str(&format!("# Pipeline run: {}\n\n", summary.pipeline_name));
+    md.push_str("| | |\n|---|---|\n");
+    md.push_str(&format!("| Execution | `{}` |\n", summary.execution_id));
+    md.push_str(&format!("| Status | {:?} |\n", summary.status));
+    md.push_str(&format!("| Started | {} |\n", times.format(summary.started_at)));
+    if let Some(completed) = summary.completed_at {
+        md.push_str(&format!("| Finished | {} |\n", times.format(completed)));
+        md.push_str(&format!("| Duration | {} |\n", elapsed(summary.started_at, completed)));
+    }
+    md.push_str(&format!(
+        "| Steps | {}/{} completed |\n",
+        summary.completed_steps, summary.total_steps
+    ));
+    if summary.bytes_written > 0 {
+        md.push_str(&format!("| Written | {} |\n", format_bytes(summary.bytes_written)));
+    }
+
+    if steps.is_empty() {
+        md.push_str("\n_No step records were saved for this run._\n");
+        return md;
+    }
+
+    // Dependency graph (Mermaid renders inline on GitHub)
+    md.push_str("\n## Steps\n\n```mermaid\ngraph TD\n");
+    for step in steps {
+        md.push_str(&format!("    {}[\"{} ({})\"]\n", node_id(&step.step_id), step.step_id, step.status));
+        for dep in &step.depends_on {
+            md.push_str(&format!("    {} --> {}\n", node_id(dep), node_id(&step.step_id)));
+        }
+        // Dotted: one of these had to complete
+        for dep in &step.depends_on_any {
+            md.push_str(&format!("    {} -.-> {}\n", node_id(dep), node_id(&step.step_id)));
+        }
+    }
+    md.push_str("```\n\n");
+
+    md.push_str("| Step | Status | Attempts | Duration | Written | Cost |\n");
+    md.push_str("|------|--------|----------|----------|---------|------|\n");
+    for step in steps {
+        let duration = match (step.started_at, step.finished_at) {
+            (Some(start), Some(end)) => elapsed(start, end),
+            _ => "-".to_string(),
+        };
+        let written = if step.bytes_written > 0 {
+            format_bytes(step.bytes_written)
+        } else {
+            "-".to_string()
+        };
+        let cost = if step.cost_usd > 0.0 {
+            format_cost(step.cost_usd)
+        } else {
+            "-".to_string()
+        };
+        md.push_str(&format!(
+            "| `{}` | {} | {} | {} | {} | {} |\n",
+            step.step_id, step.status, step.attempts, duration, written, cost
+        ));
+    }
+
+    // Spending per budget category
+    let mut categories: Vec<&str> = steps.iter().filter_map(|s| s.budget_category.as_deref()).collect();
+    categories.sort_unstable();
+    categories.dedup();
+    if !categories.is_empty() {
+        md.push_str("\n| Budget | Spent |\n|--------|-------|\n");
+        for category in categories {
+            let spent: f64 = steps
+                .iter()
+                .filter(|s| s.budget_category.as_deref() == Some(category))
+                .map(|s| s.cost_usd)
+                .sum();
+            md.push_str(&format!("| {} | {} |\n", category, format_cost(spent)));
+        }
+    }
+
+    // Per-step details with collapsed output
+    for step in steps {
+        md.push_str(&format!("\n### {}\n\n", step.step_id));
+        if let Some(error) = &step.error {
+            md.push_str(&format!("> {}\n\n", error.lines().collect::<Vec<_>>().join("\n> ")));
+        }
+        if !step.agent_attempts.is_empty() {
+            let attempts: Vec<String> = step
+                .agent_attempts
+                .iter()
+                .map(|attempt| match attempt.error {
+                    Some(_) => format!("`{}` (failed)", attempt.agent),
+                    None => format!("`{}`", attempt.agent),
+                })
+                .collect();
+            md.push_str(&format!("Agents: {}\n\n", attempts.join(" → ")));
+        }
+        match outputs.get(&step.step_id) {
+            Some(output) => {
+                let fence = fence_for(output);
+                md.push_str(&format!(
+                    "<details>\n<summary>Output ({} lines)</summary>\n\n{}text\n{}\n{}\n\n</details>\n",
+                    output.lines().count(),
+                    fence,
+                    output.trim_end(),
+                    fence
+                ));
+            }
+            None => md.push_str("_No output._\n"),
+        }
+    }
+
+    md
+}
+
+fn elapsed(start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    end.signed_duration_since(start)
+        .to_std()
+        .map(format_duration)
+        .unwrap_or_else(|_| "-".to_string())
+}
+
+/// Mermaid node identifier for a step ID (which may contain dashes or dots)
+pub(crate) fn node_id(step_id: &str) -> String {
+    step_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Code fence longer than any backtick run in `text`
+fn fence_for(text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{AgentAttempt, ExecutionStatus};
+    use uuid::Uuid;
+
+    fn record(step_id: &str, status: &str, depends_on: &[&str]) -> StepRecord {
+        let started = Utc::now();
+        StepRecord {
+            step_id: step_id.to_string(),
+            status: status.to_string(),
+            depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+            depends_on_any: vec![],
+            attempts: 1,
+            started_at: Some(started),
+            finished_at: Some(started + chrono::Duration::seconds(75)),
+            bytes_written: 0,
+            error: None,
+            agent_attempts: vec![],
+            budget_category: None,
+            cost_usd: 0.0,
+            tokens: 0,
+            prompt_hashes: vec![],
+            input_digest: None,
+        }
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let started = Utc::now();
+        let summary = ExecutionSummary {
+            execution_id: Uuid::new_v4(),
+            pipeline_name: "Feature".to_string(),
+            status: ExecutionStatus::Failed,
+            started_at: started,
+            completed_at: Some(started + chrono::Duration::seconds(200)),
+            progress: 0.5,
+            completed_steps: 1,
+            total_steps: 2,
+            bytes_written: 2048,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        };
+        let mut failed = record("code-review", "failed", &["plan"]);
+        failed.error = Some("Exceeded retry limit of 3".to_string());
+        failed.agent_attempts = vec![
+            AgentAttempt { agent: "primary".to_string(), error: Some("API error: overloaded".to_string()) },
+            AgentAttempt { agent: "fallback".to_string(), error: None },
+        ];
+        failed.budget_category = Some("codegen".to_string());
+        failed.cost_usd = 1.5;
+        let steps = vec![record("plan", "completed", &[]), failed];
+        let outputs = HashMap::from([("plan".to_string(), "Plan:\n```rust\nfn x() {}\n```\n".to_string())]);
+
+        let md = markdown_report(&summary, &steps, &outputs, &TimestampFormat::default());
+
+        assert!(md.starts_with("# Pipeline run: Feature"));
+        assert!(md.contains("| Duration | 3m 20s |"));
+        assert!(md.contains("    plan --> code_review\n"));
+        assert!(md.contains("| `plan` | completed | 1 | 1m 15s | - | - |"));
+        assert!(md.contains("| codegen | $1.50 |"));
+        assert!(md.contains("> Exceeded retry limit of 3"));
+        assert!(md.contains("Agents: `primary` (failed) → `fallback`\n"));
+        assert!(md.contains("<summary>Output (4 lines)</summary>\n\n````text\n"));
+    }
+
+    #[test]
+    fn test_fence_for() {
+        assert_eq!(fence_for("plain"), "```");
+        assert_eq!(fence_for("a ```` b"), "`````");
+    }
+}
@@ -0,0 +1,320 @@
+//! Runs on a timetable (`pipeline scheduler`)
+//!
+//! A schedules file lists cron expressions, each with a pipeline file and
+//! the variables and overrides to run it with. `pipeline scheduler run`
+//! sleeps until an entry is due, then starts a child `pipeline run` for it
+//! (see `child_run`) and records the run under the schedule's name (see
+//! `ScheduledRun`). A schedule whose previous run is still going is skipped
+//! unless it sets `allow_concurrent`; the record is what tells after a
+//! restart. Times are local, and times missed while the scheduler was not
+//! running are not made up.
+
+use crate::cli::child_run::ChildRun;
+use crate::core::ExecutionStatus;
+use crate::persistence::{PersistenceBackend, ScheduledRun, STALE_AFTER};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Longest single sleep, so a changed clock or a suspended machine delays a
+/// run by at most this much
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// An entry of the schedules file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Name the runs are recorded under
+    pub name: String,
+    /// Cron expression: minute, hour, day of month, month and day of week,
+    /// with an optional leading seconds and trailing year field, or one of
+    /// `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly`
+    pub cron: String,
+    /// Pipeline file to run, relative to the schedules file
+    pub file: PathBuf,
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Setting overrides, by dotted path as `--set` takes them
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+    /// Start a run even while the previous one is still going
+    #[serde(default)]
+    pub allow_concurrent: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SchedulesFile {
+    schedules: Vec<ScheduleConfig>,
+}
+
+/// A schedule with its cron expression parsed
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub config: ScheduleConfig,
+    cron: cron::Schedule,
+}
+
+impl Schedule {
+    /// Schedule of `config`; its `file` is used as given
+    pub fn new(config: ScheduleConfig) -> Result<Self> {
+        anyhow::ensure!(!config.name.trim().is_empty(), "A schedule needs a name");
+        let cron = parse_cron(&config.cron)
+            .with_context(|| format!("Invalid cron expression '{}' in schedule '{}'", config.cron, config.name))?;
+        Ok(Self { config, cron })
+    }
+
+    /// First time the schedule is due after `after`
+    pub fn next_after(&self, after: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.cron.after(after).next()
+    }
+}
+
+/// Parse a cron expression, reading five fields as starting at minutes
+fn parse_cron(expression: &str) -> Result<cron::Schedule> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression.trim()),
+        _ => expression.trim().to_string(),
+    };
+    Ok(expression.parse::<cron::Schedule>()?)
+}
+
+/// Read the schedules file at `path`
+pub fn load_schedules(path: &Path) -> Result<Vec<Schedule>> {
+    let yaml = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: SchedulesFile =
+        serde_yaml::from_str(&yaml).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut schedules: Vec<Schedule> = Vec::new();
+    for mut config in file.schedules {
+        anyhow::ensure!(
+            !schedules.iter().any(|schedule| schedule.config.name == config.name),
+            "Duplicate schedule '{}'",
+            config.name
+        );
+        config.file = base.join(&config.file);
+        anyhow::ensure!(
+            config.file.is_file(),
+            "Pipeline file {} of schedule '{}' not found",
+            config.file.display(),
+            config.name
+        );
+        schedules.push(Schedule::new(config)?);
+    }
+    Ok(schedules)
+}
+
+/// Starts the runs of a set of schedules as they come due
+pub struct Scheduler {
+    schedules: Vec<Schedule>,
+    data_dir: PathBuf,
+    program: PathBuf,
+    store: Arc<dyn PersistenceBackend>,
+    /// Runs started here that are still going, by schedule
+    running: Arc<Mutex<HashMap<String, HashSet<Uuid>>>>,
+}
+
+impl Scheduler {
+    /// Scheduler starting runs with `program` (this binary) on `data_dir`,
+    /// and recording them in `store`
+    pub fn new(schedules: Vec<Schedule>, data_dir: PathBuf, program: PathBuf, store: Arc<dyn PersistenceBackend>) -> Self {
+        Self {
+            schedules,
+            data_dir,
+            program,
+            store,
+            running: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Start runs as schedules come due, until the process is stopped
+    pub async fn run(&self) -> Result<()> {
+        let mut after = Local::now();
+        loop {
+            let Some(next) = self.schedules.iter().filter_map(|schedule| schedule.next_after(&after)).min() else {
+                anyhow::bail!("No schedule is due again");
+            };
+            while let Ok(wait) = (next - Local::now()).to_std() {
+                tokio::time::sleep(wait.min(MAX_SLEEP)).await;
+            }
+
+            for schedule in &self.schedules {
+                if schedule.next_after(&after) != Some(next) {
+                    continue;
+                }
+                match self.start(schedule, next.with_timezone(&Utc)).await {
+                    Ok(Some(execution_id)) => {
+                        tracing::info!("Schedule {} started run {}", schedule.config.name, execution_id)
+                    }
+                    Ok(None) => {}
+                    Err(e) => tracing::error!("Schedule {} could not start a run: {:#}", schedule.config.name, e),
+                }
+            }
+            after = next;
+        }
+    }
+
+    /// Start a run of `schedule`, due at `due_at`, and record it
+    ///
+    /// Returns `None` if the previous run is still going and the schedule
+    /// doesn't allow concurrent runs.
+    pub async fn start(&self, schedule: &Schedule, due_at: DateTime<Utc>) -> Result<Option<Uuid>> {
+        let name = &schedule.config.name;
+        if !schedule.config.allow_concurrent {
+            if let Some(previous) = self.running_run(name).await? {
+                tracing::warn!("Schedule {} skipped: run {} is still going", name, previous);
+                return Ok(None);
+            }
+        }
+
+        let execution_id = Uuid::new_v4();
+        let mut child = ChildRun {
+            program: &self.program,
+            data_dir: &self.data_dir,
+            file: &schedule.config.file,
+            execution_id,
+            variables: &schedule.config.variables,
+            overrides: &schedule.config.overrides,
+            output: self.data_dir.join("scheduler").join(format!("{}.log", execution_id)),
+        }
+        .spawn(&[])?;
+        self.running.lock().unwrap().entry(name.clone()).or_default().insert(execution_id);
+        self.store
+            .save_scheduled_run(&ScheduledRun {
+                schedule: name.clone(),
+                execution_id,
+                due_at,
+                started_at: Utc::now(),
+            })
+            .await?;
+
+        let running = self.running.clone();
+        let name = name.clone();
+        tokio::spawn(async move {
+            match child.wait().await {
+                Ok(status) => tracing::info!("Schedule {} run {} exited with {}", name, execution_id, status),
+                Err(e) => tracing::warn!("Could not wait for run {} of schedule {}: {}", execution_id, name, e),
+            }
+            if let Some(ids) = running.lock().unwrap().get_mut(&name) {
+                ids.remove(&execution_id);
+            }
+        });
+        Ok(Some(execution_id))
+    }
+
+    /// A run of `schedule` that is still going: one started here, or the
+    /// last one recorded if history says it is running
+    async fn running_run(&self, schedule: &str) -> Result<Option<Uuid>> {
+        let started_here = self
+            .running
+            .lock()
+            .unwrap()
+            .get(schedule)
+            .and_then(|ids| ids.iter().next().copied());
+        if started_here.is_some() {
+            return Ok(started_here);
+        }
+
+        let Some(last) = self.store.load_scheduled_runs(schedule, 1).await?.pop() else {
+            return Ok(None);
+        };
+        // A run that died without saying so isn't running
+        let stale_before = Utc::now() - chrono::Duration::from_std(STALE_AFTER)?;
+        self.store.mark_orphaned(stale_before).await?;
+        let summary = self.store.load_execution(last.execution_id).await?;
+        Ok(summary
+            .filter(|summary| summary.status == ExecutionStatus::Running)
+            .map(|summary| summary.execution_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{ExecutionSummary, InMemoryPersistence};
+    use chrono::TimeZone;
+
+    fn schedule(file: &Path, cron: &str, allow_concurrent: bool) -> Schedule {
+        Schedule::new(ScheduleConfig {
+            name: "nightly".to_string(),
+            cron: cron.to_string(),
+            file: file.to_path_buf(),
+            variables: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+            allow_concurrent,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cron_expressions() {
+        let after = Local.with_ymd_and_hms(2026, 3, 2, 10, 30, 0).unwrap();
+        let next = |cron: &str| schedule(Path::new("p.yaml"), cron, false).next_after(&after).unwrap();
+        // Five fields start at minutes
+        assert_eq!(next("0 2 * * *"), Local.with_ymd_and_hms(2026, 3, 3, 2, 0, 0).unwrap());
+        assert_eq!(next("*/15 * * * *"), Local.with_ymd_and_hms(2026, 3, 2, 10, 45, 0).unwrap());
+        assert_eq!(next("30 0 9 * * Mon-Fri"), Local.with_ymd_and_hms(2026, 3, 3, 9, 0, 30).unwrap());
+        assert_eq!(next("@daily"), Local.with_ymd_and_hms(2026, 3, 3, 0, 0, 0).unwrap());
+
+        let err = Schedule::new(ScheduleConfig {
+            cron: "every day".to_string(),
+            ..schedule(Path::new("p.yaml"), "@daily", false).config
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("Invalid cron expression 'every day' in schedule 'nightly'"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_runs_do_not_overlap_unless_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("p.yaml");
+        std::fs::write(&file, "name: Nightly\n").unwrap();
+        // Stands in for the pipeline binary and keeps running
+        let program = dir.path().join("pipeline");
+        std::fs::write(&program, "#!/bin/sh\nsleep 5\n").unwrap();
+        std::fs::set_permissions(&program, std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+        let store = Arc::new(InMemoryPersistence::new());
+        let scheduler = Scheduler::new(Vec::new(), dir.path().to_path_buf(), program, store.clone());
+        let due_at = Utc::now();
+
+        let exclusive = schedule(&file, "@daily", false);
+        let first = scheduler.start(&exclusive, due_at).await.unwrap().unwrap();
+        assert_eq!(scheduler.start(&exclusive, due_at).await.unwrap(), None);
+        let concurrent = schedule(&file, "@daily", true);
+        assert!(scheduler.start(&concurrent, due_at).await.unwrap().is_some());
+
+        let runs = store.load_scheduled_runs("nightly", 10).await.unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1].execution_id, first);
+        assert_eq!(runs[1].due_at, due_at);
+
+        // After a restart, history says whether the last run is going
+        let restarted = Scheduler::new(Vec::new(), dir.path().to_path_buf(), PathBuf::from("true"), store.clone());
+        let mut summary = ExecutionSummary {
+            execution_id: runs[0].execution_id,
+            pipeline_name: "Nightly".to_string(),
+            status: ExecutionStatus::Running,
+            started_at: Utc::now(),
+            completed_at: None,
+            progress: 0.0,
+            completed_steps: 0,
+            total_steps: 1,
+            bytes_written: 0,
+            outputs: BTreeMap::new(),
+            seed: None,
+            parent_execution_id: None,
+        };
+        store.save_execution(&summary).await.unwrap();
+        store.heartbeat(summary.execution_id).await.unwrap();
+        assert_eq!(restarted.start(&exclusive, due_at).await.unwrap(), None);
+        summary.status = ExecutionStatus::Completed;
+        store.save_execution(&summary).await.unwrap();
+        assert!(restarted.start(&exclusive, due_at).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,1201 @@
+//! HTTP API for triggering and observing runs (`pipeline serve`)
+//!
+//! Pipelines are submitted as YAML and kept in the server's directory. Each
+//! execution runs as a child `pipeline run` process (see `child_run`),
+//! writing its events to a JSON lines log (see `EventLog`) that the
+//! server streams to clients as server-sent events or over a WebSocket.
+//! History comes from the persistence backend the runs save to.
+//!
+//! | Method | Path | |
+//! |--------|------|-|
+//! | `GET` | `/api/pipelines` | Names of the submitted pipelines |
+//! | `PUT` | `/api/pipelines/{name}` | Submit (or replace) a pipeline; the body is its YAML |
+//! | `GET` | `/api/pipelines/{name}` | A submitted pipeline's YAML |
+//! | `POST` | `/api/pipelines/{name}/runs` | Start an execution; the optional JSON body sets `variables` and `overrides` |
+//! | `GET` | `/api/executions` | History, filtered by `pipeline`, `status` and `limit` |
+//! | `GET` | `/api/executions/{id}` | An execution's summary, step results and process state |
+//! | `GET` | `/api/executions/{id}/events` | The events of an execution started here, as server-sent events |
+//! | `GET` | `/api/executions/{id}/ws` | The same events as WebSocket text messages |
+//! | `POST` | `/hooks/{trigger}` | Run a trigger's pipeline for a signed webhook |
+//!
+//! Every `/api` request must send the server's token, if it has one, as
+//! `Authorization: Bearer <token>`; `serve` refuses to listen on anything
+//! but a loopback address without one.
+//!
+//! Triggers (`--triggers <file>`) map webhooks to a pipeline file. A hook
+//! must be signed with the trigger's secret as GitHub signs them
+//! (`X-Hub-Signature-256`), which stands in for the bearer token. The
+//! payload of a GitHub push or pull request sets `trigger.*` variables
+//! (see `trigger_variables`), which the run gets along with the trigger's
+//! own `variables`, where `{{ trigger.* }}` is replaced too. A trigger
+//! with a `worktree:` section runs in a worktree of a local clone checked
+//! out at the hook's commit (see `worktrees`).
+
+use crate::cli::child_run::ChildRun;
+use crate::cli::webhook;
+use crate::cli::worktrees::{WorktreeConfig, WorktreePool};
+use crate::core::config::{is_valid_name, PipelineConfig};
+use crate::core::ExecutionStatus;
+use crate::persistence::{HistoryQuery, PersistenceBackend, EventLogReader};
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{DefaultBodyLimit, Path as UrlPath, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures_util::{Stream, StreamExt};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+/// Largest request body accepted (pipeline YAML or run parameters)
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// How often an event stream checks its log for new events
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Quiet time after which an event stream sends a keep-alive comment
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// History entries returned unless `limit` says otherwise
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+/// State of the process of an execution started by this server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Process {
+    Running,
+    /// Exited with this code (`None` if it was killed by a signal)
+    Exited(Option<i32>),
+}
+
+/// The API's state: where pipelines and logs live, how runs are started and
+/// the processes of the runs started so far
+pub struct Server {
+    dir: PathBuf,
+    data_dir: PathBuf,
+    program: PathBuf,
+    store: Arc<dyn PersistenceBackend>,
+    token: Option<String>,
+    triggers: Vec<Trigger>,
+    /// Worktree pools of the triggers that have one, by trigger name
+    worktrees: HashMap<String, Arc<WorktreePool>>,
+    processes: Mutex<HashMap<Uuid, Process>>,
+}
+
+/// A trigger rule as written in the triggers file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TriggerConfig {
+    /// Name in the hook's path, `/hooks/{name}`
+    pub name: String,
+    /// Pipeline file to run, relative to the triggers file
+    pub file: PathBuf,
+    /// Environment variable holding the secret hooks are signed with
+    pub secret_env: String,
+    /// GitHub events (`X-GitHub-Event`) that start a run; all if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Glob patterns of the branches that start a run; all if empty
+    #[serde(default)]
+    pub branches: Vec<String>,
+    /// Variables of the run; `{{ trigger.* }}` is replaced in values
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Setting overrides of the run, by dotted path as `--set` takes them
+    #[serde(default)]
+    pub overrides: BTreeMap<String, String>,
+    /// Run in a worktree of this clone checked out at the hook's commit
+    #[serde(default)]
+    pub worktree: Option<WorktreeConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TriggersFile {
+    triggers: Vec<TriggerConfig>,
+}
+
+/// A trigger rule with its secret read and patterns compiled
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    config: TriggerConfig,
+    secret: String,
+    branches: Vec<glob::Pattern>,
+}
+
+impl Trigger {
+    /// Trigger signed with `secret`; `config.file` is used as given
+    pub fn new(config: TriggerConfig, secret: String) -> Result<Self> {
+        anyhow::ensure!(is_valid_name(&config.name), "Invalid trigger name '{}'", config.name);
+        anyhow::ensure!(!secret.is_empty(), "Trigger '{}' has an empty secret", config.name);
+        let branches = config
+            .branches
+            .iter()
+            .map(|pattern| {
+                glob::Pattern::new(pattern)
+                    .with_context(|| format!("Invalid branch pattern '{}' in trigger '{}'", pattern, config.name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            config,
+            secret,
+            branches,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Why a hook with these `trigger.*` variables starts no run, if it
+    /// doesn't
+    fn skip_reason(&self, variables: &BTreeMap<String, String>) -> Option<String> {
+        let event = variables.get("trigger.event").map(String::as_str).unwrap_or_default();
+        if !self.config.events.is_empty() && !self.config.events.iter().any(|e| e == event) {
+            return Some(format!("Event '{}' is not one of {:?}", event, self.config.events));
+        }
+        if !self.branches.is_empty() {
+            let Some(branch) = variables.get("trigger.branch") else {
+                return Some("The payload names no branch".to_string());
+            };
+            if !self.branches.iter().any(|pattern| pattern.matches(branch)) {
+                return Some(format!("Branch '{}' matches none of {:?}", branch, self.config.branches));
+            }
+        }
+        let names_commit = ["trigger.sha", "trigger.branch", "trigger.tag"]
+            .iter()
+            .any(|key| variables.contains_key(*key));
+        if self.config.worktree.is_some() && !names_commit {
+            return Some("The payload names no commit to check out".to_string());
+        }
+        None
+    }
+
+    /// Variables of a run for a hook with these `trigger.*` variables
+    fn run_variables(&self, variables: BTreeMap<String, String>) -> BTreeMap<String, String> {
+        static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+        let placeholder =
+            PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{\s*(trigger\.[A-Za-z_]+)\s*\}\}").unwrap());
+        let own: Vec<(String, String)> = self
+            .config
+            .variables
+            .iter()
+            .map(|(key, value)| {
+                let value = placeholder.replace_all(value, |captures: &regex::Captures| {
+                    variables.get(&captures[1]).cloned().unwrap_or_default()
+                });
+                (key.clone(), value.into_owned())
+            })
+            .collect();
+        let mut variables = variables;
+        variables.extend(own);
+        variables
+    }
+}
+
+/// Read the triggers file at `path`, taking each trigger's secret from the
+/// environment
+pub fn load_triggers(path: &Path) -> Result<Vec<Trigger>> {
+    let yaml = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: TriggersFile =
+        serde_yaml::from_str(&yaml).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let mut triggers: Vec<Trigger> = Vec::new();
+    for mut config in file.triggers {
+        anyhow::ensure!(
+            !triggers.iter().any(|trigger| trigger.config.name == config.name),
+            "Duplicate trigger '{}'",
+            config.name
+        );
+        config.file = base.join(&config.file);
+        anyhow::ensure!(
+            config.file.is_file(),
+            "Pipeline file {} of trigger '{}' not found",
+            config.file.display(),
+            config.name
+        );
+        if let Some(worktree) = &mut config.worktree {
+            worktree.repo = base.join(&worktree.repo);
+            anyhow::ensure!(
+                worktree.repo.is_dir(),
+                "Repository {} of trigger '{}' not found",
+                worktree.repo.display(),
+                config.name
+            );
+        }
+        let secret = std::env::var(&config.secret_env)
+            .with_context(|| format!("{} (secret of trigger '{}') is not set", config.secret_env, config.name))?;
+        triggers.push(Trigger::new(config, secret)?);
+    }
+    Ok(triggers)
+}
+
+/// `trigger.*` variables of a hook: `name` and `event` always, and from
+/// the payload of a GitHub push `branch` (or `tag`), `sha` and `repo`, and
+/// of a pull request `branch` (its head), `sha`, `repo`, `pr` and `action`
+fn trigger_variables(name: &str, event: &str, payload: &Value) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    variables.insert("trigger.name".to_string(), name.to_string());
+    variables.insert("trigger.event".to_string(), event.to_string());
+    let mut set = |key: &str, value: &Value| {
+        let value = match value {
+            Value::String(value) => value.clone(),
+            Value::Number(value) => value.to_string(),
+            _ => return,
+        };
+        variables.insert(format!("trigger.{}", key), value);
+    };
+
+    set("repo", &payload["repository"]["full_name"]);
+    match event {
+        "push" => {
+            let reference = payload["ref"].as_str().unwrap_or_default();
+            if let Some(branch) = reference.strip_prefix("refs/heads/") {
+                set("branch", &json!(branch));
+            } else if let Some(tag) = reference.strip_prefix("refs/tags/") {
+                set("tag", &json!(tag));
+            }
+            set("sha", &payload["after"]);
+        }
+        "pull_request" => {
+            set("branch", &payload["pull_request"]["head"]["ref"]);
+            set("sha", &payload["pull_request"]["head"]["sha"]);
+            set("pr", &payload["number"]);
+            set("action", &payload["action"]);
+        }
+        _ => {}
+    }
+    variables
+}
+
+/// Parameters of `POST /api/pipelines/{name}/runs`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RunRequest {
+    variables: BTreeMap<String, String>,
+    overrides: BTreeMap<String, String>,
+}
+
+/// Filters of `GET /api/executions`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct HistoryParams {
+    pipeline: Option<String>,
+    status: Option<String>,
+    limit: Option<String>,
+}
+
+/// A failed request, answered as `{"error": ...}`
+#[derive(Debug)]
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl std::fmt::Display) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+
+    fn internal(message: impl std::fmt::Display) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+type ApiResult = Result<Response, ApiError>;
+
+/// A line of an execution's event log, or the end of the log
+enum EventLine {
+    Event(String),
+    End,
+}
+
+impl Server {
+    /// Server keeping its files under `dir`, starting runs with `program`
+    /// (this binary) on `data_dir`, and reading history from `store`
+    ///
+    /// With a `token`, every request but a hook must send it as
+    /// `Authorization: Bearer <token>`.
+    pub fn new(
+        dir: PathBuf,
+        data_dir: PathBuf,
+        program: PathBuf,
+        store: Arc<dyn PersistenceBackend>,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            dir,
+            data_dir,
+            program,
+            store,
+            token,
+            triggers: Vec::new(),
+            worktrees: HashMap::new(),
+            processes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Receive hooks for `triggers`
+    ///
+    /// The worktrees of a trigger with a `worktree:` section are kept under
+    /// `worktrees/<trigger>` in the server's directory.
+    pub fn with_triggers(mut self, triggers: Vec<Trigger>) -> Self {
+        self.worktrees = triggers
+            .iter()
+            .filter_map(|trigger| {
+                let config = trigger.config.worktree.clone()?;
+                let dir = self.dir.join("worktrees").join(trigger.name());
+                Some((trigger.name().to_string(), Arc::new(WorktreePool::new(config, dir))))
+            })
+            .collect();
+        self.triggers = triggers;
+        self
+    }
+
+    fn pipeline_file(&self, name: &str) -> PathBuf {
+        self.dir.join("pipelines").join(format!("{}.yaml", name))
+    }
+
+    fn event_log(&self, execution_id: Uuid) -> PathBuf {
+        self.dir.join("events").join(format!("{}.jsonl", execution_id))
+    }
+
+    fn output_log(&self, execution_id: Uuid) -> PathBuf {
+        self.dir.join("output").join(format!("{}.log", execution_id))
+    }
+
+    fn process(&self, execution_id: Uuid) -> Option<Process> {
+        self.processes.lock().unwrap().get(&execution_id).copied()
+    }
+
+    /// Executions whose processes are still running
+    fn running(&self) -> HashSet<Uuid> {
+        let processes = self.processes.lock().unwrap();
+        processes
+            .iter()
+            .filter(|(_, process)| **process == Process::Running)
+            .map(|(execution_id, _)| *execution_id)
+            .collect()
+    }
+
+    /// Whether a request with this `Authorization` header may use the API
+    fn authorized(&self, authorization: Option<&HeaderValue>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let expected = format!("Bearer {}", token);
+        authorization.is_some_and(|value| bool::from(value.as_bytes().ct_eq(expected.as_bytes())))
+    }
+
+    fn list_pipelines(&self) -> ApiResult {
+        let mut names: Vec<String> = match std::fs::read_dir(self.dir.join("pipelines")) {
+            Ok(entries) => entries
+                .flatten()
+                .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".yaml").map(str::to_string))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(ApiError::internal(e)),
+        };
+        names.sort();
+        Ok(Json(json!({ "pipelines": names })).into_response())
+    }
+
+    fn submit_pipeline(&self, name: &str, body: &[u8]) -> ApiResult {
+        check_name(name)?;
+        let yaml = std::str::from_utf8(body).map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, "Body is not UTF-8"))?;
+        let path = self.pipeline_file(name);
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir).map_err(ApiError::internal)?;
+        // Parsing runs no commands; the files of `pipeline:` steps are
+        // loaded from the pipelines directory, where the run will look
+        let config = PipelineConfig::from_yaml_in_dir(yaml, dir)
+            .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, format!("{:#}", e)))?;
+
+        std::fs::write(&path, yaml).map_err(ApiError::internal)?;
+        Ok((
+            StatusCode::CREATED,
+            Json(json!({
+                "name": name,
+                "pipeline": config.name,
+                "steps": config.steps.len(),
+                "warnings": config.warnings,
+            })),
+        )
+            .into_response())
+    }
+
+    fn show_pipeline(&self, name: &str) -> ApiResult {
+        check_name(name)?;
+        match std::fs::read_to_string(self.pipeline_file(name)) {
+            Ok(yaml) => Ok(([(header::CONTENT_TYPE, "application/yaml")], yaml).into_response()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(ApiError::new(StatusCode::NOT_FOUND, format!("No pipeline named '{}'", name)))
+            }
+            Err(e) => Err(ApiError::internal(e)),
+        }
+    }
+
+    fn start_run(self: &Arc<Self>, name: &str, body: &[u8]) -> ApiResult {
+        check_name(name)?;
+        let file = self.pipeline_file(name);
+        if !file.exists() {
+            return Err(ApiError::new(StatusCode::NOT_FOUND, format!("No pipeline named '{}'", name)));
+        }
+        let run: RunRequest = if body.iter().all(u8::is_ascii_whitespace) {
+            RunRequest::default()
+        } else {
+            serde_json::from_slice(body).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?
+        };
+        self.launch(&file, &run)
+    }
+
+    fn hook(self: &Arc<Self>, name: &str, headers: &HeaderMap, body: &[u8]) -> ApiResult {
+        let Some(trigger) = self.triggers.iter().find(|trigger| trigger.config.name == *name) else {
+            return Err(ApiError::new(StatusCode::NOT_FOUND, format!("No trigger named '{}'", name)));
+        };
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok()).unwrap_or_default();
+        if !webhook::verify(&trigger.secret, body, header("x-hub-signature-256")) {
+            return Err(ApiError::new(StatusCode::UNAUTHORIZED, "Missing or wrong X-Hub-Signature-256"));
+        }
+
+        let event = header("x-github-event");
+        // Sent when the hook is set up on GitHub
+        if event == "ping" {
+            return Ok(Json(json!({ "ok": true })).into_response());
+        }
+        let payload: Value = if body.iter().all(u8::is_ascii_whitespace) {
+            Value::Null
+        } else {
+            serde_json::from_slice(body).map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?
+        };
+
+        let variables = trigger_variables(name, event, &payload);
+        if let Some(reason) = trigger.skip_reason(&variables) {
+            return Ok(Json(json!({ "skipped": reason })).into_response());
+        }
+        let pool = self.worktrees.get(name).cloned();
+        let target = pool.as_ref().and_then(|pool| {
+            let get = |key: &str| variables.get(key).map(String::as_str);
+            pool.target(get("trigger.sha"), get("trigger.branch"), get("trigger.tag"))
+        });
+        let run = RunRequest {
+            variables: trigger.run_variables(variables),
+            overrides: trigger.config.overrides.clone(),
+        };
+        match (pool, target) {
+            (Some(pool), Some(target)) => self.launch_in_worktree(&trigger.config.file, run, pool, target),
+            _ => self.launch(&trigger.config.file, &run),
+        }
+    }
+
+    /// Start a run of `file` and track its process
+    fn launch(self: &Arc<Self>, file: &Path, run: &RunRequest) -> ApiResult {
+        let execution_id = Uuid::new_v4();
+        let child = self
+            .spawn_run(file, execution_id, run)
+            .map_err(|e| ApiError::internal(format!("Could not start the run: {:#}", e)))?;
+        self.processes.lock().unwrap().insert(execution_id, Process::Running);
+
+        let server = self.clone();
+        tokio::spawn(async move { server.wait_for(execution_id, child).await });
+        Ok(accepted(execution_id))
+    }
+
+    /// Start a run of `file` in a worktree of `pool` checked out at
+    /// `target`, and track its process
+    ///
+    /// Fetching can take a while, so the worktree is prepared after the
+    /// hook is answered; a run whose worktree could not be prepared exits
+    /// without a code, with the reason in its output log. Once the run has
+    /// ended, the pool evicts the worktrees it no longer needs.
+    fn launch_in_worktree(
+        self: &Arc<Self>,
+        file: &Path,
+        mut run: RunRequest,
+        pool: Arc<WorktreePool>,
+        target: String,
+    ) -> ApiResult {
+        let execution_id = Uuid::new_v4();
+        let event_log = self.event_log(execution_id);
+        let create_log = || -> std::io::Result<()> {
+            std::fs::create_dir_all(event_log.parent().unwrap())?;
+            // The event stream waits for the log to exist
+            std::fs::File::create(&event_log).map(drop)
+        };
+        create_log().map_err(ApiError::internal)?;
+        self.processes.lock().unwrap().insert(execution_id, Process::Running);
+
+        let (server, file) = (self.clone(), file.to_path_buf());
+        tokio::spawn(async move {
+            let checkout = {
+                let (pool, server) = (pool.clone(), server.clone());
+                tokio::task::spawn_blocking(move || pool.checkout(execution_id, &target, || server.running())).await
+            };
+            let started = match checkout {
+                Ok(Ok(worktree)) => {
+                    run.overrides.insert("working_dir".to_string(), worktree.display().to_string());
+                    server.spawn_run(&file, execution_id, &run)
+                }
+                Ok(Err(e)) => Err(e),
+                Err(e) => Err(e.into()),
+            };
+            match started {
+                Ok(child) => server.wait_for(execution_id, child).await,
+                Err(e) => {
+                    tracing::warn!("Could not prepare the worktree of run {}: {:#}", execution_id, e);
+                    let output = server.output_log(execution_id);
+                    let write = || -> std::io::Result<()> {
+                        std::fs::create_dir_all(output.parent().unwrap())?;
+                        std::fs::write(&output, format!("Could not prepare the worktree: {:#}\n", e))
+                    };
+                    if let Err(e) = write() {
+                        tracing::debug!("Could not write the output of run {}: {}", execution_id, e);
+                    }
+                    server.processes.lock().unwrap().insert(execution_id, Process::Exited(None));
+                }
+            }
+
+            match tokio::task::spawn_blocking(move || pool.evict(|| server.running())).await {
+                Ok(Err(e)) => tracing::warn!("Could not evict worktrees: {:#}", e),
+                Err(e) => tracing::warn!("Could not evict worktrees: {}", e),
+                Ok(Ok(_)) => {}
+            }
+        });
+        Ok(accepted(execution_id))
+    }
+
+    /// Record the exit of the run `execution_id` once its process ends
+    async fn wait_for(&self, execution_id: Uuid, mut child: tokio::process::Child) {
+        let exited = match child.wait().await {
+            Ok(status) => Process::Exited(status.code()),
+            Err(e) => {
+                tracing::debug!("Could not wait for run {}: {}", execution_id, e);
+                Process::Exited(None)
+            }
+        };
+        self.processes.lock().unwrap().insert(execution_id, exited);
+    }
+
+    /// Start `pipeline run` on `file` in the background
+    fn spawn_run(&self, file: &Path, execution_id: Uuid, run: &RunRequest) -> Result<tokio::process::Child> {
+        let event_log = self.event_log(execution_id);
+        std::fs::create_dir_all(event_log.parent().unwrap())?;
+        // The event stream waits for the log to exist
+        std::fs::File::create(&event_log)?;
+
+        ChildRun {
+            program: &self.program,
+            data_dir: &self.data_dir,
+            file,
+            execution_id,
+            variables: &run.variables,
+            overrides: &run.overrides,
+            output: self.output_log(execution_id),
+        }
+        .spawn(&["--event-log".as_ref(), event_log.as_os_str()])
+    }
+
+    async fn history(&self, params: &HistoryParams) -> ApiResult {
+        let statuses = match &params.status {
+            Some(status) => vec![status
+                .parse::<ExecutionStatus>()
+                .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?],
+            None => Vec::new(),
+        };
+        let limit = match &params.limit {
+            Some(limit) => limit
+                .parse()
+                .map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid limit '{}'", limit)))?,
+            None => DEFAULT_HISTORY_LIMIT,
+        };
+        let found = self
+            .store
+            .search_executions(&HistoryQuery {
+                pipeline: params.pipeline.clone(),
+                statuses,
+                limit,
+                ..Default::default()
+            })
+            .await
+            .map_err(ApiError::internal)?;
+        let executions: Vec<_> = found.into_iter().map(|found| found.summary).collect();
+        Ok(Json(json!({ "executions": executions })).into_response())
+    }
+
+    async fn execution(&self, id: &str) -> ApiResult {
+        let execution_id = parse_id(id)?;
+        let summary = self.store.load_execution(execution_id).await.map_err(ApiError::internal)?;
+        let process = self.process(execution_id);
+        if summary.is_none() && process.is_none() {
+            return Err(ApiError::new(StatusCode::NOT_FOUND, format!("No execution {}", execution_id)));
+        }
+        let steps = self.store.load_step_results(execution_id).await.map_err(ApiError::internal)?;
+        let process = match process {
+            Some(Process::Running) => json!({ "running": true }),
+            Some(Process::Exited(code)) => json!({ "running": false, "exit_code": code }),
+            None => Value::Null,
+        };
+        Ok(Json(json!({ "execution_id": execution_id, "summary": summary, "steps": steps, "process": process }))
+            .into_response())
+    }
+
+    /// The execution `id` started here, if its event log exists
+    fn logged_execution(&self, id: &str) -> Result<Uuid, ApiError> {
+        let execution_id = parse_id(id)?;
+        if self.event_log(execution_id).exists() {
+            Ok(execution_id)
+        } else {
+            Err(ApiError::new(StatusCode::NOT_FOUND, "No events for this execution"))
+        }
+    }
+
+    /// The events of `execution_id` as they are logged, until its process
+    /// has exited and the log is read to the end
+    fn event_lines(self: Arc<Self>, execution_id: Uuid) -> impl Stream<Item = EventLine> {
+        let reader = EventLogReader::for_file(&self.event_log(execution_id));
+        let state = (self, reader, std::collections::VecDeque::new(), false);
+        futures_util::stream::unfold(state, move |(server, mut reader, mut pending, ended)| async move {
+            loop {
+                if let Some(line) = pending.pop_front() {
+                    return Some((EventLine::Event(line), (server, reader, pending, ended)));
+                }
+                if ended {
+                    return None;
+                }
+                // Checked before reading, so events logged just before the exit are sent
+                let exited = !matches!(server.process(execution_id), Some(Process::Running));
+                match reader.read_new() {
+                    Ok(lines) if !lines.is_empty() => pending.extend(lines),
+                    Ok(_) if exited => return Some((EventLine::End, (server, reader, pending, true))),
+                    Ok(_) => tokio::time::sleep(EVENT_POLL_INTERVAL).await,
+                    Err(e) => {
+                        tracing::debug!("Could not read the events of {}: {}", execution_id, e);
+                        return None;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// The API's routes, answered by `server`
+pub fn router(server: Arc<Server>) -> Router {
+    let api = Router::new()
+        .route("/api/pipelines", get(list_pipelines))
+        .route("/api/pipelines/{name}", get(show_pipeline).put(submit_pipeline))
+        .route("/api/pipelines/{name}/runs", post(start_run))
+        .route("/api/executions", get(history))
+        .route("/api/executions/{id}", get(execution))
+        .route("/api/executions/{id}/events", get(events))
+        .route("/api/executions/{id}/ws", get(events_socket))
+        .route_layer(middleware::from_fn_with_state(server.clone(), require_token));
+
+    Router::new()
+        .merge(api)
+        // Hooks are signed instead
+        .route("/hooks/{trigger}", post(hook))
+        .fallback(|| async { ApiError::new(StatusCode::NOT_FOUND, "Not found") })
+        .layer(middleware::map_response(no_store))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .with_state(server)
+}
+
+/// Serve the API on `addr` until the process is stopped
+///
+/// Without a token, only a loopback address is accepted.
+pub async fn serve(addr: &str, server: Server) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    let local = listener.local_addr()?;
+    anyhow::ensure!(
+        server.token.is_some() || local.ip().is_loopback(),
+        "Refusing to serve the API on {} without a token; set --token-env or listen on localhost",
+        local
+    );
+    axum::serve(listener, router(Arc::new(server)))
+        .await
+        .context("API server failed")
+}
+
+async fn require_token(State(server): State<Arc<Server>>, request: Request, next: Next) -> Response {
+    if server.authorized(request.headers().get(header::AUTHORIZATION)) {
+        next.run(request).await
+    } else {
+        ApiError::new(StatusCode::UNAUTHORIZED, "Missing or wrong bearer token").into_response()
+    }
+}
+
+async fn no_store(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    response
+}
+
+async fn list_pipelines(State(server): State<Arc<Server>>) -> ApiResult {
+    server.list_pipelines()
+}
+
+async fn show_pipeline(State(server): State<Arc<Server>>, UrlPath(name): UrlPath<String>) -> ApiResult {
+    server.show_pipeline(&name)
+}
+
+async fn submit_pipeline(State(server): State<Arc<Server>>, UrlPath(name): UrlPath<String>, body: Bytes) -> ApiResult {
+    server.submit_pipeline(&name, &body)
+}
+
+async fn start_run(State(server): State<Arc<Server>>, UrlPath(name): UrlPath<String>, body: Bytes) -> ApiResult {
+    server.start_run(&name, &body)
+}
+
+async fn history(State(server): State<Arc<Server>>, params: Result<Query<HistoryParams>, QueryRejection>) -> ApiResult {
+    let Query(params) = params.map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.body_text()))?;
+    server.history(&params).await
+}
+
+async fn execution(State(server): State<Arc<Server>>, UrlPath(id): UrlPath<String>) -> ApiResult {
+    server.execution(&id).await
+}
+
+async fn events(State(server): State<Arc<Server>>, UrlPath(id): UrlPath<String>) -> ApiResult {
+    let execution_id = server.logged_execution(&id)?;
+    let events = server.event_lines(execution_id).map(|line| {
+        Ok::<_, std::convert::Infallible>(match line {
+            EventLine::Event(line) => Event::default().data(line),
+            EventLine::End => Event::default().event("end").data("{}"),
+        })
+    });
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(KEEPALIVE_INTERVAL))
+        .into_response())
+}
+
+/// The events as WebSocket text messages, closing the socket after the last
+async fn events_socket(
+    State(server): State<Arc<Server>>,
+    UrlPath(id): UrlPath<String>,
+    upgrade: WebSocketUpgrade,
+) -> ApiResult {
+    let execution_id = server.logged_execution(&id)?;
+    Ok(upgrade.on_upgrade(move |socket| send_events(socket, server, execution_id)))
+}
+
+async fn send_events(mut socket: WebSocket, server: Arc<Server>, execution_id: Uuid) {
+    let events = server.event_lines(execution_id);
+    futures_util::pin_mut!(events);
+    while let Some(EventLine::Event(line)) = events.next().await {
+        if socket.send(Message::Text(line.into())).await.is_err() {
+            return;
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn hook(
+    State(server): State<Arc<Server>>,
+    UrlPath(name): UrlPath<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult {
+    server.hook(&name, &headers, &body)
+}
+
+/// Answer to a request that started the run `execution_id`
+fn accepted(execution_id: Uuid) -> Response {
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "execution_id": execution_id,
+            "events": format!("/api/executions/{}/events", execution_id),
+        })),
+    )
+        .into_response()
+}
+
+fn check_name(name: &str) -> Result<(), ApiError> {
+    if is_valid_name(name) {
+        Ok(())
+    } else {
+        Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("Invalid pipeline name '{}' (use letters, digits, '-', '_' and '.')", name),
+        ))
+    }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(id).map_err(|_| ApiError::new(StatusCode::BAD_REQUEST, format!("Invalid execution ID '{}'", id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::{ExecutionSummary, InMemoryPersistence};
+    use axum::body::Body;
+    use tower::ServiceExt;
+
+    fn request(method: &str, target: &str, body: &str) -> axum::http::Request<Body> {
+        axum::http::Request::builder()
+            .method(method)
+            .uri(target)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// Status and body of the answer to `request`
+    async fn send(server: &Arc<Server>, request: axum::http::Request<Body>) -> (StatusCode, String) {
+        let response = router(server.clone()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    async fn reply(server: &Arc<Server>, method: &str, target: &str, body: &str) -> (StatusCode, String) {
+        send(server, request(method, target, body)).await
+    }
+
+    fn server(dir: &Path, token: Option<&str>) -> Server {
+        // `true` stands in for the pipeline binary and exits at once
+        Server::new(
+            dir.to_path_buf(),
+            dir.join("data"),
+            PathBuf::from("true"),
+            Arc::new(InMemoryPersistence::new()),
+            token.map(str::to_string),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_pipelines_are_submitted_and_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Arc::new(server(dir.path(), None));
+
+        let yaml = "name: Build\nsteps:\n  - id: build\n    name: Build\n    prompt: Build it\n";
+        let (status, body) = reply(&server, "PUT", "/api/pipelines/build", yaml).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!(reply(&server, "GET", "/api/pipelines/build", "").await.1, yaml);
+        assert_eq!(
+            reply(&server, "GET", "/api/pipelines", "").await.1,
+            json!({ "pipelines": ["build"] }).to_string()
+        );
+        assert_eq!(
+            reply(&server, "PUT", "/api/pipelines/build", "steps: [").await.0,
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(reply(&server, "PUT", "/api/pipelines/..", yaml).await.0, StatusCode::BAD_REQUEST);
+
+        let (status, body) =
+            reply(&server, "POST", "/api/pipelines/build/runs", r#"{"variables": {"branch": "main"}}"#).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+        let started: Value = serde_json::from_str(&body).unwrap();
+        let id = started["execution_id"].as_str().unwrap().to_string();
+
+        assert_eq!(reply(&server, "GET", &format!("/api/executions/{}", id), "").await.0, StatusCode::OK);
+        // The stream ends once the run's process has exited
+        let (status, events) = reply(&server, "GET", &format!("/api/executions/{}/events", id), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(events.ends_with("event: end\ndata: {}\n\n"), "{}", events);
+
+        assert_eq!(
+            reply(&server, "POST", "/api/pipelines/build/runs", r#"{"vars": {}}"#).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(reply(&server, "POST", "/api/pipelines/missing/runs", "").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(
+            reply(&server, "GET", &format!("/api/executions/{}", Uuid::new_v4()), "").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            reply(&server, "GET", &format!("/api/executions/{}/events", Uuid::new_v4()), "").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_uploads_run_no_commands_and_stay_in_the_pipelines_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Arc::new(server(dir.path(), None));
+
+        let marker = dir.path().join("ran");
+        let yaml = format!(
+            "name: Build\nvariables:\n  touched:\n    command: touch {}\nsteps:\n  - id: build\n    name: Build\n    prompt: Build it\n",
+            marker.display()
+        );
+        let (status, body) = reply(&server, "PUT", "/api/pipelines/build", &yaml).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert!(!marker.exists(), "uploading must not run command variables");
+
+        // `pipeline:` files resolve against the pipelines directory, as they
+        // will when the run loads the saved file
+        let parent = "name: Release\nsteps:\n  - id: build\n    name: Build\n    pipeline:\n      file: build.yaml\n";
+        let (status, body) = reply(&server, "PUT", "/api/pipelines/release", parent).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+
+        std::fs::write(dir.path().join("outside.yaml"), &yaml).unwrap();
+        let escaping = parent.replace("build.yaml", "../outside.yaml");
+        let (status, body) = reply(&server, "PUT", "/api/pipelines/escape", &escaping).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body.contains("is outside"), "{}", body);
+        assert_eq!(reply(&server, "GET", "/api/pipelines/escape", "").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_signed_hooks_run_their_trigger() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("release.yaml");
+        std::fs::write(&file, "name: Release\nsteps:\n  - id: tag\n    name: Tag\n    prompt: Tag it\n").unwrap();
+        let trigger = Trigger::new(
+            TriggerConfig {
+                name: "github".to_string(),
+                file,
+                secret_env: "UNUSED".to_string(),
+                events: vec!["push".to_string(), "pull_request".to_string()],
+                branches: vec!["main".to_string(), "release/*".to_string()],
+                variables: BTreeMap::from([("target".to_string(), "build-{{ trigger.branch }}".to_string())]),
+                overrides: BTreeMap::new(),
+                worktree: None,
+            },
+            "hooksecret".to_string(),
+        )
+        .unwrap();
+        let server = Arc::new(server(dir.path(), Some("token")).with_triggers(vec![trigger.clone()]));
+
+        let hook = |event: &str, body: &str, secret: &str| {
+            let mut hook = request("POST", "/hooks/github", body);
+            let headers = hook.headers_mut();
+            headers.insert("x-github-event", event.parse().unwrap());
+            headers.insert("x-hub-signature-256", webhook::sign(secret, body).parse().unwrap());
+            hook
+        };
+        let push = r#"{"ref": "refs/heads/release/1.2", "after": "abc123", "repository": {"full_name": "acme/app"}}"#;
+
+        // No bearer token needed, but the signature must match
+        let (status, body) = send(&server, hook("push", push, "hooksecret")).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", body);
+        assert_eq!(send(&server, hook("push", push, "guess")).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(&server, hook("ping", "{}", "hooksecret")).await.0, StatusCode::OK);
+
+        let (status, skipped) = send(&server, hook("push", r#"{"ref": "refs/heads/feature"}"#, "hooksecret")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(skipped.contains("Branch 'feature' matches none"), "{}", skipped);
+        let (_, skipped) = send(&server, hook("issues", "{}", "hooksecret")).await;
+        assert!(skipped.contains("Event 'issues'"), "{}", skipped);
+
+        let variables = trigger_variables("github", "push", &serde_json::from_str(push).unwrap());
+        assert_eq!(
+            trigger.run_variables(variables),
+            BTreeMap::from([
+                ("target".to_string(), "build-release/1.2".to_string()),
+                ("trigger.branch".to_string(), "release/1.2".to_string()),
+                ("trigger.event".to_string(), "push".to_string()),
+                ("trigger.name".to_string(), "github".to_string()),
+                ("trigger.repo".to_string(), "acme/app".to_string()),
+                ("trigger.sha".to_string(), "abc123".to_string()),
+            ])
+        );
+        let pull_request = json!({
+            "action": "opened",
+            "number": 42,
+            "pull_request": { "head": { "ref": "fix", "sha": "def456" } },
+        });
+        let variables = trigger_variables("github", "pull_request", &pull_request);
+        assert_eq!(variables["trigger.pr"], "42");
+        assert_eq!(variables["trigger.branch"], "fix");
+        assert_eq!(variables["trigger.sha"], "def456");
+        assert_eq!(variables["trigger.action"], "opened");
+    }
+
+    #[tokio::test]
+    async fn test_hooks_of_a_trigger_with_a_worktree_run_in_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |dir: &Path, args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-C"])
+                .arg(dir)
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            String::from_utf8(output.stdout).unwrap()
+        };
+        let remote = dir.path().join("remote");
+        std::fs::create_dir_all(&remote).unwrap();
+        git(&remote, &["init", "--quiet", "--initial-branch", "main"]);
+        std::fs::write(remote.join("README"), "app\n").unwrap();
+        git(&remote, &["add", "."]);
+        git(&remote, &["commit", "--quiet", "-m", "first"]);
+        git(dir.path(), &["clone", "--quiet", "remote", "clone"]);
+        let sha = git(&remote, &["rev-parse", "HEAD"]).trim().to_string();
+
+        let file = dir.path().join("build.yaml");
+        std::fs::write(&file, "name: Build\nsteps:\n  - id: build\n    name: Build\n    prompt: Build it\n").unwrap();
+        let trigger = Trigger::new(
+            TriggerConfig {
+                name: "github".to_string(),
+                file,
+                secret_env: "UNUSED".to_string(),
+                events: Vec::new(),
+                branches: Vec::new(),
+                variables: BTreeMap::new(),
+                overrides: BTreeMap::new(),
+                worktree: serde_yaml::from_str(&format!("repo: {}", dir.path().join("clone").display())).unwrap(),
+            },
+            "hooksecret".to_string(),
+        )
+        .unwrap();
+        // `echo` stands in for the pipeline binary, writing its arguments to the output log
+        let server = Arc::new(
+            Server::new(
+                dir.path().to_path_buf(),
+                dir.path().join("data"),
+                PathBuf::from("echo"),
+                Arc::new(InMemoryPersistence::new()),
+                None,
+            )
+            .with_triggers(vec![trigger]),
+        );
+
+        let hook = |body: &str| {
+            let mut hook = request("POST", "/hooks/github", body);
+            let headers = hook.headers_mut();
+            headers.insert("x-github-event", "push".parse().unwrap());
+            headers.insert("x-hub-signature-256", webhook::sign("hooksecret", body).parse().unwrap());
+            hook
+        };
+        let (status, skipped) = send(&server, hook("{}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(skipped.contains("no commit to check out"), "{}", skipped);
+
+        let push = format!(r#"{{"ref": "refs/heads/main", "after": "{}"}}"#, sha);
+        let (status, started) = send(&server, hook(&push)).await;
+        assert_eq!(status, StatusCode::ACCEPTED, "{}", started);
+        let started: Value = serde_json::from_str(&started).unwrap();
+        let execution_id: Uuid = started["execution_id"].as_str().unwrap().parse().unwrap();
+        for _ in 0..500 {
+            if server.process(execution_id) != Some(Process::Running) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.process(execution_id), Some(Process::Exited(Some(0))));
+
+        let worktree = dir.path().join("worktrees/github/wt-1").canonicalize().unwrap();
+        assert_eq!(git(&worktree, &["rev-parse", "HEAD"]).trim(), sha);
+        let output = std::fs::read_to_string(server.output_log(execution_id)).unwrap();
+        assert!(output.contains(&format!("working_dir={}", worktree.display())), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn test_history_and_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(InMemoryPersistence::new());
+        store
+            .save_execution(&ExecutionSummary {
+                execution_id: Uuid::new_v4(),
+                pipeline_name: "Nightly build".to_string(),
+                status: ExecutionStatus::Failed,
+                started_at: chrono::Utc::now(),
+                completed_at: None,
+                progress: 1.0,
+                completed_steps: 1,
+                total_steps: 1,
+                bytes_written: 0,
+                outputs: Default::default(),
+                seed: None,
+                parent_execution_id: None,
+            })
+            .await
+            .unwrap();
+        let server = Arc::new(Server::new(
+            dir.path().to_path_buf(),
+            dir.path().join("data"),
+            PathBuf::from("true"),
+            store,
+            Some("secret".to_string()),
+        ));
+
+        assert_eq!(reply(&server, "GET", "/api/executions", "").await.0, StatusCode::UNAUTHORIZED);
+        let with_token = |target: &str, token: &str| {
+            let mut request = request("GET", target, "");
+            request
+                .headers_mut()
+                .insert(header::AUTHORIZATION, format!("Bearer {}", token).parse().unwrap());
+            request
+        };
+        assert_eq!(
+            send(&server, with_token("/api/executions", "secrets")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Query values are percent-decoded
+        let (status, history) =
+            send(&server, with_token("/api/executions?pipeline=Nightly%20build&status=failed&limit=5", "secret")).await;
+        assert_eq!(status, StatusCode::OK);
+        let history: Value = serde_json::from_str(&history).unwrap();
+        assert_eq!(history["executions"][0]["pipeline_name"], "Nightly build");
+
+        let (status, _) = send(&server, with_token("/api/executions?status=sideways", "secret")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_token_is_required_off_loopback() {
+        let dir = tempfile::tempdir().unwrap();
+        let refused = serve("0.0.0.0:0", server(dir.path(), None)).await.unwrap_err();
+        assert!(refused.to_string().contains("without a token"), "{}", refused);
+    }
+
+    #[tokio::test]
+    async fn test_events_over_websocket() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let dir = tempfile::tempdir().unwrap();
+        let server = Arc::new(server(dir.path(), None));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(server.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let yaml = "name: Build\nsteps:\n  - id: build\n    name: Build\n    prompt: Build it\n";
+        assert_eq!(reply(&server, "PUT", "/api/pipelines/build", yaml).await.0, StatusCode::CREATED);
+        let (_, started) = reply(&server, "POST", "/api/pipelines/build/runs", "").await;
+        let started: Value = serde_json::from_str(&started).unwrap();
+        let execution_id: Uuid = started["execution_id"].as_str().unwrap().parse().unwrap();
+        std::fs::write(server.event_log(execution_id), "{\"type\":\"started\"}\n").unwrap();
+
+        let url = format!("ws://{}/api/executions/{}/ws", addr, execution_id);
+        let (mut socket, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+        let mut messages = Vec::new();
+        while let Some(message) = socket.next().await {
+            match message.unwrap() {
+                WsMessage::Text(text) => messages.push(text.to_string()),
+                WsMessage::Close(_) => break,
+                _ => {}
+            }
+        }
+        assert_eq!(messages, vec!["{\"type\":\"started\"}".to_string()]);
+    }
+}
@@ -15,7 +15,7 @@
 //!   - `<bash: command>` in yellow
 //!   - `<edit: path>` in cyan
 //! - Tool execution status with ✓ (success) and ✗ (error)
-//! - Stdout flushing for immediate output
+//! - Text deltas batched into periodic writes, with an optional rate limit
 //!
 //! # Example
 //!
@@ -33,7 +33,99 @@ use crate::agent::pi_events::AssistantMessageEvent;
 use console::style;
 use serde_json::Value;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Write buffered text at least this often while deltas are arriving
+const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Write buffered text once this many bytes are pending
+const FLUSH_BYTES: usize = 4096;
+
+/// Batches text deltas for display and optionally caps the display rate
+///
+/// Only what is rendered is affected; the agent's accumulated output is
+/// collected separately by the client.
+#[derive(Debug)]
+pub struct OutputThrottle {
+    flush_interval: Duration,
+    flush_bytes: usize,
+    max_bytes_per_sec: Option<usize>,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    pending: String,
+    last_flush: Instant,
+    window_start: Instant,
+    window_bytes: usize,
+    elided: usize,
+}
+
+impl OutputThrottle {
+    /// Create a throttle flushing every `flush_interval` or `flush_bytes`
+    pub fn new(flush_interval: Duration, flush_bytes: usize, max_bytes_per_sec: Option<usize>) -> Self {
+        let now = Instant::now();
+        Self {
+            flush_interval,
+            flush_bytes,
+            max_bytes_per_sec,
+            state: Mutex::new(ThrottleState {
+                pending: String::new(),
+                last_flush: now,
+                window_start: now,
+                window_bytes: 0,
+                elided: 0,
+            }),
+        }
+    }
+
+    /// Add a delta, returning text that is due to be written
+    pub fn push(&self, delta: &str, now: Instant) -> Option<String> {
+        let mut state = self.state.lock().unwrap();
+
+        let shown = match self.max_bytes_per_sec {
+            Some(max) => {
+                if now.duration_since(state.window_start) >= Duration::from_secs(1) {
+                    state.window_start = now;
+                    state.window_bytes = 0;
+                }
+                let cut = char_boundary_at_most(delta, max.saturating_sub(state.window_bytes));
+                state.window_bytes += cut;
+                state.elided += delta.len() - cut;
+                &delta[..cut]
+            }
+            None => delta,
+        };
+        state.pending.push_str(shown);
+
+        let due = state.pending.len() >= self.flush_bytes
+            || now.duration_since(state.last_flush) >= self.flush_interval;
+        if due && !state.pending.is_empty() {
+            state.last_flush = now;
+            Some(std::mem::take(&mut state.pending))
+        } else {
+            None
+        }
+    }
+
+    /// Take all pending text and the number of bytes dropped by the rate limit
+    pub fn drain(&self) -> (String, usize) {
+        let mut state = self.state.lock().unwrap();
+        state.last_flush = Instant::now();
+        (std::mem::take(&mut state.pending), std::mem::take(&mut state.elided))
+    }
+}
+
+/// Largest char boundary in `s` that is at most `max`
+fn char_boundary_at_most(s: &str, max: usize) -> usize {
+    if max >= s.len() {
+        return s.len();
+    }
+    (0..=max).rev().find(|&i| s.is_char_boundary(i)).unwrap_or(0)
+}
 
 /// Callback that displays streaming events to the terminal
 ///
@@ -56,6 +148,7 @@ pub struct TerminalOutputCallback {
     total_steps: AtomicUsize,  // Reserved for Phase 3/4: step progress tracking
     in_thinking: AtomicBool,
     last_tool_call_id: AtomicUsize,  // Last tool call index for validation
+    throttle: OutputThrottle,
 }
 
 impl TerminalOutputCallback {
@@ -80,9 +173,18 @@ impl TerminalOutputCallback {
             total_steps: AtomicUsize::new(total_steps),
             in_thinking: AtomicBool::new(false),
             last_tool_call_id: AtomicUsize::new(0),
+            throttle: OutputThrottle::new(FLUSH_INTERVAL, FLUSH_BYTES, None),
         }
     }
 
+    /// Limit how many bytes of agent text are rendered per second
+    ///
+    /// Text over the limit is not printed; a note reports how much was skipped.
+    pub fn with_max_output_rate(mut self, max_bytes_per_sec: Option<usize>) -> Self {
+        self.throttle = OutputThrottle::new(FLUSH_INTERVAL, FLUSH_BYTES, max_bytes_per_sec);
+        self
+    }
+
     /// Get color style for a tool type
     ///
     /// # Color Mapping
@@ -301,6 +403,18 @@ impl TerminalOutputCallback {
         let _ = io::stdout().flush();
     }
 
+    /// Write any buffered text before other output
+    fn flush_text(&self) {
+        let (text, elided) = self.throttle.drain();
+        if !text.is_empty() {
+            print!("{}", text);
+        }
+        if elided > 0 {
+            print!("{}", style(format!(" [… {} bytes not shown]", elided)).dim());
+        }
+        self.flush_stdout();
+    }
+
     /// Print thinking section start
     ///
     /// Called when thinking begins and `show_thinking` is enabled.
@@ -334,54 +448,66 @@ impl TerminalOutputCallback {
 
 impl ProgressCallback for TerminalOutputCallback {
     fn on_event(&self, event: &PiJsonEvent) {
+        // Keep buffered text ahead of anything else this event prints
+        let is_text_delta = matches!(
+            event,
+            PiJsonEvent::MessageUpdate {
+                assistant_message_event: Some(AssistantMessageEvent::TextDelta { .. }),
+                ..
+            }
+        );
+        if !is_text_delta {
+            self.flush_text();
+        }
+
         match event {
             PiJsonEvent::AgentStart => {
                 // Pipeline execution started - could print header here
             }
-            PiJsonEvent::MessageUpdate { assistant_message_event, .. } => {
-                if let Some(assistant_event) = assistant_message_event {
-                    match assistant_event {
-                        AssistantMessageEvent::TextDelta { delta, .. } => {
-                            // Print text immediately and flush
-                            print!("{}", delta);
+            PiJsonEvent::MessageUpdate { assistant_message_event: Some(assistant_event), .. } => {
+                match assistant_event {
+                    AssistantMessageEvent::TextDelta { delta, .. } => {
+                        // Print text in batches rather than per delta
+                        if let Some(text) = self.throttle.push(delta, Instant::now()) {
+                            print!("{}", text);
                             self.flush_stdout();
                         }
-                        AssistantMessageEvent::TextEnd { .. } => {
-                            // Text output complete - ensure newline
-                            println!();
-                        }
-                        AssistantMessageEvent::ThinkingDelta { .. } => {
-                            // Start thinking section if not already in it
-                            if self.show_thinking && !self.in_thinking.load(Ordering::SeqCst) {
-                                self.print_thinking_start();
-                            }
-                            // Thinking deltas are printed only if show_thinking is enabled
-                        }
-                        AssistantMessageEvent::ThinkingEnd { .. } => {
-                            self.print_thinking_end();
-                        }
-                        AssistantMessageEvent::ToolcallStart { partial, .. } => {
-                            // Extract tool name from the partial message content
-                            if let Some(content_array) = partial.content.get(0) {
-                                if let Some(tool_name) = content_array.get("name").and_then(|v| v.as_str()) {
-                                    if let Some(args) = content_array.get("arguments") {
-                                        println!();
-                                        println!("{}", Self::format_tool_call(tool_name, args));
-                                        self.flush_stdout();
-                                    }
+                    }
+                    AssistantMessageEvent::TextEnd { .. } => {
+                        // Text output complete - ensure newline
+                        println!();
+                    }
+                    // Start thinking section if not already in it; thinking
+                    // deltas are printed only if show_thinking is enabled
+                    AssistantMessageEvent::ThinkingDelta { .. }
+                        if self.show_thinking && !self.in_thinking.load(Ordering::SeqCst) =>
+                    {
+                        self.print_thinking_start();
+                    }
+                    AssistantMessageEvent::ThinkingEnd { .. } => {
+                        self.print_thinking_end();
+                    }
+                    AssistantMessageEvent::ToolcallStart { partial, .. } => {
+                        // Extract tool name from the partial message content
+                        if let Some(content_array) = partial.content.first() {
+                            if let Some(tool_name) = content_array.get("name").and_then(|v| v.as_str()) {
+                                if let Some(args) = content_array.get("arguments") {
+                                    println!();
+                                    println!("{}", Self::format_tool_call(tool_name, args));
+                                    self.flush_stdout();
                                 }
                             }
                         }
-                        AssistantMessageEvent::ToolcallEnd { tool_call, .. } => {
-                            // Tool call complete - store tool_call_id for result matching
-                            // We increment a counter to track tool call sequence
-                            self.last_tool_call_id.fetch_add(1, Ordering::SeqCst);
-                        }
-                        _ => {}
                     }
+                    AssistantMessageEvent::ToolcallEnd { .. } => {
+                        // Tool call complete - store tool_call_id for result matching
+                        // We increment a counter to track tool call sequence
+                        self.last_tool_call_id.fetch_add(1, Ordering::SeqCst);
+                    }
+                    _ => {}
                 }
             }
-            PiJsonEvent::ToolExecutionStart { tool_call_id, tool_name, .. } => {
+            PiJsonEvent::ToolExecutionStart { tool_name, .. } => {
                 // Tool execution started
                 // Validate that we have a matching tool call (basic check)
                 let expected_count = self.last_tool_call_id.load(Ordering::SeqCst);
@@ -394,7 +520,7 @@ impl ProgressCallback for TerminalOutputCallback {
                 println!("  Executing {}...", tool_name);
                 self.flush_stdout();
             }
-            PiJsonEvent::ToolExecutionEnd { tool_call_id, tool_name, is_error, result, .. } => {
+            PiJsonEvent::ToolExecutionEnd { is_error, result, .. } => {
                 // Tool execution complete with result
                 // Note: tool_call_id validation would require full ID storage and lookup
                 // For now, we rely on event order as provided by the subprocess
@@ -416,6 +542,44 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    // Output throttling
+
+    #[test]
+    fn test_throttle_batches_until_interval() {
+        let throttle = OutputThrottle::new(Duration::from_millis(50), 1024, None);
+        let start = Instant::now();
+
+        assert_eq!(throttle.push("Hel", start), None);
+        assert_eq!(throttle.push("lo", start + Duration::from_millis(10)), None);
+        assert_eq!(
+            throttle.push(" world", start + Duration::from_millis(60)),
+            Some("Hello world".to_string())
+        );
+        assert_eq!(throttle.drain(), (String::new(), 0));
+    }
+
+    #[test]
+    fn test_throttle_flushes_on_size() {
+        let throttle = OutputThrottle::new(Duration::from_secs(60), 4, None);
+        let now = Instant::now();
+        assert_eq!(throttle.push("ab", now), None);
+        assert_eq!(throttle.push("cde", now), Some("abcde".to_string()));
+    }
+
+    #[test]
+    fn test_throttle_rate_limit_elides_excess() {
+        let throttle = OutputThrottle::new(Duration::from_secs(60), 1024, Some(5));
+        let start = Instant::now();
+
+        throttle.push("abc", start);
+        throttle.push("déf", start);
+        assert_eq!(throttle.drain(), ("abcd".to_string(), 3));
+
+        // A new one-second window renders again
+        throttle.push("ghi", start + Duration::from_secs(1));
+        assert_eq!(throttle.drain(), ("ghi".to_string(), 0));
+    }
+
     // Phase 3: Tool Call Formatting Tests
 
     #[test]
@@ -700,6 +864,7 @@ mod tests {
         // Create a ToolcallStart event
         let partial_message = crate::agent::pi_events::Message {
             role: "assistant".to_string(),
+            usage: None,
             content: vec![json!({
                 "type": "toolCall",
                 "id": "call_123",
@@ -733,6 +898,7 @@ mod tests {
 
         let partial_message = crate::agent::pi_events::Message {
             role: "assistant".to_string(),
+            usage: None,
             content: vec![json!({
                 "type": "toolCall",
                 "id": "call_123",
@@ -806,7 +972,7 @@ mod tests {
 
         // Verify fields are set correctly
         assert_eq!(callback.total_steps.load(Ordering::SeqCst), 3);
-        assert_eq!(callback.in_thinking.load(Ordering::SeqCst), false);
+        assert!(!callback.in_thinking.load(Ordering::SeqCst));
     }
 
     #[test]
@@ -814,7 +980,7 @@ mod tests {
         let callback = TerminalOutputCallback::new(false, 5);
 
         assert_eq!(callback.total_steps.load(Ordering::SeqCst), 5);
-        assert_eq!(callback.in_thinking.load(Ordering::SeqCst), false);
+        assert!(!callback.in_thinking.load(Ordering::SeqCst));
     }
 
     #[test]
@@ -0,0 +1,573 @@
+//! Terminal dashboard of a live run (`pipeline run --tui`)
+//!
+//! The dashboard takes over the screen while the run is in progress: the
+//! steps in execution order (indented by their depth in the DAG) with live
+//! states, the output of the selected step, and the tokens and cost spent
+//! so far. It consumes the engine's `ExecutionEvent`s over a channel and
+//! steers the run through `DashboardControls`: pausing holds back steps
+//! before they start, skipping interrupts the selected running step, and
+//! cancelling ends the run `Cancelled`.
+
+use crate::cli::output::{format_cost_with, format_duration, format_tokens};
+use crate::core::{ExecutionStatus, Pipeline};
+use crate::execution::engine::CancelHandle;
+use crate::execution::{ExecutionEvent, Interruption, Steering, SteeringAction, StepGate};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::Frame;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// Most output kept per step; older text is dropped
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// How often the screen is redrawn when no key is pressed
+const TICK: Duration = Duration::from_millis(100);
+
+/// State of a step as the dashboard shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepStatus {
+    Pending,
+    Waiting,
+    Running,
+    Retrying,
+    Completed,
+    Failed,
+    Skipped,
+}
+
+impl StepStatus {
+    fn symbol(self) -> Span<'static> {
+        match self {
+            StepStatus::Pending => Span::raw("·").dark_gray(),
+            StepStatus::Waiting => Span::raw("…").yellow(),
+            StepStatus::Running => Span::raw("▶").cyan(),
+            StepStatus::Retrying => Span::raw("↻").yellow(),
+            StepStatus::Completed => Span::raw("✓").green(),
+            StepStatus::Failed => Span::raw("✗").red(),
+            StepStatus::Skipped => Span::raw("↷").dark_gray(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StepView {
+    id: String,
+    /// Longest chain of dependencies leading to the step
+    depth: usize,
+    status: StepStatus,
+    attempt: usize,
+    /// Why the step waits, failed or was skipped
+    detail: Option<String>,
+    /// Agent text of the current attempt
+    output: String,
+    /// Whether the current attempt's text arrived in chunks, so its
+    /// `StepOutput` repeats it
+    streamed: bool,
+    tokens: u64,
+    cost_usd: f64,
+}
+
+/// What the dashboard shows, updated from the run's events
+#[derive(Debug, Clone)]
+pub struct Dashboard {
+    pipeline: String,
+    steps: Vec<StepView>,
+    selected: usize,
+    /// Select each step as it starts, until the operator picks one
+    follow: bool,
+    tokens: u64,
+    cost_usd: f64,
+    started: Instant,
+    finished: Option<ExecutionStatus>,
+}
+
+impl Dashboard {
+    /// Dashboard of a pipeline about to run, with all its steps pending
+    pub fn new(pipeline: &Pipeline) -> Self {
+        let mut depths: HashMap<&str, usize> = HashMap::new();
+        let mut steps = Vec::new();
+        for id in pipeline.execution_order() {
+            let depth = pipeline
+                .steps
+                .get(id)
+                .into_iter()
+                .flat_map(|step| step.all_dependencies())
+                .filter_map(|dependency| depths.get(dependency.as_str()))
+                .map(|depth| depth + 1)
+                .max()
+                .unwrap_or(0);
+            depths.insert(id, depth);
+            steps.push(StepView {
+                id: id.clone(),
+                depth,
+                status: StepStatus::Pending,
+                attempt: 0,
+                detail: None,
+                output: String::new(),
+                streamed: false,
+                tokens: 0,
+                cost_usd: 0.0,
+            });
+        }
+        Self {
+            pipeline: pipeline.name.clone(),
+            steps,
+            selected: 0,
+            follow: true,
+            tokens: 0,
+            cost_usd: 0.0,
+            started: Instant::now(),
+            finished: None,
+        }
+    }
+
+    fn step_mut(&mut self, step_id: &str) -> Option<&mut StepView> {
+        self.steps.iter_mut().find(|step| step.id == step_id)
+    }
+
+    fn set_status(&mut self, step_id: &str, status: StepStatus, detail: Option<String>) {
+        if let Some(step) = self.step_mut(step_id) {
+            step.status = status;
+            step.detail = detail;
+        }
+    }
+
+    /// Update the dashboard with an event of the run
+    pub fn apply(&mut self, event: &ExecutionEvent) {
+        match event {
+            ExecutionEvent::StepStarted { step_id, attempt } => {
+                if let Some(step) = self.step_mut(step_id) {
+                    step.status = StepStatus::Running;
+                    step.attempt = *attempt;
+                    step.detail = None;
+                    step.output.clear();
+                    step.streamed = false;
+                }
+                if self.follow {
+                    if let Some(index) = self.steps.iter().position(|step| step.id == *step_id) {
+                        self.selected = index;
+                    }
+                }
+            }
+            ExecutionEvent::StepOutputChunk { step_id, delta } => {
+                if let Some(step) = self.step_mut(step_id) {
+                    step.streamed = true;
+                    push_capped(&mut step.output, delta);
+                }
+            }
+            ExecutionEvent::StepOutput { step_id, output } => {
+                if let Some(step) = self.step_mut(step_id) {
+                    if !step.streamed {
+                        step.output.clear();
+                        push_capped(&mut step.output, output);
+                    }
+                }
+            }
+            ExecutionEvent::StepWaiting { step_id, condition } => {
+                self.set_status(step_id, StepStatus::Waiting, Some(condition.clone()));
+            }
+            ExecutionEvent::StepRetrying { step_id, .. } => {
+                self.set_status(step_id, StepStatus::Retrying, None);
+            }
+            ExecutionEvent::StepCompleted { step_id, .. } => {
+                self.set_status(step_id, StepStatus::Completed, None);
+            }
+            ExecutionEvent::StepReused { step_id, .. } => {
+                self.set_status(step_id, StepStatus::Completed, Some("inputs unchanged".to_string()));
+            }
+            ExecutionEvent::StepFailed { step_id, error } => {
+                self.set_status(step_id, StepStatus::Failed, Some(error.clone()));
+            }
+            ExecutionEvent::StepSkipped { step_id, reason } => {
+                self.set_status(step_id, StepStatus::Skipped, Some(reason.clone()));
+            }
+            ExecutionEvent::RunOnceStepSkipped { step_id, .. } => {
+                self.set_status(step_id, StepStatus::Skipped, Some("already ran".to_string()));
+            }
+            ExecutionEvent::StepCost {
+                step_id,
+                cost_usd,
+                tokens,
+                ..
+            } => {
+                self.tokens += tokens;
+                self.cost_usd += cost_usd;
+                if let Some(step) = self.step_mut(step_id) {
+                    step.tokens += tokens;
+                    step.cost_usd += cost_usd;
+                }
+            }
+            ExecutionEvent::PipelineCompleted { status, .. } => self.finished = Some(*status),
+            _ => {}
+        }
+    }
+
+    /// Select the step above the selected one
+    pub fn select_previous(&mut self) {
+        self.follow = false;
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Select the step below the selected one
+    pub fn select_next(&mut self) {
+        self.follow = false;
+        self.selected = (self.selected + 1).min(self.steps.len().saturating_sub(1));
+    }
+
+    /// Go back to selecting each step as it starts
+    pub fn follow_running(&mut self) {
+        self.follow = true;
+        if let Some(index) = self.steps.iter().position(|step| step.status == StepStatus::Running) {
+            self.selected = index;
+        }
+    }
+
+    /// ID of the selected step, if it is running
+    pub fn selected_running(&self) -> Option<&str> {
+        self.steps
+            .get(self.selected)
+            .filter(|step| step.status == StepStatus::Running)
+            .map(|step| step.id.as_str())
+    }
+
+    fn render(&self, frame: &mut Frame, paused: bool, run_over: bool) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let list_width = self
+            .steps
+            .iter()
+            .map(|step| step.id.chars().count() + 2 * step.depth + 6)
+            .max()
+            .unwrap_or(0)
+            .clamp(16, 40) as u16;
+        let [list_area, output_area] =
+            Layout::horizontal([Constraint::Length(list_width), Constraint::Min(10)]).areas(body);
+
+        let mut status = vec![
+            Span::raw(self.pipeline.clone()).bold(),
+            Span::raw(format!("  {}", format_duration(Duration::from_secs(self.started.elapsed().as_secs())))),
+            Span::raw(format!("  tokens {}", format_tokens(self.tokens))),
+            Span::raw(format!("  cost {}", format_cost_with(self.cost_usd, 4))),
+        ];
+        match self.finished {
+            Some(finished) => status.push(Span::raw(format!("  {:?}", finished)).bold()),
+            None if paused => status.push(Span::raw("  PAUSED").yellow().bold()),
+            None => {}
+        }
+        frame.render_widget(Line::from(status), header);
+
+        let items: Vec<ListItem> = self
+            .steps
+            .iter()
+            .map(|step| {
+                let mut spans = vec![
+                    Span::raw("  ".repeat(step.depth)),
+                    step.status.symbol(),
+                    Span::raw(format!(" {}", step.id)),
+                ];
+                if step.attempt > 1 {
+                    spans.push(Span::raw(format!(" #{}", step.attempt)).dark_gray());
+                }
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let mut list_state = ListState::default().with_selected(Some(self.selected));
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title(" Steps "))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED)),
+            list_area,
+            &mut list_state,
+        );
+
+        let (title, text) = match self.steps.get(self.selected) {
+            Some(step) => {
+                let mut title = format!(" {} ", step.id);
+                if step.tokens > 0 {
+                    title.push_str(&format!(
+                        "· {} tokens · {} ",
+                        format_tokens(step.tokens),
+                        format_cost_with(step.cost_usd, 4)
+                    ));
+                }
+                let text = match &step.detail {
+                    Some(detail) if step.output.is_empty() => detail.clone(),
+                    Some(detail) => format!("{}\n\n{}", step.output, detail),
+                    None => step.output.clone(),
+                };
+                (title, text)
+            }
+            None => (" Output ".to_string(), String::new()),
+        };
+        let inner = Rect {
+            width: output_area.width.saturating_sub(2),
+            height: output_area.height.saturating_sub(2),
+            ..output_area
+        };
+        let lines: Vec<Line> = tail_lines(&text, inner.width as usize, inner.height as usize)
+            .into_iter()
+            .map(Line::from)
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), output_area);
+
+        let keys = if run_over {
+            "q close  ↑/↓ select"
+        } else if paused {
+            "p resume  s skip step  c cancel  ↑/↓ select  f follow"
+        } else {
+            "p pause  s skip step  c cancel  ↑/↓ select  f follow"
+        };
+        frame.render_widget(Line::from(keys).fg(Color::DarkGray), footer);
+    }
+}
+
+/// Append `text`, dropping the oldest output past `MAX_OUTPUT_BYTES`
+fn push_capped(output: &mut String, text: &str) {
+    output.push_str(text);
+    if output.len() > MAX_OUTPUT_BYTES {
+        let mut cut = output.len() - MAX_OUTPUT_BYTES;
+        while !output.is_char_boundary(cut) {
+            cut += 1;
+        }
+        output.drain(..cut);
+    }
+}
+
+/// The last `height` lines of `text` wrapped at `width` characters
+fn tail_lines(text: &str, width: usize, height: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        let chars: Vec<char> = line.chars().collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+        }
+        lines.extend(chars.chunks(width).map(|chunk| chunk.iter().collect()));
+    }
+    let skip = lines.len().saturating_sub(height);
+    lines.split_off(skip)
+}
+
+/// Pause and skip requests made from the dashboard
+///
+/// Registered with the engine as a step gate (holding back steps while
+/// paused) and as the steering of interruptions (skipping the step the
+/// operator picked, retrying any other step the interruption stopped).
+#[derive(Debug, Default)]
+pub struct DashboardControls {
+    paused: AtomicBool,
+    skip: Mutex<Option<String>>,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl DashboardControls {
+    /// Flag to register with `ExecutionEngine::with_interrupts`
+    pub fn interrupt_flag(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    /// Whether steps are held back before they start
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Pause if running, resume if paused
+    pub fn toggle_pause(&self) {
+        self.paused.fetch_xor(true, Ordering::SeqCst);
+    }
+
+    /// Stop the running step `step_id` and leave it out
+    pub fn skip(&self, step_id: &str) {
+        *self.skip.lock().unwrap() = Some(step_id.to_string());
+        self.interrupt.store(true, Ordering::SeqCst);
+    }
+}
+
+#[async_trait::async_trait]
+impl StepGate for DashboardControls {
+    async fn hold_reason(&self) -> Option<String> {
+        self.is_paused().then(|| "Paused from the dashboard".to_string())
+    }
+}
+
+impl Steering for DashboardControls {
+    fn steer(&self, interruption: &Interruption) -> SteeringAction {
+        let mut skip = self.skip.lock().unwrap();
+        if skip.as_deref() == Some(interruption.step_id.as_str()) {
+            *skip = None;
+            SteeringAction::Skip
+        } else {
+            SteeringAction::Retry
+        }
+    }
+}
+
+/// Show the dashboard until the operator closes it after the run is over
+///
+/// `run_over` is set by the caller once the engine has returned. Blocks,
+/// so it belongs on a blocking task.
+pub fn run(
+    mut dashboard: Dashboard,
+    mut events: UnboundedReceiver<ExecutionEvent>,
+    controls: Arc<DashboardControls>,
+    cancel: CancelHandle,
+    run_over: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = (|| loop {
+        while let Ok(event) = events.try_recv() {
+            dashboard.apply(&event);
+        }
+        let over = run_over.load(Ordering::SeqCst);
+        terminal.draw(|frame| dashboard.render(frame, controls.is_paused(), over))?;
+
+        if !event::poll(TICK)? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc if over => return Ok(()),
+            _ if ctrl_c && over => return Ok(()),
+            KeyCode::Char('c') if !over => {
+                // A paused run would otherwise keep its next step waiting
+                if controls.is_paused() {
+                    controls.toggle_pause();
+                }
+                cancel.cancel("Cancelled from the dashboard");
+            }
+            KeyCode::Char('p') if !over => controls.toggle_pause(),
+            KeyCode::Char('s') if !over => {
+                if let Some(step_id) = dashboard.selected_running() {
+                    controls.skip(step_id);
+                }
+            }
+            KeyCode::Char('f') => dashboard.follow_running(),
+            KeyCode::Up | KeyCode::Char('k') => dashboard.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => dashboard.select_next(),
+            _ => {}
+        }
+    })();
+    ratatui::restore();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::PipelineConfig;
+
+    fn dashboard() -> Dashboard {
+        let config = PipelineConfig::from_yaml(
+            r#"
+name: demo
+steps:
+  - id: build
+    name: Build
+    prompt: Build
+  - id: test
+    name: Test
+    prompt: Test
+    depends_on: [build]
+  - id: lint
+    name: Lint
+    prompt: Lint
+"#,
+        )
+        .unwrap();
+        Dashboard::new(&config.to_pipeline())
+    }
+
+    fn step<'a>(dashboard: &'a Dashboard, id: &str) -> &'a StepView {
+        dashboard.steps.iter().find(|step| step.id == id).unwrap()
+    }
+
+    #[test]
+    fn test_events_update_steps_and_totals() {
+        let mut dashboard = dashboard();
+        assert_eq!(step(&dashboard, "test").depth, 1);
+        assert_eq!(step(&dashboard, "build").depth, 0);
+
+        for event in [
+            ExecutionEvent::StepStarted { step_id: "test".into(), attempt: 1 },
+            ExecutionEvent::StepOutputChunk { step_id: "test".into(), delta: "Running ".into() },
+            ExecutionEvent::StepOutputChunk { step_id: "test".into(), delta: "tests".into() },
+            ExecutionEvent::StepOutput { step_id: "test".into(), output: "Running tests".into() },
+            ExecutionEvent::StepCost { step_id: "test".into(), cost_usd: 0.5, tokens: 1200, budget: None },
+            ExecutionEvent::StepStarted { step_id: "lint".into(), attempt: 1 },
+            ExecutionEvent::StepOutput { step_id: "lint".into(), output: "Clean".into() },
+            ExecutionEvent::StepCost { step_id: "lint".into(), cost_usd: 0.25, tokens: 300, budget: None },
+            ExecutionEvent::StepCompleted { step_id: "lint".into(), next_step: None },
+        ] {
+            dashboard.apply(&event);
+        }
+
+        assert_eq!(step(&dashboard, "test").output, "Running tests");
+        assert_eq!(step(&dashboard, "test").status, StepStatus::Running);
+        assert_eq!(step(&dashboard, "lint").output, "Clean");
+        assert_eq!(step(&dashboard, "lint").status, StepStatus::Completed);
+        assert_eq!((dashboard.tokens, dashboard.cost_usd), (1500, 0.75));
+        // The selection follows the steps as they start...
+        assert_eq!(dashboard.steps[dashboard.selected].id, "lint");
+        assert_eq!(dashboard.selected_running(), None);
+
+        // ...until the operator picks one
+        dashboard.select_next();
+        dashboard.selected = dashboard.steps.iter().position(|step| step.id == "test").unwrap();
+        dashboard.apply(&ExecutionEvent::StepStarted { step_id: "build".into(), attempt: 2 });
+        assert_eq!(dashboard.selected_running(), Some("test"));
+        dashboard.follow_running();
+        assert!(dashboard.selected_running().is_some());
+        dashboard.apply(&ExecutionEvent::StepFailed { step_id: "test".into(), error: "boom".into() });
+        assert_eq!(step(&dashboard, "test").detail.as_deref(), Some("boom"));
+    }
+
+    #[test]
+    fn test_output_is_capped_and_tailed() {
+        let mut output = "é".repeat(MAX_OUTPUT_BYTES / 2);
+        push_capped(&mut output, "abc");
+        assert!(output.len() <= MAX_OUTPUT_BYTES);
+        assert!(output.ends_with("abc"));
+
+        assert_eq!(tail_lines("one\n\nabcdefg", 3, 4), vec!["", "abc", "def", "g"]);
+        assert_eq!(tail_lines("one\ntwo", 10, 5), vec!["one", "two"]);
+    }
+
+    #[tokio::test]
+    async fn test_controls_pause_and_skip() {
+        let controls = DashboardControls::default();
+        assert_eq!(controls.hold_reason().await, None);
+        controls.toggle_pause();
+        assert!(controls.hold_reason().await.is_some());
+        controls.toggle_pause();
+        assert!(!controls.is_paused());
+
+        let interruption = |step_id: &str| Interruption {
+            step_id: step_id.to_string(),
+            accumulated_output: String::new(),
+            recent_lines: Vec::new(),
+        };
+        controls.skip("build");
+        assert!(controls.interrupt_flag().load(Ordering::SeqCst));
+        // Other steps the interruption stopped run again
+        assert_eq!(controls.steer(&interruption("lint")), SteeringAction::Retry);
+        assert_eq!(controls.steer(&interruption("build")), SteeringAction::Skip);
+        assert_eq!(controls.steer(&interruption("build")), SteeringAction::Retry);
+    }
+}
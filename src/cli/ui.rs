@@ -0,0 +1,144 @@
+//! Local web UI for following a run
+//!
+//! Serves a single page that polls `/api/run` for the step DAG, live step
+//! states, outputs and event log of a run. Runs keep these up to date in
+//! their run directory (see `LiveRunFiles`), so the UI works from a separate
+//! process while the run is in progress and for finished runs alike.
+
+use crate::persistence::{latest_run_dir, load_run_files_from};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const PAGE: &str = include_str!("ui.html");
+
+/// Most event log lines sent to the page
+const MAX_EVENT_LINES: usize = 500;
+
+/// Which run the UI shows
+#[derive(Debug, Clone)]
+pub enum RunSelection {
+    /// Whichever run directory changed most recently, re-resolved on every
+    /// request so a new run is picked up
+    Latest(PathBuf),
+    /// A fixed run directory
+    Run(PathBuf),
+}
+
+impl RunSelection {
+    fn dir(&self) -> Option<PathBuf> {
+        match self {
+            RunSelection::Latest(runs_dir) => latest_run_dir(runs_dir),
+            RunSelection::Run(dir) => Some(dir.clone()),
+        }
+    }
+}
+
+/// Serve the UI on `addr` until the process is stopped
+pub async fn serve(addr: &str, selection: RunSelection) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let selection = selection.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &selection).await {
+                tracing::debug!("UI request failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, selection: &RunSelection) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    let (status, content_type, body) = route(path, selection);
+
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    Ok(())
+}
+
+fn route(path: &str, selection: &RunSelection) -> (&'static str, &'static str, String) {
+    match path {
+        "/" => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+        "/api/run" => match selection.dir().map(|dir| run_state(&dir)) {
+            Some(Ok(state)) => ("200 OK", "application/json", state.to_string()),
+            Some(Err(e)) => (
+                "500 Internal Server Error",
+                "application/json",
+                serde_json::json!({ "error": e.to_string() }).to_string(),
+            ),
+            None => ("200 OK", "application/json", serde_json::json!({ "run": null }).to_string()),
+        },
+        _ => ("404 Not Found", "text/plain", "Not found".to_string()),
+    }
+}
+
+/// Snapshot of a run directory for the page
+fn run_state(dir: &Path) -> Result<serde_json::Value> {
+    let (steps, outputs) = load_run_files_from(dir)?;
+    let log = std::fs::read_to_string(dir.join("events.log")).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    let events = &lines[lines.len().saturating_sub(MAX_EVENT_LINES)..];
+
+    Ok(serde_json::json!({
+        "run": dir.file_name().and_then(|name| name.to_str()),
+        "steps": steps,
+        "outputs": outputs,
+        "events": events,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_state_and_routes() {
+        let runs = tempfile::tempdir().unwrap();
+        let older = runs.path().join("older");
+        let newer = runs.path().join("newer");
+        for dir in [&older, &newer] {
+            std::fs::create_dir_all(dir.join("outputs")).unwrap();
+            std::fs::write(dir.join("steps.json"), "[]").unwrap();
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        std::fs::write(
+            newer.join("steps.json"),
+            r#"[{"step_id": "plan", "status": "running", "started_at": null, "finished_at": null, "error": null}]"#,
+        )
+        .unwrap();
+        std::fs::write(newer.join("events.log"), "plan started\n").unwrap();
+
+        let selection = RunSelection::Latest(runs.path().to_path_buf());
+        let (status, _, body) = route("/api/run", &selection);
+        assert_eq!(status, "200 OK");
+        let state: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(state["run"], "newer");
+        assert_eq!(state["steps"][0]["status"], "running");
+        assert_eq!(state["events"][0], "plan started");
+
+        assert_eq!(route("/", &selection).1, "text/html; charset=utf-8");
+        assert_eq!(route("/missing", &selection).0, "404 Not Found");
+    }
+}
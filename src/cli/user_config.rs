@@ -0,0 +1,213 @@
+//! User-level defaults from `~/.config/pipeline/config.toml`
+//!
+//! Settings here apply to every run unless something more specific wins:
+//! command-line flags override the pipeline YAML, which overrides this file,
+//! which overrides the built-in defaults.
+
+use crate::cli::commands::SchedulingStrategyArg;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Template written on first run, with every setting documented
+const TEMPLATE: &str = r#"# pipeline user configuration
+#
+# Defaults for every run. Command-line flags and the pipeline YAML take
+# precedence over these. Edit this file or use `pipeline config set <key> <value>`.
+
+# Path to the pi executable (default: `pi` on PATH)
+# pi_path = "/usr/local/bin/pi"
+
+# Scheduling strategy when --strategy is not given: sequential, parallel, parallel-limited
+# strategy = "sequential"
+
+# Colored output: auto, always, never
+# color = "auto"
+
+# Directory for the history database and run files
+# data_dir = "/home/me/.local/share/pipeline"
+
+# Model passed to pi as --model (agent profiles that set --model keep theirs)
+# model = "claude-sonnet-4-5"
+"#;
+
+/// When to color terminal output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+/// Contents of the user config file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// Path to the pi executable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pi_path: Option<String>,
+
+    /// Scheduling strategy when `--strategy` is not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<SchedulingStrategyArg>,
+
+    /// Colored output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorChoice>,
+
+    /// Directory for the history database and run files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Model passed to pi
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+/// Settings `config get/set/unset` accept
+pub const KEYS: &[&str] = &["pi_path", "strategy", "color", "data_dir", "model"];
+
+impl UserConfig {
+    /// Location of the user config file
+    pub fn path() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("pipeline")
+            .join("config.toml")
+    }
+
+    /// Load the config file, or the defaults if there is none
+    pub fn load_from(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Write the config file (comments in it are not kept)
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Write the commented template if there is no config file yet
+    ///
+    /// Returns `true` if the file was created.
+    pub fn create_template(path: &Path) -> Result<bool> {
+        if path.exists() {
+            return Ok(false);
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, TEMPLATE)?;
+        Ok(true)
+    }
+
+    /// Value of a setting, as written in the file
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        check_key(key)?;
+        let table = toml::Table::try_from(self)?;
+        Ok(table.get(key).map(|value| match value {
+            toml::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        }))
+    }
+
+    /// Change a setting, rejecting values it can't hold
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        check_key(key)?;
+        let mut table = toml::Table::try_from(&*self)?;
+        table.insert(key.to_string(), toml::Value::String(value.to_string()));
+        *self = table
+            .try_into()
+            .with_context(|| format!("Invalid value '{}' for {}", value, key))?;
+        Ok(())
+    }
+
+    /// Remove a setting so the built-in default applies again
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        check_key(key)?;
+        let mut table = toml::Table::try_from(&*self)?;
+        table.remove(key);
+        *self = table.try_into()?;
+        Ok(())
+    }
+
+    /// Whether colors should be forced on or off (None: decide per terminal)
+    pub fn colors_enabled(&self) -> Option<bool> {
+        match self.color {
+            Some(ColorChoice::Always) => Some(true),
+            Some(ColorChoice::Never) => Some(false),
+            Some(ColorChoice::Auto) | None => None,
+        }
+    }
+
+    /// Arguments to pass to pi for an agent that already has `args`
+    ///
+    /// Adds `--model` unless the args choose a model themselves.
+    pub fn agent_args(&self, mut args: Vec<String>) -> Vec<String> {
+        if let Some(model) = &self.model {
+            if !args.iter().any(|arg| arg == "--model" || arg.starts_with("--model=")) {
+                args.splice(0..0, ["--model".to_string(), model.clone()]);
+            }
+        }
+        args
+    }
+}
+
+fn check_key(key: &str) -> Result<()> {
+    if KEYS.contains(&key) {
+        Ok(())
+    } else {
+        anyhow::bail!("Unknown setting '{}' (expected one of: {})", key, KEYS.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_get_and_save() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pipeline").join("config.toml");
+
+        assert!(UserConfig::create_template(&path).unwrap());
+        assert!(!UserConfig::create_template(&path).unwrap());
+        let mut config = UserConfig::load_from(&path).unwrap();
+        assert_eq!(config, UserConfig::default());
+
+        config.set("strategy", "parallel-limited").unwrap();
+        config.set("model", "sonnet").unwrap();
+        assert!(config.set("color", "sometimes").is_err());
+        assert!(config.set("colour", "never").is_err());
+        config.save_to(&path).unwrap();
+
+        let config = UserConfig::load_from(&path).unwrap();
+        assert_eq!(config.strategy, Some(SchedulingStrategyArg::ParallelLimited));
+        assert_eq!(config.get("strategy").unwrap(), Some("parallel-limited".to_string()));
+        assert_eq!(config.get("pi_path").unwrap(), None);
+    }
+
+    #[test]
+    fn test_model_defers_to_explicit_args() {
+        let mut config = UserConfig::default();
+        config.set("model", "sonnet").unwrap();
+
+        assert_eq!(config.agent_args(vec![]), vec!["--model", "sonnet"]);
+        assert_eq!(
+            config.agent_args(vec!["--model=opus".to_string()]),
+            vec!["--model=opus"]
+        );
+
+        config.unset("model").unwrap();
+        assert_eq!(config.model, None);
+    }
+}
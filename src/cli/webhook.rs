@@ -0,0 +1,291 @@
+//! Execution summary webhooks
+//!
+//! When a run ends, its `ExecutionSummary` is POSTed as JSON to each URL
+//! under the pipeline's `webhooks:`. With a `secret_env`, the body is signed
+//! with HMAC-SHA256 and the signature sent as
+//! `X-Pipeline-Signature-256: sha256=<hex>`. A 5xx or 429 response, or a
+//! connection error, is retried with exponential backoff; a notification
+//! that still fails (or gets any other error status) becomes a dead letter.
+//!
+//! Requests are made with `curl`, so https works without a TLS stack here.
+
+use crate::core::config::WebhookConfig;
+use crate::persistence::{DeadLetter, ExecutionSummary};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::future::Future;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Retries after the first attempt unless the webhook sets `max_retries`
+const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// Delay before the first retry unless the webhook sets `backoff_secs`
+const DEFAULT_BACKOFF_SECS: u64 = 1;
+
+/// Longest a single request may take
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// A request ready to send
+#[derive(Debug, Clone)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// HMAC-SHA256 signature of a payload, as `sha256=<hex>`
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    format!("sha256={}", digest.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+/// Whether `signature` (`sha256=<hex>`) is the HMAC-SHA256 of `body`,
+/// compared in constant time
+pub fn verify(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Some(hex) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let digest: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    let Some(digest) = digest else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    mac.verify_slice(&digest).is_ok()
+}
+
+/// Send the summary to every webhook, returning the notifications that
+/// could not be delivered
+pub async fn notify(webhooks: &[WebhookConfig], summary: &ExecutionSummary) -> Vec<DeadLetter> {
+    let mut undelivered = Vec::new();
+    for webhook in webhooks {
+        if let Err(letter) = deliver_with(webhook, summary, send_with_curl).await {
+            undelivered.push(letter);
+        }
+    }
+    undelivered
+}
+
+/// Deliver the summary to one webhook through `send`, retrying as the
+/// webhook allows
+///
+/// `send` returns the response's HTTP status, or an error if no response
+/// arrived.
+pub async fn deliver_with<F, Fut>(webhook: &WebhookConfig, summary: &ExecutionSummary, mut send: F) -> Result<(), DeadLetter>
+where
+    F: FnMut(WebhookRequest) -> Fut,
+    Fut: Future<Output = Result<u16, String>>,
+{
+    let body = serde_json::to_string(summary).unwrap_or_default();
+    let dead_letter = |error: String, attempts: u32| DeadLetter {
+        execution_id: summary.execution_id,
+        url: webhook.url.clone(),
+        payload: body.clone(),
+        error,
+        attempts,
+        failed_at: Utc::now(),
+    };
+
+    let mut headers = vec![
+        ("Content-Type".to_string(), "application/json".to_string()),
+        ("User-Agent".to_string(), format!("pipeline/{}", env!("CARGO_PKG_VERSION"))),
+        ("X-Pipeline-Event".to_string(), "execution_summary".to_string()),
+        ("X-Pipeline-Delivery".to_string(), uuid::Uuid::new_v4().to_string()),
+    ];
+    if let Some(name) = &webhook.secret_env {
+        match std::env::var(name) {
+            Ok(secret) => headers.push(("X-Pipeline-Signature-256".to_string(), sign(&secret, &body))),
+            // Never send unsigned what the receiver expects signed
+            Err(_) => return Err(dead_letter(format!("secret variable {} is not set", name), 0)),
+        }
+    }
+    let request = WebhookRequest {
+        url: webhook.url.clone(),
+        headers,
+        body: body.clone(),
+    };
+
+    let max_retries = webhook.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+    let mut delay = Duration::from_secs(webhook.backoff_secs.unwrap_or(DEFAULT_BACKOFF_SECS));
+    let mut attempt = 1;
+    loop {
+        let error = match send(request.clone()).await {
+            Ok(status) if (200..300).contains(&status) => return Ok(()),
+            Ok(status) if status >= 500 || status == 429 => format!("HTTP {}", status),
+            Ok(status) => return Err(dead_letter(format!("HTTP {}", status), attempt)),
+            Err(e) => e,
+        };
+        if attempt > max_retries {
+            return Err(dead_letter(error, attempt));
+        }
+        tracing::debug!(
+            "Webhook {} failed ({}), retrying in {}s (attempt {} of {})",
+            webhook.url,
+            error,
+            delay.as_secs(),
+            attempt + 1,
+            max_retries + 1
+        );
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// POST a request with curl, returning the response status
+async fn send_with_curl(request: WebhookRequest) -> Result<u16, String> {
+    let mut command = tokio::process::Command::new("curl");
+    command.args([
+        "--silent",
+        "--show-error",
+        "--output",
+        "/dev/null",
+        "--write-out",
+        "%{http_code}",
+        "--max-time",
+        &REQUEST_TIMEOUT_SECS.to_string(),
+        "--request",
+        "POST",
+        "--data-binary",
+        "@-",
+    ]);
+    for (name, value) in &request.headers {
+        command.arg("--header").arg(format!("{}: {}", name, value));
+    }
+    command
+        .arg(&request.url)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut child = command.spawn().map_err(|e| format!("Failed to run curl: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(request.body.as_bytes())
+            .await
+            .map_err(|e| format!("Failed to send payload to curl: {}", e))?;
+    }
+    let output = child
+        .wait_with_output()
+        .await
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| "curl did not report a status".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ExecutionStatus;
+    use std::sync::{Arc, Mutex};
+
+    fn summary() -> ExecutionSummary {
+        ExecutionSummary {
+            execution_id: uuid::Uuid::new_v4(),
+            pipeline_name: "Release".to_string(),
+            status: ExecutionStatus::Failed,
+            started_at: Utc::now(),
+            completed_at: Some(Utc::now()),
+            progress: 0.5,
+            completed_steps: 1,
+            total_steps: 2,
+            bytes_written: 0,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        }
+    }
+
+    fn webhook(max_retries: u32) -> WebhookConfig {
+        WebhookConfig {
+            url: "https://hooks.example.com/pipeline".to_string(),
+            secret_env: None,
+            max_retries: Some(max_retries),
+            backoff_secs: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_sign_matches_known_hmac() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let signature = sign("Jefe", "what do ya want for nothing?");
+        assert!(verify("Jefe", b"what do ya want for nothing?", &signature));
+        assert!(!verify("Jefe", b"what do ya want for nothing!", &signature));
+        assert!(!verify("Jefe", b"what do ya want for nothing?", &signature[..signature.len() - 1]));
+        assert!(!verify("Jefe", b"what do ya want for nothing?", "sha1=5bdc"));
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried_until_delivered() {
+        let statuses = Arc::new(Mutex::new(vec![200, 503, 502]));
+        let send = |_| {
+            let status = statuses.lock().unwrap().pop().unwrap();
+            async move { Ok(status) }
+        };
+
+        assert!(deliver_with(&webhook(3), &summary(), send).await.is_ok());
+        assert!(statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_undeliverable_notification_becomes_dead_letter() {
+        let summary = summary();
+        let attempts = Arc::new(Mutex::new(0));
+        let send = |_| {
+            *attempts.lock().unwrap() += 1;
+            async { Err("Connection refused".to_string()) }
+        };
+        let letter = deliver_with(&webhook(2), &summary, send).await.unwrap_err();
+        assert_eq!((letter.attempts, *attempts.lock().unwrap()), (3, 3));
+        assert_eq!(letter.error, "Connection refused");
+        assert_eq!(letter.execution_id, summary.execution_id);
+        assert!(letter.payload.contains("\"pipeline_name\":\"Release\""));
+
+        // Client errors are not retried
+        let letter = deliver_with(&webhook(2), &summary, |_| async { Ok(404) }).await.unwrap_err();
+        assert_eq!((letter.attempts, letter.error.as_str()), (1, "HTTP 404"));
+    }
+
+    #[tokio::test]
+    async fn test_payload_is_signed_with_secret() {
+        std::env::set_var("PIPELINE_TEST_WEBHOOK_SECRET", "s3cret");
+        let mut signed = webhook(0);
+        signed.secret_env = Some("PIPELINE_TEST_WEBHOOK_SECRET".to_string());
+        let seen = Arc::new(Mutex::new(None));
+        let send = |request: WebhookRequest| {
+            *seen.lock().unwrap() = Some(request);
+            async { Ok(204) }
+        };
+        deliver_with(&signed, &summary(), send).await.unwrap();
+
+        let request = seen.lock().unwrap().take().unwrap();
+        let signature = request
+            .headers
+            .iter()
+            .find(|(name, _)| name == "X-Pipeline-Signature-256")
+            .map(|(_, value)| value.clone());
+        assert_eq!(signature, Some(sign("s3cret", &request.body)));
+
+        signed.secret_env = Some("PIPELINE_TEST_WEBHOOK_SECRET_UNSET".to_string());
+        let letter = deliver_with(&signed, &summary(), |_| async { Ok(200) }).await.unwrap_err();
+        assert!(letter.error.contains("is not set"), "{}", letter.error);
+    }
+}
@@ -0,0 +1,366 @@
+//! Pools of git worktrees for runs started by webhook triggers
+//!
+//! A trigger with a `worktree:` section runs each pipeline in a worktree of
+//! a local clone instead of the server's directory. Worktrees are kept
+//! between runs, so a run only pays for a fetch and a reset: the pool
+//! fetches the remote, takes a free worktree (the most recently used, whose
+//! files are likeliest to be close), checks it is still intact, and resets
+//! and cleans it to the commit the hook names.
+//!
+//! Each worktree `wt-<n>` has an `wt-<n>.owner` file next to it naming the
+//! run that last took it. A worktree is busy while that run's process is
+//! running, so the worktrees of runs that ended or of an earlier server are
+//! free again without any unlocking. When every worktree is busy another is
+//! added, and `evict` removes the least recently used free ones once the
+//! pool is over its size.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// A trigger's `worktree:` section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorktreeConfig {
+    /// Local clone the worktrees are made from, relative to the triggers file
+    pub repo: PathBuf,
+    /// Remote fetched before each run
+    #[serde(default = "default_remote")]
+    pub remote: String,
+    /// Free worktrees kept between runs
+    #[serde(default = "default_size")]
+    pub size: usize,
+}
+
+fn default_remote() -> String {
+    "origin".to_string()
+}
+
+fn default_size() -> usize {
+    4
+}
+
+/// A worktree of the pool with the run that last took it
+#[derive(Debug)]
+struct Worktree {
+    path: PathBuf,
+    owner: Option<Uuid>,
+    used: SystemTime,
+}
+
+/// The worktrees of one trigger, kept under `dir`
+pub struct WorktreePool {
+    config: WorktreeConfig,
+    dir: PathBuf,
+    /// Held while worktrees are taken or removed
+    lock: Mutex<()>,
+}
+
+impl WorktreePool {
+    pub fn new(config: WorktreeConfig, dir: PathBuf) -> Self {
+        Self {
+            config,
+            dir,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// What to check out for a hook's `trigger.*` variables: the commit,
+    /// else the branch on the remote, else the tag
+    pub fn target(&self, sha: Option<&str>, branch: Option<&str>, tag: Option<&str>) -> Option<String> {
+        sha.map(str::to_string)
+            .or_else(|| branch.map(|branch| format!("{}/{}", self.config.remote, branch)))
+            .or_else(|| tag.map(|tag| format!("refs/tags/{}", tag)))
+    }
+
+    /// Take a free worktree for the run `owner`, fetched and reset to
+    /// `target`
+    ///
+    /// `running` gives the runs whose processes are still running; their
+    /// worktrees are not taken. It is asked while the pool is locked, so a
+    /// run that took a worktree while this one waited is seen running.
+    pub fn checkout(&self, owner: Uuid, target: &str, running: impl FnOnce() -> HashSet<Uuid>) -> Result<PathBuf> {
+        anyhow::ensure!(!target.starts_with('-'), "Invalid checkout target '{}'", target);
+        let _guard = self.lock.lock().unwrap();
+        let dir = self.dir()?;
+
+        git(&self.config.repo, ["fetch", "--prune", "--tags", "--quiet", self.config.remote.as_str()])
+            .with_context(|| format!("Failed to fetch {}", self.config.remote))?;
+        let commit = git(
+            &self.config.repo,
+            ["rev-parse", "--verify", "--quiet", "--end-of-options", &format!("{}^{{commit}}", target)],
+        )
+        .with_context(|| format!("'{}' is not a commit of {}", target, self.config.repo.display()))?;
+        let commit = commit.trim();
+
+        let running = running();
+        let free = self
+            .worktrees(&dir)?
+            .into_iter()
+            .filter(|worktree| !worktree.owner.is_some_and(|owner| running.contains(&owner)))
+            .max_by_key(|worktree| worktree.used);
+        let path = match free {
+            Some(worktree) if self.is_intact(&worktree.path) => worktree.path,
+            Some(worktree) => {
+                tracing::warn!("Worktree {} is broken; recreating it", worktree.path.display());
+                self.remove(&worktree.path)?;
+                self.add(&worktree.path, commit)?;
+                worktree.path
+            }
+            None => {
+                let path = (1..)
+                    .map(|n| dir.join(format!("wt-{}", n)))
+                    .find(|path| !path.exists())
+                    .unwrap();
+                self.add(&path, commit)?;
+                path
+            }
+        };
+
+        git(&path, ["reset", "--hard", "--quiet", commit])?;
+        git(&path, ["clean", "-fdxq"])?;
+        std::fs::write(owner_file(&path), owner.to_string())?;
+        Ok(path)
+    }
+
+    /// Remove the least recently used free worktrees while the pool holds
+    /// more than its size, returning how many were removed
+    pub fn evict(&self, running: impl FnOnce() -> HashSet<Uuid>) -> Result<usize> {
+        let _guard = self.lock.lock().unwrap();
+        let running = running();
+        let mut worktrees = self.worktrees(&self.dir()?)?;
+        worktrees.sort_by_key(|worktree| worktree.used);
+
+        let mut count = worktrees.len();
+        let mut removed = 0;
+        for worktree in worktrees {
+            if count <= self.config.size {
+                break;
+            }
+            if worktree.owner.is_some_and(|owner| running.contains(&owner)) {
+                continue;
+            }
+            self.remove(&worktree.path)?;
+            count -= 1;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    /// The pool's directory, created if needed, as git reports paths
+    fn dir(&self) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        Ok(self.dir.canonicalize()?)
+    }
+
+    fn worktrees(&self, dir: &Path) -> Result<Vec<Worktree>> {
+        let mut worktrees = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_worktree = path.file_name().and_then(OsStr::to_str).is_some_and(|name| name.starts_with("wt-"))
+                && path.is_dir();
+            if !is_worktree {
+                continue;
+            }
+            let owner_file = owner_file(&path);
+            worktrees.push(Worktree {
+                owner: std::fs::read_to_string(&owner_file).ok().and_then(|id| id.trim().parse().ok()),
+                used: std::fs::metadata(&owner_file)
+                    .and_then(|metadata| metadata.modified())
+                    .unwrap_or(SystemTime::UNIX_EPOCH),
+                path,
+            });
+        }
+        Ok(worktrees)
+    }
+
+    /// Whether `path` is still registered with the repository and its
+    /// checkout can be read
+    fn is_intact(&self, path: &Path) -> bool {
+        let registered = git(&self.config.repo, ["worktree", "list", "--porcelain"]).is_ok_and(|list| {
+            list.lines()
+                .filter_map(|line| line.strip_prefix("worktree "))
+                .any(|listed| Path::new(listed) == path)
+        });
+        registered && git(path, ["status", "--porcelain"]).is_ok()
+    }
+
+    fn add(&self, path: &Path, commit: &str) -> Result<()> {
+        let args = ["worktree", "add", "--detach", "--quiet"].map(OsStr::new);
+        git(&self.config.repo, args.iter().copied().chain([path.as_os_str(), OsStr::new(commit)]))
+            .with_context(|| format!("Failed to add worktree {}", path.display()))?;
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        // A broken worktree may not be removable by git
+        let args = ["worktree", "remove", "--force"].map(OsStr::new);
+        let _ = git(&self.config.repo, args.iter().copied().chain([path.as_os_str()]));
+        if path.exists() {
+            std::fs::remove_dir_all(path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+        git(&self.config.repo, ["worktree", "prune"])?;
+        let _ = std::fs::remove_file(owner_file(path));
+        Ok(())
+    }
+}
+
+fn owner_file(worktree: &Path) -> PathBuf {
+    worktree.with_extension("owner")
+}
+
+/// Run git in `dir`, returning its output
+fn git<I, S>(dir: &Path, args: I) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let args: Vec<S> = args.into_iter().collect();
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(&args)
+        // Fail instead of waiting for credentials nobody will type
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        let command: Vec<_> = args.iter().map(|arg| arg.as_ref().to_string_lossy()).collect();
+        anyhow::bail!(
+            "git {} failed: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_git(dir: &Path, args: &[&str]) -> String {
+        git(dir, ["-c", "user.name=Test", "-c", "user.email=test@example.com"].iter().chain(args)).unwrap()
+    }
+
+    /// A remote with one commit, a clone of it and a pool of the clone
+    fn setup(dir: &Path, size: usize) -> (PathBuf, WorktreePool) {
+        let remote = dir.join("remote");
+        std::fs::create_dir_all(&remote).unwrap();
+        run_git(&remote, &["init", "--quiet", "--initial-branch", "main"]);
+        std::fs::write(remote.join("file.txt"), "one\n").unwrap();
+        run_git(&remote, &["add", "."]);
+        run_git(&remote, &["commit", "--quiet", "-m", "one"]);
+        run_git(dir, &["clone", "--quiet", "remote", "clone"]);
+
+        let config = WorktreeConfig {
+            repo: dir.join("clone"),
+            remote: default_remote(),
+            size,
+        };
+        (remote, WorktreePool::new(config, dir.join("pool")))
+    }
+
+    fn commit(remote: &Path, content: &str) -> String {
+        std::fs::write(remote.join("file.txt"), content).unwrap();
+        run_git(remote, &["commit", "--quiet", "-am", content]);
+        run_git(remote, &["rev-parse", "HEAD"]).trim().to_string()
+    }
+
+    #[test]
+    fn test_checkout_fetches_and_resets() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, pool) = setup(dir.path(), 2);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let sha = commit(&remote, "two\n");
+        let worktree = pool.checkout(first, &sha, HashSet::new).unwrap();
+        assert_eq!(std::fs::read_to_string(worktree.join("file.txt")).unwrap(), "two\n");
+
+        // Leftovers of the last run are cleaned away
+        std::fs::write(worktree.join("file.txt"), "edited\n").unwrap();
+        std::fs::write(worktree.join("build.log"), "").unwrap();
+        commit(&remote, "three\n");
+        let target = pool.target(None, Some("main"), None).unwrap();
+        assert_eq!(pool.checkout(second, &target, HashSet::new).unwrap(), worktree);
+        assert_eq!(std::fs::read_to_string(worktree.join("file.txt")).unwrap(), "three\n");
+        assert!(!worktree.join("build.log").exists());
+
+        assert!(pool.checkout(second, "--help", HashSet::new).is_err());
+        assert!(pool.checkout(second, "no-such-ref", HashSet::new).is_err());
+    }
+
+    #[test]
+    fn test_busy_worktrees_are_skipped_and_evicted_when_free() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, pool) = setup(dir.path(), 1);
+        let sha = commit(&remote, "two\n");
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let a = pool.checkout(first, &sha, HashSet::new).unwrap();
+        let b = pool.checkout(second, &sha, || HashSet::from([first])).unwrap();
+        assert_ne!(a, b);
+
+        // Over its size, but both runs are still going
+        assert_eq!(pool.evict(|| HashSet::from([first, second])).unwrap(), 0);
+        assert_eq!(pool.evict(|| HashSet::from([second])).unwrap(), 1);
+        assert!(!a.exists());
+        assert!(b.exists());
+        assert!(!run_git(&dir.path().join("clone"), &["worktree", "list"]).contains(a.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_concurrent_checkouts_skip_worktrees_of_runs_that_just_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, pool) = setup(dir.path(), 2);
+        let sha = commit(&remote, "two\n");
+        // One free worktree, left by a run that has ended
+        let free = pool.checkout(Uuid::new_v4(), &sha, HashSet::new).unwrap();
+
+        let running = Mutex::new(HashSet::new());
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let barrier = std::sync::Barrier::new(3);
+        let (a, b) = std::thread::scope(|scope| {
+            // Both runs start while the pool is busy, as two hooks arriving
+            // together would
+            let guard = pool.lock.lock().unwrap();
+            let checkout = |owner: Uuid| {
+                let (pool, running, barrier, sha) = (&pool, &running, &barrier, &sha);
+                scope.spawn(move || {
+                    running.lock().unwrap().insert(owner);
+                    barrier.wait();
+                    pool.checkout(owner, sha, || running.lock().unwrap().clone()).unwrap()
+                })
+            };
+            let (a, b) = (checkout(first), checkout(second));
+            barrier.wait();
+            drop(guard);
+            (a.join().unwrap(), b.join().unwrap())
+        });
+
+        assert_ne!(a, b);
+        assert!(a == free || b == free);
+        assert_eq!(std::fs::read_to_string(owner_file(&a)).unwrap(), first.to_string());
+        assert_eq!(std::fs::read_to_string(owner_file(&b)).unwrap(), second.to_string());
+    }
+
+    #[test]
+    fn test_broken_worktrees_are_recreated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (remote, pool) = setup(dir.path(), 1);
+        let sha = commit(&remote, "two\n");
+
+        let worktree = pool.checkout(Uuid::new_v4(), &sha, HashSet::new).unwrap();
+        std::fs::remove_file(worktree.join(".git")).unwrap();
+        assert!(!pool.is_intact(&worktree));
+
+        assert_eq!(pool.checkout(Uuid::new_v4(), &sha, HashSet::new).unwrap(), worktree);
+        assert!(pool.is_intact(&worktree));
+        assert_eq!(std::fs::read_to_string(worktree.join("file.txt")).unwrap(), "two\n");
+    }
+}
@@ -1,12 +1,51 @@
-//! Termination condition model
+//! Conditions on step output
+//!
+//! `Matcher` is the one check used wherever output is tested: termination
+//! and continuation conditions, the `until:` check of poll steps and the
+//! tests of `when:` conditions. Its YAML form is `MatcherConfig`.
 
-use crate::core::step::ConditionPattern;
+use crate::core::config::{JsonPathConfig, MatcherConfig};
+use crate::core::step::substitute_variables;
+use regex::Regex;
+use std::collections::HashMap;
+use thiserror::Error;
 
-/// Termination condition for a step (not serializable due to ConditionPattern::Regex)
+/// A pattern that does not compile as a regular expression
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("'{pattern}' is not a valid regex: {problem}")]
+pub struct RegexError {
+    pub pattern: String,
+    /// What is wrong, and where in the pattern
+    pub problem: String,
+}
+
+/// Compile a regular expression, locating syntax errors in the pattern
+pub fn compile_regex(pattern: &str) -> Result<Regex, RegexError> {
+    Regex::new(pattern).map_err(|error| {
+        let located = |kind: &dyn std::fmt::Display, position: &regex_syntax::ast::Position| {
+            match pattern.contains('\n') {
+                true => format!("{} at line {}, character {}", kind, position.line, position.column),
+                false => format!("{} at character {}", kind, position.column),
+            }
+        };
+        let problem = match regex_syntax::Parser::new().parse(pattern) {
+            Err(regex_syntax::Error::Parse(e)) => located(e.kind(), &e.span().start),
+            Err(regex_syntax::Error::Translate(e)) => located(e.kind(), &e.span().start),
+            // Valid syntax, but too big to compile
+            _ => error.to_string(),
+        };
+        RegexError {
+            pattern: pattern.to_string(),
+            problem,
+        }
+    })
+}
+
+/// Termination condition for a step (not serializable due to Matcher::Regex)
 #[derive(Debug, Clone)]
 pub struct TerminationCondition {
     /// Pattern that signals successful completion
-    pub success_pattern: ConditionPattern,
+    pub success_pattern: Matcher,
 
     /// Which step to execute on success (None = end pipeline)
     pub on_success: Option<String>,
@@ -15,15 +54,359 @@ pub struct TerminationCondition {
     pub on_failure: Option<String>,
 }
 
+/// A check against step output (not serializable due to Regex)
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// Output contains the string
+    Contains(String),
+    /// Output matches the regular expression
+    Regex(Regex),
+    /// The inner matcher does not match
+    Not(Box<Matcher>),
+    /// At least one matcher matches (none: never)
+    Any(Vec<Matcher>),
+    /// Every matcher matches (none: always)
+    All(Vec<Matcher>),
+    /// Output holds JSON with a value at `path`, optionally equal to `equals`
+    JsonPath {
+        path: String,
+        equals: Option<serde_json::Value>,
+    },
+    /// The command producing the output exited with this code
+    ExitCode(i32),
+}
+
+impl Matcher {
+    /// Compile a matcher from its config
+    pub fn from_config(config: &MatcherConfig) -> Result<Self, RegexError> {
+        Ok(match config {
+            MatcherConfig::Contains(text) => Matcher::Contains(text.clone()),
+            MatcherConfig::Regex(pattern) => Matcher::Regex(compile_regex(pattern)?),
+            MatcherConfig::Not(inner) => Matcher::Not(Box::new(Matcher::from_config(inner)?)),
+            MatcherConfig::Any(matchers) => {
+                Matcher::Any(matchers.iter().map(Matcher::from_config).collect::<Result<_, _>>()?)
+            }
+            MatcherConfig::All(matchers) => {
+                Matcher::All(matchers.iter().map(Matcher::from_config).collect::<Result<_, _>>()?)
+            }
+            MatcherConfig::Jsonpath(JsonPathConfig::Path(path)) => Matcher::JsonPath {
+                path: path.clone(),
+                equals: None,
+            },
+            MatcherConfig::Jsonpath(JsonPathConfig::Compare { path, equals }) => Matcher::JsonPath {
+                path: path.clone(),
+                equals: Some(equals.clone()),
+            },
+            MatcherConfig::ExitCode(code) => Matcher::ExitCode(*code),
+        })
+    }
+
+    /// Check agent output (which has no exit code)
+    pub fn matches(&self, text: &str) -> bool {
+        self.evaluate(text, None)
+    }
+
+    /// Check the output and exit code of a command
+    pub fn evaluate(&self, text: &str, exit_code: Option<i32>) -> bool {
+        match self {
+            Matcher::Contains(pattern) => text.contains(pattern.as_str()),
+            Matcher::Regex(regex) => regex.is_match(text),
+            Matcher::Not(inner) => !inner.evaluate(text, exit_code),
+            Matcher::Any(matchers) => matchers.iter().any(|m| m.evaluate(text, exit_code)),
+            Matcher::All(matchers) => matchers.iter().all(|m| m.evaluate(text, exit_code)),
+            Matcher::JsonPath { path, equals } => {
+                let Some(json) = find_json(text) else {
+                    return false;
+                };
+                match (json_path(&json, path), equals) {
+                    (Some(value), Some(expected)) => value == expected,
+                    (Some(value), None) => !matches!(value, serde_json::Value::Null | serde_json::Value::Bool(false)),
+                    (None, _) => false,
+                }
+            }
+            Matcher::ExitCode(code) => exit_code == Some(*code),
+        }
+    }
+
+    /// `text` with what a plain pattern or regex of the matcher finds
+    /// replaced by `replacement`
+    ///
+    /// `not`, JSON and exit code matchers are left alone, as is a regex
+    /// matching empty text, which no replacement could stop matching.
+    pub fn replace_in(&self, text: &str, replacement: &str) -> String {
+        match self {
+            Matcher::Contains(pattern) if !pattern.is_empty() => text.replace(pattern.as_str(), replacement),
+            Matcher::Regex(regex) if !regex.is_match("") => {
+                regex.replace_all(text, regex::NoExpand(replacement)).into_owned()
+            }
+            Matcher::Any(matchers) | Matcher::All(matchers) => matchers
+                .iter()
+                .fold(text.to_string(), |text, matcher| matcher.replace_in(&text, replacement)),
+            _ => text.to_string(),
+        }
+    }
+
+    /// Text the agent should print for a plain pattern, if the matcher is one
+    pub fn literal(&self) -> Option<String> {
+        match self {
+            Matcher::Contains(text) => Some(text.clone()),
+            Matcher::Regex(regex) => Some(format!("[regex: {}]", regex.as_str())),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Matcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let list = |matchers: &[Matcher]| {
+            matchers.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(", ")
+        };
+        match self {
+            Matcher::Contains(text) => write!(f, "contains {:?}", text),
+            Matcher::Regex(regex) => write!(f, "matches /{}/", regex.as_str()),
+            Matcher::Not(inner) => write!(f, "not ({})", inner),
+            Matcher::Any(matchers) => write!(f, "any of ({})", list(matchers)),
+            Matcher::All(matchers) => write!(f, "all of ({})", list(matchers)),
+            Matcher::JsonPath { path, equals: Some(expected) } => write!(f, "JSON {} == {}", path, expected),
+            Matcher::JsonPath { path, equals: None } => write!(f, "JSON {} is set", path),
+            Matcher::ExitCode(code) => write!(f, "exit code {}", code),
+        }
+    }
+}
+
+/// `when:` condition deciding whether a step runs
+///
+/// Tests joined by `and` / `or` (`and` binds tighter). A test is
+/// `<value> <op> '<text>'` with op `contains`, `not contains`, `matches`
+/// (a regex), `==` or `!=`, or a bare `<value>` that holds when it is not
+/// empty, `false`, `0` or `no`; a leading `not` negates it. Values are
+/// templates such as `{{ steps.test.output }}`, and undefined variables
+/// render as empty text.
+#[derive(Debug, Clone)]
+pub struct When {
+    source: String,
+    /// Alternatives (`or`), each a list of tests that must all hold (`and`)
+    any_of: Vec<Vec<WhenTest>>,
+}
+
+#[derive(Debug, Clone)]
+struct WhenTest {
+    subject: String,
+    matcher: Matcher,
+}
+
+impl When {
+    /// Parse a `when:` expression
+    pub fn parse(source: &str) -> Result<Self, String> {
+        if source.trim().is_empty() {
+            return Err("must not be empty".to_string());
+        }
+        let any_of = split_top_level(source, " or ")
+            .into_iter()
+            .map(|all| {
+                split_top_level(all, " and ")
+                    .into_iter()
+                    .map(WhenTest::parse)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            source: source.trim().to_string(),
+            any_of,
+        })
+    }
+
+    /// Check the condition against the variables available to prompts
+    pub fn evaluate(&self, variables: &HashMap<String, String>) -> bool {
+        self.any_of
+            .iter()
+            .any(|all| all.iter().all(|test| test.evaluate(variables)))
+    }
+}
+
+impl std::fmt::Display for When {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl WhenTest {
+    fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        if let Some(rest) = text.strip_prefix("not ") {
+            let inner = Self::parse(rest)?;
+            return Ok(Self {
+                subject: inner.subject,
+                matcher: Matcher::Not(Box::new(inner.matcher)),
+            });
+        }
+
+        const OPERATORS: [&str; 5] = [" not contains ", " contains ", " matches ", " == ", " != "];
+        let operator = OPERATORS
+            .iter()
+            .filter_map(|op| find_top_level(text, op).map(|at| (at, *op)))
+            .min_by_key(|(at, _)| *at);
+        let Some((at, op)) = operator else {
+            if text.is_empty() {
+                return Err("expected a value to test".to_string());
+            }
+            let falsy = Regex::new(r"(?i)^\s*(false|0|no)?\s*$").unwrap();
+            return Ok(Self {
+                subject: text.to_string(),
+                matcher: Matcher::Not(Box::new(Matcher::Regex(falsy))),
+            });
+        };
+
+        let subject = text[..at].trim();
+        if subject.is_empty() {
+            return Err(format!("expected a value before `{}`", op.trim()));
+        }
+        let value = unquote(text[at + op.len()..].trim())?;
+        let equals = || Regex::new(&format!(r"^\s*{}\s*$", regex::escape(value))).unwrap();
+        let matcher = match op.trim() {
+            "contains" => Matcher::Contains(value.to_string()),
+            "not contains" => Matcher::Not(Box::new(Matcher::Contains(value.to_string()))),
+            "matches" => Matcher::Regex(compile_regex(value).map_err(|e| e.to_string())?),
+            "==" => Matcher::Regex(equals()),
+            _ => Matcher::Not(Box::new(Matcher::Regex(equals()))),
+        };
+        Ok(Self {
+            subject: subject.to_string(),
+            matcher,
+        })
+    }
+
+    fn evaluate(&self, variables: &HashMap<String, String>) -> bool {
+        let rendered = substitute_variables(&self.subject, variables);
+        let text = if rendered.missing.is_empty() {
+            rendered.text
+        } else {
+            let mut variables = variables.clone();
+            for name in rendered.missing {
+                variables.insert(name, String::new());
+            }
+            substitute_variables(&self.subject, &variables).text
+        };
+        self.matcher.matches(&text)
+    }
+}
+
+/// Text between matching single or double quotes
+fn unquote(text: &str) -> Result<&str, String> {
+    let quoted = text.len() >= 2
+        && (text.starts_with('\'') && text.ends_with('\'')
+            || text.starts_with('"') && text.ends_with('"'));
+    if quoted {
+        Ok(&text[1..text.len() - 1])
+    } else {
+        Err(format!("expected a quoted value, found `{}`", text))
+    }
+}
+
+/// Position of `pattern` outside quotes and `{{ }}` placeholders
+fn find_top_level(text: &str, pattern: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut depth = 0;
+    for (at, c) in text.char_indices() {
+        let rest = &text[at..];
+        match quote {
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None if rest.starts_with("{{") => depth += 1,
+            None if rest.starts_with("}}") && depth > 0 => depth -= 1,
+            None if depth == 0 && rest.starts_with(pattern) => return Some(at),
+            None if depth == 0 && (c == '\'' || c == '"') => quote = Some(c),
+            None => {}
+        }
+    }
+    None
+}
+
+fn split_top_level<'a>(text: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(at) = find_top_level(rest, separator) {
+        parts.push(&rest[..at]);
+        rest = &rest[at + separator.len()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+/// JSON in the output: the whole output, or the outermost object or array in it
+fn find_json(text: &str) -> Option<serde_json::Value> {
+    if let Ok(value) = serde_json::from_str(text.trim()) {
+        return Some(value);
+    }
+    [('{', '}'), ('[', ']')].iter().find_map(|(open, close)| {
+        let start = text.find(*open)?;
+        let end = text.rfind(*close)?;
+        (start < end).then(|| serde_json::from_str(&text[start..=end]).ok()).flatten()
+    })
+}
+
+/// Value at a JSONPath of the form `$.key.other[0]['quoted key']`
+fn json_path<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    let mut rest = path.trim().strip_prefix('$').unwrap_or(path.trim());
+    let mut current = value;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            current = current.get(&after[..end])?;
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')?;
+            let key = &after[..end];
+            current = match key.trim_matches(|c| c == '\'' || c == '"') {
+                quoted if quoted.len() != key.len() => current.get(quoted)?,
+                _ => current.get(key.parse::<usize>().ok()?)?,
+            };
+            rest = &after[end + 1..];
+        } else {
+            return None;
+        }
+    }
+    Some(current)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use regex::Regex;
+
+    #[test]
+    fn test_when_conditions() {
+        let variables = HashMap::from([
+            ("steps.test.output".to_string(), "2 passed, 1 FLAKY".to_string()),
+            ("deploy".to_string(), "false".to_string()),
+            ("env".to_string(), "prod".to_string()),
+        ]);
+        let holds = |source: &str| When::parse(source).unwrap().evaluate(&variables);
+
+        assert!(holds("{{ steps.test.output }} contains 'FLAKY'"));
+        assert!(!holds("{{ steps.test.output }} not contains 'FLAKY'"));
+        assert!(holds(r#"{{ steps.test.output }} matches "\d+ passed""#));
+        assert!(holds("{{ env }} == 'prod'"));
+        assert!(holds("{{ env }} != 'staging'"));
+        assert!(!holds("{{ deploy }}"));
+        assert!(holds("not {{ deploy }}"));
+        assert!(holds("{{ env }}"));
+        // Undefined variables render as empty text
+        assert!(!holds("{{ steps.lint.output }}"));
+        assert!(holds("{{ steps.lint.output }} == ''"));
+        // `and` binds tighter than `or`; quoted operators are plain text
+        assert!(holds("{{ deploy }} and {{ env }} or {{ env }} == 'prod'"));
+        assert!(!holds("{{ env }} == 'prod' and {{ deploy }}"));
+        assert!(!holds("{{ env }} contains 'a or b'"));
+
+        assert!(When::parse("").is_err());
+        assert!(When::parse("{{ env }} == prod").is_err());
+        assert!(When::parse("{{ env }} matches '('").is_err());
+    }
 
     #[test]
     fn test_termination_condition() {
         let condition = TerminationCondition {
-            success_pattern: ConditionPattern::Simple("DONE".to_string()),
+            success_pattern: Matcher::Contains("DONE".to_string()),
             on_success: Some("next_step".to_string()),
             on_failure: Some("retry_step".to_string()),
         };
@@ -38,7 +421,7 @@ mod tests {
     #[test]
     fn test_termination_condition_with_regex() {
         let condition = TerminationCondition {
-            success_pattern: ConditionPattern::Regex(Regex::new(r"✅\s*\w+").unwrap()),
+            success_pattern: Matcher::Regex(Regex::new(r"✅\s*\w+").unwrap()),
             on_success: None,
             on_failure: None,
         };
@@ -47,4 +430,64 @@ mod tests {
         assert!(condition.success_pattern.matches("✅   DONE"));
         assert!(!condition.success_pattern.matches("❌ FAILED"));
     }
+
+    fn matcher(yaml: &str) -> Matcher {
+        let config: MatcherConfig = serde_yaml::with::singleton_map_recursive::deserialize(
+            serde_yaml::Deserializer::from_str(yaml),
+        )
+        .unwrap();
+        Matcher::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn test_combinators() {
+        let m = matcher("all: [{contains: APPROVED}, {not: {regex: 'TODO|FIXME'}}]");
+        assert!(m.matches("APPROVED, ship it"));
+        assert!(!m.matches("APPROVED but TODO: tests"));
+        assert!(!m.matches("looks fine"));
+
+        let m = matcher("any: [{contains: LGTM}, {contains: APPROVED}]");
+        assert!(m.matches("LGTM"));
+        assert!(!m.matches("changes requested"));
+        assert!(!Matcher::Any(vec![]).matches("anything"));
+    }
+
+    #[test]
+    fn test_jsonpath() {
+        let m = matcher("jsonpath: {path: '$.checks[0].state', equals: success}");
+        assert!(m.matches(r#"Result: {"checks": [{"state": "success"}]}"#));
+        assert!(!m.matches(r#"{"checks": [{"state": "pending"}]}"#));
+        assert!(!m.matches("not json"));
+
+        let m = matcher("jsonpath: \"$['ready']\"");
+        assert!(m.matches(r#"{"ready": true}"#));
+        assert!(!m.matches(r#"{"ready": false}"#));
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let m = matcher("all: [{exit_code: 0}, {contains: ok}]");
+        assert!(m.evaluate("ok", Some(0)));
+        assert!(!m.evaluate("ok", Some(1)));
+        // Agent output has no exit code
+        assert!(!m.matches("ok"));
+    }
+
+    #[test]
+    fn test_invalid_regex_is_rejected() {
+        let config = MatcherConfig::Not(Box::new(MatcherConfig::Regex("(".to_string())));
+        assert!(Matcher::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_regex_errors_say_where() {
+        let error = compile_regex(r"APPROVED (by \w+").unwrap_err();
+        assert_eq!(error.pattern, r"APPROVED (by \w+");
+        assert_eq!(error.problem, "unclosed group at character 10");
+
+        let error = compile_regex("DONE\n[a-").unwrap_err();
+        assert!(error.problem.ends_with("at line 2, character 1"), "{}", error.problem);
+
+        assert!(compile_regex(r"\d+ passed").is_ok());
+    }
 }
@@ -1,32 +1,170 @@
 //! Pipeline configuration from YAML
 
+use crate::core::config_warnings::{self, ConfigWarning};
+use crate::core::file_variables::{self, FileCheck};
 use crate::core::Pipeline;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
-use std::path::Path;
-use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::{Context, Result};
 
-/// Variable definition - can be a simple string or a file reference
+/// Variable definition - a simple string, a file reference or a command
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VariableDefinition {
     /// Simple string value
     String(String),
-    /// File reference with validation flag
-    File { path: String, validate_exists: bool },
+    /// File reference with validation flag, and the largest size the file
+    /// may have (`None`: `file_variables::DEFAULT_MAX_BYTES`)
+    File {
+        path: String,
+        validate_exists: bool,
+        max_bytes: Option<u64>,
+    },
+    /// Shell command whose output becomes the value, run once when a run
+    /// starts (see `PipelineConfig::resolve_command_variables`)
+    Command {
+        command: String,
+        timeout_secs: u64,
+        /// Value used if the command fails (otherwise loading fails)
+        default: Option<String>,
+    },
 }
 
+/// Default time allowed for a variable's command
+pub const DEFAULT_VARIABLE_COMMAND_TIMEOUT_SECS: u64 = 10;
+
 impl VariableDefinition {
     /// Get the string representation for rendering in prompts
+    ///
+    /// Command variables render their `default` here; their output is only
+    /// known once they have been resolved.
     pub fn render_value(&self) -> String {
         match self {
             VariableDefinition::String(s) => s.clone(),
             VariableDefinition::File { path, .. } => format!("@{}", path),
+            VariableDefinition::Command { default, .. } => default.clone().unwrap_or_default(),
+        }
+    }
+}
+
+/// Run a variable's command and return its trimmed standard output
+fn run_variable_command(command: &str, timeout: std::time::Duration) -> Result<String> {
+    use std::io::Read;
+
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+
+    // Drain the pipes on other threads so a chatty command can't block on a full pipe
+    let drain = |pipe: Option<Box<dyn Read + Send>>| {
+        std::thread::spawn(move || {
+            let mut text = String::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_string(&mut text);
+            }
+            text
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+    let deadline = std::time::Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if std::time::Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!("timed out after {}s", timeout.as_secs());
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    if !status.success() {
+        match stderr.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => anyhow::bail!("{}: {}", status, line.trim()),
+            None => anyhow::bail!("{}", status),
+        }
+    }
+    Ok(stdout.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Step ID suffix for a matrix combination, with characters that would
+/// not survive a placeholder or dotted path replaced by `-`
+fn instance_suffix(values: &[&str]) -> String {
+    values
+        .iter()
+        .map(|value| {
+            value
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' { c } else { '-' })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Names that become file names (step IDs, and the pipelines and triggers
+/// of `serve`): letters, digits, `-`, `_` and `.`, not leading
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Set the value at a dotted path, creating missing map keys
+///
+/// The value is parsed as YAML, so `600` is a number and `[a, b]` a list.
+fn set_path(document: &mut Value, path: &str, value: &str) -> Result<(), String> {
+    let mut node = document;
+    for segment in path.split('.') {
+        if node.is_null() {
+            *node = Value::Mapping(Default::default());
         }
+        node = match node {
+            Value::Mapping(map) => map.entry(Value::String(segment.to_string())).or_insert(Value::Null),
+            Value::Sequence(items) => {
+                let index = list_index(items, segment)?;
+                &mut items[index]
+            }
+            _ => return Err(format!("'{}' is inside a value that is not a map or list", segment)),
+        };
     }
+    *node = serde_yaml::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok(())
+}
+
+/// Look up the value at a dotted path
+fn get_path<'a>(document: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(document, |node, segment| match node {
+        Value::Mapping(map) => map.get(segment),
+        Value::Sequence(items) => list_index(items, segment).ok().map(|index| &items[index]),
+        _ => None,
+    })
+}
+
+/// Position of a list item by `id` or index
+fn list_index(items: &[Value], segment: &str) -> Result<usize, String> {
+    let position = match segment.parse::<usize>() {
+        Ok(index) => Some(index).filter(|index| *index < items.len()),
+        Err(_) => items.iter().position(|item| item.get("id").and_then(Value::as_str) == Some(segment)),
+    };
+    position.ok_or_else(|| format!("no item '{}'", segment))
 }
 
 /// Top-level pipeline configuration loaded from YAML
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct PipelineConfig {
     /// Pipeline name
     pub name: String,
@@ -37,6 +175,7 @@ pub struct PipelineConfig {
 
     /// Global variables available to all steps
     #[serde(default)]
+    #[schemars(with = "std::collections::HashMap<String, serde_json::Value>")]
     variables: std::collections::HashMap<String, Value>,
 
     /// Pipeline steps
@@ -49,10 +188,336 @@ pub struct PipelineConfig {
     /// Default timeout for steps (in seconds)
     #[serde(default)]
     pub default_timeout_secs: Option<u64>,
+
+    /// Wall-clock limit for the whole run (in seconds); in-flight steps are
+    /// cancelled when it runs out
+    #[serde(default)]
+    pub max_duration_secs: Option<u64>,
+
+    /// Use pi's JSON streaming mode (default: true)
+    #[serde(default)]
+    pub streaming: Option<bool>,
+
+    /// Report a missed heartbeat after this many seconds without agent events
+    #[serde(default)]
+    pub heartbeat_secs: Option<u64>,
+
+    /// Language the agent should respond in (e.g. "Portuguese")
+    #[serde(default)]
+    pub language: Option<String>,
+
+    /// Instruction appended to prompts when a language is set
+    /// (default: "Respond in {{ language }}.")
+    #[serde(default)]
+    pub language_template: Option<String>,
+
+    /// Directory the agent runs in for every step, unless a step sets its
+    /// own (may use `{{ variables }}`; default: the current directory)
+    #[serde(default)]
+    pub working_dir: Option<String>,
+
+    /// Environment variables set on the agent's process for every step
+    /// (values may use `{{ variables }}`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// Variables of pi-peline's own environment the agent's process
+    /// inherits; when set, all others are withheld (default: all inherited)
+    #[serde(default)]
+    pub env_passthrough: Option<Vec<String>>,
+
+    /// Stamp collected artifacts with a header naming the run that produced them
+    #[serde(default)]
+    pub provenance: bool,
+
+    /// Replace a step's own success and continuation markers found in the
+    /// step outputs its prompt injects
+    #[serde(default)]
+    pub sanitize_markers: bool,
+
+    /// How step prompts are rendered (default: `placeholders`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<TemplateSyntax>,
+
+    /// Advisory lock preventing concurrent runs of this pipeline
+    #[serde(default)]
+    pub locking: LockScope,
+
+    /// What to do when another run holds the lock
+    #[serde(default)]
+    pub on_lock_conflict: LockConflictPolicy,
+
+    /// Concurrency group shared by related runs (e.g. one per pull request)
+    #[serde(default)]
+    pub concurrency: Option<ConcurrencyConfig>,
+
+    /// Most steps running at once with the parallel strategies
+    /// (`--max-parallel` overrides it)
+    #[serde(default)]
+    pub max_parallel: Option<usize>,
+
+    /// Output of command variables, captured when the config was loaded
+    #[serde(skip)]
+    command_outputs: std::collections::HashMap<String, String>,
+
+    /// Codes of config warnings not to report for this file (e.g. `W001`)
+    #[serde(default)]
+    pub suppress_warnings: Vec<String>,
+
+    /// Warnings about deprecated or ineffective settings, found when the
+    /// config was loaded
+    #[serde(skip)]
+    pub warnings: Vec<ConfigWarning>,
+
+    /// Flag the agent takes a random seed with (e.g. `--seed`); when set,
+    /// the run's seed is passed with it to every agent
+    #[serde(default)]
+    pub seed_arg: Option<String>,
+
+    /// Named agent backends that steps can list under `agents:`
+    #[serde(default)]
+    pub agents: std::collections::HashMap<String, AgentProfileConfig>,
+
+    /// Spending limits in USD per budget category, e.g. `codegen: 5.0`
+    #[serde(default)]
+    pub budgets: std::collections::HashMap<String, f64>,
+
+    /// Named results of the run: name -> template over variables and step
+    /// results, e.g. `pr_url: "{{ steps.open_pr.values.url }}"`
+    #[serde(default)]
+    pub outputs: std::collections::BTreeMap<String, String>,
+
+    /// Priority against other runs on the machine (default: normal)
+    #[serde(default)]
+    pub priority: Priority,
+
+    /// When the run counts as successful (default: no step failed)
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Option<SuccessCriteria>")]
+    pub success: Option<SuccessCriteria>,
+
+    /// What a failed step does to the rest of the run (default: fail_fast,
+    /// or continue_all with `success:` criteria)
+    #[serde(default)]
+    pub failure_policy: Option<FailurePolicy>,
+
+    /// URLs the execution summary is sent to when the run ends
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// Where each step's streamed output is logged, and how much is kept
+    #[serde(default)]
+    pub logs: LogsConfig,
+}
+
+/// A named agent backend
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+pub struct AgentProfileConfig {
+    /// Path to the pi executable (default: "pi")
+    #[serde(default)]
+    pub pi_path: Option<String>,
+
+    /// Extra arguments passed to pi, e.g. `["--provider", "openai"]`
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Model to run, passed to pi as `--model` unless `args` already picks one
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl AgentProfileConfig {
+    /// Arguments for pi, with `--model` in front when a model is set
+    pub fn pi_args(&self) -> Vec<String> {
+        let mut args = self.args.clone();
+        if let Some(model) = &self.model {
+            if !args.iter().any(|arg| arg == "--model" || arg.starts_with("--model=")) {
+                args.splice(0..0, ["--model".to_string(), model.clone()]);
+            }
+        }
+        args
+    }
+}
+
+/// A URL the execution summary is POSTed to when a run ends
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Where to send the summary (http or https)
+    pub url: String,
+
+    /// Environment variable holding the secret the payload is signed with
+    #[serde(default)]
+    pub secret_env: Option<String>,
+
+    /// Retries after a 5xx response or a connection error (default: 3)
+    #[serde(default)]
+    pub max_retries: Option<u32>,
+
+    /// Seconds before the first retry, doubled for each later one (default: 1)
+    #[serde(default)]
+    pub backoff_secs: Option<u64>,
+}
+
+/// Default directory of step logs, relative to where `pipeline` runs
+pub const DEFAULT_LOGS_DIR: &str = ".pipeline/logs";
+
+/// Size at which a step log is rotated, in bytes (10 MiB)
+pub const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Number of runs whose step logs are kept
+pub const DEFAULT_LOG_RETENTION: usize = 20;
+
+/// Step log configuration
+///
+/// Every step's streamed output, tool calls included, is written to
+/// `<dir>/<execution id>/<step id>.log`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+pub struct LogsConfig {
+    /// Write step logs (default: true)
+    #[serde(default)]
+    pub enabled: Option<bool>,
+
+    /// Directory the logs go to (default: ".pipeline/logs")
+    #[serde(default)]
+    pub dir: Option<String>,
+
+    /// Size at which a step's log is moved to `<step id>.log.1`, replacing
+    /// the one before (default: 10 MiB)
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+
+    /// Number of runs whose logs are kept; older ones are removed when a
+    /// run starts (default: 20)
+    #[serde(default)]
+    pub retention: Option<usize>,
+}
+
+/// Concurrency group configuration
+///
+/// Only one run per group executes at a time. A new run either waits for the
+/// current one to finish or, with `cancel_in_progress`, supersedes it.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// Group key template, e.g. `pr-{{ pr_number }}`
+    pub group: String,
+
+    /// Cancel the in-flight run of the group instead of waiting for it
+    #[serde(default)]
+    pub cancel_in_progress: bool,
+}
+
+impl ConcurrencyConfig {
+    /// Resolve the group key with the run's variables
+    pub fn group_key(&self, variables: &std::collections::HashMap<String, String>) -> String {
+        crate::core::substitute_variables(&self.group, variables).text
+    }
+}
+
+/// Scope of the advisory run lock
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LockScope {
+    /// No locking - runs may overlap
+    #[default]
+    None,
+    /// Only one run of the pipeline (by name) at a time
+    Pipeline,
+}
+
+/// Behavior when the run lock is already held
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LockConflictPolicy {
+    /// Fail immediately
+    #[default]
+    Fail,
+    /// Wait until the lock is released
+    Wait,
+    /// Wait in line: runs take the lock in the order they started waiting
+    Queue,
+}
+
+/// What a step failing without an `on_failure` route does to the rest of the run
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Stop scheduling steps; the run fails
+    #[default]
+    FailFast,
+    /// Keep running steps that don't depend on the failed step, directly or
+    /// through others
+    FinishIndependent,
+    /// Keep running every step, including those depending on the failed step
+    ContinueAll,
+}
+
+/// Priority of a run against the other runs on the machine
+///
+/// While a run of higher priority is active, a run starts no new steps; the
+/// step it is running finishes first.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    /// Background work, held back by normal and high-priority runs
+    Low,
+    #[default]
+    Normal,
+    /// Interactive work that background runs make way for
+    High,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+
+    /// Rank stored in the database (higher wins)
+    pub fn rank(&self) -> i64 {
+        *self as i64
+    }
+}
+
+/// How much of a step's run leaves the process: history, run files, logs
+/// and events
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Telemetry {
+    /// Nothing beyond the step's status
+    None,
+    /// Status, timings, attempts, costs and sizes, but no prompts, outputs
+    /// or error text
+    Metadata,
+    /// Everything, including outputs and transcripts
+    #[default]
+    Full,
+}
+
+impl Telemetry {
+    /// Stands in for text a step's telemetry level keeps back
+    pub const WITHHELD: &'static str = "[withheld by telemetry setting]";
+
+    /// Whether prompts, outputs and error text may be kept
+    pub fn keeps_content(&self) -> bool {
+        *self == Telemetry::Full
+    }
+
+    /// Whether timings, attempts, costs and sizes may be kept
+    pub fn keeps_metadata(&self) -> bool {
+        *self != Telemetry::None
+    }
 }
 
 /// Step configuration as defined in YAML
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct StepConfig {
     /// Unique step identifier
     pub id: String,
@@ -64,13 +529,24 @@ pub struct StepConfig {
     #[serde(default)]
     pub description: Option<String>,
 
-    /// The prompt template for this step
+    /// The prompt template for this step (not used by `poll:`, `pipeline:`
+    /// or `queue:` steps)
+    #[serde(default)]
     pub prompt: String,
 
+    /// How the prompt is rendered (overrides global)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<TemplateSyntax>,
+
     /// List of step IDs this step depends on
     #[serde(default)]
     pub depends_on: Vec<String>,
 
+    /// Alternative steps, one of which must complete before this step runs
+    /// (see `Step::dependencies_met`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on_any: Vec<String>,
+
     /// Termination condition configuration
     #[serde(default)]
     pub termination: Option<TerminationConfig>,
@@ -79,6 +555,20 @@ pub struct StepConfig {
     #[serde(default)]
     pub continuation: Option<ContinuationConfig>,
 
+    /// Which pattern wins when an output matches both the termination and
+    /// the continuation pattern
+    #[serde(default)]
+    pub precedence: Precedence,
+
+    /// Replies sent to the agent's session when its output matches a
+    /// pattern (e.g. to answer a clarifying question)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub followups: Vec<FollowupConfig>,
+
+    /// Most follow-up replies sent in one attempt (default: 3)
+    #[serde(default)]
+    pub max_followups: Option<usize>,
+
     /// Maximum retries for this step (overrides global)
     #[serde(default)]
     pub max_retries: Option<usize>,
@@ -87,86 +577,859 @@ pub struct StepConfig {
     #[serde(default)]
     pub timeout_secs: Option<u64>,
 
-    /// Whether this step can run in parallel with others
-    #[serde(default)]
-    pub allow_parallel: bool,
-}
+    /// Whether this step can run in parallel with others
+    #[serde(default)]
+    pub allow_parallel: bool,
+
+    /// Order among steps ready at the same time: higher runs first (default: 0)
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Abort the step if the agent writes more than this many bytes
+    #[serde(default)]
+    pub max_write_bytes: Option<u64>,
+
+    /// Use pi's JSON streaming mode for this step (overrides global)
+    #[serde(default)]
+    pub streaming: Option<bool>,
+
+    /// Language for this step (overrides global)
+    #[serde(default)]
+    pub language: Option<String>,
+
+    /// Heartbeat interval for this step (overrides global)
+    #[serde(default)]
+    pub heartbeat_secs: Option<u64>,
+
+    /// Values to remember for future runs: key -> regex matched against the
+    /// step output (first capture group, or the whole match)
+    #[serde(default)]
+    pub remember: std::collections::HashMap<String, String>,
+
+    /// External conditions that must hold before the step starts
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Vec<WaitCondition>")]
+    pub wait_for: Vec<WaitCondition>,
+
+    /// How long to wait for `wait_for` conditions (default: 300)
+    #[serde(default)]
+    pub wait_timeout_secs: Option<u64>,
+
+    /// Agent profiles to use, in failover order (default: the built-in agent)
+    #[serde(default)]
+    pub agents: Vec<String>,
+
+    /// Agent to run this step with instead of the default (`pi_path`, `args`, `model`)
+    #[serde(default)]
+    pub agent: Option<AgentProfileConfig>,
+
+    /// Consecutive provider errors before failing over to the next agent (default: 2)
+    #[serde(default)]
+    pub failover_after: Option<usize>,
+
+    /// Budget category the step's spending counts against
+    #[serde(default)]
+    pub budget_category: Option<String>,
+
+    /// Run a check until it passes instead of running the agent
+    #[serde(default)]
+    pub poll: Option<PollConfig>,
+
+    /// Run another pipeline file instead of running the agent
+    #[serde(default)]
+    pub pipeline: Option<SubPipelineConfig>,
+
+    /// Push items to, or take items from, a named queue instead of running
+    /// the agent
+    #[serde(default)]
+    pub queue: Option<QueueConfig>,
+
+    /// Never run again once completed, even if a route leads back to it
+    #[serde(default)]
+    pub run_once: bool,
+
+    /// Ask the agent for a plan of what it would do instead of doing it
+    #[serde(default)]
+    pub plan_only: bool,
+
+    /// Keep the step's `{{ step.tmp_dir }}` when an attempt fails, for inspection
+    #[serde(default)]
+    pub keep_tmp_dir_on_failure: bool,
+
+    /// Directory the agent runs in for this step (overrides global; may use
+    /// `{{ variables }}`)
+    #[serde(default)]
+    pub working_dir: Option<String>,
+
+    /// Environment variables set on the agent's process for this step, on
+    /// top of the global ones (values may use `{{ variables }}`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+
+    /// Inherited environment variables for this step (overrides global)
+    #[serde(default)]
+    pub env_passthrough: Option<Vec<String>>,
+
+    /// Path globs the step locks while it runs (steps with overlapping locks never run together)
+    #[serde(default)]
+    pub locks: Vec<String>,
+
+    /// Globs of files to collect into the run's artifacts once the step
+    /// completes (relative to its working directory)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+
+    /// Stamp this step's artifacts with a provenance header (overrides global)
+    #[serde(default)]
+    pub provenance: Option<bool>,
+
+    /// Replace this step's markers in the step outputs its prompt injects (overrides global)
+    #[serde(default)]
+    pub sanitize_markers: Option<bool>,
+
+    /// What the step's result depends on; while these are unchanged since
+    /// the step last completed, the step is skipped and its output reused
+    #[serde(default)]
+    pub inputs: Option<StepInputs>,
+
+    /// Condition the step runs under (e.g. `{{ steps.test.output }} contains 'FLAKY'`); skipped otherwise
+    #[serde(default)]
+    pub when: Option<String>,
+
+    /// Values to fan the step out over, one instance per combination (`language: [rust, go]`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[schemars(with = "BTreeMap<String, Vec<serde_json::Value>>")]
+    pub matrix: BTreeMap<String, Vec<Value>>,
+
+    /// What of the step is persisted and exported: `none`, `metadata` or `full` (default)
+    #[serde(default)]
+    pub telemetry: Telemetry,
+}
+
+/// Reply sent to the agent's session when its output matches `pattern`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct FollowupConfig {
+    /// Text in the agent's output that calls for the reply
+    pub pattern: String,
+
+    /// The reply (may use `{{ variables }}`)
+    pub reply: String,
+
+    /// Whether to use regex pattern matching
+    #[serde(default)]
+    pub use_regex: bool,
+}
+
+/// Inputs of an incremental step (`inputs:`)
+///
+/// The outputs of the step's dependencies and its prompt always count as
+/// inputs too.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct StepInputs {
+    /// Globs of files whose contents the step reads (relative to its working directory)
+    #[serde(default)]
+    pub files: Vec<String>,
+
+    /// Names of variables the step depends on
+    #[serde(default)]
+    pub variables: Vec<String>,
+}
+
+/// Check repeated by a `poll:` step
+///
+/// Exactly one of `command` and `http` must be set.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct PollConfig {
+    /// Shell command that exits successfully once the check passes
+    #[serde(default)]
+    pub command: Option<String>,
+
+    /// `http://` URL that responds with a 2xx status once the check passes
+    #[serde(default)]
+    pub http: Option<String>,
+
+    /// Seconds between attempts (default: 30)
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+
+    /// Give up after this many seconds (default: 1800)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// Matcher on the command's output and exit code, or the response body,
+    /// that decides when the check passes
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Option<MatcherConfig>")]
+    pub until: Option<MatcherConfig>,
+}
+
+impl PollConfig {
+    /// The check as a wait condition
+    pub fn condition(&self) -> Option<WaitCondition> {
+        match (&self.command, &self.http) {
+            (Some(command), None) => Some(WaitCondition::Command(command.clone())),
+            (None, Some(url)) => Some(WaitCondition::Http(url.clone())),
+            _ => None,
+        }
+    }
+}
+
+/// Pipeline run by a `pipeline:` step
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct SubPipelineConfig {
+    /// Pipeline file, relative to the file of the pipeline it is used in
+    pub file: String,
+
+    /// Variables set for the child run (values may use `{{ variables }}`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+
+    /// Cancel the child run after this many seconds (default: its own
+    /// `max_duration_secs`, if any)
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// The child pipeline, loaded along with this one
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub config: Option<Arc<PipelineConfig>>,
+}
+
+/// Queue operation of a `queue:` step
+///
+/// Exactly one of `push` and `pop` must be set.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct QueueConfig {
+    /// Queue to add `items` to
+    #[serde(default)]
+    pub push: Option<String>,
+
+    /// Queue to take items from
+    #[serde(default)]
+    pub pop: Option<String>,
+
+    /// Items to push, one per non-empty line (may use `{{ variables }}`)
+    #[serde(default)]
+    pub items: Option<String>,
+
+    /// Most items to take at once (default: 1)
+    #[serde(default)]
+    pub batch: Option<usize>,
+}
+
+/// External precondition polled before a step starts
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WaitCondition {
+    /// A file or directory exists
+    File(String),
+    /// A TCP port accepts connections (`host:port`)
+    Port(String),
+    /// An `http://` URL responds with a 2xx status
+    Http(String),
+    /// A shell command exits successfully
+    Command(String),
+}
+
+impl std::fmt::Display for WaitCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaitCondition::File(path) => write!(f, "file {}", path),
+            WaitCondition::Port(address) => write!(f, "port {}", address),
+            WaitCondition::Http(url) => write!(f, "{}", url),
+            WaitCondition::Command(command) => write!(f, "`{}`", command),
+        }
+    }
+}
+
+/// A check against step output, written as a single-key map
+///
+/// ```yaml
+/// all:
+///   - contains: "APPROVED"
+///   - not: { regex: "TODO|FIXME" }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatcherConfig {
+    /// Output contains the string
+    Contains(String),
+    /// Output matches the regular expression
+    Regex(String),
+    /// The inner matcher does not match
+    Not(Box<MatcherConfig>),
+    /// At least one of the matchers matches
+    Any(Vec<MatcherConfig>),
+    /// All of the matchers match
+    All(Vec<MatcherConfig>),
+    /// Output is JSON with a value at a path
+    Jsonpath(JsonPathConfig),
+    /// The command exited with this code (poll checks only)
+    ExitCode(i32),
+}
+
+/// `jsonpath:` matcher: a path that must be set, or a path and its expected value
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(untagged)]
+#[schemars(deny_unknown_fields)]
+pub enum JsonPathConfig {
+    Path(String),
+    Compare { path: String, equals: serde_json::Value },
+}
+
+/// Condition on step results deciding whether a run succeeded, written as a
+/// single-key map like `MatcherConfig`
+///
+/// ```yaml
+/// any:
+///   - completed: deploy
+///   - all:
+///       - completed: build
+///       - skipped: publish
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SuccessCriteria {
+    /// The step completed
+    Completed(String),
+    /// The step failed
+    Failed(String),
+    /// The step never ran (or was skipped)
+    Skipped(String),
+    /// The inner condition does not hold
+    Not(Box<SuccessCriteria>),
+    /// At least one of the conditions holds
+    Any(Vec<SuccessCriteria>),
+    /// All of the conditions hold
+    All(Vec<SuccessCriteria>),
+}
+
+impl SuccessCriteria {
+    /// Step IDs the condition refers to
+    pub fn step_ids(&self) -> Vec<&str> {
+        match self {
+            SuccessCriteria::Completed(id)
+            | SuccessCriteria::Failed(id)
+            | SuccessCriteria::Skipped(id) => vec![id.as_str()],
+            SuccessCriteria::Not(inner) => inner.step_ids(),
+            SuccessCriteria::Any(conditions) | SuccessCriteria::All(conditions) => {
+                conditions.iter().flat_map(SuccessCriteria::step_ids).collect()
+            }
+        }
+    }
+}
+
+/// Termination condition configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct TerminationConfig {
+    /// Pattern that signals successful completion
+    #[serde(default)]
+    pub success_pattern: String,
+
+    /// Matcher that signals successful completion (instead of `success_pattern`)
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Option<MatcherConfig>")]
+    pub success_when: Option<MatcherConfig>,
+
+    /// Which step to execute on success (null = end pipeline)
+    #[serde(default)]
+    pub on_success: Option<String>,
+
+    /// Which step to execute on failure/rejection
+    #[serde(default)]
+    pub on_failure: Option<String>,
+
+    /// Whether to use regex pattern matching
+    #[serde(default)]
+    pub use_regex: bool,
+}
+
+/// Continuation condition configuration
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ContinuationConfig {
+    /// Pattern that signals "not done, continue"
+    #[serde(default)]
+    pub pattern: String,
+
+    /// Matcher that signals "not done, continue" (instead of `pattern`)
+    #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
+    #[schemars(with = "Option<MatcherConfig>")]
+    pub when: Option<MatcherConfig>,
+
+    /// Action to take: "retry" (same step) or "route" (different step)
+    #[serde(default = "default_continuation_action")]
+    pub action: ContinuationAction,
+
+    /// Target step ID when action is "route"
+    #[serde(default)]
+    pub target: Option<String>,
+
+    /// Whether to pass notes/context when routing
+    #[serde(default)]
+    pub carry_notes: bool,
+
+    /// Whether to use regex pattern matching
+    #[serde(default)]
+    pub use_regex: bool,
+}
+
+/// Action to take when continuation pattern is matched
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContinuationAction {
+    /// Retry the same step with another turn
+    Retry,
+    /// Route to a different step
+    Route,
+}
+
+/// How a prompt is rendered
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TemplateSyntax {
+    /// `{{ name }}` placeholders are filled in; everything else is left as
+    /// written
+    #[default]
+    Placeholders,
+    /// Rendered as a Jinja template (filters, conditions, loops)
+    Jinja,
+}
+
+/// Which pattern wins when an output matches both the termination and the
+/// continuation pattern
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Precedence {
+    /// The continuation is taken and the step goes on
+    #[default]
+    ContinuationFirst,
+    /// The step completes
+    SuccessFirst,
+    /// The step fails, as the output is ambiguous
+    Error,
+}
+
+impl Precedence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precedence::ContinuationFirst => "continuation_first",
+            Precedence::SuccessFirst => "success_first",
+            Precedence::Error => "error",
+        }
+    }
+}
+
+fn default_continuation_action() -> ContinuationAction {
+    ContinuationAction::Retry
+}
+
+impl PipelineConfig {
+    /// Load pipeline configuration from a YAML file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_file_with_overrides(path, &[])
+    }
+
+    /// Load pipeline configuration from a YAML file with `--set` overrides
+    ///
+    /// The pipelines of `pipeline:` steps are loaded too, relative to the file.
+    pub fn from_file_with_overrides<P: AsRef<Path>>(path: P, overrides: &[(String, String)]) -> Result<Self> {
+        Self::load_file(path.as_ref(), overrides, None, &mut Vec::new())
+    }
+
+    /// Load a pipeline file; `including` holds the files of the pipelines
+    /// that run it, so a file running itself is caught
+    fn load_file(
+        path: &Path,
+        overrides: &[(String, String)],
+        root: Option<&Path>,
+        including: &mut Vec<PathBuf>,
+    ) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let mut config = Self::parse(&content, overrides)?;
+        including.push(path.canonicalize()?);
+        let result = config.load_sub_pipelines(path.parent().unwrap_or(Path::new("")), root, including);
+        including.pop();
+        result.map(|_| config)
+    }
+
+    /// Load the pipelines of `pipeline:` steps, with files relative to
+    /// `base_dir` and, given a `root`, inside it
+    fn load_sub_pipelines(&mut self, base_dir: &Path, root: Option<&Path>, including: &mut Vec<PathBuf>) -> Result<()> {
+        let mut loaded: HashMap<PathBuf, Arc<PipelineConfig>> = HashMap::new();
+        for step in &mut self.steps {
+            let Some(sub_pipeline) = &mut step.pipeline else {
+                continue;
+            };
+            let path = base_dir.join(&sub_pipeline.file);
+            let file = path
+                .canonicalize()
+                .with_context(|| format!("Step '{}' pipeline {} cannot be read", step.id, path.display()))?;
+            if let Some(root) = root.filter(|root| !file.starts_with(root)) {
+                anyhow::bail!("Step '{}' pipeline {} is outside {}", step.id, path.display(), root.display());
+            }
+            if including.contains(&file) {
+                anyhow::bail!(
+                    "Step '{}' pipeline {} runs a pipeline that is already running it",
+                    step.id,
+                    path.display()
+                );
+            }
+            let config = match loaded.get(&file) {
+                Some(config) => config.clone(),
+                None => {
+                    let config = Self::load_file(&file, &[], root, including)
+                        .with_context(|| format!("Step '{}' pipeline {} could not be loaded", step.id, path.display()))?;
+                    loaded.entry(file).or_insert(Arc::new(config)).clone()
+                }
+            };
+            sub_pipeline.config = Some(config);
+        }
+        Ok(())
+    }
+
+    /// Parse pipeline configuration from YAML string
+    ///
+    /// Parsing runs nothing: command variables keep their `default` until
+    /// `resolve_command_variables` is called.
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        Self::from_yaml_with_overrides(yaml, &[])
+    }
+
+    /// Parse pipeline configuration from YAML string, overriding keys first
+    ///
+    /// Each override is a dotted path and a YAML value, e.g.
+    /// `steps.test.timeout_secs` and `600`. Steps (and other lists) are
+    /// addressed by `id` or by index. The result is validated as usual, and
+    /// the files of `pipeline:` steps are loaded relative to the current
+    /// directory.
+    pub fn from_yaml_with_overrides(yaml: &str, overrides: &[(String, String)]) -> Result<Self> {
+        let mut config = Self::parse(yaml, overrides)?;
+        config.load_sub_pipelines(Path::new(""), None, &mut Vec::new())?;
+        Ok(config)
+    }
+
+    /// Parse pipeline configuration from YAML string as if it were a file
+    /// in `dir`
+    ///
+    /// The files of `pipeline:` steps are loaded relative to `dir`, and they
+    /// (and the files their pipelines run) must be inside it.
+    pub fn from_yaml_in_dir(yaml: &str, dir: &Path) -> Result<Self> {
+        let dir = dir.canonicalize().with_context(|| format!("{} cannot be read", dir.display()))?;
+        let mut config = Self::parse(yaml, &[])?;
+        config.load_sub_pipelines(&dir, Some(&dir), &mut Vec::new())?;
+        Ok(config)
+    }
+
+    /// Parse and validate a pipeline
+    fn parse(yaml: &str, overrides: &[(String, String)]) -> Result<Self> {
+        let mut document: Value = serde_yaml::from_str(yaml)?;
+        for (path, value) in overrides {
+            set_path(&mut document, path, value)
+                .map_err(|e| anyhow::anyhow!("Cannot set {}: {}", path, e))?;
+        }
+        let mut config: PipelineConfig = serde_yaml::from_value(document)?;
+
+        // Unknown keys are dropped when parsing, so a misspelled override
+        // would otherwise be ignored silently
+        if !overrides.is_empty() {
+            let parsed = serde_yaml::to_value(&config)?;
+            for (path, _) in overrides {
+                if get_path(&parsed, path).is_none() {
+                    anyhow::bail!("Cannot set {}: not a pipeline setting", path);
+                }
+            }
+        }
+
+        config.expand_matrices()?;
+        config.validate()?;
+        config.warnings = config_warnings::check(&config);
+        Ok(config)
+    }
+
+    /// Replace each step with a `matrix:` by one instance per combination
+    /// of its values
+    ///
+    /// Instances are named `<id>-<value>...` (values in key order) and get
+    /// `{{ matrix.<key> }}` filled in in their prompt, name and `when:`.
+    /// Steps that depend on a matrix step depend on all of its instances.
+    pub fn expand_matrices(&mut self) -> Result<()> {
+        let mut instances: HashMap<String, Vec<String>> = HashMap::new();
+        let mut steps = Vec::with_capacity(self.steps.len());
+
+        for step in std::mem::take(&mut self.steps) {
+            if step.matrix.is_empty() {
+                steps.push(step);
+                continue;
+            }
+
+            let mut combinations: Vec<Vec<(String, String)>> = vec![Vec::new()];
+            for (key, values) in &step.matrix {
+                if values.is_empty() {
+                    anyhow::bail!("Step '{}' matrix '{}' needs at least one value", step.id, key);
+                }
+                let values = values
+                    .iter()
+                    .map(|value| match value {
+                        Value::String(s) => Ok(s.clone()),
+                        Value::Number(n) => Ok(n.to_string()),
+                        Value::Bool(b) => Ok(b.to_string()),
+                        _ => Err(anyhow::anyhow!(
+                            "Step '{}' matrix '{}' values must be strings, numbers or booleans",
+                            step.id,
+                            key
+                        )),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                combinations = combinations
+                    .into_iter()
+                    .flat_map(|combination| {
+                        values.iter().map(move |value| {
+                            let mut combination = combination.clone();
+                            combination.push((key.clone(), value.clone()));
+                            combination
+                        })
+                    })
+                    .collect();
+            }
+
+            let ids = instances.entry(step.id.clone()).or_default();
+            for combination in combinations {
+                let variables: HashMap<String, String> = combination
+                    .iter()
+                    .map(|(key, value)| (format!("matrix.{}", key), value.clone()))
+                    .collect();
+                let render = |text: &str| crate::core::substitute_variables(text, &variables).text;
+                let values: Vec<&str> = combination.iter().map(|(_, value)| value.as_str()).collect();
+
+                let mut instance = step.clone();
+                instance.id = format!("{}-{}", step.id, instance_suffix(&values));
+                instance.name = format!("{} ({})", render(&step.name), values.join(", "));
+                instance.prompt = render(&step.prompt);
+                instance.when = step.when.as_deref().map(render);
+                instance.matrix = BTreeMap::new();
+                ids.push(instance.id.clone());
+                steps.push(instance);
+            }
+        }
+
+        if instances.is_empty() {
+            self.steps = steps;
+            return Ok(());
+        }
 
-/// Termination condition configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TerminationConfig {
-    /// Pattern that signals successful completion
-    pub success_pattern: String,
+        for step in &mut steps {
+            let expand = |deps: &[String]| -> Vec<String> {
+                deps.iter()
+                    .flat_map(|dep| instances.get(dep).cloned().unwrap_or_else(|| vec![dep.clone()]))
+                    .collect()
+            };
+            step.depends_on = expand(&step.depends_on);
+            step.depends_on_any = expand(&step.depends_on_any);
+
+            // A route has to name one instance; which one is not for us to guess
+            let targets = step
+                .termination
+                .iter()
+                .flat_map(|t| t.on_success.iter().chain(&t.on_failure))
+                .chain(step.continuation.iter().flat_map(|c| &c.target));
+            for target in targets {
+                if let Some(ids) = instances.get(target) {
+                    anyhow::bail!(
+                        "Step '{}' routes to matrix step '{}'; route to one of its instances ({})",
+                        step.id,
+                        target,
+                        ids.join(", ")
+                    );
+                }
+            }
+        }
 
-    /// Which step to execute on success (null = end pipeline)
-    #[serde(default)]
-    pub on_success: Option<String>,
+        self.steps = steps;
+        Ok(())
+    }
 
-    /// Which step to execute on failure/rejection
-    #[serde(default)]
-    pub on_failure: Option<String>,
+    /// Cut a standalone pipeline out of a YAML file: the given steps and
+    /// everything they depend on
+    ///
+    /// Works on the document rather than the parsed config, so fields keep
+    /// the form they were written in. Routes to steps left out are dropped,
+    /// `{{ steps.<id>.* }}` references to them become stub variables, and
+    /// outputs and success criteria naming them are removed. Commands are
+    /// not run; the result is validated before it is returned.
+    pub fn extract(yaml: &str, step_ids: &[String]) -> Result<String> {
+        let config: PipelineConfig = serde_yaml::from_str(yaml)?;
+        let depends_on: HashMap<&str, Vec<&String>> = config
+            .steps
+            .iter()
+            .map(|step| (step.id.as_str(), step.depends_on.iter().chain(&step.depends_on_any).collect()))
+            .collect();
+
+        let mut keep = std::collections::HashSet::new();
+        let mut pending: Vec<&str> = Vec::new();
+        for id in step_ids {
+            if !depends_on.contains_key(id.as_str()) {
+                anyhow::bail!("No step '{}' in pipeline '{}'", id, config.name);
+            }
+            pending.push(id);
+        }
+        while let Some(id) = pending.pop() {
+            if keep.insert(id.to_string()) {
+                pending.extend(depends_on.get(id).into_iter().flat_map(|deps| deps.iter().map(|dep| dep.as_str())));
+            }
+        }
 
-    /// Whether to use regex pattern matching
-    #[serde(default)]
-    pub use_regex: bool,
-}
+        let placeholder = regex::Regex::new(r"\{\{ (steps\.([^{}\s.]+)\.[^{}\s]+) \}\}").unwrap();
+        let mut stubs = BTreeMap::new();
+        let mut document: Value = serde_yaml::from_str(yaml)?;
 
-/// Continuation condition configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ContinuationConfig {
-    /// Pattern that signals "not done, continue"
-    pub pattern: String,
+        if let Some(Value::Sequence(steps)) = document.get_mut("steps") {
+            steps.retain(|step| step.get("id").and_then(Value::as_str).is_some_and(|id| keep.contains(id)));
+            for step in steps.iter_mut() {
+                let Value::Mapping(step) = step else { continue };
 
-    /// Action to take: "retry" (same step) or "route" (different step)
-    #[serde(default = "default_continuation_action")]
-    pub action: ContinuationAction,
+                if let Some(Value::Mapping(termination)) = step.get_mut("termination") {
+                    for key in ["on_success", "on_failure"] {
+                        let removed = termination.get(key).and_then(Value::as_str).is_some_and(|t| !keep.contains(t));
+                        if removed {
+                            termination.remove(key);
+                        }
+                    }
+                }
+                let continuation_removed = step
+                    .get("continuation")
+                    .and_then(|c| c.get("target"))
+                    .and_then(Value::as_str)
+                    .is_some_and(|target| !keep.contains(target));
+                if continuation_removed {
+                    step.remove("continuation");
+                }
 
-    /// Target step ID when action is "route"
-    #[serde(default)]
-    pub target: Option<String>,
+                for key in ["prompt", "when"] {
+                    let text = step.get(key).and_then(Value::as_str).unwrap_or_default();
+                    for caps in placeholder.captures_iter(text) {
+                        if !keep.contains(&caps[2]) {
+                            stubs.insert(
+                                caps[1].to_string(),
+                                format!("(stub: step '{}' is not part of this extract)", &caps[2]),
+                            );
+                        }
+                    }
+                }
+            }
+        }
 
-    /// Whether to pass notes/context when routing
-    #[serde(default)]
-    pub carry_notes: bool,
+        if let Value::Mapping(root) = &mut document {
+            if !stubs.is_empty() {
+                let variables = root
+                    .entry(Value::String("variables".to_string()))
+                    .or_insert_with(|| Value::Mapping(Default::default()));
+                if variables.is_null() {
+                    *variables = Value::Mapping(Default::default());
+                }
+                if let Value::Mapping(variables) = variables {
+                    for (name, text) in stubs {
+                        variables.insert(Value::String(name), Value::String(text));
+                    }
+                }
+            }
 
-    /// Whether to use regex pattern matching
-    #[serde(default)]
-    pub use_regex: bool,
-}
+            let success_removed = config
+                .success
+                .as_ref()
+                .is_some_and(|success| success.step_ids().iter().any(|id| !keep.contains(*id)));
+            if success_removed {
+                root.remove("success");
+            }
 
-/// Action to take when continuation pattern is matched
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
-pub enum ContinuationAction {
-    /// Retry the same step with another turn
-    Retry,
-    /// Route to a different step
-    Route,
-}
+            if let Some(Value::Mapping(outputs)) = root.get_mut("outputs") {
+                outputs.retain(|_, template| {
+                    let template = template.as_str().unwrap_or_default();
+                    placeholder.captures_iter(template).all(|caps| keep.contains(&caps[2]))
+                });
+                if outputs.is_empty() {
+                    root.remove("outputs");
+                }
+            }
 
-fn default_continuation_action() -> ContinuationAction {
-    ContinuationAction::Retry
-}
+            let name = format!("{} (extract: {})", config.name, step_ids.join(", "));
+            root.insert(Value::String("name".to_string()), Value::String(name));
+        }
 
-impl PipelineConfig {
-    /// Load pipeline configuration from a YAML file
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        Self::from_yaml(&content)
+        let mut extracted: PipelineConfig = serde_yaml::from_value(document.clone())?;
+        extracted.expand_matrices()?;
+        extracted.validate()?;
+        Ok(serde_yaml::to_string(&document)?)
     }
 
-    /// Parse pipeline configuration from YAML string
-    pub fn from_yaml(yaml: &str) -> Result<Self> {
-        let config: PipelineConfig = serde_yaml::from_str(yaml)?;
-        config.validate()?;
-        Ok(config)
+    /// Run command variables and capture their output, here and in the
+    /// pipelines of `pipeline:` steps
+    ///
+    /// Loading a config never runs them, so this is called once when a run
+    /// starts. A failing command (non-zero exit, timeout) falls back to the
+    /// variable's `default`, or fails if it has none.
+    pub fn resolve_command_variables(&mut self) -> Result<()> {
+        for step in &mut self.steps {
+            if let Some(config) = step.pipeline.as_mut().and_then(|sub_pipeline| sub_pipeline.config.as_mut()) {
+                Arc::make_mut(config)
+                    .resolve_command_variables()
+                    .with_context(|| format!("Step '{}' pipeline", step.id))?;
+            }
+        }
+
+        let mut commands: Vec<_> = self
+            .get_variables()
+            .into_iter()
+            .filter_map(|(name, definition)| match definition {
+                VariableDefinition::Command { command, timeout_secs, default } => {
+                    Some((name, command, timeout_secs, default))
+                }
+                _ => None,
+            })
+            .collect();
+        commands.sort();
+
+        for (name, command, timeout_secs, default) in commands {
+            let value = match run_variable_command(&command, std::time::Duration::from_secs(timeout_secs)) {
+                Ok(output) => output,
+                Err(e) => match default {
+                    Some(default) => {
+                        tracing::warn!("Variable '{}' command `{}` failed ({}), using default", name, command, e);
+                        default
+                    }
+                    None => anyhow::bail!("Variable '{}' command `{}` failed: {}", name, command, e),
+                },
+            };
+            self.command_outputs.insert(name, value);
+        }
+
+        Ok(())
     }
 
     /// Validate the pipeline configuration
     pub fn validate(&self) -> Result<()> {
+        for code in &self.suppress_warnings {
+            if !config_warnings::is_known(code) {
+                anyhow::bail!("Unknown warning code '{}' in suppress_warnings", code);
+            }
+        }
+
         // Check that all step IDs are unique
         let mut seen_ids = std::collections::HashSet::new();
         for step in &self.steps {
+            // Step IDs name the files of a run (outputs, logs, artifacts)
+            if !is_valid_name(&step.id) {
+                anyhow::bail!(
+                    "Step ID '{}' may only contain letters, digits, '-', '_' and '.', and may not start with '.'",
+                    step.id
+                );
+            }
             if !seen_ids.insert(&step.id) {
                 anyhow::bail!("Duplicate step ID: {}", step.id);
             }
@@ -184,6 +1447,18 @@ impl PipelineConfig {
                     );
                 }
             }
+            for dep in &step.depends_on_any {
+                if !step_ids.contains(dep) {
+                    anyhow::bail!("Step '{}' depends_on_any names non-existent step '{}'", step.id, dep);
+                }
+                if step.depends_on.contains(dep) {
+                    anyhow::bail!(
+                        "Step '{}' lists '{}' under both depends_on and depends_on_any",
+                        step.id,
+                        dep
+                    );
+                }
+            }
 
             // Validate termination targets
             if let Some(termination) = &step.termination {
@@ -229,23 +1504,353 @@ impl PipelineConfig {
             }
         }
 
-        // Validate file existence for variables with validate_exists: true
-        for (var_name, var_def) in self.get_variables() {
-            if let VariableDefinition::File { path, validate_exists } = &var_def {
-                if *validate_exists {
-                    let path_obj = std::path::Path::new(path);
-                    if !path_obj.exists() {
-                        anyhow::bail!(
-                            "Variable '{}' references file that doesn't exist: {}",
-                            var_name,
-                            path
-                        );
+        if let Some(concurrency) = &self.concurrency {
+            if concurrency.group.trim().is_empty() {
+                anyhow::bail!("concurrency.group must not be empty");
+            }
+        }
+
+        if self.max_parallel == Some(0) {
+            anyhow::bail!("max_parallel must be at least 1");
+        }
+
+        // Validate poll, pipeline and queue steps (and that every other step has a prompt)
+        for step in &self.steps {
+            let kinds: Vec<&str> = [
+                step.poll.as_ref().map(|_| "`poll:`"),
+                step.pipeline.as_ref().map(|_| "`pipeline:`"),
+                step.queue.as_ref().map(|_| "`queue:`"),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            if kinds.len() > 1 {
+                anyhow::bail!("Step '{}' can only have one of {}", step.id, kinds.join(", "));
+            }
+            if step.pipeline.as_ref().is_some_and(|pipeline| pipeline.file.trim().is_empty()) {
+                anyhow::bail!("Step '{}' pipeline needs a `file`", step.id);
+            }
+            match &step.poll {
+                Some(poll) => match poll.condition() {
+                    None => anyhow::bail!(
+                        "Step '{}' poll needs exactly one of `command` or `http`",
+                        step.id
+                    ),
+                    Some(WaitCondition::Http(url)) if !url.starts_with("http://") => anyhow::bail!(
+                        "Step '{}' poll http '{}' must be an http:// URL (use `command: curl -fsS ...` for https)",
+                        step.id,
+                        url
+                    ),
+                    Some(_) if poll.interval_secs == Some(0) => anyhow::bail!(
+                        "Step '{}' poll interval_secs must be at least 1",
+                        step.id
+                    ),
+                    Some(_) => {}
+                },
+                None if step.prompt.trim().is_empty() && step.pipeline.is_none() && step.queue.is_none() => {
+                    anyhow::bail!("Step '{}' needs a prompt (or a `poll:` check, `pipeline:` or `queue:`)", step.id)
+                }
+                None => {}
+            }
+            if let Some(queue) = &step.queue {
+                match (&queue.push, &queue.pop) {
+                    (Some(name), None) | (None, Some(name)) if name.trim().is_empty() => {
+                        anyhow::bail!("Step '{}' queue name must not be empty", step.id)
+                    }
+                    (Some(_), None) if queue.items.is_none() => {
+                        anyhow::bail!("Step '{}' queue push needs `items`", step.id)
+                    }
+                    (Some(_), None) if queue.batch.is_some() => {
+                        anyhow::bail!("Step '{}' queue `batch` only applies to `pop`", step.id)
                     }
+                    (None, Some(_)) if queue.items.is_some() => {
+                        anyhow::bail!("Step '{}' queue `items` only applies to `push`", step.id)
+                    }
+                    (None, Some(_)) if queue.batch == Some(0) => {
+                        anyhow::bail!("Step '{}' queue batch must be at least 1", step.id)
+                    }
+                    (Some(_), None) | (None, Some(_)) => {}
+                    _ => anyhow::bail!("Step '{}' queue needs exactly one of `push` or `pop`", step.id),
+                }
+            }
+            if step.template.or(self.template) == Some(TemplateSyntax::Jinja) {
+                if let Err(problem) = crate::core::template::check(&step.prompt) {
+                    anyhow::bail!("Step '{}' prompt is not a valid template: {}", step.id, problem);
+                }
+            }
+        }
+
+        // Validate wait_for conditions
+        for step in &self.steps {
+            for condition in &step.wait_for {
+                match condition {
+                    WaitCondition::Port(address) if !address.contains(':') => anyhow::bail!(
+                        "Step '{}' wait_for port '{}' must be host:port",
+                        step.id,
+                        address
+                    ),
+                    WaitCondition::Http(url) if !url.starts_with("http://") => anyhow::bail!(
+                        "Step '{}' wait_for http '{}' must be an http:// URL (use `command: curl -fsS ...` for https)",
+                        step.id,
+                        url
+                    ),
+                    _ => {}
+                }
+            }
+        }
+
+        // Validate path locks
+        for step in &self.steps {
+            for lock in &step.locks {
+                if let Err(e) = crate::core::locks::validate(lock) {
+                    anyhow::bail!("Step '{}' lock '{}' is invalid: {}", step.id, lock, e);
+                }
+            }
+        }
+
+        // Validate artifact globs
+        for step in &self.steps {
+            for pattern in &step.artifacts {
+                if pattern.trim().is_empty() {
+                    anyhow::bail!("Step '{}' has an empty artifact pattern", step.id);
+                }
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    anyhow::bail!("Step '{}' artifact '{}' is invalid: {}", step.id, pattern, e);
+                }
+            }
+        }
+
+        // Validate input globs
+        for step in &self.steps {
+            for pattern in step.inputs.iter().flat_map(|inputs| &inputs.files) {
+                if pattern.trim().is_empty() {
+                    anyhow::bail!("Step '{}' has an empty input pattern", step.id);
+                }
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    anyhow::bail!("Step '{}' input '{}' is invalid: {}", step.id, pattern, e);
+                }
+            }
+        }
+
+        // Validate `when:` conditions
+        for step in &self.steps {
+            if let Some(when) = &step.when {
+                if let Err(e) = crate::core::condition::When::parse(when) {
+                    anyhow::bail!("Step '{}' when condition is invalid: {}", step.id, e);
+                }
+            }
+        }
+
+        // Validate webhooks
+        for webhook in &self.webhooks {
+            if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+                anyhow::bail!("Webhook URL '{}' must start with http:// or https://", webhook.url);
+            }
+            if webhook.secret_env.as_deref().is_some_and(|name| name.trim().is_empty()) {
+                anyhow::bail!("Webhook '{}' secret_env must not be empty", webhook.url);
+            }
+        }
+
+        // Validate agent profiles
+        for step in &self.steps {
+            if step.agent.is_some() && !step.agents.is_empty() {
+                anyhow::bail!(
+                    "Step '{}' sets both agent and agents; use one or the other",
+                    step.id
+                );
+            }
+            for agent in &step.agents {
+                if !self.agents.contains_key(agent) {
+                    anyhow::bail!(
+                        "Step '{}' references unknown agent profile '{}'",
+                        step.id,
+                        agent
+                    );
+                }
+            }
+            if step.failover_after == Some(0) {
+                anyhow::bail!("Step '{}' failover_after must be at least 1", step.id);
+            }
+            if step.heartbeat_secs.or(self.heartbeat_secs) == Some(0) {
+                anyhow::bail!("Step '{}' heartbeat_secs must be at least 1", step.id);
+            }
+        }
+
+        if self.max_duration_secs == Some(0) {
+            anyhow::bail!("max_duration_secs must be at least 1");
+        }
+        if self.logs.max_bytes == Some(0) {
+            anyhow::bail!("logs.max_bytes must be at least 1");
+        }
+        if self.logs.retention == Some(0) {
+            anyhow::bail!("logs.retention must be at least 1");
+        }
+
+        // Validate budgets
+        for (category, limit) in &self.budgets {
+            if !limit.is_finite() || *limit < 0.0 {
+                anyhow::bail!("Budget '{}' must be a non-negative amount", category);
+            }
+        }
+        for step in &self.steps {
+            if let Some(category) = &step.budget_category {
+                if !self.budgets.contains_key(category) {
+                    anyhow::bail!(
+                        "Step '{}' references unknown budget category '{}'",
+                        step.id,
+                        category
+                    );
+                }
+            }
+        }
+
+        // Validate output matchers
+        for step in &self.steps {
+            let mut matchers = Vec::new();
+            if let Some(termination) = &step.termination {
+                if termination.success_when.is_some() && !termination.success_pattern.is_empty() {
+                    anyhow::bail!(
+                        "Step '{}' termination sets both success_pattern and success_when",
+                        step.id
+                    );
+                }
+                matchers.extend(termination.success_when.as_ref().map(|m| ("success_when", m)));
+                if termination.use_regex {
+                    if let Err(e) = crate::core::condition::compile_regex(&termination.success_pattern) {
+                        anyhow::bail!("Step '{}' termination success_pattern has an invalid regex '{}': {}", step.id, e.pattern, e.problem);
+                    }
+                }
+            }
+            if let Some(continuation) = &step.continuation {
+                match (&continuation.when, continuation.pattern.is_empty()) {
+                    (Some(_), false) => anyhow::bail!(
+                        "Step '{}' continuation sets both pattern and when",
+                        step.id
+                    ),
+                    (None, true) => anyhow::bail!(
+                        "Step '{}' continuation needs a pattern or a when matcher",
+                        step.id
+                    ),
+                    _ => {}
+                }
+                matchers.extend(continuation.when.as_ref().map(|m| ("continuation when", m)));
+                if continuation.use_regex && continuation.when.is_none() {
+                    if let Err(e) = crate::core::condition::compile_regex(&continuation.pattern) {
+                        anyhow::bail!("Step '{}' continuation pattern has an invalid regex '{}': {}", step.id, e.pattern, e.problem);
+                    }
+                }
+            }
+            if let Some(poll) = &step.poll {
+                matchers.extend(poll.until.as_ref().map(|m| ("poll until", m)));
+            }
+            for (field, matcher) in matchers {
+                if let Err(e) = crate::core::condition::Matcher::from_config(matcher) {
+                    anyhow::bail!("Step '{}' {} has an invalid regex '{}': {}", step.id, field, e.pattern, e.problem);
+                }
+            }
+        }
+
+        // Validate follow-up patterns
+        for step in &self.steps {
+            for followup in &step.followups {
+                if followup.pattern.is_empty() {
+                    anyhow::bail!("Step '{}' has a follow-up with an empty pattern", step.id);
+                }
+                if followup.use_regex {
+                    if let Err(e) = crate::core::condition::compile_regex(&followup.pattern) {
+                        anyhow::bail!("Step '{}' follow-up '{}' has an invalid regex: {}", step.id, e.pattern, e.problem);
+                    }
+                }
+            }
+        }
+
+        // Validate environment variable names
+        let env_names = self.env.keys().chain(self.env_passthrough.iter().flatten()).map(|name| (None, name));
+        let step_env_names = self.steps.iter().flat_map(|step| {
+            step.env
+                .keys()
+                .chain(step.env_passthrough.iter().flatten())
+                .map(move |name| (Some(&step.id), name))
+        });
+        for (step_id, name) in env_names.chain(step_env_names) {
+            if name.is_empty() || name.contains(['=', '\0']) {
+                match step_id {
+                    Some(id) => anyhow::bail!("Step '{}' has an invalid environment variable name '{}'", id, name),
+                    None => anyhow::bail!("Invalid environment variable name '{}'", name),
+                }
+            }
+        }
+
+        // Validate remember patterns
+        for step in &self.steps {
+            for (key, pattern) in &step.remember {
+                if let Err(e) = crate::core::condition::compile_regex(pattern) {
+                    anyhow::bail!("Step '{}' remember '{}' has an invalid regex '{}': {}", step.id, key, e.pattern, e.problem);
                 }
             }
         }
 
-        // Check for cycles in the dependency graph (only depends_on, not termination/continuation)
+        if let Some(success) = &self.success {
+            for step_id in success.step_ids() {
+                if !self.steps.iter().any(|s| s.id == step_id) {
+                    anyhow::bail!("success references non-existent step '{}'", step_id);
+                }
+            }
+        }
+
+        // Validate pipeline outputs: step references must name a step and,
+        // for `values.<key>`, one of its `remember` keys (a child pipeline's
+        // outputs are only known once it has run)
+        let output_name = regex::Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+        let step_reference = regex::Regex::new(r"\{\{ steps\.([^.{}\s]+)\.([^{}\s]+) \}\}").unwrap();
+        for (name, template) in &self.outputs {
+            if !output_name.is_match(name) {
+                anyhow::bail!("Output '{}' must be a name of letters, digits and underscores", name);
+            }
+            for caps in step_reference.captures_iter(template) {
+                let Some(step) = self.steps.iter().find(|s| s.id == caps[1]) else {
+                    anyhow::bail!("Output '{}' references non-existent step: {}", name, &caps[1]);
+                };
+                match caps[2].strip_prefix("values.") {
+                    Some(key) if !step.remember.contains_key(key) => anyhow::bail!(
+                        "Output '{}' references value '{}' that step '{}' does not remember",
+                        name,
+                        key,
+                        step.id
+                    ),
+                    Some(_) => {}
+                    None if &caps[2] == "output" => {}
+                    None if step.pipeline.is_some() && caps[2].starts_with("outputs.") => {}
+                    None => anyhow::bail!(
+                        "Output '{}' references unknown field '{}' of step '{}' (expected output, values.<key> or, for a `pipeline:` step, outputs.<name>)",
+                        name,
+                        &caps[2],
+                        step.id
+                    ),
+                }
+            }
+        }
+
+        // Files of file variables: existence (with validate_exists: true),
+        // size and readability, all reported at once
+        let mut files: Vec<FileCheck> = self
+            .get_variables()
+            .into_iter()
+            .filter_map(|(variable, definition)| match definition {
+                VariableDefinition::File { path, validate_exists, max_bytes } => Some(FileCheck {
+                    variable,
+                    path,
+                    validate_exists,
+                    max_bytes: max_bytes.unwrap_or(file_variables::DEFAULT_MAX_BYTES),
+                }),
+                _ => None,
+            })
+            .collect();
+        files.sort_by(|a, b| a.variable.cmp(&b.variable));
+        file_variables::check(&files)?;
+
+        // Check for cycles in the dependency graph (only depends_on and
+        // depends_on_any, not termination/continuation)
         self.check_cycles()?;
 
         Ok(())
@@ -253,7 +1858,8 @@ impl PipelineConfig {
 
     /// Check for cycles in the step dependency graph
     ///
-    /// Note: This only checks `depends_on` relationships for cycles.
+    /// Note: This only checks `depends_on` and `depends_on_any`
+    /// relationships for cycles.
     /// Cycles through termination/continuation targets are allowed
     /// as they are intentional (e.g., review → implementation loops).
     fn check_cycles(&self) -> Result<()> {
@@ -281,7 +1887,7 @@ impl PipelineConfig {
         if let Some(step) = self.steps.iter().find(|s| s.id == step_id) {
             // Check dependencies only (not termination/continuation targets)
             // as those can create intentional loops like review → implementation
-            for dep in &step.depends_on {
+            for dep in step.depends_on.iter().chain(&step.depends_on_any) {
                 if recursion_stack.contains(dep) {
                     anyhow::bail!("Cycle detected in dependency graph involving step '{}'", dep);
                 }
@@ -297,40 +1903,63 @@ impl PipelineConfig {
 
     /// Get variables as parsed VariableDefinition enum
     pub fn get_variables(&self) -> std::collections::HashMap<String, VariableDefinition> {
-        let mut vars = std::collections::HashMap::new();
+        self.variables
+            .keys()
+            .filter_map(|key| self.get_variable(key).map(|var_def| (key.clone(), var_def)))
+            .collect()
+    }
 
-        for (key, value) in &self.variables {
-            let var_def = match value {
-                Value::String(s) => VariableDefinition::String(s.clone()),
-                Value::Mapping(map) => {
-                    // Parse file variable: { path: "...", validate_exists: true/false }
-                    let path = map.get(&Value::String("path".to_string()))
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
+    /// Parse a single variable definition
+    fn get_variable(&self, key: &str) -> Option<VariableDefinition> {
+        let value = self.variables.get(key)?;
+
+        let var_def = match value {
+            Value::String(s) => VariableDefinition::String(s.clone()),
+            Value::Mapping(map) if map.contains_key("command") => {
+                // Parse command variable: { command: "...", timeout_secs: 10, default: "..." }
+                let field = |name: &str| map.get(name);
+                VariableDefinition::Command {
+                    command: field("command").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                    timeout_secs: field("timeout_secs")
+                        .and_then(|v| v.as_u64())
+                        .unwrap_or(DEFAULT_VARIABLE_COMMAND_TIMEOUT_SECS),
+                    default: field("default").and_then(|v| v.as_str()).map(str::to_string),
+                }
+            }
+            Value::Mapping(map) => {
+                // Parse file variable: { path: "...", validate_exists: true/false, max_bytes: N }
+                let path = map.get("path")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string();
 
-                    let validate_exists = map.get(&Value::String("validate_exists".to_string()))
-                        .and_then(|v| v.as_bool())
-                        .unwrap_or(false);
+                let validate_exists = map.get("validate_exists")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(false);
 
-                    VariableDefinition::File { path, validate_exists }
-                }
-                _ => {
-                    // Fallback: convert to string
-                    VariableDefinition::String(serde_yaml::to_string(value).unwrap_or_default())
-                }
-            };
-            vars.insert(key.clone(), var_def);
-        }
+                let max_bytes = map.get("max_bytes").and_then(|v| v.as_u64());
+
+                VariableDefinition::File { path, validate_exists, max_bytes }
+            }
+            _ => {
+                // Fallback: convert to string
+                VariableDefinition::String(serde_yaml::to_string(value).unwrap_or_default())
+            }
+        };
 
-        vars
+        Some(var_def)
     }
 
     /// Get variables as string map (for backward compatibility)
+    ///
+    /// Command variables take their resolved output, else their `default`.
     pub fn variables_as_string_map(&self) -> std::collections::HashMap<String, String> {
         self.get_variables()
             .iter()
-            .map(|(k, v)| (k.clone(), v.render_value()))
+            .map(|(k, v)| {
+                let value = self.command_outputs.get(k).cloned().unwrap_or_else(|| v.render_value());
+                (k.clone(), value)
+            })
             .collect()
     }
 
@@ -338,6 +1967,25 @@ impl PipelineConfig {
     pub fn to_pipeline(&self) -> Pipeline {
         Pipeline::from_config(self)
     }
+
+    /// The configuration as JSON, with each step's `effective` retry limit
+    /// and timeout: its own, else the pipeline's default, else the built-in one
+    pub fn to_json_with_effective_settings(&self) -> serde_json::Result<serde_json::Value> {
+        let mut json = serde_json::to_value(self)?;
+        let pipeline = self.to_pipeline();
+        if let Some(steps) = json.get_mut("steps").and_then(|steps| steps.as_array_mut()) {
+            for step_json in steps {
+                let id = step_json.get("id").and_then(|id| id.as_str()).unwrap_or_default();
+                if let Some(step) = pipeline.step(id) {
+                    step_json["effective"] = serde_json::json!({
+                        "max_retries": step.max_retries,
+                        "timeout_secs": step.timeout_secs,
+                    });
+                }
+            }
+        }
+        Ok(json)
+    }
 }
 
 #[cfg(test)]
@@ -380,6 +2028,9 @@ steps: []
             VariableDefinition::File { .. } => {
                 panic!("Expected String variable, got File");
             }
+            VariableDefinition::Command { .. } => {
+                panic!("Expected String variable, got Command");
+            }
         }
     }
 
@@ -400,13 +2051,16 @@ steps: []
         assert!(var.is_some(), "Variable should exist");
 
         match var.unwrap() {
-            VariableDefinition::File { path, validate_exists } => {
+            VariableDefinition::File { path, validate_exists, .. } => {
                 assert_eq!(path, "README.md");
-                assert_eq!(*validate_exists, false, "Should default to false");
+                assert!(!*validate_exists, "Should default to false");
             }
             VariableDefinition::String(_) => {
                 panic!("Expected File variable, got String");
             }
+            VariableDefinition::Command { .. } => {
+                panic!("Expected File variable, got Command");
+            }
         }
     }
 
@@ -429,13 +2083,16 @@ steps: []
         assert!(var.is_some(), "Variable should exist");
 
         match var.unwrap() {
-            VariableDefinition::File { path, validate_exists } => {
+            VariableDefinition::File { path, validate_exists, .. } => {
                 assert_eq!(path, "docs/spec.md");
-                assert_eq!(*validate_exists, true);
+                assert!(*validate_exists);
             }
             VariableDefinition::String(_) => {
                 panic!("Expected File variable, got String");
             }
+            VariableDefinition::Command { .. } => {
+                panic!("Expected File variable, got Command");
+            }
         }
     }
 
@@ -457,13 +2114,16 @@ steps: []
         assert!(var.is_some(), "Variable should exist");
 
         match var.unwrap() {
-            VariableDefinition::File { path, validate_exists } => {
+            VariableDefinition::File { path, validate_exists, .. } => {
                 assert_eq!(path, "./dist/bundle.js");
-                assert_eq!(*validate_exists, false);
+                assert!(!*validate_exists);
             }
             VariableDefinition::String(_) => {
                 panic!("Expected File variable, got String");
             }
+            VariableDefinition::Command { .. } => {
+                panic!("Expected File variable, got Command");
+            }
         }
     }
 
@@ -539,7 +2199,7 @@ steps: []
 "#;
 
         // Parse without validation using serde_yaml directly
-        let config: PipelineConfig = serde_yaml::from_str(&yaml).unwrap();
+        let config: PipelineConfig = serde_yaml::from_str(yaml).unwrap();
         let result = config.validate();
         assert!(result.is_err(), "Validation should fail when file doesn't exist");
         let error_msg = result.unwrap_err().to_string();
@@ -558,7 +2218,7 @@ variables:
 steps: []
 "#;
 
-        let config: PipelineConfig = serde_yaml::from_str(&yaml).unwrap();
+        let config: PipelineConfig = serde_yaml::from_str(yaml).unwrap();
         config.validate().expect("Validation should pass when validate_exists is false");
     }
 
@@ -572,7 +2232,7 @@ variables:
 steps: []
 "#;
 
-        let config = PipelineConfig::from_yaml(&yaml).unwrap();
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
         config.validate().expect("Validation should pass for string variables");
     }
 
@@ -642,6 +2302,68 @@ steps:
         // assert_eq!(config.variables.get("feature_name"), Some(&"test feature".to_string()));
     }
 
+    #[test]
+    fn test_parse_locking() {
+        let yaml = r#"
+name: "Test Pipeline"
+locking: pipeline
+on_lock_conflict: wait
+steps: []
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.locking, LockScope::Pipeline);
+        assert_eq!(config.on_lock_conflict, LockConflictPolicy::Wait);
+
+        let config = PipelineConfig::from_yaml("name: \"Test\"\non_lock_conflict: queue\nsteps: []\n").unwrap();
+        assert_eq!(config.on_lock_conflict, LockConflictPolicy::Queue);
+
+        let config = PipelineConfig::from_yaml("name: \"Test\"\nsteps: []\n").unwrap();
+        assert_eq!(config.locking, LockScope::None);
+        assert_eq!(config.on_lock_conflict, LockConflictPolicy::Fail);
+    }
+
+    #[test]
+    fn test_step_settings_fall_back_to_pipeline_then_built_in_defaults() {
+        let yaml = r#"
+name: "Test Pipeline"
+max_retries: 5
+default_timeout_secs: 600
+steps:
+  - id: "own"
+    name: "Own"
+    prompt: "Test"
+    max_retries: 0
+    timeout_secs: 60
+  - id: "inherited"
+    name: "Inherited"
+    prompt: "Test"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let pipeline = config.to_pipeline();
+        let settings = |id: &str| {
+            let step = pipeline.step(id).unwrap();
+            (step.max_retries, step.timeout_secs)
+        };
+        assert_eq!(settings("own"), (0, 60));
+        assert_eq!(settings("inherited"), (5, 600));
+
+        let bare = yaml.replace("max_retries: 5\ndefault_timeout_secs: 600\n", "");
+        let pipeline = PipelineConfig::from_yaml(&bare).unwrap().to_pipeline();
+        let step = pipeline.step("inherited").unwrap();
+        assert_eq!(
+            (step.max_retries, step.timeout_secs),
+            (crate::core::step::DEFAULT_MAX_RETRIES, crate::core::step::DEFAULT_TIMEOUT_SECS)
+        );
+
+        // `validate --json` reports what each step runs with
+        let json = config.to_json_with_effective_settings().unwrap();
+        assert_eq!(json["steps"][0]["effective"], serde_json::json!({ "max_retries": 0, "timeout_secs": 60 }));
+        assert_eq!(json["steps"][1]["max_retries"], serde_json::Value::Null);
+        assert_eq!(json["steps"][1]["effective"], serde_json::json!({ "max_retries": 5, "timeout_secs": 600 }));
+    }
+
     #[test]
     fn test_duplicate_step_id_fails() {
         let yaml = r#"
@@ -658,6 +2380,17 @@ steps:
         assert!(PipelineConfig::from_yaml(yaml).is_err());
     }
 
+    #[test]
+    fn test_step_ids_must_be_file_names() {
+        let yaml = |id: &str| format!("name: \"Test\"\nsteps:\n  - id: \"{}\"\n    name: \"A\"\n    prompt: \"Go\"\n", id);
+
+        assert!(PipelineConfig::from_yaml(&yaml("build-1.fast_path")).is_ok());
+        for id in ["../../x", "a/b", "a\\\\b", ".hidden", "", "with space"] {
+            let err = PipelineConfig::from_yaml(&yaml(id)).unwrap_err().to_string();
+            assert!(err.contains("may only contain letters, digits"), "{}: {}", id, err);
+        }
+    }
+
     #[test]
     fn test_invalid_dependency_fails() {
         let yaml = r#"
@@ -671,4 +2404,708 @@ steps:
 
         assert!(PipelineConfig::from_yaml(yaml).is_err());
     }
+
+    #[test]
+    fn test_matrix_expands_into_instances() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "test"
+    name: "Test {{ matrix.language }}"
+    prompt: "Run the {{ matrix.language }} tests on {{ matrix.python }} for {{ target }}"
+    matrix:
+      language: [rust, go]
+      python: [3.12]
+  - id: "report"
+    name: "Report"
+    prompt: "Summarize"
+    depends_on: ["test"]
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let ids: Vec<_> = config.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["test-rust-3-12", "test-go-3-12", "report"]);
+        assert_eq!(config.steps[0].name, "Test rust (rust, 3.12)");
+        assert_eq!(config.steps[1].prompt, "Run the go tests on 3.12 for {{ target }}");
+        assert!(config.steps[0].matrix.is_empty());
+        assert_eq!(config.steps[2].depends_on, vec!["test-rust-3-12", "test-go-3-12"]);
+
+        let routed = yaml.replace(
+            "    depends_on: [\"test\"]\n",
+            "    termination:\n      success_pattern: \"DONE\"\n      on_failure: \"test\"\n",
+        );
+        let error = PipelineConfig::from_yaml(&routed).unwrap_err().to_string();
+        assert!(error.contains("routes to matrix step 'test'"), "{}", error);
+
+        let empty = yaml.replace("[3.12]", "[]");
+        assert!(PipelineConfig::from_yaml(&empty).is_err());
+    }
+
+    #[test]
+    fn test_extract_keeps_steps_and_their_dependencies() {
+        let yaml = r#"
+name: "Release"
+steps:
+  - id: "triage"
+    name: "Triage"
+    prompt: "Triage the issues"
+  - id: "build"
+    name: "Build"
+    prompt: "Build it"
+  - id: "test"
+    name: "Test"
+    prompt: "Test the build, triage said: {{ steps.triage.output }}"
+    depends_on: ["build"]
+    termination:
+      success_pattern: "PASS"
+      on_failure: "fix"
+  - id: "fix"
+    name: "Fix"
+    prompt: "Fix it"
+    depends_on: ["test"]
+outputs:
+  tested: "{{ steps.test.output }}"
+  fixed: "{{ steps.fix.output }}"
+success:
+  completed: fix
+"#;
+
+        let extracted = PipelineConfig::extract(yaml, &["test".to_string()]).unwrap();
+        let config = PipelineConfig::from_yaml(&extracted).unwrap();
+        assert_eq!(config.name, "Release (extract: test)");
+        let ids: Vec<_> = config.steps.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["build", "test"]);
+        assert!(config.steps[1].termination.as_ref().unwrap().on_failure.is_none());
+        assert!(config.variables_as_string_map()["steps.triage.output"].contains("stub"));
+        assert_eq!(config.outputs.keys().collect::<Vec<_>>(), vec!["tested"]);
+        assert!(config.success.is_none());
+
+        let error = PipelineConfig::extract(yaml, &["deploy".to_string()]).unwrap_err();
+        assert!(error.to_string().contains("No step 'deploy'"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_when_condition_fails() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Test"
+    when: "{{ env }} == prod"
+"#;
+
+        let error = PipelineConfig::from_yaml(yaml).unwrap_err().to_string();
+        assert!(error.contains("when condition"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_env_names_fail() {
+        let yaml = r#"
+name: "Test Pipeline"
+env:
+  "A=B": "x"
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Test"
+"#;
+        let error = PipelineConfig::from_yaml(yaml).unwrap_err().to_string();
+        assert!(error.contains("Invalid environment variable name 'A=B'"), "{}", error);
+
+        let yaml = yaml.replace("env:\n  \"A=B\": \"x\"\n", "").replace("    prompt: \"Test\"", "    prompt: \"Test\"\n    env_passthrough: [\"\"]");
+        let error = PipelineConfig::from_yaml(&yaml).unwrap_err().to_string();
+        assert!(error.contains("Step 'step1' has an invalid environment variable name"), "{}", error);
+    }
+
+    #[test]
+    fn test_invalid_artifact_globs_fail() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Test"
+    artifacts: ["dist/**/*.tar.gz", "reports/[*.xml"]
+"#;
+        let error = PipelineConfig::from_yaml(yaml).unwrap_err().to_string();
+        assert!(error.contains("Step 'step1' artifact 'reports/[*.xml' is invalid"), "{}", error);
+
+        let config = PipelineConfig::from_yaml(&yaml.replace(", \"reports/[*.xml\"", "")).unwrap();
+        assert_eq!(config.steps[0].artifacts, vec!["dist/**/*.tar.gz".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_step_inputs() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build it"
+    inputs:
+      files: ["src/**/*.rs", "Cargo.lock"]
+      variables: ["target"]
+"#;
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let inputs = config.steps[0].inputs.as_ref().unwrap();
+        assert_eq!(inputs.files, vec!["src/**/*.rs".to_string(), "Cargo.lock".to_string()]);
+        assert_eq!(inputs.variables, vec!["target".to_string()]);
+
+        let error = PipelineConfig::from_yaml(&yaml.replace("\"Cargo.lock\"", "\"[lock\"")).unwrap_err().to_string();
+        assert!(error.contains("Step 'build' input '[lock' is invalid"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_followups() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "migrate"
+    name: "Migrate"
+    prompt: "Migrate the schema"
+    followups:
+      - pattern: "which database"
+        reply: "Use {{ database }}"
+      - pattern: "(?i)proceed\\?"
+        reply: "Yes"
+        use_regex: true
+    max_followups: 5
+"#;
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let step = &config.steps[0];
+        assert_eq!(step.followups.len(), 2);
+        assert_eq!(step.followups[0].reply, "Use {{ database }}");
+        assert!(step.followups[1].use_regex);
+        assert_eq!(step.max_followups, Some(5));
+
+        let error = PipelineConfig::from_yaml(&yaml.replace("(?i)proceed", "(proceed")).unwrap_err().to_string();
+        assert!(error.contains("Step 'migrate' follow-up '(proceed\\?' has an invalid regex"), "{}", error);
+    }
+
+    #[test]
+    fn test_sub_pipelines_load_relative_to_their_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("lib")).unwrap();
+        let step = |id: &str, file: &str| {
+            format!("name: \"{id}\"\nsteps:\n  - id: \"{id}\"\n    name: \"{id}\"\n    pipeline:\n      file: \"{file}\"\n")
+        };
+        let leaf = "name: \"Leaf\"\nsteps:\n  - id: \"work\"\n    name: \"Work\"\n    prompt: \"Work\"\n";
+        std::fs::write(dir.path().join("main.yaml"), step("main", "lib/child.yaml")).unwrap();
+        std::fs::write(dir.path().join("lib/child.yaml"), step("child", "leaf.yaml")).unwrap();
+        std::fs::write(dir.path().join("lib/leaf.yaml"), leaf).unwrap();
+
+        let config = PipelineConfig::from_file(dir.path().join("main.yaml")).unwrap();
+        let child = config.steps[0].pipeline.as_ref().unwrap().config.clone().unwrap();
+        assert_eq!(child.name, "child");
+        let leaf = child.steps[0].pipeline.as_ref().unwrap().config.clone().unwrap();
+        assert_eq!(leaf.name, "Leaf");
+
+        // A pipeline may not end up running itself
+        std::fs::write(dir.path().join("lib/leaf.yaml"), step("leaf", "../main.yaml")).unwrap();
+        let error = format!("{:#}", PipelineConfig::from_file(dir.path().join("main.yaml")).unwrap_err());
+        assert!(error.contains("Step 'leaf' pipeline"), "{}", error);
+        assert!(error.contains("main.yaml runs a pipeline that is already running it"), "{}", error);
+
+        std::fs::remove_file(dir.path().join("lib/leaf.yaml")).unwrap();
+        let error = format!("{:#}", PipelineConfig::from_file(dir.path().join("main.yaml")).unwrap_err());
+        assert!(error.contains("Step 'child' pipeline"), "{}", error);
+        assert!(error.contains("leaf.yaml cannot be read"), "{}", error);
+    }
+
+    #[test]
+    fn test_sub_pipelines_stay_inside_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let pipelines = dir.path().join("pipelines");
+        std::fs::create_dir(&pipelines).unwrap();
+        let leaf = "name: \"Leaf\"\nsteps:\n  - id: \"work\"\n    name: \"Work\"\n    prompt: \"Work\"\n";
+        std::fs::write(pipelines.join("leaf.yaml"), leaf).unwrap();
+        std::fs::write(dir.path().join("outside.yaml"), leaf).unwrap();
+        let step = |file: &str| {
+            format!("name: \"Main\"\nsteps:\n  - id: \"run\"\n    name: \"Run\"\n    pipeline:\n      file: \"{file}\"\n")
+        };
+
+        let config = PipelineConfig::from_yaml_in_dir(&step("leaf.yaml"), &pipelines).unwrap();
+        assert_eq!(config.steps[0].pipeline.as_ref().unwrap().config.as_ref().unwrap().name, "Leaf");
+
+        let outside = dir.path().join("outside.yaml");
+        for file in ["../outside.yaml", outside.to_str().unwrap()] {
+            let error = format!("{:#}", PipelineConfig::from_yaml_in_dir(&step(file), &pipelines).unwrap_err());
+            assert!(error.contains("Step 'run' pipeline"), "{}", error);
+            assert!(error.contains("is outside"), "{}", error);
+        }
+
+        // Nor may the pipelines they run
+        std::fs::write(pipelines.join("middle.yaml"), step("../outside.yaml")).unwrap();
+        let error = format!("{:#}", PipelineConfig::from_yaml_in_dir(&step("middle.yaml"), &pipelines).unwrap_err());
+        assert!(error.contains("is outside"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: "Review the change"
+    termination:
+      success_pattern: "APPROVED"
+    continuation:
+      pattern: "NEEDS WORK"
+    precedence: success_first
+  - id: "publish"
+    name: "Publish"
+    prompt: "Publish the change"
+"#;
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.steps[0].precedence, Precedence::SuccessFirst);
+        assert_eq!(config.steps[1].precedence, Precedence::ContinuationFirst);
+
+        let error = PipelineConfig::from_yaml(&yaml.replace("success_first", "first")).unwrap_err();
+        assert!(format!("{:#}", error).contains("expected one of `continuation_first`, `success_first`, `error`"), "{:#}", error);
+    }
+
+    #[test]
+    fn test_parse_agent_profiles() {
+        let yaml = r#"
+name: "Test Pipeline"
+agents:
+  primary:
+    args: ["--provider", "anthropic"]
+  fallback:
+    pi_path: "/opt/pi/bin/pi"
+    args: ["--provider", "openai"]
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Test"
+    agents: [primary, fallback]
+    failover_after: 3
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.agents["fallback"].pi_path.as_deref(), Some("/opt/pi/bin/pi"));
+        assert_eq!(config.agents["primary"].args, vec!["--provider", "anthropic"]);
+        assert_eq!(config.steps[0].agents, vec!["primary", "fallback"]);
+        assert_eq!(config.steps[0].failover_after, Some(3));
+
+        let unknown = yaml.replace("[primary, fallback]", "[primary, backup]");
+        let err = PipelineConfig::from_yaml(&unknown).unwrap_err();
+        assert!(err.to_string().contains("unknown agent profile 'backup'"));
+    }
+
+    #[test]
+    fn test_parse_step_agent() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "triage"
+    name: "Triage"
+    prompt: "Sort the issues"
+    agent:
+      model: "claude-haiku-4-5"
+  - id: "design"
+    name: "Design"
+    prompt: "Design the fix"
+    agent:
+      pi_path: "/opt/pi/bin/pi"
+      args: ["--provider", "openai", "--model=o3"]
+      model: "gpt-5"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let triage = config.steps[0].agent.as_ref().unwrap();
+        assert_eq!(triage.pi_args(), vec!["--model", "claude-haiku-4-5"]);
+        // A model picked in args wins
+        let design = config.steps[1].agent.as_ref().unwrap();
+        assert_eq!(design.pi_args(), vec!["--provider", "openai", "--model=o3"]);
+        assert_eq!(design.pi_path.as_deref(), Some("/opt/pi/bin/pi"));
+
+        let both = yaml.replacen("    agent:\n      model", "    agents: [fast]\n    agent:\n      model", 1);
+        let both = both.replacen("steps:", "agents:\n  fast: {}\nsteps:", 1);
+        let err = PipelineConfig::from_yaml(&both).unwrap_err();
+        assert!(err.to_string().contains("both agent and agents"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_budgets() {
+        let yaml = r#"
+name: "Test Pipeline"
+budgets:
+  analysis: 1
+  codegen: 5.5
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Test"
+    budget_category: analysis
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.budgets["analysis"], 1.0);
+        assert_eq!(config.budgets["codegen"], 5.5);
+        assert_eq!(config.steps[0].budget_category.as_deref(), Some("analysis"));
+
+        let unknown = yaml.replace("budget_category: analysis", "budget_category: review");
+        let err = PipelineConfig::from_yaml(&unknown).unwrap_err();
+        assert!(err.to_string().contains("unknown budget category 'review'"));
+    }
+
+    #[test]
+    fn test_command_variables_run_when_resolved() {
+        let yaml = r#"
+name: "Test Pipeline"
+variables:
+  greeting:
+    command: "printf 'hello\\n'"
+  fallback:
+    command: "echo oops >&2; exit 3"
+    default: "main"
+steps: []
+"#;
+
+        let mut config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.variables_as_string_map()["greeting"], "");
+        config.resolve_command_variables().unwrap();
+        match &config.get_variables()["greeting"] {
+            VariableDefinition::Command { command, timeout_secs, default } => {
+                assert_eq!(command, "printf 'hello\\n'");
+                assert_eq!(*timeout_secs, DEFAULT_VARIABLE_COMMAND_TIMEOUT_SECS);
+                assert!(default.is_none());
+            }
+            other => panic!("Expected Command variable, got {:?}", other),
+        }
+
+        let vars = config.variables_as_string_map();
+        assert_eq!(vars["greeting"], "hello");
+        assert_eq!(vars["fallback"], "main");
+    }
+
+    #[test]
+    fn test_loading_runs_no_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let child = format!(
+            "name: \"Child\"\nvariables:\n  touched:\n    command: \"touch {}; echo child\"\nsteps: []\n",
+            marker.display()
+        );
+        let main = "name: \"Main\"\nsteps:\n  - id: \"child\"\n    name: \"Child\"\n    pipeline:\n      file: \"child.yaml\"\n";
+        std::fs::write(dir.path().join("child.yaml"), child).unwrap();
+        std::fs::write(dir.path().join("main.yaml"), main).unwrap();
+
+        let mut config = PipelineConfig::from_file(dir.path().join("main.yaml")).unwrap();
+        assert!(!marker.exists(), "loading must not run command variables");
+
+        config.resolve_command_variables().unwrap();
+        assert!(marker.exists());
+        let child = config.steps[0].pipeline.as_ref().unwrap().config.clone().unwrap();
+        assert_eq!(child.variables_as_string_map()["touched"], "child");
+    }
+
+    #[test]
+    fn test_failing_command_variable_fails_resolution() {
+        let yaml = r#"
+name: "Test Pipeline"
+variables:
+  branch:
+    command: "echo 'not a git repository' >&2; exit 128"
+  slow:
+    command: "sleep 5"
+    timeout_secs: 0
+    default: "n/a"
+steps: []
+"#;
+
+        let mut config = PipelineConfig::from_yaml(yaml).unwrap();
+        let err = config.resolve_command_variables().unwrap_err().to_string();
+        assert!(err.contains("Variable 'branch' command"), "{}", err);
+        assert!(err.contains("not a git repository"), "{}", err);
+
+        let mut config = PipelineConfig::from_yaml(&yaml.replace("exit 128", "exit 0")).unwrap();
+        config.resolve_command_variables().unwrap();
+        assert_eq!(config.variables_as_string_map()["slow"], "n/a");
+    }
+
+    #[test]
+    fn test_parse_poll_step() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "ci"
+    name: "Wait for CI"
+    poll:
+      command: "gh pr checks --required"
+      interval_secs: 60
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let poll = config.steps[0].poll.as_ref().unwrap();
+        assert_eq!(poll.condition(), Some(WaitCondition::Command("gh pr checks --required".to_string())));
+        assert_eq!(poll.interval_secs, Some(60));
+        assert!(config.steps[0].prompt.is_empty());
+
+        let both = yaml.replace("interval_secs: 60", "http: \"http://ci.local/status\"");
+        assert!(PipelineConfig::from_yaml(&both).is_err());
+
+        let no_prompt = "name: \"Test\"\nsteps:\n  - id: \"a\"\n    name: \"A\"\n";
+        let err = PipelineConfig::from_yaml(no_prompt).unwrap_err();
+        assert!(err.to_string().contains("needs a prompt"));
+    }
+
+    #[test]
+    fn test_parse_max_parallel() {
+        let yaml = "name: \"Test\"\nmax_parallel: 2\nsteps:\n  - id: \"a\"\n    name: \"A\"\n    prompt: \"Go\"\n";
+        assert_eq!(PipelineConfig::from_yaml(yaml).unwrap().max_parallel, Some(2));
+
+        let err = PipelineConfig::from_yaml(&yaml.replace("max_parallel: 2", "max_parallel: 0")).unwrap_err();
+        assert!(err.to_string().contains("max_parallel must be at least 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_queue_step() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "take"
+    name: "Take"
+    queue:
+      pop: "migrate"
+      batch: 5
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let queue = config.steps[0].queue.as_ref().unwrap();
+        assert_eq!(queue.pop.as_deref(), Some("migrate"));
+        assert_eq!(queue.batch, Some(5));
+
+        for (from, to, problem) in [
+            ("pop: \"migrate\"", "push: \"migrate\"", "push needs `items`"),
+            ("batch: 5", "batch: 0", "batch must be at least 1"),
+            ("batch: 5", "items: \"a.rs\"", "`items` only applies to `push`"),
+            ("batch: 5", "push: \"done\"", "exactly one of `push` or `pop`"),
+            ("queue:", "poll:\n      command: \"true\"\n    queue:", "only have one of `poll:`, `queue:`"),
+        ] {
+            let err = PipelineConfig::from_yaml(&yaml.replace(from, to)).unwrap_err();
+            assert!(err.to_string().contains(problem), "{}: {}", problem, err);
+        }
+    }
+
+    #[test]
+    fn test_prompt_templates_are_checked_at_load() {
+        let yaml = r#"
+name: "Test Pipeline"
+template: jinja
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: |
+      {% for file in files | lines %}
+      Review {{ file }}
+      {% endfor %}
+"#;
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.to_pipeline().step("review").unwrap().template, TemplateSyntax::Jinja);
+
+        let err = PipelineConfig::from_yaml(&yaml.replace("{% endfor %}", "")).unwrap_err();
+        assert!(err.to_string().starts_with("Step 'review' prompt is not a valid template: syntax error"), "{}", err);
+
+        // Without the opt-in, a prompt that merely looks like a template
+        // loads and renders as written; a step can opt out of the pipeline's
+        let legacy = yaml.replace("template: jinja\n", "").replace("{% endfor %}", "{# not a comment");
+        let config = PipelineConfig::from_yaml(&legacy).unwrap();
+        let step = config.to_pipeline().step("review").unwrap().clone();
+        assert_eq!(step.template, TemplateSyntax::Placeholders);
+        let rendered = step.render_with_variables(&HashMap::from([("file".to_string(), "a.rs".to_string())]));
+        assert_eq!(rendered, "{% for file in files | lines %}\nReview a.rs\n{# not a comment\n");
+
+        let opted_out = yaml.replace("{% endfor %}", "").replace("    name: \"Review\"\n", "    name: \"Review\"\n    template: placeholders\n");
+        assert!(PipelineConfig::from_yaml(&opted_out).is_ok());
+    }
+
+    #[test]
+    fn test_parse_output_matchers() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: "Review the change"
+    termination:
+      success_when:
+        all:
+          - contains: "APPROVED"
+          - not: { regex: "TODO|FIXME" }
+    continuation:
+      when:
+        jsonpath: { path: "$.verdict", equals: "revise" }
+  - id: "ci"
+    name: "CI"
+    poll:
+      command: "ci-status --json"
+      until:
+        jsonpath: "$.green"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let termination = config.steps[0].termination.as_ref().unwrap();
+        assert_eq!(
+            termination.success_when,
+            Some(MatcherConfig::All(vec![
+                MatcherConfig::Contains("APPROVED".to_string()),
+                MatcherConfig::Not(Box::new(MatcherConfig::Regex("TODO|FIXME".to_string()))),
+            ]))
+        );
+        assert_eq!(
+            config.steps[1].poll.as_ref().unwrap().until,
+            Some(MatcherConfig::Jsonpath(JsonPathConfig::Path("$.green".to_string())))
+        );
+
+        let pipeline = config.to_pipeline();
+        let review = pipeline.step("review").unwrap();
+        assert!(review.is_success("APPROVED"));
+        assert!(!review.is_success("APPROVED with a TODO"));
+        assert!(review.needs_continuation(r#"{"verdict": "revise"}"#));
+
+        let both = yaml.replace("success_when:", "success_pattern: \"DONE\"\n      success_when:");
+        let err = PipelineConfig::from_yaml(&both).unwrap_err();
+        assert!(err.to_string().contains("both success_pattern and success_when"));
+
+        let bad_regex = yaml.replace("TODO|FIXME", "(");
+        let err = PipelineConfig::from_yaml(&bad_regex).unwrap_err();
+        assert!(err.to_string().contains("success_when has an invalid regex"));
+    }
+
+    #[test]
+    fn test_legacy_regex_patterns_are_checked_at_load() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: "Review"
+    termination:
+      success_pattern: "APPROVED"
+      use_regex: true
+    continuation:
+      pattern: "REVISE"
+      use_regex: true
+      action: retry
+"#;
+        assert!(PipelineConfig::from_yaml(yaml).is_ok());
+
+        let error = PipelineConfig::from_yaml(&yaml.replace("\"APPROVED\"", "\"APPROVED (by\"")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Step 'review' termination success_pattern has an invalid regex 'APPROVED (by': unclosed group at character 10"
+        );
+        let error = PipelineConfig::from_yaml(&yaml.replace("\"REVISE\"", "\"REVISE[\"")).unwrap_err();
+        assert!(error.to_string().starts_with("Step 'review' continuation pattern has an invalid regex 'REVISE['"), "{}", error);
+
+        // Without use_regex the pattern is plain text
+        let plain = yaml.replace("APPROVED\"\n      use_regex: true", "APPROVED (by\"");
+        assert!(PipelineConfig::from_yaml(&plain).is_ok());
+    }
+
+    #[test]
+    fn test_outputs_must_reference_known_results() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "open_pr"
+    name: "Open PR"
+    prompt: "Open a pull request"
+    remember:
+      url: 'PR: (\S+)'
+outputs:
+  pr_url: "{{ steps.open_pr.values.url }}"
+  summary: "{{ steps.open_pr.output }}"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(config.outputs.len(), 2);
+
+        let unknown_step = yaml.replace("steps.open_pr.output", "steps.review.output");
+        let err = PipelineConfig::from_yaml(&unknown_step).unwrap_err();
+        assert!(err.to_string().contains("references non-existent step: review"));
+
+        let unknown_value = yaml.replace("values.url", "values.number");
+        let err = PipelineConfig::from_yaml(&unknown_value).unwrap_err();
+        assert!(err.to_string().contains("does not remember"));
+
+        let bad_name = yaml.replace("pr_url:", "\"pr url\":");
+        assert!(PipelineConfig::from_yaml(&bad_name).is_err());
+    }
+
+    #[test]
+    fn test_overrides_apply_before_parsing() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+  - id: "test"
+    name: "Test"
+    prompt: "Test"
+    timeout_secs: 60
+"#;
+
+        let set = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+
+        let config = PipelineConfig::from_yaml_with_overrides(
+            yaml,
+            &set(&[
+                ("steps.test.timeout_secs", "600"),
+                ("steps.0.locks", "[src/**]"),
+                ("variables.branch", "main"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.steps[1].timeout_secs, Some(600));
+        assert_eq!(config.steps[0].locks, vec!["src/**".to_string()]);
+        assert_eq!(config.variables_as_string_map()["branch"], "main");
+
+        let err = PipelineConfig::from_yaml_with_overrides(yaml, &set(&[("steps.deploy.timeout_secs", "5")])).unwrap_err();
+        assert!(err.to_string().contains("no item 'deploy'"));
+
+        let err = PipelineConfig::from_yaml_with_overrides(yaml, &set(&[("steps.test.timout_secs", "5")])).unwrap_err();
+        assert!(err.to_string().contains("not a pipeline setting"));
+
+        // Overrides are validated like the file itself
+        assert!(PipelineConfig::from_yaml_with_overrides(yaml, &set(&[("steps.test.depends_on", "[deploy]")])).is_err());
+    }
+
+    #[test]
+    fn test_parse_success_criteria() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+  - id: "deploy"
+    name: "Deploy"
+    prompt: "Deploy"
+success:
+  any:
+    - completed: deploy
+    - not: { failed: build }
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        assert_eq!(
+            config.success,
+            Some(SuccessCriteria::Any(vec![
+                SuccessCriteria::Completed("deploy".to_string()),
+                SuccessCriteria::Not(Box::new(SuccessCriteria::Failed("build".to_string()))),
+            ]))
+        );
+
+        let unknown = yaml.replace("completed: deploy", "completed: publish");
+        let err = PipelineConfig::from_yaml(&unknown).unwrap_err();
+        assert!(err.to_string().contains("non-existent step 'publish'"));
+    }
 }
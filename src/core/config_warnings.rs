@@ -0,0 +1,150 @@
+//! Warnings about pipeline files
+//!
+//! Syntax that still works but has a replacement, and settings that have
+//! no effect, are reported with a stable code when a pipeline is loaded,
+//! with a hint on what to write instead, rather than being accepted (or
+//! changing meaning) silently. A pipeline that has been looked at can list
+//! codes under `suppress_warnings:`.
+
+use crate::core::config::PipelineConfig;
+use serde::Serialize;
+
+/// A kind of warning in the registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WarningKind {
+    /// Stable code, e.g. `W001`
+    pub code: &'static str,
+    pub message: &'static str,
+    /// What to write instead
+    pub help: &'static str,
+}
+
+pub const DEPRECATED_TERMINATION_REGEX: WarningKind = WarningKind {
+    code: "W001",
+    message: "deprecated 'termination.use_regex'",
+    help: "use `success_when: { regex: ... }` instead of `success_pattern` with `use_regex: true`",
+};
+
+pub const DEPRECATED_CONTINUATION_REGEX: WarningKind = WarningKind {
+    code: "W002",
+    message: "deprecated 'continuation.use_regex'",
+    help: "use `when: { regex: ... }` instead of `pattern` with `use_regex: true`",
+};
+
+pub const IGNORED_USE_REGEX: WarningKind = WarningKind {
+    code: "W003",
+    message: "'use_regex' has no effect next to a matcher",
+    help: "the matcher says how it matches; remove `use_regex`",
+};
+
+/// Every kind of warning, by code
+pub const REGISTRY: &[WarningKind] = &[
+    DEPRECATED_TERMINATION_REGEX,
+    DEPRECATED_CONTINUATION_REGEX,
+    IGNORED_USE_REGEX,
+];
+
+/// A warning about one place in a pipeline file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConfigWarning {
+    pub code: &'static str,
+    /// Dotted path of the setting, as `--set` takes it
+    pub path: String,
+    pub message: &'static str,
+    pub help: &'static str,
+}
+
+impl ConfigWarning {
+    fn new(kind: WarningKind, path: String) -> Self {
+        Self {
+            code: kind.code,
+            path,
+            message: kind.message,
+            help: kind.help,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} at {}: {}", self.code, self.message, self.path, self.help)
+    }
+}
+
+/// Whether `code` is in the registry
+pub fn is_known(code: &str) -> bool {
+    REGISTRY.iter().any(|kind| kind.code == code)
+}
+
+/// Warnings about `config`, leaving out the codes it suppresses
+pub fn check(config: &PipelineConfig) -> Vec<ConfigWarning> {
+    let mut warnings = Vec::new();
+    for step in &config.steps {
+        if let Some(termination) = step.termination.as_ref().filter(|t| t.use_regex) {
+            let kind = match termination.success_when {
+                Some(_) => IGNORED_USE_REGEX,
+                None => DEPRECATED_TERMINATION_REGEX,
+            };
+            warnings.push(ConfigWarning::new(kind, format!("steps.{}.termination.use_regex", step.id)));
+        }
+        if let Some(continuation) = step.continuation.as_ref().filter(|c| c.use_regex) {
+            let kind = match continuation.when {
+                Some(_) => IGNORED_USE_REGEX,
+                None => DEPRECATED_CONTINUATION_REGEX,
+            };
+            warnings.push(ConfigWarning::new(kind, format!("steps.{}.continuation.use_regex", step.id)));
+        }
+    }
+    warnings.retain(|warning| !config.suppress_warnings.iter().any(|code| code == warning.code));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+name: "Warnings"
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: "Review"
+    termination:
+      success_pattern: "APPROVED|LGTM"
+      use_regex: true
+    continuation:
+      when:
+        regex: "CHANGES?"
+      use_regex: true
+      action: retry
+"#;
+
+    #[test]
+    fn test_deprecated_syntax_is_reported_unless_suppressed() {
+        let config = PipelineConfig::from_yaml(YAML).unwrap();
+        let codes: Vec<(&str, &str)> = config
+            .warnings
+            .iter()
+            .map(|warning| (warning.code, warning.path.as_str()))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                ("W001", "steps.review.termination.use_regex"),
+                ("W003", "steps.review.continuation.use_regex"),
+            ]
+        );
+        assert!(config.warnings[0].to_string().starts_with(
+            "W001 deprecated 'termination.use_regex' at steps.review.termination.use_regex: use `success_when"
+        ));
+
+        let suppressed = format!("suppress_warnings: [\"W001\"]\n{}", YAML);
+        let config = PipelineConfig::from_yaml(&suppressed).unwrap();
+        assert_eq!(config.warnings.len(), 1);
+        assert_eq!(config.warnings[0].code, "W003");
+
+        let unknown = format!("suppress_warnings: [\"W999\"]\n{}", YAML);
+        let err = PipelineConfig::from_yaml(&unknown).unwrap_err();
+        assert!(err.to_string().contains("Unknown warning code 'W999'"), "{}", err);
+    }
+}
@@ -0,0 +1,121 @@
+//! Checks of the files that file variables point at
+//!
+//! A file variable reaches the agent as `@<path>`, which attaches the file
+//! to the prompt. When a pipeline is validated, all of them are looked at
+//! concurrently: a file marked `validate_exists` must exist, and one that
+//! exists must be readable and no larger than its `max_bytes`. Every
+//! problem is reported in one error rather than stopping at the first.
+
+use anyhow::Result;
+use std::path::Path;
+
+/// Largest file a file variable may attach unless it sets `max_bytes`
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Files looked at at once
+const MAX_CONCURRENT: usize = 16;
+
+/// A file variable to check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileCheck {
+    pub variable: String,
+    pub path: String,
+    pub validate_exists: bool,
+    pub max_bytes: u64,
+}
+
+impl FileCheck {
+    /// What is wrong with the file, if anything
+    fn problem(&self) -> Option<String> {
+        let path = Path::new(&self.path);
+        let metadata = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return self.validate_exists.then(|| {
+                    format!("Variable '{}' references file that doesn't exist: {}", self.variable, self.path)
+                });
+            }
+            Err(e) => return Some(format!("Variable '{}' cannot read {}: {}", self.variable, self.path, e)),
+        };
+        if !metadata.is_file() {
+            return None;
+        }
+        if metadata.len() > self.max_bytes {
+            return Some(format!(
+                "Variable '{}' references {} ({} bytes), over its max_bytes of {}",
+                self.variable,
+                self.path,
+                metadata.len(),
+                self.max_bytes
+            ));
+        }
+        std::fs::File::open(path)
+            .err()
+            .map(|e| format!("Variable '{}' cannot read {}: {}", self.variable, self.path, e))
+    }
+}
+
+/// Check the files, failing with every problem found
+///
+/// Problems are listed in the order of `checks`.
+pub fn check(checks: &[FileCheck]) -> Result<()> {
+    let chunk_size = checks.len().div_ceil(MAX_CONCURRENT).max(1);
+    let problems: Vec<String> = std::thread::scope(|scope| {
+        let workers: Vec<_> = checks
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().filter_map(FileCheck::problem).collect::<Vec<_>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("file checks don't panic"))
+            .collect()
+    });
+
+    match problems.as_slice() {
+        [] => Ok(()),
+        [problem] => anyhow::bail!("{}", problem),
+        _ => anyhow::bail!(
+            "{} file variables have problems:\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_problem_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("small.md");
+        let large = dir.path().join("large.md");
+        std::fs::write(&small, "notes").unwrap();
+        std::fs::write(&large, "x".repeat(100)).unwrap();
+        let file = |variable: &str, path: &Path, validate_exists: bool| FileCheck {
+            variable: variable.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            validate_exists,
+            max_bytes: 50,
+        };
+
+        let mut checks: Vec<FileCheck> = (0..40).map(|i| file(&format!("ok{}", i), &small, true)).collect();
+        // Files that don't need to exist yet, such as outputs, are fine
+        checks.push(file("output", &dir.path().join("out.md"), false));
+        assert!(check(&checks).is_ok());
+
+        checks.insert(3, file("spec", &dir.path().join("spec.md"), true));
+        checks.push(file("log", &large, false));
+        let err = check(&checks).unwrap_err().to_string();
+        let lines: Vec<&str> = err.lines().collect();
+        assert_eq!(lines.len(), 3, "{}", err);
+        assert_eq!(lines[0], "2 file variables have problems:");
+        assert!(lines[1].contains("Variable 'spec' references file that doesn't exist"), "{}", err);
+        assert!(lines[2].contains("Variable 'log' references") && lines[2].ends_with("(100 bytes), over its max_bytes of 50"), "{}", err);
+
+        // A single problem is the whole message
+        let err = check(&checks[..5]).unwrap_err().to_string();
+        assert!(err.starts_with("Variable 'spec' references file that doesn't exist"), "{}", err);
+    }
+}
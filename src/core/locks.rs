@@ -0,0 +1,105 @@
+//! Path locks declared by steps (`locks: ["src/**"]`)
+//!
+//! Steps whose lock globs overlap never run at the same time. Two globs
+//! overlap unless a pair of path components tells them apart: two different
+//! literal names, or a literal name that the other side's pattern does not
+//! match. A glob ending early (`src` against `src/main.rs`) locks everything
+//! beneath it, so it overlaps too.
+
+use glob::Pattern;
+
+/// Check whether a lock glob is valid
+pub fn validate(lock: &str) -> Result<(), String> {
+    if lock.trim().is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    Pattern::new(lock).map(|_| ()).map_err(|e| e.to_string())
+}
+
+/// Check whether two lock globs can cover the same path
+pub fn globs_overlap(a: &str, b: &str) -> bool {
+    let a: Vec<_> = components(a).collect();
+    let b: Vec<_> = components(b).collect();
+
+    for (left, right) in a.iter().zip(&b) {
+        if *left == "**" || *right == "**" {
+            return true;
+        }
+        match (is_literal(left), is_literal(right)) {
+            (true, true) if left != right => return false,
+            (true, false) if !component_matches(right, left) => return false,
+            (false, true) if !component_matches(left, right) => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Check whether any glob of one step overlaps any glob of the other
+pub fn locks_overlap(a: &[String], b: &[String]) -> bool {
+    a.iter().any(|a| b.iter().any(|b| globs_overlap(a, b)))
+}
+
+/// Check whether a written path is covered by one of the locks
+///
+/// Absolute paths are taken relative to the current directory when they
+/// are inside it.
+pub fn is_covered(locks: &[String], path: &str) -> bool {
+    let cwd = std::env::current_dir().ok();
+    let relative = cwd
+        .as_deref()
+        .and_then(|cwd| std::path::Path::new(path).strip_prefix(cwd).ok())
+        .and_then(|p| p.to_str())
+        .unwrap_or(path);
+    let path: Vec<_> = components(relative).collect();
+    let path = path.join("/");
+
+    locks.iter().any(|lock| {
+        let lock: Vec<_> = components(lock).collect();
+        let lock = lock.join("/");
+        path == lock
+            || path.starts_with(&format!("{}/", lock))
+            || Pattern::new(&lock).is_ok_and(|pattern| pattern.matches(&path))
+    })
+}
+
+fn components(glob: &str) -> impl Iterator<Item = &str> {
+    glob.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+fn is_literal(component: &str) -> bool {
+    !component.contains(['*', '?', '['])
+}
+
+fn component_matches(pattern: &str, name: &str) -> bool {
+    // An invalid pattern is rejected at validation; assume it could match
+    Pattern::new(pattern).map_or(true, |p| p.matches(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_globs_overlap() {
+        assert!(globs_overlap("src/**", "src/core/step.rs"));
+        assert!(globs_overlap("src", "src/main.rs"));
+        assert!(globs_overlap("src/*.rs", "src/main.rs"));
+        assert!(globs_overlap("**/*.md", "docs/guide"));
+        assert!(!globs_overlap("src/**", "docs/**"));
+        assert!(!globs_overlap("src/*.rs", "src/README.md"));
+        assert!(!globs_overlap("./src/main.rs", "src/lib.rs"));
+    }
+
+    #[test]
+    fn test_written_paths_are_checked_against_locks() {
+        let locks = vec!["src/**".to_string(), "Cargo.toml".to_string()];
+        assert!(is_covered(&locks, "src/core/step.rs"));
+        assert!(is_covered(&locks, "./Cargo.toml"));
+        assert!(!is_covered(&locks, "README.md"));
+
+        let cwd = std::env::current_dir().unwrap();
+        assert!(is_covered(&locks, cwd.join("src/main.rs").to_str().unwrap()));
+        assert!(!is_covered(&locks, cwd.join("docs/a.md").to_str().unwrap()));
+    }
+}
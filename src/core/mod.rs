@@ -4,11 +4,17 @@
 //! pipelines, steps, and their configuration.
 
 pub mod config;
+pub mod config_warnings;
+pub mod file_variables;
 pub mod pipeline;
 pub mod step;
 pub mod condition;
 pub mod context;
+pub mod locks;
+pub mod redact;
+pub mod schema;
 pub mod state;
+pub mod template;
 
 pub use pipeline::*;
 pub use step::*;
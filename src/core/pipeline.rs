@@ -1,12 +1,14 @@
 //! Pipeline domain model
 
 use crate::core::{
-    config::PipelineConfig,
-    step::{Step, StepDefaults},
+    config::{FailurePolicy, PipelineConfig, SuccessCriteria, Telemetry},
+    step::{
+        substitute_variables, Step, StepDefaults, DEFAULT_LANGUAGE_TEMPLATE, DEFAULT_MAX_RETRIES, DEFAULT_TIMEOUT_SECS,
+    },
     state::{PipelineState, ExecutionStatus},
     context::PipelineContext,
 };
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// A pipeline definition
 #[derive(Debug, Clone)]
@@ -23,16 +25,54 @@ pub struct Pipeline {
     /// Execution state
     pub state: PipelineState,
 
+    /// Values remembered during this run, to be persisted for the next
+    pub remembered: HashMap<String, String>,
+
+    /// Spending limits in USD per budget category
+    pub budgets: HashMap<String, f64>,
+
+    /// Templates of the run's named outputs, materialized into
+    /// `state.outputs` when the run ends
+    pub outputs: BTreeMap<String, String>,
+
+    /// When the run counts as successful; without it, any failed step fails the run
+    pub success: Option<SuccessCriteria>,
+
+    /// Wall-clock limit for the run, after which it is cancelled
+    pub max_duration: Option<std::time::Duration>,
+
+    /// What a step failing without an `on_failure` route does to the rest of the run
+    pub failure_policy: FailurePolicy,
+
     /// Step execution order (topological sort) (not serialized)
     execution_order: Vec<String>,
 }
 
+/// Prefix under which remembered values are exposed as variables
+pub const MEMORY_VARIABLE_PREFIX: &str = "memory.";
+
+/// Variable holding the run's random seed
+pub const RUN_SEED_VARIABLE: &str = "run.seed";
+
 impl Pipeline {
     /// Create a pipeline from configuration
     pub fn from_config(config: &PipelineConfig) -> Self {
         let defaults = StepDefaults {
-            max_retries: config.max_retries.unwrap_or(3),
-            timeout_secs: config.default_timeout_secs.unwrap_or(10800),
+            max_retries: config.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            timeout_secs: config.default_timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS),
+            streaming: config.streaming.unwrap_or(true),
+            heartbeat_secs: config.heartbeat_secs,
+            language: config.language.clone(),
+            language_template: config
+                .language_template
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LANGUAGE_TEMPLATE.to_string()),
+            working_dir: config.working_dir.clone(),
+            env: config.env.clone(),
+            env_passthrough: config.env_passthrough.clone(),
+            provenance: config.provenance,
+            sanitize_markers: config.sanitize_markers,
+            template: config.template.unwrap_or_default(),
         };
 
         let steps: HashMap<String, Step> = config
@@ -51,10 +91,74 @@ impl Pipeline {
             variables: config.variables_as_string_map(),
             steps,
             state: PipelineState::new(),
+            remembered: HashMap::new(),
+            budgets: config.budgets.clone(),
+            outputs: config.outputs.clone(),
+            success: config.success.clone(),
+            max_duration: config.max_duration_secs.map(std::time::Duration::from_secs),
+            // With `success:` criteria the run has always carried on past failures
+            failure_policy: config.failure_policy.unwrap_or(if config.success.is_some() {
+                FailurePolicy::ContinueAll
+            } else {
+                FailurePolicy::FailFast
+            }),
             execution_order,
         }
     }
 
+    /// Expose values remembered by earlier runs as `memory.<key>` variables
+    ///
+    /// Variables that are already set (e.g. overridden on the command line) win.
+    pub fn load_memory(&mut self, memory: HashMap<String, String>) {
+        for (key, value) in memory {
+            self.variables
+                .entry(format!("{}{}", MEMORY_VARIABLE_PREFIX, key))
+                .or_insert(value);
+        }
+    }
+
+    /// Remember a value for future runs (also visible to later steps in this run)
+    pub fn remember(&mut self, key: &str, value: String) {
+        self.variables.insert(format!("{}{}", MEMORY_VARIABLE_PREFIX, key), value.clone());
+        self.remembered.insert(key.to_string(), value);
+    }
+
+    /// Render the declared outputs against the results of the run into
+    /// `state.outputs`
+    ///
+    /// Outputs referencing something the run never produced (e.g. a step that
+    /// didn't complete) are left out; their names are returned. Outputs
+    /// drawing on a step whose telemetry level is below `full` are withheld.
+    pub fn materialize_outputs(&mut self) -> Vec<String> {
+        let variables = self.create_context_for_step("").get_rendering_variables();
+        let mut unresolved = Vec::new();
+        for (name, template) in &self.outputs {
+            let withheld = self.steps.values().any(|step| {
+                !step.telemetry.keeps_content() && template.contains(&format!("{{{{ steps.{}.", step.id))
+            });
+            if withheld {
+                self.state.outputs.insert(name.clone(), Telemetry::WITHHELD.to_string());
+                continue;
+            }
+            let rendered = substitute_variables(template, &variables);
+            if rendered.missing.is_empty() {
+                self.state.outputs.insert(name.clone(), rendered.text);
+            } else {
+                unresolved.push(name.clone());
+            }
+        }
+        unresolved
+    }
+
+    /// Amount spent so far by the steps in a budget category, in USD
+    pub fn spent_in_category(&self, category: &str) -> f64 {
+        self.steps
+            .values()
+            .filter(|step| step.budget_category.as_deref() == Some(category))
+            .filter_map(|step| self.state.cost_usd.get(&step.id))
+            .sum()
+    }
+
     /// Get a step by ID
     pub fn step(&self, id: &str) -> Option<&Step> {
         self.steps.get(id)
@@ -67,24 +171,55 @@ impl Pipeline {
 
     /// Get steps ready to execute (dependencies satisfied)
     pub fn ready_steps(&self) -> Vec<&Step> {
-        let completed_or_failed: HashSet<String> = self
-            .steps
+        let satisfied = self.satisfied_steps();
+        let completed = self.completed_steps();
+
+        self.steps
             .values()
             .filter(|s| {
-                matches!(s.state, crate::core::state::StepState::Completed { .. } | crate::core::state::StepState::Failed { .. })
+                matches!(s.state, crate::core::state::StepState::Pending | crate::core::state::StepState::Retrying { .. })
+                    && s.dependencies_met(&satisfied, &completed)
             })
+            .collect()
+    }
+
+    /// IDs of the steps that count as done for their dependents: every step
+    /// in a terminal state, except under `finish_independent` the ones that
+    /// failed unhandled
+    pub fn satisfied_steps(&self) -> HashSet<String> {
+        self.steps
+            .values()
+            .filter(|s| s.state.is_terminal())
+            .filter(|s| self.failure_policy != FailurePolicy::FinishIndependent || !Self::failed_unhandled(s))
             .map(|s| s.id.clone())
-            .collect();
+            .collect()
+    }
 
+    /// IDs of the steps that completed successfully
+    pub fn completed_steps(&self) -> HashSet<String> {
         self.steps
             .values()
-            .filter(|s| {
-                matches!(s.state, crate::core::state::StepState::Pending | crate::core::state::StepState::Retrying { .. })
-                    && s.dependencies_met(&completed_or_failed)
-            })
+            .filter(|s| matches!(s.state, crate::core::state::StepState::Completed { .. }))
+            .map(|s| s.id.clone())
             .collect()
     }
 
+    /// IDs of the steps that failed with no `on_failure` route to handle it, sorted
+    pub fn unhandled_failures(&self) -> Vec<String> {
+        let mut failed: Vec<_> = self
+            .steps
+            .values()
+            .filter(|s| Self::failed_unhandled(s))
+            .map(|s| s.id.clone())
+            .collect();
+        failed.sort();
+        failed
+    }
+
+    fn failed_unhandled(step: &Step) -> bool {
+        matches!(step.state, crate::core::state::StepState::Failed { .. }) && step.next_step_on_failure().is_none()
+    }
+
     /// Get all currently running steps
     pub fn running_steps(&self) -> Vec<&Step> {
         self.steps
@@ -101,10 +236,35 @@ impl Pipeline {
                 crate::core::state::StepState::Completed { .. }
                     | crate::core::state::StepState::Skipped { .. }
                     | crate::core::state::StepState::Failed { .. }
+                    | crate::core::state::StepState::Cancelled { .. }
             )
         })
     }
 
+    /// Evaluate the `success:` criteria against the step results, if set
+    pub fn success_criteria_met(&self) -> Option<bool> {
+        self.success.as_ref().map(|criteria| self.criteria_hold(criteria))
+    }
+
+    fn criteria_hold(&self, criteria: &SuccessCriteria) -> bool {
+        let state = |id: &str| self.steps.get(id).map(|s| &s.state);
+        match criteria {
+            SuccessCriteria::Completed(id) => {
+                matches!(state(id), Some(crate::core::state::StepState::Completed { .. }))
+            }
+            SuccessCriteria::Failed(id) => {
+                matches!(state(id), Some(crate::core::state::StepState::Failed { .. }))
+            }
+            SuccessCriteria::Skipped(id) => !matches!(
+                state(id),
+                Some(crate::core::state::StepState::Completed { .. } | crate::core::state::StepState::Failed { .. })
+            ),
+            SuccessCriteria::Not(inner) => !self.criteria_hold(inner),
+            SuccessCriteria::Any(conditions) => conditions.iter().any(|c| self.criteria_hold(c)),
+            SuccessCriteria::All(conditions) => conditions.iter().all(|c| self.criteria_hold(c)),
+        }
+    }
+
     /// Check if pipeline has failed
     pub fn has_failed(&self) -> bool {
         self.state.status == ExecutionStatus::Failed
@@ -148,7 +308,7 @@ impl Pipeline {
         temp_visited.insert(step_id.to_string());
 
         if let Some(step) = steps.get(step_id) {
-            for dep in &step.dependencies {
+            for dep in step.all_dependencies() {
                 Self::visit(dep, steps, visited, temp_visited, result);
             }
         }
@@ -164,11 +324,19 @@ impl Pipeline {
 
         // Add global variables
         context.variables.extend(self.variables.clone());
+        context.variables.insert(RUN_SEED_VARIABLE.to_string(), self.state.seed.to_string());
 
-        // Add outputs from previous steps
+        // Add outputs from previous steps, the values they remembered and
+        // the outputs of the pipelines they ran
         for (id, step) in &self.steps {
             if let crate::core::state::StepState::Completed { output, .. } = &step.state {
                 context.set_step_output(id, output.clone());
+                for (key, value) in step.remembered_values(output) {
+                    context.variables.insert(format!("steps.{}.values.{}", id, key), value);
+                }
+                for (name, value) in step.sub_pipeline_outputs(output) {
+                    context.variables.insert(format!("steps.{}.outputs.{}", id, name), value);
+                }
             }
         }
 
@@ -209,6 +377,23 @@ steps:
         assert!(order.iter().position(|x| x == "step2").unwrap() < order.iter().position(|x| x == "step3").unwrap());
     }
 
+    #[test]
+    fn test_prompts_see_the_run_seed() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Try seed {{ run.seed }}"
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        pipeline.state.seed = 4242;
+
+        let context = pipeline.create_context_for_step("step1");
+        let prompt = pipeline.step("step1").unwrap().render_prompt(&context).unwrap().text;
+        assert!(prompt.starts_with("Try seed 4242"), "{}", prompt);
+    }
+
     #[test]
     fn test_ready_steps() {
         let yaml = r#"
@@ -249,4 +434,74 @@ steps:
         assert_eq!(ready.len(), 1);
         assert_eq!(ready[0].id, "step2");
     }
+
+    #[test]
+    fn test_any_of_dependencies() {
+        use crate::core::state::StepState;
+
+        let yaml = |policy: &str| {
+            format!(
+                r#"
+name: "Alternatives"
+failure_policy: {}
+steps:
+  - id: "fast"
+    name: "Fast path"
+    prompt: "Try the quick fix"
+  - id: "fallback"
+    name: "Fallback"
+    prompt: "Do it properly"
+  - id: "merge"
+    name: "Merge"
+    prompt: "Merge whichever worked"
+    depends_on_any: ["fast", "fallback"]
+"#,
+                policy
+            )
+        };
+        let ready = |pipeline: &Pipeline| {
+            let mut ids: Vec<String> = pipeline.ready_steps().iter().map(|s| s.id.clone()).collect();
+            ids.sort();
+            ids
+        };
+        let failed = || StepState::Failed {
+            error: "gave up".to_string(),
+            attempts: 1,
+            last_started_at: chrono::Utc::now(),
+            failed_at: chrono::Utc::now(),
+        };
+        let skipped = || StepState::Skipped {
+            reason: "when: false".to_string(),
+        };
+
+        let mut pipeline = PipelineConfig::from_yaml(&yaml("continue_all")).unwrap().to_pipeline();
+        assert_eq!(pipeline.execution_order().last().map(String::as_str), Some("merge"));
+        assert_eq!(ready(&pipeline), vec!["fallback", "fast"]);
+
+        // A failed alternative doesn't count while another may still complete
+        pipeline.step_mut("fast").unwrap().state = failed();
+        assert_eq!(ready(&pipeline), vec!["fallback"]);
+        pipeline.step_mut("fallback").unwrap().state = StepState::Completed {
+            output: "DONE".to_string(),
+            attempts: 1,
+            started_at: chrono::Utc::now(),
+            completed_at: chrono::Utc::now(),
+        };
+        assert_eq!(ready(&pipeline), vec!["merge"]);
+
+        // With none completed, they are met as depends_on would be
+        pipeline.step_mut("fallback").unwrap().state = skipped();
+        assert_eq!(ready(&pipeline), vec!["merge"]);
+        let mut pipeline = PipelineConfig::from_yaml(&yaml("finish_independent")).unwrap().to_pipeline();
+        pipeline.step_mut("fast").unwrap().state = failed();
+        pipeline.step_mut("fallback").unwrap().state = skipped();
+        assert!(ready(&pipeline).is_empty());
+
+        let both = yaml("fail_fast").replace("depends_on_any", "depends_on: [\"fast\"]\n    depends_on_any");
+        let err = PipelineConfig::from_yaml(&both).unwrap_err();
+        assert!(err.to_string().contains("lists 'fast' under both depends_on and depends_on_any"), "{}", err);
+        let unknown = yaml("fail_fast").replace("\"fallback\"]", "\"slow\"]");
+        let err = PipelineConfig::from_yaml(&unknown).unwrap_err();
+        assert!(err.to_string().contains("depends_on_any names non-existent step 'slow'"), "{}", err);
+    }
 }
@@ -0,0 +1,158 @@
+//! JSON Schema of pipeline files, and strict checking against it
+//!
+//! The schema is derived from `PipelineConfig` with schemars. Config objects
+//! carry schemars' `deny_unknown_fields` (`additionalProperties: false`), so
+//! editors flag misspelled keys, while serde itself still ignores them and
+//! older files keep loading. `unknown_fields` walks a document against the
+//! schema to find the keys `validate --strict` rejects.
+
+use crate::core::config::PipelineConfig;
+use schemars::schema::RootSchema;
+use serde_json::Value as Json;
+use serde_yaml::Value;
+
+/// JSON Schema of pipeline files
+pub fn pipeline_schema() -> RootSchema {
+    schemars::schema_for!(PipelineConfig)
+}
+
+/// Keys of a pipeline document that are not settings, as dotted paths with
+/// steps addressed by ID (e.g. `steps.build.termintaion`)
+pub fn unknown_fields(document: &Value) -> Vec<String> {
+    let schema = serde_json::to_value(pipeline_schema()).unwrap_or_default();
+    let definitions = schema.get("definitions").cloned().unwrap_or_default();
+    let mut walker = Walker {
+        definitions: &definitions,
+        unknown: Vec::new(),
+    };
+    walker.check(document, &schema, "");
+    walker.unknown
+}
+
+struct Walker<'a> {
+    definitions: &'a Json,
+    unknown: Vec<String>,
+}
+
+impl<'a> Walker<'a> {
+    fn check(&mut self, value: &Value, schema: &'a Json, path: &str) {
+        match value {
+            Value::Mapping(map) => {
+                // Of the shapes the value may take (an enum's variants, say),
+                // check against the one that knows most of its keys
+                let keys: Vec<&str> = map.keys().filter_map(Value::as_str).collect();
+                let known = |schema: &Json| keys.iter().filter(|key| schema["properties"].get(**key).is_some()).count();
+                let mut best: Option<&Json> = None;
+                for candidate in self.alternatives(schema) {
+                    let is_object = candidate.get("properties").is_some() || candidate.get("additionalProperties").is_some();
+                    if is_object && best.is_none_or(|best| known(candidate) > known(best)) {
+                        best = Some(candidate);
+                    }
+                }
+                let Some(object) = best else { return };
+
+                for (key, value) in map {
+                    let Some(key) = key.as_str() else { continue };
+                    let child = join(path, key);
+                    match (object["properties"].get(key), object.get("additionalProperties")) {
+                        (Some(property), _) => self.check(value, property, &child),
+                        (None, Some(Json::Bool(false))) => self.unknown.push(child),
+                        (None, Some(additional)) => self.check(value, additional, &child),
+                        (None, None) => {}
+                    }
+                }
+            }
+            Value::Sequence(items) => {
+                let Some(item_schema) = self.alternatives(schema).into_iter().find_map(|s| s.get("items")) else {
+                    return;
+                };
+                for (index, item) in items.iter().enumerate() {
+                    let segment = item.get("id").and_then(Value::as_str).map_or(index.to_string(), str::to_string);
+                    self.check(item, item_schema, &join(path, &segment));
+                }
+            }
+            Value::Tagged(tagged) => self.check(&tagged.value, schema, path),
+            _ => {}
+        }
+    }
+
+    /// The schemas a value may match, with references and combinators resolved
+    fn alternatives(&self, schema: &'a Json) -> Vec<&'a Json> {
+        if let Some(name) = schema.get("$ref").and_then(Json::as_str) {
+            let name = name.trim_start_matches("#/definitions/");
+            return self.definitions.get(name).map_or(vec![], |schema| self.alternatives(schema));
+        }
+        let combined: Vec<&Json> = ["anyOf", "oneOf", "allOf"]
+            .iter()
+            .filter_map(|key| schema.get(*key).and_then(Json::as_array))
+            .flatten()
+            .collect();
+        if combined.is_empty() {
+            vec![schema]
+        } else {
+            combined.into_iter().flat_map(|schema| self.alternatives(schema)).collect()
+        }
+    }
+}
+
+fn join(path: &str, segment: &str) -> String {
+    if path.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{}.{}", path, segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_fields_are_found_at_any_depth() {
+        let yaml = r#"
+name: "Test Pipeline"
+varaibles: {}
+variables:
+  anything: { goes: "here" }
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build it"
+    termintaion:
+      success_pattern: "DONE"
+  - id: "check"
+    name: "Check"
+    prompt: "Check it"
+    wait_for:
+      - file: "Cargo.lock"
+    termination:
+      success_pattern: "DONE"
+      success_when:
+        all:
+          - contains: "OK"
+          - jsonpath: { path: "$.status", equals: "ok", tolerance: 1 }
+    poll:
+      command: "true"
+      intervall_secs: 5
+"#;
+        let document: Value = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            unknown_fields(&document),
+            vec![
+                "varaibles",
+                "steps.build.termintaion",
+                "steps.check.termination.success_when.all.1.jsonpath.tolerance",
+                "steps.check.poll.intervall_secs",
+            ]
+        );
+
+        // The same file without typos is clean
+        let fixed = yaml
+            .replace("varaibles: {}\n", "")
+            .replace("termintaion", "termination")
+            .replace(", tolerance: 1", "")
+            .replace("intervall_secs", "interval_secs");
+        let document: Value = serde_yaml::from_str(&fixed).unwrap();
+        assert!(unknown_fields(&document).is_empty(), "{:?}", unknown_fields(&document));
+    }
+}
@@ -15,10 +15,46 @@ pub enum ExecutionStatus {
     Completed,
     /// Pipeline failed
     Failed,
+    /// Pipeline ran to its end with some steps failed, under a failure
+    /// policy that kept the independent ones going
+    PartiallyCompleted,
     /// Pipeline was cancelled
     Cancelled,
     /// Pipeline is paused (for future distributed execution)
     Paused,
+    /// The process running the pipeline stopped sending heartbeats (e.g. the
+    /// machine crashed mid-run)
+    Orphaned,
+}
+
+impl ExecutionStatus {
+    /// Whether the run has ended, however it went
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, ExecutionStatus::Pending | ExecutionStatus::Running | ExecutionStatus::Paused)
+    }
+}
+
+/// Parses the status names shown by `history`, in any case: `failed`,
+/// `partially_completed` (or `partial`), `cancelled`, ...
+impl std::str::FromStr for ExecutionStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().replace('-', "_").as_str() {
+            "pending" => Ok(ExecutionStatus::Pending),
+            "running" => Ok(ExecutionStatus::Running),
+            "completed" => Ok(ExecutionStatus::Completed),
+            "failed" => Ok(ExecutionStatus::Failed),
+            "partially_completed" | "partiallycompleted" | "partial" => Ok(ExecutionStatus::PartiallyCompleted),
+            "cancelled" | "canceled" => Ok(ExecutionStatus::Cancelled),
+            "paused" => Ok(ExecutionStatus::Paused),
+            "orphaned" => Ok(ExecutionStatus::Orphaned),
+            other => Err(format!(
+                "Unknown status '{}' (expected pending, running, completed, failed, partial, cancelled, paused or orphaned)",
+                other
+            )),
+        }
+    }
 }
 
 /// State of a single step
@@ -49,6 +85,13 @@ pub enum StepState {
         last_started_at: DateTime<Utc>,
         failed_at: DateTime<Utc>,
     },
+    /// Step was stopped mid-run because the pipeline was cancelled
+    Cancelled {
+        reason: String,
+        attempts: usize,
+        started_at: DateTime<Utc>,
+        cancelled_at: DateTime<Utc>,
+    },
     /// Step was skipped (e.g., conditional execution)
     Skipped {
         reason: String,
@@ -65,7 +108,10 @@ impl StepState {
     pub fn is_terminal(&self) -> bool {
         matches!(
             self,
-            StepState::Completed { .. } | StepState::Failed { .. } | StepState::Skipped { .. }
+            StepState::Completed { .. }
+                | StepState::Failed { .. }
+                | StepState::Cancelled { .. }
+                | StepState::Skipped { .. }
         )
     }
 }
@@ -76,6 +122,14 @@ pub struct PipelineState {
     /// Unique execution ID
     pub execution_id: Uuid,
 
+    /// Random seed of the run, exposed to prompts as `{{ run.seed }}`
+    #[serde(default)]
+    pub seed: u32,
+
+    /// Run whose `pipeline:` step started this one, if any
+    #[serde(default)]
+    pub parent_execution_id: Option<Uuid>,
+
     /// Current execution status
     pub status: ExecutionStatus,
 
@@ -96,6 +150,57 @@ pub struct PipelineState {
 
     /// Number of currently running steps
     pub running_steps: usize,
+
+    /// Bytes written by the agent, per step
+    #[serde(default)]
+    pub bytes_written: std::collections::HashMap<String, u64>,
+
+    /// Cost of the agent's turns in USD, per step
+    #[serde(default)]
+    pub cost_usd: std::collections::HashMap<String, f64>,
+
+    /// Tokens used by the agent's turns, per step
+    #[serde(default)]
+    pub tokens: std::collections::HashMap<String, u64>,
+
+    /// Digest of the declared inputs of incremental steps, per step
+    #[serde(default)]
+    pub input_digests: std::collections::HashMap<String, String>,
+
+    /// Agent calls made by steps with agent profiles, per step
+    #[serde(default)]
+    pub agent_attempts: std::collections::HashMap<String, Vec<AgentAttempt>>,
+
+    /// Hashes of the prompt sent on each attempt, per step
+    #[serde(default)]
+    pub prompt_hashes: std::collections::HashMap<String, Vec<PromptHashes>>,
+
+    /// Named outputs of the run, materialized when it ends
+    #[serde(default)]
+    pub outputs: std::collections::BTreeMap<String, String>,
+}
+
+/// SHA-256 hashes of the prompt a step attempt sent to the agent
+///
+/// The template hash changes only when the pipeline file does; the rendered
+/// hash also changes with variables and earlier step outputs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptHashes {
+    pub attempt: usize,
+    /// Hash of the raw `prompt` template
+    pub template: String,
+    /// Hash of the full prompt, after substitution and added instructions
+    pub rendered: String,
+}
+
+/// One agent call of a step that lists agent profiles
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentAttempt {
+    /// Profile that served the call
+    pub agent: String,
+
+    /// Provider error, if the call failed
+    pub error: Option<String>,
 }
 
 impl PipelineState {
@@ -103,6 +208,8 @@ impl PipelineState {
     pub fn new() -> Self {
         Self {
             execution_id: Uuid::new_v4(),
+            seed: Uuid::new_v4().as_u128() as u32,
+            parent_execution_id: None,
             status: ExecutionStatus::Pending,
             started_at: None,
             completed_at: None,
@@ -110,28 +217,78 @@ impl PipelineState {
             completed_steps: 0,
             failed_steps: 0,
             running_steps: 0,
+            bytes_written: std::collections::HashMap::new(),
+            cost_usd: std::collections::HashMap::new(),
+            tokens: std::collections::HashMap::new(),
+            input_digests: std::collections::HashMap::new(),
+            agent_attempts: std::collections::HashMap::new(),
+            prompt_hashes: std::collections::HashMap::new(),
+            outputs: std::collections::BTreeMap::new(),
         }
     }
 
     /// Mark pipeline as started
+    ///
+    /// A resumed run keeps the time it was first started.
     pub fn start(&mut self, total_steps: usize) {
         self.status = ExecutionStatus::Running;
-        self.started_at = Some(Utc::now());
+        self.started_at.get_or_insert_with(Utc::now);
         self.total_steps = total_steps;
     }
 
+    /// Add bytes written by a step
+    pub fn record_bytes_written(&mut self, step_id: &str, bytes: u64) {
+        *self.bytes_written.entry(step_id.to_string()).or_insert(0) += bytes;
+    }
+
+    /// Add the cost and tokens of a step's agent turns
+    pub fn record_cost(&mut self, step_id: &str, cost_usd: f64, tokens: u64) {
+        *self.cost_usd.entry(step_id.to_string()).or_insert(0.0) += cost_usd;
+        *self.tokens.entry(step_id.to_string()).or_insert(0) += tokens;
+    }
+
+    /// Add agent calls made by a step
+    pub fn record_agent_attempts(&mut self, step_id: &str, attempts: Vec<AgentAttempt>) {
+        self.agent_attempts
+            .entry(step_id.to_string())
+            .or_default()
+            .extend(attempts);
+    }
+
+    /// Add the prompt hashes of a step attempt
+    pub fn record_prompt_hashes(&mut self, step_id: &str, hashes: PromptHashes) {
+        self.prompt_hashes.entry(step_id.to_string()).or_default().push(hashes);
+    }
+
+    /// Total bytes written across all steps
+    pub fn total_bytes_written(&self) -> u64 {
+        self.bytes_written.values().sum()
+    }
+
     /// Mark pipeline as completed
     pub fn complete(&mut self) {
         self.status = ExecutionStatus::Completed;
         self.completed_at = Some(Utc::now());
     }
 
+    /// Mark pipeline as completed with some steps failed
+    pub fn complete_partially(&mut self) {
+        self.status = ExecutionStatus::PartiallyCompleted;
+        self.completed_at = Some(Utc::now());
+    }
+
     /// Mark pipeline as failed
     pub fn fail(&mut self) {
         self.status = ExecutionStatus::Failed;
         self.completed_at = Some(Utc::now());
     }
 
+    /// Mark pipeline as cancelled
+    pub fn cancel(&mut self) {
+        self.status = ExecutionStatus::Cancelled;
+        self.completed_at = Some(Utc::now());
+    }
+
     /// Update step counts based on current steps
     pub fn update_counts(&mut self, steps: &usize, completed: &usize, failed: &usize, running: &usize) {
         self.total_steps = *steps;
@@ -161,12 +318,12 @@ mod tests {
 
     #[test]
     fn test_step_state_is_terminal() {
-        assert!(StepState::Pending.is_terminal() == false);
-        assert!(StepState::Running {
+        assert!(!StepState::Pending.is_terminal());
+        assert!(!StepState::Running {
             started_at: Utc::now(),
             attempt: 1
         }
-        .is_terminal() == false);
+        .is_terminal());
         assert!(StepState::Completed {
             output: "test".to_string(),
             attempts: 1,
@@ -0,0 +1,308 @@
+//! Prompt templates
+//!
+//! The prompts of steps with `template: jinja` (set on the step or the
+//! pipeline) are rendered by minijinja, so they can use filters such as
+//! `{{ name | upper }}`, `{% if %}` and `{% for %}`. Other prompts only have
+//! their `{{ name }}` placeholders filled in by `substitute_variables`, so
+//! text that merely looks like template syntax is left as written. Dotted
+//! variable names such as `steps.plan.output`
+//! become nested values there, and a dashed segment such as the `quick-fix`
+//! of `steps.quick-fix.output` is read as part of the name, so subtraction
+//! needs spaces around its `-`.
+//!
+//! Rendering is strict about undefined variables: they are reported as
+//! missing, as unmatched placeholders are, and the text is rendered with
+//! them empty. `default(...)` and `is defined` handle a variable that may
+//! not be set.
+
+use crate::core::step::{RenderedPrompt, MAX_RENDERED_BYTES};
+use minijinja::{Environment, ErrorKind, UndefinedBehavior, Value};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+/// Instructions a render may run, so a runaway loop ends
+const MAX_FUEL: u64 = 10_000_000;
+
+/// Undefined variables checked one by one for whether the template relies
+/// on them; with more, all of them are reported
+const MAX_PROBED_UNDEFINED: usize = 8;
+
+/// Check that `text` parses as a template, describing the problem if not
+pub fn check(text: &str) -> Result<(), String> {
+    let source = with_dashed_names(text);
+    environment(UndefinedBehavior::SemiStrict)
+        .template_from_str(&source)
+        .map(|_| ())
+        .map_err(|e| describe(&e))
+}
+
+/// Render `template` against `variables`
+///
+/// `missing` lists undefined variables the template relies on and `error`
+/// says why it could not be rendered at all (the text is then the template
+/// itself).
+pub fn render(template: &str, variables: &HashMap<String, String>) -> RenderedPrompt {
+    let unrendered = |missing: Vec<String>, oversized: Option<usize>, error: Option<String>| RenderedPrompt {
+        text: template.to_string(),
+        substituted: Vec::new(),
+        missing,
+        oversized,
+        error,
+    };
+
+    let source = with_dashed_names(template);
+    let strict = environment(UndefinedBehavior::SemiStrict);
+    let compiled = match strict.template_from_str(&source) {
+        Ok(compiled) => compiled,
+        Err(e) => return unrendered(Vec::new(), None, Some(describe(&e))),
+    };
+    let globals: Vec<&str> = strict.globals().map(|(name, _)| name).collect();
+    let mut names: Vec<String> = compiled
+        .undeclared_variables(true)
+        .into_iter()
+        .filter(|name| !globals.contains(&name.split('.').next().unwrap_or_default()))
+        .collect();
+    names.sort();
+    let (substituted, undefined): (Vec<String>, Vec<String>) =
+        names.into_iter().partition(|name| is_defined(name, variables));
+
+    let context = Value::from_serialize(nested(variables));
+    let mut output = CappedOutput::default();
+    let rendered = compiled.render_captured_to(&context, &mut output).map(|_| ());
+    match rendered {
+        Ok(_) => RenderedPrompt {
+            text: output.text,
+            substituted,
+            missing: Vec::new(),
+            oversized: None,
+            error: None,
+        },
+        Err(_) if output.bytes > MAX_RENDERED_BYTES => unrendered(Vec::new(), Some(output.bytes), None),
+        Err(e) if e.kind() == ErrorKind::UndefinedError && !undefined.is_empty() => {
+            // Those the template relies on: a variable given a default (or
+            // only tested) is fine, and rendering still fails without it.
+            // Each check is a render, so past a few all of them count.
+            let undefined: Vec<String> = undefined
+                .iter()
+                .filter(|name| {
+                    if undefined.len() > MAX_PROBED_UNDEFINED {
+                        return true;
+                    }
+                    let mut probe = variables.clone();
+                    for other in undefined.iter().filter(|other| other != name) {
+                        probe.insert(other.clone(), String::new());
+                    }
+                    let context = Value::from_serialize(nested(&probe));
+                    compiled
+                        .render_captured_to(&context, &mut CappedOutput::default())
+                        .is_err_and(|e| e.kind() == ErrorKind::UndefinedError)
+                })
+                .cloned()
+                .collect();
+            if undefined.is_empty() {
+                return unrendered(Vec::new(), None, Some(describe(&e)));
+            }
+
+            // Render again with the undefined variables empty
+            let lenient = environment(UndefinedBehavior::Chainable);
+            let mut output = CappedOutput::default();
+            let rendered = lenient
+                .template_from_str(&source)
+                .and_then(|compiled| compiled.render_captured_to(&context, &mut output).map(|_| ()));
+            match rendered {
+                Ok(_) => RenderedPrompt {
+                    text: output.text,
+                    substituted,
+                    missing: undefined,
+                    oversized: None,
+                    error: None,
+                },
+                Err(_) if output.bytes > MAX_RENDERED_BYTES => unrendered(undefined, Some(output.bytes), None),
+                Err(e) => unrendered(undefined, None, Some(describe(&e))),
+            }
+        }
+        Err(e) => unrendered(Vec::new(), None, Some(describe(&e))),
+    }
+}
+
+fn environment(undefined: UndefinedBehavior) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(undefined);
+    env.set_keep_trailing_newline(true);
+    // Block tags on lines of their own leave no blank lines behind
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_fuel(Some(MAX_FUEL));
+    env
+}
+
+/// A render error on one line, with where it happened
+fn describe(error: &minijinja::Error) -> String {
+    let message = match error.detail() {
+        Some(detail) => format!("{}: {}", error.kind(), detail),
+        None => error.kind().to_string(),
+    };
+    match error.line() {
+        Some(line) => format!("{} (line {})", message, line),
+        None => message,
+    }
+}
+
+/// Whether `name` (possibly dotted) resolves: it is a variable, a prefix of
+/// some variable's name, or an attribute of a variable (such as a method)
+fn is_defined(name: &str, variables: &HashMap<String, String>) -> bool {
+    variables.contains_key(name)
+        || variables.keys().any(|key| key.strip_prefix(name).is_some_and(|rest| rest.starts_with('.')))
+        || name.match_indices('.').any(|(at, _)| variables.contains_key(&name[..at]))
+}
+
+/// Rewrite dashed attribute names in template tags as subscripts
+/// (`steps.quick-fix.output` to `steps["quick-fix"].output`)
+fn with_dashed_names(template: &str) -> String {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static NAME: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap());
+    // Quoted strings are matched so that names inside them are left alone
+    let name = NAME.get_or_init(|| {
+        Regex::new(r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)*'|\b[A-Za-z_]\w*(?:\.\w+(?:-\w+)*)+"#).unwrap()
+    });
+
+    tag.replace_all(template, |tag: &regex::Captures| {
+        name.replace_all(&tag[0], |name: &regex::Captures| {
+            let name = &name[0];
+            if name.starts_with(['"', '\'']) || !name.contains('-') {
+                return name.to_string();
+            }
+            let mut segments = name.split('.');
+            let mut rewritten = segments.next().unwrap_or_default().to_string();
+            for segment in segments {
+                if segment.contains('-') {
+                    rewritten.push_str(&format!("[\"{}\"]", segment));
+                } else {
+                    rewritten.push('.');
+                    rewritten.push_str(segment);
+                }
+            }
+            rewritten
+        })
+        .into_owned()
+    })
+    .into_owned()
+}
+
+/// A level of variables nested by the dots in their names
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Tree<'a> {
+    Value(&'a str),
+    Map(BTreeMap<&'a str, Tree<'a>>),
+}
+
+/// Variables nested by the dots in their names; where a name is both a
+/// variable and a prefix of others (`a` and `a.b`), the variable wins
+fn nested(variables: &HashMap<String, String>) -> Tree<'_> {
+    let mut sorted: Vec<_> = variables.iter().collect();
+    sorted.sort();
+
+    let mut root = BTreeMap::new();
+    'variables: for (name, value) in sorted {
+        let mut segments: Vec<&str> = name.split('.').collect();
+        let last = segments.pop().unwrap_or_default();
+        let mut level = &mut root;
+        for segment in segments {
+            match level.entry(segment).or_insert_with(|| Tree::Map(BTreeMap::new())) {
+                Tree::Map(next) => level = next,
+                Tree::Value(_) => continue 'variables,
+            }
+        }
+        level.entry(last).or_insert(Tree::Value(value));
+    }
+    Tree::Map(root)
+}
+
+/// Rendered text, refusing to grow past `MAX_RENDERED_BYTES`
+#[derive(Default)]
+struct CappedOutput {
+    text: String,
+    bytes: usize,
+}
+
+impl std::io::Write for CappedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.bytes += buf.len();
+        if self.bytes > MAX_RENDERED_BYTES {
+            return Err(std::io::Error::other("rendered text too large"));
+        }
+        // minijinja writes whole strings, so each write is valid UTF-8
+        self.text.push_str(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_templates_filter_default_and_loop() {
+        let vars = variables(&[
+            ("task", "fix the parser"),
+            ("files", "src/a.rs\nsrc/b.rs"),
+            ("steps.quick-fix.output", "patched"),
+        ]);
+        let template = "\
+{{ task | upper }} ({{ priority | default(\"normal\") }})
+{% for file in files | lines %}
+- {{ file }}
+{% endfor %}
+{% if steps.quick-fix.output is defined %}
+Build on: {{ steps.quick-fix.output }}
+{% endif %}
+";
+        let rendered = render(template, &vars);
+        assert_eq!(rendered.error, None);
+        assert!(rendered.missing.is_empty());
+        assert_eq!(
+            rendered.text,
+            "FIX THE PARSER (normal)\n- src/a.rs\n- src/b.rs\nBuild on: patched\n"
+        );
+        assert_eq!(rendered.substituted, vec!["files", "steps", "task"]);
+
+        // Quoted names stay as they are
+        assert_eq!(render("{{ \"a.b-c\" | upper }}", &vars).text, "A.B-C");
+    }
+
+    #[test]
+    fn test_template_problems_are_reported() {
+        let vars = variables(&[("task", "x")]);
+
+        let rendered = render("{{ task | upper }} for {{ target.name | lower }} {{ owner | default(\"me\") }}", &vars);
+        assert_eq!(rendered.missing, vec!["target.name"]);
+        assert_eq!(rendered.text, "X for  me");
+
+        let rendered = render("{% for x in items %}{{ x }}", &vars);
+        assert!(rendered.error.as_deref().is_some_and(|e| e.starts_with("syntax error")), "{:?}", rendered);
+        assert_eq!(rendered.text, "{% for x in items %}{{ x }}");
+        assert!(check("{% if task %}{{ task | upper }}{% endif %}").is_ok());
+        assert!(check("{{ task | }}").unwrap_err().contains("(line 1)"));
+
+        let rendered = render("{% for i in range(100) %}{{ big }}{% endfor %}", &variables(&[("big", &"x".repeat(100_000))]));
+        assert!(rendered.oversized.is_some_and(|bytes| bytes > MAX_RENDERED_BYTES));
+
+        // Past the probe limit, every undefined variable is reported
+        let template: String = (0..=MAX_PROBED_UNDEFINED).map(|i| format!("{{{{ v{} | default(\"\") }}}}", i)).collect();
+        let rendered = render(&format!("{}{{{{ needed | upper }}}}", template), &vars);
+        assert_eq!(rendered.missing.len(), MAX_PROBED_UNDEFINED + 2);
+        assert!(rendered.missing.contains(&"needed".to_string()));
+    }
+}
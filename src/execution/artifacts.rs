@@ -0,0 +1,188 @@
+//! Step artifacts (`artifacts: ["target/release/app", "reports/*.xml"]`)
+//!
+//! Once a step completes, the files its globs match are copied into the
+//! run's artifacts directory under `<step id>/`, keeping their path relative
+//! to the step's working directory; a file outside it keeps only its name.
+//! Each copy is recorded with its size and SHA-256 so that a later
+//! `history --extract` can check what it hands out, and a skipped
+//! incremental step can carry its earlier copies over intact. With
+//! provenance, copies of text files get a header naming the run (see
+//! `provenance`); the recorded hash is of the stamped copy.
+
+use crate::execution::provenance::Provenance;
+use crate::persistence::Artifact;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Files collected for a step, and the globs that matched none
+#[derive(Debug, Default)]
+pub struct Collected {
+    pub artifacts: Vec<Artifact>,
+    pub unmatched: Vec<String>,
+}
+
+/// Copy the files matching `patterns` (relative to `working_dir`) into
+/// `artifacts_dir/<step id>/`, stamping them with `provenance` if given
+pub fn collect(
+    step_id: &str,
+    patterns: &[String],
+    working_dir: &Path,
+    artifacts_dir: &Path,
+    provenance: Option<&Provenance>,
+) -> std::io::Result<Collected> {
+    let mut collected = Collected::default();
+    let mut files = BTreeSet::new();
+    for pattern in patterns {
+        let mut matched = false;
+        for path in matching_files(pattern, working_dir)? {
+            matched = true;
+            files.insert(path);
+        }
+        if !matched {
+            collected.unmatched.push(pattern.clone());
+        }
+    }
+
+    for source in files {
+        let relative = match source.strip_prefix(working_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => PathBuf::from(source.file_name().unwrap_or_default()),
+        };
+        let path = Path::new(step_id).join(relative);
+        let dest = artifacts_dir.join(&path);
+        let (size, sha256) = match provenance {
+            Some(provenance) => copy_stamped(&source, &dest, provenance)?,
+            None => copy_hashed(&source, &dest)?,
+        };
+        collected.artifacts.push(Artifact {
+            step_id: step_id.to_string(),
+            path: path.to_string_lossy().into_owned(),
+            source: source.to_string_lossy().into_owned(),
+            size,
+            sha256,
+            collected_at: Utc::now(),
+        });
+    }
+    Ok(collected)
+}
+
+/// Regular files matching a glob, which is taken relative to `working_dir`
+/// unless absolute
+pub fn matching_files(pattern: &str, working_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let full = if Path::new(pattern).is_absolute() {
+        pattern.to_string()
+    } else {
+        // The directory itself may contain glob characters
+        let base = glob::Pattern::escape(&working_dir.to_string_lossy());
+        format!("{}/{}", base.trim_end_matches('/'), pattern)
+    };
+    let paths = glob::glob(&full).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    Ok(paths.flatten().filter(|path| path.is_file()).collect())
+}
+
+/// Copy `source` to `dest` with a provenance header if its type takes one,
+/// returning the bytes written and their SHA-256
+fn copy_stamped(source: &Path, dest: &Path, provenance: &Provenance) -> std::io::Result<(u64, String)> {
+    let contents = std::fs::read(source)?;
+    let Some(stamped) = provenance.stamp(source, &contents) else {
+        return copy_hashed(source, dest);
+    };
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(dest, &stamped)?;
+    let sha256 = Sha256::digest(&stamped).iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok((stamped.len() as u64, sha256))
+}
+
+/// Copy `source` to `dest`, returning the bytes copied and their SHA-256
+pub fn copy_hashed(source: &Path, dest: &Path) -> std::io::Result<(u64, String)> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut reader = std::fs::File::open(source)?;
+    let mut writer = std::fs::File::create(dest)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+        size += read as u64;
+    }
+    let sha256 = hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok((size, sha256))
+}
+
+/// Copy artifacts an earlier run collected from its `from_dir` into
+/// `to_dir`, failing if any is gone or no longer has its recorded hash
+pub fn reuse(artifacts: &[Artifact], from_dir: &Path, to_dir: &Path) -> std::io::Result<()> {
+    for artifact in artifacts {
+        let source = from_dir.join(&artifact.path);
+        let dest = to_dir.join(&artifact.path);
+        let sha256 = if source == dest {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut std::fs::File::open(&source)?, &mut hasher)?;
+            hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+        } else {
+            copy_hashed(&source, &dest)?.1
+        };
+        if sha256 != artifact.sha256 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} changed after it was collected", artifact.path),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_files_are_copied_and_hashed() {
+        let work = tempfile::tempdir().unwrap();
+        let store = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(work.path().join("target/release")).unwrap();
+        std::fs::write(work.path().join("target/release/app"), "abc").unwrap();
+        std::fs::write(work.path().join("report.xml"), "<ok/>").unwrap();
+        std::fs::write(work.path().join("notes.txt"), "skip").unwrap();
+        let outside = tempfile::NamedTempFile::new().unwrap();
+
+        let patterns = vec![
+            "target/release/*".to_string(),
+            "*.xml".to_string(),
+            "report.*".to_string(),
+            outside.path().to_string_lossy().into_owned(),
+            "dist/*.tar.gz".to_string(),
+        ];
+        let collected = collect("build", &patterns, work.path(), store.path(), None).unwrap();
+
+        let mut paths: Vec<_> = collected.artifacts.iter().map(|a| a.path.clone()).collect();
+        paths.sort();
+        let outside_name = outside.path().file_name().unwrap().to_string_lossy();
+        let mut expected = vec![
+            "build/report.xml".to_string(),
+            "build/target/release/app".to_string(),
+            format!("build/{}", outside_name),
+        ];
+        expected.sort();
+        assert_eq!(paths, expected);
+        assert_eq!(collected.unmatched, vec!["dist/*.tar.gz".to_string()]);
+
+        let app = collected.artifacts.iter().find(|a| a.path.ends_with("app")).unwrap();
+        assert_eq!(app.size, 3);
+        // SHA-256 of "abc"
+        assert_eq!(app.sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(std::fs::read_to_string(store.path().join(&app.path)).unwrap(), "abc");
+    }
+}
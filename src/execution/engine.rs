@@ -1,27 +1,58 @@
 //! Main execution engine - orchestrates the entire pipeline run
 
+#[cfg(feature = "cli")]
+use crate::cli::terminal_output::TerminalOutputCallback;
 use crate::{
-    cli::terminal_output::TerminalOutputCallback,
-    core::{Pipeline, StepState, ExecutionStatus},
-    execution::{StepExecutor, ExecutionResult, ContinueAction, ExecutionScheduler, SchedulingStrategy},
-    agent::AgentExecutor,
+    core::{config::{FailurePolicy, Precedence, Telemetry}, locks, step::{substitute_variables, QueueOp, SubPipeline}, Pipeline, PipelineContext, Poll, Step, StepState, ExecutionStatus, ContextNote, RenderError},
+    execution::{artifacts, incremental::Inputs, preconditions, provenance::Provenance, step_logs::StepLogs, StepExecutor, ExecutionResult, ContinueAction, ExecutionScheduler, SchedulingStrategy},
+    persistence::{create_summary, PersistenceBackend, StepResult, ToolDecision},
+    agent::{AgentExecutor, ApprovalDecision, BufferedCallback, ReadOnlyApprover, STEP_ENV, STEP_ENV_PASSTHROUGH, STEP_WORKING_DIR, HeartbeatCallback, ProgressCallback, StepLogCallback, TextChunkCallback, ToolApprovalPolicy, ToolApprover, ToolApprovalCallback, UnknownEventCallback, UsageTrackingCallback, WriteTrackingCallback},
 };
-use tokio::sync::Mutex;
-use tracing::{info, warn, error};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, info, warn, error};
+use std::collections::{HashMap, HashSet};
+use super::handlers::{HandlerProblem, HandlerWorkers};
+use std::future::Future;
+use std::pin::Pin;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 /// Events that can occur during pipeline execution
-#[derive(Debug, Clone)]
+///
+/// Serialized with the variant's snake_case name under `type`, e.g.
+/// `{"type": "step_started", "step_id": "build", "attempt": 1}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum ExecutionEvent {
     PipelineStarted {
         execution_id: Uuid,
         pipeline_name: String,
     },
+    /// A resumed run starts with the steps it completed before it stopped
+    PipelineResumed {
+        completed_steps: Vec<String>,
+    },
     StepStarted {
         step_id: String,
         attempt: usize,
     },
+    PromptPreview {
+        step_id: String,
+        attempt: usize,
+        prompt: String,
+    },
+    /// Agent text as it arrives; a step's chunks come before its
+    /// `StepOutput`
+    StepOutputChunk {
+        step_id: String,
+        delta: String,
+    },
     StepOutput {
         step_id: String,
         output: String,
@@ -47,38 +78,887 @@ pub enum ExecutionEvent {
         from_step: String,
         to_step: String,
     },
+    RunOnceStepSkipped {
+        step_id: String,
+        from_step: String,
+    },
+    /// An incremental step's inputs were unchanged, so it completed with
+    /// the output of its last completed run instead of running
+    StepReused {
+        step_id: String,
+        from_execution: Uuid,
+    },
+    /// A step's `when:` condition did not hold, so it never ran
+    StepSkipped {
+        step_id: String,
+        reason: String,
+    },
+    ToolCallReviewed {
+        step_id: String,
+        tool_name: String,
+        approved: bool,
+    },
+    StepFilesWritten {
+        step_id: String,
+        bytes: u64,
+        files: usize,
+    },
+    ValueRemembered {
+        step_id: String,
+        key: String,
+        value: String,
+    },
+    StepWaiting {
+        step_id: String,
+        condition: String,
+    },
+    StepHeartbeatMissed {
+        step_id: String,
+        silent_secs: u64,
+    },
+    PollAttempt {
+        step_id: String,
+        attempt: usize,
+        passed: bool,
+    },
+    /// A `pipeline:` step started its child run
+    SubPipelineStarted {
+        step_id: String,
+        execution_id: Uuid,
+        pipeline_name: String,
+    },
+    /// A step of a `pipeline:` step's child run completed or failed
+    SubPipelineStepFinished {
+        step_id: String,
+        child_step_id: String,
+        error: Option<String>,
+    },
+    StepCost {
+        step_id: String,
+        cost_usd: f64,
+        tokens: u64,
+        budget: Option<BudgetUsage>,
+    },
+    AgentFailover {
+        step_id: String,
+        from: String,
+        to: String,
+        error: String,
+    },
+    Warning {
+        scope: WarningScope,
+        code: WarningCode,
+        message: String,
+    },
+    /// The run outlived `max_duration_secs` and was cancelled
+    PipelineTimedOut {
+        limit_secs: u64,
+        cancelled_steps: Vec<String>,
+    },
     PipelineCompleted {
         execution_id: Uuid,
         status: ExecutionStatus,
     },
 }
 
+impl ExecutionEvent {
+    /// Step the event is about, if any
+    fn step_id(&self) -> Option<&str> {
+        match self {
+            ExecutionEvent::StepStarted { step_id, .. }
+            | ExecutionEvent::PromptPreview { step_id, .. }
+            | ExecutionEvent::StepOutputChunk { step_id, .. }
+            | ExecutionEvent::StepOutput { step_id, .. }
+            | ExecutionEvent::StepCompleted { step_id, .. }
+            | ExecutionEvent::StepFailed { step_id, .. }
+            | ExecutionEvent::StepContinued { step_id, .. }
+            | ExecutionEvent::StepRetrying { step_id, .. }
+            | ExecutionEvent::RunOnceStepSkipped { step_id, .. }
+            | ExecutionEvent::StepReused { step_id, .. }
+            | ExecutionEvent::StepSkipped { step_id, .. }
+            | ExecutionEvent::ToolCallReviewed { step_id, .. }
+            | ExecutionEvent::StepFilesWritten { step_id, .. }
+            | ExecutionEvent::ValueRemembered { step_id, .. }
+            | ExecutionEvent::StepWaiting { step_id, .. }
+            | ExecutionEvent::StepHeartbeatMissed { step_id, .. }
+            | ExecutionEvent::PollAttempt { step_id, .. }
+            | ExecutionEvent::SubPipelineStarted { step_id, .. }
+            | ExecutionEvent::SubPipelineStepFinished { step_id, .. }
+            | ExecutionEvent::StepCost { step_id, .. }
+            | ExecutionEvent::AgentFailover { step_id, .. } => Some(step_id),
+            ExecutionEvent::StepRerouted { from_step, .. } => Some(from_step),
+            ExecutionEvent::Warning { scope: WarningScope::Step(step_id), .. } => Some(step_id),
+            _ => None,
+        }
+    }
+
+    /// The event as a step with this telemetry level may report it
+    ///
+    /// Below `full`, prompts, outputs and remembered values are dropped and
+    /// error text is withheld; at `none` only status changes remain.
+    fn minimized(self, telemetry: Telemetry) -> Option<Self> {
+        if telemetry.keeps_content() {
+            return Some(self);
+        }
+        let status_only = !telemetry.keeps_metadata();
+        match self {
+            ExecutionEvent::PromptPreview { .. }
+            | ExecutionEvent::StepOutputChunk { .. }
+            | ExecutionEvent::StepOutput { .. }
+            | ExecutionEvent::ValueRemembered { .. } => None,
+            ExecutionEvent::StepFailed { step_id, .. } => Some(ExecutionEvent::StepFailed {
+                step_id,
+                error: Telemetry::WITHHELD.to_string(),
+            }),
+            ExecutionEvent::SubPipelineStepFinished { step_id, child_step_id, error } => {
+                Some(ExecutionEvent::SubPipelineStepFinished {
+                    step_id,
+                    child_step_id,
+                    error: error.map(|_| Telemetry::WITHHELD.to_string()),
+                })
+            }
+            ExecutionEvent::ToolCallReviewed { .. }
+            | ExecutionEvent::StepFilesWritten { .. }
+            | ExecutionEvent::StepWaiting { .. }
+            | ExecutionEvent::StepHeartbeatMissed { .. }
+            | ExecutionEvent::PollAttempt { .. }
+            | ExecutionEvent::StepCost { .. }
+            | ExecutionEvent::AgentFailover { .. }
+            | ExecutionEvent::Warning { .. }
+                if status_only =>
+            {
+                None
+            }
+            ExecutionEvent::AgentFailover { step_id, from, to, .. } => Some(ExecutionEvent::AgentFailover {
+                step_id,
+                from,
+                to,
+                error: Telemetry::WITHHELD.to_string(),
+            }),
+            event => Some(event),
+        }
+    }
+}
+
+/// What a warning applies to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningScope {
+    Pipeline,
+    Step(String),
+}
+
+impl std::fmt::Display for WarningScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WarningScope::Pipeline => write!(f, "pipeline"),
+            WarningScope::Step(step_id) => write!(f, "{}", step_id),
+        }
+    }
+}
+
+/// Machine-readable warning kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    /// Prompt references variables that are not defined
+    MissingVariables,
+    /// Step used most of its timeout
+    SlowStep,
+    /// Pipeline finished with steps that never ran
+    UnreachedSteps,
+    /// An event handler did not return in time
+    SlowEventHandler,
+    /// An event handler panicked
+    EventHandlerPanicked,
+    /// Pipeline outputs reference results the run never produced
+    UnresolvedOutputs,
+    /// A step wrote files outside its declared path locks
+    UnlockedWrites,
+    /// The agent sent event types this version does not understand
+    UnknownAgentEvents,
+    /// Progress could not be saved to the persistence backend
+    PersistenceFailed,
+    /// A step's artifacts could not all be collected
+    MissingArtifacts,
+    /// An incremental step's inputs could not be read, so it ran regardless
+    UnreadableInputs,
+    /// A step output injected into the prompt carries the step's own marker
+    InjectedMarkers,
+    /// An output matched both the success and the continuation pattern
+    BothPatternsMatched,
+}
+
+impl WarningCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WarningCode::MissingVariables => "missing_variables",
+            WarningCode::SlowStep => "slow_step",
+            WarningCode::UnreachedSteps => "unreached_steps",
+            WarningCode::SlowEventHandler => "slow_event_handler",
+            WarningCode::EventHandlerPanicked => "event_handler_panicked",
+            WarningCode::UnresolvedOutputs => "unresolved_outputs",
+            WarningCode::UnlockedWrites => "unlocked_writes",
+            WarningCode::UnknownAgentEvents => "unknown_agent_events",
+            WarningCode::PersistenceFailed => "persistence_failed",
+            WarningCode::MissingArtifacts => "missing_artifacts",
+            WarningCode::UnreadableInputs => "unreadable_inputs",
+            WarningCode::InjectedMarkers => "injected_markers",
+            WarningCode::BothPatternsMatched => "both_patterns_matched",
+        }
+    }
+}
+
+/// How often `wait_for` conditions are re-checked
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a silent agent's event stream is checked against its heartbeat
+const HEARTBEAT_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often a running step checks whether the operator interrupted it
+const INTERRUPT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often a run waiting for its running steps rescans for ready steps
+/// and checks whether it is stuck, even without a readiness notification
+const STUCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default time an event handler may take before it is reported and skipped
+pub const DEFAULT_HANDLER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time agent text is gathered for before it is emitted as one
+/// `StepOutputChunk`, so handlers see a few events a second rather than one
+/// per token
+pub const DEFAULT_OUTPUT_CHUNK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Fraction of a step's timeout after which it is reported as slow
+const SLOW_STEP_THRESHOLD: f64 = 0.8;
+
+/// Variable holding the running step's scratch directory
+pub const STEP_TMP_DIR_VARIABLE: &str = "step.tmp_dir";
+
+/// Environment variable holding the running step's scratch directory
+pub const STEP_TMP_DIR_ENV: &str = "PIPELINE_STEP_TMP_DIR";
+
+/// Variable holding the directory the running step's agent works in, when
+/// the step sets `working_dir`
+pub const STEP_WORKING_DIR_VARIABLE: &str = "step.working_dir";
+
+/// Spending in a budget category after a step
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BudgetUsage {
+    pub category: String,
+    pub spent_usd: f64,
+    pub limit_usd: f64,
+}
+
+/// Name of an event's variant, for messages
+fn event_kind(event: &ExecutionEvent) -> String {
+    let debug = format!("{:?}", event);
+    debug.split([' ', '{', '(']).next().unwrap_or_default().to_string()
+}
+
+/// A running child pipeline, resolving to the pipeline and its result
+type ChildRun = Pin<Box<dyn Future<Output = (Pipeline, Result<(), String>)> + Send>>;
+
+/// Run a child pipeline on its own engine, handing the pipeline back with
+/// the result
+///
+/// Boxed, as the child's steps may run pipelines of their own.
+fn run_child<A: AgentExecutor + Send + Sync + 'static>(
+    engine: ExecutionEngine<A>,
+    mut pipeline: Pipeline,
+) -> ChildRun {
+    Box::pin(async move {
+        let result = engine.execute(&mut pipeline).await;
+        (pipeline, result)
+    })
+}
+
+/// Run `future`, returning the message of a panic while polling it as an error
+async fn catch_panic<F: Future>(future: F) -> Result<F::Output, String> {
+    let mut future = std::pin::pin!(future);
+    std::future::poll_fn(|cx| {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(std::task::Poll::Ready(output)) => std::task::Poll::Ready(Ok(output)),
+            Ok(std::task::Poll::Pending) => std::task::Poll::Pending,
+            Err(payload) => std::task::Poll::Ready(Err(panic_message(payload.as_ref()))),
+        }
+    })
+    .await
+}
+
+/// Text a panic was raised with
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string())
+}
+
+/// A step marked running and ready to run, with what it runs on
+struct StartedStep {
+    step: Step,
+    attempt: usize,
+    execution_id: Uuid,
+    work: StepWork,
+}
+
+/// What a started step runs
+enum StepWork {
+    Poll(Poll),
+    /// The child pipeline, with the step's rendering variables
+    SubPipeline(SubPipeline, HashMap<String, String>),
+    /// The queue operation, with the step's rendering variables
+    Queue(QueueOp, HashMap<String, String>),
+    Agent(AgentWork),
+}
+
+/// An agent step's rendered context and scratch directory, and the budget
+/// it may spend
+struct AgentWork {
+    context: PipelineContext,
+    tmp_dir: tempfile::TempDir,
+    budget: Option<(String, f64)>,
+    remaining_budget: Option<f64>,
+    /// Steps in the pipeline, for the live display
+    #[cfg(feature = "cli")]
+    step_count: usize,
+}
+
+/// A step that ran, with its result
+struct FinishedStep {
+    step: Step,
+    attempt: usize,
+    result: ExecutionResult,
+    /// What the agent used and wrote, for agent steps
+    usage: Option<AgentUsage>,
+}
+
+/// What an agent step's attempt spent and wrote
+struct AgentUsage {
+    budget: Option<(String, f64)>,
+    cost_usd: f64,
+    tokens: u64,
+    bytes: u64,
+    files: usize,
+    paths_written: Vec<String>,
+    unknown_events: Vec<(String, usize)>,
+    elapsed: Duration,
+    working_dir: Option<PathBuf>,
+}
+
+/// Steps of a batch that are running, each resolving to its ID and what it
+/// finished with (or the message it panicked with)
+type InFlight<'a> = Vec<Pin<Box<dyn Future<Output = (String, Result<FinishedStep, String>)> + Send + 'a>>>;
+
+/// Poll the running steps, moving those that finished to `finished`
+fn poll_in_flight(
+    in_flight: &mut InFlight<'_>,
+    finished: &mut Vec<(String, Result<FinishedStep, String>)>,
+    cx: &mut std::task::Context<'_>,
+) {
+    let mut i = 0;
+    while i < in_flight.len() {
+        match in_flight[i].as_mut().poll(cx) {
+            std::task::Poll::Ready(done) => {
+                finished.push(done);
+                drop(in_flight.remove(i));
+            }
+            std::task::Poll::Pending => i += 1,
+        }
+    }
+}
+
+/// Run `future` while the running steps keep going, so starting or
+/// finishing one step does not hold up the others
+async fn alongside<F: Future>(
+    in_flight: &mut InFlight<'_>,
+    finished: &mut Vec<(String, Result<FinishedStep, String>)>,
+    future: F,
+) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    std::future::poll_fn(|cx| {
+        poll_in_flight(in_flight, finished, cx);
+        future.as_mut().poll(cx)
+    })
+    .await
+}
+
+/// Wait until at least one running step has finished
+async fn next_finished(in_flight: &mut InFlight<'_>, finished: &mut Vec<(String, Result<FinishedStep, String>)>) {
+    std::future::poll_fn(|cx| {
+        poll_in_flight(in_flight, finished, cx);
+        if finished.is_empty() {
+            std::task::Poll::Pending
+        } else {
+            std::task::Poll::Ready(())
+        }
+    })
+    .await
+}
+
 /// Type for event handlers
 pub type EventHandler = Arc<dyn Fn(ExecutionEvent) + Send + Sync>;
 
+/// Receiver end of `ExecutionEngine::subscribe_filtered`
+pub type EventReceiver = tokio::sync::mpsc::UnboundedReceiver<ExecutionEvent>;
+
+/// A subscription: the events it wants and where to send them
+struct Subscriber {
+    filter: Box<dyn Fn(&ExecutionEvent) -> bool + Send + Sync>,
+    sender: tokio::sync::mpsc::UnboundedSender<ExecutionEvent>,
+}
+
+/// Check made before each step starts, holding it back while something
+/// else has precedence (e.g. runs of a higher priority)
+#[async_trait::async_trait]
+pub trait StepGate: Send + Sync {
+    /// Why steps must wait right now, or `None` to go ahead
+    async fn hold_reason(&self) -> Option<String>;
+}
+
+/// What to do with a step the operator interrupted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SteeringAction {
+    /// Run the step again
+    Retry,
+    /// Leave the step out and carry on with the rest of the pipeline
+    Skip,
+    /// Stop the run; it ends `Cancelled`
+    Abort,
+}
+
+/// What the operator gets to see of an interrupted step
+#[derive(Debug, Clone)]
+pub struct Interruption {
+    pub step_id: String,
+    /// Output the agent returned before it was stopped
+    pub accumulated_output: String,
+    /// Last lines the agent streamed
+    pub recent_lines: Vec<String>,
+}
+
+/// What to do with a failed step, as chosen by the operator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureAction {
+    /// Run the step again, even past its retry limit
+    Retry,
+    /// Run the step again with a new prompt template
+    RetryWithPrompt(String),
+    /// Leave the step out and carry on with the rest of the pipeline
+    Skip,
+    /// Fail the step and run this one next, as an `on_failure` route would
+    Route(String),
+    /// Stop the run; it ends `Cancelled`
+    Abort,
+    /// What the pipeline says: follow `on_failure`, or fail the step under
+    /// the failure policy
+    Default,
+}
+
+/// What the operator gets to see of a failed step
+#[derive(Debug, Clone)]
+pub struct StepFailure {
+    pub step_id: String,
+    pub error: String,
+    /// Attempts made so far
+    pub attempts: usize,
+    /// The step's prompt template, as a starting point for editing
+    pub prompt_template: String,
+    /// Where the step's `on_failure` route leads, if it has one
+    pub on_failure: Option<String>,
+}
+
+/// Decides what happens to an interrupted or failed step (e.g. by asking
+/// the operator)
+///
+/// Called on a blocking thread, so it may wait for input.
+pub trait Steering: Send + Sync {
+    fn steer(&self, interruption: &Interruption) -> SteeringAction;
+
+    /// Decide what happens to a failed step, answering within `timeout`
+    /// (`FailureAction::Default` once it has passed)
+    fn steer_failure(&self, _failure: &StepFailure, _timeout: Duration) -> FailureAction {
+        FailureAction::Default
+    }
+}
+
+/// Token that cancels a running pipeline from another task
+///
+/// Obtained from `ExecutionEngine::cancel_handle()`; clones share the token.
+/// Cancelling stops the steps in flight (killing their agents) and ends the
+/// run `Cancelled`; `cancel_after_current` lets them finish first. Once
+/// cancelled, the engine stays cancelled.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    reason: Arc<tokio::sync::watch::Sender<Option<String>>>,
+    stop_reason: Arc<tokio::sync::watch::Sender<Option<String>>>,
+}
+
+impl CancelHandle {
+    fn new() -> Self {
+        Self {
+            reason: Arc::new(tokio::sync::watch::channel(None).0),
+            stop_reason: Arc::new(tokio::sync::watch::channel(None).0),
+        }
+    }
+
+    /// Start no more steps, giving the reason reported for it
+    ///
+    /// The steps in flight finish and are recorded, then the run ends
+    /// `Cancelled` with the steps that never started left pending (so it
+    /// can be resumed). Only the first reason is kept.
+    pub fn cancel_after_current(&self, reason: impl Into<String>) {
+        let reason = reason.into();
+        self.stop_reason.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        });
+    }
+
+    /// Whether the run was told to stop after its current steps
+    pub fn is_stopping(&self) -> bool {
+        self.stop_reason.borrow().is_some()
+    }
+
+    /// Why the run was told to stop after its current steps, if it was
+    fn stop_reason(&self) -> Option<String> {
+        self.stop_reason.borrow().clone()
+    }
+
+    /// Wait until the run is told to stop after its current steps
+    async fn stopping(&self) {
+        let _ = self.stop_reason.subscribe().wait_for(Option::is_some).await;
+    }
+
+    /// Cancel the run, giving the reason reported for it
+    ///
+    /// Only the first reason is kept.
+    pub fn cancel(&self, reason: impl Into<String>) {
+        let reason = reason.into();
+        self.reason.send_if_modified(|current| {
+            if current.is_some() {
+                return false;
+            }
+            *current = Some(reason);
+            true
+        });
+    }
+
+    /// Why the run was cancelled, if it was
+    pub fn reason(&self) -> Option<String> {
+        self.reason.borrow().clone()
+    }
+
+    /// Wait until the run is cancelled
+    async fn cancelled(&self) {
+        let _ = self.reason.subscribe().wait_for(Option::is_some).await;
+    }
+}
+
+/// Cancels a child run when dropped, so it stops along with the step that
+/// started it
+struct CancelOnDrop(CancelHandle);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel("Cancelled: the run that started it stopped");
+    }
+}
+
+/// Engine options
+///
+/// Start from `EngineConfig::default()` and set options with the `with_*`
+/// methods, so adding an option doesn't change existing call sites.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EngineConfig {
+    /// How ready steps are scheduled
+    pub strategy: SchedulingStrategy,
+
+    /// Show agent thinking in the terminal output
+    pub show_thinking: bool,
+
+    /// Upper bound on steps running at once (applies to parallel strategies)
+    pub max_parallel: Option<usize>,
+
+    /// Cap on agent text rendered to the terminal, in bytes per second
+    pub max_output_rate: Option<usize>,
+
+    /// Render agent output to the terminal as it streams (off when
+    /// something else draws the screen, e.g. the dashboard)
+    pub terminal_output: bool,
+
+    /// Emit each step's rendered prompt (redacted) before it runs
+    pub prompt_preview: bool,
+
+    /// Time an event handler may take per event
+    pub handler_timeout: Duration,
+
+    /// Time agent text is gathered for before it is emitted as a
+    /// `StepOutputChunk` (zero: as soon as handlers are free)
+    pub output_chunk_interval: Duration,
+
+    /// Run incremental steps even when their inputs are unchanged
+    pub force: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SchedulingStrategy::Sequential,
+            show_thinking: false,
+            max_parallel: None,
+            max_output_rate: None,
+            terminal_output: true,
+            prompt_preview: false,
+            handler_timeout: DEFAULT_HANDLER_TIMEOUT,
+            output_chunk_interval: DEFAULT_OUTPUT_CHUNK_INTERVAL,
+            force: false,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Set the scheduling strategy
+    pub fn with_strategy(mut self, strategy: SchedulingStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Show or hide agent thinking
+    pub fn with_show_thinking(mut self, show_thinking: bool) -> Self {
+        self.show_thinking = show_thinking;
+        self
+    }
+
+    /// Cap the number of steps running at once
+    pub fn with_max_parallel(mut self, max_parallel: usize) -> Self {
+        self.max_parallel = Some(max_parallel.max(1));
+        self
+    }
+
+    /// Limit how much agent text is rendered per second
+    pub fn with_max_output_rate(mut self, bytes_per_sec: Option<usize>) -> Self {
+        self.max_output_rate = bytes_per_sec;
+        self
+    }
+
+    /// Render or don't render agent output to the terminal
+    pub fn with_terminal_output(mut self, terminal_output: bool) -> Self {
+        self.terminal_output = terminal_output;
+        self
+    }
+
+    /// Preview each step's prompt before it runs
+    pub fn with_prompt_preview(mut self, prompt_preview: bool) -> Self {
+        self.prompt_preview = prompt_preview;
+        self
+    }
+
+    /// Limit how long an event handler may take per event
+    pub fn with_handler_timeout(mut self, timeout: Duration) -> Self {
+        self.handler_timeout = timeout;
+        self
+    }
+
+    /// Gather agent text for `interval` before emitting it as one chunk
+    pub fn with_output_chunk_interval(mut self, interval: Duration) -> Self {
+        self.output_chunk_interval = interval;
+        self
+    }
+
+    /// Run incremental steps even when their inputs are unchanged
+    pub fn with_force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Scheduling strategy with `max_parallel` applied
+    pub fn effective_strategy(&self) -> SchedulingStrategy {
+        match (self.strategy, self.max_parallel) {
+            (SchedulingStrategy::Parallel, Some(max)) => SchedulingStrategy::LimitedParallel(max),
+            (SchedulingStrategy::LimitedParallel(limit), Some(max)) => {
+                SchedulingStrategy::LimitedParallel(limit.min(max))
+            }
+            (strategy, _) => strategy,
+        }
+    }
+}
+
 /// Main pipeline execution engine
 pub struct ExecutionEngine<A> {
     scheduler: Arc<Mutex<ExecutionScheduler>>,
-    executor: Arc<StepExecutor<A>>,
+    executor: StepExecutor<A>,
     event_handlers: Arc<Mutex<Vec<EventHandler>>>,
+    handler_timeout: Duration,
+    output_chunk_interval: Duration,
+    /// Threads the handlers run on
+    handler_workers: HandlerWorkers,
+    /// Terminal display settings, which only the `cli` feature uses
     show_thinking: bool,
+    max_output_rate: Option<usize>,
+    terminal_output: bool,
+    prompt_preview: bool,
+    force: bool,
+    tool_approval: Option<(ToolApprovalPolicy, Arc<dyn ToolApprover>)>,
+    denied_tools: Mutex<HashMap<String, Vec<String>>>,
+    step_gates: Vec<Arc<dyn StepGate>>,
+    /// Telemetry level of each step of the running pipeline that is not `full`
+    telemetry: std::sync::Mutex<HashMap<String, Telemetry>>,
+    cancel: CancelHandle,
+    subscribers: std::sync::Mutex<Vec<Subscriber>>,
+    /// Flag the operator sets to interrupt the running step, and what
+    /// decides the step's fate then
+    interrupt: Option<(Arc<AtomicBool>, Arc<dyn Steering>)>,
+    /// How long the operator gets to rescue a failed step, and what asks them
+    failure_prompt: Option<(Duration, Arc<dyn Steering>)>,
+    /// Where the run's summary, step results and remembered values are saved
+    persistence: Option<Arc<dyn PersistenceBackend>>,
+    /// Where step artifacts are collected (default: the run directory's `artifacts/`)
+    artifacts_dir: Option<PathBuf>,
+    /// Model each step runs on, by step ID, for provenance headers
+    step_models: HashMap<String, String>,
+    /// Where each step's streamed output is logged, if anywhere
+    step_logs: Option<StepLogs>,
 }
 
 impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
-    pub fn new(
-        agent: A,
-        strategy: SchedulingStrategy,
-        show_thinking: bool,
-    ) -> Self {
-        let executor = Arc::new(StepExecutor::new(agent));
-        let scheduler = Arc::new(Mutex::new(ExecutionScheduler::new(strategy)));
+    /// Create an engine with default options and the given strategy
+    pub fn new(agent: A, strategy: SchedulingStrategy) -> Self {
+        Self::with_config(agent, EngineConfig::default().with_strategy(strategy))
+    }
+
+    /// Create an engine from a full configuration
+    pub fn with_config(agent: A, config: EngineConfig) -> Self {
+        let executor = StepExecutor::new(agent);
+        let scheduler = Arc::new(Mutex::new(ExecutionScheduler::new(config.effective_strategy())));
 
         Self {
             scheduler,
             executor,
             event_handlers: Arc::new(Mutex::new(Vec::new())),
-            show_thinking,
+            handler_timeout: config.handler_timeout,
+            output_chunk_interval: config.output_chunk_interval,
+            handler_workers: HandlerWorkers::new(config.handler_timeout),
+            show_thinking: config.show_thinking,
+            max_output_rate: config.max_output_rate,
+            terminal_output: config.terminal_output,
+            prompt_preview: config.prompt_preview,
+            force: config.force,
+            tool_approval: None,
+            denied_tools: Mutex::new(HashMap::new()),
+            step_gates: Vec::new(),
+            telemetry: std::sync::Mutex::new(HashMap::new()),
+            cancel: CancelHandle::new(),
+            subscribers: std::sync::Mutex::new(Vec::new()),
+            interrupt: None,
+            failure_prompt: None,
+            persistence: None,
+            artifacts_dir: None,
+            step_models: HashMap::new(),
+            step_logs: None,
+        }
+    }
+
+    /// Save the run's progress to `store` as it goes
+    ///
+    /// The summary is saved as running when the run starts and updated with
+    /// each step's result as the step finishes; the final summary, every
+    /// step's result and the values steps remembered are saved when the run
+    /// ends. A save that fails is reported as a `persistence_failed` warning
+    /// and the run carries on.
+    pub fn with_persistence(mut self, store: Arc<dyn PersistenceBackend>) -> Self {
+        self.persistence = Some(store);
+        self
+    }
+
+    /// Collect step artifacts into `dir` instead of the run directory
+    pub fn with_artifacts_dir(mut self, dir: PathBuf) -> Self {
+        self.artifacts_dir = Some(dir);
+        self
+    }
+
+    /// Write each step's streamed output to a log file under `logs.dir`
+    ///
+    /// A log that can't be written is reported and the step carries on.
+    pub fn with_step_logs(mut self, logs: StepLogs) -> Self {
+        self.step_logs = Some(logs);
+        self
+    }
+
+    /// Name the model each step runs on, by step ID, in the provenance
+    /// headers of its artifacts
+    pub fn with_step_models(mut self, models: HashMap<String, String>) -> Self {
+        self.step_models = models;
+        self
+    }
+
+    /// Let the operator interrupt the running step by setting `flag`
+    ///
+    /// The step's agent is stopped and `steering` chooses whether to retry
+    /// the step, skip it or abort the run. The flag is cleared once it has.
+    pub fn with_interrupts(mut self, flag: Arc<AtomicBool>, steering: Arc<dyn Steering>) -> Self {
+        self.interrupt = Some((flag, steering));
+        self
+    }
+
+    /// Let the operator rescue a failed step within `timeout`
+    ///
+    /// `steering` may retry the step (optionally with an edited prompt), skip
+    /// it, route to another step or abort the run. Without an answer in
+    /// time, the failure takes its configured course.
+    pub fn with_failure_prompt(mut self, timeout: Duration, steering: Arc<dyn Steering>) -> Self {
+        self.failure_prompt = Some((timeout, steering));
+        self
+    }
+
+    /// Require operator approval for the tools covered by `policy`
+    ///
+    /// A denied tool call aborts the running agent and the step is retried
+    /// with a note telling the agent not to use that tool.
+    pub fn with_tool_approval(
+        mut self,
+        policy: ToolApprovalPolicy,
+        approver: Arc<dyn ToolApprover>,
+    ) -> Self {
+        if !policy.is_empty() {
+            self.tool_approval = Some((policy, approver));
         }
+        self
+    }
+
+    /// Check `gate` before each step starts and wait while it holds steps back
+    ///
+    /// A running step is never interrupted; the gate only delays the next one.
+    /// With several gates, a step waits while any of them holds it.
+    pub fn with_step_gate(mut self, gate: Arc<dyn StepGate>) -> Self {
+        self.step_gates.push(gate);
+        self
+    }
+
+    /// Register the named agent profiles steps can list under `agents:`
+    pub fn with_agent_profiles(mut self, profiles: HashMap<String, Arc<dyn AgentExecutor>>) -> Self {
+        self.executor = self.executor.with_profiles(profiles);
+        self
+    }
+
+    /// Register the agents of steps that set their own `agent:`, by step ID
+    ///
+    /// Other steps keep running on the default agent.
+    pub fn with_step_agents(mut self, step_agents: HashMap<String, Arc<dyn AgentExecutor>>) -> Self {
+        self.executor = self.executor.with_step_agents(step_agents);
+        self
+    }
+
+    /// Token that cancels this engine's run from another task (e.g. a
+    /// signal handler)
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
     }
 
     /// Add an event handler
@@ -92,39 +972,260 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
         });
     }
 
-    /// Emit an event to all handlers
+    /// Receive the events `filter` accepts, for as long as the receiver is kept
+    ///
+    /// Events arrive in the order they were emitted, cut down to the step's
+    /// telemetry level like those given to handlers. Only accepted events
+    /// are cloned, and a slow receiver never holds up the run.
+    pub fn subscribe_filtered<F>(&self, filter: F) -> EventReceiver
+    where
+        F: Fn(&ExecutionEvent) -> bool + Send + Sync + 'static,
+    {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(Subscriber {
+            filter: Box::new(filter),
+            sender,
+        });
+        receiver
+    }
+
+    /// Send an event to the subscribers that want it, dropping those whose
+    /// receiver is gone
+    fn publish(&self, event: &ExecutionEvent) {
+        self.subscribers.lock().unwrap().retain(|subscriber| {
+            !(subscriber.filter)(event) || subscriber.sender.send(event.clone()).is_ok()
+        });
+    }
+
+    /// Emit an event to all handlers and subscribers
+    ///
+    /// Events about a step are first cut down to what its telemetry level
+    /// allows. A handler that panics or doesn't return in time is reported
+    /// with a warning to the other handlers; dispatch carries on without
+    /// waiting for it, and it gets its events late (see `handlers`).
     async fn emit_event(&self, event: ExecutionEvent) {
-        let handlers = self.event_handlers.lock().await;
-        for handler in handlers.iter() {
-            handler(event.clone());
+        let telemetry = event
+            .step_id()
+            .and_then(|step_id| self.telemetry.lock().unwrap().get(step_id).copied())
+            .unwrap_or_default();
+        let Some(event) = event.minimized(telemetry) else {
+            return;
+        };
+        self.publish(&event);
+        let handlers = self.event_handlers.lock().await.clone();
+        let mut problems = self.handler_workers.deliver(&handlers, &event, &event_kind(&event), None).await;
+        // Taken here rather than by the deliveries of the warnings, whose
+        // problems are only logged
+        problems.extend(self.handler_workers.late_panics());
+        self.report_handler_problems(&handlers, problems).await;
+    }
+
+    /// Warn the other handlers about handlers that misbehaved
+    ///
+    /// Problems while handling these warnings are only logged.
+    async fn report_handler_problems(&self, handlers: &[EventHandler], problems: Vec<HandlerProblem>) {
+        for problem in problems {
+            let (code, message) = self.handler_problem_warning(&problem);
+            warn!("{}", message);
+            let warning = ExecutionEvent::Warning {
+                scope: WarningScope::Pipeline,
+                code,
+                message,
+            };
+            self.publish(&warning);
+            let skip = Some(problem.index());
+            for other in self.handler_workers.deliver(handlers, &warning, "Warning", skip).await {
+                warn!("{}", self.handler_problem_warning(&other).1);
+            }
+        }
+    }
+
+    fn handler_problem_warning(&self, problem: &HandlerProblem) -> (WarningCode, String) {
+        match problem {
+            HandlerProblem::Slow { index, event } => (
+                WarningCode::SlowEventHandler,
+                format!(
+                    "Event handler #{} did not return within {}s handling {}; its events are queued until it catches up",
+                    index + 1,
+                    self.handler_timeout.as_secs_f64(),
+                    event
+                ),
+            ),
+            HandlerProblem::Panicked { index, event } => (
+                WarningCode::EventHandlerPanicked,
+                format!("Event handler #{} panicked handling {}", index + 1, event),
+            ),
+            HandlerProblem::Stuck { index } => (
+                WarningCode::SlowEventHandler,
+                format!(
+                    "Event handler #{} handled no events for {}s; it gets no more events from this run",
+                    index + 1,
+                    self.handler_timeout.as_secs_f64()
+                ),
+            ),
+        }
+    }
+
+    /// Wait for the handlers to handle every event emitted so far
+    async fn flush_handlers(&self) {
+        let handlers = self.event_handlers.lock().await.clone();
+        let problems = self.handler_workers.flush().await;
+        self.report_handler_problems(&handlers, problems).await;
+        for problem in self.handler_workers.flush().await {
+            warn!("{}", self.handler_problem_warning(&problem).1);
         }
     }
 
     /// Execute the entire pipeline
+    ///
+    /// A run cancelled through its `cancel_handle()`, or still going when
+    /// `max_duration_secs` runs out, stops its in-flight steps, ends
+    /// `Cancelled` and returns the reason as an error.
     pub async fn execute(&self, pipeline: &mut Pipeline) -> Result<(), String> {
+        let result = self.run(pipeline).await;
+        self.save_run(pipeline).await;
+        self.flush_handlers().await;
+        result
+    }
+
+    /// Run the pipeline until it finishes or is cancelled
+    async fn run(&self, pipeline: &mut Pipeline) -> Result<(), String> {
         let execution_id = pipeline.state.execution_id;
         let pipeline_name = pipeline.name.clone();
         let pipeline_name_ref = pipeline_name.as_str();
 
         info!("Starting pipeline execution: {} ({})", pipeline_name_ref, execution_id);
+        *self.telemetry.lock().unwrap() = pipeline
+            .steps
+            .values()
+            .filter(|step| !step.telemetry.keeps_content())
+            .map(|step| (step.id.clone(), step.telemetry))
+            .collect();
         self.emit_event(ExecutionEvent::PipelineStarted {
             execution_id,
             pipeline_name: pipeline_name.clone(),
         })
         .await;
+        if let Some(logs) = &self.step_logs {
+            if let Err(e) = logs.start_run(execution_id) {
+                warn!("Could not prepare step logs in {}: {}", logs.dir.display(), e);
+            }
+        }
 
         pipeline.state.start(pipeline.steps.len());
+        self.save_progress(pipeline, &[]).await;
 
-        // Main execution loop
-        while !pipeline.is_complete() && !pipeline.has_failed() {
-            // Get next steps to run
-            let step_ids = {
-                let scheduler = self.scheduler.lock().await;
-                scheduler.next_steps(pipeline)
-            };
+        // Steps already completed (a resumed run) are not run again
+        let completed_steps: Vec<String> = pipeline
+            .execution_order()
+            .iter()
+            .filter(|id| pipeline.step(id).is_some_and(|s| matches!(s.state, StepState::Completed { .. })))
+            .cloned()
+            .collect();
+        if !completed_steps.is_empty() {
+            self.update_state_counts(pipeline);
+            self.emit_event(ExecutionEvent::PipelineResumed { completed_steps }).await;
+        }
 
-            if step_ids.is_empty() {
-                // Check if we're stuck (running but no progress)
+        let limit = pipeline.max_duration;
+        // Cancellation wins over steps finishing at the same time
+        let timed_out = tokio::select! {
+            biased;
+            _ = self.cancel.cancelled() => false,
+            _ = tokio::time::sleep(limit.unwrap_or_default()), if limit.is_some() => true,
+            result = self.run_steps(pipeline) => return result,
+        };
+
+        // The in-flight steps' futures were dropped above, killing their agents
+        let reason = match limit {
+            Some(limit) if timed_out => {
+                warn!("Pipeline {} exceeded max_duration_secs ({}s)", pipeline_name_ref, limit.as_secs());
+                format!("Cancelled: pipeline ran longer than max_duration_secs ({}s)", limit.as_secs())
+            }
+            _ => self.cancel.reason().unwrap_or_else(|| "Cancelled".to_string()),
+        };
+        let cancelled_steps = self.cancel_running_steps(pipeline, &reason);
+        pipeline.state.cancel();
+        self.materialize_outputs(pipeline).await;
+        if let Some(limit) = limit.filter(|_| timed_out) {
+            self.emit_event(ExecutionEvent::PipelineTimedOut {
+                limit_secs: limit.as_secs(),
+                cancelled_steps,
+            })
+            .await;
+        }
+        self.emit_event(ExecutionEvent::PipelineCompleted {
+            execution_id,
+            status: ExecutionStatus::Cancelled,
+        })
+        .await;
+        Err(reason)
+    }
+
+    /// Run steps until the pipeline completes, fails or gets stuck
+    async fn run_steps(&self, pipeline: &mut Pipeline) -> Result<(), String> {
+        let execution_id = pipeline.state.execution_id;
+        let pipeline_name = pipeline.name.clone();
+        let pipeline_name_ref = pipeline_name.as_str();
+
+        // Signalled when steps are enqueued
+        let readiness = self.scheduler.lock().await.readiness();
+
+        // Steps of the batch still running, and those that finished and are
+        // not applied to the pipeline yet
+        let mut in_flight: InFlight<'_> = Vec::new();
+        let mut finished = Vec::new();
+
+        // Main execution loop
+        loop {
+            // Apply what finished steps came to; others keep running meanwhile
+            let mut progressed = Vec::new();
+            while !finished.is_empty() {
+                for (step_id, outcome) in std::mem::take(&mut finished) {
+                    match outcome {
+                        Ok(step) => {
+                            alongside(&mut in_flight, &mut finished, self.finish_step(pipeline, step)).await?
+                        }
+                        Err(message) => {
+                            alongside(
+                                &mut in_flight,
+                                &mut finished,
+                                self.fail_panicked_step(pipeline, &step_id, message),
+                            )
+                            .await
+                        }
+                    }
+                    progressed.push(step_id);
+                }
+            }
+            if !progressed.is_empty() {
+                self.update_state_counts(pipeline);
+                self.save_progress(pipeline, &progressed).await;
+            }
+
+            // Steps already running finish before the run ends, fails or
+            // stops; none start meanwhile
+            let stop_reason = self.cancel.stop_reason();
+            let ended = pipeline.is_complete() || pipeline.has_failed();
+            if in_flight.is_empty() {
+                if let Some(reason) = stop_reason {
+                    return self.finish_stopped(pipeline, reason).await;
+                }
+                if ended {
+                    break;
+                }
+            }
+
+            // Get next steps to run
+            let step_ids = if ended || stop_reason.is_some() {
+                Vec::new()
+            } else {
+                let scheduler = self.scheduler.lock().await;
+                scheduler.next_steps(pipeline)
+            };
+
+            if step_ids.is_empty() && in_flight.is_empty() {
+                // Check if we're stuck (running but no progress)
                 if pipeline.running_steps().is_empty() {
                     // Check if all steps are in terminal states
                     let all_terminal = pipeline.steps.values()
@@ -134,7 +1235,7 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
                     let blocked_by_failed_deps = pipeline.steps.values()
                         .any(|s| {
                             matches!(s.state, StepState::Pending)
-                                && s.dependencies.iter().any(|dep| {
+                                && s.all_dependencies().any(|dep| {
                                     pipeline.step(dep).is_some_and(|dep_step| {
                                         matches!(dep_step.state, StepState::Failed { .. })
                                     })
@@ -145,23 +1246,34 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
                     let retrying_blocked = pipeline.steps.values()
                         .any(|s| {
                             matches!(s.state, StepState::Retrying { .. })
-                                && s.dependencies.iter().any(|dep| {
+                                && s.all_dependencies().any(|dep| {
                                     pipeline.step(dep).is_some_and(|dep_step| {
-                                        !matches!(dep_step.state, StepState::Completed { .. } | StepState::Failed { .. })
+                                        !dep_step.state.is_terminal()
                                     })
                                 })
                         });
 
                     if all_terminal || (blocked_by_failed_deps && !retrying_blocked) {
+                        let mut unreached: Vec<_> = pipeline.steps.values()
+                            .filter(|s| matches!(s.state, StepState::Pending))
+                            .map(|s| s.id.clone())
+                            .collect();
+                        if !unreached.is_empty() {
+                            unreached.sort();
+                            self.emit_event(ExecutionEvent::Warning {
+                                scope: WarningScope::Pipeline,
+                                code: WarningCode::UnreachedSteps,
+                                message: format!("Steps never ran: {}", unreached.join(", ")),
+                            })
+                            .await;
+                        }
+
                         // Pipeline is complete
-                        let status = if pipeline.has_failed() {
-                            ExecutionStatus::Failed
-                        } else {
-                            ExecutionStatus::Completed
-                        };
+                        let status = Self::final_status(pipeline);
                         if matches!(pipeline.state.status, ExecutionStatus::Running) {
                             pipeline.state.status = status;
                         }
+                        self.materialize_outputs(pipeline).await;
                         self.emit_event(ExecutionEvent::PipelineCompleted {
                             execution_id,
                             status,
@@ -173,6 +1285,7 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
                     // Otherwise, truly stuck
                     error!("No steps ready to run and none running - pipeline stuck");
                     pipeline.state.fail();
+                    self.materialize_outputs(pipeline).await;
                     self.emit_event(ExecutionEvent::PipelineCompleted {
                         execution_id,
                         status: ExecutionStatus::Failed,
@@ -180,49 +1293,71 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
                     .await;
                     return Err("Pipeline stuck - no runnable steps".to_string());
                 }
+            }
 
-                // Wait a bit before checking again
-                tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            if step_ids.is_empty() {
+                // Steps are still running; rescan once one of them finishes or
+                // steps are enqueued, the run is told to stop, or the stuck
+                // check is due
+                tokio::select! {
+                    _ = next_finished(&mut in_flight, &mut finished) => {}
+                    _ = readiness.notified() => {}
+                    _ = self.cancel.stopping(), if stop_reason.is_none() => {}
+                    _ = tokio::time::sleep(STUCK_CHECK_INTERVAL) => {
+                        let running: Vec<_> = pipeline.running_steps().iter().map(|s| s.id.clone()).collect();
+                        debug!("Still waiting for running steps: {}", running.join(", "));
+                    }
+                    _ = self.cancel.cancelled() => {
+                        // `execute` ends a cancelled run and drops this future
+                        std::future::pending::<()>().await;
+                    }
+                }
                 continue;
             }
 
-            // Execute each ready step
+            // Start each ready step, leaving it to run alongside the others;
+            // a panic fails the step, not the process
+            let mut settled = Vec::new();
             for step_id in &step_ids {
-                self.execute_step(pipeline, step_id).await?;
-            }
-
-            // Check for completion - if no runnable steps but some completed, we're done
-            if step_ids.is_empty()
-                && !pipeline.running_steps().is_empty()
-                && pipeline.state.completed_steps > 0
-            {
-                // Check if all steps are in terminal states
-                let all_terminal = pipeline.steps.values()
-                    .all(|s| s.state.is_terminal());
-
-                if all_terminal {
-                    info!("All steps in terminal states, pipeline complete");
+                if self.cancel.is_stopping() {
                     break;
                 }
+                match alongside(&mut in_flight, &mut finished, catch_panic(self.start_step(pipeline, step_id))).await {
+                    Ok(Ok(Some(started))) => {
+                        let step_id = step_id.clone();
+                        in_flight.push(Box::pin(async move {
+                            let outcome = catch_panic(self.run_started_step(started)).await;
+                            (step_id, outcome)
+                        }));
+                    }
+                    Ok(Ok(None)) => settled.push(step_id.clone()),
+                    Ok(Err(e)) => return Err(e),
+                    Err(message) => {
+                        alongside(&mut in_flight, &mut finished, self.fail_panicked_step(pipeline, step_id, message))
+                            .await;
+                        settled.push(step_id.clone());
+                    }
+                }
             }
 
             // Update state counts
             self.update_state_counts(pipeline);
+            self.save_progress(pipeline, &settled).await;
         }
 
         // Pipeline is complete
-        let status = if pipeline.has_failed() {
-            pipeline.state.fail();
-            ExecutionStatus::Failed
-        } else {
-            pipeline.state.complete();
-            ExecutionStatus::Completed
-        };
+        let status = Self::final_status(pipeline);
+        match status {
+            ExecutionStatus::Failed => pipeline.state.fail(),
+            ExecutionStatus::PartiallyCompleted => pipeline.state.complete_partially(),
+            _ => pipeline.state.complete(),
+        }
 
         info!(
             "Pipeline execution finished: {} - {:?}",
             pipeline_name_ref, status
         );
+        self.materialize_outputs(pipeline).await;
         self.emit_event(ExecutionEvent::PipelineCompleted {
             execution_id,
             status,
@@ -232,8 +1367,45 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
         Ok(())
     }
 
-    /// Execute a single step
-    async fn execute_step(&self, pipeline: &mut Pipeline, step_id: &str) -> Result<(), String> {
+    /// End a run told to stop after its current steps, once they are done
+    async fn finish_stopped(&self, pipeline: &mut Pipeline, reason: String) -> Result<(), String> {
+        info!("Pipeline {} stopped after its current steps: {}", pipeline.name, reason);
+        self.update_state_counts(pipeline);
+        pipeline.state.cancel();
+        self.materialize_outputs(pipeline).await;
+        self.emit_event(ExecutionEvent::PipelineCompleted {
+            execution_id: pipeline.state.execution_id,
+            status: ExecutionStatus::Cancelled,
+        })
+        .await;
+        Err(reason)
+    }
+
+    /// Mark the steps that were running as cancelled, returning their IDs
+    fn cancel_running_steps(&self, pipeline: &mut Pipeline, reason: &str) -> Vec<String> {
+        let mut cancelled_steps = Vec::new();
+        for step in pipeline.steps.values_mut() {
+            if let StepState::Running { started_at, attempt } = step.state {
+                step.state = StepState::Cancelled {
+                    reason: reason.to_string(),
+                    attempts: attempt,
+                    started_at,
+                    cancelled_at: chrono::Utc::now(),
+                };
+                cancelled_steps.push(step.id.clone());
+            }
+        }
+        cancelled_steps.sort();
+        self.update_state_counts(pipeline);
+        cancelled_steps
+    }
+
+    /// Start a step: check that it may run, mark it running and prepare
+    /// what it runs on
+    ///
+    /// Returns `None` for a step dealt with without running: skipped,
+    /// reused, failed before it started, or held back by a stopping run.
+    async fn start_step(&self, pipeline: &mut Pipeline, step_id: &str) -> Result<Option<StartedStep>, String> {
         let step = match pipeline.step(step_id).cloned() {
             Some(s) => s,
             None => return Err(format!("Step {} not found", step_id)),
@@ -259,9 +1431,121 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
         if retry_count > max_retries {
             warn!("Step {} exceeded retry limit (retry_count {} > max_retries {})", step_id, retry_count, max_retries);
             let error = format!("Exceeded retry limit of {}", max_retries);
+            // Its retry queued it; left in the queue it would keep the
+            // scheduler from picking anything else
+            self.scheduler.lock().await.dequeue(step_id);
             // Use attempt - 1 as the actual number of completed attempts
             self.mark_step_failed(pipeline, step_id, error, attempt - 1).await;
-            return Ok(());
+            return Ok(None);
+        }
+
+        // Skip the step if its condition does not hold when it comes up
+        if let (false, Some(when)) = (is_retry, &step.when) {
+            let variables = pipeline.create_context_for_step(step_id).get_rendering_variables();
+            if !when.evaluate(&variables) {
+                let reason = format!("Condition not met: {}", when);
+                info!("Skipping step {}: {}", step_id, reason);
+                if let Some(s) = pipeline.step_mut(step_id) {
+                    s.state = StepState::Skipped { reason: reason.clone() };
+                }
+                self.emit_event(ExecutionEvent::StepSkipped {
+                    step_id: step_id.to_string(),
+                    reason,
+                })
+                .await;
+                return Ok(None);
+            }
+        }
+
+        // An incremental step whose inputs are unchanged reuses its last
+        // output; the digest is saved with whatever the step does instead
+        if let Some(inputs) = self.step_inputs(pipeline, &step) {
+            let digest = tokio::task::spawn_blocking(move || inputs.digest())
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+            match digest {
+                Ok(digest) => {
+                    if !is_retry && !self.force && self.reuse_unchanged(pipeline, &step, &digest).await? {
+                        return Ok(None);
+                    }
+                    pipeline.state.input_digests.insert(step_id.to_string(), digest);
+                }
+                Err(e) => {
+                    pipeline.state.input_digests.remove(step_id);
+                    self.emit_event(ExecutionEvent::Warning {
+                        scope: WarningScope::Step(step_id.to_string()),
+                        code: WarningCode::UnreadableInputs,
+                        message: format!("Could not read inputs, running anyway: {}", e),
+                    })
+                    .await;
+                }
+            }
+        }
+
+        // Make way for whatever the gates give precedence to
+        for gate in &self.step_gates {
+            let mut reported = false;
+            while let Some(reason) = gate.hold_reason().await {
+                if self.cancel.is_stopping() {
+                    break;
+                }
+                if !reported {
+                    self.emit_event(ExecutionEvent::StepWaiting {
+                        step_id: step_id.to_string(),
+                        condition: reason,
+                    })
+                    .await;
+                    reported = true;
+                }
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+            }
+        }
+
+        // Hold the step until its external preconditions are met
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(step.wait_timeout_secs);
+        for condition in &step.wait_for {
+            if let Err(e) = preconditions::ensure_supported(condition) {
+                self.mark_step_failed(pipeline, step_id, e.to_string(), attempt).await;
+                return Ok(None);
+            }
+            if preconditions::is_satisfied(condition).await {
+                continue;
+            }
+            self.emit_event(ExecutionEvent::StepWaiting {
+                step_id: step_id.to_string(),
+                condition: condition.to_string(),
+            })
+            .await;
+            if !preconditions::wait_until(condition, deadline, WAIT_POLL_INTERVAL).await {
+                let error = format!(
+                    "Timed out after {}s waiting for {}",
+                    step.wait_timeout_secs, condition
+                );
+                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+                return Ok(None);
+            }
+        }
+
+        // Don't start a step whose budget category is already spent
+        let budget = step
+            .budget_category
+            .as_ref()
+            .and_then(|category| pipeline.budgets.get(category).map(|limit| (category.clone(), *limit)));
+        if let Some((category, limit)) = &budget {
+            let spent = pipeline.spent_in_category(category);
+            if spent >= *limit {
+                let error = format!(
+                    "Budget '{}' exhausted (${:.2} of ${:.2} spent)",
+                    category, spent, limit
+                );
+                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+                return Ok(None);
+            }
+        }
+
+        // A run told to stop after its current steps starts no new ones
+        if self.cancel.is_stopping() {
+            return Ok(None);
         }
 
         // Update step state to running
@@ -287,347 +1571,3421 @@ impl<A: AgentExecutor + Send + Sync + 'static> ExecutionEngine<A> {
             .await;
         }
 
-        // Create context and execute
-        let context = pipeline.create_context_for_step(step_id);
-
-        // Create terminal output callback for live display
-        let total_steps = pipeline.steps.len();
-        let callback = TerminalOutputCallback::new(self.show_thinking, total_steps);
+        // Poll, pipeline and queue steps run their check, child pipeline or
+        // queue operation instead of the agent
+        let variables = || pipeline.create_context_for_step(step_id).get_rendering_variables();
+        let work = match (&step.poll, &step.sub_pipeline, &step.queue) {
+            (Some(poll), _, _) => Some(StepWork::Poll(poll.clone())),
+            (None, Some(sub_pipeline), _) => Some(StepWork::SubPipeline(sub_pipeline.clone(), variables())),
+            (None, None, Some(queue)) => Some(StepWork::Queue(queue.clone(), variables())),
+            (None, None, None) => None,
+        };
+        if let Some(work) = work {
+            return Ok(Some(StartedStep {
+                step,
+                attempt,
+                execution_id: pipeline.state.execution_id,
+                work,
+            }));
+        }
 
-        let result = self.executor.execute(&step, &context, Some(&callback)).await;
+        // Scratch space for the agent, removed when the attempt ends
+        let tmp_dir = match tempfile::Builder::new()
+            .prefix(&format!("pipeline-{}-", step_id))
+            .tempdir()
+        {
+            Ok(dir) => dir,
+            Err(e) => {
+                let error = format!("Could not create temporary directory: {}", e);
+                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+                return Ok(None);
+            }
+        };
 
-        match result {
-            ExecutionResult::Success { output, next_step } => {
-                self.mark_step_success(pipeline, step_id, output).await;
+        // Create context and execute
+        let mut context = pipeline.create_context_for_step(step_id);
+        context.variables.insert(
+            STEP_TMP_DIR_VARIABLE.to_string(),
+            tmp_dir.path().to_string_lossy().into_owned(),
+        );
 
-                // Enqueue next step if specified
-                if let Some(next) = next_step.clone() {
-                    // Reset target step to Retrying if it was already completed
-                    // Increment attempts to track re-execution due to routing
-                    let next_attempt = match pipeline.step(&next) {
-                        Some(step) => match &step.state {
-                            StepState::Completed { attempts, .. } | StepState::Failed { attempts, .. } => {
-                                *attempts + 1
-                            }
-                            StepState::Pending | StepState::Retrying { .. } => 1,
-                            _ => 1,
-                        },
-                        None => 1,
-                    };
+        // The agent runs in the step's working directory, which must exist
+        match step.render_working_dir(&context) {
+            Ok(Some(dir)) if !dir.is_dir() => {
+                let error = format!("Working directory {} does not exist", dir.display());
+                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+                return Ok(None);
+            }
+            Ok(Some(dir)) => {
+                context
+                    .variables
+                    .insert(STEP_WORKING_DIR_VARIABLE.to_string(), dir.to_string_lossy().into_owned());
+            }
+            Ok(None) => {}
+            Err(error) => {
+                self.mark_step_failed(pipeline, step_id, format!("Invalid working_dir: {}", error), attempt).await;
+                return Ok(None);
+            }
+        }
+        if let Err(error) = step.render_env(&context) {
+            self.mark_step_failed(pipeline, step_id, format!("Invalid env: {}", error), attempt).await;
+            return Ok(None);
+        }
 
-                    if let Some(step) = pipeline.step_mut(&next) {
-                        if matches!(step.state, StepState::Completed { .. } | StepState::Failed { .. }) {
-                            step.state = StepState::Retrying {
-                                attempt: next_attempt,
-                            };
-                        }
-                    }
+        // Tell the agent about tools the operator denied on earlier attempts
+        if let Some(denied) = self.denied_tools.lock().await.get(step_id) {
+            for tool in denied {
+                context.notes.push(ContextNote {
+                    content: format!(
+                        "The operator denied use of the '{}' tool. Complete the task without it.",
+                        tool
+                    ),
+                    from_step: "operator".to_string(),
+                    timestamp: chrono::Utc::now(),
+                });
+            }
+        }
 
-                    let mut scheduler = self.scheduler.lock().await;
-                    scheduler.enqueue(next.clone());
-                    self.emit_event(ExecutionEvent::StepCompleted {
-                        step_id: step_id.to_string(),
-                        next_step: Some(next),
-                    })
-                    .await;
-                } else {
-                    self.emit_event(ExecutionEvent::StepCompleted {
-                        step_id: step_id.to_string(),
-                        next_step: None,
+        // Placeholders left in the prompt usually mean a typo or a missing --variable
+        // (`notes` is only defined once a note exists, so it is not reported)
+        match step.render_prompt(&context) {
+            Err(RenderError::UndefinedVariables { missing, .. }) => {
+                let missing: Vec<_> = missing.into_iter().filter(|name| name != "notes").collect();
+                if !missing.is_empty() {
+                    self.emit_event(ExecutionEvent::Warning {
+                        scope: WarningScope::Step(step_id.to_string()),
+                        code: WarningCode::MissingVariables,
+                        message: format!("Prompt references undefined variables: {}", missing.join(", ")),
                     })
                     .await;
                 }
             }
-            ExecutionResult::Continue { action, target } => {
-                self.handle_continuation(pipeline, step_id, action, target).await?;
+            // Never hand the agent a runaway or broken prompt
+            Err(error @ (RenderError::TooLarge { .. } | RenderError::Template { .. })) => {
+                self.mark_step_failed(pipeline, step_id, error.to_string(), attempt).await;
+                return Ok(None);
             }
-            ExecutionResult::FailedWithRoute { error, next_step } => {
-                // Mark step as failed but route to handler
-                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+            Ok(_) => {}
+        }
 
-                // Don't fail the pipeline yet - route to handler first
-                // Reset pipeline state to running (so it doesn't fail)
-                if matches!(pipeline.state.status, ExecutionStatus::Failed) {
-                    pipeline.state.status = ExecutionStatus::Running;
+        // A dependency that printed this step's marker could end it early
+        // once its output is in the prompt
+        for (from_step, marker) in step.injected_markers(&context) {
+            let message = if step.sanitize_markers {
+                if let Some(output) = context.step_outputs.get_mut(&from_step) {
+                    *output = step.without_markers(output);
                 }
+                format!("Output of step '{}' contains this step's {} marker; it was replaced", from_step, marker)
+            } else {
+                format!(
+                    "Output of step '{}' contains this step's {} marker, which could end the step early (see sanitize_markers)",
+                    from_step, marker
+                )
+            };
+            self.emit_event(ExecutionEvent::Warning {
+                scope: WarningScope::Step(step_id.to_string()),
+                code: WarningCode::InjectedMarkers,
+                message,
+            })
+            .await;
+        }
 
-                // Emit reroute event
-                self.emit_event(ExecutionEvent::StepRerouted {
-                    from_step: step_id.to_string(),
-                    to_step: next_step.clone(),
-                })
-                .await;
+        pipeline
+            .state
+            .record_prompt_hashes(step_id, step.prompt_hashes(&context.get_rendering_variables(), attempt));
 
-                // Reset target step to Retrying if it was already completed/failed
-                // Increment attempts to track re-execution due to routing
-                let target_attempt = match pipeline.step(&next_step) {
-                    Some(step) => match &step.state {
-                        StepState::Completed { attempts, .. } | StepState::Failed { attempts, .. } => {
-                            *attempts + 1
-                        }
-                        StepState::Pending | StepState::Retrying { .. } => 1,
-                        _ => 1,
-                    },
-                    None => 1,
-                };
+        if self.prompt_preview {
+            self.emit_event(ExecutionEvent::PromptPreview {
+                step_id: step_id.to_string(),
+                attempt,
+                prompt: step.preview_prompt(&context.get_rendering_variables()),
+            })
+            .await;
+        }
 
-                if let Some(step) = pipeline.step_mut(&next_step) {
-                    if matches!(step.state, StepState::Completed { .. } | StepState::Failed { .. }) {
-                        step.state = StepState::Retrying {
-                            attempt: target_attempt,
-                        };
-                    }
-                }
+        let remaining_budget = budget
+            .as_ref()
+            .map(|(category, limit)| limit - pipeline.spent_in_category(category));
+        Ok(Some(StartedStep {
+            step,
+            attempt,
+            execution_id: pipeline.state.execution_id,
+            work: StepWork::Agent(AgentWork {
+                context,
+                tmp_dir,
+                budget,
+                remaining_budget,
+                #[cfg(feature = "cli")]
+                step_count: pipeline.steps.len(),
+            }),
+        }))
+    }
 
-                // Enqueue the failure handler
-                let mut scheduler = self.scheduler.lock().await;
-                scheduler.enqueue(next_step);
+    /// Run a started step to its result
+    ///
+    /// The pipeline is not needed, so the steps of a batch run at the same
+    /// time.
+    async fn run_started_step(&self, started: StartedStep) -> FinishedStep {
+        let StartedStep {
+            step,
+            attempt,
+            execution_id,
+            work,
+        } = started;
+        let (result, usage) = match work {
+            StepWork::Poll(poll) => (self.run_poll(&step, &poll).await, None),
+            StepWork::SubPipeline(sub_pipeline, variables) => {
+                (self.run_sub_pipeline(execution_id, &variables, &step, &sub_pipeline).await, None)
             }
-            ExecutionResult::Failed { error } => {
-                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+            StepWork::Queue(queue, variables) => {
+                (self.run_queue_op(execution_id, &variables, &step, &queue).await, None)
             }
-            ExecutionResult::Interrupted {
-                step_id: _interrupted_step_id,
-                accumulated_output,
-                recent_lines,
-                original_prompt,
-            } => {
-                // Phase 4: Handle interruption
-                // For now, just mark the step as failed with interruption info
-                // Full steering menu handling will be added in a future update
-                let error_msg = format!(
-                    "Execution interrupted. Output: \"{}\", Lines: {}, Original prompt: \"{}\"",
-                    accumulated_output,
-                    recent_lines.len(),
-                    original_prompt
-                );
-                self.mark_step_failed(pipeline, step_id, error_msg, attempt).await;
+            StepWork::Agent(work) => {
+                let (result, usage) = self.run_agent_step(&step, attempt, execution_id, work).await;
+                (result, Some(usage))
             }
+        };
+        FinishedStep {
+            step,
+            attempt,
+            result,
+            usage,
         }
-
-        Ok(())
     }
 
-    /// Handle continuation (retry or route)
-    async fn handle_continuation(
+    /// Run an agent step's attempt, returning its result and what the agent
+    /// used and wrote
+    async fn run_agent_step(
         &self,
-        pipeline: &mut Pipeline,
-        step_id: &str,
-        action: ContinueAction,
-        _target: Option<String>,
-    ) -> Result<(), String> {
-        self.emit_event(ExecutionEvent::StepContinued {
-            step_id: step_id.to_string(),
-            action: action.clone(),
-        })
-        .await;
+        step: &Step,
+        attempt: usize,
+        execution_id: Uuid,
+        work: AgentWork,
+    ) -> (ExecutionResult, AgentUsage) {
+        let AgentWork {
+            context,
+            tmp_dir,
+            budget,
+            remaining_budget,
+            #[cfg(feature = "cli")]
+            step_count,
+        } = work;
+        let step_id = step.id.as_str();
 
-        match action {
-            ContinueAction::Retry => {
-                // Extract current attempt count before changing state
-                let current_attempt = match pipeline.step(step_id) {
-                    Some(step) => match &step.state {
-                        StepState::Running { attempt, .. } => *attempt,
-                        StepState::Retrying { attempt } => *attempt,
-                        _ => 0,
-                    },
-                    None => 0,
-                };
+        // Create terminal output callback for live display
+        #[cfg(feature = "cli")]
+        let terminal = self.terminal_output.then(|| {
+            TerminalOutputCallback::new(self.show_thinking, step_count)
+                .with_max_output_rate(self.max_output_rate)
+        });
+        #[cfg(not(feature = "cli"))]
+        let terminal: Option<crate::agent::streaming::NoopCallback> = None;
+        let callback: &dyn ProgressCallback = match &terminal {
+            Some(terminal) => terminal,
+            None => &crate::agent::streaming::NoopCallback,
+        };
+        let step_log = self.step_logs.as_ref().and_then(|logs| {
+            let path = logs.path(execution_id, step_id);
+            StepLogCallback::open(&path, attempt, logs.max_bytes, Some(callback))
+                .map_err(|e| warn!("Could not open step log {}: {}", path.display(), e))
+                .ok()
+        });
+        let logged: &dyn ProgressCallback = match &step_log {
+            Some(step_log) => step_log,
+            None => callback,
+        };
+        // The last lines of agent text show where an interrupted step was
+        let recent_lines = BufferedCallback::new(Some(logged));
+        let unknown_events = UnknownEventCallback::new(Some(&recent_lines));
+        let writes = WriteTrackingCallback::new(Some(&unknown_events), step.max_write_bytes);
+        let usage = UsageTrackingCallback::new(Some(&writes), remaining_budget);
 
-                // Increment attempt count for retry (each retry is a new execution attempt)
-                let new_attempt = current_attempt + 1;
+        let started = std::time::Instant::now();
+        // Plan-only steps must not touch the workspace, whatever the operator
+        // allows; bash commands that only read are let through
+        let read_only = ToolApprovalPolicy::read_only();
+        let tool_approval: Option<(&ToolApprovalPolicy, &dyn ToolApprover)> = if step.plan_only {
+            Some((&read_only, &ReadOnlyApprover))
+        } else {
+            self.tool_approval
+                .as_ref()
+                .map(|(policy, approver)| (policy, approver.as_ref()))
+        };
+        let result = match tool_approval {
+            Some((policy, approver)) => {
+                let approval = ToolApprovalCallback::new(Some(&usage), policy, approver);
+                let result = self.run_executor(step, &context, &approval).await;
 
-                // Set step to retrying state with incremented attempt
-                if let Some(step) = pipeline.step_mut(step_id) {
-                    step.state = StepState::Retrying {
-                        attempt: new_attempt,
-                    };
+                for decision in approval.decisions() {
+                    self.save_tool_decision(execution_id, step_id, &decision).await;
+                    self.emit_event(ExecutionEvent::ToolCallReviewed {
+                        step_id: step_id.to_string(),
+                        tool_name: decision.tool_name,
+                        approved: decision.approved,
+                    })
+                    .await;
+                }
+
+                match approval.denied_tool() {
+                    Some(tool) => {
+                        info!("Step {} will retry without denied tool '{}'", step_id, tool);
+                        self.denied_tools
+                            .lock()
+                            .await
+                            .entry(step_id.to_string())
+                            .or_default()
+                            .push(tool);
+                        ExecutionResult::Continue {
+                            action: ContinueAction::Retry,
+                            target: None,
+                        }
+                    }
+                    None => result,
                 }
-                // Re-enqueue for execution
-                let mut scheduler = self.scheduler.lock().await;
-                scheduler.enqueue(step_id.to_string());
             }
-            ContinueAction::Route(target_id) => {
-                self.emit_event(ExecutionEvent::StepRerouted {
-                    from_step: step_id.to_string(),
-                    to_step: target_id.clone(),
+            None => self.run_executor(step, &context, &usage).await,
+        };
+
+        let result = match result {
+            ExecutionResult::Failed { error } if step.keep_tmp_dir_on_failure => {
+                let kept = tmp_dir.keep();
+                ExecutionResult::Failed {
+                    error: format!("{} (scratch files kept in {})", error, kept.display()),
+                }
+            }
+            result => {
+                if let Err(e) = tmp_dir.close() {
+                    warn!("Could not remove temporary directory of step {}: {}", step_id, e);
+                }
+                result
+            }
+        };
+
+        let usage = AgentUsage {
+            budget,
+            cost_usd: usage.cost_usd(),
+            tokens: usage.tokens(),
+            bytes: writes.bytes_written(),
+            files: writes.files_written(),
+            paths_written: writes.paths_written(),
+            unknown_events: unknown_events.counts(),
+            elapsed: started.elapsed(),
+            working_dir: context.variables.get(STEP_WORKING_DIR_VARIABLE).map(PathBuf::from),
+        };
+        (result, usage)
+    }
+
+    /// Apply a finished step to the pipeline: record and report what its
+    /// agent used, then schedule what follows from its result
+    async fn finish_step(&self, pipeline: &mut Pipeline, finished: FinishedStep) -> Result<(), String> {
+        let FinishedStep {
+            step,
+            attempt,
+            result,
+            usage,
+        } = finished;
+        let step_id = step.id.as_str();
+        let Some(usage) = usage else {
+            return self.apply_result(pipeline, step_id, attempt, result).await;
+        };
+
+        // Record which agent profile served each call, and any failovers
+        let agent_attempts = self.executor.take_agent_attempts(step_id);
+        for pair in agent_attempts.windows(2) {
+            if let (Some(error), true) = (&pair[0].error, pair[0].agent != pair[1].agent) {
+                self.emit_event(ExecutionEvent::AgentFailover {
+                    step_id: step_id.to_string(),
+                    from: pair[0].agent.clone(),
+                    to: pair[1].agent.clone(),
+                    error: error.clone(),
                 })
                 .await;
+            }
+        }
+        if !agent_attempts.is_empty() {
+            pipeline.state.record_agent_attempts(step_id, agent_attempts);
+        }
 
-                // Mark current step as completed (it's not a failure, just routing)
-                // Get the current attempt count from the Running state
-                let attempt = match pipeline.step(step_id) {
-                    Some(step) => match &step.state {
-                        StepState::Running { attempt, .. } => *attempt,
-                        _ => 1,
-                    },
-                    None => 1,
-                };
+        // Attribute the agent's spending to the step and its budget category
+        let cost_usd = usage.cost_usd;
+        if cost_usd > 0.0 || usage.tokens > 0 {
+            pipeline.state.record_cost(step_id, cost_usd, usage.tokens);
+            self.emit_event(ExecutionEvent::StepCost {
+                step_id: step_id.to_string(),
+                cost_usd,
+                tokens: usage.tokens,
+                budget: usage.budget.map(|(category, limit_usd)| BudgetUsage {
+                    spent_usd: pipeline.spent_in_category(&category),
+                    category,
+                    limit_usd,
+                }),
+            })
+            .await;
+        }
 
-                if let Some(step) = pipeline.step_mut(step_id) {
-                    let now = chrono::Utc::now();
-                    step.state = StepState::Completed {
-                        output: String::new(),
-                        attempts: attempt,
-                        started_at: now,
-                        completed_at: now,
+        let bytes = usage.bytes;
+        if bytes > 0 {
+            pipeline.state.record_bytes_written(step_id, bytes);
+            self.emit_event(ExecutionEvent::StepFilesWritten {
+                step_id: step_id.to_string(),
+                bytes,
+                files: usage.files,
+            })
+            .await;
+        }
+
+        // Locks only protect the paths they name, so report writes elsewhere
+        if !step.locks.is_empty() {
+            let unlocked: Vec<_> = usage
+                .paths_written
+                .into_iter()
+                .filter(|path| !locks::is_covered(&step.locks, path))
+                .collect();
+            if !unlocked.is_empty() {
+                self.emit_event(ExecutionEvent::Warning {
+                    scope: WarningScope::Step(step_id.to_string()),
+                    code: WarningCode::UnlockedWrites,
+                    message: format!("Wrote outside its locks: {}", unlocked.join(", ")),
+                })
+                .await;
+            }
+        }
+
+        // A newer pi may send events that carry text this version misses
+        let unknown = usage.unknown_events;
+        if !unknown.is_empty() {
+            let total: usize = unknown.iter().map(|(_, count)| count).sum();
+            let types: Vec<_> = unknown
+                .iter()
+                .map(|(event_type, count)| format!("{} ({})", event_type, count))
+                .collect();
+            self.emit_event(ExecutionEvent::Warning {
+                scope: WarningScope::Step(step_id.to_string()),
+                code: WarningCode::UnknownAgentEvents,
+                message: format!(
+                    "Ignored {} unknown agent events: {}; run with --strict-events to fail instead",
+                    total,
+                    types.join(", ")
+                ),
+            })
+            .await;
+        }
+
+        let elapsed = usage.elapsed;
+        if step.timeout_secs > 0
+            && elapsed.as_secs_f64() >= step.timeout_secs as f64 * SLOW_STEP_THRESHOLD
+        {
+            self.emit_event(ExecutionEvent::Warning {
+                scope: WarningScope::Step(step_id.to_string()),
+                code: WarningCode::SlowStep,
+                message: format!(
+                    "Took {}s of its {}s timeout",
+                    elapsed.as_secs(),
+                    step.timeout_secs
+                ),
+            })
+            .await;
+        }
+
+        if self.executor.take_both_matched(step_id) {
+            let outcome = match step.precedence {
+                Precedence::ContinuationFirst => "continued",
+                Precedence::SuccessFirst => "completed",
+                Precedence::Error => "failed",
+            };
+            self.emit_event(ExecutionEvent::Warning {
+                scope: WarningScope::Step(step_id.to_string()),
+                code: WarningCode::BothPatternsMatched,
+                message: format!(
+                    "Output matched both the success and the continuation pattern; the step {} as its precedence is {}",
+                    outcome,
+                    step.precedence.as_str()
+                ),
+            })
+            .await;
+        }
+
+        if matches!(result, ExecutionResult::Success { .. }) && !step.artifacts.is_empty() {
+            self.collect_artifacts(pipeline, &step, usage.working_dir).await;
+        }
+
+        self.apply_result(pipeline, step_id, attempt, result).await
+    }
+
+    /// Declared inputs of a step, resolved against its working directory
+    fn step_inputs(&self, pipeline: &Pipeline, step: &Step) -> Option<Inputs> {
+        step.inputs.as_ref()?;
+        let context = pipeline.create_context_for_step(&step.id);
+        // A working directory that doesn't render fails the step later on
+        let working_dir = match step.render_working_dir(&context) {
+            Ok(Some(dir)) => dir,
+            _ => std::env::current_dir().unwrap_or_default(),
+        };
+        Inputs::of(step, pipeline, &context.get_rendering_variables(), working_dir)
+    }
+
+    /// Complete a step with the output of its last completed run if that
+    /// run's inputs had `digest`, returning whether it did
+    ///
+    /// The step's artifacts from that run are carried over; if any is gone
+    /// or altered, the step runs instead.
+    async fn reuse_unchanged(&self, pipeline: &mut Pipeline, step: &Step, digest: &str) -> Result<bool, String> {
+        let Some(store) = &self.persistence else {
+            return Ok(false);
+        };
+        let (from_execution, output) = match store.last_completed_step(&pipeline.name, &step.id).await {
+            Ok(Some((from_execution, result))) if result.input_digest.as_deref() == Some(digest) => {
+                match result.output {
+                    Some(output) => (from_execution, output),
+                    // Its telemetry kept the output out of history
+                    None => return Ok(false),
+                }
+            }
+            Ok(_) => return Ok(false),
+            Err(e) => {
+                self.report_persistence_failure(e).await;
+                return Ok(false);
+            }
+        };
+
+        let execution_id = pipeline.state.execution_id;
+        if !step.artifacts.is_empty() {
+            let artifacts: Vec<_> = match store.load_artifacts(from_execution).await {
+                Ok(artifacts) => artifacts.into_iter().filter(|a| a.step_id == step.id).collect(),
+                Err(e) => {
+                    self.report_persistence_failure(e).await;
+                    return Ok(false);
+                }
+            };
+            let (from_dir, to_dir) = (self.artifacts_dir_of(from_execution), self.artifacts_dir_of(execution_id));
+            let copies = artifacts.clone();
+            let reused = tokio::task::spawn_blocking(move || artifacts::reuse(&copies, &from_dir, &to_dir))
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+            if let Err(e) = reused {
+                info!("Step {} runs again: its earlier artifacts can't be reused ({})", step.id, e);
+                return Ok(false);
+            }
+            for artifact in &artifacts {
+                if let Err(e) = store.save_artifact(execution_id, artifact).await {
+                    self.report_persistence_failure(e.context(format!("artifact {}", artifact.path))).await;
+                }
+            }
+        }
+
+        info!("Step {} inputs unchanged; reusing its output from run {}", step.id, from_execution);
+        pipeline.state.input_digests.insert(step.id.clone(), digest.to_string());
+        self.emit_event(ExecutionEvent::StepReused {
+            step_id: step.id.clone(),
+            from_execution,
+        })
+        .await;
+        let result = ExecutionResult::Success {
+            output,
+            next_step: step.next_step_on_success().cloned(),
+        };
+        self.apply_result(pipeline, &step.id, 1, result).await?;
+        Ok(true)
+    }
+
+    /// Directory the artifacts of a run are collected into
+    fn artifacts_dir_of(&self, execution_id: Uuid) -> PathBuf {
+        self.artifacts_dir
+            .clone()
+            .unwrap_or_else(|| crate::persistence::artifacts_dir(execution_id))
+    }
+
+    /// Copy a completed step's artifacts into the run's artifacts directory
+    /// and record them
+    ///
+    /// Missing files and failed copies are reported as warnings; the step
+    /// still completes.
+    async fn collect_artifacts(&self, pipeline: &Pipeline, step: &Step, working_dir: Option<PathBuf>) {
+        let execution_id = pipeline.state.execution_id;
+        let artifacts_dir = self.artifacts_dir_of(execution_id);
+        let provenance = step.provenance.then(|| Provenance {
+            pipeline: pipeline.name.clone(),
+            step_id: step.id.clone(),
+            execution_id,
+            generated_at: chrono::Utc::now(),
+            model: self.step_models.get(&step.id).cloned(),
+        });
+        let (step_id, patterns) = (step.id.clone(), step.artifacts.clone());
+        let collected = tokio::task::spawn_blocking(move || {
+            let working_dir = match working_dir {
+                Some(dir) => dir,
+                None => std::env::current_dir()?,
+            };
+            artifacts::collect(&step_id, &patterns, &working_dir, &artifacts_dir, provenance.as_ref())
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+
+        let warn = |message: String| ExecutionEvent::Warning {
+            scope: WarningScope::Step(step.id.clone()),
+            code: WarningCode::MissingArtifacts,
+            message,
+        };
+        let collected = match collected {
+            Ok(collected) => collected,
+            Err(e) => {
+                self.emit_event(warn(format!("Could not collect artifacts: {}", e))).await;
+                return;
+            }
+        };
+        if !collected.unmatched.is_empty() {
+            self.emit_event(warn(format!("No files match artifacts: {}", collected.unmatched.join(", ")))).await;
+        }
+        info!("Step {} collected {} artifact(s)", step.id, collected.artifacts.len());
+
+        let Some(store) = &self.persistence else { return };
+        for artifact in &collected.artifacts {
+            if let Err(e) = store.save_artifact(execution_id, artifact).await {
+                self.report_persistence_failure(e.context(format!("artifact {}", artifact.path))).await;
+            }
+        }
+    }
+
+    /// Update the step and schedule follow-up steps from an execution result
+    async fn apply_result(
+        &self,
+        pipeline: &mut Pipeline,
+        step_id: &str,
+        attempt: usize,
+        result: ExecutionResult,
+    ) -> Result<(), String> {
+        match result {
+            ExecutionResult::Success { output, next_step } => {
+                self.mark_step_success(pipeline, step_id, output).await;
+
+                // Enqueue next step if specified
+                let next_step = match next_step {
+                    Some(next) => self.route_target(pipeline, step_id, next).await,
+                    None => None,
+                };
+                if let Some(next) = next_step {
+                    // Reset target step to Retrying if it was already completed
+                    // Increment attempts to track re-execution due to routing
+                    let next_attempt = match pipeline.step(&next) {
+                        Some(step) => match &step.state {
+                            StepState::Completed { attempts, .. } | StepState::Failed { attempts, .. } => {
+                                *attempts + 1
+                            }
+                            StepState::Pending | StepState::Retrying { .. } => 1,
+                            _ => 1,
+                        },
+                        None => 1,
                     };
+
+                    if let Some(step) = pipeline.step_mut(&next) {
+                        if matches!(step.state, StepState::Completed { .. } | StepState::Failed { .. }) {
+                            step.state = StepState::Retrying {
+                                attempt: next_attempt,
+                            };
+                        }
+                    }
+
+                    let mut scheduler = self.scheduler.lock().await;
+                    scheduler.enqueue(next.clone());
+                    self.emit_event(ExecutionEvent::StepCompleted {
+                        step_id: step_id.to_string(),
+                        next_step: Some(next),
+                    })
+                    .await;
+                } else {
+                    self.emit_event(ExecutionEvent::StepCompleted {
+                        step_id: step_id.to_string(),
+                        next_step: None,
+                    })
+                    .await;
+                }
+            }
+            ExecutionResult::Continue { action, target } => {
+                self.handle_continuation(pipeline, step_id, action, target).await?;
+            }
+            ExecutionResult::FailedWithRoute { error, next_step } => {
+                // Mark step as failed but route to handler
+                if self.mark_step_failed(pipeline, step_id, error, attempt).await {
+                    return Ok(());
                 }
+                self.route_failure(pipeline, step_id, next_step).await;
+            }
+            ExecutionResult::Failed { error } => {
+                self.mark_step_failed(pipeline, step_id, error, attempt).await;
+            }
+            ExecutionResult::Interrupted {
+                step_id: _interrupted_step_id,
+                accumulated_output,
+                recent_lines,
+                original_prompt,
+            } => {
+                let Some((flag, steering)) = self.interrupt.clone() else {
+                    let error_msg = format!(
+                        "Execution interrupted. Output: \"{}\", Lines: {}, Original prompt: \"{}\"",
+                        accumulated_output,
+                        recent_lines.len(),
+                        original_prompt
+                    );
+                    self.mark_step_failed(pipeline, step_id, error_msg, attempt).await;
+                    return Ok(());
+                };
 
-                // Reset target step to Retrying state so it will execute again
-                // If target was already completed, use its attempt count (don't increment)
-                let target_attempt = match pipeline.step(&target_id) {
-                    Some(step) => match &step.state {
-                        StepState::Completed { attempts, .. } => *attempts,
-                        StepState::Failed { attempts, .. } => *attempts,
-                        StepState::Pending | StepState::Retrying { .. } => 1,
-                        _ => 1,
-                    },
-                    None => 1,
+                let interruption = Interruption {
+                    step_id: step_id.to_string(),
+                    accumulated_output,
+                    recent_lines,
                 };
+                let action = tokio::task::spawn_blocking(move || steering.steer(&interruption))
+                    .await
+                    .unwrap_or(SteeringAction::Abort);
+                flag.store(false, Ordering::SeqCst);
+                info!("Step {} interrupted by the operator: {:?}", step_id, action);
 
-                if let Some(step) = pipeline.step_mut(&target_id) {
-                    step.state = StepState::Retrying {
-                        attempt: target_attempt,
-                    };
+                match action {
+                    SteeringAction::Retry => {
+                        self.handle_continuation(pipeline, step_id, ContinueAction::Retry, None).await?;
+                    }
+                    SteeringAction::Skip => self.skip_by_operator(pipeline, step_id).await,
+                    SteeringAction::Abort => self.abort_by_operator().await,
+                }
+            }
+        }
+
+
+        Ok(())
+    }
+
+    /// Repeat a poll step's check until it passes or its deadline runs out
+    async fn run_poll(&self, step: &Step, poll: &Poll) -> ExecutionResult {
+        let interval = Duration::from_secs(poll.interval_secs);
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(poll.timeout_secs);
+        if let Err(e) = preconditions::ensure_supported(&poll.condition) {
+            return ExecutionResult::Failed { error: e.to_string() };
+        }
+
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let passed = match &poll.until {
+                Some(matcher) => match preconditions::probe(&poll.condition).await {
+                    Some((output, exit_code)) => matcher.evaluate(&output, exit_code),
+                    None => false,
+                },
+                None => preconditions::is_satisfied(&poll.condition).await,
+            };
+            self.emit_event(ExecutionEvent::PollAttempt {
+                step_id: step.id.clone(),
+                attempt,
+                passed,
+            })
+            .await;
+
+            if passed {
+                return ExecutionResult::Success {
+                    output: format!("{} passed on attempt {}", poll.condition, attempt),
+                    next_step: step.next_step_on_success().cloned(),
+                };
+            }
+            if tokio::time::Instant::now() + interval > deadline {
+                break;
+            }
+            tokio::time::sleep(interval).await;
+        }
+
+        let error = format!(
+            "{} did not pass within {}s ({} attempt{})",
+            poll.condition,
+            poll.timeout_secs,
+            attempt,
+            if attempt == 1 { "" } else { "s" }
+        );
+        match step.next_step_on_failure() {
+            Some(target) => ExecutionResult::FailedWithRoute {
+                error,
+                next_step: target.clone(),
+            },
+            None => ExecutionResult::Failed { error },
+        }
+    }
+
+    /// Run a `pipeline:` step's child pipeline to its end
+    ///
+    /// The child run gets its own execution ID, saved with this run's as its
+    /// parent. Its agent text and the results of its steps are relayed as
+    /// events of the step, and its outputs become the step's output as a
+    /// JSON object. Unless the child completes, the step fails.
+    ///
+    /// `variables` are the step's rendering variables, which the child's
+    /// variables are rendered with.
+    async fn run_sub_pipeline(
+        &self,
+        parent_execution_id: Uuid,
+        variables: &HashMap<String, String>,
+        step: &Step,
+        sub_pipeline: &SubPipeline,
+    ) -> ExecutionResult {
+        let mut child = sub_pipeline.config.to_pipeline();
+        child.state.parent_execution_id = Some(parent_execution_id);
+        for (name, value) in &sub_pipeline.variables {
+            child.variables.insert(name.clone(), substitute_variables(value, variables).text);
+        }
+        if let Some(timeout) = sub_pipeline.timeout_secs.map(Duration::from_secs) {
+            child.max_duration = Some(child.max_duration.map_or(timeout, |limit| limit.min(timeout)));
+        }
+        let execution_id = child.state.execution_id;
+        let run_name = format!("Pipeline '{}' (run {})", child.name, &execution_id.to_string()[..8]);
+        self.emit_event(ExecutionEvent::SubPipelineStarted {
+            step_id: step.id.clone(),
+            execution_id,
+            pipeline_name: child.name.clone(),
+        })
+        .await;
+
+        let engine = self.sub_pipeline_engine().await;
+        let mut events = engine.subscribe_filtered(|event| {
+            matches!(
+                event,
+                ExecutionEvent::StepOutputChunk { .. } | ExecutionEvent::StepCompleted { .. } | ExecutionEvent::StepFailed { .. }
+            )
+        });
+        let _stop_with_step = CancelOnDrop(engine.cancel_handle());
+        let run = tokio::spawn(run_child(engine, child));
+        while let Some(event) = events.recv().await {
+            let event = match event {
+                ExecutionEvent::StepOutputChunk { delta, .. } => ExecutionEvent::StepOutputChunk {
+                    step_id: step.id.clone(),
+                    delta,
+                },
+                ExecutionEvent::StepCompleted { step_id, .. } => ExecutionEvent::SubPipelineStepFinished {
+                    step_id: step.id.clone(),
+                    child_step_id: step_id,
+                    error: None,
+                },
+                ExecutionEvent::StepFailed { step_id, error } => ExecutionEvent::SubPipelineStepFinished {
+                    step_id: step.id.clone(),
+                    child_step_id: step_id,
+                    error: Some(error),
+                },
+                _ => continue,
+            };
+            self.emit_event(event).await;
+        }
+
+        let error = match run.await {
+            Ok((child, Ok(()))) if child.state.status == ExecutionStatus::Completed => {
+                return ExecutionResult::Success {
+                    output: serde_json::to_string_pretty(&child.state.outputs).unwrap_or_default(),
+                    next_step: step.next_step_on_success().cloned(),
+                };
+            }
+            Ok((child, Ok(()))) => {
+                let mut failures: Vec<String> = child
+                    .steps
+                    .values()
+                    .filter_map(|s| match &s.state {
+                        StepState::Failed { error, .. } => Some(format!("{}: {}", s.id, error)),
+                        _ => None,
+                    })
+                    .collect();
+                failures.sort();
+                match failures.is_empty() {
+                    true => format!("{} did not meet its success criteria", run_name),
+                    false => format!("{} failed: {}", run_name, failures.join("; ")),
+                }
+            }
+            Ok((_, Err(reason))) => format!("{} did not finish: {}", run_name, reason),
+            Err(e) => format!("{} did not finish: {}", run_name, e),
+        };
+        match step.next_step_on_failure() {
+            Some(target) => ExecutionResult::FailedWithRoute {
+                error,
+                next_step: target.clone(),
+            },
+            None => ExecutionResult::Failed { error },
+        }
+    }
+
+    /// Push a `queue:` step's items to its queue, or take a batch from it
+    ///
+    /// Taken items become the step's output, one per line; an empty queue
+    /// leaves the output empty.
+    async fn run_queue_op(
+        &self,
+        execution_id: Uuid,
+        variables: &HashMap<String, String>,
+        step: &Step,
+        op: &QueueOp,
+    ) -> ExecutionResult {
+        let result = match (&self.persistence, op) {
+            (None, _) => Err(anyhow::anyhow!("Queues are kept in history, which is off for this run")),
+            (Some(store), QueueOp::Push { queue, items }) => {
+                let items: Vec<String> = substitute_variables(items, variables)
+                    .text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(String::from)
+                    .collect();
+                store
+                    .push_queue_items(queue, &items, Some(execution_id))
+                    .await
+                    .map(|()| format!("Pushed {} item(s) to queue '{}'", items.len(), queue))
+            }
+            (Some(store), QueueOp::Pop { queue, batch }) => store.pop_queue_items(queue, *batch).await.map(|items| {
+                items.iter().map(|item| item.payload.as_str()).collect::<Vec<_>>().join("\n")
+            }),
+        };
+
+        match result {
+            Ok(output) => ExecutionResult::Success {
+                output,
+                next_step: step.next_step_on_success().cloned(),
+            },
+            Err(e) => {
+                let error = format!("Queue operation failed: {:#}", e);
+                match step.next_step_on_failure() {
+                    Some(target) => ExecutionResult::FailedWithRoute {
+                        error,
+                        next_step: target.clone(),
+                    },
+                    None => ExecutionResult::Failed { error },
                 }
+            }
+        }
+    }
+
+    /// An engine for the child run of a `pipeline:` step, with this one's
+    /// agents, persistence, step logs, display settings and operator controls
+    async fn sub_pipeline_engine(&self) -> Self {
+        let strategy = self.scheduler.lock().await.strategy();
+        Self {
+            scheduler: Arc::new(Mutex::new(ExecutionScheduler::new(strategy))),
+            executor: self.executor.for_sub_pipeline(),
+            event_handlers: Arc::new(Mutex::new(Vec::new())),
+            handler_timeout: self.handler_timeout,
+            output_chunk_interval: self.output_chunk_interval,
+            handler_workers: HandlerWorkers::new(self.handler_timeout),
+            show_thinking: self.show_thinking,
+            max_output_rate: self.max_output_rate,
+            terminal_output: self.terminal_output,
+            prompt_preview: false,
+            force: self.force,
+            tool_approval: self.tool_approval.clone(),
+            denied_tools: Mutex::new(HashMap::new()),
+            step_gates: self.step_gates.clone(),
+            telemetry: std::sync::Mutex::new(HashMap::new()),
+            cancel: CancelHandle::new(),
+            subscribers: std::sync::Mutex::new(Vec::new()),
+            interrupt: self.interrupt.clone(),
+            failure_prompt: self.failure_prompt.clone(),
+            persistence: self.persistence.clone(),
+            artifacts_dir: None,
+            step_models: HashMap::new(),
+            step_logs: self.step_logs.clone(),
+        }
+    }
+
+    /// Run the executor, emitting the agent's text as `StepOutputChunk`
+    /// events while it runs
+    async fn run_executor(
+        &self,
+        step: &Step,
+        context: &PipelineContext,
+        callback: &dyn ProgressCallback,
+    ) -> ExecutionResult {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let chunks = TextChunkCallback::new(Some(callback), sender);
+        let finished = Notify::new();
+        let run = async {
+            let result = self.run_agent(step, context, &chunks).await;
+            finished.notify_one();
+            result
+        };
+        tokio::join!(run, self.forward_chunks(&step.id, receiver, &finished)).0
+    }
+
+    /// Emit the text arriving on `chunks` until `finished` is notified;
+    /// text arriving within `output_chunk_interval` of the first, or while
+    /// handlers run, is sent as one chunk
+    async fn forward_chunks(&self, step_id: &str, mut chunks: UnboundedReceiver<String>, finished: &Notify) {
+        let mut done = false;
+        while !done {
+            let mut delta = tokio::select! {
+                Some(delta) = chunks.recv() => delta,
+                _ = finished.notified() => break,
+            };
+            let deadline = tokio::time::Instant::now() + self.output_chunk_interval;
+            loop {
+                tokio::select! {
+                    Some(more) = chunks.recv() => delta.push_str(&more),
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = finished.notified() => {
+                        done = true;
+                        break;
+                    }
+                }
+            }
+            while let Ok(more) = chunks.try_recv() {
+                delta.push_str(&more);
+            }
+            self.emit_event(ExecutionEvent::StepOutputChunk {
+                step_id: step_id.to_string(),
+                delta,
+            })
+            .await;
+        }
+
+        // Text sent just before the agent finished
+        let mut rest = String::new();
+        while let Ok(more) = chunks.try_recv() {
+            rest.push_str(&more);
+        }
+        if !rest.is_empty() {
+            self.emit_event(ExecutionEvent::StepOutputChunk {
+                step_id: step_id.to_string(),
+                delta: rest,
+            })
+            .await;
+        }
+    }
+
+    /// Run the agent, reporting missed heartbeats while it is silent
+    async fn run_agent(
+        &self,
+        step: &Step,
+        context: &PipelineContext,
+        callback: &dyn ProgressCallback,
+    ) -> ExecutionResult {
+        // The agent finds the step's scratch directory in its environment,
+        // next to the step's `env:` (checked to render before the step started)
+        let mut step_env = step.render_env(context).unwrap_or_default();
+        step_env.extend(
+            context
+                .variables
+                .get(STEP_TMP_DIR_VARIABLE)
+                .map(|dir| (STEP_TMP_DIR_ENV.to_string(), dir.clone())),
+        );
+        let passthrough = step.env_passthrough.clone();
+
+        let working_dir = context.variables.get(STEP_WORKING_DIR_VARIABLE).map(PathBuf::from);
+
+        // Text mode only emits events once the agent is done
+        let interval = match step.heartbeat_secs {
+            Some(secs) if step.streaming => Duration::from_secs(secs),
+            _ => {
+                let run = STEP_ENV.scope(step_env, self.run_interruptible(step, context, callback));
+                let run = STEP_ENV_PASSTHROUGH.scope(passthrough, run);
+                return STEP_WORKING_DIR.scope(working_dir, run).await;
+            }
+        };
+
+        let heartbeat = HeartbeatCallback::new(Some(callback));
+        let run = STEP_ENV.scope(step_env, async {
+            tokio::select! {
+                result = self.run_interruptible(step, context, &heartbeat) => result,
+                _ = self.watch_heartbeat(&step.id, &heartbeat, interval) => unreachable!("heartbeat watch never ends"),
+            }
+        });
+        let run = STEP_ENV_PASSTHROUGH.scope(passthrough, run);
+        STEP_WORKING_DIR.scope(working_dir, run).await
+    }
+
+    /// Run the step, stopping its agent if the operator interrupts it
+    async fn run_interruptible(
+        &self,
+        step: &Step,
+        context: &PipelineContext,
+        callback: &dyn ProgressCallback,
+    ) -> ExecutionResult {
+        let Some((flag, _)) = &self.interrupt else {
+            return self.executor.execute(step, context, Some(callback)).await;
+        };
+
+        tokio::select! {
+            result = self.executor.execute_interruptible(step, context, Some(callback), flag.clone()) => result,
+            _ = async {
+                while !flag.load(Ordering::SeqCst) {
+                    tokio::time::sleep(INTERRUPT_CHECK_INTERVAL).await;
+                }
+            } => ExecutionResult::Interrupted {
+                step_id: step.id.clone(),
+                accumulated_output: String::new(),
+                recent_lines: callback.get_context_lines().unwrap_or_default(),
+                original_prompt: step.prompt_template.clone(),
+            },
+        }
+    }
+
+    /// Emit `StepHeartbeatMissed` each time another `interval` passes without
+    /// agent events (runs until dropped)
+    async fn watch_heartbeat(&self, step_id: &str, heartbeat: &HeartbeatCallback<'_>, interval: Duration) {
+        let mut reported = 0;
+        loop {
+            tokio::time::sleep(HEARTBEAT_CHECK_INTERVAL).await;
+            let silence = heartbeat.silence();
+            let missed = silence.as_millis() / interval.as_millis().max(1);
+            if missed > reported {
+                self.emit_event(ExecutionEvent::StepHeartbeatMissed {
+                    step_id: step_id.to_string(),
+                    silent_secs: silence.as_secs(),
+                })
+                .await;
+            }
+            reported = missed;
+        }
+    }
+
+    /// Handle continuation (retry or route)
+    async fn handle_continuation(
+        &self,
+        pipeline: &mut Pipeline,
+        step_id: &str,
+        action: ContinueAction,
+        _target: Option<String>,
+    ) -> Result<(), String> {
+        self.emit_event(ExecutionEvent::StepContinued {
+            step_id: step_id.to_string(),
+            action: action.clone(),
+        })
+        .await;
+
+        match action {
+            ContinueAction::Retry => {
+                // Extract current attempt count before changing state
+                let current_attempt = match pipeline.step(step_id) {
+                    Some(step) => match &step.state {
+                        StepState::Running { attempt, .. } => *attempt,
+                        StepState::Retrying { attempt } => *attempt,
+                        _ => 0,
+                    },
+                    None => 0,
+                };
+
+                // Increment attempt count for retry (each retry is a new execution attempt)
+                let new_attempt = current_attempt + 1;
+
+                // Set step to retrying state with incremented attempt
+                if let Some(step) = pipeline.step_mut(step_id) {
+                    step.state = StepState::Retrying {
+                        attempt: new_attempt,
+                    };
+                }
+                // Re-enqueue for execution
+                let mut scheduler = self.scheduler.lock().await;
+                scheduler.enqueue(step_id.to_string());
+            }
+            ContinueAction::Route(target_id) => {
+                self.emit_event(ExecutionEvent::StepRerouted {
+                    from_step: step_id.to_string(),
+                    to_step: target_id.clone(),
+                })
+                .await;
+
+                // Mark current step as completed (it's not a failure, just routing)
+                // Get the current attempt count from the Running state
+                let attempt = match pipeline.step(step_id) {
+                    Some(step) => match &step.state {
+                        StepState::Running { attempt, .. } => *attempt,
+                        _ => 1,
+                    },
+                    None => 1,
+                };
+
+                if let Some(step) = pipeline.step_mut(step_id) {
+                    let now = chrono::Utc::now();
+                    step.state = StepState::Completed {
+                        output: String::new(),
+                        attempts: attempt,
+                        started_at: now,
+                        completed_at: now,
+                    };
+                }
+
+                let Some(target_id) = self.route_target(pipeline, step_id, target_id).await else {
+                    return Ok(());
+                };
+
+                // Reset target step to Retrying state so it will execute again
+                // If target was already completed, use its attempt count (don't increment)
+                let target_attempt = match pipeline.step(&target_id) {
+                    Some(step) => match &step.state {
+                        StepState::Completed { attempts, .. } => *attempts,
+                        StepState::Failed { attempts, .. } => *attempts,
+                        StepState::Pending | StepState::Retrying { .. } => 1,
+                        _ => 1,
+                    },
+                    None => 1,
+                };
+
+                // A target still running from the same batch is left to finish
+                if let Some(step) = pipeline.step_mut(&target_id) {
+                    if !matches!(step.state, StepState::Running { .. }) {
+                        step.state = StepState::Retrying {
+                            attempt: target_attempt,
+                        };
+                    }
+                }
+
+                // Enqueue target step
+                let mut scheduler = self.scheduler.lock().await;
+                scheduler.enqueue(target_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a failed step on to its failure handler
+    async fn route_failure(&self, pipeline: &mut Pipeline, step_id: &str, next_step: String) {
+        // Don't fail the pipeline yet - route to handler first
+        // Reset pipeline state to running (so it doesn't fail)
+        if matches!(pipeline.state.status, ExecutionStatus::Failed) {
+            pipeline.state.status = ExecutionStatus::Running;
+        }
+
+        // Emit reroute event
+        self.emit_event(ExecutionEvent::StepRerouted {
+            from_step: step_id.to_string(),
+            to_step: next_step.clone(),
+        })
+        .await;
+
+        let Some(next_step) = self.route_target(pipeline, step_id, next_step).await else {
+            return;
+        };
+
+        // Reset target step to Retrying if it was already completed/failed
+        // Increment attempts to track re-execution due to routing
+        let target_attempt = match pipeline.step(&next_step) {
+            Some(step) => match &step.state {
+                StepState::Completed { attempts, .. } | StepState::Failed { attempts, .. } => {
+                    *attempts + 1
+                }
+                StepState::Pending | StepState::Retrying { .. } => 1,
+                _ => 1,
+            },
+            None => 1,
+        };
+
+        if let Some(step) = pipeline.step_mut(&next_step) {
+            if matches!(step.state, StepState::Completed { .. } | StepState::Failed { .. }) {
+                step.state = StepState::Retrying {
+                    attempt: target_attempt,
+                };
+            }
+        }
+
+        // Enqueue the failure handler
+        let mut scheduler = self.scheduler.lock().await;
+        scheduler.enqueue(next_step);
+    }
+
+    /// Step a route from `from` to `target` should actually run
+    ///
+    /// A completed `run_once` step counts as satisfied, so the route carries
+    /// on to where that step's success leads (or nowhere).
+    async fn route_target(&self, pipeline: &Pipeline, from: &str, target: String) -> Option<String> {
+        let mut target = target;
+        let mut passed = HashSet::new();
+        loop {
+            let step = pipeline.step(&target)?;
+            if !(step.run_once && matches!(step.state, StepState::Completed { .. })) {
+                return Some(target);
+            }
+            // Completed run-once steps routing to each other lead nowhere
+            if !passed.insert(target.clone()) {
+                return None;
+            }
+
+            info!("Step {} already ran (run_once); not running it again", target);
+            self.emit_event(ExecutionEvent::RunOnceStepSkipped {
+                step_id: target.clone(),
+                from_step: from.to_string(),
+            })
+            .await;
+            target = step.next_step_on_success()?.clone();
+        }
+    }
+
+    /// Render the pipeline's declared outputs, warning about any the run
+    /// could not produce
+    async fn materialize_outputs(&self, pipeline: &mut Pipeline) {
+        let unresolved = pipeline.materialize_outputs();
+        if !unresolved.is_empty() {
+            self.emit_event(ExecutionEvent::Warning {
+                scope: WarningScope::Pipeline,
+                code: WarningCode::UnresolvedOutputs,
+                message: format!("Outputs not produced by this run: {}", unresolved.join(", ")),
+            })
+            .await;
+        }
+    }
+
+    /// Mark a step as completed successfully
+    async fn mark_step_success(&self, pipeline: &mut Pipeline, step_id: &str, output: String) {
+        let remembered = pipeline
+            .step(step_id)
+            .map(|step| step.remembered_values(&output))
+            .unwrap_or_default();
+        for (key, value) in remembered {
+            pipeline.remember(&key, value.clone());
+            self.emit_event(ExecutionEvent::ValueRemembered {
+                step_id: step_id.to_string(),
+                key,
+                value,
+            })
+            .await;
+        }
+
+        if let Some(step) = pipeline.step_mut(step_id) {
+            let (started_at, attempt) = match &step.state {
+                StepState::Running { started_at, attempt } => (*started_at, *attempt),
+                _ => (chrono::Utc::now(), 1),
+            };
+
+            step.state = StepState::Completed {
+                output: output.clone(),
+                attempts: attempt,
+                started_at,
+                completed_at: chrono::Utc::now(),
+            };
+
+            self.emit_event(ExecutionEvent::StepOutput {
+                step_id: step_id.to_string(),
+                output,
+            })
+            .await;
+        }
+    }
+
+    /// Fail a step whose execution panicked
+    ///
+    /// The failure takes its configured course like any other, so other
+    /// branches keep running unless the failure policy stops them.
+    async fn fail_panicked_step(&self, pipeline: &mut Pipeline, step_id: &str, message: String) {
+        error!("Step {} panicked: {}", step_id, message);
+        let attempt = match pipeline.step(step_id).map(|step| &step.state) {
+            Some(StepState::Running { attempt, .. } | StepState::Retrying { attempt }) => *attempt,
+            Some(state) if state.is_terminal() => return,
+            _ => 1,
+        };
+        // A retry may have queued it
+        self.scheduler.lock().await.dequeue(step_id);
+        self.record_failure(pipeline, step_id, format!("Step panicked: {}", message), attempt).await;
+    }
+
+    /// Leave out a step the operator chose to skip
+    async fn skip_by_operator(&self, pipeline: &mut Pipeline, step_id: &str) {
+        let reason = "Skipped by the operator".to_string();
+        if let Some(step) = pipeline.step_mut(step_id) {
+            step.state = StepState::Skipped { reason: reason.clone() };
+        }
+        // A retry may have queued it
+        self.scheduler.lock().await.dequeue(step_id);
+        self.emit_event(ExecutionEvent::StepSkipped {
+            step_id: step_id.to_string(),
+            reason,
+        })
+        .await;
+    }
+
+    /// Stop the run at the operator's request
+    async fn abort_by_operator(&self) {
+        // `execute` notices the cancellation while this waits, and ends the
+        // run with the step cancelled
+        self.cancel.cancel("Aborted by the operator");
+        std::future::pending::<()>().await;
+    }
+
+    /// Mark a step as failed, unless the operator rescues it
+    ///
+    /// Returns whether they did; the step is then retried, skipped or
+    /// routed as they chose, and the caller must leave it alone.
+    async fn mark_step_failed(&self, pipeline: &mut Pipeline, step_id: &str, error: String, attempt: usize) -> bool {
+        let Some((timeout, steering)) = self.failure_prompt.clone() else {
+            self.record_failure(pipeline, step_id, error, attempt).await;
+            return false;
+        };
+        let Some(step) = pipeline.step(step_id) else {
+            return false;
+        };
+        let failure = StepFailure {
+            step_id: step_id.to_string(),
+            error: error.clone(),
+            attempts: attempt,
+            prompt_template: step.prompt_template.clone(),
+            on_failure: step.next_step_on_failure().cloned(),
+        };
+        let action = tokio::task::spawn_blocking(move || steering.steer_failure(&failure, timeout))
+            .await
+            .unwrap_or(FailureAction::Default);
+        info!("Failure of step {} handled by the operator: {:?}", step_id, action);
+
+        match action {
+            FailureAction::Default => {
+                self.record_failure(pipeline, step_id, error, attempt).await;
+                return false;
+            }
+            FailureAction::Retry | FailureAction::RetryWithPrompt(_) => {
+                if let Some(step) = pipeline.step_mut(step_id) {
+                    if let FailureAction::RetryWithPrompt(prompt) = action {
+                        step.prompt_template = prompt;
+                    }
+                    // The operator's retry doesn't count against the limit
+                    step.max_retries = step.max_retries.max(attempt);
+                    step.state = StepState::Retrying { attempt: attempt + 1 };
+                }
+                self.emit_event(ExecutionEvent::StepContinued {
+                    step_id: step_id.to_string(),
+                    action: ContinueAction::Retry,
+                })
+                .await;
+                self.scheduler.lock().await.enqueue(step_id.to_string());
+            }
+            FailureAction::Skip => self.skip_by_operator(pipeline, step_id).await,
+            FailureAction::Route(target) if pipeline.step(&target).is_some() => {
+                self.record_failure(pipeline, step_id, error, attempt).await;
+                self.route_failure(pipeline, step_id, target).await;
+            }
+            FailureAction::Route(target) => {
+                warn!("Cannot route step {} to unknown step {}", step_id, target);
+                self.record_failure(pipeline, step_id, error, attempt).await;
+                return false;
+            }
+            FailureAction::Abort => self.abort_by_operator().await,
+        }
+        true
+    }
+
+    /// Record a step's failure, failing the run under `fail_fast`
+    async fn record_failure(&self, pipeline: &mut Pipeline, step_id: &str, error: String, attempt: usize) {
+        if let Some(step) = pipeline.step_mut(step_id) {
+            let started_at = match &step.state {
+                StepState::Running { started_at, .. } => *started_at,
+                _ => chrono::Utc::now(),
+            };
+
+            step.state = StepState::Failed {
+                error: error.clone(),
+                attempts: attempt,
+                last_started_at: started_at,
+                failed_at: chrono::Utc::now(),
+            };
+        }
+
+        self.emit_event(ExecutionEvent::StepFailed {
+            step_id: step_id.to_string(),
+            error,
+        })
+        .await;
+
+        // Under fail_fast a step failure fails the run; otherwise the run
+        // carries on and its status is decided at the end
+        if pipeline.failure_policy == FailurePolicy::FailFast {
+            pipeline.state.fail();
+        }
+    }
+
+    /// Status of a run that reached its end: decided by the `success:`
+    /// criteria when set, otherwise failed if any step failed unhandled
+    /// (partially completed if other steps completed despite it)
+    fn final_status(pipeline: &Pipeline) -> ExecutionStatus {
+        if pipeline.has_failed() {
+            return ExecutionStatus::Failed;
+        }
+        match pipeline.success_criteria_met() {
+            Some(false) => {
+                info!("Pipeline {} did not meet its success criteria", pipeline.name);
+                ExecutionStatus::Failed
+            }
+            Some(true) => ExecutionStatus::Completed,
+            None if pipeline.unhandled_failures().is_empty() => ExecutionStatus::Completed,
+            None if pipeline.steps.values().any(|s| matches!(s.state, StepState::Completed { .. })) => {
+                ExecutionStatus::PartiallyCompleted
+            }
+            None => ExecutionStatus::Failed,
+        }
+    }
+
+    /// Save the summary and the results of `step_ids` to the persistence
+    /// backend, if there is one
+    async fn save_progress(&self, pipeline: &Pipeline, step_ids: &[String]) {
+        let Some(store) = &self.persistence else {
+            return;
+        };
+        let saved = async {
+            store.save_execution(&create_summary(pipeline)).await?;
+            for result in StepResult::from_pipeline(pipeline) {
+                if step_ids.contains(&result.step_id) {
+                    store.save_step_result(pipeline.state.execution_id, &result).await?;
+                }
+            }
+            anyhow::Ok(())
+        };
+        if let Err(e) = saved.await {
+            self.report_persistence_failure(e).await;
+        }
+    }
+
+    /// Save the finished run: its summary, every step's result and the
+    /// values steps remembered for the next run
+    async fn save_run(&self, pipeline: &Pipeline) {
+        let Some(store) = &self.persistence else {
+            return;
+        };
+        // Remembered values are kept even if a later step failed
+        for (key, value) in &pipeline.remembered {
+            if let Err(e) = store.save_memory(&pipeline.name, key, value).await {
+                self.report_persistence_failure(e).await;
+            }
+        }
+        let step_ids: Vec<String> = pipeline.steps.keys().cloned().collect();
+        self.save_progress(pipeline, &step_ids).await;
+    }
+
+    /// Keep a decision on a tool call of `step_id` in the run's audit trail
+    ///
+    /// Only a digest of the arguments is kept, since they can hold whole
+    /// files or secrets.
+    async fn save_tool_decision(&self, execution_id: Uuid, step_id: &str, decision: &ApprovalDecision) {
+        let Some(store) = &self.persistence else { return };
+        let arguments_sha256 = Sha256::digest(decision.arguments.to_string().as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let saved = ToolDecision {
+            step_id: step_id.to_string(),
+            tool_name: decision.tool_name.clone(),
+            arguments_sha256,
+            approved: decision.approved,
+            approver: decision.approver.clone(),
+            decided_at: decision.decided_at,
+        };
+        if let Err(e) = store.save_tool_decision(execution_id, &saved).await {
+            self.report_persistence_failure(e.context(format!("decision on {} call", decision.tool_name))).await;
+        }
+    }
+
+    async fn report_persistence_failure(&self, error: anyhow::Error) {
+        self.emit_event(ExecutionEvent::Warning {
+            scope: WarningScope::Pipeline,
+            code: WarningCode::PersistenceFailed,
+            message: format!("Could not save run progress: {}", error),
+        })
+        .await;
+    }
+
+    /// Update pipeline state counts
+    fn update_state_counts(&self, pipeline: &mut Pipeline) {
+        let mut completed = 0;
+        let mut failed = 0;
+        let mut running = 0;
+
+        for step in pipeline.steps.values() {
+            match &step.state {
+                // A skipped step is as done as a completed one, as far as progress goes
+                StepState::Completed { .. } | StepState::Skipped { .. } => completed += 1,
+                StepState::Failed { .. } | StepState::Cancelled { .. } => failed += 1,
+                StepState::Running { .. } => running += 1,
+                _ => {}
+            }
+        }
+
+        pipeline.state.update_counts(&pipeline.steps.len(), &completed, &failed, &running);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::PipelineConfig;
+    use crate::agent::{AgentResponse, AgentError};
+    use std::sync::Arc;
+
+    // Mock agent for testing
+    struct MockAgent {
+        responses: Vec<String>,
+        index: Arc<Mutex<usize>>,
+    }
+
+    impl MockAgent {
+        fn new(responses: Vec<String>) -> Self {
+            Self {
+                responses,
+                index: Arc::new(Mutex::new(0)),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for MockAgent {
+        async fn execute(&self, _prompt: &str) -> Result<AgentResponse, AgentError> {
+            let mut idx = self.index.lock().await;
+            if *idx < self.responses.len() {
+                let response = self.responses[*idx].clone();
+                *idx += 1;
+                Ok(AgentResponse::new(response))
+            } else {
+                Ok(AgentResponse::new("DONE".to_string()))
+            }
+        }
+
+        async fn execute_streaming(
+            &self,
+            _prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            // For testing, delegate to execute
+            self.execute(_prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_simple_pipeline() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Do task 1"
+    termination:
+      success_pattern: "DONE"
+      on_success: "step2"
+  - id: "step2"
+    name: "Second"
+    prompt: "Do task 2"
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let agent = MockAgent::new(vec!["DONE".to_string(), "DONE".to_string()]);
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+
+        let result = engine.execute(&mut pipeline).await;
+        assert!(result.is_ok());
+        assert!(pipeline.is_complete());
+    }
+
+    #[test]
+    fn test_engine_config_max_parallel_caps_strategy() {
+        let config = EngineConfig::default();
+        assert_eq!(config.effective_strategy(), SchedulingStrategy::Sequential);
+
+        let config = config.with_strategy(SchedulingStrategy::Parallel).with_max_parallel(2);
+        assert_eq!(config.effective_strategy(), SchedulingStrategy::LimitedParallel(2));
+
+        let config = config.with_strategy(SchedulingStrategy::LimitedParallel(4)).with_max_parallel(8);
+        assert_eq!(config.effective_strategy(), SchedulingStrategy::LimitedParallel(4));
+
+        let config = config.with_strategy(SchedulingStrategy::Sequential);
+        assert_eq!(config.effective_strategy(), SchedulingStrategy::Sequential);
+    }
+
+    #[tokio::test]
+    async fn test_missing_variables_emit_warning() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "step1"
+    name: "First"
+    prompt: "Fix {{ issue }} using {{ notes }}"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::Warning { scope, code, message } = event {
+                sink.lock().unwrap().push((scope, code, message));
+            }
+        }));
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, WarningScope::Step("step1".to_string()));
+        assert_eq!(warnings[0].1, WarningCode::MissingVariables);
+        assert!(warnings[0].2.ends_with("issue"));
+    }
+
+    #[tokio::test]
+    async fn test_injected_markers_warn_and_can_be_replaced() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+  - id: "build"
+    name: "Build"
+    prompt: "Build from {{ steps.plan.output }}"
+    depends_on: ["plan"]
+"#;
+        // Runs the pipeline, returning its warnings and build's prompt
+        let run = |yaml: String| async move {
+            let mut pipeline = PipelineConfig::from_yaml(&yaml).unwrap().to_pipeline();
+            let engine = ExecutionEngine::with_config(
+                MockAgent::new(vec!["Plan: ship it. DONE".to_string()]),
+                EngineConfig::default().with_prompt_preview(true),
+            );
+            let events = Arc::new(std::sync::Mutex::new((Vec::new(), String::new())));
+            let sink = events.clone();
+            engine.event_handlers.lock().await.push(Arc::new(move |event| match event {
+                ExecutionEvent::Warning { code, message, .. } => sink.lock().unwrap().0.push((code, message)),
+                ExecutionEvent::PromptPreview { step_id, prompt, .. } if step_id == "build" => {
+                    sink.lock().unwrap().1 = prompt
+                }
+                _ => {}
+            }));
+            engine.execute(&mut pipeline).await.unwrap();
+            let events = events.lock().unwrap().clone();
+            events
+        };
+
+        let (warnings, prompt) = run(yaml.to_string()).await;
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, WarningCode::InjectedMarkers);
+        assert!(warnings[0].1.contains("step 'plan' contains this step's success marker"), "{}", warnings[0].1);
+        assert!(prompt.starts_with("Build from Plan: ship it. DONE"), "{}", prompt);
+
+        let (warnings, prompt) = run(format!("sanitize_markers: true\n{}", yaml)).await;
+        assert!(warnings[0].1.ends_with("it was replaced"), "{}", warnings[0].1);
+        assert!(prompt.starts_with("Build from Plan: ship it. [success marker]"), "{}", prompt);
+    }
+
+    #[tokio::test]
+    async fn test_successful_step_remembers_values() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "triage"
+    name: "Triage"
+    prompt: "Triage issues after {{ memory.last_issue }}"
+    remember:
+      last_issue: 'LAST #(\d+)'
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+        pipeline.load_memory([("last_issue".to_string(), "7".to_string())].into());
+
+        let agent = MockAgent::new(vec!["LAST #9\nLAST #12\nDONE".to_string()]);
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(pipeline.remembered.get("last_issue").map(String::as_str), Some("12"));
+        assert_eq!(pipeline.variables.get("memory.last_issue").map(String::as_str), Some("12"));
+    }
+
+    #[tokio::test]
+    async fn test_outputs_are_materialized_at_the_end() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "open_pr"
+    name: "Open PR"
+    prompt: "Open a pull request"
+    remember:
+      url: 'PR: (\S+)'
+    termination:
+      success_pattern: "DONE"
+outputs:
+  pr_url: "{{ steps.open_pr.values.url }}"
+  branch: "{{ branch }}"
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let agent = MockAgent::new(vec!["PR: https://example.com/pr/7\nDONE".to_string()]);
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = warnings.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::Warning { code, message, .. } = event {
+                seen.lock().unwrap().push((code, message));
+            }
+        }));
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(
+            pipeline.state.outputs.get("pr_url").map(String::as_str),
+            Some("https://example.com/pr/7")
+        );
+        // `branch` was never defined, so it is left out and reported
+        assert!(!pipeline.state.outputs.contains_key("branch"));
+        let warnings = warnings.lock().unwrap();
+        assert!(warnings
+            .iter()
+            .any(|(code, message)| *code == WarningCode::UnresolvedOutputs && message.contains("branch")));
+    }
+
+    #[tokio::test]
+    async fn test_unmet_wait_for_fails_step() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "deploy"
+    name: "Deploy"
+    prompt: "Deploy the build"
+    wait_for:
+      - command: "true"
+      - file: "/nonexistent/build/output"
+    wait_timeout_secs: 0
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        let waiting = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = waiting.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::StepWaiting { condition, .. } = event {
+                sink.lock().unwrap().push(condition);
+            }
+        }));
+
+        let _ = engine.execute(&mut pipeline).await;
+
+        assert_eq!(*waiting.lock().unwrap(), vec!["file /nonexistent/build/output".to_string()]);
+        match &pipeline.step("deploy").unwrap().state {
+            StepState::Failed { error, .. } => assert!(error.contains("Timed out")),
+            other => panic!("expected failed step, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_only_matching_events() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "deploy"
+    name: "Deploy"
+    prompt: "Deploy the build"
+    wait_for:
+      - file: "/nonexistent/build/output"
+    wait_timeout_secs: 0
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        let mut failures = engine.subscribe_filtered(|event| {
+            matches!(event, ExecutionEvent::StepFailed { .. } | ExecutionEvent::PipelineCompleted { .. })
+        });
+        drop(engine.subscribe_filtered(|_| true));
+
+        let _ = engine.execute(&mut pipeline).await;
+
+        let mut received = Vec::new();
+        while let Ok(event) = failures.try_recv() {
+            received.push(event_kind(&event));
+        }
+        assert_eq!(received, vec!["StepFailed", "PipelineCompleted"]);
+        assert_eq!(engine.subscribers.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_success_criteria_decide_final_status() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "publish"
+    name: "Publish"
+    prompt: "Publish the package"
+    wait_for:
+      - file: "/nonexistent/package.tgz"
+    wait_timeout_secs: 0
+  - id: "notify"
+    name: "Notify"
+    prompt: "Post the release notes"
+success:
+  any:
+    - completed: publish
+    - all:
+        - completed: notify
+        - failed: publish
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+
+        // The failed step no longer stops the run
+        assert!(matches!(pipeline.step("notify").unwrap().state, StepState::Completed { .. }));
+        assert_eq!(pipeline.state.status, ExecutionStatus::Completed);
+
+        let strict = yaml.replace("    - all:\n        - completed: notify\n        - failed: publish\n", "");
+        let mut pipeline = PipelineConfig::from_yaml(&strict).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+        assert_eq!(pipeline.state.status, ExecutionStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_failure_policy_decides_what_runs_after_a_failure() {
+        let yaml = r#"
+name: "Test Pipeline"
+failure_policy: finish_independent
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build the package"
+    wait_for:
+      - file: "/nonexistent/Cargo.lock"
+    wait_timeout_secs: 0
+  - id: "publish"
+    name: "Publish"
+    prompt: "Publish the package"
+    depends_on: ["build"]
+  - id: "announce"
+    name: "Announce"
+    prompt: "Announce the release"
+    depends_on: ["publish"]
+  - id: "docs"
+    name: "Docs"
+    prompt: "Update the docs"
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+
+        // Steps downstream of the failure never run; the independent one does
+        assert!(matches!(pipeline.step("docs").unwrap().state, StepState::Completed { .. }));
+        assert!(matches!(pipeline.step("publish").unwrap().state, StepState::Pending));
+        assert!(matches!(pipeline.step("announce").unwrap().state, StepState::Pending));
+        assert_eq!(pipeline.state.status, ExecutionStatus::PartiallyCompleted);
+        assert_eq!(pipeline.unhandled_failures(), vec!["build".to_string()]);
+
+        let continue_all = yaml.replace("finish_independent", "continue_all");
+        let mut pipeline = PipelineConfig::from_yaml(&continue_all).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Parallel);
+        engine.execute(&mut pipeline).await.unwrap();
+        assert!(matches!(pipeline.step("announce").unwrap().state, StepState::Completed { .. }));
+        assert_eq!(pipeline.state.status, ExecutionStatus::PartiallyCompleted);
+
+        // Likewise when the step fails by running out of retries
+        let out_of_retries = continue_all.replace(
+            "    wait_for:\n      - file: \"/nonexistent/Cargo.lock\"\n    wait_timeout_secs: 0\n",
+            "    max_retries: 0\n    termination:\n      success_pattern: \"PUBLISHABLE\"\n",
+        );
+        let mut pipeline = PipelineConfig::from_yaml(&out_of_retries).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+        assert!(matches!(pipeline.step("build").unwrap().state, StepState::Failed { .. }));
+        assert!(matches!(pipeline.step("announce").unwrap().state, StepState::Completed { .. }));
+
+        let fail_fast = yaml.replace("finish_independent", "fail_fast");
+        let mut pipeline = PipelineConfig::from_yaml(&fail_fast).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+        assert!(matches!(pipeline.step("docs").unwrap().state, StepState::Pending));
+        assert_eq!(pipeline.state.status, ExecutionStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_when_condition_skips_steps() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "test"
+    name: "Test"
+    prompt: "Run the tests"
+  - id: "rerun"
+    name: "Rerun"
+    prompt: "Rerun the flaky tests"
+    depends_on: ["test"]
+    when: "{{ steps.test.output }} contains 'FLAKY'"
+  - id: "bisect"
+    name: "Bisect"
+    prompt: "Find the breaking commit"
+    depends_on: ["test"]
+    when: "{{ steps.test.output }} contains 'FAILED'"
+  - id: "report"
+    name: "Report"
+    prompt: "Summarize"
+    depends_on: ["rerun", "bisect"]
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(
+            MockAgent::new(vec!["1 FLAKY test. DONE".to_string()]),
+            SchedulingStrategy::Sequential,
+        );
+        let skipped = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = skipped.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::StepSkipped { step_id, .. } = event {
+                sink.lock().unwrap().push(step_id);
+            }
+        }));
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(*skipped.lock().unwrap(), vec!["bisect".to_string()]);
+        match &pipeline.step("bisect").unwrap().state {
+            StepState::Skipped { reason } => assert!(reason.contains("contains 'FAILED'"), "{}", reason),
+            other => panic!("expected skipped step, got {:?}", other),
+        }
+        // A skipped dependency is satisfied
+        assert!(matches!(pipeline.step("rerun").unwrap().state, StepState::Completed { .. }));
+        assert!(matches!(pipeline.step("report").unwrap().state, StepState::Completed { .. }));
+        assert_eq!(pipeline.state.status, ExecutionStatus::Completed);
+        assert_eq!(pipeline.state.progress(), 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_step_telemetry_minimizes_events() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "audit"
+    name: "Audit"
+    prompt: "Audit the secrets"
+    telemetry: metadata
+  - id: "summary"
+    name: "Summary"
+    prompt: "Summarize"
+    depends_on: ["audit"]
+outputs:
+  findings: "{{ steps.audit.output }}"
+  summary: "{{ steps.summary.output }}"
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let engine = ExecutionEngine::with_config(
+            MockAgent::new(vec![]),
+            EngineConfig::default().with_prompt_preview(true),
+        );
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            sink.lock().unwrap().push(event);
+        }));
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let events = events.lock().unwrap();
+        let of = |step: &str| -> Vec<String> {
+            events
+                .iter()
+                .filter(|event| event.step_id() == Some(step))
+                .map(event_kind)
+                .collect()
+        };
+        assert!(of("audit").contains(&"StepCompleted".to_string()), "{:?}", of("audit"));
+        assert!(!of("audit").contains(&"StepOutput".to_string()));
+        assert!(!of("audit").contains(&"PromptPreview".to_string()));
+        assert!(of("summary").contains(&"StepOutput".to_string()));
+        assert!(of("summary").contains(&"PromptPreview".to_string()));
+
+        assert_eq!(pipeline.state.outputs["findings"], Telemetry::WITHHELD);
+        assert_eq!(pipeline.state.outputs["summary"], "DONE");
+    }
+
+    #[tokio::test]
+    async fn test_execute_continues_partially_completed_pipeline() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+    termination:
+      success_pattern: "DONE"
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+    depends_on: ["plan"]
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        pipeline.step_mut("plan").unwrap().state = StepState::Completed {
+            output: "DONE".to_string(),
+            attempts: 1,
+            started_at: chrono::Utc::now(),
+            completed_at: chrono::Utc::now(),
+        };
+
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| match event {
+            ExecutionEvent::PipelineResumed { completed_steps } => {
+                sink.lock().unwrap().push(format!("resumed after {}", completed_steps.join(",")))
+            }
+            ExecutionEvent::StepStarted { step_id, .. } => sink.lock().unwrap().push(step_id),
+            _ => {}
+        }));
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec!["resumed after plan", "build"]);
+        assert_eq!(pipeline.state.completed_steps, 2);
+    }
+
+    /// Gate holding steps back for its first `holds` checks
+    struct CountingGate {
+        holds: std::sync::Mutex<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl StepGate for CountingGate {
+        async fn hold_reason(&self) -> Option<String> {
+            let mut holds = self.holds.lock().unwrap();
+            if *holds == 0 {
+                return None;
+            }
+            *holds -= 1;
+            Some("1 higher-priority run(s) to finish".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_step_gate_delays_steps() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential)
+            .with_step_gate(Arc::new(CountingGate { holds: std::sync::Mutex::new(2) }));
+        let waits = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = waits.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::StepWaiting { condition, .. } = event {
+                sink.lock().unwrap().push(condition);
+            }
+        }));
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        // Reported once, however long the step is held
+        assert_eq!(*waits.lock().unwrap(), vec!["1 higher-priority run(s) to finish".to_string()]);
+        assert!(matches!(pipeline.step("build").unwrap().state, StepState::Completed { .. }));
+    }
+
+    /// Tasks (first prompt lines) with the working directory each ran in
+    type SeenDirs = Arc<std::sync::Mutex<Vec<(String, Option<PathBuf>)>>>;
+
+    /// Agent that records the working directory each task ran in
+    struct WorkingDirAgent {
+        seen: SeenDirs,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for WorkingDirAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            let dir = STEP_WORKING_DIR.try_with(Clone::clone).ok().flatten();
+            let task = prompt.lines().next().unwrap_or_default();
+            self.seen.lock().unwrap().push((task.to_string(), dir));
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_steps_run_in_their_working_dir() {
+        let root = tempfile::tempdir().unwrap();
+        std::fs::create_dir(root.path().join("shared")).unwrap();
+        std::fs::create_dir(root.path().join("repo")).unwrap();
+        let yaml = format!(
+            r#"
+name: "Test Pipeline"
+max_retries: 0
+failure_policy: continue_all
+working_dir: "{{{{ root }}}}/shared"
+variables:
+  root: "{}"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+  - id: "build"
+    name: "Build"
+    prompt: "Build in {{{{ step.working_dir }}}}"
+    working_dir: "{{{{ root }}}}/repo"
+  - id: "missing"
+    name: "Missing"
+    prompt: "Missing"
+    working_dir: "{{{{ root }}}}/nowhere"
+  - id: "typo"
+    name: "Typo"
+    prompt: "Typo"
+    working_dir: "{{{{ rot }}}}/repo"
+"#,
+            root.path().display()
+        );
+
+        let mut pipeline = PipelineConfig::from_yaml(&yaml).unwrap().to_pipeline();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let agent = WorkingDirAgent { seen: seen.clone() };
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+        let _ = engine.execute(&mut pipeline).await;
+
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        let repo = root.path().join("repo");
+        assert_eq!(
+            seen,
+            vec![
+                (format!("Build in {}", repo.display()), Some(repo.clone())),
+                ("Plan".to_string(), Some(root.path().join("shared"))),
+            ]
+        );
+
+        // Steps whose directory is missing or can't be rendered fail without running
+        let error = |id: &str| match &pipeline.step(id).unwrap().state {
+            StepState::Failed { error, .. } => error.clone(),
+            other => panic!("expected {} to fail, got {:?}", id, other),
+        };
+        assert!(error("missing").contains("nowhere does not exist"), "{}", error("missing"));
+        assert!(error("typo").contains("Invalid working_dir"), "{}", error("typo"));
+    }
+
+    /// Environment and passthrough list of each step's agent, by prompt
+    type SeenEnv = Arc<std::sync::Mutex<Vec<(String, Vec<(String, String)>, Option<Vec<String>>)>>>;
+
+    /// Agent that records the environment it would give pi
+    struct EnvAgent {
+        seen: SeenEnv,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for EnvAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            let env = STEP_ENV
+                .try_with(Clone::clone)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|(name, _)| name != STEP_TMP_DIR_ENV)
+                .collect();
+            let passthrough = STEP_ENV_PASSTHROUGH.try_with(Clone::clone).ok().flatten();
+            let task = prompt.lines().next().unwrap_or_default().to_string();
+            self.seen.lock().unwrap().push((task, env, passthrough));
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_steps_get_their_env() {
+        let yaml = r#"
+name: "Test Pipeline"
+max_retries: 0
+failure_policy: continue_all
+env:
+  REGION: "{{ region }}"
+  LOG_LEVEL: "info"
+env_passthrough: ["PATH", "HOME"]
+variables:
+  region: "eu-west-1"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+  - id: "deploy"
+    name: "Deploy"
+    prompt: "Deploy"
+    env:
+      LOG_LEVEL: "debug"
+    env_passthrough: []
+  - id: "typo"
+    name: "Typo"
+    prompt: "Typo"
+    env:
+      TOKEN: "{{ tokn }}"
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let seen: SeenEnv = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let agent = EnvAgent { seen: seen.clone() };
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+        let _ = engine.execute(&mut pipeline).await;
+
+        let env = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(
+            seen,
+            vec![
+                ("Deploy".to_string(), env(&[("LOG_LEVEL", "debug"), ("REGION", "eu-west-1")]), Some(vec![])),
+                (
+                    "Plan".to_string(),
+                    env(&[("LOG_LEVEL", "info"), ("REGION", "eu-west-1")]),
+                    Some(vec!["PATH".to_string(), "HOME".to_string()])
+                ),
+            ]
+        );
+
+        // A value that can't be rendered fails the step without running it
+        match &pipeline.step("typo").unwrap().state {
+            StepState::Failed { error, .. } => assert!(error.contains("Invalid env"), "{}", error),
+            other => panic!("expected typo to fail, got {:?}", other),
+        }
+    }
+
+    /// Run status and (step ID, status) of each saved step result
+    type Snapshots = Arc<std::sync::Mutex<Vec<(ExecutionStatus, Vec<(String, String)>)>>>;
+
+    /// Agent that records what the store holds for the run when it's called
+    struct SnapshotAgent {
+        store: Arc<crate::persistence::InMemoryPersistence>,
+        snapshots: Snapshots,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for SnapshotAgent {
+        async fn execute(&self, _prompt: &str) -> Result<AgentResponse, AgentError> {
+            let summary = self.store.list_executions("Test Pipeline").await.unwrap().remove(0);
+            let results = self.store.load_step_results(summary.execution_id).await.unwrap();
+            let results = results.into_iter().map(|r| (r.step_id, r.status)).collect();
+            self.snapshots.lock().unwrap().push((summary.status, results));
+            Ok(AgentResponse::new("DONE version=1.2".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_is_saved_as_steps_finish() {
+        use crate::persistence::{InMemoryPersistence, PersistenceBackend};
+
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+    remember:
+      version: "version=(\\S+)"
+  - id: "test"
+    name: "Test"
+    prompt: "Test"
+    depends_on: ["build"]
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let store = Arc::new(InMemoryPersistence::new());
+        let snapshots = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let agent = SnapshotAgent {
+            store: store.clone(),
+            snapshots: snapshots.clone(),
+        };
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential).with_persistence(store.clone());
+        engine.execute(&mut pipeline).await.unwrap();
+
+        // The run was saved as running before the first step, and the first
+        // step's result was saved before the second ran
+        let snapshots = snapshots.lock().unwrap().clone();
+        assert_eq!(
+            snapshots,
+            vec![
+                (ExecutionStatus::Running, vec![]),
+                (ExecutionStatus::Running, vec![("build".to_string(), "completed".to_string())]),
+            ]
+        );
+
+        let summary = store.load_execution(pipeline.state.execution_id).await.unwrap().unwrap();
+        assert_eq!((summary.status, summary.completed_steps), (ExecutionStatus::Completed, 2));
+        let results = store.load_step_results(summary.execution_id).await.unwrap();
+        let statuses: Vec<_> = results.iter().map(|r| (r.step_id.as_str(), r.status.as_str())).collect();
+        assert_eq!(statuses, vec![("build", "completed"), ("test", "completed")]);
+        let memory = store.load_memory("Test Pipeline").await.unwrap();
+        assert_eq!(memory.get("version").map(String::as_str), Some("1.2"));
+    }
+
+    /// Agent that announces a bash call with each of `commands` before it
+    /// answers
+    struct BashAgent {
+        commands: Vec<&'static str>,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for BashAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            self.execute_streaming(prompt, None).await
+        }
+
+        async fn execute_streaming(
+            &self,
+            _prompt: &str,
+            callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            use crate::agent::pi_events::{AssistantMessageEvent, Message, ToolCall};
+            for command in &self.commands {
+                let event = crate::agent::PiJsonEvent::MessageUpdate {
+                    assistant_message_event: Some(AssistantMessageEvent::ToolcallEnd {
+                        content_index: 0,
+                        tool_call: ToolCall {
+                            tool_type: "toolCall".to_string(),
+                            id: "call_1".to_string(),
+                            name: "bash".to_string(),
+                            arguments: serde_json::json!({ "command": command }),
+                        },
+                        partial: Message {
+                            role: "assistant".to_string(),
+                            usage: None,
+                            content: vec![],
+                        },
+                    }),
+                    message: None,
+                };
+                if let Some(callback) = callback {
+                    callback.on_event(&event);
+                    if callback.abort_reason().is_some() {
+                        return Err(AgentError::Aborted("denied".to_string()));
+                    }
+                }
+            }
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_call_decisions_are_saved() {
+        use crate::persistence::{InMemoryPersistence, PersistenceBackend};
+
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan the change"
+    plan_only: true
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let store = Arc::new(InMemoryPersistence::new());
+        let agent = BashAgent {
+            commands: vec!["git status", "ls src | wc -l"],
+        };
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential).with_persistence(store.clone());
+        engine.execute(&mut pipeline).await.unwrap();
+
+        // Reading in a plan-only step neither aborts nor retries it
+        assert!(matches!(pipeline.step("plan").unwrap().state, StepState::Completed { .. }));
+        let decisions = store.load_tool_decisions(pipeline.state.execution_id).await.unwrap();
+        let saved: Vec<_> = decisions
+            .iter()
+            .map(|d| (d.step_id.as_str(), d.tool_name.as_str(), d.approved, d.approver.as_str()))
+            .collect();
+        assert_eq!(saved, vec![("plan", "bash", true, "read-only"), ("plan", "bash", true, "read-only")]);
+        let digest: String = Sha256::digest(serde_json::json!({ "command": "git status" }).to_string().as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(decisions[0].arguments_sha256, digest);
+        assert_ne!(decisions[0].arguments_sha256, decisions[1].arguments_sha256);
+    }
+
+    /// Agent that panics on prompts mentioning "explode"
+    struct PanickingAgent;
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for PanickingAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            if prompt.contains("explode") {
+                panic!("index out of bounds in {}", prompt.lines().next().unwrap_or_default());
+            }
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panicking_step_fails_without_stopping_others() {
+        let yaml = r#"
+name: "Test Pipeline"
+max_retries: 2
+failure_policy: finish_independent
+steps:
+  - id: "boom"
+    name: "Boom"
+    prompt: "explode"
+  - id: "fine"
+    name: "Fine"
+    prompt: "Carry on"
+  - id: "after_boom"
+    name: "After Boom"
+    prompt: "Never runs"
+    depends_on: ["boom"]
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let store = Arc::new(crate::persistence::InMemoryPersistence::new());
+        let engine = ExecutionEngine::new(PanickingAgent, SchedulingStrategy::Sequential).with_persistence(store.clone());
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        engine.add_event_handler(move |event| {
+            if let ExecutionEvent::StepFailed { step_id, error, .. } = event {
+                seen.lock().unwrap().push((step_id.clone(), error.clone()));
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        engine.execute(&mut pipeline).await.unwrap();
+
+        match &pipeline.step("boom").unwrap().state {
+            StepState::Failed { error, .. } => {
+                assert!(error.contains("panicked: index out of bounds in explode"), "{}", error)
+            }
+            other => panic!("expected boom to fail, got {:?}", other),
+        }
+        assert!(matches!(pipeline.step("fine").unwrap().state, StepState::Completed { .. }));
+        assert!(matches!(pipeline.step("after_boom").unwrap().state, StepState::Pending));
+        assert_eq!(pipeline.state.status, ExecutionStatus::PartiallyCompleted);
+        // Panics are not retried
+        assert_eq!(events.lock().unwrap().len(), 1);
+
+        // The failure is saved with the run
+        let summary = store.list_executions("Test Pipeline").await.unwrap().remove(0);
+        let results = store.load_step_results(summary.execution_id).await.unwrap();
+        let boom = results.iter().find(|r| r.step_id == "boom").unwrap();
+        assert_eq!(boom.status, "failed");
+    }
+
+    #[tokio::test]
+    async fn test_completed_steps_collect_their_artifacts() {
+        let root = tempfile::tempdir().unwrap();
+        let collected = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("dist")).unwrap();
+        std::fs::write(root.path().join("dist/app.tar.gz"), "archive").unwrap();
+        std::fs::write(root.path().join("dist/install.sh"), "#!/bin/sh\ntar xzf app.tar.gz\n").unwrap();
+        let yaml = format!(
+            r#"
+name: "Test Pipeline"
+working_dir: "{}"
+provenance: true
+steps:
+  - id: "package"
+    name: "Package"
+    prompt: "Package the app"
+    artifacts: ["dist/*.tar.gz", "dist/*.sh", "dist/*.sig"]
+"#,
+            root.path().display()
+        );
+
+        let mut pipeline = PipelineConfig::from_yaml(&yaml).unwrap().to_pipeline();
+        let store = Arc::new(crate::persistence::InMemoryPersistence::new());
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential)
+            .with_persistence(store.clone())
+            .with_artifacts_dir(collected.path().to_path_buf())
+            .with_step_models(HashMap::from([("package".to_string(), "claude-haiku-4-5".to_string())]));
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = warnings.clone();
+        engine.add_event_handler(move |event| {
+            if let ExecutionEvent::Warning { code, message, .. } = event {
+                seen.lock().unwrap().push((code, message));
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let artifacts = store.load_artifacts(pipeline.state.execution_id).await.unwrap();
+        let paths: Vec<_> = artifacts.iter().map(|a| a.path.as_str()).collect();
+        assert_eq!(paths, vec!["package/dist/app.tar.gz", "package/dist/install.sh"]);
+        assert_eq!(artifacts[0].size, 7);
+        let copy = collected.path().join("package/dist/app.tar.gz");
+        assert_eq!(std::fs::read_to_string(copy).unwrap(), "archive");
+
+        // Text files name the run that produced them; archives can't
+        let script = std::fs::read_to_string(collected.path().join("package/dist/install.sh")).unwrap();
+        let execution = format!("# execution: {}\n", pipeline.state.execution_id);
+        assert!(script.starts_with("#!/bin/sh\n# Generated by pi-peline\n"), "{}", script);
+        assert!(script.contains(&execution) && script.contains("# model: claude-haiku-4-5\n"), "{}", script);
+        assert!(script.ends_with("tar xzf app.tar.gz\n"), "{}", script);
+        assert_eq!(artifacts[1].size, script.len() as u64);
+
+        // A glob that matches nothing is reported, but the step still completes
+        assert!(matches!(pipeline.step("package").unwrap().state, StepState::Completed { .. }));
+        assert_eq!(
+            *warnings.lock().unwrap(),
+            vec![(WarningCode::MissingArtifacts, "No files match artifacts: dist/*.sig".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_inputs_reuse_the_last_output() {
+        let root = tempfile::tempdir().unwrap();
+        let collected = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(root.path().join("src")).unwrap();
+        std::fs::write(root.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.path().join("app.bin"), "binary").unwrap();
+        let yaml = format!(
+            r#"
+name: "Test Pipeline"
+working_dir: "{}"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build the app"
+    inputs:
+      files: ["src/*.rs"]
+    artifacts: ["app.bin"]
+  - id: "ship"
+    name: "Ship"
+    prompt: "Ship {{{{ steps.build.output }}}}"
+    depends_on: ["build"]
+"#,
+            root.path().display()
+        );
+        let config = PipelineConfig::from_yaml(&yaml).unwrap();
+        let store = Arc::new(crate::persistence::InMemoryPersistence::new());
+
+        // Runs the pipeline, returning it and how many times the agent ran
+        let run = |force: bool| {
+            let (config, store, collected) = (config.clone(), store.clone(), collected.path().to_path_buf());
+            async move {
+                let agent = MockAgent::new(vec!["built DONE".to_string(), "shipped DONE".to_string()]);
+                let calls = agent.index.clone();
+                let engine = ExecutionEngine::with_config(agent, EngineConfig::default().with_force(force))
+                    .with_persistence(store)
+                    .with_artifacts_dir(collected);
+                let reused = Arc::new(std::sync::Mutex::new(Vec::new()));
+                let seen = reused.clone();
+                engine.add_event_handler(move |event| {
+                    if let ExecutionEvent::StepReused { step_id, from_execution } = event {
+                        seen.lock().unwrap().push((step_id, from_execution));
+                    }
+                });
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                let mut pipeline = config.to_pipeline();
+                engine.execute(&mut pipeline).await.unwrap();
+                let calls = *calls.lock().await;
+                let reused = reused.lock().unwrap().clone();
+                (pipeline, calls, reused)
+            }
+        };
+
+        let (first, calls, reused) = run(false).await;
+        assert_eq!((calls, reused.len()), (2, 0));
+
+        // Only the dependent step runs; the build's output and artifacts carry over
+        let (second, calls, reused) = run(false).await;
+        assert_eq!(calls, 1);
+        assert_eq!(reused, vec![("build".to_string(), first.state.execution_id)]);
+        assert!(matches!(
+            &second.step("build").unwrap().state,
+            StepState::Completed { output, .. } if output == "built DONE"
+        ));
+        assert!(matches!(second.step("ship").unwrap().state, StepState::Completed { .. }));
+        let artifacts = store.load_artifacts(second.state.execution_id).await.unwrap();
+        assert_eq!(artifacts.iter().map(|a| a.path.as_str()).collect::<Vec<_>>(), vec!["build/app.bin"]);
+
+        // --force runs everything, as does a changed input
+        let (_, calls, reused) = run(true).await;
+        assert_eq!((calls, reused.len()), (2, 0));
+        std::fs::write(root.path().join("src/main.rs"), "fn main() { ship() }").unwrap();
+        let (_, calls, reused) = run(false).await;
+        assert_eq!((calls, reused.len()), (2, 0));
+
+        // A collected artifact that was tampered with can't be reused
+        std::fs::write(collected.path().join("build/app.bin"), "tampered").unwrap();
+        let (_, calls, _) = run(false).await;
+        assert_eq!(calls, 2);
+    }
+
+    /// Agent that writes into the scratch directory from its environment,
+    /// then fails on prompts mentioning "fail"
+    struct ScratchAgent {
+        seen: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for ScratchAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            let env = STEP_ENV.try_with(Clone::clone).unwrap_or_default();
+            let (_, dir) = env.iter().find(|(name, _)| name == STEP_TMP_DIR_ENV).unwrap();
+            std::fs::write(std::path::Path::new(dir).join("notes.txt"), "junk").unwrap();
+            self.seen.lock().unwrap().push((prompt.to_string(), dir.clone()));
+            if prompt.contains("fail") {
+                return Err(AgentError::Api("boom".to_string()));
+            }
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_step_tmp_dir_is_removed_unless_kept_on_failure() {
+        let yaml = r#"
+name: "Test Pipeline"
+max_retries: 0
+steps:
+  - id: "draft"
+    name: "Draft"
+    prompt: "Scratch in {{ step.tmp_dir }}"
+    termination:
+      success_pattern: "DONE"
+  - id: "check"
+    name: "Check"
+    prompt: "Scratch in {{ step.tmp_dir }}, then fail"
+    depends_on: ["draft"]
+    keep_tmp_dir_on_failure: true
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let engine = ExecutionEngine::new(ScratchAgent { seen: seen.clone() }, SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        for (prompt, dir) in seen.iter() {
+            assert!(prompt.starts_with(&format!("Scratch in {}", dir)), "{}", prompt);
+        }
+        let (draft_dir, check_dir) = (std::path::Path::new(&seen[0].1), std::path::Path::new(&seen[1].1));
+        assert_ne!(draft_dir, check_dir);
+        assert!(!draft_dir.exists());
+        assert!(check_dir.join("notes.txt").exists());
+        match &pipeline.step("check").unwrap().state {
+            StepState::Failed { error, .. } => assert!(error.contains(&seen[1].1), "{}", error),
+            other => panic!("expected failed step, got {:?}", other),
+        }
+        std::fs::remove_dir_all(check_dir).unwrap();
+    }
+
+    /// Agent that takes a while to answer, noting which prompts (by their
+    /// first line) ran at the same time and the most that did
+    #[derive(Default)]
+    struct OverlapAgent {
+        running: std::sync::Mutex<Vec<String>>,
+        overlaps: Arc<std::sync::Mutex<Vec<(String, String)>>>,
+        most: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for OverlapAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            let prompt = prompt.lines().next().unwrap_or_default();
+            {
+                let mut running = self.running.lock().unwrap();
+                for other in running.iter() {
+                    self.overlaps.lock().unwrap().push((other.clone(), prompt.to_string()));
+                }
+                running.push(prompt.to_string());
+                self.most.fetch_max(running.len(), Ordering::SeqCst);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            self.running.lock().unwrap().retain(|other| other != prompt);
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    /// A pipeline of independent steps, each prompting with its ID
+    fn independent_steps(ids: &[&str]) -> Pipeline {
+        let steps: String = ids
+            .iter()
+            .map(|id| format!("  - id: \"{id}\"\n    name: \"{id}\"\n    prompt: \"{id}\"\n    termination:\n      success_pattern: \"DONE\"\n"))
+            .collect();
+        PipelineConfig::from_yaml(&format!("name: \"Test Pipeline\"\nsteps:\n{}", steps))
+            .unwrap()
+            .to_pipeline()
+    }
+
+    #[tokio::test]
+    async fn test_parallel_batch_runs_at_the_same_time() {
+        let mut pipeline = independent_steps(&["a", "b", "c"]);
+        let agent = OverlapAgent::default();
+        let most = agent.most.clone();
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Parallel);
+
+        let started = std::time::Instant::now();
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(most.load(Ordering::SeqCst), 3);
+        assert!(started.elapsed() < Duration::from_millis(550), "{:?}", started.elapsed());
+        assert_eq!(pipeline.state.completed_steps, 3);
+    }
+
+    #[tokio::test]
+    async fn test_max_parallel_is_never_exceeded() {
+        let mut pipeline = independent_steps(&["a", "b", "c", "d", "e"]);
+        let agent = OverlapAgent::default();
+        let most = agent.most.clone();
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::LimitedParallel(2));
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        assert_eq!(pipeline.state.completed_steps, 5);
+
+        // Sequential runs one step at a time
+        let mut pipeline = independent_steps(&["a", "b", "c"]);
+        let agent = OverlapAgent::default();
+        let most = agent.most.clone();
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        assert_eq!(pipeline.state.completed_steps, 3);
+    }
+
+    #[tokio::test]
+    async fn test_steps_with_overlapping_locks_never_run_together() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "api"
+    name: "API"
+    prompt: "api"
+    locks: ["src/api/**"]
+    termination:
+      success_pattern: "DONE"
+  - id: "refactor"
+    name: "Refactor"
+    prompt: "refactor"
+    locks: ["src/**"]
+    termination:
+      success_pattern: "DONE"
+  - id: "docs"
+    name: "Docs"
+    prompt: "docs"
+    locks: ["docs/**"]
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+        let agent = OverlapAgent::default();
+        let overlaps = agent.overlaps.clone();
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Parallel);
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let overlaps = overlaps.lock().unwrap();
+        let together = |a: &str, b: &str| overlaps.iter().any(|(x, y)| (x == a && y == b) || (x == b && y == a));
+        assert!(!together("api", "refactor"), "{:?}", overlaps);
+        // Steps whose locks don't overlap still run alongside each other
+        assert!(together("docs", "api") || together("docs", "refactor"), "{:?}", overlaps);
+        assert_eq!(pipeline.state.completed_steps, 3);
+    }
+
+    /// Agent whose "slow" prompt answers only once the "after" prompt has
+    /// come in
+    #[derive(Default)]
+    struct GatedAgent {
+        after_started: Notify,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for GatedAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, AgentError> {
+            match prompt.lines().next() {
+                Some("slow") => self.after_started.notified().await,
+                Some("after") => self.after_started.notify_one(),
+                _ => {}
+            }
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dependent_starts_as_soon_as_its_dependency_finishes() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "fast"
+    name: "Fast"
+    prompt: "fast"
+    termination:
+      success_pattern: "DONE"
+  - id: "slow"
+    name: "Slow"
+    prompt: "slow"
+    termination:
+      success_pattern: "DONE"
+  - id: "after"
+    name: "After"
+    prompt: "after"
+    depends_on: ["fast"]
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+        let engine = ExecutionEngine::new(GatedAgent::default(), SchedulingStrategy::Parallel);
+
+        // "slow" only finishes once "after" starts, which must not wait for
+        // the stuck check
+        tokio::time::timeout(STUCK_CHECK_INTERVAL / 2, engine.execute(&mut pipeline))
+            .await
+            .expect("dependent started while another step was still running")
+            .unwrap();
+        assert_eq!(pipeline.state.completed_steps, 3);
+    }
+
+    /// Agent that stays silent for a while before answering
+    struct SilentAgent(Duration);
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for SilentAgent {
+        async fn execute(&self, _prompt: &str) -> Result<AgentResponse, AgentError> {
+            tokio::time::sleep(self.0).await;
+            Ok(AgentResponse::new("DONE".to_string()))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_silent_agent_misses_heartbeat() {
+        let yaml = r#"
+name: "Test Pipeline"
+heartbeat_secs: 1
+steps:
+  - id: "think"
+    name: "Think"
+    prompt: "Think hard"
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let engine = ExecutionEngine::new(SilentAgent(Duration::from_millis(1400)), SchedulingStrategy::Sequential);
+        let missed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = missed.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::StepHeartbeatMissed { step_id, silent_secs } = event {
+                sink.lock().unwrap().push((step_id, silent_secs));
+            }
+        }));
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(*missed.lock().unwrap(), vec![("think".to_string(), 1)]);
+        assert!(matches!(pipeline.step("think").unwrap().state, StepState::Completed { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_cancel_handle_stops_running_pipeline() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "think"
+    name: "Think"
+    prompt: "Think forever"
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let engine = ExecutionEngine::new(SilentAgent(Duration::from_secs(60)), SchedulingStrategy::Sequential);
+        let statuses = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = statuses.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::PipelineCompleted { status, .. } = event {
+                sink.lock().unwrap().push(status);
+            }
+        }));
+
+        let cancel = engine.cancel_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel("Cancelled by user");
+            cancel.cancel("Cancelled again");
+        });
+
+        let err = engine.execute(&mut pipeline).await.unwrap_err();
+        assert_eq!(err, "Cancelled by user");
+        assert!(engine.cancel_handle().reason().is_some());
+        assert_eq!(pipeline.state.status, ExecutionStatus::Cancelled);
+        match &pipeline.step("think").unwrap().state {
+            StepState::Cancelled { reason, attempts, .. } => assert_eq!((reason.as_str(), *attempts), ("Cancelled by user", 1)),
+            other => panic!("expected cancelled step, got {:?}", other),
+        }
+        assert_eq!(*statuses.lock().unwrap(), vec![ExecutionStatus::Cancelled]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_after_current_lets_the_running_step_finish() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "write"
+    name: "Write"
+    prompt: "Write the code"
+    termination:
+      success_pattern: "DONE"
+  - id: "review"
+    name: "Review"
+    prompt: "Review the code"
+    depends_on: ["write"]
+    termination:
+      success_pattern: "DONE"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let engine = ExecutionEngine::new(SilentAgent(Duration::from_millis(300)), SchedulingStrategy::Sequential);
+        let cancel = engine.cancel_handle();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            cancel.cancel_after_current("Stopped by user");
+            cancel.cancel_after_current("Stopped again");
+        });
+
+        let err = engine.execute(&mut pipeline).await.unwrap_err();
+        assert_eq!(err, "Stopped by user");
+        assert!(engine.cancel_handle().reason().is_none());
+        assert_eq!(pipeline.state.status, ExecutionStatus::Cancelled);
+        assert!(matches!(pipeline.step("write").unwrap().state, StepState::Completed { .. }));
+        assert!(matches!(pipeline.step("review").unwrap().state, StepState::Pending));
+    }
+
+    #[tokio::test]
+    async fn test_waiting_for_running_steps_wakes_on_stop_and_cancel() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "write"
+    name: "Write"
+    prompt: "Write the code"
+  - id: "review"
+    name: "Review"
+    prompt: "Review the code"
+    depends_on: ["write"]
+"#;
+
+        // A step left running (by nothing this engine drives) parks the run
+        // in the wait for readiness until it is told to stop or cancelled
+        for stop in [true, false] {
+            let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+            pipeline.step_mut("write").unwrap().state = StepState::Running {
+                started_at: chrono::Utc::now(),
+                attempt: 1,
+            };
+
+            let engine = ExecutionEngine::new(SilentAgent(Duration::from_secs(60)), SchedulingStrategy::Sequential);
+            let cancel = engine.cancel_handle();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                if stop {
+                    cancel.cancel_after_current("Stopped by user");
+                } else {
+                    cancel.cancel("Cancelled by user");
+                }
+            });
+
+            let err = tokio::time::timeout(Duration::from_secs(2), engine.execute(&mut pipeline))
+                .await
+                .expect("the wait ends well before the stuck check")
+                .unwrap_err();
+            assert_eq!(err, if stop { "Stopped by user" } else { "Cancelled by user" });
+            assert_eq!(pipeline.state.status, ExecutionStatus::Cancelled);
+            assert!(matches!(pipeline.step("review").unwrap().state, StepState::Pending));
+        }
+    }
+
+    /// Steering that always takes the same action
+    struct FixedSteering(SteeringAction);
+
+    impl Steering for FixedSteering {
+        fn steer(&self, _interruption: &Interruption) -> SteeringAction {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_step_is_steered() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "think"
+    name: "Think"
+    prompt: "Think forever"
+  - id: "report"
+    name: "Report"
+    prompt: "Report"
+    depends_on: ["think"]
+"#;
+
+        for action in [SteeringAction::Skip, SteeringAction::Abort] {
+            let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+            let flag = Arc::new(AtomicBool::new(false));
+            let engine = ExecutionEngine::new(SilentAgent(Duration::from_secs(60)), SchedulingStrategy::Sequential)
+                .with_interrupts(flag.clone(), Arc::new(FixedSteering(action)));
+
+            // Interrupt whatever runs, every 200ms
+            let interrupt = flag.clone();
+            let interrupter = tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    interrupt.store(true, Ordering::SeqCst);
+                }
+            });
+            let result = engine.execute(&mut pipeline).await;
+            interrupter.abort();
+
+            let think = &pipeline.step("think").unwrap().state;
+            let report = &pipeline.step("report").unwrap().state;
+            match action {
+                SteeringAction::Skip => {
+                    // The pipeline carries on past each skipped step
+                    assert!(result.is_ok());
+                    assert!(matches!(think, StepState::Skipped { .. }), "{:?}", think);
+                    assert!(matches!(report, StepState::Skipped { .. }), "{:?}", report);
+                }
+                _ => {
+                    assert_eq!(result.unwrap_err(), "Aborted by the operator");
+                    assert!(matches!(think, StepState::Cancelled { .. }), "{:?}", think);
+                    assert!(matches!(report, StepState::Pending));
+                    assert_eq!(pipeline.state.status, ExecutionStatus::Cancelled);
+                }
+            }
+        }
+    }
+
+    /// Steering answering failures from a script, recording what it was shown
+    struct ScriptedSteering {
+        answers: std::sync::Mutex<Vec<FailureAction>>,
+        seen: std::sync::Mutex<Vec<StepFailure>>,
+    }
+
+    impl Steering for ScriptedSteering {
+        fn steer(&self, _interruption: &Interruption) -> SteeringAction {
+            SteeringAction::Abort
+        }
+
+        fn steer_failure(&self, failure: &StepFailure, _timeout: Duration) -> FailureAction {
+            self.seen.lock().unwrap().push(failure.clone());
+            self.answers.lock().unwrap().pop().unwrap_or(FailureAction::Default)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_operator_rescues_failed_step() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build the package"
+    max_retries: 0
+    wait_for:
+      - file: "/nonexistent/Cargo.lock"
+    wait_timeout_secs: 0
+  - id: "ship"
+    name: "Ship"
+    prompt: "Ship it"
+    depends_on: ["build"]
+"#;
+
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let steering = Arc::new(ScriptedSteering {
+            answers: std::sync::Mutex::new(vec![
+                FailureAction::Skip,
+                FailureAction::RetryWithPrompt("Build it offline".to_string()),
+            ]),
+            seen: std::sync::Mutex::new(Vec::new()),
+        });
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential)
+            .with_failure_prompt(Duration::from_secs(30), steering.clone());
+        engine.execute(&mut pipeline).await.unwrap();
 
-                // Enqueue target step
-                let mut scheduler = self.scheduler.lock().await;
-                scheduler.enqueue(target_id);
-            }
-        }
+        // Retried past max_retries with the edited prompt, then skipped
+        let seen = steering.seen.lock().unwrap().clone();
+        assert_eq!(seen.iter().map(|f| f.attempts).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(seen[0].error.contains("waiting for"), "{}", seen[0].error);
+        let build = pipeline.step("build").unwrap();
+        assert_eq!(build.prompt_template, "Build it offline");
+        assert!(matches!(build.state, StepState::Skipped { .. }), "{:?}", build.state);
+        assert!(matches!(pipeline.step("ship").unwrap().state, StepState::Completed { .. }));
+        assert_eq!(pipeline.state.status, ExecutionStatus::Completed);
 
-        Ok(())
+        // Without an answer the failure takes its course
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let steering = Arc::new(ScriptedSteering {
+            answers: std::sync::Mutex::new(Vec::new()),
+            seen: std::sync::Mutex::new(Vec::new()),
+        });
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential)
+            .with_failure_prompt(Duration::from_secs(30), steering);
+        engine.execute(&mut pipeline).await.unwrap();
+        assert!(matches!(pipeline.step("build").unwrap().state, StepState::Failed { .. }));
+        assert_eq!(pipeline.state.status, ExecutionStatus::Failed);
     }
 
-    /// Mark a step as completed successfully
-    async fn mark_step_success(&self, pipeline: &mut Pipeline, step_id: &str, output: String) {
-        if let Some(step) = pipeline.step_mut(step_id) {
-            let (started_at, attempt) = match &step.state {
-                StepState::Running { started_at, attempt } => (*started_at, *attempt),
-                _ => (chrono::Utc::now(), 1),
-            };
+    #[tokio::test]
+    async fn test_pipeline_outliving_max_duration_is_cancelled() {
+        let yaml = r#"
+name: "Test Pipeline"
+max_duration_secs: 1
+steps:
+  - id: "think"
+    name: "Think"
+    prompt: "Think forever"
+    termination:
+      success_pattern: "DONE"
+  - id: "report"
+    name: "Report"
+    prompt: "Report"
+    depends_on: ["think"]
+"#;
 
-            step.state = StepState::Completed {
-                output: output.clone(),
-                attempts: attempt,
-                started_at,
-                completed_at: chrono::Utc::now(),
-            };
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
 
-            self.emit_event(ExecutionEvent::StepOutput {
-                step_id: step_id.to_string(),
-                output,
-            })
-            .await;
-        }
+        let engine = ExecutionEngine::new(SilentAgent(Duration::from_secs(60)), SchedulingStrategy::Sequential);
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            sink.lock().unwrap().push(event);
+        }));
+
+        let started = std::time::Instant::now();
+        let err = engine.execute(&mut pipeline).await.unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(err.contains("max_duration_secs"), "{}", err);
+
+        assert_eq!(pipeline.state.status, ExecutionStatus::Cancelled);
+        assert!(matches!(pipeline.step("think").unwrap().state, StepState::Cancelled { .. }));
+        assert!(matches!(pipeline.step("report").unwrap().state, StepState::Pending));
+        let events = events.lock().unwrap();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            ExecutionEvent::PipelineTimedOut { limit_secs: 1, cancelled_steps, .. } if cancelled_steps == &["think"]
+        )));
+        assert!(matches!(
+            events.last(),
+            Some(ExecutionEvent::PipelineCompleted { status: ExecutionStatus::Cancelled, .. })
+        ));
     }
 
-    /// Mark a step as failed
-    async fn mark_step_failed(&self, pipeline: &mut Pipeline, step_id: &str, error: String, attempt: usize) {
-        if let Some(step) = pipeline.step_mut(step_id) {
-            let started_at = match &step.state {
-                StepState::Running { started_at, .. } => *started_at,
-                _ => chrono::Utc::now(),
-            };
+    /// Agent that streams its answer in small pieces
+    struct ChattyAgent;
 
-            step.state = StepState::Failed {
-                error: error.clone(),
-                attempts: attempt,
-                last_started_at: started_at,
-                failed_at: chrono::Utc::now(),
-            };
+    #[async_trait::async_trait]
+    impl AgentExecutor for ChattyAgent {
+        async fn execute(&self, _prompt: &str) -> Result<AgentResponse, AgentError> {
+            Ok(AgentResponse::new("Looks fine. DONE".to_string()))
         }
 
-        self.emit_event(ExecutionEvent::StepFailed {
-            step_id: step_id.to_string(),
-            error,
-        })
-        .await;
-
-        // Check if pipeline should fail overall (configurable - for now fail on any step failure)
-        pipeline.state.fail();
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, AgentError> {
+            if let Some(cb) = callback {
+                for delta in ["Looks", " fine.", " DONE"] {
+                    let update = serde_json::json!({
+                        "type": "message_update",
+                        "assistantMessageEvent": {"type": "text_delta", "contentIndex": 0, "delta": delta}
+                    });
+                    cb.on_event(&serde_json::from_value(update).unwrap());
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+            }
+            self.execute(prompt).await
+        }
     }
 
-    /// Update pipeline state counts
-    fn update_state_counts(&self, pipeline: &mut Pipeline) {
-        let mut completed = 0;
-        let mut failed = 0;
-        let mut running = 0;
+    #[tokio::test]
+    async fn test_agent_text_is_emitted_as_it_arrives() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: "Review"
+  - id: "audit"
+    name: "Audit"
+    prompt: "Audit"
+    depends_on: ["review"]
+    telemetry: metadata
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let engine = ExecutionEngine::new(ChattyAgent, SchedulingStrategy::Sequential);
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| match event {
+            ExecutionEvent::StepOutputChunk { step_id, delta } => sink.lock().unwrap().push((step_id, Some(delta))),
+            ExecutionEvent::StepOutput { step_id, .. } => sink.lock().unwrap().push((step_id, None)),
+            _ => {}
+        }));
+        engine.execute(&mut pipeline).await.unwrap();
 
-        for step in pipeline.steps.values() {
-            match &step.state {
-                StepState::Completed { .. } => completed += 1,
-                StepState::Failed { .. } => failed += 1,
-                StepState::Running { .. } => running += 1,
-                _ => {}
-            }
-        }
+        // The chunks add up to the output and precede it; a step whose
+        // telemetry drops output streams nothing
+        let events = events.lock().unwrap().clone();
+        let (last, chunks) = events.split_last().unwrap();
+        assert_eq!(last, &("review".to_string(), None));
+        assert!(chunks.iter().all(|(step_id, _)| step_id == "review"));
+        let text: String = chunks.iter().filter_map(|(_, delta)| delta.clone()).collect();
+        assert_eq!(text, "Looks fine. DONE");
 
-        pipeline.state.update_counts(&pipeline.steps.len(), &completed, &failed, &running);
+        // Text arriving within the chunk interval is one chunk
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let config = EngineConfig::default().with_output_chunk_interval(Duration::from_secs(5));
+        let engine = ExecutionEngine::with_config(ChattyAgent, config);
+        let chunks = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = chunks.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::StepOutputChunk { delta, .. } = event {
+                sink.lock().unwrap().push(delta);
+            }
+        }));
+        let started = std::time::Instant::now();
+        engine.execute(&mut pipeline).await.unwrap();
+        assert_eq!(*chunks.lock().unwrap(), vec!["Looks fine. DONE".to_string()]);
+        // The agent finishing ends the wait
+        assert!(started.elapsed() < Duration::from_secs(5));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::core::config::PipelineConfig;
-    use crate::agent::{AgentResponse, AgentError};
-    use std::sync::Arc;
+    #[tokio::test]
+    async fn test_step_output_is_logged_per_attempt() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+    streaming: false
+"#;
+        let logs_dir = tempfile::tempdir().unwrap();
+        let logs = StepLogs {
+            dir: logs_dir.path().to_path_buf(),
+            max_bytes: 1024,
+            retention: 5,
+        };
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let agent = MockAgent::new(vec!["still building".to_string(), "built DONE".to_string()]);
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential).with_step_logs(logs.clone());
+        engine.execute(&mut pipeline).await.unwrap();
 
-    // Mock agent for testing
-    struct MockAgent {
-        responses: Vec<String>,
-        index: Arc<Mutex<usize>>,
+        let log = std::fs::read_to_string(logs.path(pipeline.state.execution_id, "build")).unwrap();
+        let attempts: Vec<&str> = log.split("=== attempt ").skip(1).collect();
+        assert_eq!(attempts.len(), 2, "{}", log);
+        assert!(attempts[0].starts_with("1 at ") && attempts[0].ends_with("===\nstill building\n"), "{}", log);
+        assert!(attempts[1].starts_with("2 at ") && attempts[1].ends_with("===\nbuilt DONE"), "{}", log);
     }
 
-    impl MockAgent {
-        fn new(responses: Vec<String>) -> Self {
-            Self {
-                responses,
-                index: Arc::new(Mutex::new(0)),
-            }
-        }
-    }
+    /// Agent that reports a fixed cost for each run
+    struct CostlyAgent(f64);
 
     #[async_trait::async_trait]
-    impl AgentExecutor for MockAgent {
+    impl AgentExecutor for CostlyAgent {
         async fn execute(&self, _prompt: &str) -> Result<AgentResponse, AgentError> {
-            let mut idx = self.index.lock().await;
-            if *idx < self.responses.len() {
-                let response = self.responses[*idx].clone();
-                *idx += 1;
-                Ok(AgentResponse::new(response))
-            } else {
-                Ok(AgentResponse::new("DONE".to_string()))
-            }
+            Ok(AgentResponse::new("DONE".to_string()))
         }
 
         async fn execute_streaming(
             &self,
-            _prompt: &str,
-            _callback: Option<&dyn crate::agent::ProgressCallback>,
+            prompt: &str,
+            callback: Option<&dyn crate::agent::ProgressCallback>,
         ) -> Result<AgentResponse, AgentError> {
-            // For testing, delegate to execute
-            self.execute(_prompt).await
+            if let Some(cb) = callback {
+                let turn_end = serde_json::json!({
+                    "type": "turn_end",
+                    "message": {"role": "assistant", "content": [], "usage": {"totalTokens": 500, "cost": {"total": self.0}}},
+                    "toolResults": []
+                });
+                cb.on_event(&serde_json::from_value(turn_end).unwrap());
+            }
+            self.execute(prompt).await
         }
     }
 
     #[tokio::test]
-    async fn test_execute_simple_pipeline() {
+    async fn test_exhausted_budget_fails_step() {
         let yaml = r#"
 name: "Test Pipeline"
+budgets:
+  analysis: 1.0
 steps:
-  - id: "step1"
-    name: "First"
-    prompt: "Do task 1"
+  - id: "a"
+    name: "A"
+    prompt: "Analyze"
+    budget_category: analysis
     termination:
       success_pattern: "DONE"
-      on_success: "step2"
-  - id: "step2"
-    name: "Second"
-    prompt: "Do task 2"
+  - id: "b"
+    name: "B"
+    prompt: "Analyze more"
+    depends_on: ["a"]
+    budget_category: analysis
+    termination:
+      success_pattern: "DONE"
+  - id: "c"
+    name: "C"
+    prompt: "Analyze even more"
+    depends_on: ["b"]
+    budget_category: analysis
     termination:
       success_pattern: "DONE"
 "#;
@@ -635,12 +4993,490 @@ steps:
         let config = PipelineConfig::from_yaml(yaml).unwrap();
         let mut pipeline = config.to_pipeline();
 
-        let agent = MockAgent::new(vec!["DONE".to_string(), "DONE".to_string()]);
-        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential, false);
+        let engine = ExecutionEngine::new(CostlyAgent(0.6), SchedulingStrategy::Sequential);
+        let costs = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = costs.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::StepCost { step_id, budget: Some(budget), .. } = event {
+                sink.lock().unwrap().push((step_id, (budget.spent_usd * 10.0).round() / 10.0));
+            }
+        }));
 
-        let result = engine.execute(&mut pipeline).await;
-        assert!(result.is_ok());
-        assert!(pipeline.is_complete());
+        let _ = engine.execute(&mut pipeline).await;
+
+        assert_eq!(*costs.lock().unwrap(), vec![("a".to_string(), 0.6), ("b".to_string(), 1.2)]);
+        match &pipeline.step("c").unwrap().state {
+            StepState::Failed { error, .. } => {
+                assert_eq!(error, "Budget 'analysis' exhausted ($1.20 of $1.00 spent)")
+            }
+            other => panic!("expected failed step, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_route_passes_over_completed_run_once_step() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "setup"
+    name: "Setup"
+    prompt: "Set up the environment"
+    run_once: true
+    termination:
+      success_pattern: "DONE"
+      on_success: "build"
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+    depends_on: ["setup"]
+    termination:
+      success_pattern: "DONE"
+      on_success: "review"
+  - id: "review"
+    name: "Review"
+    prompt: "Review"
+    depends_on: ["build"]
+    continuation:
+      pattern: "REVISE"
+      action: "route"
+      target: "setup"
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+
+        let agent = MockAgent::new(vec!["DONE".to_string(), "DONE".to_string(), "REVISE".to_string()]);
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            match event {
+                ExecutionEvent::StepStarted { step_id, .. } => sink.lock().unwrap().push(step_id),
+                ExecutionEvent::RunOnceStepSkipped { step_id, from_step } => {
+                    sink.lock().unwrap().push(format!("skipped {} from {}", step_id, from_step))
+                }
+                _ => {}
+            }
+        }));
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec!["setup", "build", "review", "skipped setup from review", "build", "review"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_misbehaving_handlers_do_not_stop_dispatch() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+
+        let engine = ExecutionEngine::with_config(
+            MockAgent::new(vec![]),
+            // Long enough that a handler busy printing its panic's backtrace
+            // is not taken for stuck and detached before the panic is heard
+            EngineConfig::default().with_handler_timeout(Duration::from_secs(1)),
+        );
+        // The slow handler is held up until the sink hears it was slow
+        let (release, held) = std::sync::mpsc::channel::<()>();
+        let held = std::sync::Mutex::new(held);
+        let release = std::sync::Mutex::new(release);
+        let slow_events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (slow_sink, sink) = (slow_events.clone(), events.clone());
+        {
+            let mut handlers = engine.event_handlers.lock().await;
+            handlers.push(Arc::new(move |event| {
+                if matches!(event, ExecutionEvent::PipelineStarted { .. }) {
+                    held.lock().unwrap().recv().unwrap();
+                }
+                slow_sink.lock().unwrap().push(event_kind(&event));
+            }));
+            handlers.push(Arc::new(|event| {
+                if matches!(event, ExecutionEvent::StepStarted { .. }) {
+                    panic!("handler bug");
+                }
+            }));
+            handlers.push(Arc::new(move |event| {
+                if let ExecutionEvent::Warning { code: WarningCode::SlowEventHandler, .. } = &event {
+                    release.lock().unwrap().send(()).unwrap();
+                }
+                sink.lock().unwrap().push(event);
+            }));
+        }
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let events = events.lock().unwrap();
+        let warnings: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ExecutionEvent::Warning { code, message, .. } => Some((*code, message.clone())),
+                _ => None,
+            })
+            .collect();
+        assert!(
+            warnings.iter().any(|(code, message)| *code == WarningCode::SlowEventHandler
+                && message.starts_with("Event handler #1 did not return")),
+            "{:?}",
+            warnings
+        );
+        assert!(
+            warnings.iter().any(|(code, message)| *code == WarningCode::EventHandlerPanicked
+                && message.contains("#2 panicked handling StepStarted")),
+            "{:?}",
+            warnings
+        );
+        assert!(events.iter().any(|event| matches!(event, ExecutionEvent::PipelineCompleted { .. })));
+
+        // The slow handler got every event, in order, by the time the run returned
+        let slow_events = slow_events.lock().unwrap();
+        assert_eq!(slow_events.first().map(String::as_str), Some("PipelineStarted"));
+        let expected: Vec<String> = events.iter().map(event_kind).collect();
+        let received: Vec<&String> = slow_events.iter().filter(|kind| kind.as_str() != "Warning").collect();
+        let expected: Vec<&String> = expected.iter().filter(|kind| kind.as_str() != "Warning").collect();
+        assert_eq!(expected.last().map(|kind| kind.as_str()), Some("PipelineCompleted"));
+        assert_eq!(received, expected);
+    }
+
+    #[tokio::test]
+    async fn test_handler_that_never_returns_does_not_hold_up_the_run() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+
+        let engine = ExecutionEngine::with_config(
+            MockAgent::new(vec![]),
+            EngineConfig::default().with_handler_timeout(Duration::from_millis(50)),
+        );
+        let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = warnings.clone();
+        {
+            let mut handlers = engine.event_handlers.lock().await;
+            handlers.push(Arc::new(|event| {
+                if matches!(event, ExecutionEvent::StepStarted { .. }) {
+                    loop {
+                        std::thread::park();
+                    }
+                }
+            }));
+            handlers.push(Arc::new(move |event| {
+                if let ExecutionEvent::Warning { message, .. } = event {
+                    sink.lock().unwrap().push(message);
+                }
+            }));
+        }
+
+        tokio::time::timeout(Duration::from_secs(10), engine.execute(&mut pipeline))
+            .await
+            .expect("execute should not wait for a stuck handler")
+            .unwrap();
+
+        let warnings = warnings.lock().unwrap();
+        assert!(
+            warnings.iter().any(|message| message.starts_with("Event handler #1 handled no events")
+                && message.ends_with("it gets no more events from this run")),
+            "{:?}",
+            warnings
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prompt_preview_is_redacted() {
+        let yaml = r#"
+name: "Test Pipeline"
+variables:
+  deploy_token: "s3cr3t-value"
+  branch: "main"
+steps:
+  - id: "deploy"
+    name: "Deploy"
+    prompt: "Deploy {{ branch }} using {{ deploy_token }}"
+"#;
+        let mut pipeline = PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+
+        let engine = ExecutionEngine::with_config(
+            MockAgent::new(vec![]),
+            EngineConfig::default().with_prompt_preview(true),
+        );
+        let previews = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = previews.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::PromptPreview { prompt, .. } = event {
+                sink.lock().unwrap().push(prompt);
+            }
+        }));
+
+        engine.execute(&mut pipeline).await.unwrap();
+
+        let previews = previews.lock().unwrap();
+        assert_eq!(previews.len(), 1);
+        assert!(previews[0].starts_with("Deploy main using [redacted]"), "{}", previews[0]);
+        assert!(!previews[0].contains("s3cr3t"));
+    }
+
+    #[tokio::test]
+    async fn test_poll_step_retries_check_until_it_passes() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = dir.path().join("count");
+        let yaml = format!(
+            r#"
+name: "Test Pipeline"
+steps:
+  - id: "ci"
+    name: "Wait for CI"
+    poll:
+      command: "n=$(cat {counter} 2>/dev/null || echo 0); echo $((n+1)) > {counter}; [ $n -ge 1 ]"
+      interval_secs: 1
+  - id: "never"
+    name: "Never green"
+    depends_on: ["ci"]
+    poll:
+      command: "false"
+      timeout_secs: 0
+"#,
+            counter = counter.display()
+        );
+
+        let config = PipelineConfig::from_yaml(&yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+
+        let engine = ExecutionEngine::new(MockAgent::new(vec![]), SchedulingStrategy::Sequential);
+        let attempts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = attempts.clone();
+        engine.event_handlers.lock().await.push(Arc::new(move |event| {
+            if let ExecutionEvent::PollAttempt { step_id, attempt, passed } = event {
+                sink.lock().unwrap().push((step_id, attempt, passed));
+            }
+        }));
+
+        let _ = engine.execute(&mut pipeline).await;
+
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec![
+                ("ci".to_string(), 1, false),
+                ("ci".to_string(), 2, true),
+                ("never".to_string(), 1, false),
+            ]
+        );
+        assert!(matches!(pipeline.step("ci").unwrap().state, StepState::Completed { .. }));
+        match &pipeline.step("never").unwrap().state {
+            StepState::Failed { error, .. } => assert_eq!(error, "`false` did not pass within 0s (1 attempt)"),
+            other => panic!("expected failed step, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_step_runs_child_pipeline() {
+        use crate::persistence::{InMemoryPersistence, PersistenceBackend};
+        use std::collections::BTreeMap;
+
+        let dir = tempfile::tempdir().unwrap();
+        let write = |file: &str, yaml: &str| std::fs::write(dir.path().join(file), yaml).unwrap();
+        write(
+            "release.yaml",
+            r#"
+name: "Release"
+outputs:
+  version: "{{ steps.build.values.version }}"
+  channel: "{{ channel }}"
+steps:
+  - id: "build"
+    name: "Build"
+    prompt: "Build for {{ channel }}"
+    remember:
+      version: "version=(\\S+)"
+"#,
+        );
+        write(
+            "broken.yaml",
+            r#"
+name: "Broken"
+steps:
+  - id: "check"
+    name: "Check"
+    poll:
+      command: "false"
+      timeout_secs: 0
+"#,
+        );
+        write(
+            "stuck.yaml",
+            r#"
+name: "Stuck"
+steps:
+  - id: "wait"
+    name: "Wait"
+    poll:
+      command: "false"
+      interval_secs: 1
+"#,
+        );
+        write(
+            "parent.yaml",
+            r#"
+name: "Parent"
+failure_policy: continue_all
+variables:
+  channel: "beta"
+outputs:
+  version: "{{ steps.release.outputs.version }}"
+steps:
+  - id: "release"
+    name: "Release"
+    pipeline:
+      file: "release.yaml"
+      variables:
+        channel: "{{ channel }}"
+  - id: "broken"
+    name: "Broken"
+    depends_on: ["release"]
+    pipeline:
+      file: "broken.yaml"
+  - id: "stuck"
+    name: "Stuck"
+    depends_on: ["release"]
+    pipeline:
+      file: "stuck.yaml"
+      timeout_secs: 1
+"#,
+        );
+        let mut pipeline = PipelineConfig::from_file(dir.path().join("parent.yaml")).unwrap().to_pipeline();
+
+        let store = Arc::new(InMemoryPersistence::new());
+        let engine = ExecutionEngine::new(MockAgent::new(vec!["version=2.0 DONE".to_string()]), SchedulingStrategy::Sequential)
+            .with_persistence(store.clone());
+        let mut events = engine.subscribe_filtered(|event| matches!(event, ExecutionEvent::SubPipelineStepFinished { .. }));
+        engine.execute(&mut pipeline).await.unwrap();
+
+        // The child's outputs are the step's output, and reach the parent's prompts
+        match &pipeline.step("release").unwrap().state {
+            StepState::Completed { output, .. } => {
+                let outputs: BTreeMap<String, String> = serde_json::from_str(output).unwrap();
+                assert_eq!(outputs, BTreeMap::from([("channel".into(), "beta".into()), ("version".into(), "2.0".into())]));
+            }
+            other => panic!("expected completed step, got {:?}", other),
+        }
+        assert_eq!(pipeline.state.outputs.get("version").map(String::as_str), Some("2.0"));
+
+        // A failed or cancelled child fails the step
+        let error = |id: &str| match &pipeline.step(id).unwrap().state {
+            StepState::Failed { error, .. } => error.clone(),
+            other => panic!("expected failed step, got {:?}", other),
+        };
+        let broken = error("broken");
+        assert!(broken.starts_with("Pipeline 'Broken' (run "), "{}", broken);
+        assert!(broken.ends_with("failed: check: `false` did not pass within 0s (1 attempt)"), "{}", broken);
+        let stuck = error("stuck");
+        assert!(
+            stuck.ends_with("did not finish: Cancelled: pipeline ran longer than max_duration_secs (1s)"),
+            "{}",
+            stuck
+        );
+
+        // Each child run is saved under its own ID, linked to the parent's
+        for (name, status) in [("Release", ExecutionStatus::Completed), ("Stuck", ExecutionStatus::Cancelled)] {
+            let runs = store.list_executions(name).await.unwrap();
+            assert_eq!(runs.len(), 1, "{}", name);
+            assert_eq!(runs[0].status, status);
+            assert_eq!(runs[0].parent_execution_id, Some(pipeline.state.execution_id));
+        }
+
+        let mut finished = Vec::new();
+        while let Ok(ExecutionEvent::SubPipelineStepFinished { step_id, child_step_id, error }) = events.try_recv() {
+            finished.push((step_id, child_step_id, error.is_none()));
+        }
+        assert_eq!(
+            finished,
+            vec![("release".to_string(), "build".to_string(), true), ("broken".to_string(), "check".to_string(), false)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queue_steps_hand_items_between_runs() {
+        use crate::persistence::{InMemoryPersistence, PersistenceBackend};
+
+        let producer = PipelineConfig::from_yaml(
+            r#"
+name: "Producer"
+variables:
+  files: "a.rs\n\nb.rs\nc.rs"
+steps:
+  - id: "enqueue"
+    name: "Enqueue"
+    queue:
+      push: "migrate"
+      items: "{{ files }}"
+"#,
+        )
+        .unwrap();
+        let consumer = PipelineConfig::from_yaml(
+            r#"
+name: "Consumer"
+steps:
+  - id: "take"
+    name: "Take"
+    queue:
+      pop: "migrate"
+      batch: 2
+  - id: "migrate"
+    name: "Migrate"
+    depends_on: ["take"]
+    when: "{{ steps.take.output }}"
+    prompt: "Migrate {{ steps.take.output }}"
+"#,
+        )
+        .unwrap();
+
+        let store = Arc::new(InMemoryPersistence::new());
+        let run = |config: &PipelineConfig| {
+            let mut pipeline = config.to_pipeline();
+            let engine = ExecutionEngine::new(MockAgent::new(vec!["DONE".to_string()]), SchedulingStrategy::Sequential)
+                .with_persistence(store.clone());
+            async move {
+                engine.execute(&mut pipeline).await.unwrap();
+                pipeline
+            }
+        };
+
+        let produced = run(&producer).await;
+        assert_eq!(produced.state.status, ExecutionStatus::Completed);
+        let queues = store.list_queues().await.unwrap();
+        assert_eq!((queues[0].queue.as_str(), queues[0].items), ("migrate", 3));
+
+        // Consumers take batches in order until the queue runs dry
+        let mut taken = Vec::new();
+        for _ in 0..3 {
+            let consumed = run(&consumer).await;
+            assert_eq!(consumed.state.status, ExecutionStatus::Completed);
+            match (&consumed.step("take").unwrap().state, &consumed.step("migrate").unwrap().state) {
+                (StepState::Completed { output, .. }, StepState::Completed { .. }) => taken.push(output.clone()),
+                (StepState::Completed { output, .. }, StepState::Skipped { .. }) => assert_eq!(output, ""),
+                other => panic!("unexpected states {:?}", other),
+            }
+        }
+        assert_eq!(taken, vec!["a.rs\nb.rs".to_string(), "c.rs".to_string()]);
+        assert!(store.list_queues().await.unwrap().is_empty());
+
+        // Without history there is nowhere to keep the queue
+        let mut pipeline = consumer.to_pipeline();
+        let engine = ExecutionEngine::new(MockAgent::new(vec!["DONE".to_string()]), SchedulingStrategy::Sequential);
+        let _ = engine.execute(&mut pipeline).await;
+        match &pipeline.step("take").unwrap().state {
+            StepState::Failed { error, .. } => assert!(error.contains("kept in history"), "{}", error),
+            other => panic!("expected failed step, got {:?}", other),
+        }
     }
 
     // Phase 4: Engine Steering Action Tests
@@ -684,7 +5520,6 @@ steps:
     async fn test_engine_handle_steering_action_retry() {
         // Test that retry action properly re-enqueues the step
         use crate::core::StepState;
-        use crate::execution::ExecutionScheduler;
 
         let yaml = r#"
 name: "Test Pipeline"
@@ -700,7 +5535,7 @@ steps:
         let pipeline = config.to_pipeline();
 
         let agent = MockAgent::new(vec!["DONE".to_string()]);
-        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential, false);
+        let _engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
 
         // Simulate retry by setting step to Retrying state
         let mut pipeline_mut = pipeline;
@@ -818,7 +5653,7 @@ steps:
 
         // Agent never outputs "DONE", so step will retry until max_retries exceeded
         let agent = MockAgent::new(vec!["Still working...".to_string(); 10]);
-        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential, false);
+        let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
 
         let result = engine.execute(&mut pipeline).await;
 
@@ -0,0 +1,114 @@
+//! JSON lines log of execution events
+//!
+//! Every event of a run is appended to the log as one JSON object, stamped
+//! with the time it was handled and the run's execution ID, for analysis
+//! after the run (with `jq`, say):
+//!
+//! ```json
+//! {"timestamp":"2024-05-14T12:00:01Z","execution_id":"3f2a…","event":{"type":"step_started","step_id":"build","attempt":1}}
+//! ```
+
+use crate::execution::ExecutionEvent;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+/// A log file events are appended to
+pub struct EventLog {
+    file: Mutex<File>,
+    execution_id: Uuid,
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    timestamp: DateTime<Utc>,
+    execution_id: Uuid,
+    event: &'a ExecutionEvent,
+}
+
+impl EventLog {
+    /// Append the events of run `execution_id` to the file at `path`,
+    /// creating it if needed
+    pub fn open(path: &Path, execution_id: Uuid) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            execution_id,
+        })
+    }
+
+    /// Append one event as a line of JSON
+    pub fn append(&self, event: &ExecutionEvent) -> std::io::Result<()> {
+        let entry = Entry {
+            timestamp: Utc::now(),
+            execution_id: self.execution_id,
+            event,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file.lock().unwrap().write_all(&line)
+    }
+
+    /// Event handler appending every event to the log
+    ///
+    /// An event that can't be written is skipped with a debug message, so
+    /// a full disk doesn't stop the run.
+    pub fn into_handler(self) -> impl Fn(ExecutionEvent) + Send + Sync + 'static {
+        move |event| {
+            if let Err(e) = self.append(&event) {
+                tracing::debug!("Could not write to the event log: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ExecutionStatus;
+    use crate::execution::engine::{WarningCode, WarningScope};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_events_are_appended_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let execution_id = Uuid::new_v4();
+        std::fs::write(&path, "{\"earlier\":true}\n").unwrap();
+
+        let handler = EventLog::open(&path, execution_id).unwrap().into_handler();
+        handler(ExecutionEvent::StepStarted {
+            step_id: "build".to_string(),
+            attempt: 1,
+        });
+        handler(ExecutionEvent::Warning {
+            scope: WarningScope::Step("build".to_string()),
+            code: WarningCode::SlowStep,
+            message: "Slow".to_string(),
+        });
+        handler(ExecutionEvent::PipelineCompleted {
+            execution_id,
+            status: ExecutionStatus::Completed,
+        });
+
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 4, "earlier lines are kept");
+        assert!(lines[1..].iter().all(|line| line["execution_id"] == json!(execution_id)));
+        assert!(lines[1]["timestamp"].as_str().unwrap().parse::<DateTime<Utc>>().is_ok());
+        assert_eq!(lines[1]["event"], json!({"type": "step_started", "step_id": "build", "attempt": 1}));
+        assert_eq!(
+            lines[2]["event"],
+            json!({"type": "warning", "scope": {"step": "build"}, "code": "slow_step", "message": "Slow"})
+        );
+        assert_eq!(lines[3]["event"]["type"], "pipeline_completed");
+        assert_eq!(lines[3]["event"]["status"], "Completed");
+    }
+}
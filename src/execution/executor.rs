@@ -1,11 +1,15 @@
 //! Step executor - runs individual steps with the agent
 
 use crate::{
-    agent::{AgentExecutor, ProgressCallback},
-    core::{Step, PipelineContext},
+    agent::{AgentExecutor, AgentError, AgentResponse, PiJsonEvent, ProgressCallback, STEP_SESSION},
+    agent::pi_events::AssistantMessageEvent,
+    core::{AgentAttempt, Step, PipelineContext},
+    core::config::{ContinuationAction, Precedence},
 };
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use tokio::time::{timeout, Duration};
 use tracing::{debug, info, warn, error};
 
@@ -41,7 +45,8 @@ pub enum ExecutionResult {
 }
 
 /// Action to take for continuation
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ContinueAction {
     /// Retry the same step
     Retry,
@@ -51,107 +56,326 @@ pub enum ContinueAction {
 
 /// Executes a single step
 pub struct StepExecutor<A> {
-    agent: A,
+    agent: Arc<A>,
+    profiles: HashMap<String, Arc<dyn AgentExecutor>>,
+    step_agents: HashMap<String, Arc<dyn AgentExecutor>>,
+    agent_attempts: Mutex<HashMap<String, Vec<AgentAttempt>>>,
+    both_matched: Mutex<HashSet<String>>,
 }
 
 impl<A: AgentExecutor> StepExecutor<A> {
     pub fn new(agent: A) -> Self {
-        Self { agent }
+        Self {
+            agent: Arc::new(agent),
+            profiles: HashMap::new(),
+            step_agents: HashMap::new(),
+            agent_attempts: Mutex::new(HashMap::new()),
+            both_matched: Mutex::new(HashSet::new()),
+        }
     }
 
-    /// Execute a step and return the result
-    pub async fn execute(
+    /// An executor for the steps of a child pipeline: the same agent and
+    /// profiles, without the agents of this pipeline's steps
+    pub fn for_sub_pipeline(&self) -> Self {
+        Self {
+            agent: self.agent.clone(),
+            profiles: self.profiles.clone(),
+            step_agents: HashMap::new(),
+            agent_attempts: Mutex::new(HashMap::new()),
+            both_matched: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Register the named agent profiles steps can list under `agents:`
+    pub fn with_profiles(mut self, profiles: HashMap<String, Arc<dyn AgentExecutor>>) -> Self {
+        self.profiles = profiles;
+        self
+    }
+
+    /// Register the agents of steps that set their own `agent:`, by step ID
+    pub fn with_step_agents(mut self, step_agents: HashMap<String, Arc<dyn AgentExecutor>>) -> Self {
+        self.step_agents = step_agents;
+        self
+    }
+
+    /// Take the agent calls recorded for a step since the last call
+    ///
+    /// Only steps that list agent profiles are recorded.
+    pub fn take_agent_attempts(&self, step_id: &str) -> Vec<AgentAttempt> {
+        self.agent_attempts
+            .lock()
+            .unwrap()
+            .remove(step_id)
+            .unwrap_or_default()
+    }
+
+    /// Take whether a step's last output matched both its success and its
+    /// continuation pattern
+    pub fn take_both_matched(&self, step_id: &str) -> bool {
+        self.both_matched.lock().unwrap().remove(step_id)
+    }
+
+    /// What the agent's final output means for the step
+    ///
+    /// An output matching both the success and the continuation pattern is
+    /// settled by the step's `precedence`, and recorded for
+    /// `take_both_matched`.
+    fn outcome(&self, step: &Step, output: String) -> ExecutionResult {
+        let success = step.is_success(&output);
+        let continuation = step.needs_continuation(&output);
+        if success && continuation {
+            warn!("Step {} output matched both its success and continuation patterns", step.id);
+            self.both_matched.lock().unwrap().insert(step.id.clone());
+        }
+
+        match (success, continuation, step.precedence) {
+            (true, true, Precedence::Error) => {
+                let error = "Output matched both the success and the continuation pattern".to_string();
+                match step.next_step_on_failure() {
+                    Some(target) => {
+                        info!("Step {} routing to failure handler: {}", step.id, target);
+                        ExecutionResult::FailedWithRoute {
+                            error,
+                            next_step: target.clone(),
+                        }
+                    }
+                    None => ExecutionResult::Failed { error },
+                }
+            }
+            (true, _, Precedence::SuccessFirst) | (true, false, _) => {
+                let next_step = step.next_step_on_success().cloned();
+                info!("Step {} completed successfully", step.id);
+                if let Some(ref next) = next_step {
+                    info!("  Next step: {}", next);
+                }
+                ExecutionResult::Success { output, next_step }
+            }
+            (_, true, _) => match step.get_continuation_action() {
+                Some((ContinuationAction::Route, Some(target))) => {
+                    info!("Step {} requested continuation (route to {})", step.id, target);
+                    ExecutionResult::Continue {
+                        action: ContinueAction::Route(target.clone()),
+                        target: Some(target.clone()),
+                    }
+                }
+                _ => {
+                    info!("Step {} requested continuation (retry)", step.id);
+                    ExecutionResult::Continue {
+                        action: ContinueAction::Retry,
+                        target: None,
+                    }
+                }
+            },
+            (false, false, _) => {
+                // No expected pattern found - handle as retry (if no on_failure route)
+                warn!(
+                    "Step {} did not output expected termination pattern",
+                    step.id
+                );
+                let next_step = step.next_step_on_failure().cloned();
+
+                // If there's an on_failure route, route to failure handler
+                if let Some(target) = next_step {
+                    info!("Step {} routing to failure handler: {}", step.id, target);
+                    ExecutionResult::FailedWithRoute {
+                        error: "No termination pattern found".to_string(),
+                        next_step: target,
+                    }
+                } else {
+                    // No failure handler - retry the step instead of failing the pipeline
+                    info!("Step {} will retry for termination pattern", step.id);
+                    ExecutionResult::Continue {
+                        action: ContinueAction::Retry,
+                        target: None,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run the step's agents in order until one responds
+    ///
+    /// Each agent profile gets `failover_after` consecutive attempts that end
+    /// in a provider error (pi exiting with an error, or a timeout) before the
+    /// next profile takes over. Other errors, such as aborts, fail at once.
+    /// Steps without profiles make a single call to their own agent, or the
+    /// default agent if they have none. A profile that isn't registered
+    /// fails the step before any agent runs.
+    async fn run_with_failover(
+        &self,
+        step: &Step,
+        prompt: &str,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, String> {
+        if step.agents.is_empty() {
+            let agent: &dyn AgentExecutor = match self.step_agents.get(&step.id) {
+                Some(agent) => agent.as_ref(),
+                None => self.agent.as_ref(),
+            };
+            return self.run_with_timeout(agent, step, prompt, callback).await.map_err(|e| {
+                error!("Agent error for step {}: {}", step.id, e);
+                e.to_string()
+            });
+        }
+
+        if let Some(name) = step.agents.iter().find(|name| !self.profiles.contains_key(*name)) {
+            error!("Agent profile '{}' of step {} is not registered", name, step.id);
+            return Err(format!("Agent profile '{}' is not registered", name));
+        }
+
+        let mut last_error = String::new();
+        for name in &step.agents {
+            let agent = self.profiles[name].as_ref();
+
+            for _ in 0..step.failover_after {
+                let result = self.run_with_timeout(agent, step, prompt, callback).await;
+                let error = result.as_ref().err().map(|e| e.to_string());
+                self.agent_attempts
+                    .lock()
+                    .unwrap()
+                    .entry(step.id.clone())
+                    .or_default()
+                    .push(AgentAttempt {
+                        agent: name.clone(),
+                        error: error.clone(),
+                    });
+
+                match result {
+                    Ok(response) => return Ok(response),
+                    Err(e @ (AgentError::Api(_) | AgentError::Timeout(_))) => {
+                        warn!("Agent '{}' failed for step {}: {}", name, step.id, e);
+                        last_error = e.to_string();
+                    }
+                    Err(e) => {
+                        error!("Agent error for step {}: {}", step.id, e);
+                        return Err(e.to_string());
+                    }
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Send the prompt, then the step's follow-up replies while the agent's
+    /// output calls for one, up to `max_followups`
+    ///
+    /// Replies continue the agent's session (a session file in a temporary
+    /// directory); each call gets the step's full timeout. Returns the last
+    /// response.
+    async fn run_conversation(
         &self,
         step: &Step,
         context: &PipelineContext,
+        prompt: &str,
         callback: Option<&dyn ProgressCallback>,
-    ) -> ExecutionResult {
-        info!("Executing step: {}", step.id);
+    ) -> Result<AgentResponse, String> {
+        if step.followups.is_empty() {
+            return self.run_with_failover(step, prompt, callback).await;
+        }
 
-        let effective_prompt = step.build_effective_prompt(&context.get_rendering_variables());
-        debug!("Effective prompt for step {}: {}", step.id, effective_prompt);
+        let session_dir = tempfile::tempdir().map_err(|e| format!("Could not create a session directory: {}", e))?;
+        let session = Some(session_dir.path().join("session.jsonl"));
+        STEP_SESSION
+            .scope(session, async {
+                let mut response = self.run_with_failover(step, prompt, callback).await?;
+                let variables = context.get_rendering_variables();
+                for round in 1..=step.max_followups {
+                    let Some(reply) = step.followup_reply(&response.content, &variables) else {
+                        break;
+                    };
+                    info!("Step {} sends follow-up {}/{}", step.id, round, step.max_followups);
+                    if step.telemetry.keeps_content() {
+                        debug!("Follow-up for step {}: {}", step.id, reply);
+                    }
+                    response = self.run_with_failover(step, &reply, callback).await?;
+                }
+                Ok(response)
+            })
+            .await
+    }
 
-        // Execute with streaming for live output display
+    /// Run an agent, giving up after the step's timeout
+    async fn run_with_timeout(
+        &self,
+        agent: &dyn AgentExecutor,
+        step: &Step,
+        prompt: &str,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, AgentError> {
         let timeout_duration = Duration::from_secs(step.timeout_secs);
-        let result = match timeout(
-            timeout_duration,
-            self.agent.execute_streaming(&effective_prompt, callback)
-        ).await {
-            Ok(Ok(response)) => response,
-            Ok(Err(e)) => {
-                error!("Agent error for step {}: {}", step.id, e);
-                return ExecutionResult::Failed {
-                    error: e.to_string(),
-                };
-            }
+        match timeout(timeout_duration, self.run_agent(agent, step, prompt, callback)).await {
+            Ok(result) => result,
             Err(_) => {
                 error!("Timeout for step {} after {}s", step.id, step.timeout_secs);
-                return ExecutionResult::Failed {
-                    error: format!("Timeout after {} seconds", step.timeout_secs),
-                };
+                Err(AgentError::Timeout(step.timeout_secs))
             }
-        };
+        }
+    }
 
-        debug!("Agent response for step {}: {}", step.id, result.content);
-
-        // Check for continuation first (agent wants more work)
-        if step.needs_continuation(&result.content) {
-            if let Some((action, target)) = step.get_continuation_action() {
-                return match action {
-                    crate::core::config::ContinuationAction::Retry => {
-                        info!("Step {} requested continuation (retry)", step.id);
-                        ExecutionResult::Continue {
-                            action: ContinueAction::Retry,
-                            target: None,
-                        }
-                    }
-                    crate::core::config::ContinuationAction::Route => {
-                        let target_id = target.unwrap().clone();
-                        info!("Step {} requested continuation (route to {})", step.id, target_id);
-                        ExecutionResult::Continue {
-                            action: ContinueAction::Route(target_id.clone()),
-                            target: Some(target_id),
-                        }
-                    }
-                };
-            }
+    /// Run the agent in streaming or text mode, as configured for the step
+    ///
+    /// In text mode the callback still receives start, text and end events,
+    /// synthesized from the final output.
+    async fn run_agent(
+        &self,
+        agent: &dyn AgentExecutor,
+        step: &Step,
+        prompt: &str,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> Result<AgentResponse, AgentError> {
+        if step.streaming {
+            return agent.execute_streaming(prompt, callback).await;
         }
 
-        // Check for successful completion
-        if step.is_success(&result.content) {
-            let next_step = step.next_step_on_success().cloned();
-            info!("Step {} completed successfully", step.id);
-            if let Some(ref next) = next_step {
-                info!("  Next step: {}", next);
-            }
-            return ExecutionResult::Success {
-                output: result.content,
-                next_step,
+        debug!("Step {} runs in text mode", step.id);
+        let response = agent.execute(prompt).await?;
+
+        if let Some(cb) = callback {
+            let text_event = |event| PiJsonEvent::MessageUpdate {
+                assistant_message_event: Some(event),
+                message: None,
             };
+            cb.on_event(&PiJsonEvent::AgentStart);
+            cb.on_event(&text_event(AssistantMessageEvent::TextDelta {
+                content_index: 0,
+                delta: response.content.clone(),
+            }));
+            cb.on_event(&text_event(AssistantMessageEvent::TextEnd {
+                content_index: 0,
+                content: Some(response.content.clone()),
+            }));
+            cb.on_event(&PiJsonEvent::AgentEnd);
         }
 
-        // No expected pattern found - handle as retry (if no on_failure route)
-        warn!(
-            "Step {} did not output expected termination pattern",
-            step.id
-        );
-        let next_step = step.next_step_on_failure().cloned();
-
-        // If there's an on_failure route, route to failure handler
-        if let Some(target) = next_step {
-            info!("Step {} routing to failure handler: {}", step.id, target);
-            ExecutionResult::FailedWithRoute {
-                error: "No termination pattern found".to_string(),
-                next_step: target,
-            }
-        } else {
-            // No failure handler - retry the step instead of failing the pipeline
-            info!("Step {} will retry for termination pattern", step.id);
-            ExecutionResult::Continue {
-                action: ContinueAction::Retry,
-                target: None,
-            }
+        Ok(response)
+    }
+
+    /// Execute a step and return the result
+    pub async fn execute(
+        &self,
+        step: &Step,
+        context: &PipelineContext,
+        callback: Option<&dyn ProgressCallback>,
+    ) -> ExecutionResult {
+        info!("Executing step: {}", step.id);
+
+        let effective_prompt = step.build_effective_prompt(&context.get_rendering_variables());
+        if step.telemetry.keeps_content() {
+            debug!("Effective prompt for step {}: {}", step.id, effective_prompt);
+        }
+
+        // Execute with streaming for live output display
+        let result = match self.run_conversation(step, context, &effective_prompt, callback).await {
+            Ok(response) => response,
+            Err(error) => return ExecutionResult::Failed { error },
+        };
+
+        if step.telemetry.keeps_content() {
+            debug!("Agent response for step {}: {}", step.id, result.content);
         }
+
+        self.outcome(step, result.content)
     }
 
     /// Execute a step with interruption support
@@ -194,17 +418,14 @@ impl<A: AgentExecutor> StepExecutor<A> {
         }
 
         let effective_prompt = step.build_effective_prompt(&context.get_rendering_variables());
-        debug!("Effective prompt for step {}: {}", step.id, effective_prompt);
+        if step.telemetry.keeps_content() {
+            debug!("Effective prompt for step {}: {}", step.id, effective_prompt);
+        }
 
         // Execute with streaming for live output display
-        let timeout_duration = Duration::from_secs(step.timeout_secs);
-        let result = match timeout(
-            timeout_duration,
-            self.agent.execute_streaming(&effective_prompt, callback)
-        ).await {
-            Ok(Ok(response)) => response,
-            Ok(Err(e)) => {
-                error!("Agent error for step {}: {}", step.id, e);
+        let result = match self.run_conversation(step, context, &effective_prompt, callback).await {
+            Ok(response) => response,
+            Err(error) => {
                 // Check for interruption
                 if interrupted.load(Ordering::SeqCst) {
                     return ExecutionResult::Interrupted {
@@ -216,26 +437,7 @@ impl<A: AgentExecutor> StepExecutor<A> {
                         original_prompt: effective_prompt,
                     };
                 }
-                return ExecutionResult::Failed {
-                    error: e.to_string(),
-                };
-            }
-            Err(_) => {
-                error!("Timeout for step {} after {}s", step.id, step.timeout_secs);
-                // Check for interruption
-                if interrupted.load(Ordering::SeqCst) {
-                    return ExecutionResult::Interrupted {
-                        step_id: step.id.clone(),
-                        accumulated_output: String::new(),
-                        recent_lines: callback
-                            .and_then(|cb| cb.get_context_lines())
-                            .unwrap_or_default(),
-                        original_prompt: effective_prompt,
-                    };
-                }
-                return ExecutionResult::Failed {
-                    error: format!("Timeout after {} seconds", step.timeout_secs),
-                };
+                return ExecutionResult::Failed { error };
             }
         };
 
@@ -252,73 +454,19 @@ impl<A: AgentExecutor> StepExecutor<A> {
             };
         }
 
-        debug!("Agent response for step {}: {}", step.id, result.content);
-
-        // Check for continuation first (agent wants more work)
-        if step.needs_continuation(&result.content) {
-            if let Some((action, target)) = step.get_continuation_action() {
-                return match action {
-                    crate::core::config::ContinuationAction::Retry => {
-                        info!("Step {} requested continuation (retry)", step.id);
-                        ExecutionResult::Continue {
-                            action: ContinueAction::Retry,
-                            target: None,
-                        }
-                    }
-                    crate::core::config::ContinuationAction::Route => {
-                        let target_id = target.unwrap().clone();
-                        info!("Step {} requested continuation (route to {})", step.id, target_id);
-                        ExecutionResult::Continue {
-                            action: ContinueAction::Route(target_id.clone()),
-                            target: Some(target_id),
-                        }
-                    }
-                };
-            }
-        }
-
-        // Check for successful completion
-        if step.is_success(&result.content) {
-            let next_step = step.next_step_on_success().cloned();
-            info!("Step {} completed successfully", step.id);
-            if let Some(ref next) = next_step {
-                info!("  Next step: {}", next);
-            }
-            return ExecutionResult::Success {
-                output: result.content,
-                next_step,
-            };
+        if step.telemetry.keeps_content() {
+            debug!("Agent response for step {}: {}", step.id, result.content);
         }
 
-        // No expected pattern found - handle as retry (if no on_failure route)
-        warn!(
-            "Step {} did not output expected termination pattern",
-            step.id
-        );
-        let next_step = step.next_step_on_failure().cloned();
-
-        // If there's an on_failure route, route to failure handler
-        if let Some(target) = next_step {
-            info!("Step {} routing to failure handler: {}", step.id, target);
-            ExecutionResult::FailedWithRoute {
-                error: "No termination pattern found".to_string(),
-                next_step: target,
-            }
-        } else {
-            // No failure handler - retry the step instead of failing the pipeline
-            info!("Step {} will retry for termination pattern", step.id);
-            ExecutionResult::Continue {
-                action: ContinueAction::Retry,
-                target: None,
-            }
-        }
+        self.outcome(step, result.content)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::core::step::{Step, ContinuationCondition};
+    use crate::core::step::{Step, ContinuationCondition, DEFAULT_FAILOVER_AFTER, DEFAULT_MAX_FOLLOWUPS, DEFAULT_WAIT_TIMEOUT_SECS};
+    use crate::core::config::{Precedence, TemplateSyntax, Telemetry};
     use crate::core::condition::TerminationCondition as DomainTerminationCondition;
     use crate::core::state::StepState;
     use crate::agent::AgentResponse;
@@ -349,15 +497,47 @@ mod tests {
         let step = Step {
             id: "test".to_string(),
             prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
             dependencies: vec![],
+            any_dependencies: vec![],
             termination: Some(DomainTerminationCondition {
-                success_pattern: crate::core::step::ConditionPattern::Simple("DONE".to_string()),
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
                 on_success: Some("next".to_string()),
                 on_failure: None,
             }),
             continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
             max_retries: 3,
             timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
             state: StepState::Pending,
         };
 
@@ -383,19 +563,51 @@ mod tests {
         let step = Step {
             id: "test".to_string(),
             prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
             dependencies: vec![],
+            any_dependencies: vec![],
             termination: Some(DomainTerminationCondition {
-                success_pattern: crate::core::step::ConditionPattern::Simple("DONE".to_string()),
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
                 on_success: None,
                 on_failure: None,
             }),
             continuation: Some(ContinuationCondition {
-                pattern: crate::core::step::ConditionPattern::Simple("CONTINUE".to_string()),
+                pattern: crate::core::condition::Matcher::Contains("CONTINUE".to_string()),
                 action: crate::core::config::ContinuationAction::Retry,
                 target: None,
             }),
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
             max_retries: 3,
             timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
             state: StepState::Pending,
         };
 
@@ -416,6 +628,290 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_precedence_settles_outputs_matching_both_patterns() {
+        let mut step = Step {
+            id: "test".to_string(),
+            prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
+            dependencies: vec![],
+            any_dependencies: vec![],
+            termination: Some(DomainTerminationCondition {
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
+                on_success: None,
+                on_failure: None,
+            }),
+            continuation: Some(ContinuationCondition {
+                pattern: crate::core::condition::Matcher::Contains("CONTINUE".to_string()),
+                action: crate::core::config::ContinuationAction::Retry,
+                target: None,
+            }),
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
+            max_retries: 3,
+            timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
+            state: StepState::Pending,
+        };
+
+        let agent = MockAgent {
+            response: "DONE for now, but CONTINUE".to_string(),
+        };
+        let executor = StepExecutor::new(agent);
+        let context = PipelineContext::new();
+
+        // The continuation wins by default, as it always has
+        let result = executor.execute(&step, &context, None).await;
+        assert!(
+            matches!(result, ExecutionResult::Continue { action: ContinueAction::Retry, .. }),
+            "{:?}",
+            result
+        );
+        assert!(executor.take_both_matched("test"));
+        assert!(!executor.take_both_matched("test"));
+
+        step.precedence = Precedence::SuccessFirst;
+        let result = executor.execute(&step, &context, None).await;
+        assert!(matches!(result, ExecutionResult::Success { .. }), "{:?}", result);
+        assert!(executor.take_both_matched("test"));
+
+        // An ambiguous output fails the step, or routes it to its failure handler
+        step.precedence = Precedence::Error;
+        let result = executor.execute(&step, &context, None).await;
+        match result {
+            ExecutionResult::Failed { error } => assert!(error.contains("matched both"), "{}", error),
+            _ => panic!("Expected failure, got {:?}", result),
+        }
+        step.termination.as_mut().unwrap().on_failure = Some("triage".to_string());
+        let result = executor.execute(&step, &context, None).await;
+        assert!(
+            matches!(&result, ExecutionResult::FailedWithRoute { next_step, .. } if next_step == "triage"),
+            "{:?}",
+            result
+        );
+        assert!(executor.take_both_matched("test"));
+
+        // Only outputs matching both are recorded
+        let executor = StepExecutor::new(MockAgent {
+            response: "DONE".to_string(),
+        });
+        let result = executor.execute(&step, &context, None).await;
+        assert!(matches!(result, ExecutionResult::Success { .. }), "{:?}", result);
+        assert!(!executor.take_both_matched("test"));
+    }
+
+    #[tokio::test]
+    async fn test_step_fails_over_to_next_agent() {
+        use crate::agent::{ChaosConfig, FaultyAgent};
+
+        let step = Step {
+            id: "test".to_string(),
+            prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
+            dependencies: vec![],
+            any_dependencies: vec![],
+            termination: Some(DomainTerminationCondition {
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
+                on_success: None,
+                on_failure: None,
+            }),
+            continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
+            max_retries: 3,
+            timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec!["primary".to_string(), "fallback".to_string()],
+            failover_after: 2,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
+            state: StepState::Pending,
+        };
+
+        let always_exit = ChaosConfig {
+            exit_rate: 1.0,
+            ..ChaosConfig::default()
+        };
+        let primary = FaultyAgent::new(MockAgent { response: "DONE".to_string() }, always_exit);
+        let fallback = MockAgent { response: "Fallback DONE".to_string() };
+        let executor = StepExecutor::new(MockAgent { response: "unused".to_string() }).with_profiles(HashMap::from([
+            ("primary".to_string(), Arc::new(primary) as Arc<dyn AgentExecutor>),
+            ("fallback".to_string(), Arc::new(fallback) as Arc<dyn AgentExecutor>),
+        ]));
+
+        let result = executor.execute(&step, &PipelineContext::new(), None).await;
+        assert!(matches!(result, ExecutionResult::Success { ref output, .. } if output == "Fallback DONE"));
+
+        let attempts = executor.take_agent_attempts("test");
+        let agents: Vec<_> = attempts.iter().map(|a| a.agent.as_str()).collect();
+        assert_eq!(agents, vec!["primary", "primary", "fallback"]);
+        assert!(attempts[0].error.as_deref().unwrap().starts_with("API error"));
+        assert!(attempts[2].error.is_none());
+        assert!(executor.take_agent_attempts("test").is_empty());
+
+        // A profile that isn't registered fails the step rather than
+        // falling back to the default agent
+        let executor = StepExecutor::new(MockAgent { response: "Default DONE".to_string() }).with_profiles(HashMap::from([(
+            "primary".to_string(),
+            Arc::new(MockAgent { response: "DONE".to_string() }) as Arc<dyn AgentExecutor>,
+        )]));
+        let step = Step {
+            agents: vec!["fallback".to_string(), "primary".to_string()],
+            ..step
+        };
+        let result = executor.execute(&step, &PipelineContext::new(), None).await;
+        assert!(matches!(result, ExecutionResult::Failed { ref error, .. } if error == "Agent profile 'fallback' is not registered"));
+        assert!(executor.take_agent_attempts("test").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_step_runs_on_its_own_agent() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "triage"
+    name: "Triage"
+    prompt: "Sort the issues"
+    agent:
+      model: "fast"
+  - id: "design"
+    name: "Design"
+    prompt: "Design the fix"
+"#;
+        let pipeline = crate::core::config::PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let executor = StepExecutor::new(MockAgent { response: "Default DONE".to_string() }).with_step_agents(
+            HashMap::from([(
+                "triage".to_string(),
+                Arc::new(MockAgent { response: "Fast DONE".to_string() }) as Arc<dyn AgentExecutor>,
+            )]),
+        );
+
+        let context = PipelineContext::new();
+        let triage = executor.execute(pipeline.step("triage").unwrap(), &context, None).await;
+        assert!(matches!(triage, ExecutionResult::Success { ref output, .. } if output == "Fast DONE"));
+        let design = executor.execute(pipeline.step("design").unwrap(), &context, None).await;
+        assert!(matches!(design, ExecutionResult::Success { ref output, .. } if output == "Default DONE"));
+        assert!(executor.take_agent_attempts("triage").is_empty());
+    }
+
+    /// Agent that asks a question until it gets an answer, recording the
+    /// session and prompt of each call
+    struct CuriousAgent {
+        calls: Mutex<Vec<(Option<std::path::PathBuf>, String)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl AgentExecutor for CuriousAgent {
+        async fn execute(&self, prompt: &str) -> Result<AgentResponse, crate::agent::AgentError> {
+            let session = STEP_SESSION.try_with(Clone::clone).ok().flatten();
+            self.calls.lock().unwrap().push((session, prompt.to_string()));
+            Ok(AgentResponse::new(match prompt {
+                "Use Postgres" => "Migrated to Postgres. DONE".to_string(),
+                _ => "QUESTION: which database?".to_string(),
+            }))
+        }
+
+        async fn execute_streaming(
+            &self,
+            prompt: &str,
+            _callback: Option<&dyn crate::agent::ProgressCallback>,
+        ) -> Result<AgentResponse, crate::agent::AgentError> {
+            self.execute(prompt).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_followups_answer_in_the_same_session() {
+        let yaml = r#"
+name: "Test Pipeline"
+variables:
+  database: "Postgres"
+steps:
+  - id: "migrate"
+    name: "Migrate"
+    prompt: "Migrate the schema"
+    followups:
+      - pattern: "QUESTION: which database"
+        reply: "Use {{ database }}"
+  - id: "stubborn"
+    name: "Stubborn"
+    prompt: "Migrate again"
+    followups:
+      - pattern: "QUESTION"
+        reply: "Decide yourself"
+    max_followups: 2
+"#;
+        let config = crate::core::config::PipelineConfig::from_yaml(yaml).unwrap();
+        let pipeline = config.to_pipeline();
+        let mut context = PipelineContext::new();
+        context.variables = config.variables_as_string_map();
+        let executor = StepExecutor::new(CuriousAgent { calls: Mutex::new(Vec::new()) });
+
+        let result = executor.execute(pipeline.step("migrate").unwrap(), &context, None).await;
+        assert!(matches!(result, ExecutionResult::Success { ref output, .. } if output == "Migrated to Postgres. DONE"));
+        let calls = std::mem::take(&mut *executor.agent.calls.lock().unwrap());
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].1.starts_with("Migrate the schema"));
+        assert_eq!(calls[1].1, "Use Postgres");
+        assert!(calls[0].0.is_some());
+        assert_eq!(calls[0].0, calls[1].0);
+
+        // Replies stop at the cap and the step retries as usual
+        let result = executor.execute(pipeline.step("stubborn").unwrap(), &context, None).await;
+        assert!(matches!(result, ExecutionResult::Continue { action: ContinueAction::Retry, .. }));
+        assert_eq!(executor.agent.calls.lock().unwrap().len(), 3);
+    }
+
     // Phase 4: Interruption Result Tests
 
     #[test]
@@ -468,15 +964,47 @@ mod tests {
         let step = Step {
             id: "test".to_string(),
             prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
             dependencies: vec![],
+            any_dependencies: vec![],
             termination: Some(DomainTerminationCondition {
-                success_pattern: crate::core::step::ConditionPattern::Simple("DONE".to_string()),
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
                 on_success: None,
                 on_failure: None,
             }),
             continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
             max_retries: 3,
             timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
             state: StepState::Pending,
         };
 
@@ -505,15 +1033,47 @@ mod tests {
         let step = Step {
             id: "test".to_string(),
             prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
             dependencies: vec![],
+            any_dependencies: vec![],
             termination: Some(DomainTerminationCondition {
-                success_pattern: crate::core::step::ConditionPattern::Simple("DONE".to_string()),
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
                 on_success: None,
                 on_failure: None,
             }),
             continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
             max_retries: 3,
             timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
             state: StepState::Pending,
         };
 
@@ -543,15 +1103,47 @@ mod tests {
         let step = Step {
             id: "test".to_string(),
             prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
             dependencies: vec![],
+            any_dependencies: vec![],
             termination: Some(DomainTerminationCondition {
-                success_pattern: crate::core::step::ConditionPattern::Simple("DONE".to_string()),
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
                 on_success: None,
                 on_failure: None,  // No failure handler
             }),
             continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
             max_retries: 3,
             timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
             state: StepState::Pending,
         };
 
@@ -580,15 +1172,47 @@ mod tests {
         let step = Step {
             id: "test".to_string(),
             prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
             dependencies: vec![],
+            any_dependencies: vec![],
             termination: Some(DomainTerminationCondition {
-                success_pattern: crate::core::step::ConditionPattern::Simple("DONE".to_string()),
+                success_pattern: crate::core::condition::Matcher::Contains("DONE".to_string()),
                 on_success: None,
                 on_failure: Some("handler".to_string()),  // Has failure handler
             }),
             continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
             max_retries: 3,
             timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: true,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
             state: StepState::Pending,
         };
 
@@ -609,5 +1233,100 @@ mod tests {
             _ => panic!("Expected FailedWithRoute, got {:?}", result),
         }
     }
-}
 
+    #[tokio::test]
+    async fn test_text_mode_step_synthesizes_events() {
+        struct ModeAgent;
+
+        #[async_trait::async_trait]
+        impl AgentExecutor for ModeAgent {
+            async fn execute(&self, _prompt: &str) -> Result<AgentResponse, crate::agent::AgentError> {
+                Ok(AgentResponse::new("text mode DONE".to_string()))
+            }
+
+            async fn execute_streaming(
+                &self,
+                _prompt: &str,
+                _callback: Option<&dyn crate::agent::ProgressCallback>,
+            ) -> Result<AgentResponse, crate::agent::AgentError> {
+                Ok(AgentResponse::new("streaming DONE".to_string()))
+            }
+        }
+
+        struct Collector(std::sync::Mutex<Vec<PiJsonEvent>>);
+
+        impl ProgressCallback for Collector {
+            fn on_event(&self, event: &PiJsonEvent) {
+                self.0.lock().unwrap().push(event.clone());
+            }
+        }
+
+        let mut step = Step {
+            id: "test".to_string(),
+            prompt_template: "Do the task".to_string(),
+            template: TemplateSyntax::Placeholders,
+            dependencies: vec![],
+            any_dependencies: vec![],
+            termination: None,
+            continuation: None,
+            followups: vec![],
+            max_followups: DEFAULT_MAX_FOLLOWUPS,
+            max_retries: 3,
+            timeout_secs: 300,
+            priority: 0,
+            max_write_bytes: None,
+            streaming: false,
+            heartbeat_secs: None,
+            remember: vec![],
+            wait_for: vec![],
+            wait_timeout_secs: DEFAULT_WAIT_TIMEOUT_SECS,
+            language_hint: None,
+            agents: vec![],
+            failover_after: DEFAULT_FAILOVER_AFTER,
+            budget_category: None,
+            poll: None,
+            sub_pipeline: None,
+            queue: None,
+            run_once: false,
+            plan_only: false,
+            keep_tmp_dir_on_failure: false,
+            working_dir: None,
+            env: Default::default(),
+            env_passthrough: None,
+            locks: vec![],
+            artifacts: vec![],
+            provenance: false,
+            sanitize_markers: false,
+            inputs: None,
+            when: None,
+            precedence: Precedence::ContinuationFirst,
+            telemetry: Telemetry::Full,
+            state: StepState::Pending,
+        };
+        let executor = StepExecutor::new(ModeAgent);
+        let context = PipelineContext::new();
+        let collector = Collector(std::sync::Mutex::new(Vec::new()));
+
+        match executor.execute(&step, &context, Some(&collector)).await {
+            ExecutionResult::Success { output, .. } => assert_eq!(output, "text mode DONE"),
+            other => panic!("Expected success, got {:?}", other),
+        }
+
+        let events = collector.0.lock().unwrap().clone();
+        assert_eq!(events.first(), Some(&PiJsonEvent::AgentStart));
+        assert_eq!(events.last(), Some(&PiJsonEvent::AgentEnd));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            PiJsonEvent::MessageUpdate {
+                assistant_message_event: Some(AssistantMessageEvent::TextDelta { delta, .. }),
+                ..
+            } if delta == "text mode DONE"
+        )));
+
+        step.streaming = true;
+        match executor.execute(&step, &context, None).await {
+            ExecutionResult::Success { output, .. } => assert_eq!(output, "streaming DONE"),
+            other => panic!("Expected success, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! Delivery of execution events to registered handlers
+//!
+//! Each handler runs on a thread of its own, fed through a bounded queue.
+//! The engine waits for a handler to finish each event, so handlers keep in
+//! step with the run. Once a handler takes longer than the handler timeout,
+//! the engine stops waiting for it and its events queue up, in order, until
+//! it has caught up. When a lagging handler's queue is full, the engine
+//! waits up to the handler timeout for room.
+//!
+//! A handler that handles nothing for a whole handler timeout while its
+//! queue is full, or while the run waits for it at the end, is stuck: it is
+//! detached and gets no more events from the run, so a handler that never
+//! returns cannot hold the run up. Its thread is left to finish (or not) on
+//! its own.
+
+use super::engine::{EventHandler, ExecutionEvent};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Events a lagging handler can fall behind by before the engine waits for it
+const QUEUE_CAPACITY: usize = 1024;
+
+/// How often `flush` checks whether the handlers have caught up
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How a handler misbehaved, with the index of the handler and the kind of
+/// event it was handling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandlerProblem {
+    /// Did not return within the handler timeout
+    Slow { index: usize, event: String },
+    /// Panicked
+    Panicked { index: usize, event: String },
+    /// Handled nothing for a whole handler timeout with events waiting, and
+    /// was detached
+    Stuck { index: usize },
+}
+
+impl HandlerProblem {
+    /// Index of the handler the problem is about
+    pub fn index(&self) -> usize {
+        match self {
+            HandlerProblem::Slow { index, .. }
+            | HandlerProblem::Panicked { index, .. }
+            | HandlerProblem::Stuck { index } => *index,
+        }
+    }
+}
+
+/// An event for a worker, with the channel its outcome (whether the
+/// handler panicked) is sent back on
+struct Job {
+    event: ExecutionEvent,
+    kind: String,
+    done: tokio::sync::oneshot::Sender<bool>,
+}
+
+/// The thread running one handler
+struct Worker {
+    /// Queue of the handler's events; `None` once it has been detached
+    sender: Option<tokio::sync::mpsc::Sender<Job>>,
+    /// Events sent and not handled yet
+    pending: Arc<AtomicUsize>,
+    /// Set when the handler ran past the timeout; cleared by the worker once
+    /// its queue is empty
+    lagging: Arc<AtomicBool>,
+}
+
+impl Worker {
+    fn start(index: usize, handler: EventHandler, late_panics: Arc<std::sync::Mutex<Vec<HandlerProblem>>>) -> Self {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel::<Job>(QUEUE_CAPACITY);
+        let pending = Arc::new(AtomicUsize::new(0));
+        let lagging = Arc::new(AtomicBool::new(false));
+
+        let (worker_pending, worker_lagging) = (pending.clone(), lagging.clone());
+        std::thread::Builder::new()
+            .name(format!("event-handler-{}", index + 1))
+            .spawn(move || {
+                while let Some(job) = receiver.blocking_recv() {
+                    let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| handler(job.event))).is_err();
+                    // Nobody waits for a lagging handler, so its panics are
+                    // reported with a later event
+                    if job.done.send(panicked).is_err() && panicked {
+                        late_panics.lock().unwrap().push(HandlerProblem::Panicked { index, event: job.kind });
+                    }
+                    if worker_pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                        worker_lagging.store(false, Ordering::SeqCst);
+                    }
+                }
+            })
+            .expect("failed to start an event handler thread");
+
+        Self {
+            sender: Some(sender),
+            pending,
+            lagging,
+        }
+    }
+
+    /// Whether the handler has handled nothing for `timeout` while events
+    /// waited, polling until it catches up
+    async fn is_stuck(&self, timeout: Duration) -> bool {
+        let mut progress = (self.pending.load(Ordering::SeqCst), Instant::now());
+        loop {
+            let pending = self.pending.load(Ordering::SeqCst);
+            if pending == 0 {
+                return false;
+            }
+            if pending < progress.0 {
+                progress = (pending, Instant::now());
+            } else if progress.1.elapsed() >= timeout {
+                return true;
+            }
+            tokio::time::sleep(FLUSH_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// The worker threads of an engine's handlers
+pub struct HandlerWorkers {
+    timeout: Duration,
+    workers: tokio::sync::Mutex<Vec<Worker>>,
+    /// Panics of handlers nobody was waiting for, not reported yet
+    late_panics: Arc<std::sync::Mutex<Vec<HandlerProblem>>>,
+}
+
+impl HandlerWorkers {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            workers: tokio::sync::Mutex::new(Vec::new()),
+            late_panics: Arc::new(std::sync::Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Deliver `event` (of the given kind) to every handler but `skip`
+    ///
+    /// Handlers added since the last event get a worker first. Returns the
+    /// problems to report: handlers that panicked or ran past the timeout on
+    /// this event. Panics of lagging handlers are collected separately by
+    /// `late_panics`.
+    pub async fn deliver(
+        &self,
+        handlers: &[EventHandler],
+        event: &ExecutionEvent,
+        kind: &str,
+        skip: Option<usize>,
+    ) -> Vec<HandlerProblem> {
+        let mut workers = self.workers.lock().await;
+        while workers.len() < handlers.len() {
+            let index = workers.len();
+            workers.push(Worker::start(index, handlers[index].clone(), self.late_panics.clone()));
+        }
+
+        let mut problems = Vec::new();
+        for (index, worker) in workers.iter_mut().enumerate().take(handlers.len()) {
+            let Some(sender) = worker.sender.as_ref().filter(|_| skip != Some(index)) else {
+                continue;
+            };
+            let (done, outcome) = tokio::sync::oneshot::channel();
+            let job = Job {
+                event: event.clone(),
+                kind: kind.to_string(),
+                done,
+            };
+            worker.pending.fetch_add(1, Ordering::SeqCst);
+            let sent = tokio::time::timeout(self.timeout, sender.send(job)).await;
+            match sent {
+                Ok(Ok(())) => {}
+                Ok(Err(_)) => {
+                    worker.pending.fetch_sub(1, Ordering::SeqCst);
+                    continue;
+                }
+                // Its queue stayed full for a whole timeout
+                Err(_) => {
+                    worker.pending.fetch_sub(1, Ordering::SeqCst);
+                    worker.sender = None;
+                    problems.push(HandlerProblem::Stuck { index });
+                    continue;
+                }
+            }
+            if worker.lagging.load(Ordering::SeqCst) {
+                continue;
+            }
+            match tokio::time::timeout(self.timeout, outcome).await {
+                Ok(Ok(true)) => problems.push(HandlerProblem::Panicked {
+                    index,
+                    event: kind.to_string(),
+                }),
+                Ok(_) => {}
+                Err(_) => {
+                    worker.lagging.store(true, Ordering::SeqCst);
+                    problems.push(HandlerProblem::Slow {
+                        index,
+                        event: kind.to_string(),
+                    });
+                }
+            }
+        }
+        problems
+    }
+
+    /// Wait until every handler has handled the events delivered to it,
+    /// returning the panics of lagging handlers not reported yet and the
+    /// handlers detached as stuck
+    ///
+    /// A lagging handler is waited for as long as it keeps handling events;
+    /// one that handles nothing for a whole handler timeout is detached.
+    pub async fn flush(&self) -> Vec<HandlerProblem> {
+        let mut workers = self.workers.lock().await;
+        let mut problems = Vec::new();
+        for (index, worker) in workers.iter_mut().enumerate() {
+            if worker.sender.is_some() && worker.is_stuck(self.timeout).await {
+                worker.sender = None;
+                problems.push(HandlerProblem::Stuck { index });
+            }
+        }
+        problems.extend(self.late_panics());
+        problems
+    }
+
+    /// Panics of lagging handlers not reported yet
+    pub fn late_panics(&self) -> Vec<HandlerProblem> {
+        std::mem::take(&mut *self.late_panics.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution::engine::{WarningCode, WarningScope};
+
+    fn warning() -> ExecutionEvent {
+        ExecutionEvent::Warning {
+            scope: WarningScope::Pipeline,
+            code: WarningCode::MissingVariables,
+            message: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stuck_handler_is_detached_once_its_queue_is_full() {
+        let workers = HandlerWorkers::new(Duration::from_millis(20));
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let counter = delivered.clone();
+        let handlers: Vec<EventHandler> = vec![
+            Arc::new(|_| loop {
+                std::thread::park();
+            }),
+            Arc::new(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }),
+        ];
+
+        let events = QUEUE_CAPACITY + 10;
+        let mut problems = Vec::new();
+        let deliver_all = async {
+            for _ in 0..events {
+                problems.extend(workers.deliver(&handlers, &warning(), "Warning", None).await);
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), deliver_all).await.unwrap();
+
+        assert_eq!(
+            problems,
+            vec![
+                HandlerProblem::Slow { index: 0, event: "Warning".to_string() },
+                HandlerProblem::Stuck { index: 0 },
+            ]
+        );
+        // The other handler got every event
+        assert!(workers.flush().await.is_empty());
+        assert_eq!(delivered.load(Ordering::SeqCst), events);
+    }
+}
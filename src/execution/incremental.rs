@@ -0,0 +1,166 @@
+//! Incremental steps (`inputs:`)
+//!
+//! Before a step that declares its inputs runs, its prompt template, the
+//! declared variables, the outputs of the steps it depends on and the files
+//! its globs match are hashed into one digest, which is saved with the
+//! step's result. A later run of the pipeline that arrives at the same digest
+//! reuses the output of the step's last completed run instead of running it.
+
+use crate::core::{Pipeline, Step, StepState};
+use crate::execution::artifacts;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+
+/// What a step's input digest covers, gathered from the pipeline; files are
+/// only read when the digest is computed
+#[derive(Debug, Clone)]
+pub struct Inputs {
+    prompt: String,
+    variables: Vec<(String, Option<String>)>,
+    dependency_outputs: Vec<(String, Option<String>)>,
+    files: Vec<String>,
+    working_dir: PathBuf,
+}
+
+impl Inputs {
+    /// Inputs of `step` with the given rendering variables, or `None` if the
+    /// step declares none
+    pub fn of(
+        step: &Step,
+        pipeline: &Pipeline,
+        variables: &HashMap<String, String>,
+        working_dir: PathBuf,
+    ) -> Option<Self> {
+        let declared = step.inputs.as_ref()?;
+        let names: BTreeSet<&String> = declared.variables.iter().collect();
+        let dependencies: BTreeSet<&String> = step.all_dependencies().collect();
+        Some(Self {
+            prompt: step.prompt_template.clone(),
+            variables: names
+                .into_iter()
+                .map(|name| (name.clone(), variables.get(name).cloned()))
+                .collect(),
+            dependency_outputs: dependencies
+                .into_iter()
+                .map(|id| {
+                    let output = match pipeline.step(id).map(|dep| &dep.state) {
+                        Some(StepState::Completed { output, .. }) => Some(output.clone()),
+                        _ => None,
+                    };
+                    (id.clone(), output)
+                })
+                .collect(),
+            files: declared.files.clone(),
+            working_dir,
+        })
+    }
+
+    /// SHA-256 of the inputs as they are now, reading the matching files
+    pub fn digest(&self) -> std::io::Result<String> {
+        let mut hasher = Sha256::new();
+        add_field(&mut hasher, "prompt", "", Some(self.prompt.as_bytes()));
+        for (name, value) in &self.variables {
+            add_field(&mut hasher, "variable", name, value.as_deref().map(str::as_bytes));
+        }
+        for (id, output) in &self.dependency_outputs {
+            add_field(&mut hasher, "dependency", id, output.as_deref().map(str::as_bytes));
+        }
+
+        let mut files = BTreeSet::new();
+        for pattern in &self.files {
+            files.extend(artifacts::matching_files(pattern, &self.working_dir)?);
+        }
+        for path in files {
+            let mut file_hasher = Sha256::new();
+            std::io::copy(&mut std::fs::File::open(&path)?, &mut file_hasher)?;
+            let name = path.strip_prefix(&self.working_dir).unwrap_or(&path).to_string_lossy();
+            add_field(&mut hasher, "file", &name, Some(&file_hasher.finalize()));
+        }
+
+        Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+/// Feed one named value to the digest; lengths keep one field from running
+/// into the next, and a missing value differs from an empty one
+fn add_field(hasher: &mut Sha256, kind: &str, name: &str, value: Option<&[u8]>) {
+    hasher.update(format!("{} {}:{}\n", kind, name.len(), name));
+    match value {
+        Some(value) => {
+            hasher.update(format!("{}\n", value.len()));
+            hasher.update(value);
+        }
+        None => hasher.update("-\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::config::PipelineConfig;
+
+    #[test]
+    fn test_digest_follows_declared_inputs() {
+        let work = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(work.path().join("src")).unwrap();
+        std::fs::write(work.path().join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(work.path().join("notes.txt"), "not an input").unwrap();
+
+        let config = PipelineConfig::from_yaml(
+            r#"
+name: "Build"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+  - id: "build"
+    name: "Build"
+    prompt: "Build for {{ target }}"
+    depends_on: ["plan"]
+    inputs:
+      files: ["src/**/*.rs"]
+      variables: ["target"]
+"#,
+        )
+        .unwrap();
+        let mut pipeline = config.to_pipeline();
+        pipeline.step_mut("plan").unwrap().state = StepState::Completed {
+            output: "the plan".to_string(),
+            attempts: 1,
+            started_at: chrono::Utc::now(),
+            completed_at: chrono::Utc::now(),
+        };
+        let variables = HashMap::from([
+            ("target".to_string(), "x86_64".to_string()),
+            ("unrelated".to_string(), "1".to_string()),
+        ]);
+        let digest = |pipeline: &Pipeline, variables: &HashMap<String, String>| {
+            let step = pipeline.step("build").unwrap();
+            Inputs::of(step, pipeline, variables, work.path().to_path_buf()).unwrap().digest().unwrap()
+        };
+        let original = digest(&pipeline, &variables);
+        assert_eq!(digest(&pipeline, &variables), original);
+        assert!(Inputs::of(pipeline.step("plan").unwrap(), &pipeline, &variables, work.path().to_path_buf()).is_none());
+
+        // Undeclared variables and files don't count
+        let mut changed = variables.clone();
+        changed.insert("unrelated".to_string(), "2".to_string());
+        std::fs::write(work.path().join("notes.txt"), "still not an input").unwrap();
+        assert_eq!(digest(&pipeline, &changed), original);
+
+        changed.insert("target".to_string(), "aarch64".to_string());
+        assert_ne!(digest(&pipeline, &changed), original);
+
+        std::fs::write(work.path().join("src/main.rs"), "fn main() { run() }").unwrap();
+        let edited = digest(&pipeline, &variables);
+        assert_ne!(edited, original);
+        std::fs::write(work.path().join("src/lib.rs"), "").unwrap();
+        assert_ne!(digest(&pipeline, &variables), edited);
+
+        if let StepState::Completed { output, .. } = &mut pipeline.step_mut("plan").unwrap().state {
+            *output = "another plan".to_string();
+        }
+        assert_ne!(digest(&pipeline, &variables), edited);
+    }
+}
@@ -1,9 +1,16 @@
 //! Pipeline execution engine
 
+pub mod artifacts;
 pub mod engine;
+pub mod event_log;
 pub mod executor;
+mod handlers;
+pub mod incremental;
+pub mod preconditions;
+pub mod provenance;
 pub mod scheduler;
+pub mod step_logs;
 
-pub use engine::{ExecutionEngine, ExecutionEvent};
+pub use engine::{EngineConfig, ExecutionEngine, ExecutionEvent, FailureAction, Interruption, SteeringAction, Steering, StepFailure, StepGate};
 pub use executor::{StepExecutor, ExecutionResult, ContinueAction};
-pub use scheduler::{ExecutionScheduler, SchedulingStrategy};
+pub use scheduler::{ExecutionScheduler, SchedulingStrategy, DEFAULT_MAX_PARALLEL};
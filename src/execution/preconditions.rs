@@ -0,0 +1,311 @@
+//! External preconditions checked before a step starts
+//!
+//! Steps can `wait_for:` files, TCP ports, HTTP endpoints or shell commands.
+//! Each condition is polled until it holds or the step's wait timeout runs
+//! out, so pipelines can synchronize with outside processes without spending
+//! agent tokens on polling.
+
+use crate::core::config::WaitCondition;
+use anyhow::{Context, Result};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+/// Time allowed for a single network or command check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Largest HTTP response body read for a matcher
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+/// Longest HTTP status or header line read
+const MAX_LINE_BYTES: u64 = 8 * 1024;
+
+/// Fail for conditions that can never hold as written, so a step using one
+/// fails at once instead of waiting out its timeout
+pub fn ensure_supported(condition: &WaitCondition) -> Result<()> {
+    match condition {
+        WaitCondition::Http(url) => split_url(url).map(|_| ()),
+        WaitCondition::File(_) | WaitCondition::Port(_) | WaitCondition::Command(_) => Ok(()),
+    }
+}
+
+/// Check a condition once
+pub async fn is_satisfied(condition: &WaitCondition) -> bool {
+    match condition {
+        WaitCondition::File(path) => std::path::Path::new(path).exists(),
+        WaitCondition::Port(address) => {
+            matches!(tokio::time::timeout(CHECK_TIMEOUT, TcpStream::connect(address)).await, Ok(Ok(_)))
+        }
+        WaitCondition::Http(url) => match http_status(url).await {
+            Ok(status) => (200..=299).contains(&status),
+            Err(e) => {
+                tracing::debug!("Check of {} failed: {:#}", url, e);
+                false
+            }
+        },
+        WaitCondition::Command(command) => {
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .status();
+            matches!(tokio::time::timeout(CHECK_TIMEOUT, status).await, Ok(Ok(status)) if status.success())
+        }
+    }
+}
+
+/// Output of a command or HTTP check, for conditions checked with a matcher
+///
+/// Commands give their stdout and exit code, HTTP checks the response body
+/// (and no exit code). Other conditions have no output.
+pub async fn probe(condition: &WaitCondition) -> Option<(String, Option<i32>)> {
+    match condition {
+        WaitCondition::Http(url) => match http_get(url).await {
+            Ok((_, body)) => Some((body, None)),
+            Err(e) => {
+                tracing::debug!("Check of {} failed: {:#}", url, e);
+                None
+            }
+        },
+        WaitCondition::Command(command) => {
+            let output = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .stdin(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .kill_on_drop(true)
+                .output();
+            let output = tokio::time::timeout(CHECK_TIMEOUT, output).await.ok()?.ok()?;
+            Some((String::from_utf8_lossy(&output.stdout).into_owned(), output.status.code()))
+        }
+        WaitCondition::File(_) | WaitCondition::Port(_) => None,
+    }
+}
+
+/// Poll a condition until it holds or `deadline` passes
+///
+/// Returns `true` if the condition was satisfied in time.
+pub async fn wait_until(condition: &WaitCondition, deadline: Instant, interval: Duration) -> bool {
+    loop {
+        if is_satisfied(condition).await {
+            return true;
+        }
+        if Instant::now() + interval > deadline {
+            return false;
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Status code and body of a plain `GET` to an `http://` URL
+///
+/// The whole exchange must finish within `CHECK_TIMEOUT`, and bodies over
+/// `MAX_BODY_BYTES` are refused.
+async fn http_get(url: &str) -> Result<(u16, String)> {
+    let exchange = async {
+        let mut response = send_get(url).await?;
+        let status = read_status(&mut response).await?;
+        while !read_line(&mut response).await?.is_empty() {}
+        let mut body = Vec::new();
+        (&mut response).take(MAX_BODY_BYTES + 1).read_to_end(&mut body).await?;
+        anyhow::ensure!(body.len() as u64 <= MAX_BODY_BYTES, "Response body is over {} bytes", MAX_BODY_BYTES);
+        Ok((status, String::from_utf8_lossy(&body).into_owned()))
+    };
+    tokio::time::timeout(CHECK_TIMEOUT, exchange)
+        .await
+        .with_context(|| format!("No response within {}s", CHECK_TIMEOUT.as_secs()))?
+}
+
+/// Status code of a plain `GET` to an `http://` URL, given within
+/// `CHECK_TIMEOUT`
+async fn http_status(url: &str) -> Result<u16> {
+    let exchange = async { read_status(&mut send_get(url).await?).await };
+    tokio::time::timeout(CHECK_TIMEOUT, exchange)
+        .await
+        .with_context(|| format!("No response within {}s", CHECK_TIMEOUT.as_secs()))?
+}
+
+/// Status code from a response's status line: "HTTP/1.1 200 OK"
+async fn read_status(response: &mut BufReader<TcpStream>) -> Result<u16> {
+    let line = read_line(response).await?;
+    line.split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .with_context(|| format!("Invalid status line '{}'", line))
+}
+
+/// Next line of a response head, without its line ending
+///
+/// A line is read until its CRLF however many reads it arrives in.
+async fn read_line(response: &mut BufReader<TcpStream>) -> Result<String> {
+    let mut line = Vec::new();
+    response.take(MAX_LINE_BYTES).read_until(b'\n', &mut line).await?;
+    anyhow::ensure!(line.ends_with(b"\n"), "Response ended or ran over {} bytes before a line break", MAX_LINE_BYTES);
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+/// Host and path of an `http://` URL
+fn split_url(url: &str) -> Result<(&str, &str)> {
+    if url.starts_with("https://") {
+        anyhow::bail!("{} is an https:// URL, which can't be checked directly; use `command: curl -fsS {}`", url, url);
+    }
+    let rest = url
+        .strip_prefix("http://")
+        .with_context(|| format!("{} is not an http:// URL", url))?;
+    Ok(match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Connect to an `http://` URL and send a `GET` for it
+async fn send_get(url: &str) -> Result<BufReader<TcpStream>> {
+    let (authority, path) = split_url(url)?;
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+
+    let mut stream = TcpStream::connect(&address)
+        .await
+        .with_context(|| format!("Failed to connect to {}", address))?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    stream.write_all(request.as_bytes()).await?;
+    Ok(BufReader::new(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Serve one canned HTTP response per connection
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut buf = [0u8; 512];
+                let _ = socket.read(&mut buf).await;
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_file_condition() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ready");
+        let condition = WaitCondition::File(path.to_string_lossy().to_string());
+
+        assert!(!is_satisfied(&condition).await);
+        std::fs::write(&path, "").unwrap();
+        assert!(is_satisfied(&condition).await);
+    }
+
+    #[tokio::test]
+    async fn test_port_condition() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert!(is_satisfied(&WaitCondition::Port(address.clone())).await);
+
+        drop(listener);
+        assert!(!is_satisfied(&WaitCondition::Port(address)).await);
+    }
+
+    #[tokio::test]
+    async fn test_http_condition() {
+        let healthy = serve("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+        let failing = serve("HTTP/1.1 503 Service Unavailable\r\n\r\n").await;
+
+        assert!(is_satisfied(&WaitCondition::Http(format!("http://{}/health", healthy))).await);
+        assert!(!is_satisfied(&WaitCondition::Http(format!("http://{}/health", failing))).await);
+    }
+
+    #[tokio::test]
+    async fn test_http_status_line_split_across_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 512];
+            let _ = socket.read(&mut buf).await;
+            for part in ["HTTP/1.1 2", "00 OK\r", "\n\r\n"] {
+                socket.write_all(part.as_bytes()).await.unwrap();
+                socket.flush().await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        assert_eq!(http_status(&format!("http://{}/", address)).await.unwrap(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_http_check_gives_up_on_a_silent_server() {
+        // Accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let error = http_status(&format!("http://{}/", address)).await.unwrap_err();
+        assert!(error.to_string().starts_with("No response within"), "{}", error);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn test_http_body_is_capped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 512];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\n\r\n").await;
+            let chunk = vec![b'x'; 64 * 1024];
+            while socket.write_all(&chunk).await.is_ok() {}
+        });
+
+        let error = http_get(&format!("http://{}/", address)).await.unwrap_err();
+        assert!(error.to_string().contains("over 1048576 bytes"), "{}", error);
+    }
+
+    #[test]
+    fn test_https_urls_are_rejected() {
+        let https = WaitCondition::Http("https://example.com/health".to_string());
+        let error = ensure_supported(&https).unwrap_err().to_string();
+        assert!(error.contains("https://") && error.contains("curl -fsS"), "{}", error);
+        assert!(ensure_supported(&WaitCondition::Http("http://localhost:3000/".to_string())).is_ok());
+        assert!(ensure_supported(&WaitCondition::Command("true".to_string())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_command_condition() {
+        assert!(is_satisfied(&WaitCondition::Command("true".to_string())).await);
+        assert!(!is_satisfied(&WaitCondition::Command("exit 3".to_string())).await);
+    }
+
+    #[tokio::test]
+    async fn test_probe_output() {
+        let command = WaitCondition::Command("echo ready; exit 2".to_string());
+        assert_eq!(probe(&command).await, Some(("ready\n".to_string(), Some(2))));
+
+        let status = serve("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{\"state\": \"green\"}").await;
+        let (body, exit_code) = probe(&WaitCondition::Http(format!("http://{}/", status))).await.unwrap();
+        assert_eq!(body, "{\"state\": \"green\"}");
+        assert_eq!(exit_code, None);
+    }
+
+    #[tokio::test]
+    async fn test_wait_until_times_out() {
+        let condition = WaitCondition::Command("false".to_string());
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(!wait_until(&condition, deadline, Duration::from_millis(10)).await);
+    }
+}
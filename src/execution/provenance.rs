@@ -0,0 +1,134 @@
+//! Provenance headers stamped on collected artifacts (`provenance: true`)
+//!
+//! The header names the pipeline, step, execution, time and model that
+//! produced a file, written as comments in the file's own syntax so the file
+//! stays valid. The syntax is picked by extension (or name, for files like
+//! `Dockerfile`); files without a comment syntax, such as JSON or binaries,
+//! are left as they are. A shebang or XML declaration stays on the first line.
+
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use uuid::Uuid;
+
+/// Where a file came from
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    pub pipeline: String,
+    pub step_id: String,
+    pub execution_id: Uuid,
+    pub generated_at: DateTime<Utc>,
+    pub model: Option<String>,
+}
+
+/// How a file type writes comments
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CommentStyle {
+    Line(&'static str),
+    Block(&'static str, &'static str),
+}
+
+fn comment_style(path: &Path) -> Option<CommentStyle> {
+    let name = path.file_name()?.to_str()?;
+    if matches!(name, "Dockerfile" | "Makefile" | "Containerfile" | "Justfile" | ".gitignore") {
+        return Some(CommentStyle::Line("#"));
+    }
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let style = match extension.as_str() {
+        "sh" | "bash" | "zsh" | "py" | "rb" | "pl" | "r" | "yaml" | "yml" | "toml" | "conf" | "cfg" | "env"
+        | "tf" | "nix" | "ps1" | "mk" | "dockerfile" => CommentStyle::Line("#"),
+        "rs" | "js" | "mjs" | "cjs" | "ts" | "jsx" | "tsx" | "go" | "java" | "kt" | "kts" | "scala" | "swift"
+        | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "dart" | "proto" | "zig" => CommentStyle::Line("//"),
+        "sql" | "lua" | "hs" | "elm" => CommentStyle::Line("--"),
+        "ini" | "el" | "clj" | "lisp" => CommentStyle::Line(";"),
+        "tex" | "erl" => CommentStyle::Line("%"),
+        "vim" => CommentStyle::Line("\""),
+        "css" | "scss" | "less" => CommentStyle::Block("/*", "*/"),
+        "html" | "htm" | "xml" | "svg" | "md" | "markdown" | "vue" => CommentStyle::Block("<!--", "-->"),
+        _ => return None,
+    };
+    Some(style)
+}
+
+impl Provenance {
+    /// Header for a file at `path`, or `None` if its type has no comments
+    pub fn header(&self, path: &Path) -> Option<String> {
+        let style = comment_style(path)?;
+        let mut lines = vec![
+            "Generated by pi-peline".to_string(),
+            format!("pipeline: {}", self.pipeline),
+            format!("step: {}", self.step_id),
+            format!("execution: {}", self.execution_id),
+            format!("generated_at: {}", self.generated_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)),
+        ];
+        lines.extend(self.model.as_ref().map(|model| format!("model: {}", model)));
+        // Values must not end the comment early
+        let lines = lines.into_iter().map(|line| line.replace(['\n', '\r'], " "));
+
+        Some(match style {
+            CommentStyle::Line(prefix) => lines.map(|line| format!("{} {}\n", prefix, line)).collect(),
+            CommentStyle::Block(open, close) => {
+                let body: String = lines.map(|line| format!("  {}\n", line.replace(close, ""))).collect();
+                format!("{}\n{}{}\n", open, body, close)
+            }
+        })
+    }
+
+    /// `contents` of a file at `path` with the header added, or `None` if
+    /// the file can't carry one
+    pub fn stamp(&self, path: &Path, contents: &[u8]) -> Option<Vec<u8>> {
+        let header = self.header(path)?;
+        let text = std::str::from_utf8(contents).ok()?;
+        // A shebang or XML declaration must stay first
+        let keep_first = text.starts_with("#!") || text.starts_with("<?xml");
+        let split = match (keep_first, text.find('\n')) {
+            (true, Some(end)) => end + 1,
+            (true, None) => text.len(),
+            (false, _) => 0,
+        };
+        let (first, rest) = text.split_at(split);
+        let separator = if keep_first && !first.ends_with('\n') { "\n" } else { "" };
+        Some(format!("{}{}{}{}", first, separator, header, rest).into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provenance() -> Provenance {
+        Provenance {
+            pipeline: "Release".to_string(),
+            step_id: "package".to_string(),
+            execution_id: Uuid::nil(),
+            generated_at: DateTime::parse_from_rfc3339("2025-03-01T12:00:00Z").unwrap().with_timezone(&Utc),
+            model: Some("claude-sonnet-4-5".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_header_uses_the_file_types_comments() {
+        let stamped = provenance().stamp(Path::new("deploy.sh"), b"#!/bin/sh\necho hi\n").unwrap();
+        assert_eq!(
+            String::from_utf8(stamped).unwrap(),
+            "#!/bin/sh\n\
+             # Generated by pi-peline\n\
+             # pipeline: Release\n\
+             # step: package\n\
+             # execution: 00000000-0000-0000-0000-000000000000\n\
+             # generated_at: 2025-03-01T12:00:00Z\n\
+             # model: claude-sonnet-4-5\n\
+             echo hi\n"
+        );
+
+        let html = provenance().header(Path::new("report.html")).unwrap();
+        assert!(html.starts_with("<!--\n  Generated by pi-peline\n"), "{}", html);
+        assert!(html.ends_with("  model: claude-sonnet-4-5\n-->\n"), "{}", html);
+        assert!(provenance().header(Path::new("src/lib.rs")).unwrap().starts_with("// Generated"));
+        assert!(provenance().header(Path::new("Dockerfile")).unwrap().starts_with("# Generated"));
+
+        // Files that can't hold comments are left alone
+        assert!(provenance().stamp(Path::new("data.json"), b"{}").is_none());
+        assert!(provenance().stamp(Path::new("app"), b"\x7fELF").is_none());
+        assert!(provenance().stamp(Path::new("notes.md"), &[0xff, 0xfe]).is_none());
+    }
+}
@@ -1,7 +1,16 @@
 //! Execution scheduler - determines which steps to run next
+//!
+//! Steps that are ready at the same time start in order of `priority`,
+//! highest first. Ties go to the step that comes first in the pipeline's
+//! execution order (dependencies before dependents, otherwise by step ID),
+//! so the order never depends on timing. Steps a route queued run in the
+//! order they were routed to, ahead of the others.
 
-use crate::core::{Pipeline, StepState};
-use std::collections::{HashSet, VecDeque};
+use crate::core::{locks, Pipeline, Step, StepState};
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::Notify;
 
 /// Strategy for scheduling step execution
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -17,10 +26,15 @@ pub enum SchedulingStrategy {
     LimitedParallel(usize),
 }
 
+/// Steps run at once by the parallel-limited strategy unless a limit is given
+pub const DEFAULT_MAX_PARALLEL: usize = 4;
+
 /// Scheduler for determining which steps to run
 pub struct ExecutionScheduler {
     strategy: SchedulingStrategy,
     explicit_queue: VecDeque<String>,
+    /// Signalled when steps are enqueued
+    readiness: Arc<Notify>,
 }
 
 impl ExecutionScheduler {
@@ -28,14 +42,43 @@ impl ExecutionScheduler {
         Self {
             strategy,
             explicit_queue: VecDeque::new(),
+            readiness: Arc::new(Notify::new()),
         }
     }
 
+    /// How steps are scheduled
+    pub fn strategy(&self) -> SchedulingStrategy {
+        self.strategy
+    }
+
     /// Add a step to the explicit execution queue (removes existing entries first)
     pub fn enqueue(&mut self, step_id: String) {
         // Remove any existing entries for this step to prevent duplicates
         self.explicit_queue.retain(|id| id != &step_id);
         self.explicit_queue.push_back(step_id);
+        self.notify_readiness();
+    }
+
+    /// Wake whoever waits on `readiness()`, as enqueueing a step does
+    ///
+    /// A notification sent while no one waits is kept for the next waiter,
+    /// so one that lands between a scan and the wait is not lost.
+    pub fn notify_readiness(&self) {
+        self.readiness.notify_one();
+    }
+
+    /// Notified when steps are enqueued, so a caller with nothing ready can
+    /// wait for that instead of polling
+    ///
+    /// Steps finishing don't notify it: the engine's run loop waits on its
+    /// running steps directly.
+    pub fn readiness(&self) -> Arc<Notify> {
+        self.readiness.clone()
+    }
+
+    /// Remove a step from the explicit execution queue
+    pub fn dequeue(&mut self, step_id: &str) {
+        self.explicit_queue.retain(|id| id != step_id);
     }
 
     /// Get the next batch of steps to execute
@@ -52,64 +95,79 @@ impl ExecutionScheduler {
         }
     }
 
-    fn collect_ready_from_queue(&self, pipeline: &Pipeline) -> Vec<String> {
-        // Steps that are either completed or failed (dependencies are satisfied either way)
-        let completed_or_failed: HashSet<String> = pipeline
-            .steps
-            .values()
-            .filter(|s| {
-                matches!(s.state, StepState::Completed { .. } | StepState::Failed { .. })
-            })
-            .map(|s| s.id.clone())
+    /// Drop candidates whose path locks overlap a running step or one picked before them
+    fn without_lock_conflicts<'a>(
+        pipeline: &'a Pipeline,
+        candidates: impl IntoIterator<Item = &'a Step>,
+    ) -> impl Iterator<Item = &'a Step> {
+        let mut held: Vec<&[String]> = pipeline
+            .running_steps()
+            .into_iter()
+            .map(|s| s.locks.as_slice())
+            .filter(|locks| !locks.is_empty())
             .collect();
 
-        let mut ready = Vec::new();
-
-        for step_id in &self.explicit_queue {
-            if let Some(step) = pipeline.step(step_id) {
-                // Check if step is ready (Pending or Retrying) and dependencies satisfied
-                let is_ready = matches!(step.state, StepState::Pending | StepState::Retrying { .. });
-                let deps_met = step.dependencies_met(&completed_or_failed);
-                if is_ready && deps_met {
-                    ready.push(step_id.clone());
-                    if self.strategy == SchedulingStrategy::Sequential {
-                        break; // Only one at a time for sequential
-                    }
-                }
+        candidates.into_iter().filter(move |step| {
+            if held.iter().any(|locks| locks::locks_overlap(locks, &step.locks)) {
+                return false;
+            }
+            if !step.locks.is_empty() {
+                held.push(&step.locks);
             }
+            true
+        })
+    }
+
+    fn collect_ready_from_queue(&self, pipeline: &Pipeline) -> Vec<String> {
+        // Steps that are completed, skipped or (unless the failure policy
+        // holds their dependents back) failed
+        let completed_or_failed = pipeline.satisfied_steps();
+        let completed = pipeline.completed_steps();
+
+        let queued = self.explicit_queue.iter().filter_map(|step_id| {
+            let step = pipeline.step(step_id)?;
+            // Check if step is ready (Pending or Retrying) and dependencies satisfied
+            let is_ready = matches!(step.state, StepState::Pending | StepState::Retrying { .. });
+            (is_ready && step.dependencies_met(&completed_or_failed, &completed)).then_some(step)
+        });
+
+        let ready = Self::without_lock_conflicts(pipeline, queued).map(|s| s.id.clone());
+        let running_count = pipeline.running_steps().len();
+        match self.strategy {
+            // Only one at a time for sequential
+            SchedulingStrategy::Sequential => ready.take(1usize.saturating_sub(running_count)).collect(),
+            SchedulingStrategy::Parallel => ready.collect(),
+            SchedulingStrategy::LimitedParallel(max) => ready.take(max.saturating_sub(running_count)).collect(),
         }
+    }
 
+    /// Ready steps in the order they should start (see the module docs)
+    fn ready_by_priority(pipeline: &Pipeline) -> Vec<&Step> {
+        let position: HashMap<&str, usize> = pipeline
+            .execution_order()
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.as_str(), i))
+            .collect();
+        let mut ready = pipeline.ready_steps();
+        ready.sort_by_key(|step| (Reverse(step.priority), position.get(step.id.as_str()).copied()));
         ready
     }
 
     fn next_sequential(&self, pipeline: &Pipeline) -> Vec<String> {
-        // Get the first ready step in execution order
-        for step_id in pipeline.execution_order() {
-            if let Some(step) = pipeline.step(step_id) {
-                // Check if step is ready (Pending or Retrying)
-                let is_ready = matches!(step.state, StepState::Pending | StepState::Retrying { .. });
-                if is_ready {
-                    let completed_or_failed: HashSet<String> = pipeline
-                        .steps
-                        .values()
-                        .filter(|s| {
-                            matches!(s.state, StepState::Completed { .. } | StepState::Failed { .. })
-                        })
-                        .map(|s| s.id.clone())
-                        .collect();
-
-                    if step.dependencies_met(&completed_or_failed) {
-                        return vec![step_id.clone()];
-                    }
-                }
-            }
+        if !pipeline.running_steps().is_empty() {
+            return vec![];
         }
-
-        vec![]
+        Self::ready_by_priority(pipeline)
+            .first()
+            .map(|step| vec![step.id.clone()])
+            .unwrap_or_default()
     }
 
     fn next_parallel(&self, pipeline: &Pipeline) -> Vec<String> {
-        pipeline.ready_steps().iter().map(|s| s.id.clone()).collect()
+        Self::without_lock_conflicts(pipeline, Self::ready_by_priority(pipeline))
+            .map(|s| s.id.clone())
+            .collect()
     }
 
     fn next_limited_parallel(&self, pipeline: &Pipeline, max: usize) -> Vec<String> {
@@ -120,9 +178,7 @@ impl ExecutionScheduler {
             return vec![];
         }
 
-        pipeline
-            .ready_steps()
-            .into_iter()
+        Self::without_lock_conflicts(pipeline, Self::ready_by_priority(pipeline))
             .take(remaining)
             .map(|s| s.id.clone())
             .collect()
@@ -133,6 +189,27 @@ impl ExecutionScheduler {
 mod tests {
     use super::*;
     use crate::core::config::PipelineConfig;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_enqueue_wakes_readiness_waiter() {
+        let mut scheduler = ExecutionScheduler::new(SchedulingStrategy::Sequential);
+        let readiness = scheduler.readiness();
+        let waiter = tokio::spawn(async move { readiness.notified().await });
+        tokio::task::yield_now().await;
+
+        scheduler.enqueue("step1".to_string());
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("waiter is woken by the enqueue")
+            .unwrap();
+
+        // A notification with no one waiting is kept for the next wait
+        scheduler.notify_readiness();
+        tokio::time::timeout(Duration::from_secs(1), scheduler.readiness().notified())
+            .await
+            .expect("earlier notification is not lost");
+    }
 
     #[test]
     fn test_sequential_scheduler() {
@@ -200,4 +277,112 @@ steps:
         assert!(next.contains(&"step1".to_string()));
         assert!(next.contains(&"step2".to_string()));
     }
+
+    #[test]
+    fn test_overlapping_locks_are_not_scheduled_together() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "api"
+    name: "API"
+    prompt: "Test"
+    locks: ["src/api/**"]
+  - id: "refactor"
+    name: "Refactor"
+    prompt: "Test"
+    locks: ["src/**"]
+  - id: "docs"
+    name: "Docs"
+    prompt: "Test"
+    locks: ["docs/**"]
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+        let scheduler = ExecutionScheduler::new(SchedulingStrategy::Parallel);
+
+        // Only one of the src steps joins the batch
+        let next = scheduler.next_steps(&pipeline);
+        assert_eq!(next.len(), 2);
+        assert!(next.contains(&"docs".to_string()));
+
+        // A running step holds its locks until it finishes
+        pipeline.step_mut("refactor").unwrap().state = StepState::Running {
+            started_at: chrono::Utc::now(),
+            attempt: 1,
+        };
+        let next = scheduler.next_steps(&pipeline);
+        assert_eq!(next, vec!["docs"]);
+    }
+
+    #[test]
+    fn test_running_steps_count_against_the_limit() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "a"
+    name: "A"
+    prompt: "Test"
+  - id: "b"
+    name: "B"
+    prompt: "Test"
+  - id: "c"
+    name: "C"
+    prompt: "Test"
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let mut pipeline = config.to_pipeline();
+        pipeline.step_mut("a").unwrap().state = StepState::Running {
+            started_at: chrono::Utc::now(),
+            attempt: 1,
+        };
+
+        let scheduler = ExecutionScheduler::new(SchedulingStrategy::Sequential);
+        assert!(scheduler.next_steps(&pipeline).is_empty());
+        let scheduler = ExecutionScheduler::new(SchedulingStrategy::LimitedParallel(2));
+        assert_eq!(scheduler.next_steps(&pipeline), vec!["b"]);
+
+        // Routed steps are held to the same limits
+        let mut scheduler = ExecutionScheduler::new(SchedulingStrategy::LimitedParallel(2));
+        scheduler.enqueue("c".to_string());
+        scheduler.enqueue("b".to_string());
+        assert_eq!(scheduler.next_steps(&pipeline), vec!["c"]);
+        let mut scheduler = ExecutionScheduler::new(SchedulingStrategy::Sequential);
+        scheduler.enqueue("b".to_string());
+        assert!(scheduler.next_steps(&pipeline).is_empty());
+    }
+
+    #[test]
+    fn test_ready_steps_start_by_priority() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "lint"
+    name: "Lint"
+    prompt: "Test"
+  - id: "docs"
+    name: "Docs"
+    prompt: "Test"
+  - id: "e2e"
+    name: "End-to-end tests"
+    prompt: "Test"
+    priority: 10
+  - id: "cleanup"
+    name: "Cleanup"
+    prompt: "Test"
+    priority: -1
+"#;
+
+        let config = PipelineConfig::from_yaml(yaml).unwrap();
+        let pipeline = config.to_pipeline();
+
+        // Highest priority first; equal priorities in execution order
+        let scheduler = ExecutionScheduler::new(SchedulingStrategy::LimitedParallel(3));
+        assert_eq!(scheduler.next_steps(&pipeline), vec!["e2e", "docs", "lint"]);
+        let scheduler = ExecutionScheduler::new(SchedulingStrategy::Parallel);
+        assert_eq!(scheduler.next_steps(&pipeline), vec!["e2e", "docs", "lint", "cleanup"]);
+        let scheduler = ExecutionScheduler::new(SchedulingStrategy::Sequential);
+        assert_eq!(scheduler.next_steps(&pipeline), vec!["e2e"]);
+    }
 }
@@ -0,0 +1,151 @@
+//! Where step logs go (`logs:`)
+//!
+//! Each run gets a directory named after its execution ID under the logs
+//! directory, holding one `<step id>.log` per step (see
+//! `agent::step_log`). When a run starts, the directories of all but the
+//! most recent runs are removed.
+
+use crate::core::config::{LogsConfig, DEFAULT_LOGS_DIR, DEFAULT_LOG_MAX_BYTES, DEFAULT_LOG_RETENTION};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Location, size limit and retention of step logs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepLogs {
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+    pub retention: usize,
+}
+
+impl StepLogs {
+    /// Settings from a pipeline's `logs:` block, or `None` if it turns
+    /// logging off
+    pub fn from_config(config: &LogsConfig) -> Option<Self> {
+        config.enabled.unwrap_or(true).then(|| Self {
+            dir: PathBuf::from(config.dir.as_deref().unwrap_or(DEFAULT_LOGS_DIR)),
+            max_bytes: config.max_bytes.unwrap_or(DEFAULT_LOG_MAX_BYTES),
+            retention: config.retention.unwrap_or(DEFAULT_LOG_RETENTION),
+        })
+    }
+
+    /// Directory of the logs of run `execution_id`
+    pub fn run_dir(&self, execution_id: Uuid) -> PathBuf {
+        self.dir.join(execution_id.to_string())
+    }
+
+    /// Log file of a step
+    pub fn path(&self, execution_id: Uuid, step_id: &str) -> PathBuf {
+        log_file(&self.run_dir(execution_id), step_id)
+    }
+
+    /// Create the log directory of run `execution_id` and remove those of
+    /// runs beyond the retention count, oldest first
+    pub fn start_run(&self, execution_id: Uuid) -> std::io::Result<()> {
+        let current = self.run_dir(execution_id);
+        std::fs::create_dir_all(&current)?;
+
+        let mut runs: Vec<(std::time::SystemTime, PathBuf)> = run_dirs(&self.dir)?
+            .into_iter()
+            .filter(|dir| *dir != current)
+            .filter_map(|dir| Some((last_written(&dir)?, dir)))
+            .collect();
+        runs.sort_by_key(|(written, _)| std::cmp::Reverse(*written));
+        for (_, dir) in runs.into_iter().skip(self.retention.saturating_sub(1)) {
+            std::fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Log file of a step in a run's log directory; path separators in its
+/// ID are replaced
+pub fn log_file(run_dir: &Path, step_id: &str) -> PathBuf {
+    run_dir.join(format!("{}.log", step_id.replace(['/', '\\'], "_")))
+}
+
+/// Run log directories under `dir`: those named after an execution ID
+fn run_dirs(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_dir()
+                && path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| Uuid::parse_str(name).is_ok())
+        })
+        .collect())
+}
+
+/// When a file in a run's log directory was last written, or the
+/// directory itself if it has none
+fn last_written(dir: &Path) -> Option<std::time::SystemTime> {
+    let files = std::fs::read_dir(dir).ok()?.flatten().filter_map(|entry| entry.metadata().ok()?.modified().ok());
+    files.max().or_else(|| std::fs::metadata(dir).ok()?.modified().ok())
+}
+
+/// Log directory of the run whose ID starts with `prefix`
+pub fn find_run(dir: &Path, prefix: &str) -> Result<PathBuf> {
+    let matches: Vec<PathBuf> = run_dirs(dir)?
+        .into_iter()
+        .filter(|run| {
+            run.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(prefix))
+        })
+        .collect();
+    match matches.as_slice() {
+        [run] => Ok(run.clone()),
+        [] => anyhow::bail!("No run matches '{}' in {}", prefix, dir.display()),
+        _ => anyhow::bail!("'{}' matches {} runs; use a longer prefix", prefix, matches.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_run_keeps_the_most_recent_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = StepLogs::from_config(&LogsConfig {
+            dir: Some(dir.path().to_string_lossy().into_owned()),
+            retention: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        std::fs::create_dir_all(dir.path().join("notes")).unwrap();
+
+        let runs: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for run in &runs {
+            logs.start_run(*run).unwrap();
+            std::fs::write(logs.path(*run, "build"), "output").unwrap();
+            // Modification times need to differ
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+
+        assert!(!logs.run_dir(runs[0]).exists());
+        assert!(logs.run_dir(runs[1]).exists());
+        assert!(logs.run_dir(runs[2]).exists());
+        // Directories that aren't runs are left alone
+        assert!(dir.path().join("notes").exists());
+
+        let prefix = &runs[1].to_string()[..8];
+        assert_eq!(find_run(dir.path(), prefix).unwrap(), logs.run_dir(runs[1]));
+        assert!(find_run(dir.path(), "zz").is_err());
+
+        assert_eq!(logs.path(runs[2], "a/b"), logs.run_dir(runs[2]).join("a_b.log"));
+        assert!(StepLogs::from_config(&LogsConfig {
+            enabled: Some(false),
+            ..Default::default()
+        })
+        .is_none());
+    }
+}
@@ -1,6 +1,17 @@
 //! pi-peline - A CI/CD pipeline tool powered by Pi agent
+//!
+//! # Features
+//!
+//! - `cli` (default): the `cli` module behind the `pipeline` binary
+//! - `sqlite` (default): `persistence::SqliteExecutionStore`
+//! - `ui`: `pipeline ui`, following a run in the browser (implies `cli`)
+//!
+//! With `default-features = false` the crate is the engine alone: `core`,
+//! `agent`, `execution` and in-memory `persistence`. The engine then
+//! leaves the terminal alone; agent output reaches handlers as events.
 
 pub mod agent;
+#[cfg(feature = "cli")]
 pub mod cli;
 pub mod core;
 pub mod execution;
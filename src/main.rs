@@ -1,58 +1,180 @@
-mod agent;
-mod cli;
-mod core;
-mod execution;
-mod persistence;
+use pipeline::{agent, cli, core, execution, persistence};
 
 use cli::output::{INFO, style};
 
 use anyhow::{Context, Result};
 use cli::{Cli, Command};
-use cli::commands::{RunCommand, ValidateCommand, ListCommand, HistoryCommand, SchedulingStrategyArg};
+use cli::commands::{RunCommand, ResumeCommand, ValidateCommand, ListCommand, HistoryCommand, HistoryAction, HistoryOpenCommand, HistoryUndeliveredCommand, HistorySearchCommand, StatsCommand, TailCommand, LogsCommand, ReportCommand, DiffCommand, CancelCommand, UnlockCommand, ConfigCommand, ExtractCommand, SchemaCommand, EstimateCommand, GraphCommand, ConfigAction, SchedulingStrategyArg};
+use cli::user_config::UserConfig;
 use cli::output::*;
-use execution::{ExecutionEngine, SchedulingStrategy, ExecutionEvent};
-use agent::{PiAgentClient, AgentClientConfig};
-use persistence::{SqliteExecutionStore, InMemoryPersistence, PersistenceBackend, create_summary, ExecutionSummary};
+use execution::{EngineConfig, ExecutionEngine, SchedulingStrategy, ExecutionEvent};
+use execution::event_log::EventLog;
+use execution::step_logs::StepLogs;
+use agent::{AgentExecutor, PiAgentClient, AgentClientConfig, ToolApprovalPolicy, FaultyAgent};
+use cli::interactive::{TerminalSteering, TerminalToolApprover};
+use cli::report::ReportFormat;
+use persistence::{SqliteExecutionStore, InMemoryPersistence, PersistenceBackend, PriorityGate, create_summary, StepResult, acquire_pipeline_lock, join_concurrency_group, wait_until_superseded, ExecutionSummary, HistoryQuery, StatsQuery};
+use core::config::{LockScope, Priority};
+use core::RenderError;
+use std::collections::HashMap;
 use std::sync::Arc;
-use tracing::{error, Level};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tracing::error;
+use tracing::level_filters::LevelFilter;
 use tracing_subscriber::FmtSubscriber;
 
+/// How long an attended run waits for the operator to rescue a failed step,
+/// unless `--failure-prompt-secs` says otherwise
+const DEFAULT_FAILURE_PROMPT_SECS: u64 = 30;
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::from_args();
 
-    // Initialize logging
-    let log_level = if cli.verbose { Level::DEBUG } else { Level::INFO };
+    // Initialize logging; a dashboard owns the screen, so it gets none
+    let log_level = match &cli.command {
+        Command::Run(cmd) if wants_dashboard(cmd) => LevelFilter::OFF,
+        _ if cli.verbose => LevelFilter::DEBUG,
+        _ => LevelFilter::INFO,
+    };
     let subscriber = FmtSubscriber::builder()
         .with_max_level(log_level)
         .finish();
     tracing::subscriber::set_global_default(subscriber)
         .context("Failed to set logging subscriber")?;
 
+    // Load user-level defaults; `config` must keep working with a broken file
+    let config_path = UserConfig::path();
+    let user_config = match (&cli.command, UserConfig::load_from(&config_path)) {
+        (Command::Config(_), Err(_)) => UserConfig::default(),
+        (_, loaded) => loaded?,
+    };
+    if let Some(enabled) = user_config.colors_enabled() {
+        console::set_colors_enabled(enabled);
+        console::set_colors_enabled_stderr(enabled);
+    }
+    // --data-dir, then PIPELINE_DATA_DIR, then the user config
+    let data_dir = cli
+        .data_dir
+        .clone()
+        .or_else(persistence::data_dir_from_env)
+        .or_else(|| user_config.data_dir.clone());
+    if let Some(dir) = data_dir {
+        // Run files record paths, which must not depend on where we were started
+        persistence::set_data_dir(std::path::absolute(&dir).unwrap_or(dir));
+    }
+
+    // First run: leave a documented config file to edit
+    if !matches!(cli.command, Command::Config(_)) && console::user_attended() {
+        if let Ok(true) = UserConfig::create_template(&config_path) {
+            println!(
+                "{} Welcome! User defaults (pi path, strategy, colors, model) can be set in {}",
+                INFO,
+                style(config_path.display()).cyan()
+            );
+        }
+    }
+
     // Execute command
     match &cli.command {
-        Command::Run(cmd) => run_pipeline(cmd, cli.clone()).await?,
+        Command::Run(cmd) => run_pipeline(cmd, cli.clone(), &user_config, None).await?,
+        Command::Resume(cmd) => resume_pipeline(cmd, cli.clone(), &user_config).await?,
         Command::Validate(cmd) => validate_pipeline(cmd)?,
+        Command::Extract(cmd) => extract_pipeline(cmd)?,
+        Command::Schema(cmd) => write_schema(cmd)?,
+        Command::Estimate(cmd) => estimate_pipeline(cmd, &user_config).await?,
+        Command::Graph(cmd) => draw_graph(cmd).await?,
         Command::List(cmd) => list_pipelines(cmd).await?,
-        Command::History(cmd) => show_history(cmd).await?,
+        Command::History(cmd) => show_history(cmd, &cli.timestamp_format()).await?,
+        Command::Stats(cmd) => show_stats(cmd, &cli.timestamp_format()).await?,
+        Command::Tail(cmd) => tail_run(cmd).await?,
+        Command::Logs(cmd) => show_logs(cmd).await?,
+        Command::Report(cmd) => generate_report(cmd, &cli.timestamp_format()).await?,
+        Command::Diff(cmd) => diff_runs(cmd).await?,
+        Command::Cancel(cmd) => cancel_run(cmd).await?,
+        Command::Unlock(cmd) => unlock_pipeline(cmd).await?,
+        Command::Config(cmd) => edit_user_config(cmd, &config_path)?,
+        Command::Scheduler(cmd) => run_scheduler(cmd).await?,
+        Command::Queue(cmd) => manage_queue(cmd).await?,
+        #[cfg(feature = "ui")]
+        Command::Ui(cmd) => serve_ui(cmd).await?,
+        #[cfg(feature = "serve")]
+        Command::Serve(cmd) => serve_api(cmd).await?,
     }
 
     Ok(())
 }
 
-async fn run_pipeline(cmd: &RunCommand, cli: Cli) -> Result<()> {
+/// Run a pipeline again from its checkpoint, skipping the steps it completed
+async fn resume_pipeline(cmd: &ResumeCommand, cli: Cli, user_config: &UserConfig) -> Result<()> {
+    let dir = persistence::find_run_dir(&cmd.execution_id)?;
+    let checkpoint = persistence::RunCheckpoint::load(&dir)?;
+
+    let run = RunCommand {
+        file: checkpoint.file.to_string_lossy().into_owned(),
+        variable: checkpoint.variables,
+        set: checkpoint.overrides,
+        seed: checkpoint.seed,
+        strategy: cmd.strategy,
+        show_thinking: cmd.show_thinking,
+        event_log: cmd.event_log.clone(),
+        ..Default::default()
+    };
+    run_pipeline(&run, cli, user_config, Some(&dir)).await
+}
+
+/// Whether the run is followed in the terminal dashboard (`--tui`)
+fn wants_dashboard(cmd: &RunCommand) -> bool {
+    #[cfg(feature = "tui")]
+    return cmd.tui;
+    #[cfg(not(feature = "tui"))]
+    {
+        let _ = cmd;
+        false
+    }
+}
+
+/// Run a pipeline, or continue the run saved in `resume_from`
+async fn run_pipeline(cmd: &RunCommand, cli: Cli, user_config: &UserConfig, resume_from: Option<&std::path::Path>) -> Result<()> {
     // Load pipeline config
-    let config = core::config::PipelineConfig::from_file(&cmd.file)
+    let mut config = core::config::PipelineConfig::from_file_with_overrides(&cmd.file, &cmd.set)
         .context("Failed to load pipeline config")?;
+    config.resolve_command_variables().context("Failed to resolve command variables")?;
 
     println!(
         "{} Loaded pipeline: {}",
         INFO,
         style(&config.name).bold()
     );
+    for warning in &config.warnings {
+        println!("{} {}", WARN, warning);
+    }
+    for (path, value) in &cmd.set {
+        println!(
+            "{} Setting override: {} = {}",
+            INFO,
+            style(path).cyan(),
+            style(value).dim()
+        );
+    }
 
     // Create pipeline
     let mut pipeline = config.to_pipeline();
+    if let Some(execution_id) = cmd.execution_id {
+        pipeline.state.execution_id = execution_id;
+    }
+
+    if cmd.no_streaming {
+        for step in pipeline.steps.values_mut() {
+            step.streaming = false;
+        }
+    }
+
+    if cmd.plan {
+        for step in pipeline.steps.values_mut() {
+            step.plan_only = true;
+        }
+    }
 
     // Apply variable overrides
     for (key, value) in &cmd.variable {
@@ -65,6 +187,25 @@ async fn run_pipeline(cmd: &RunCommand, cli: Cli) -> Result<()> {
         );
     }
 
+    // Pick up a stopped run where it left off
+    if let Some(dir) = resume_from {
+        let completed = persistence::restore_run(&mut pipeline, dir)?;
+        if completed.len() == pipeline.steps.len() {
+            anyhow::bail!("Run {} already completed every step", &pipeline.state.execution_id.to_string()[..8]);
+        }
+    }
+    if let Some(seed) = cmd.seed {
+        pipeline.state.seed = seed;
+    }
+    let seed = pipeline.state.seed;
+    println!("{} Run seed: {}", INFO, style(seed).cyan());
+    // Agents that take a seed get the run's
+    let seed_args: Vec<String> = config
+        .seed_arg
+        .iter()
+        .flat_map(|flag| [flag.clone(), seed.to_string()])
+        .collect();
+
     // Set up persistence
     let store: Arc<dyn PersistenceBackend> = if cmd.no_history {
         Arc::new(InMemoryPersistence::new())
@@ -72,48 +213,449 @@ async fn run_pipeline(cmd: &RunCommand, cli: Cli) -> Result<()> {
         Arc::new(SqliteExecutionStore::with_default_path().await?)
     };
 
+    if !cmd.no_history {
+        mark_orphaned_runs(store.as_ref()).await?;
+    }
+
+    // Make values remembered by earlier runs available as {{ memory.<key> }}
+    pipeline.load_memory(store.load_memory(&pipeline.name).await?);
+
     // Create agent client (mock for now - TODO: implement actual Pi client)
-    let agent_config = AgentClientConfig::default();
-    let agent = PiAgentClient::new(agent_config);
+    let chaos = cmd.chaos.map(|chaos| match cmd.chaos_seed {
+        Some(seed) => chaos.with_seed(seed),
+        None => chaos,
+    });
+    if chaos.is_some() {
+        println!("{} Chaos mode: injecting agent failures", WARN);
+    }
+    let make_agent = |agent_config: AgentClientConfig| -> Box<dyn AgentExecutor> {
+        match chaos {
+            Some(chaos) => Box::new(FaultyAgent::new(PiAgentClient::new(agent_config), chaos)),
+            None => Box::new(PiAgentClient::new(agent_config)),
+        }
+    };
+    let max_event_bytes = cmd.max_event_bytes.unwrap_or(agent::event_reader::DEFAULT_MAX_EVENT_BYTES);
+    let mut default_agent = AgentClientConfig::default()
+        .with_args(user_config.agent_args(seed_args.clone()))
+        .with_strict_events(cmd.strict_events)
+        .with_max_event_bytes(max_event_bytes);
+    default_agent.endpoint = user_config.pi_path.clone();
+    let agent = make_agent(default_agent);
+
+    let make_profile_agent = |profile: &core::config::AgentProfileConfig| -> Arc<dyn AgentExecutor> {
+        let mut agent_config = AgentClientConfig::default()
+            .with_args(user_config.agent_args([profile.pi_args(), seed_args.clone()].concat()))
+            .with_strict_events(cmd.strict_events)
+            .with_max_event_bytes(max_event_bytes);
+        agent_config.endpoint = profile.pi_path.clone().or_else(|| user_config.pi_path.clone());
+        Arc::from(make_agent(agent_config))
+    };
+
+    // Named agent profiles that steps can fail over between
+    let agent_profiles: HashMap<String, Arc<dyn AgentExecutor>> = config
+        .agents
+        .iter()
+        .map(|(name, profile)| (name.clone(), make_profile_agent(profile)))
+        .collect();
 
-    // Convert scheduling strategy
-    let strategy: SchedulingStrategy = match cmd.strategy {
+    // Agents of steps that set their own
+    let step_agents: HashMap<String, Arc<dyn AgentExecutor>> = config
+        .steps
+        .iter()
+        .filter_map(|step| Some((step.id.clone(), make_profile_agent(step.agent.as_ref()?))))
+        .collect();
+
+    // Convert scheduling strategy; --max-parallel, then the pipeline's
+    // max_parallel, limits the parallel ones
+    let max_parallel = cmd.max_parallel.or(config.max_parallel);
+    let strategy: SchedulingStrategy = match cmd.strategy.or(user_config.strategy).unwrap_or(SchedulingStrategyArg::Sequential) {
         SchedulingStrategyArg::Sequential => SchedulingStrategy::Sequential,
         SchedulingStrategyArg::Parallel => SchedulingStrategy::Parallel,
-        SchedulingStrategyArg::ParallelLimited => SchedulingStrategy::LimitedParallel(4),
+        SchedulingStrategyArg::ParallelLimited => {
+            SchedulingStrategy::LimitedParallel(max_parallel.unwrap_or(execution::DEFAULT_MAX_PARALLEL))
+        }
     };
 
-    // Create execution engine with show_thinking flag
-    let engine = ExecutionEngine::new(agent, strategy, cmd.show_thinking);
+    // Create execution engine
+    let dashboard = wants_dashboard(cmd);
+    let mut engine_config = EngineConfig::default()
+        .with_strategy(strategy)
+        .with_show_thinking(cmd.show_thinking)
+        .with_max_output_rate(cmd.max_output_rate)
+        .with_terminal_output(!dashboard)
+        .with_force(cmd.force)
+        .with_prompt_preview(cli.verbose);
+    if let Some(max_parallel) = max_parallel {
+        engine_config = engine_config.with_max_parallel(max_parallel);
+    }
+    let mut engine = ExecutionEngine::with_config(agent, engine_config)
+        .with_agent_profiles(agent_profiles)
+        .with_step_agents(step_agents)
+        .with_step_models(step_models(&config, user_config))
+        .with_persistence(store.clone());
+    if let Some(logs) = StepLogs::from_config(&config.logs) {
+        engine = engine.with_step_logs(logs);
+    }
+
+    // Gate selected tools behind operator confirmation
+    if !cmd.confirm_tools.is_empty() {
+        engine = engine.with_tool_approval(
+            ToolApprovalPolicy::new(cmd.confirm_tools.clone()),
+            Arc::new(TerminalToolApprover),
+        );
+    }
+
+    // In an attended run, Ctrl-C interrupts the running step and asks what to do with it
+    let interrupted = Arc::new(AtomicBool::new(false));
+    if console::user_attended() && !dashboard {
+        engine = engine.with_interrupts(interrupted.clone(), Arc::new(TerminalSteering));
+    }
+
+    // ...and a failed step gets a chance to be rescued before the run moves on
+    let failure_prompt_secs = cmd.failure_prompt_secs.unwrap_or(DEFAULT_FAILURE_PROMPT_SECS);
+    if console::user_attended() && failure_prompt_secs > 0 && !dashboard {
+        engine = engine.with_failure_prompt(std::time::Duration::from_secs(failure_prompt_secs), Arc::new(TerminalSteering));
+    }
+
+    // Keep the run files current while the run is in progress (for `pipeline ui`)
+    let live_files = if cmd.no_history {
+        None
+    } else {
+        let dir = persistence::run_dir(pipeline.state.execution_id);
+        let checkpoint = persistence::RunCheckpoint {
+            file: std::fs::canonicalize(&cmd.file).unwrap_or_else(|_| cmd.file.clone().into()),
+            variables: cmd.variable.clone(),
+            overrides: cmd.set.clone(),
+            seed: Some(seed),
+        };
+        if let Err(e) = checkpoint.save(&dir) {
+            println!("{} Could not save run checkpoint: {}", WARN, e);
+        }
+        let live = match resume_from {
+            Some(_) => persistence::LiveRunFiles::resume(dir, &pipeline),
+            None => persistence::LiveRunFiles::create(dir, &pipeline),
+        };
+        match live {
+            Ok(live) => Some(live),
+            Err(e) => {
+                println!("{} Could not write live run files: {}", WARN, e);
+                None
+            }
+        }
+    };
 
     // Set up event handler for console output
     let stream = cli.stream;
+    let warning_count = Arc::new(AtomicUsize::new(0));
+    let warnings_seen = warning_count.clone();
     engine.add_event_handler(move |event| {
-        println!("{}", format_execution_event(&event));
+        // The terminal callback already shows agent text as it arrives
+        if matches!(event, ExecutionEvent::StepOutputChunk { .. }) {
+            return;
+        }
+        let line = format_execution_event(&event);
+        if !dashboard {
+            println!("{}", line);
+        }
+
+        if let Some(live) = &live_files {
+            if let Err(e) = live.record(&event, &console::strip_ansi_codes(&line)) {
+                tracing::debug!("Could not update live run files: {}", e);
+            }
+        }
+
+        if matches!(event, ExecutionEvent::Warning { .. }) {
+            warnings_seen.fetch_add(1, Ordering::Relaxed);
+        }
 
         // For streaming, show step output as it arrives
-        if stream {
+        if stream && !dashboard {
             if let ExecutionEvent::StepOutput { output, .. } = &event {
                 println!("{}", format_output(output, 5));
             }
         }
     });
 
-    // Execute pipeline
-    println!();
-    let result = engine.execute(&mut pipeline).await;
+    // The dashboard pauses, skips and cancels with its own keys
+    #[cfg(feature = "tui")]
+    let dashboard_feed = dashboard.then(|| {
+        let controls = Arc::new(cli::tui::DashboardControls::default());
+        let (sender, events) = tokio::sync::mpsc::unbounded_channel();
+        engine.add_event_handler(move |event| {
+            let _ = sender.send(event);
+        });
+        (controls, events)
+    });
+    #[cfg(feature = "tui")]
+    if let Some((controls, _)) = &dashboard_feed {
+        engine = engine
+            .with_step_gate(controls.clone())
+            .with_interrupts(controls.interrupt_flag(), controls.clone());
+    }
+
+    // Structured copy of the events for later analysis
+    if let Some(path) = &cmd.event_log {
+        let log = EventLog::open(path, pipeline.state.execution_id)
+            .with_context(|| format!("Failed to open event log {}", path.display()))?;
+        engine.add_event_handler(log.into_handler());
+    }
+
+    // Locks, concurrency groups and priorities must be visible to other
+    // processes, so they always live in the database
+    let run_id = pipeline.state.execution_id;
+    let priority = cmd.priority.map(Priority::from).unwrap_or(config.priority);
+    let shared_store: Arc<dyn PersistenceBackend> = if cmd.no_history
+        && (config.locking != LockScope::None || config.concurrency.is_some() || priority != Priority::Normal)
+    {
+        Arc::new(SqliteExecutionStore::with_default_path().await?)
+    } else {
+        store.clone()
+    };
+
+    // Take the run lock before touching the workspace
+    let lock = match config.locking {
+        LockScope::None => None,
+        LockScope::Pipeline => Some(
+            acquire_pipeline_lock(
+                shared_store.clone(),
+                &pipeline.name,
+                run_id,
+                config.on_lock_conflict,
+                std::time::Duration::from_secs(2),
+                || println!("{} Waiting for another run of {} to finish...", INFO, style(&pipeline.name).bold()),
+            )
+            .await?,
+        ),
+    };
 
-    // Save to history
+    #[cfg(feature = "tui")]
+    let run_over = Arc::new(AtomicBool::new(false));
+    #[cfg(feature = "tui")]
+    let mut dashboard_task = None;
+    // Everything between taking the lock and giving it up runs in here, so
+    // the lock is released however the run ends
+    let result = async {
+        // Enter the concurrency group, waiting for or superseding its active run
+        let concurrency = config
+            .concurrency
+            .as_ref()
+            .map(|concurrency| (concurrency.group_key(&pipeline.variables), concurrency.cancel_in_progress));
+        if let Some((group, cancel_in_progress)) = &concurrency {
+            if !cancel_in_progress && !shared_store.try_join_group(group, run_id).await? {
+                println!("{} Waiting for the active run in group {} to finish...", INFO, style(group).bold());
+            }
+            let superseded = join_concurrency_group(
+                shared_store.as_ref(),
+                group,
+                run_id,
+                *cancel_in_progress,
+                std::time::Duration::from_secs(2),
+            )
+            .await?;
+            if let Some(superseded) = superseded {
+                println!(
+                    "{} Superseding run {} in group {}",
+                    WARN,
+                    style(&superseded.to_string()[..8]).dim(),
+                    style(group).bold()
+                );
+            }
+        }
+
+        // Announce the run's priority for as long as it runs, and make way for
+        // runs of a higher priority between steps
+        if priority != Priority::Normal {
+            println!("{} Priority: {}", INFO, style(priority.as_str()).cyan());
+        }
+        shared_store.announce_priority(run_id, priority).await?;
+        let priority_refresh = {
+            let shared_store = shared_store.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(persistence::HEARTBEAT_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = shared_store.announce_priority(run_id, priority).await {
+                        tracing::debug!("Could not refresh run priority: {}", e);
+                    }
+                }
+            })
+        };
+        if priority != Priority::High {
+            engine = engine.with_step_gate(Arc::new(PriorityGate::new(shared_store.clone(), priority)));
+        }
+
+        // The engine records the run as running; a heartbeat lets a crash leave
+        // it detectably orphaned rather than running forever
+        let heartbeat = if cmd.no_history {
+            None
+        } else {
+            let store = store.clone();
+            Some(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(persistence::HEARTBEAT_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = store.heartbeat(run_id).await {
+                        tracing::debug!("Could not record heartbeat: {}", e);
+                    }
+                }
+            }))
+        };
+
+        // Execute pipeline, stopping early if a newer run supersedes this one
+        println!();
+        let supersede_watch = match &concurrency {
+            Some((group, true)) => {
+                let cancel = engine.cancel_handle();
+                let store = shared_store.clone();
+                let group = group.clone();
+                Some(tokio::spawn(async move {
+                    let reason = match wait_until_superseded(store.as_ref(), &group, run_id, std::time::Duration::from_secs(2)).await {
+                        Ok(newer) => format!("Cancelled: superseded by run {}", &newer.to_string()[..8]),
+                        Err(e) => format!("Cancelled: could not check for newer runs: {}", e),
+                    };
+                    cancel.cancel(reason);
+                }))
+            }
+            _ => None,
+        };
+        // Ctrl-C while a step is already interrupted cancels the run, so its
+        // summary is still saved. An unattended run lets its running steps
+        // finish on the first Ctrl-C and is cancelled on the second.
+        let interrupt_listener = {
+            let cancel = engine.cancel_handle();
+            let attended = console::user_attended();
+            tokio::spawn(async move {
+                while tokio::signal::ctrl_c().await.is_ok() {
+                    if attended {
+                        if interrupted.swap(true, Ordering::SeqCst) {
+                            cancel.cancel("Cancelled by user");
+                        }
+                    } else if cancel.is_stopping() {
+                        cancel.cancel("Cancelled by user");
+                    } else {
+                        println!("\n{} Stopping once the running steps finish; press Ctrl-C again to cancel them", WARN);
+                        cancel.cancel_after_current("Stopped by user after the running steps");
+                    }
+                }
+            })
+        };
+        // `pipeline cancel` leaves its request in the run directory
+        let cancel_watch = (!cmd.no_history).then(|| {
+            let cancel = engine.cancel_handle();
+            let dir = persistence::run_dir(run_id);
+            persistence::CancelRequest::clear(&dir);
+            tokio::spawn(async move {
+                loop {
+                    match persistence::CancelRequest::read(&dir) {
+                        Some(persistence::CancelRequest::Now) => {
+                            cancel.cancel("Cancelled with `pipeline cancel`");
+                            return;
+                        }
+                        Some(persistence::CancelRequest::AfterCurrent) if !cancel.is_stopping() => {
+                            println!("\n{} Stopping once the running steps finish (`pipeline cancel --after-current`)", WARN);
+                            cancel.cancel_after_current("Stopped with `pipeline cancel --after-current`");
+                        }
+                        _ => {}
+                    }
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            })
+        });
+        #[cfg(feature = "tui")]
+        {
+            dashboard_task = dashboard_feed.map(|(controls, events)| {
+                let dashboard = cli::tui::Dashboard::new(&pipeline);
+                let cancel = engine.cancel_handle();
+                let run_over = run_over.clone();
+                tokio::task::spawn_blocking(move || cli::tui::run(dashboard, events, controls, cancel, run_over))
+            });
+        }
+        let result = engine.execute(&mut pipeline).await;
+        interrupt_listener.abort();
+        if let Some(watch) = cancel_watch {
+            watch.abort();
+        }
+        if let Some(watch) = supersede_watch {
+            watch.abort();
+        }
+        if let Some(heartbeat) = heartbeat {
+            heartbeat.abort();
+        }
+        priority_refresh.abort();
+        shared_store.withdraw_priority(run_id).await?;
+
+        if let Some((group, _)) = &concurrency {
+            shared_store.leave_group(group, run_id).await?;
+        }
+        Ok::<_, anyhow::Error>(result)
+    }
+    .await;
+    let released = match lock {
+        Some(lock) => lock.release().await,
+        None => Ok(()),
+    };
+    let result = result?;
+    released?;
+
+    // The dashboard stays up until the operator closes it
+    #[cfg(feature = "tui")]
+    if let Some(task) = dashboard_task {
+        run_over.store(true, Ordering::SeqCst);
+        task.await.context("Dashboard failed")?.context("Dashboard failed")?;
+    }
+
+    // The engine has saved the summary, step results and remembered values
     if !cmd.no_history {
-        let summary = create_summary(&pipeline);
-        store.save_execution(&summary).await?;
+        if let Err(e) = persistence::save_run_files(&pipeline) {
+            println!("{} Could not save run files: {}", WARN, e);
+        }
         println!(
             "\n{} Execution saved to history (ID: {})",
             INFO,
-            style(&summary.execution_id.to_string()[..8]).dim()
+            style(&run_id.to_string()[..8]).dim()
         );
     }
 
+    // Notify webhooks; undelivered notifications are kept in the history
+    // database even with --no-history, so missed alerts can be found
+    if !config.webhooks.is_empty() {
+        let summary = create_summary(&pipeline);
+        let undelivered = cli::webhook::notify(&config.webhooks, &summary).await;
+        if !undelivered.is_empty() {
+            let dead_letters: Arc<dyn PersistenceBackend> = if cmd.no_history {
+                Arc::new(SqliteExecutionStore::with_default_path().await?)
+            } else {
+                store.clone()
+            };
+            for letter in &undelivered {
+                println!(
+                    "{} Webhook {} failed after {} attempt(s): {}",
+                    WARN, letter.url, letter.attempts, letter.error
+                );
+                dead_letters.save_dead_letter(letter).await?;
+            }
+            println!("{} Undelivered notifications are listed by `pipeline history undelivered`", INFO);
+        }
+    }
+
+    // Treat warnings as failures when asked to (for CI)
+    let warnings = warning_count.load(Ordering::Relaxed);
+    let result = match result {
+        Ok(()) if pipeline.state.status == ExecutionStatus::Failed => match pipeline.success_criteria_met() {
+            Some(false) => Err("success criteria not met".to_string()),
+            _ => Err("a step failed".to_string()),
+        },
+        Ok(()) if pipeline.state.status == ExecutionStatus::PartiallyCompleted => {
+            Err(format!("steps failed: {}", pipeline.unhandled_failures().join(", ")))
+        }
+        Ok(()) if cmd.fail_on_warnings && warnings > 0 => {
+            Err(format!("{} warning(s) raised and --fail-on-warnings is set", warnings))
+        }
+        other => other,
+    };
+
     // Print final status
     if result.is_ok() {
         println!(
@@ -129,17 +671,628 @@ async fn run_pipeline(cmd: &RunCommand, cli: Cli) -> Result<()> {
             style(&pipeline.name).bold(),
             style("failed").red()
         );
-        error!("{}", result.unwrap_err());
+    }
+
+    let times = cli.timestamp_format();
+    if let Some(started) = pipeline.state.started_at {
+        let finished = pipeline.state.completed_at.unwrap_or_else(chrono::Utc::now);
+        println!(
+            "  Started {}, finished {}{}",
+            style(times.format(started)).dim(),
+            style(times.format(finished)).dim(),
+            finished
+                .signed_duration_since(started)
+                .to_std()
+                .map(|took| format!(" (took {})", format_duration(took)))
+                .unwrap_or_default()
+        );
+    }
+    let cost_usd: f64 = pipeline.state.cost_usd.values().sum();
+    if cost_usd > 0.0 {
+        println!("  Cost: {}", style(format_cost(cost_usd)).dim());
+    }
+    print_outputs(&pipeline.state.outputs);
+
+    match persistence::save_plan(&pipeline) {
+        Ok(Some(path)) => println!("\n{} Plan saved to {}", INFO, style(path.display()).cyan()),
+        Ok(None) => {}
+        Err(e) => println!("{} Could not save plan: {}", WARN, e),
+    }
+
+    if let Err(e) = result {
+        error!("{}", e);
         std::process::exit(1);
     }
 
     Ok(())
 }
 
+/// Mark runs that stopped sending heartbeats (their process died) as orphaned
+async fn mark_orphaned_runs(store: &dyn PersistenceBackend) -> Result<()> {
+    let stale_before = chrono::Utc::now()
+        - chrono::Duration::from_std(persistence::STALE_AFTER).expect("stale timeout fits");
+    for exec_id in store.mark_orphaned(stale_before).await? {
+        println!(
+            "{} Run {} stopped without finishing; marked as orphaned",
+            WARN,
+            style(&exec_id.to_string()[..8]).dim()
+        );
+    }
+    Ok(())
+}
+
+/// Resolve a full execution ID or a unique prefix of one
+async fn resolve_execution_id(store: &dyn PersistenceBackend, id: &str) -> Result<uuid::Uuid> {
+    if let Ok(exec_id) = uuid::Uuid::parse_str(id) {
+        return Ok(exec_id);
+    }
+
+    let mut matches = Vec::new();
+    for pipeline in store.list_pipelines().await? {
+        for summary in store.list_executions(&pipeline).await? {
+            if summary.execution_id.to_string().starts_with(id) {
+                matches.push(summary.execution_id);
+            }
+        }
+    }
+
+    match matches.as_slice() {
+        [exec_id] => Ok(*exec_id),
+        [] => anyhow::bail!("No execution matches '{}'", id),
+        _ => anyhow::bail!("'{}' matches {} executions; use a longer prefix", id, matches.len()),
+    }
+}
+
+async fn show_undelivered(
+    store: &dyn PersistenceBackend,
+    cmd: &HistoryUndeliveredCommand,
+    times: &TimestampFormat,
+) -> Result<()> {
+    let letters = store.load_dead_letters(cmd.limit).await?;
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&letters)?);
+        return Ok(());
+    }
+    if letters.is_empty() {
+        println!("{} No undelivered notifications", CHECK);
+        return Ok(());
+    }
+
+    for letter in &letters {
+        println!(
+            "{} {}  {}  {} after {} attempt(s): {}",
+            CROSS,
+            style(times.format(letter.failed_at)).dim(),
+            style(&letter.execution_id.to_string()[..8]).dim(),
+            style(&letter.url).cyan(),
+            letter.attempts,
+            style(&letter.error).red()
+        );
+    }
+    Ok(())
+}
+
+/// List the artifacts of a run, copying them into `extract_to` if given
+async fn show_artifacts(
+    store: &dyn PersistenceBackend,
+    summary: &ExecutionSummary,
+    extract_to: Option<&std::path::Path>,
+    json: bool,
+) -> Result<()> {
+    let artifacts = store.load_artifacts(summary.execution_id).await?;
+    if let Some(dest) = extract_to {
+        let source = persistence::artifacts_dir(summary.execution_id);
+        for artifact in &artifacts {
+            let (_, sha256) = execution::artifacts::copy_hashed(&source.join(&artifact.path), &dest.join(&artifact.path))
+                .with_context(|| format!("Failed to extract {}", artifact.path))?;
+            if sha256 != artifact.sha256 {
+                anyhow::bail!("{} changed since it was collected (hash mismatch)", artifact.path);
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&artifacts)?);
+        return Ok(());
+    }
+    if artifacts.is_empty() {
+        println!("{} No artifacts were collected for this run", INFO);
+        return Ok(());
+    }
+    for artifact in &artifacts {
+        println!(
+            "  {}  {}  {}",
+            style(&artifact.path).cyan(),
+            format_bytes(artifact.size),
+            style(&artifact.sha256[..12.min(artifact.sha256.len())]).dim()
+        );
+    }
+    if let Some(dest) = extract_to {
+        println!("{} Extracted {} artifact(s) to {}", CHECK, artifacts.len(), dest.display());
+    }
+    Ok(())
+}
+
+async fn search_history(
+    store: &dyn PersistenceBackend,
+    cmd: &HistorySearchCommand,
+    times: &TimestampFormat,
+) -> Result<()> {
+    let query = HistoryQuery {
+        pipeline: cmd.pipeline.clone(),
+        statuses: cmd.status.clone(),
+        since: cmd.since,
+        contains: cmd.contains.clone(),
+        limit: cmd.limit,
+    };
+    let matches = store.search_executions(&query).await?;
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "executions": matches }))?);
+        return Ok(());
+    }
+    if matches.is_empty() {
+        println!("{} No matching executions", INFO);
+        return Ok(());
+    }
+
+    println!("{} {} matching execution(s), newest first:", INFO, matches.len());
+    for found in &matches {
+        println!("  {}", format_execution_summary(&found.summary, times));
+        if !found.matched_steps.is_empty() {
+            println!("      {} {}", style("matched in").dim(), found.matched_steps.join(", "));
+        }
+    }
+    Ok(())
+}
+
+async fn open_run_files(store: &dyn PersistenceBackend, cmd: &HistoryOpenCommand) -> Result<()> {
+    let exec_id = resolve_execution_id(store, &cmd.execution_id).await?;
+    let dir = persistence::run_dir(exec_id);
+
+    println!("{} Run {}", INFO, style(exec_id).bold());
+    if !dir.exists() {
+        println!("{} No files recorded for this run (expected in {})", WARN, dir.display());
+        return Ok(());
+    }
+
+    println!("  {}", style(dir.display()).cyan());
+    let mut files: Vec<_> = walk_files(&dir)?;
+    files.sort();
+    for file in &files {
+        let relative = file.strip_prefix(&dir).unwrap_or(file);
+        println!("    {}", relative.display());
+    }
+
+    if cmd.open {
+        let opener = if cfg!(target_os = "macos") {
+            "open"
+        } else if cfg!(target_os = "windows") {
+            "explorer"
+        } else {
+            "xdg-open"
+        };
+        std::process::Command::new(opener)
+            .arg(&dir)
+            .spawn()
+            .with_context(|| format!("Failed to launch {}", opener))?;
+    }
+
+    Ok(())
+}
+
+/// All files below a directory
+fn walk_files(dir: &std::path::Path) -> Result<Vec<std::path::PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(walk_files(&path)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+/// Print the last events of a run, then new ones as they are recorded,
+/// until the run is saved to history as finished
+async fn tail_run(cmd: &TailCommand) -> Result<()> {
+    let dir = match &cmd.execution_id {
+        Some(id) => persistence::find_run_dir(id)?,
+        None => persistence::latest_run_dir(&persistence::data_dir().join("runs"))
+            .context("No runs recorded yet")?,
+    };
+    let exec_id = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| uuid::Uuid::parse_str(name).ok())
+        .with_context(|| format!("{} is not a run directory", dir.display()))?;
+    let store = SqliteExecutionStore::with_default_path().await?;
+
+    let mut log = persistence::EventLogReader::new(&dir);
+    let earlier = log.read_new()?;
+    for line in &earlier[earlier.len().saturating_sub(cmd.lines)..] {
+        println!("{}", line);
+    }
+
+    let finished = |summary: Option<ExecutionSummary>| {
+        summary.is_some_and(|summary| summary.status != ExecutionStatus::Running)
+    };
+    if !finished(store.load_execution(exec_id).await?) {
+        println!(
+            "{} Following run {} (Ctrl-C to detach; the run keeps going)",
+            INFO,
+            style(&exec_id.to_string()[..8]).dim()
+        );
+        loop {
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+            for line in log.read_new()? {
+                println!("{}", line);
+            }
+            // The run is saved to history once it has finished
+            mark_orphaned_runs(&store).await?;
+            if finished(store.load_execution(exec_id).await?) {
+                for line in log.read_new()? {
+                    println!("{}", line);
+                }
+                break;
+            }
+        }
+    }
+
+    if let Some(summary) = store.load_execution(exec_id).await? {
+        println!("{} Run {} {}", INFO, style(&exec_id.to_string()[..8]).dim(), format_status(summary.status));
+    }
+    Ok(())
+}
+
+/// List the step logs of a run, or print one, optionally following it
+/// until the run is saved to history as finished
+async fn show_logs(cmd: &LogsCommand) -> Result<()> {
+    let logs_dir = cmd
+        .dir
+        .clone()
+        .unwrap_or_else(|| std::path::PathBuf::from(core::config::DEFAULT_LOGS_DIR));
+    let dir = execution::step_logs::find_run(&logs_dir, &cmd.execution_id)?;
+
+    let Some(step_id) = &cmd.step_id else {
+        let mut files: Vec<_> = std::fs::read_dir(&dir)?
+            .flatten()
+            .filter_map(|entry| Some((entry.file_name().into_string().ok()?, entry.metadata().ok()?.len())))
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            println!("{} No step logs in {}", INFO, dir.display());
+        }
+        for (name, size) in files {
+            println!("{:<40} {}", name, format_bytes(size));
+        }
+        return Ok(());
+    };
+
+    let path = execution::step_logs::log_file(&dir, step_id);
+    if !path.exists() && !cmd.follow {
+        anyhow::bail!("Step '{}' has no log in {}", step_id, dir.display());
+    }
+    let mut log = persistence::EventLogReader::for_file(&path);
+    let earlier = log.read_new()?;
+    let skip = cmd.lines.map_or(0, |lines| earlier.len().saturating_sub(lines));
+    for line in &earlier[skip..] {
+        println!("{}", line);
+    }
+    if !cmd.follow {
+        return Ok(());
+    }
+
+    // Runs made with `--no-history` are followed until Ctrl-C
+    let exec_id = dir.file_name().and_then(|name| name.to_str()).and_then(|name| uuid::Uuid::parse_str(name).ok());
+    let store = SqliteExecutionStore::with_default_path().await?;
+    let finished = |summary: Option<ExecutionSummary>| {
+        summary.is_some_and(|summary| summary.status != ExecutionStatus::Running)
+    };
+    loop {
+        for line in log.read_new()? {
+            println!("{}", line);
+        }
+        match exec_id {
+            Some(exec_id) if finished(store.load_execution(exec_id).await?) => break,
+            _ => tokio::time::sleep(std::time::Duration::from_millis(500)).await,
+        }
+    }
+    Ok(())
+}
+
+async fn generate_report(cmd: &ReportCommand, times: &TimestampFormat) -> Result<()> {
+    let store = SqliteExecutionStore::with_default_path().await?;
+    let exec_id = resolve_execution_id(&store, &cmd.execution_id).await?;
+    let summary = store
+        .load_execution(exec_id)
+        .await?
+        .with_context(|| format!("Execution {} not found in history", exec_id))?;
+    let (steps, outputs) = persistence::load_run_files(exec_id)?;
+
+    let report = match cmd.format {
+        ReportFormat::Md => cli::report::markdown_report(&summary, &steps, &outputs, times),
+    };
+
+    match &cmd.output {
+        Some(path) => {
+            std::fs::write(path, report).with_context(|| format!("Failed to write {}", path))?;
+            println!("{} Report written to {}", CHECK, style(path).cyan());
+        }
+        None => print!("{}", report),
+    }
+
+    Ok(())
+}
+
+/// Compare two runs from history
+async fn diff_runs(cmd: &DiffCommand) -> Result<()> {
+    let store = SqliteExecutionStore::with_default_path().await?;
+    let mut runs = Vec::new();
+    for id in [&cmd.execution_a, &cmd.execution_b] {
+        let exec_id = resolve_execution_id(&store, id).await?;
+        let summary = store
+            .load_execution(exec_id)
+            .await?
+            .with_context(|| format!("Execution {} not found in history", exec_id))?;
+        let mut steps = store.load_step_results(exec_id).await?;
+        // Outputs left out of history are still in the run files
+        if let Ok((_, mut outputs)) = persistence::load_run_files(exec_id) {
+            for step in steps.iter_mut().filter(|s| s.output.is_none()) {
+                step.output = outputs.remove(&step.step_id);
+            }
+        }
+        runs.push((summary, steps));
+    }
+
+    let (a, b) = (&runs[0], &runs[1]);
+    if a.0.pipeline_name != b.0.pipeline_name {
+        println!(
+            "{} Comparing runs of different pipelines ('{}' and '{}')",
+            WARN, a.0.pipeline_name, b.0.pipeline_name
+        );
+    }
+    print!("{}", cli::diff::render_diff((&a.0, &a.1), (&b.0, &b.1), cmd.context));
+    Ok(())
+}
+
+/// Ask a run in another process to cancel, through its run directory
+async fn cancel_run(cmd: &CancelCommand) -> Result<()> {
+    let dir = persistence::find_run_dir(&cmd.execution_id)?;
+    let exec_id = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| uuid::Uuid::parse_str(name).ok())
+        .with_context(|| format!("{} is not a run directory", dir.display()))?;
+
+    let store = SqliteExecutionStore::with_default_path().await?;
+    if let Some(summary) = store.load_execution(exec_id).await? {
+        anyhow::ensure!(
+            !summary.status.is_finished(),
+            "Run {} has already finished ({:?})",
+            &exec_id.to_string()[..8],
+            summary.status
+        );
+    }
+
+    let request = if cmd.after_current {
+        persistence::CancelRequest::AfterCurrent
+    } else {
+        persistence::CancelRequest::Now
+    };
+    request.send(&dir)?;
+    match request {
+        persistence::CancelRequest::AfterCurrent => println!(
+            "{} Run {} will start no more steps and stop once its running steps finish",
+            CHECK,
+            style(&exec_id.to_string()[..8]).dim()
+        ),
+        persistence::CancelRequest::Now => {
+            println!("{} Run {} will be cancelled", CHECK, style(&exec_id.to_string()[..8]).dim())
+        }
+    }
+    Ok(())
+}
+
+async fn run_scheduler(cmd: &cli::commands::SchedulerCommand) -> Result<()> {
+    use cli::commands::SchedulerAction;
+    use cli::scheduler::{load_schedules, Scheduler};
+
+    let store = Arc::new(SqliteExecutionStore::with_default_path().await?);
+    match &cmd.action {
+        SchedulerAction::Run { file } => {
+            let schedules = load_schedules(file)?;
+            anyhow::ensure!(!schedules.is_empty(), "No schedules in {}", file.display());
+            println!("{} Scheduling {} pipeline(s) from {}", INFO, schedules.len(), file.display());
+            let scheduler = Scheduler::new(
+                schedules,
+                persistence::data_dir(),
+                std::env::current_exe().context("Could not find the pipeline binary")?,
+                store,
+            );
+            scheduler.run().await
+        }
+        SchedulerAction::List { file } => {
+            let now = chrono::Local::now();
+            for schedule in load_schedules(file)? {
+                let config = &schedule.config;
+                let next = match schedule.next_after(&now) {
+                    Some(next) => next.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    None => "never".to_string(),
+                };
+                println!("{} {} ({}): next {}", INFO, style(&config.name).bold(), config.cron, style(next).cyan());
+                if let Some(last) = store.load_scheduled_runs(&config.name, 1).await?.pop() {
+                    let status = match store.load_execution(last.execution_id).await? {
+                        Some(summary) => format_status(summary.status),
+                        None => "not recorded".to_string(),
+                    };
+                    println!(
+                        "    last run {} at {}: {}",
+                        last.execution_id,
+                        last.started_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+                        status
+                    );
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn manage_queue(cmd: &cli::commands::QueueCommand) -> Result<()> {
+    use cli::commands::QueueAction;
+
+    let store = SqliteExecutionStore::with_default_path().await?;
+    match &cmd.action {
+        QueueAction::Push { queue, items } => {
+            let items: Vec<String> = if items.is_empty() {
+                std::io::stdin()
+                    .lines()
+                    .collect::<std::io::Result<Vec<_>>>()
+                    .context("Failed to read items from stdin")?
+                    .into_iter()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect()
+            } else {
+                items.clone()
+            };
+            store.push_queue_items(queue, &items, None).await?;
+            println!("{} Pushed {} item(s) to {}", CHECK, items.len(), style(queue).cyan());
+        }
+        QueueAction::Pop { queue, batch } => {
+            anyhow::ensure!(*batch > 0, "--batch must be at least 1");
+            for item in store.pop_queue_items(queue, *batch).await? {
+                println!("{}", item.payload);
+            }
+        }
+        QueueAction::List => {
+            let queues = store.list_queues().await?;
+            if queues.is_empty() {
+                println!("{} No items queued", INFO);
+            }
+            for queue in queues {
+                println!(
+                    "{} {}: {} item(s), oldest pushed {}",
+                    INFO,
+                    style(&queue.queue).bold(),
+                    queue.items,
+                    queue.oldest_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ui")]
+async fn serve_ui(cmd: &cli::commands::UiCommand) -> Result<()> {
+    use cli::ui::RunSelection;
+
+    let selection = match &cmd.execution_id {
+        Some(id) => {
+            let store = SqliteExecutionStore::with_default_path().await?;
+            RunSelection::Run(persistence::run_dir(resolve_execution_id(&store, id).await?))
+        }
+        None => RunSelection::Latest(persistence::data_dir().join("runs")),
+    };
+
+    let addr = format!("127.0.0.1:{}", cmd.port);
+    println!("{} Serving the pipeline UI at {}", INFO, style(format!("http://{}", addr)).cyan());
+    cli::ui::serve(&addr, selection).await
+}
+
+#[cfg(feature = "serve")]
+async fn serve_api(cmd: &cli::commands::ServeCommand) -> Result<()> {
+    let token = match &cmd.token_env {
+        Some(var) => Some(std::env::var(var).with_context(|| format!("{} is not set", var))?),
+        None => None,
+    };
+    let data_dir = persistence::data_dir();
+    let server = cli::serve::Server::new(
+        data_dir.join("serve"),
+        data_dir,
+        std::env::current_exe().context("Could not find the pipeline binary")?,
+        Arc::new(SqliteExecutionStore::with_default_path().await?),
+        token,
+    );
+    let server = match &cmd.triggers {
+        Some(path) => {
+            let triggers = cli::serve::load_triggers(path)?;
+            for trigger in &triggers {
+                println!("{} Trigger {} at {}", INFO, trigger.name(), style(format!("/hooks/{}", trigger.name())).cyan());
+            }
+            server.with_triggers(triggers)
+        }
+        None => server,
+    };
+
+    let addr = format!("{}:{}", cmd.host, cmd.port);
+    println!("{} Serving the pipeline API at {}", INFO, style(format!("http://{}/api", addr)).cyan());
+    cli::serve::serve(&addr, server).await
+}
+
+fn edit_user_config(cmd: &ConfigCommand, path: &std::path::Path) -> Result<()> {
+    match &cmd.action {
+        ConfigAction::Path => println!("{}", path.display()),
+        ConfigAction::Get { key: Some(key) } => match UserConfig::load_from(path)?.get(key)? {
+            Some(value) => println!("{}", value),
+            None => println!("{} {} is not set", INFO, style(key).bold()),
+        },
+        ConfigAction::Get { key: None } => {
+            let config = UserConfig::load_from(path)?;
+            for key in cli::user_config::KEYS {
+                if let Some(value) = config.get(key)? {
+                    println!("{} = {}", style(key).cyan(), value);
+                }
+            }
+        }
+        ConfigAction::Set { key, value } => {
+            let mut config = UserConfig::load_from(path)?;
+            config.set(key, value)?;
+            config.save_to(path)?;
+            println!("{} {} = {}", CHECK, style(key).cyan(), value);
+        }
+        ConfigAction::Unset { key } => {
+            let mut config = UserConfig::load_from(path)?;
+            config.unset(key)?;
+            config.save_to(path)?;
+            println!("{} {} unset", CHECK, style(key).cyan());
+        }
+    }
+    Ok(())
+}
+
+async fn unlock_pipeline(cmd: &UnlockCommand) -> Result<()> {
+    let store = SqliteExecutionStore::with_default_path().await?;
+
+    if store.clear_lock(&cmd.pipeline).await? {
+        println!("{} Released lock for {}", CHECK, style(&cmd.pipeline).bold());
+    } else {
+        println!("{} {} is not locked", INFO, style(&cmd.pipeline).bold());
+    }
+
+    Ok(())
+}
+
 fn validate_pipeline(cmd: &ValidateCommand) -> Result<()> {
     println!("{} Validating pipeline...", INFO);
 
-    let result = core::config::PipelineConfig::from_file(&cmd.file);
+    let result = core::config::PipelineConfig::from_file(&cmd.file).and_then(|config| {
+        if cmd.strict {
+            let document: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(&cmd.file)?)?;
+            let unknown = core::schema::unknown_fields(&document);
+            if !unknown.is_empty() {
+                anyhow::bail!("Unknown fields: {}", unknown.join(", "));
+            }
+            let undefined = undefined_variable_warnings(&config);
+            if !undefined.is_empty() {
+                anyhow::bail!("{}", undefined.join("\n  "));
+            }
+        }
+        Ok(config)
+    });
 
     match result {
         Ok(config) => {
@@ -148,8 +1301,15 @@ fn validate_pipeline(cmd: &ValidateCommand) -> Result<()> {
             println!("  Steps: {}", style(config.steps.len()).cyan());
             println!("  Variables: {}", style(config.variables_as_string_map().len()).cyan());
 
+            for warning in &config.warnings {
+                println!("{} {}", WARN, warning);
+            }
+            for warning in undefined_variable_warnings(&config) {
+                println!("{} {}", WARN, warning);
+            }
+
             if cmd.json {
-                let json = serde_json::to_string_pretty(&config)?;
+                let json = serde_json::to_string_pretty(&config.to_json_with_effective_settings()?)?;
                 println!("\n{}", json);
             }
             Ok(())
@@ -162,6 +1322,174 @@ fn validate_pipeline(cmd: &ValidateCommand) -> Result<()> {
     }
 }
 
+/// Estimate a run of a pipeline from the step records of its recent runs
+/// The model each step runs on, where its pi arguments name one
+fn step_models(config: &core::config::PipelineConfig, user_config: &UserConfig) -> HashMap<String, String> {
+    config
+        .steps
+        .iter()
+        .filter_map(|step| {
+            let profile = step.agent.as_ref().or_else(|| config.agents.get(step.agents.first()?));
+            let args = user_config.agent_args(profile.map(|p| p.pi_args()).unwrap_or_default());
+            Some((step.id.clone(), cli::estimate::model_from_args(&args)?))
+        })
+        .collect()
+}
+
+async fn estimate_pipeline(cmd: &EstimateCommand, user_config: &UserConfig) -> Result<()> {
+    let config = core::config::PipelineConfig::from_file_with_overrides(&cmd.file, &cmd.set)
+        .context("Failed to load pipeline config")?;
+    let mut pipeline = config.to_pipeline();
+    pipeline.variables.extend(cmd.variable.iter().cloned());
+
+    let models = step_models(&config, user_config);
+
+    // Runs made with --no-history or before run files were kept have no records
+    let store = SqliteExecutionStore::with_default_path().await?;
+    let runs: Vec<_> = store
+        .list_executions(&pipeline.name)
+        .await?
+        .iter()
+        .filter_map(|summary| persistence::load_run_files(summary.execution_id).ok())
+        .map(|(steps, _)| steps)
+        .take(cmd.runs)
+        .collect();
+    let estimate = cli::estimate::estimate(&pipeline, &models, &cli::estimate::step_history(&runs));
+
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&estimate)?);
+        return Ok(());
+    }
+    println!(
+        "{} Estimate for {} from {} recent run(s)\n",
+        INFO,
+        style(&estimate.pipeline_name).bold(),
+        runs.len()
+    );
+    print!("{}", cli::estimate::format_estimate(&estimate));
+    if estimate.without_history.len() == estimate.steps.len() {
+        println!("\n{} No step has history yet; run the pipeline once to get an estimate", WARN);
+    } else if !estimate.without_history.is_empty() {
+        println!(
+            "\n{} No history for {}; their figures (marked ~) are extrapolated from other steps",
+            WARN,
+            estimate.without_history.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn write_schema(cmd: &SchemaCommand) -> Result<()> {
+    let schema = serde_json::to_string_pretty(&core::schema::pipeline_schema())?;
+    match &cmd.output {
+        Some(path) => {
+            std::fs::write(path, format!("{}\n", schema)).with_context(|| format!("Failed to write {}", path))?;
+            eprintln!("{} Wrote {}", CHECK, path);
+        }
+        None => println!("{}", schema),
+    }
+    Ok(())
+}
+
+/// Draw a pipeline's graph, with the step statuses of a run when one is given
+async fn draw_graph(cmd: &GraphCommand) -> Result<()> {
+    let config = core::config::PipelineConfig::from_file(&cmd.file).context("Failed to load pipeline config")?;
+
+    let mut statuses = HashMap::new();
+    if let Some(id) = &cmd.execution {
+        let store = SqliteExecutionStore::with_default_path().await?;
+        let exec_id = resolve_execution_id(&store, id).await?;
+        let summary = store
+            .load_execution(exec_id)
+            .await?
+            .with_context(|| format!("Execution {} not found in history", exec_id))?;
+        if summary.pipeline_name != config.name {
+            eprintln!(
+                "{} Execution {} is a run of '{}', not '{}'",
+                WARN, exec_id, summary.pipeline_name, config.name
+            );
+        }
+        statuses = store
+            .load_step_results(exec_id)
+            .await?
+            .into_iter()
+            .map(|result| (result.step_id, result.status))
+            .collect();
+    }
+
+    let graph = cli::graph::render(&config, cmd.format, &statuses);
+    match &cmd.output {
+        Some(path) => {
+            std::fs::write(path, console::strip_ansi_codes(&graph).as_ref())
+                .with_context(|| format!("Failed to write {}", path))?;
+            println!("{} Graph written to {}", CHECK, style(path).cyan());
+        }
+        None => print!("{}", graph),
+    }
+
+    Ok(())
+}
+
+fn extract_pipeline(cmd: &ExtractCommand) -> Result<()> {
+    let yaml = std::fs::read_to_string(&cmd.file)
+        .with_context(|| format!("Failed to read {}", cmd.file))?;
+    let extracted = core::config::PipelineConfig::extract(&yaml, &cmd.steps)?;
+
+    match &cmd.output {
+        Some(path) => {
+            std::fs::write(path, extracted).with_context(|| format!("Failed to write {}", path))?;
+            eprintln!("{} Wrote {}", CHECK, path);
+        }
+        None => print!("{}", extracted),
+    }
+    Ok(())
+}
+
+/// Render every step prompt and report placeholders with no variable behind them
+///
+/// Step outputs and notes only exist at run time, so outputs of any step are
+/// treated as defined and `notes` is ignored.
+fn undefined_variable_warnings(config: &core::config::PipelineConfig) -> Vec<String> {
+    let pipeline = config.to_pipeline();
+    let mut warnings = Vec::new();
+
+    for step_config in &config.steps {
+        let Some(step) = pipeline.steps.get(&step_config.id) else {
+            continue;
+        };
+        let mut context = pipeline.create_context_for_step(&step.id);
+        for other in &config.steps {
+            context.set_step_output(&other.id, String::new());
+            // Pipeline steps expose the outputs their pipeline declares
+            let child = other.pipeline.as_ref().and_then(|pipeline| pipeline.config.as_ref());
+            for name in child.iter().flat_map(|child| child.outputs.keys()) {
+                context.variables.insert(format!("steps.{}.outputs.{}", other.id, name), String::new());
+            }
+        }
+        // The scratch directory is created when the step runs
+        context
+            .variables
+            .insert(execution::engine::STEP_TMP_DIR_VARIABLE.to_string(), String::new());
+
+        if let Err(RenderError::UndefinedVariables { step_id, missing, .. }) = step.render_prompt(&context) {
+            // Notes and remembered values only exist at run time
+            let missing: Vec<_> = missing
+                .into_iter()
+                .filter(|name| name != "notes" && !name.starts_with(core::pipeline::MEMORY_VARIABLE_PREFIX))
+                .collect();
+            if !missing.is_empty() {
+                warnings.push(format!(
+                    "Step '{}' references undefined variables: {}",
+                    step_id,
+                    missing.join(", ")
+                ));
+            }
+        }
+    }
+
+    warnings
+}
+
 async fn list_pipelines(cmd: &ListCommand) -> Result<()> {
     let store = SqliteExecutionStore::with_default_path().await?;
     let pipelines = store.list_pipelines().await?;
@@ -207,8 +1535,67 @@ async fn list_pipelines(cmd: &ListCommand) -> Result<()> {
     Ok(())
 }
 
-async fn show_history(cmd: &HistoryCommand) -> Result<()> {
+async fn show_stats(cmd: &StatsCommand, times: &TimestampFormat) -> Result<()> {
     let store = SqliteExecutionStore::with_default_path().await?;
+    mark_orphaned_runs(&store).await?;
+
+    let query = StatsQuery {
+        pipeline: cmd.pipeline.clone(),
+        since: cmd.since,
+    };
+    let stats = store.pipeline_stats(&query).await?;
+    if cmd.json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "pipelines": stats }))?);
+        return Ok(());
+    }
+    if stats.is_empty() {
+        println!("{} No finished runs found", INFO);
+        return Ok(());
+    }
+
+    match cmd.since {
+        Some(since) => println!("{} Runs started since {}:\n", INFO, times.format(since)),
+        None => println!("{} All finished runs:\n", INFO),
+    }
+    print!("{}", format_stats(&stats));
+    Ok(())
+}
+
+async fn show_history(cmd: &HistoryCommand, times: &TimestampFormat) -> Result<()> {
+    let store = SqliteExecutionStore::with_default_path().await?;
+    mark_orphaned_runs(&store).await?;
+
+    match &cmd.action {
+        Some(HistoryAction::Open(open)) => return open_run_files(&store, open).await,
+        Some(HistoryAction::Undelivered(undelivered)) => {
+            return show_undelivered(&store, undelivered, times).await
+        }
+        Some(HistoryAction::Search(search)) => return search_history(&store, search, times).await,
+        None => {}
+    }
+
+    if let Some(id) = &cmd.id {
+        let exec_id = resolve_execution_id(&store, id).await?;
+        let summary = store
+            .load_execution(exec_id)
+            .await?
+            .with_context(|| format!("Execution {} not found in history", exec_id))?;
+        if cmd.prompts {
+            return show_prompt_hashes(&store, &summary, cmd.json).await;
+        }
+        if cmd.artifacts || cmd.extract.is_some() {
+            return show_artifacts(&store, &summary, cmd.extract.as_deref(), cmd.json).await;
+        }
+        let verbose = cmd.verbose || !cmd.steps.is_empty();
+        let mut steps = load_step_results_if(&store, &summary, verbose).await?;
+        if !cmd.steps.is_empty() {
+            if let Some(unknown) = cmd.steps.iter().find(|id| !steps.iter().any(|step| &step.step_id == *id)) {
+                anyhow::bail!("Execution {} has no result for step '{}'", exec_id, unknown);
+            }
+            steps.retain(|step| cmd.steps.contains(&step.step_id));
+        }
+        return print_execution_details(&summary, &steps, verbose, cmd.max_output_lines, times);
+    }
 
     // If specific execution ID is requested
     if let Some(exec_id_str) = &cmd.execution_id {
@@ -218,7 +1605,8 @@ async fn show_history(cmd: &HistoryCommand) -> Result<()> {
 
         match summary {
             Some(summary) => {
-                print_execution_details(&summary, cmd.verbose)?;
+                let steps = load_step_results_if(&store, &summary, cmd.verbose).await?;
+                print_execution_details(&summary, &steps, cmd.verbose, cmd.max_output_lines, times)?;
             }
             None => {
                 println!("{} Execution not found", WARN);
@@ -238,7 +1626,7 @@ async fn show_history(cmd: &HistoryCommand) -> Result<()> {
             all_execs.extend(store.list_executions(pipeline).await?);
         }
         // Sort by started_at descending
-        all_execs.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+        all_execs.sort_by_key(|e| std::cmp::Reverse(e.started_at));
         all_execs.into_iter().take(cmd.limit).collect()
     };
 
@@ -254,23 +1642,124 @@ async fn show_history(cmd: &HistoryCommand) -> Result<()> {
         println!("{}", serde_json::to_string_pretty(&data)?);
     } else {
         for summary in &executions {
-            println!("  {}", format_execution_summary(summary));
+            println!("  {}", format_execution_summary(summary, times));
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the prompt hashes of each step, noting which changed since the
+/// previous run of the pipeline that recorded hashes
+async fn show_prompt_hashes(store: &dyn PersistenceBackend, summary: &ExecutionSummary, json: bool) -> Result<()> {
+    let (steps, _) = persistence::load_run_files(summary.execution_id)?;
+    if json {
+        let data: Vec<_> = steps
+            .iter()
+            .map(|step| serde_json::json!({ "step_id": step.step_id, "prompts": step.prompt_hashes }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "execution_id": summary.execution_id,
+            "steps": data,
+        }))?);
+        return Ok(());
+    }
+
+    let mut earlier: Vec<_> = store
+        .list_executions(&summary.pipeline_name)
+        .await?
+        .into_iter()
+        .filter(|other| other.started_at < summary.started_at)
+        .collect();
+    earlier.sort_by_key(|other| std::cmp::Reverse(other.started_at));
+    let mut previous = None;
+    for other in earlier {
+        let (records, _) = persistence::load_run_files(other.execution_id)?;
+        if records.iter().any(|r| !r.prompt_hashes.is_empty()) {
+            previous = Some((other.execution_id, records));
+            break;
+        }
+    }
+
+    println!("{} Prompt hashes for run {} ({})", INFO, style(summary.execution_id).cyan(), summary.pipeline_name);
+    if let Some((id, _)) = &previous {
+        println!("  compared with previous run {}", style(&id.to_string()[..8]).dim());
+    }
+    if steps.iter().all(|step| step.prompt_hashes.is_empty()) {
+        println!("{} No prompt hashes recorded for this run", WARN);
+        return Ok(());
+    }
+
+    for step in steps.iter().filter(|step| !step.prompt_hashes.is_empty()) {
+        let before = previous
+            .as_ref()
+            .and_then(|(_, records)| records.iter().find(|r| r.step_id == step.step_id))
+            .and_then(|r| r.prompt_hashes.last());
+        println!("\n  {}", style(&step.step_id).bold());
+        for hashes in &step.prompt_hashes {
+            let compare = |now: &str, then: Option<&String>| match then {
+                Some(then) if then == now => style("unchanged").dim().to_string(),
+                Some(_) => style("changed").yellow().to_string(),
+                None => String::new(),
+            };
+            println!(
+                "    attempt {}  template {} {}  rendered {} {}",
+                hashes.attempt,
+                &hashes.template[..12],
+                compare(&hashes.template, before.map(|b| &b.template)),
+                &hashes.rendered[..12],
+                compare(&hashes.rendered, before.map(|b| &b.rendered)),
+            );
         }
     }
 
     Ok(())
 }
 
-fn print_execution_details(summary: &ExecutionSummary, verbose: bool) -> Result<()> {
+/// Print the named outputs of a run, one per line
+fn print_outputs(outputs: &std::collections::BTreeMap<String, String>) {
+    if outputs.is_empty() {
+        return;
+    }
+    println!("  Outputs:");
+    for (name, value) in outputs {
+        let mut lines = value.lines();
+        println!("    {} = {}", style(name).bold(), lines.next().unwrap_or(""));
+        for line in lines {
+            println!("      {}", line);
+        }
+    }
+}
+
+/// Step results of an execution, loaded only when they will be shown
+async fn load_step_results_if(
+    store: &SqliteExecutionStore,
+    summary: &ExecutionSummary,
+    verbose: bool,
+) -> Result<Vec<StepResult>> {
+    if verbose {
+        store.load_step_results(summary.execution_id).await
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+fn print_execution_details(
+    summary: &ExecutionSummary,
+    steps: &[StepResult],
+    verbose: bool,
+    max_output_lines: usize,
+    times: &TimestampFormat,
+) -> Result<()> {
     println!("{} Execution Details", INFO);
     println!("  ID: {}", style(summary.execution_id).cyan());
     println!("  Pipeline: {}", style(&summary.pipeline_name).bold());
     println!("  Status: {}", format_status(summary.status));
-    println!("  Started: {}", style(summary.started_at.to_rfc3339()).dim());
+    println!("  Started: {}", style(times.format(summary.started_at)).dim());
     if let Some(completed) = summary.completed_at {
         println!(
             "  Completed: {}",
-            style(completed.to_rfc3339()).dim()
+            style(times.format(completed)).dim()
         );
         if let Ok(duration) = completed.signed_duration_since(summary.started_at).to_std() {
             println!("  Duration: {}", style(format_duration(duration)).dim());
@@ -281,6 +1770,50 @@ fn print_execution_details(summary: &ExecutionSummary, verbose: bool) -> Result<
         summary.completed_steps,
         summary.total_steps
     );
+    if summary.bytes_written > 0 {
+        println!("  Written: {}", style(format_bytes(summary.bytes_written)).dim());
+    }
+    if let Some(seed) = summary.seed {
+        println!("  Seed: {} {}", style(seed).cyan(), style(format!("(re-run with --seed {})", seed)).dim());
+    }
+    if let Some(parent) = summary.parent_execution_id {
+        println!("  Started by: {}", style(&parent.to_string()[..8]).cyan());
+    }
+    print_outputs(&summary.outputs);
+
+    if verbose && !steps.is_empty() {
+        println!("\n  {}", style("Steps:").bold());
+        for step in steps {
+            let took = match (step.started_at, step.finished_at) {
+                (Some(start), Some(end)) => end
+                    .signed_duration_since(start)
+                    .to_std()
+                    .map(|took| format!(", {}", format_duration(took)))
+                    .unwrap_or_default(),
+                _ => String::new(),
+            };
+            println!(
+                "\n  {} {} ({}, {} attempt(s){})",
+                style("▸").dim(),
+                style(&step.step_id).bold(),
+                step.status,
+                step.attempts,
+                took
+            );
+            if let Some(error) = &step.error {
+                println!("    {}", style(error).red());
+            }
+            if let Some(output) = &step.output {
+                let output = match max_output_lines {
+                    0 => output.clone(),
+                    max => format_output(output, max),
+                };
+                for line in output.lines() {
+                    println!("    {}", line);
+                }
+            }
+        }
+    }
 
     if verbose {
         println!("\n  {}", style("Full details:").bold());
@@ -293,15 +1826,5 @@ fn print_execution_details(summary: &ExecutionSummary, verbose: bool) -> Result<
     Ok(())
 }
 
-fn format_duration(duration: std::time::Duration) -> String {
-    let secs = duration.as_secs();
-    if secs < 60 {
-        format!("{}s", secs)
-    } else if secs < 3600 {
-        format!("{}m {}s", secs / 60, secs % 60)
-    } else {
-        format!("{}h {}m {}s", secs / 3600, (secs % 3600) / 60, secs % 60)
-    }
-}
 
 use core::ExecutionStatus;
@@ -0,0 +1,448 @@
+//! Versioned SQLite schema migrations
+//!
+//! Each migration runs once, in order, inside its own transaction. Applied
+//! versions are recorded in `schema_migrations`, so new columns and tables are
+//! added by appending a migration here rather than editing an earlier one.
+
+use anyhow::{Context, Result};
+use sqlx::SqlitePool;
+
+/// A single schema change
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    /// Monotonically increasing schema version
+    pub version: i64,
+
+    /// Short description recorded alongside the version
+    pub description: &'static str,
+
+    /// SQL applied to reach this version
+    pub sql: &'static str,
+}
+
+/// All migrations, oldest first
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "executions table",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS executions (
+                id TEXT PRIMARY KEY,
+                pipeline_name TEXT NOT NULL,
+                status TEXT NOT NULL,
+                started_at TEXT NOT NULL,
+                completed_at TEXT,
+                progress REAL NOT NULL DEFAULT 0.0,
+                completed_steps INTEGER NOT NULL DEFAULT 0,
+                total_steps INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_pipeline_name ON executions(pipeline_name);
+            CREATE INDEX IF NOT EXISTS idx_status ON executions(status);
+            CREATE INDEX IF NOT EXISTS idx_started_at ON executions(started_at);
+        "#,
+    },
+    Migration {
+        version: 2,
+        description: "pipeline run locks",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS pipeline_locks (
+                pipeline_name TEXT PRIMARY KEY,
+                execution_id TEXT NOT NULL,
+                acquired_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+        "#,
+    },
+    Migration {
+        version: 3,
+        description: "bytes written per execution",
+        sql: r#"
+            ALTER TABLE executions ADD COLUMN bytes_written INTEGER NOT NULL DEFAULT 0;
+        "#,
+    },
+    Migration {
+        version: 4,
+        description: "pipeline memory",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS pipeline_memory (
+                pipeline_name TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                updated_at TEXT NOT NULL DEFAULT (datetime('now')),
+                PRIMARY KEY (pipeline_name, key)
+            );
+        "#,
+    },
+    Migration {
+        version: 5,
+        description: "concurrency groups",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS concurrency_groups (
+                group_name TEXT PRIMARY KEY,
+                execution_id TEXT NOT NULL,
+                joined_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+        "#,
+    },
+    Migration {
+        version: 6,
+        description: "pipeline outputs per execution",
+        sql: r#"
+            ALTER TABLE executions ADD COLUMN outputs TEXT NOT NULL DEFAULT '{}';
+        "#,
+    },
+    Migration {
+        version: 7,
+        description: "heartbeats of running executions",
+        sql: r#"
+            ALTER TABLE executions ADD COLUMN heartbeat_at TEXT;
+        "#,
+    },
+    Migration {
+        version: 8,
+        description: "priorities of active runs",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS run_priorities (
+                execution_id TEXT PRIMARY KEY,
+                priority INTEGER NOT NULL,
+                seen_at TEXT NOT NULL
+            );
+        "#,
+    },
+    Migration {
+        version: 9,
+        description: "step results per execution",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS step_results (
+                execution_id TEXT NOT NULL,
+                step_id TEXT NOT NULL,
+                status TEXT NOT NULL,
+                output TEXT,
+                error TEXT,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                started_at TEXT,
+                finished_at TEXT,
+                PRIMARY KEY (execution_id, step_id)
+            );
+        "#,
+    },
+    Migration {
+        version: 10,
+        description: "undelivered webhook notifications",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS dead_letters (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                execution_id TEXT NOT NULL,
+                url TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                error TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                failed_at TEXT NOT NULL
+            );
+        "#,
+    },
+    Migration {
+        version: 11,
+        description: "history search indexes",
+        sql: r#"
+            CREATE INDEX IF NOT EXISTS idx_status_started_at ON executions(status, started_at);
+
+            -- Substring index over step results, kept in sync by triggers
+            CREATE VIRTUAL TABLE IF NOT EXISTS step_results_fts USING fts5(
+                step_id, output, error,
+                content = 'step_results', tokenize = 'trigram'
+            );
+            INSERT INTO step_results_fts(step_results_fts) VALUES ('rebuild');
+
+            CREATE TRIGGER IF NOT EXISTS step_results_fts_insert AFTER INSERT ON step_results BEGIN
+                INSERT INTO step_results_fts(rowid, step_id, output, error)
+                VALUES (new.rowid, new.step_id, new.output, new.error);
+            END;
+            CREATE TRIGGER IF NOT EXISTS step_results_fts_delete AFTER DELETE ON step_results BEGIN
+                INSERT INTO step_results_fts(step_results_fts, rowid, step_id, output, error)
+                VALUES ('delete', old.rowid, old.step_id, old.output, old.error);
+            END;
+            CREATE TRIGGER IF NOT EXISTS step_results_fts_update AFTER UPDATE ON step_results BEGIN
+                INSERT INTO step_results_fts(step_results_fts, rowid, step_id, output, error)
+                VALUES ('delete', old.rowid, old.step_id, old.output, old.error);
+                INSERT INTO step_results_fts(rowid, step_id, output, error)
+                VALUES (new.rowid, new.step_id, new.output, new.error);
+            END;
+        "#,
+    },
+    Migration {
+        version: 12,
+        description: "step artifacts per execution",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS artifacts (
+                execution_id TEXT NOT NULL,
+                path TEXT NOT NULL,
+                step_id TEXT NOT NULL,
+                source TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                sha256 TEXT NOT NULL,
+                collected_at TEXT NOT NULL,
+                PRIMARY KEY (execution_id, path)
+            );
+        "#,
+    },
+    Migration {
+        version: 13,
+        description: "compressed step outputs",
+        sql: r#"
+            -- Large outputs move to a zstd-compressed column, which SQL can't
+            -- read, so the search index no longer follows the table through
+            -- triggers: the store writes each result's text to it instead
+            ALTER TABLE step_results ADD COLUMN output_zstd BLOB;
+
+            DROP TRIGGER IF EXISTS step_results_fts_insert;
+            DROP TRIGGER IF EXISTS step_results_fts_delete;
+            DROP TRIGGER IF EXISTS step_results_fts_update;
+            DROP TABLE IF EXISTS step_results_fts;
+            CREATE VIRTUAL TABLE step_results_fts USING fts5(
+                step_id, output, error,
+                content = '', contentless_delete = 1, tokenize = 'trigram'
+            );
+            INSERT INTO step_results_fts(rowid, step_id, output, error)
+            SELECT rowid, step_id, output, error FROM step_results;
+        "#,
+    },
+    Migration {
+        version: 14,
+        description: "agent usage per step result",
+        sql: r#"
+            ALTER TABLE step_results ADD COLUMN tokens INTEGER NOT NULL DEFAULT 0;
+            ALTER TABLE step_results ADD COLUMN cost_usd REAL NOT NULL DEFAULT 0;
+        "#,
+    },
+    Migration {
+        version: 15,
+        description: "input digests of incremental steps",
+        sql: r#"
+            ALTER TABLE step_results ADD COLUMN input_digest TEXT;
+        "#,
+    },
+    Migration {
+        version: 16,
+        description: "random seed per execution",
+        sql: r#"
+            ALTER TABLE executions ADD COLUMN seed INTEGER;
+        "#,
+    },
+    Migration {
+        version: 17,
+        description: "runs started by the scheduler",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS scheduled_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                schedule TEXT NOT NULL,
+                execution_id TEXT NOT NULL,
+                due_at TEXT NOT NULL,
+                started_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_scheduled_runs_schedule ON scheduled_runs(schedule, id);
+        "#,
+    },
+    Migration {
+        version: 18,
+        description: "runs started by pipeline steps",
+        sql: r#"
+            ALTER TABLE executions ADD COLUMN parent_execution_id TEXT;
+            CREATE INDEX IF NOT EXISTS idx_executions_parent ON executions(parent_execution_id);
+        "#,
+    },
+    Migration {
+        version: 19,
+        description: "named queues",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS queue_items (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                queue TEXT NOT NULL,
+                payload TEXT NOT NULL,
+                pushed_by TEXT,
+                pushed_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_queue_items_queue ON queue_items(queue, id);
+        "#,
+    },
+    Migration {
+        version: 20,
+        description: "run lock waiting line",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS lock_waiters (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                pipeline_name TEXT NOT NULL,
+                execution_id TEXT NOT NULL,
+                seen_at TEXT NOT NULL,
+                UNIQUE (pipeline_name, execution_id)
+            );
+        "#,
+    },
+    Migration {
+        version: 21,
+        description: "run lock check-ins",
+        sql: r#"
+            ALTER TABLE pipeline_locks ADD COLUMN seen_at TEXT;
+        "#,
+    },
+    Migration {
+        version: 22,
+        description: "tool call decisions",
+        sql: r#"
+            CREATE TABLE IF NOT EXISTS tool_decisions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                execution_id TEXT NOT NULL,
+                step_id TEXT NOT NULL,
+                tool_name TEXT NOT NULL,
+                arguments_sha256 TEXT NOT NULL,
+                approved INTEGER NOT NULL,
+                approver TEXT NOT NULL,
+                decided_at TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_tool_decisions_execution ON tool_decisions(execution_id, id);
+        "#,
+    },
+];
+
+/// Latest schema version known to this build
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Bring the database up to the latest schema version
+///
+/// Returns the resulting schema version.
+pub async fn migrate(pool: &SqlitePool) -> Result<i64> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create schema_migrations table")?;
+
+    let mut current = current_version(pool).await?;
+    if current == 0 {
+        current = baseline_unversioned(pool).await?;
+    }
+
+    if current > latest_version() {
+        anyhow::bail!(
+            "Database schema version {} is newer than this build supports ({})",
+            current,
+            latest_version()
+        );
+    }
+
+    let pending: Vec<&Migration> = MIGRATIONS.iter().filter(|m| m.version > current).collect();
+    for migration in pending {
+        let mut tx = pool.begin().await?;
+        sqlx::query(migration.sql)
+            .execute(&mut *tx)
+            .await
+            .with_context(|| format!("Migration {} ({}) failed", migration.version, migration.description))?;
+        record(&mut tx, migration).await?;
+        tx.commit().await?;
+        current = migration.version;
+    }
+
+    Ok(current)
+}
+
+/// Highest applied schema version (0 if none)
+pub async fn current_version(pool: &SqlitePool) -> Result<i64> {
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM schema_migrations")
+        .fetch_one(pool)
+        .await
+        .context("Failed to read schema version")?;
+    Ok(version.unwrap_or(0))
+}
+
+/// Record the version of a database created before migrations existed
+///
+/// Such databases were built with `CREATE TABLE IF NOT EXISTS`, so the version
+/// is inferred from which tables and columns are present.
+async fn baseline_unversioned(pool: &SqlitePool) -> Result<i64> {
+    let has_table = |name: &'static str| async move {
+        sqlx::query_scalar::<_, bool>(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?",
+        )
+        .bind(name)
+        .fetch_one(pool)
+        .await
+    };
+
+    if !has_table("executions").await? {
+        return Ok(0);
+    }
+
+    let has_bytes_written: bool = sqlx::query_scalar(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info('executions') WHERE name = 'bytes_written'",
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let version = if has_bytes_written {
+        3
+    } else if has_table("pipeline_locks").await? {
+        2
+    } else {
+        1
+    };
+
+    let mut tx = pool.begin().await?;
+    for migration in MIGRATIONS.iter().filter(|m| m.version <= version) {
+        record(&mut tx, migration).await?;
+    }
+    tx.commit().await?;
+
+    Ok(version)
+}
+
+async fn record(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, migration: &Migration) -> Result<()> {
+    sqlx::query("INSERT INTO schema_migrations (version, description) VALUES (?, ?)")
+        .bind(migration.version)
+        .bind(migration.description)
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record migration")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_are_increasing() {
+        for pair in MIGRATIONS.windows(2) {
+            assert!(pair[0].version < pair[1].version);
+        }
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_migrate_fresh_database_is_idempotent() {
+        // A single connection, since each in-memory connection is its own database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        assert_eq!(migrate(&pool).await.unwrap(), latest_version());
+        assert_eq!(migrate(&pool).await.unwrap(), latest_version());
+
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM schema_migrations")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len() as i64);
+    }
+}
@@ -1,5 +1,7 @@
 //! Persistence layer for pipeline execution history
 
+#[cfg(feature = "sqlite")]
+pub mod migrations;
 #[cfg(feature = "sqlite")]
 pub mod store;
 
@@ -7,10 +9,14 @@ pub mod store;
 pub use store::SqliteExecutionStore;
 
 pub use crate::core::ExecutionStatus;
-use crate::core::{Pipeline};
+use crate::core::{AgentAttempt, Pipeline, PromptHashes, Step, StepState};
+use crate::core::config::{LockConflictPolicy, Priority, Telemetry};
+use crate::execution::ExecutionEvent;
 use anyhow::Result;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 use uuid::Uuid;
 
 /// Summary of a pipeline execution
@@ -39,6 +45,806 @@ pub struct ExecutionSummary {
 
     /// Total number of steps
     pub total_steps: usize,
+
+    /// Bytes written by the agent across all steps
+    #[serde(default)]
+    pub bytes_written: u64,
+
+    /// Named outputs declared by the pipeline, as produced by the run
+    #[serde(default)]
+    pub outputs: std::collections::BTreeMap<String, String>,
+
+    /// The run's random seed (`{{ run.seed }}`); unknown for older runs
+    #[serde(default)]
+    pub seed: Option<u32>,
+
+    /// Run whose `pipeline:` step started this one, if any
+    #[serde(default)]
+    pub parent_execution_id: Option<Uuid>,
+}
+
+/// How often a running execution records that it is still alive
+pub const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Running executions without a heartbeat for this long are marked orphaned
+pub const STALE_AFTER: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Runs holding or waiting in line for the run lock that have not checked
+/// in for this long (a crashed or interrupted run) lose the lock or their
+/// place
+pub const LOCK_STALE_AFTER: std::time::Duration = std::time::Duration::from_secs(30);
+
+static DATA_DIR_OVERRIDE: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+/// Environment variable naming the data directory
+pub const DATA_DIR_ENV: &str = "PIPELINE_DATA_DIR";
+
+/// Data directory named by `PIPELINE_DATA_DIR`, if set and not empty
+pub fn data_dir_from_env() -> Option<PathBuf> {
+    std::env::var_os(DATA_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Use `dir` instead of the platform data directory
+///
+/// Set once at startup; later calls are ignored.
+pub fn set_data_dir(dir: PathBuf) {
+    let _ = DATA_DIR_OVERRIDE.set(dir);
+}
+
+/// Directory holding the history database and per-run files
+///
+/// The directory given to `set_data_dir` wins over `PIPELINE_DATA_DIR`,
+/// which wins over the platform data directory.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = DATA_DIR_OVERRIDE.get().cloned().or_else(data_dir_from_env) {
+        return dir;
+    }
+    dirs::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("pipeline")
+}
+
+/// Directory for the files produced by a single run (step outputs, reports)
+pub fn run_dir(execution_id: Uuid) -> PathBuf {
+    data_dir().join("runs").join(execution_id.to_string())
+}
+
+/// Directory a run's step artifacts are collected into, one subdirectory
+/// per step
+pub fn artifacts_dir(execution_id: Uuid) -> PathBuf {
+    run_dir(execution_id).join("artifacts")
+}
+
+/// Run directory under `runs_dir` that was modified most recently
+pub fn latest_run_dir(runs_dir: &std::path::Path) -> Option<PathBuf> {
+    std::fs::read_dir(runs_dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| {
+            let dir = entry.path();
+            // Steps change the step records, not the directory itself
+            let modified = std::fs::metadata(dir.join("steps.json"))
+                .or_else(|_| entry.metadata())
+                .and_then(|meta| meta.modified())
+                .ok()?;
+            Some((modified, dir))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, dir)| dir)
+}
+
+/// Run directory of the execution whose ID starts with `prefix`
+///
+/// Unlike the history database, run directories exist while a run is still
+/// in progress.
+pub fn find_run_dir(prefix: &str) -> Result<PathBuf> {
+    let runs_dir = data_dir().join("runs");
+    let matches: Vec<PathBuf> = std::fs::read_dir(&runs_dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|dir| {
+                    dir.is_dir()
+                        && dir
+                            .file_name()
+                            .and_then(|name| name.to_str())
+                            .is_some_and(|name| name.starts_with(prefix))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    match matches.as_slice() {
+        [dir] => Ok(dir.clone()),
+        [] => anyhow::bail!("No run matches '{}' in {}", prefix, runs_dir.display()),
+        _ => anyhow::bail!("'{}' matches {} runs; use a longer prefix", prefix, matches.len()),
+    }
+}
+
+/// Reads lines appended to a run's `events.log` since the last read
+pub struct EventLogReader {
+    path: PathBuf,
+    offset: u64,
+}
+
+impl EventLogReader {
+    /// Start reading the event log of a run directory from the beginning
+    pub fn new(dir: &std::path::Path) -> Self {
+        Self {
+            path: dir.join("events.log"),
+            offset: 0,
+        }
+    }
+
+    /// Start reading another append-only log, such as a step log
+    pub fn for_file(path: &std::path::Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            offset: 0,
+        }
+    }
+
+    /// Complete lines appended since the last call
+    ///
+    /// A line still being written is left for the next call.
+    pub fn read_new(&mut self) -> Result<Vec<String>> {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let mut file = match std::fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        // The log is recreated if the run directory is reused
+        if file.metadata()?.len() < self.offset {
+            self.offset = 0;
+        }
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut appended = Vec::new();
+        file.read_to_end(&mut appended)?;
+
+        let Some(end) = appended.iter().rposition(|&b| b == b'\n') else {
+            return Ok(Vec::new());
+        };
+        self.offset += end as u64 + 1;
+        Ok(String::from_utf8_lossy(&appended[..end])
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// Outcome of a single step, saved with the run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub step_id: String,
+    pub status: String,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Alternatives, one of which had to complete (`depends_on_any`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on_any: Vec<String>,
+    #[serde(default)]
+    pub attempts: usize,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub bytes_written: u64,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub agent_attempts: Vec<AgentAttempt>,
+    #[serde(default)]
+    pub budget_category: Option<String>,
+    #[serde(default)]
+    pub cost_usd: f64,
+    #[serde(default)]
+    pub tokens: u64,
+    #[serde(default)]
+    pub prompt_hashes: Vec<PromptHashes>,
+    #[serde(default)]
+    pub input_digest: Option<String>,
+}
+
+impl StepRecord {
+    /// Record the current state of each step, in execution order
+    pub fn from_pipeline(pipeline: &Pipeline) -> Vec<StepRecord> {
+        pipeline
+            .execution_order()
+            .iter()
+            .filter_map(|id| pipeline.step(id))
+            .map(|step| {
+                let (status, attempts, started_at, finished_at, error) = match &step.state {
+                    StepState::Pending => ("pending", 0, None, None, None),
+                    StepState::Retrying { attempt } => ("retrying", *attempt, None, None, None),
+                    StepState::Running { started_at, attempt } => {
+                        ("running", *attempt, Some(*started_at), None, None)
+                    }
+                    StepState::Completed { attempts, started_at, completed_at, .. } => {
+                        ("completed", *attempts, Some(*started_at), Some(*completed_at), None)
+                    }
+                    StepState::Failed { error, attempts, last_started_at, failed_at } => (
+                        "failed",
+                        *attempts,
+                        Some(*last_started_at),
+                        Some(*failed_at),
+                        Some(error.clone()),
+                    ),
+                    StepState::Cancelled { reason, attempts, started_at, cancelled_at } => (
+                        "cancelled",
+                        *attempts,
+                        Some(*started_at),
+                        Some(*cancelled_at),
+                        Some(reason.clone()),
+                    ),
+                    StepState::Skipped { reason } => ("skipped", 0, None, None, Some(reason.clone())),
+                    StepState::Blocked { reason, blocked_at } => {
+                        ("blocked", 0, Some(*blocked_at), None, Some(reason.clone()))
+                    }
+                };
+                let record = StepRecord {
+                    step_id: step.id.clone(),
+                    status: status.to_string(),
+                    depends_on: step.dependencies.clone(),
+                    depends_on_any: step.any_dependencies.clone(),
+                    attempts,
+                    started_at,
+                    finished_at,
+                    bytes_written: pipeline.state.bytes_written.get(&step.id).copied().unwrap_or(0),
+                    error,
+                    agent_attempts: pipeline.state.agent_attempts.get(&step.id).cloned().unwrap_or_default(),
+                    budget_category: step.budget_category.clone(),
+                    cost_usd: pipeline.state.cost_usd.get(&step.id).copied().unwrap_or(0.0),
+                    tokens: pipeline.state.tokens.get(&step.id).copied().unwrap_or(0),
+                    prompt_hashes: pipeline.state.prompt_hashes.get(&step.id).cloned().unwrap_or_default(),
+                    input_digest: pipeline.state.input_digests.get(&step.id).cloned(),
+                };
+                record.minimized(step.telemetry)
+            })
+            .collect()
+    }
+
+    /// The record as a step with this telemetry level may keep it
+    ///
+    /// Below `full`, error text is withheld; at `none` only the status and
+    /// dependencies remain.
+    pub fn minimized(mut self, telemetry: Telemetry) -> Self {
+        if telemetry.keeps_content() {
+            return self;
+        }
+        if self.error.is_some() && self.status != "skipped" {
+            self.error = Some(Telemetry::WITHHELD.to_string());
+        }
+        for attempt in &mut self.agent_attempts {
+            if attempt.error.is_some() {
+                attempt.error = Some(Telemetry::WITHHELD.to_string());
+            }
+        }
+        if !telemetry.keeps_metadata() {
+            self = StepRecord {
+                step_id: self.step_id,
+                status: self.status,
+                depends_on: self.depends_on,
+                depends_on_any: self.depends_on_any,
+                attempts: 0,
+                started_at: None,
+                finished_at: None,
+                bytes_written: 0,
+                error: None,
+                agent_attempts: Vec::new(),
+                budget_category: self.budget_category,
+                cost_usd: 0.0,
+                tokens: 0,
+                prompt_hashes: Vec::new(),
+                input_digest: None,
+            };
+        }
+        self
+    }
+}
+
+/// Webhook notification that could not be delivered, kept so missed
+/// alerts can be found and resent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub execution_id: Uuid,
+    pub url: String,
+    /// The body that was sent
+    pub payload: String,
+    /// Why the last attempt failed
+    pub error: String,
+    pub attempts: u32,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Run started by `pipeline scheduler run` for a schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledRun {
+    pub schedule: String,
+    pub execution_id: Uuid,
+    /// The time the schedule was due
+    pub due_at: DateTime<Utc>,
+    pub started_at: DateTime<Utc>,
+}
+
+/// File a step declared under `artifacts:`, as collected after it completed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub step_id: String,
+    /// Where the copy lives, relative to the run's artifacts directory
+    /// (`<step id>/<path>`)
+    pub path: String,
+    /// Where the step left the file
+    pub source: String,
+    pub size: u64,
+    /// SHA-256 of the contents, as hex
+    pub sha256: String,
+    pub collected_at: DateTime<Utc>,
+}
+
+/// Decision on an agent tool call that needed approval, kept as an audit
+/// trail
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolDecision {
+    pub step_id: String,
+    pub tool_name: String,
+    /// SHA-256 of the call's arguments as JSON, as hex
+    pub arguments_sha256: String,
+    pub approved: bool,
+    /// Who decided: the operator at a terminal or a policy of the run
+    pub approver: String,
+    pub decided_at: DateTime<Utc>,
+}
+
+/// Item waiting in a named queue, pushed by a `queue:` step or
+/// `pipeline queue push`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub queue: String,
+    pub payload: String,
+    /// The run whose step pushed it, if any
+    pub pushed_by: Option<Uuid>,
+    pub pushed_at: DateTime<Utc>,
+}
+
+/// Items waiting in a named queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueSummary {
+    pub queue: String,
+    pub items: usize,
+    /// When the item at the front of the queue was pushed
+    pub oldest_at: DateTime<Utc>,
+}
+
+/// Filters for a history search; filters left unset match every execution
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub pipeline: Option<String>,
+    /// Match executions with any of these statuses
+    pub statuses: Vec<ExecutionStatus>,
+    /// Match executions started at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Match executions with a step whose ID, output or error contains this
+    /// text (ignoring case)
+    pub contains: Option<String>,
+    /// Most executions to return
+    pub limit: usize,
+}
+
+impl HistoryQuery {
+    /// Whether a step result contains the searched text
+    pub fn step_matches(&self, result: &StepResult) -> bool {
+        let Some(text) = &self.contains else {
+            return false;
+        };
+        let text = text.to_lowercase();
+        [Some(&result.step_id), result.output.as_ref(), result.error.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|field| field.to_lowercase().contains(&text))
+    }
+}
+
+/// An execution found by a history search
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryMatch {
+    #[serde(flatten)]
+    pub summary: ExecutionSummary,
+    /// Steps that contain the searched text, in execution order
+    pub matched_steps: Vec<String>,
+}
+
+/// Filters for aggregate run metrics; filters left unset match every execution
+#[derive(Debug, Clone, Default)]
+pub struct StatsQuery {
+    pub pipeline: Option<String>,
+    /// Count only executions started at or after this time
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Aggregate metrics of a pipeline's finished runs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineStats {
+    pub pipeline_name: String,
+    pub runs: usize,
+    /// Runs that completed
+    pub succeeded: usize,
+    /// Fraction of runs that completed
+    pub success_rate: f64,
+    /// Mean time from start to end, over the runs that recorded an end
+    pub avg_duration_secs: Option<f64>,
+    /// Mean step attempts beyond the first, per run
+    pub avg_retries: f64,
+    /// Tokens used by the agents of all the runs
+    pub tokens: u64,
+    /// What the agents of all the runs cost, in USD
+    pub cost_usd: f64,
+}
+
+/// Final result of a step, saved to the history database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StepResult {
+    pub step_id: String,
+    pub status: String,
+    /// Output of a completed step
+    pub output: Option<String>,
+    pub error: Option<String>,
+    pub attempts: usize,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Tokens used by the step's agent turns
+    #[serde(default)]
+    pub tokens: u64,
+    /// Cost of the step's agent turns in USD
+    #[serde(default)]
+    pub cost_usd: f64,
+    /// Digest of the inputs an incremental step ran with
+    #[serde(default)]
+    pub input_digest: Option<String>,
+}
+
+impl StepResult {
+    /// Results of each step, in execution order
+    pub fn from_pipeline(pipeline: &Pipeline) -> Vec<StepResult> {
+        StepRecord::from_pipeline(pipeline)
+            .into_iter()
+            .map(|record| StepResult {
+                output: match pipeline.step(&record.step_id) {
+                    Some(Step { state: StepState::Completed { output, .. }, telemetry, .. })
+                        if telemetry.keeps_content() =>
+                    {
+                        Some(output.clone())
+                    }
+                    _ => None,
+                },
+                step_id: record.step_id,
+                status: record.status,
+                error: record.error,
+                attempts: record.attempts,
+                started_at: record.started_at,
+                finished_at: record.finished_at,
+                tokens: record.tokens,
+                cost_usd: record.cost_usd,
+                input_digest: record.input_digest,
+            })
+            .collect()
+    }
+}
+
+/// Save step records to `<run dir>/steps.json` and the output of each
+/// completed step to `<run dir>/outputs/<step id>.txt`
+///
+/// Outputs of steps whose telemetry level is below `full` are not written,
+/// so resuming the run runs those steps again. Returns the run directory.
+pub fn save_run_files(pipeline: &Pipeline) -> Result<PathBuf> {
+    let dir = run_dir(pipeline.state.execution_id);
+    let outputs = dir.join("outputs");
+    std::fs::create_dir_all(&outputs)?;
+
+    let records = StepRecord::from_pipeline(pipeline);
+    std::fs::write(dir.join("steps.json"), serde_json::to_string_pretty(&records)?)?;
+
+    for (step_id, step) in &pipeline.steps {
+        if let (true, StepState::Completed { output, .. }) = (step.telemetry.keeps_content(), &step.state) {
+            std::fs::write(outputs.join(format!("{}.txt", step_id)), output)?;
+        }
+    }
+
+    Ok(dir)
+}
+
+/// Save the plans written by plan-only steps to `<run dir>/plan.md`
+///
+/// Plans are collected in execution order under a heading per step. Returns
+/// `None` when no plan-only step completed.
+pub fn save_plan(pipeline: &Pipeline) -> Result<Option<PathBuf>> {
+    let mut plan = format!("# Plan: {}\n", pipeline.name);
+    let mut found = false;
+    for step_id in pipeline.execution_order() {
+        let Some(step) = pipeline.steps.get(step_id) else { continue };
+        if let (true, StepState::Completed { output, .. }) = (step.plan_only && step.telemetry.keeps_content(), &step.state) {
+            plan.push_str(&format!("\n## {}\n\n{}\n", step_id, output.trim_end()));
+            found = true;
+        }
+    }
+    if !found {
+        return Ok(None);
+    }
+
+    let dir = run_dir(pipeline.state.execution_id);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join("plan.md");
+    std::fs::write(&path, plan)?;
+    Ok(Some(path))
+}
+
+/// Load the step records and outputs saved for a run
+///
+/// Runs saved before step records existed have no records.
+pub fn load_run_files(execution_id: Uuid) -> Result<(Vec<StepRecord>, HashMap<String, String>)> {
+    load_run_files_from(&run_dir(execution_id))
+}
+
+/// Load the step records and outputs saved in a run directory
+pub fn load_run_files_from(dir: &std::path::Path) -> Result<(Vec<StepRecord>, HashMap<String, String>)> {
+    let records = match std::fs::read_to_string(dir.join("steps.json")) {
+        Ok(json) => serde_json::from_str(&json)?,
+        Err(_) => Vec::new(),
+    };
+
+    let mut outputs = HashMap::new();
+    if let Ok(entries) = std::fs::read_dir(dir.join("outputs")) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(step_id) = path.file_stem().and_then(|s| s.to_str()) {
+                outputs.insert(step_id.to_string(), std::fs::read_to_string(&path)?);
+            }
+        }
+    }
+
+    Ok((records, outputs))
+}
+
+/// How a run was started, saved to `<run dir>/run.json` so that
+/// `pipeline resume` can start it again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunCheckpoint {
+    /// Pipeline file, as an absolute path
+    pub file: PathBuf,
+    /// `--variable` overrides
+    #[serde(default)]
+    pub variables: Vec<(String, String)>,
+    /// `--set` overrides
+    #[serde(default)]
+    pub overrides: Vec<(String, String)>,
+    /// The run's random seed, kept when it is resumed
+    #[serde(default)]
+    pub seed: Option<u32>,
+}
+
+impl RunCheckpoint {
+    /// Save to `run.json` in a run directory
+    pub fn save(&self, dir: &std::path::Path) -> Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("run.json"), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Load from `run.json` in a run directory
+    pub fn load(dir: &std::path::Path) -> Result<Self> {
+        let json = std::fs::read_to_string(dir.join("run.json"))
+            .map_err(|_| anyhow::anyhow!("{} has no run.json (runs started before resume support can't be resumed)", dir.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+/// Request left in `<run dir>/cancel` by `pipeline cancel`, which the run
+/// checks for while it is in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelRequest {
+    /// Stop the steps in flight and end the run
+    Now,
+    /// Start no more steps and end the run once the steps in flight finish
+    AfterCurrent,
+}
+
+impl CancelRequest {
+    /// Leave the request in a run directory
+    ///
+    /// A request to cancel now is not replaced by a later one to stop after
+    /// the current steps.
+    pub fn send(self, dir: &std::path::Path) -> Result<()> {
+        if self == CancelRequest::AfterCurrent && Self::read(dir) == Some(CancelRequest::Now) {
+            return Ok(());
+        }
+        let request = match self {
+            CancelRequest::Now => "now",
+            CancelRequest::AfterCurrent => "after-current",
+        };
+        std::fs::write(dir.join("cancel"), request)?;
+        Ok(())
+    }
+
+    /// The request left in a run directory, if any
+    pub fn read(dir: &std::path::Path) -> Option<Self> {
+        match std::fs::read_to_string(dir.join("cancel")).ok()?.trim() {
+            "now" => Some(CancelRequest::Now),
+            "after-current" => Some(CancelRequest::AfterCurrent),
+            _ => None,
+        }
+    }
+
+    /// Remove a request left for an earlier attempt of a run (before it is
+    /// resumed)
+    pub fn clear(dir: &std::path::Path) {
+        let _ = std::fs::remove_file(dir.join("cancel"));
+    }
+}
+
+/// Restore a stopped run from its run directory into a freshly loaded pipeline
+///
+/// The pipeline takes the run's execution ID and start time. Steps that
+/// completed get their output back and are not run again; every other step
+/// starts over as pending. Attempt history, costs and bytes written are kept
+/// for all steps. Returns the IDs of the completed steps, in execution order.
+pub fn restore_run(pipeline: &mut Pipeline, dir: &std::path::Path) -> Result<Vec<String>> {
+    let execution_id = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| Uuid::parse_str(name).ok())
+        .ok_or_else(|| anyhow::anyhow!("{} is not a run directory", dir.display()))?;
+    let (records, mut outputs) = load_run_files_from(dir)?;
+
+    pipeline.state.execution_id = execution_id;
+    pipeline.state.started_at = records.iter().filter_map(|r| r.started_at).min();
+
+    let mut restored = Vec::new();
+    for record in records {
+        let Some(step) = pipeline.steps.get_mut(&record.step_id) else { continue };
+        let id = record.step_id;
+        if let (true, Some(output), Some(started_at), Some(completed_at)) = (
+            record.status == "completed",
+            outputs.remove(&id),
+            record.started_at,
+            record.finished_at,
+        ) {
+            step.state = StepState::Completed {
+                output,
+                attempts: record.attempts,
+                started_at,
+                completed_at,
+            };
+            restored.push(id.clone());
+        }
+
+        let state = &mut pipeline.state;
+        if record.bytes_written > 0 {
+            state.bytes_written.insert(id.clone(), record.bytes_written);
+        }
+        if record.cost_usd > 0.0 {
+            state.cost_usd.insert(id.clone(), record.cost_usd);
+        }
+        if record.tokens > 0 {
+            state.tokens.insert(id.clone(), record.tokens);
+        }
+        if let Some(digest) = record.input_digest {
+            state.input_digests.insert(id.clone(), digest);
+        }
+        if !record.agent_attempts.is_empty() {
+            state.agent_attempts.insert(id.clone(), record.agent_attempts);
+        }
+        if !record.prompt_hashes.is_empty() {
+            state.prompt_hashes.insert(id, record.prompt_hashes);
+        }
+    }
+
+    Ok(restored)
+}
+
+/// Run files kept up to date while the run is in progress
+///
+/// `steps.json` and the step outputs are rewritten as events arrive and each
+/// event is appended to `events.log`, so another process (`pipeline ui`) can
+/// follow the run. `save_run_files` writes the final state when it ends.
+pub struct LiveRunFiles {
+    dir: PathBuf,
+    records: std::sync::Mutex<Vec<StepRecord>>,
+    telemetry: HashMap<String, Telemetry>,
+}
+
+impl LiveRunFiles {
+    /// Start the live files for a run in `dir`, with every step in its
+    /// initial state
+    pub fn create(dir: PathBuf, pipeline: &Pipeline) -> Result<Self> {
+        std::fs::create_dir_all(dir.join("outputs"))?;
+        std::fs::write(dir.join("events.log"), "")?;
+        Self::open(dir, pipeline)
+    }
+
+    /// Continue the live files of a resumed run, appending to its event log
+    pub fn resume(dir: PathBuf, pipeline: &Pipeline) -> Result<Self> {
+        std::fs::create_dir_all(dir.join("outputs"))?;
+        Self::open(dir, pipeline)
+    }
+
+    fn open(dir: PathBuf, pipeline: &Pipeline) -> Result<Self> {
+        let live = Self {
+            dir,
+            records: std::sync::Mutex::new(StepRecord::from_pipeline(pipeline)),
+            telemetry: pipeline
+                .steps
+                .values()
+                .map(|step| (step.id.clone(), step.telemetry))
+                .collect(),
+        };
+        live.write_records()?;
+        Ok(live)
+    }
+
+    /// Apply an event to the step records and append its rendered `line` to
+    /// the event log
+    pub fn record(&self, event: &ExecutionEvent, line: &str) -> Result<()> {
+        use std::io::Write;
+
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(self.dir.join("events.log"))?;
+        writeln!(log, "{}", line)?;
+
+        let step_id = match event {
+            ExecutionEvent::StepOutput { step_id, output } => {
+                std::fs::write(self.dir.join("outputs").join(format!("{}.txt", step_id)), output)?;
+                return Ok(());
+            }
+            ExecutionEvent::StepStarted { step_id, .. }
+            | ExecutionEvent::StepCompleted { step_id, .. }
+            | ExecutionEvent::StepFailed { step_id, .. }
+            | ExecutionEvent::StepRetrying { step_id, .. }
+            | ExecutionEvent::StepWaiting { step_id, .. } => step_id,
+            _ => return Ok(()),
+        };
+
+        {
+            let mut records = self.records.lock().unwrap();
+            let Some(record) = records.iter_mut().find(|r| &r.step_id == step_id) else {
+                return Ok(());
+            };
+            match event {
+                ExecutionEvent::StepStarted { attempt, .. } => {
+                    record.status = "running".to_string();
+                    record.attempts = *attempt;
+                    record.started_at = Some(Utc::now());
+                    record.finished_at = None;
+                    record.error = None;
+                }
+                ExecutionEvent::StepCompleted { .. } => {
+                    record.status = "completed".to_string();
+                    record.finished_at = Some(Utc::now());
+                }
+                ExecutionEvent::StepFailed { error, .. } => {
+                    record.status = "failed".to_string();
+                    record.finished_at = Some(Utc::now());
+                    record.error = Some(error.clone());
+                }
+                ExecutionEvent::StepRetrying { .. } => record.status = "retrying".to_string(),
+                ExecutionEvent::StepWaiting { .. } => record.status = "waiting".to_string(),
+                _ => {}
+            }
+            let telemetry = self.telemetry.get(step_id).copied().unwrap_or_default();
+            *record = record.clone().minimized(telemetry);
+        }
+        self.write_records()
+    }
+
+    fn write_records(&self) -> Result<()> {
+        let json = serde_json::to_string_pretty(&*self.records.lock().unwrap())?;
+        std::fs::write(self.dir.join("steps.json"), json)?;
+        Ok(())
+    }
 }
 
 /// Trait for persistence backends
@@ -58,12 +864,340 @@ pub trait PersistenceBackend: Send + Sync {
 
     /// List all pipeline names
     async fn list_pipelines(&self) -> Result<Vec<String>>;
+
+    /// Try to take the advisory run lock for a pipeline, or record that
+    /// `owner` still holds it
+    ///
+    /// A lock whose holder last checked in before `seen_after` is taken
+    /// over. Returns `true` if `owner` now holds the lock (or already held it).
+    async fn try_acquire_lock(&self, pipeline_name: &str, owner: Uuid, seen_after: DateTime<Utc>) -> Result<bool>;
+
+    /// Release the run lock if it is held by `owner`
+    async fn release_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()>;
+
+    /// Remove the run lock regardless of owner (for stale locks)
+    ///
+    /// Returns `true` if a lock was removed.
+    async fn clear_lock(&self, pipeline_name: &str) -> Result<bool>;
+
+    /// Join the line of runs waiting for a pipeline's run lock, or record
+    /// that `owner` is still waiting (keeping its place)
+    async fn wait_for_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()>;
+
+    /// First run in line for a pipeline's run lock, dropping waiters last
+    /// seen before `seen_after`
+    async fn next_lock_waiter(&self, pipeline_name: &str, seen_after: DateTime<Utc>) -> Result<Option<Uuid>>;
+
+    /// Leave the line of runs waiting for a pipeline's run lock
+    async fn stop_waiting_for_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()>;
+
+    /// Record that a running execution is still alive
+    async fn heartbeat(&self, execution_id: Uuid) -> Result<()>;
+
+    /// Mark running executions last seen before `stale_before` as orphaned
+    ///
+    /// Returns the executions that were marked.
+    async fn mark_orphaned(&self, stale_before: DateTime<Utc>) -> Result<Vec<Uuid>>;
+
+    /// Load the values remembered by earlier runs of a pipeline
+    async fn load_memory(&self, pipeline_name: &str) -> Result<HashMap<String, String>>;
+
+    /// Remember a value for future runs of a pipeline (overwrites)
+    async fn save_memory(&self, pipeline_name: &str, key: &str, value: &str) -> Result<()>;
+
+    /// Join a concurrency group if no other run is active in it
+    ///
+    /// Returns `true` if `owner` is now the group's active run.
+    async fn try_join_group(&self, group: &str, owner: Uuid) -> Result<bool>;
+
+    /// Become the group's active run, superseding any other
+    ///
+    /// Returns the run that was superseded, if any.
+    async fn take_over_group(&self, group: &str, owner: Uuid) -> Result<Option<Uuid>>;
+
+    /// Current active run of a concurrency group
+    async fn group_holder(&self, group: &str) -> Result<Option<Uuid>>;
+
+    /// Leave a concurrency group if `owner` is still its active run
+    async fn leave_group(&self, group: &str, owner: Uuid) -> Result<()>;
+
+    /// Announce an active run's priority, or refresh the announcement
+    async fn announce_priority(&self, owner: Uuid, priority: Priority) -> Result<()>;
+
+    /// Withdraw a run's priority announcement when it ends
+    async fn withdraw_priority(&self, owner: Uuid) -> Result<()>;
+
+    /// Count runs of a higher priority whose announcement was refreshed
+    /// since `seen_after`
+    async fn higher_priority_runs(&self, priority: Priority, seen_after: DateTime<Utc>) -> Result<usize>;
+
+    /// Save the result of a step of an execution (overwrites)
+    async fn save_step_result(&self, execution_id: Uuid, result: &StepResult) -> Result<()>;
+
+    /// Load the step results of an execution, in the order they were first saved
+    async fn load_step_results(&self, execution_id: Uuid) -> Result<Vec<StepResult>>;
+
+    /// Executions matching a history search, newest first
+    async fn search_executions(&self, query: &HistoryQuery) -> Result<Vec<HistoryMatch>>;
+
+    /// Latest completed result of a step in the executions of a pipeline,
+    /// with the execution it belongs to
+    async fn last_completed_step(&self, pipeline_name: &str, step_id: &str) -> Result<Option<(Uuid, StepResult)>>;
+
+    /// Metrics of the finished executions matching `query`, per pipeline,
+    /// ordered by pipeline name
+    async fn pipeline_stats(&self, query: &StatsQuery) -> Result<Vec<PipelineStats>>;
+
+    /// Record a notification that could not be delivered
+    async fn save_dead_letter(&self, letter: &DeadLetter) -> Result<()>;
+
+    /// Load the most recent undelivered notifications, newest first
+    async fn load_dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>>;
+
+    /// Record a run started for a schedule
+    async fn save_scheduled_run(&self, run: &ScheduledRun) -> Result<()>;
+
+    /// Load the most recent runs started for `schedule`, newest first
+    async fn load_scheduled_runs(&self, schedule: &str, limit: usize) -> Result<Vec<ScheduledRun>>;
+
+    /// Record an artifact collected for an execution (overwrites one at the
+    /// same path)
+    async fn save_artifact(&self, execution_id: Uuid, artifact: &Artifact) -> Result<()>;
+
+    /// Load the artifacts of an execution, in the order they were first saved
+    async fn load_artifacts(&self, execution_id: Uuid) -> Result<Vec<Artifact>>;
+
+    /// Record a decision on one of an execution's tool calls
+    async fn save_tool_decision(&self, execution_id: Uuid, decision: &ToolDecision) -> Result<()>;
+
+    /// Load the tool call decisions of an execution, oldest first
+    async fn load_tool_decisions(&self, execution_id: Uuid) -> Result<Vec<ToolDecision>>;
+
+    /// Add items to the back of a named queue
+    async fn push_queue_items(&self, queue: &str, items: &[String], pushed_by: Option<Uuid>) -> Result<()>;
+
+    /// Take up to `limit` items from the front of a named queue
+    ///
+    /// Taken items are removed, so each is handed out once even when several
+    /// runs take from the queue at the same time.
+    async fn pop_queue_items(&self, queue: &str, limit: usize) -> Result<Vec<QueueItem>>;
+
+    /// Queues with items waiting, ordered by name
+    async fn list_queues(&self) -> Result<Vec<QueueSummary>>;
+}
+
+/// A pipeline's run lock, checked in every `poll_interval` while it is held
+///
+/// `release` gives the lock up. A lock that is dropped without being
+/// released (the run failed on its way out, or its process died) stops
+/// checking in and is taken over once it is `LOCK_STALE_AFTER` old.
+pub struct RunLock {
+    store: std::sync::Arc<dyn PersistenceBackend>,
+    pipeline_name: String,
+    owner: Uuid,
+    refresh: tokio::task::JoinHandle<()>,
+}
+
+impl RunLock {
+    fn hold(
+        store: std::sync::Arc<dyn PersistenceBackend>,
+        pipeline_name: &str,
+        owner: Uuid,
+        poll_interval: std::time::Duration,
+    ) -> Self {
+        let refresh = {
+            let (store, pipeline_name) = (store.clone(), pipeline_name.to_string());
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(poll_interval).await;
+                    match store.try_acquire_lock(&pipeline_name, owner, lock_seen_after()).await {
+                        Ok(true) => {}
+                        Ok(false) => tracing::warn!("Run lock of {} was taken over by another run", pipeline_name),
+                        Err(e) => tracing::debug!("Could not refresh the run lock of {}: {}", pipeline_name, e),
+                    }
+                }
+            })
+        };
+        Self {
+            store,
+            pipeline_name: pipeline_name.to_string(),
+            owner,
+            refresh,
+        }
+    }
+
+    /// Give the lock up
+    pub async fn release(self) -> Result<()> {
+        self.refresh.abort();
+        self.store.release_lock(&self.pipeline_name, self.owner).await
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        self.refresh.abort();
+    }
+}
+
+/// Holders and waiters last seen before this have lost the lock or their
+/// place in line
+fn lock_seen_after() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::from_std(LOCK_STALE_AFTER).unwrap_or_default()
+}
+
+/// Acquire the run lock, failing fast, waiting or waiting in line according
+/// to `policy`
+///
+/// `on_wait` is called once, before the first wait. With
+/// `LockConflictPolicy::Queue` the run takes its place in the line stored
+/// in `store`, so runs of any process take the lock in the order they
+/// asked for it. The lock (and a place in line) is kept by checking in
+/// every `poll_interval`, which must be well under `LOCK_STALE_AFTER`.
+pub async fn acquire_pipeline_lock(
+    store: std::sync::Arc<dyn PersistenceBackend>,
+    pipeline_name: &str,
+    owner: Uuid,
+    policy: LockConflictPolicy,
+    poll_interval: std::time::Duration,
+    on_wait: impl FnOnce(),
+) -> Result<RunLock> {
+    if policy == LockConflictPolicy::Queue {
+        let acquired = acquire_in_line(store.as_ref(), pipeline_name, owner, poll_interval, on_wait).await;
+        if acquired.is_err() {
+            let _ = store.stop_waiting_for_lock(pipeline_name, owner).await;
+        }
+        acquired?;
+        return Ok(RunLock::hold(store, pipeline_name, owner, poll_interval));
+    }
+
+    let mut on_wait = Some(on_wait);
+    loop {
+        if store.try_acquire_lock(pipeline_name, owner, lock_seen_after()).await? {
+            return Ok(RunLock::hold(store, pipeline_name, owner, poll_interval));
+        }
+        if policy == LockConflictPolicy::Fail {
+            anyhow::bail!(
+                "Pipeline '{}' is already running (locked by another execution)",
+                pipeline_name
+            );
+        }
+        if let Some(on_wait) = on_wait.take() {
+            on_wait();
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Wait in line for the run lock until `owner` is first and the lock is free
+async fn acquire_in_line(
+    store: &dyn PersistenceBackend,
+    pipeline_name: &str,
+    owner: Uuid,
+    poll_interval: std::time::Duration,
+    on_wait: impl FnOnce(),
+) -> Result<()> {
+    let mut on_wait = Some(on_wait);
+    loop {
+        store.wait_for_lock(pipeline_name, owner).await?;
+        let next = store.next_lock_waiter(pipeline_name, lock_seen_after()).await?;
+        if next == Some(owner) && store.try_acquire_lock(pipeline_name, owner, lock_seen_after()).await? {
+            store.stop_waiting_for_lock(pipeline_name, owner).await?;
+            return Ok(());
+        }
+        if let Some(on_wait) = on_wait.take() {
+            on_wait();
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+/// Enter a concurrency group, waiting for or superseding its active run
+///
+/// Returns the superseded run when `cancel_in_progress` is set.
+pub async fn join_concurrency_group(
+    store: &dyn PersistenceBackend,
+    group: &str,
+    owner: Uuid,
+    cancel_in_progress: bool,
+    poll_interval: std::time::Duration,
+) -> Result<Option<Uuid>> {
+    if cancel_in_progress {
+        return store.take_over_group(group, owner).await;
+    }
+    while !store.try_join_group(group, owner).await? {
+        tokio::time::sleep(poll_interval).await;
+    }
+    Ok(None)
+}
+
+/// Resolve once another run has taken over the group, returning that run
+pub async fn wait_until_superseded(
+    store: &dyn PersistenceBackend,
+    group: &str,
+    owner: Uuid,
+    poll_interval: std::time::Duration,
+) -> Result<Uuid> {
+    loop {
+        match store.group_holder(group).await? {
+            Some(holder) if holder != owner => return Ok(holder),
+            _ => tokio::time::sleep(poll_interval).await,
+        }
+    }
+}
+
+/// Step gate holding a run's steps back while runs of a higher priority
+/// are active
+///
+/// Runs announce their priority with `announce_priority` and refresh it at
+/// least every `HEARTBEAT_INTERVAL`; announcements older than `STALE_AFTER`
+/// (a crashed run) are ignored.
+pub struct PriorityGate {
+    store: std::sync::Arc<dyn PersistenceBackend>,
+    priority: Priority,
 }
 
+impl PriorityGate {
+    pub fn new(store: std::sync::Arc<dyn PersistenceBackend>, priority: Priority) -> Self {
+        Self { store, priority }
+    }
+}
+
+#[async_trait::async_trait]
+impl crate::execution::StepGate for PriorityGate {
+    async fn hold_reason(&self) -> Option<String> {
+        let seen_after = Utc::now() - chrono::Duration::from_std(STALE_AFTER).unwrap_or_default();
+        match self.store.higher_priority_runs(self.priority, seen_after).await {
+            Ok(0) => None,
+            Ok(count) => Some(format!("{} higher-priority run(s) to finish", count)),
+            Err(e) => {
+                tracing::debug!("Could not check run priorities: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Runs waiting for a pipeline's lock, first in line first, with when each
+/// last checked in
+type WaitingLine = Vec<(Uuid, DateTime<Utc>)>;
+
 /// In-memory persistence (for testing or ephemeral use)
 pub struct InMemoryPersistence {
     executions: tokio::sync::RwLock<std::collections::HashMap<Uuid, ExecutionSummary>>,
     by_pipeline: tokio::sync::RwLock<std::collections::HashMap<String, Vec<Uuid>>>,
+    /// Holder of each pipeline's run lock and when it last checked in
+    locks: tokio::sync::Mutex<std::collections::HashMap<String, (Uuid, DateTime<Utc>)>>,
+    lock_waiters: tokio::sync::Mutex<HashMap<String, WaitingLine>>,
+    memory: tokio::sync::RwLock<HashMap<String, HashMap<String, String>>>,
+    groups: tokio::sync::Mutex<HashMap<String, Uuid>>,
+    heartbeats: tokio::sync::Mutex<HashMap<Uuid, DateTime<Utc>>>,
+    priorities: tokio::sync::Mutex<HashMap<Uuid, (Priority, DateTime<Utc>)>>,
+    step_results: tokio::sync::RwLock<HashMap<Uuid, Vec<StepResult>>>,
+    dead_letters: tokio::sync::RwLock<Vec<DeadLetter>>,
+    scheduled_runs: tokio::sync::RwLock<Vec<ScheduledRun>>,
+    artifacts: tokio::sync::RwLock<HashMap<Uuid, Vec<Artifact>>>,
+    tool_decisions: tokio::sync::RwLock<HashMap<Uuid, Vec<ToolDecision>>>,
+    queues: tokio::sync::Mutex<std::collections::BTreeMap<String, std::collections::VecDeque<QueueItem>>>,
 }
 
 impl InMemoryPersistence {
@@ -71,6 +1205,18 @@ impl InMemoryPersistence {
         Self {
             executions: tokio::sync::RwLock::new(std::collections::HashMap::new()),
             by_pipeline: tokio::sync::RwLock::new(std::collections::HashMap::new()),
+            locks: tokio::sync::Mutex::new(std::collections::HashMap::new()),
+            lock_waiters: tokio::sync::Mutex::new(HashMap::new()),
+            memory: tokio::sync::RwLock::new(HashMap::new()),
+            groups: tokio::sync::Mutex::new(HashMap::new()),
+            heartbeats: tokio::sync::Mutex::new(HashMap::new()),
+            priorities: tokio::sync::Mutex::new(HashMap::new()),
+            step_results: tokio::sync::RwLock::new(HashMap::new()),
+            dead_letters: tokio::sync::RwLock::new(Vec::new()),
+            scheduled_runs: tokio::sync::RwLock::new(Vec::new()),
+            artifacts: tokio::sync::RwLock::new(HashMap::new()),
+            tool_decisions: tokio::sync::RwLock::new(HashMap::new()),
+            queues: tokio::sync::Mutex::new(std::collections::BTreeMap::new()),
         }
     }
 }
@@ -88,11 +1234,14 @@ impl PersistenceBackend for InMemoryPersistence {
         execs.insert(execution.execution_id, execution.clone());
 
         let mut by_pipeline = self.by_pipeline.write().await;
-        by_pipeline
+        let ids = by_pipeline
             .entry(execution.pipeline_name.clone())
-            .or_insert_with(Vec::new)
-            .push(execution.execution_id);
+            .or_insert_with(Vec::new);
+        if !ids.contains(&execution.execution_id) {
+            ids.push(execution.execution_id);
+        }
 
+        self.heartbeats.lock().await.insert(execution.execution_id, Utc::now());
         Ok(())
     }
 
@@ -125,6 +1274,330 @@ impl PersistenceBackend for InMemoryPersistence {
         let by_pipeline = self.by_pipeline.read().await;
         Ok(by_pipeline.keys().cloned().collect())
     }
+
+    async fn try_acquire_lock(&self, pipeline_name: &str, owner: Uuid, seen_after: DateTime<Utc>) -> Result<bool> {
+        let mut locks = self.locks.lock().await;
+        let (holder, seen_at) = locks.entry(pipeline_name.to_string()).or_insert((owner, Utc::now()));
+        if *holder == owner || *seen_at < seen_after {
+            (*holder, *seen_at) = (owner, Utc::now());
+        }
+        Ok(*holder == owner)
+    }
+
+    async fn release_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()> {
+        let mut locks = self.locks.lock().await;
+        if locks.get(pipeline_name).is_some_and(|(holder, _)| *holder == owner) {
+            locks.remove(pipeline_name);
+        }
+        Ok(())
+    }
+
+    async fn clear_lock(&self, pipeline_name: &str) -> Result<bool> {
+        Ok(self.locks.lock().await.remove(pipeline_name).is_some())
+    }
+
+    async fn wait_for_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()> {
+        let mut waiters = self.lock_waiters.lock().await;
+        let line = waiters.entry(pipeline_name.to_string()).or_default();
+        match line.iter_mut().find(|(waiter, _)| *waiter == owner) {
+            Some((_, seen_at)) => *seen_at = Utc::now(),
+            None => line.push((owner, Utc::now())),
+        }
+        Ok(())
+    }
+
+    async fn next_lock_waiter(&self, pipeline_name: &str, seen_after: DateTime<Utc>) -> Result<Option<Uuid>> {
+        let mut waiters = self.lock_waiters.lock().await;
+        let Some(line) = waiters.get_mut(pipeline_name) else {
+            return Ok(None);
+        };
+        line.retain(|(_, seen_at)| *seen_at >= seen_after);
+        Ok(line.first().map(|(waiter, _)| *waiter))
+    }
+
+    async fn stop_waiting_for_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()> {
+        if let Some(line) = self.lock_waiters.lock().await.get_mut(pipeline_name) {
+            line.retain(|(waiter, _)| *waiter != owner);
+        }
+        Ok(())
+    }
+
+    async fn heartbeat(&self, execution_id: Uuid) -> Result<()> {
+        self.heartbeats.lock().await.insert(execution_id, Utc::now());
+        Ok(())
+    }
+
+    async fn mark_orphaned(&self, stale_before: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        let heartbeats = self.heartbeats.lock().await;
+        let mut execs = self.executions.write().await;
+        let mut orphaned = Vec::new();
+        for exec in execs.values_mut() {
+            let last_seen = heartbeats.get(&exec.execution_id).copied().unwrap_or(exec.started_at);
+            if exec.status == ExecutionStatus::Running && last_seen < stale_before {
+                exec.status = ExecutionStatus::Orphaned;
+                exec.completed_at = Some(last_seen);
+                orphaned.push(exec.execution_id);
+            }
+        }
+        Ok(orphaned)
+    }
+
+    async fn load_memory(&self, pipeline_name: &str) -> Result<HashMap<String, String>> {
+        Ok(self.memory.read().await.get(pipeline_name).cloned().unwrap_or_default())
+    }
+
+    async fn save_memory(&self, pipeline_name: &str, key: &str, value: &str) -> Result<()> {
+        self.memory
+            .write()
+            .await
+            .entry(pipeline_name.to_string())
+            .or_default()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn try_join_group(&self, group: &str, owner: Uuid) -> Result<bool> {
+        let mut groups = self.groups.lock().await;
+        let holder = groups.entry(group.to_string()).or_insert(owner);
+        Ok(*holder == owner)
+    }
+
+    async fn take_over_group(&self, group: &str, owner: Uuid) -> Result<Option<Uuid>> {
+        let previous = self.groups.lock().await.insert(group.to_string(), owner);
+        Ok(previous.filter(|previous| *previous != owner))
+    }
+
+    async fn group_holder(&self, group: &str) -> Result<Option<Uuid>> {
+        Ok(self.groups.lock().await.get(group).copied())
+    }
+
+    async fn leave_group(&self, group: &str, owner: Uuid) -> Result<()> {
+        let mut groups = self.groups.lock().await;
+        if groups.get(group) == Some(&owner) {
+            groups.remove(group);
+        }
+        Ok(())
+    }
+
+    async fn announce_priority(&self, owner: Uuid, priority: Priority) -> Result<()> {
+        self.priorities.lock().await.insert(owner, (priority, Utc::now()));
+        Ok(())
+    }
+
+    async fn withdraw_priority(&self, owner: Uuid) -> Result<()> {
+        self.priorities.lock().await.remove(&owner);
+        Ok(())
+    }
+
+    async fn higher_priority_runs(&self, priority: Priority, seen_after: DateTime<Utc>) -> Result<usize> {
+        Ok(self
+            .priorities
+            .lock()
+            .await
+            .values()
+            .filter(|(other, seen_at)| *other > priority && *seen_at >= seen_after)
+            .count())
+    }
+
+    async fn save_step_result(&self, execution_id: Uuid, result: &StepResult) -> Result<()> {
+        let mut all = self.step_results.write().await;
+        let results = all.entry(execution_id).or_default();
+        match results.iter_mut().find(|r| r.step_id == result.step_id) {
+            Some(existing) => *existing = result.clone(),
+            None => results.push(result.clone()),
+        }
+        Ok(())
+    }
+
+    async fn load_step_results(&self, execution_id: Uuid) -> Result<Vec<StepResult>> {
+        Ok(self.step_results.read().await.get(&execution_id).cloned().unwrap_or_default())
+    }
+
+    async fn search_executions(&self, query: &HistoryQuery) -> Result<Vec<HistoryMatch>> {
+        let executions = self.executions.read().await;
+        let step_results = self.step_results.read().await;
+
+        let mut matches: Vec<HistoryMatch> = executions
+            .values()
+            .filter(|exec| query.pipeline.as_ref().is_none_or(|name| &exec.pipeline_name == name))
+            .filter(|exec| query.statuses.is_empty() || query.statuses.contains(&exec.status))
+            .filter(|exec| query.since.is_none_or(|since| exec.started_at >= since))
+            .filter_map(|exec| {
+                let matched_steps: Vec<String> = step_results
+                    .get(&exec.execution_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|result| query.step_matches(result))
+                    .map(|result| result.step_id.clone())
+                    .collect();
+                (query.contains.is_none() || !matched_steps.is_empty()).then(|| HistoryMatch {
+                    summary: exec.clone(),
+                    matched_steps,
+                })
+            })
+            .collect();
+        matches.sort_by_key(|found| std::cmp::Reverse(found.summary.started_at));
+        matches.truncate(query.limit);
+        Ok(matches)
+    }
+
+    async fn last_completed_step(&self, pipeline_name: &str, step_id: &str) -> Result<Option<(Uuid, StepResult)>> {
+        let executions = self.executions.read().await;
+        let step_results = self.step_results.read().await;
+        Ok(executions
+            .values()
+            .filter(|exec| exec.pipeline_name == pipeline_name)
+            .flat_map(|exec| {
+                step_results
+                    .get(&exec.execution_id)
+                    .into_iter()
+                    .flatten()
+                    .filter(|result| result.step_id == step_id && result.status == "completed")
+                    .map(|result| (exec.execution_id, result.clone()))
+            })
+            .max_by_key(|(_, result)| result.finished_at))
+    }
+
+    async fn pipeline_stats(&self, query: &StatsQuery) -> Result<Vec<PipelineStats>> {
+        let executions = self.executions.read().await;
+        let step_results = self.step_results.read().await;
+
+        let mut by_pipeline: std::collections::BTreeMap<&str, Vec<&ExecutionSummary>> = Default::default();
+        for exec in executions.values() {
+            if exec.status.is_finished()
+                && query.pipeline.as_ref().is_none_or(|name| &exec.pipeline_name == name)
+                && query.since.is_none_or(|since| exec.started_at >= since)
+            {
+                by_pipeline.entry(&exec.pipeline_name).or_default().push(exec);
+            }
+        }
+
+        Ok(by_pipeline
+            .into_iter()
+            .map(|(name, runs)| {
+                let results: Vec<&StepResult> = runs
+                    .iter()
+                    .flat_map(|run| step_results.get(&run.execution_id).into_iter().flatten())
+                    .collect();
+                let durations: Vec<f64> = runs
+                    .iter()
+                    .filter_map(|run| run.completed_at.map(|end| (end - run.started_at).num_milliseconds() as f64 / 1000.0))
+                    .collect();
+                let succeeded = runs.iter().filter(|run| run.status == ExecutionStatus::Completed).count();
+                let retries: usize = results.iter().map(|result| result.attempts.saturating_sub(1)).sum();
+                PipelineStats {
+                    pipeline_name: name.to_string(),
+                    runs: runs.len(),
+                    succeeded,
+                    success_rate: succeeded as f64 / runs.len() as f64,
+                    avg_duration_secs: (!durations.is_empty())
+                        .then(|| durations.iter().sum::<f64>() / durations.len() as f64),
+                    avg_retries: retries as f64 / runs.len() as f64,
+                    tokens: results.iter().map(|result| result.tokens).sum(),
+                    cost_usd: results.iter().map(|result| result.cost_usd).sum(),
+                }
+            })
+            .collect())
+    }
+
+    async fn save_dead_letter(&self, letter: &DeadLetter) -> Result<()> {
+        self.dead_letters.write().await.push(letter.clone());
+        Ok(())
+    }
+
+    async fn load_dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>> {
+        Ok(self.dead_letters.read().await.iter().rev().take(limit).cloned().collect())
+    }
+
+    async fn save_scheduled_run(&self, run: &ScheduledRun) -> Result<()> {
+        self.scheduled_runs.write().await.push(run.clone());
+        Ok(())
+    }
+
+    async fn load_scheduled_runs(&self, schedule: &str, limit: usize) -> Result<Vec<ScheduledRun>> {
+        Ok(self
+            .scheduled_runs
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|run| run.schedule == schedule)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn save_artifact(&self, execution_id: Uuid, artifact: &Artifact) -> Result<()> {
+        let mut all = self.artifacts.write().await;
+        let artifacts = all.entry(execution_id).or_default();
+        match artifacts.iter_mut().find(|a| a.path == artifact.path) {
+            Some(existing) => *existing = artifact.clone(),
+            None => artifacts.push(artifact.clone()),
+        }
+        Ok(())
+    }
+
+    async fn load_artifacts(&self, execution_id: Uuid) -> Result<Vec<Artifact>> {
+        Ok(self.artifacts.read().await.get(&execution_id).cloned().unwrap_or_default())
+    }
+
+    async fn save_tool_decision(&self, execution_id: Uuid, decision: &ToolDecision) -> Result<()> {
+        self.tool_decisions
+            .write()
+            .await
+            .entry(execution_id)
+            .or_default()
+            .push(decision.clone());
+        Ok(())
+    }
+
+    async fn load_tool_decisions(&self, execution_id: Uuid) -> Result<Vec<ToolDecision>> {
+        Ok(self.tool_decisions.read().await.get(&execution_id).cloned().unwrap_or_default())
+    }
+
+    async fn push_queue_items(&self, queue: &str, items: &[String], pushed_by: Option<Uuid>) -> Result<()> {
+        let pushed_at = Utc::now();
+        self.queues
+            .lock()
+            .await
+            .entry(queue.to_string())
+            .or_default()
+            .extend(items.iter().map(|payload| QueueItem {
+                queue: queue.to_string(),
+                payload: payload.clone(),
+                pushed_by,
+                pushed_at,
+            }));
+        Ok(())
+    }
+
+    async fn pop_queue_items(&self, queue: &str, limit: usize) -> Result<Vec<QueueItem>> {
+        let mut queues = self.queues.lock().await;
+        let Some(items) = queues.get_mut(queue) else {
+            return Ok(Vec::new());
+        };
+        let taken = items.drain(..limit.min(items.len())).collect();
+        if items.is_empty() {
+            queues.remove(queue);
+        }
+        Ok(taken)
+    }
+
+    async fn list_queues(&self) -> Result<Vec<QueueSummary>> {
+        Ok(self
+            .queues
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(queue, items)| {
+                items.front().map(|front| QueueSummary {
+                    queue: queue.clone(),
+                    items: items.len(),
+                    oldest_at: front.pushed_at,
+                })
+            })
+            .collect())
+    }
 }
 
 /// Create a summary from a pipeline
@@ -138,5 +1611,420 @@ pub fn create_summary(pipeline: &Pipeline) -> ExecutionSummary {
         progress: pipeline.state.progress(),
         completed_steps: pipeline.state.completed_steps,
         total_steps: pipeline.state.total_steps,
+        bytes_written: pipeline.state.total_bytes_written(),
+        outputs: pipeline.state.outputs.clone(),
+        seed: Some(pipeline.state.seed),
+        parent_execution_id: pipeline.state.parent_execution_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_dir_comes_from_env() {
+        // Anything another test saves meanwhile lands in a scratch directory
+        let scratch = tempfile::tempdir().unwrap();
+        std::env::set_var(DATA_DIR_ENV, scratch.path());
+        assert_eq!(data_dir_from_env(), Some(scratch.path().to_path_buf()));
+        if DATA_DIR_OVERRIDE.get().is_none() {
+            assert_eq!(data_dir(), scratch.path());
+        }
+
+        std::env::set_var(DATA_DIR_ENV, "");
+        assert_eq!(data_dir_from_env(), None);
+        std::env::remove_var(DATA_DIR_ENV);
+    }
+
+    #[tokio::test]
+    async fn test_lock_is_exclusive_until_released() {
+        let store = InMemoryPersistence::new();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let a_minute_ago = Utc::now() - chrono::Duration::minutes(1);
+
+        assert!(store.try_acquire_lock("p", first, a_minute_ago).await.unwrap());
+        assert!(store.try_acquire_lock("p", first, a_minute_ago).await.unwrap());
+        assert!(!store.try_acquire_lock("p", second, a_minute_ago).await.unwrap());
+
+        // Releasing with the wrong owner keeps the lock
+        store.release_lock("p", second).await.unwrap();
+        assert!(!store.try_acquire_lock("p", second, a_minute_ago).await.unwrap());
+
+        store.release_lock("p", first).await.unwrap();
+        assert!(store.try_acquire_lock("p", second, a_minute_ago).await.unwrap());
+
+        // A holder that stopped checking in loses the lock
+        let in_a_minute = Utc::now() + chrono::Duration::minutes(1);
+        assert!(store.try_acquire_lock("p", first, in_a_minute).await.unwrap());
+    }
+
+    #[test]
+    fn test_step_records_follow_execution_order() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "review"
+    name: "Review"
+    prompt: "Review"
+    depends_on: ["plan"]
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+"#;
+        let mut pipeline = crate::core::config::PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        pipeline.step_mut("plan").unwrap().state = StepState::Completed {
+            output: "done".to_string(),
+            attempts: 2,
+            started_at: Utc::now(),
+            completed_at: Utc::now(),
+        };
+        pipeline.state.record_bytes_written("plan", 10);
+
+        let records = StepRecord::from_pipeline(&pipeline);
+        assert_eq!(records[0].step_id, "plan");
+        assert_eq!(records[0].status, "completed");
+        assert_eq!(records[0].attempts, 2);
+        assert_eq!(records[0].bytes_written, 10);
+        assert_eq!(records[1].status, "pending");
+        assert_eq!(records[1].depends_on, vec!["plan".to_string()]);
+    }
+
+    #[test]
+    fn test_step_telemetry_limits_what_is_kept() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "audit"
+    name: "Audit"
+    prompt: "Audit the secrets"
+    telemetry: metadata
+  - id: "scan"
+    name: "Scan"
+    prompt: "Scan"
+    telemetry: none
+"#;
+        let mut pipeline = crate::core::config::PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        pipeline.step_mut("audit").unwrap().state = StepState::Completed {
+            output: "found hunter2".to_string(),
+            attempts: 1,
+            started_at: Utc::now(),
+            completed_at: Utc::now(),
+        };
+        pipeline.step_mut("scan").unwrap().state = StepState::Failed {
+            error: "leaked hunter2".to_string(),
+            attempts: 2,
+            last_started_at: Utc::now(),
+            failed_at: Utc::now(),
+        };
+        pipeline.state.record_bytes_written("audit", 10);
+        pipeline.state.record_bytes_written("scan", 10);
+
+        let records = StepRecord::from_pipeline(&pipeline);
+        assert_eq!(records[0].bytes_written, 10);
+        assert!(records[0].finished_at.is_some());
+        assert_eq!(records[1].status, "failed");
+        assert_eq!(records[1].error, None);
+        assert_eq!((records[1].attempts, records[1].bytes_written), (0, 0));
+        assert!(records[1].started_at.is_none());
+
+        let results = StepResult::from_pipeline(&pipeline);
+        assert_eq!(results[0].output, None);
+        assert_eq!(results[0].status, "completed");
+    }
+
+    #[test]
+    fn test_live_run_files_follow_events() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+"#;
+        let pipeline = crate::core::config::PipelineConfig::from_yaml(yaml).unwrap().to_pipeline();
+        let dir = tempfile::tempdir().unwrap();
+        let live = LiveRunFiles::create(dir.path().to_path_buf(), &pipeline).unwrap();
+
+        let started = ExecutionEvent::StepStarted { step_id: "plan".to_string(), attempt: 1 };
+        live.record(&started, "plan started").unwrap();
+        let (records, _) = load_run_files_from(dir.path()).unwrap();
+        assert_eq!(records[0].status, "running");
+
+        let output = ExecutionEvent::StepOutput { step_id: "plan".to_string(), output: "Plan: x".to_string() };
+        live.record(&output, "plan output").unwrap();
+        let completed = ExecutionEvent::StepCompleted { step_id: "plan".to_string(), next_step: None };
+        live.record(&completed, "plan completed").unwrap();
+
+        let (records, outputs) = load_run_files_from(dir.path()).unwrap();
+        assert_eq!(records[0].status, "completed");
+        assert_eq!(outputs["plan"], "Plan: x");
+        let log = std::fs::read_to_string(dir.path().join("events.log")).unwrap();
+        assert_eq!(log, "plan started\nplan output\nplan completed\n");
+    }
+
+    #[test]
+    fn test_restore_run_keeps_completed_steps() {
+        let yaml = r#"
+name: "Test Pipeline"
+steps:
+  - id: "plan"
+    name: "Plan"
+    prompt: "Plan"
+  - id: "build"
+    name: "Build"
+    prompt: "Build"
+    depends_on: ["plan"]
+"#;
+        let config = crate::core::config::PipelineConfig::from_yaml(yaml).unwrap();
+        let execution_id = Uuid::new_v4();
+        let runs = tempfile::tempdir().unwrap();
+        let dir = runs.path().join(execution_id.to_string());
+
+        // The crashed run completed plan and was in the middle of build
+        let crashed = config.to_pipeline();
+        let live = LiveRunFiles::create(dir.clone(), &crashed).unwrap();
+        for event in [
+            ExecutionEvent::StepStarted { step_id: "plan".to_string(), attempt: 1 },
+            ExecutionEvent::StepOutput { step_id: "plan".to_string(), output: "Plan: x".to_string() },
+            ExecutionEvent::StepCompleted { step_id: "plan".to_string(), next_step: None },
+            ExecutionEvent::StepStarted { step_id: "build".to_string(), attempt: 1 },
+        ] {
+            live.record(&event, "").unwrap();
+        }
+
+        let mut pipeline = config.to_pipeline();
+        let restored = restore_run(&mut pipeline, &dir).unwrap();
+
+        assert_eq!(restored, vec!["plan"]);
+        assert_eq!(pipeline.state.execution_id, execution_id);
+        assert!(pipeline.state.started_at.is_some());
+        match &pipeline.step("plan").unwrap().state {
+            StepState::Completed { output, .. } => assert_eq!(output, "Plan: x"),
+            other => panic!("expected completed step, got {:?}", other),
+        }
+        assert!(matches!(pipeline.step("build").unwrap().state, StepState::Pending));
+    }
+
+    #[test]
+    fn test_event_log_reader_returns_new_complete_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reader = EventLogReader::new(dir.path());
+        assert!(reader.read_new().unwrap().is_empty());
+
+        let log = dir.path().join("events.log");
+        std::fs::write(&log, "plan started\nplan comp").unwrap();
+        assert_eq!(reader.read_new().unwrap(), vec!["plan started"]);
+
+        std::fs::write(&log, "plan started\nplan completed\nreview started\n").unwrap();
+        assert_eq!(reader.read_new().unwrap(), vec!["plan completed", "review started"]);
+        assert!(reader.read_new().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cancel_request_escalates_but_never_softens() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(CancelRequest::read(dir.path()), None);
+
+        CancelRequest::AfterCurrent.send(dir.path()).unwrap();
+        assert_eq!(CancelRequest::read(dir.path()), Some(CancelRequest::AfterCurrent));
+        CancelRequest::Now.send(dir.path()).unwrap();
+        CancelRequest::AfterCurrent.send(dir.path()).unwrap();
+        assert_eq!(CancelRequest::read(dir.path()), Some(CancelRequest::Now));
+
+        CancelRequest::clear(dir.path());
+        assert_eq!(CancelRequest::read(dir.path()), None);
+    }
+
+    #[tokio::test]
+    async fn test_memory_is_scoped_per_pipeline() {
+        let store = InMemoryPersistence::new();
+        store.save_memory("a", "last_commit", "abc123").await.unwrap();
+        store.save_memory("a", "last_commit", "def456").await.unwrap();
+        store.save_memory("b", "other", "x").await.unwrap();
+
+        let memory = store.load_memory("a").await.unwrap();
+        assert_eq!(memory.len(), 1);
+        assert_eq!(memory["last_commit"], "def456");
+        assert!(store.load_memory("missing").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_new_run_supersedes_group() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let poll = std::time::Duration::from_millis(5);
+
+        assert_eq!(join_concurrency_group(store.as_ref(), "pr-1", first, true, poll).await.unwrap(), None);
+
+        let watcher = store.clone();
+        let superseded = tokio::spawn(async move {
+            wait_until_superseded(watcher.as_ref(), "pr-1", first, poll).await.unwrap()
+        });
+
+        assert_eq!(
+            join_concurrency_group(store.as_ref(), "pr-1", second, true, poll).await.unwrap(),
+            Some(first)
+        );
+        assert_eq!(superseded.await.unwrap(), second);
+
+        // The superseded run leaving must not evict the new one
+        store.leave_group("pr-1", first).await.unwrap();
+        assert_eq!(store.group_holder("pr-1").await.unwrap(), Some(second));
+    }
+
+    #[tokio::test]
+    async fn test_group_without_cancel_waits() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        let first = Uuid::new_v4();
+        let poll = std::time::Duration::from_millis(5);
+        join_concurrency_group(store.as_ref(), "pr-1", first, false, poll).await.unwrap();
+
+        let leaver = store.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+            leaver.leave_group("pr-1", first).await.unwrap();
+        });
+
+        let second = Uuid::new_v4();
+        join_concurrency_group(store.as_ref(), "pr-1", second, false, poll).await.unwrap();
+        assert_eq!(store.group_holder("pr-1").await.unwrap(), Some(second));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_pipeline_lock_fail_fast() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        store.try_acquire_lock("p", Uuid::new_v4(), Utc::now()).await.unwrap();
+
+        let result = acquire_pipeline_lock(
+            store,
+            "p",
+            Uuid::new_v4(),
+            LockConflictPolicy::Fail,
+            std::time::Duration::from_millis(10),
+            || {},
+        )
+        .await;
+
+        assert!(result.err().unwrap().to_string().contains("already running"));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_pipeline_lock_waits_for_release() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        let holder = Uuid::new_v4();
+        store.try_acquire_lock("p", holder, Utc::now()).await.unwrap();
+
+        let releaser = store.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(30)).await;
+            releaser.release_lock("p", holder).await.unwrap();
+        });
+
+        acquire_pipeline_lock(
+            store,
+            "p",
+            Uuid::new_v4(),
+            LockConflictPolicy::Wait,
+            std::time::Duration::from_millis(5),
+            || {},
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_acquire_pipeline_lock_queue_is_first_come_first_served() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        let holder = Uuid::new_v4();
+        store.try_acquire_lock("p", holder, Utc::now()).await.unwrap();
+
+        // Line up in order, then start the waiters in reverse so that only
+        // their place in line decides who goes first
+        let waiters: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for waiter in &waiters {
+            store.wait_for_lock("p", *waiter).await.unwrap();
+        }
+        let order = std::sync::Arc::new(tokio::sync::Mutex::new(Vec::new()));
+        let mut tasks = Vec::new();
+        for waiter in waiters.iter().rev().copied() {
+            let (store, order) = (store.clone(), order.clone());
+            tasks.push(tokio::spawn(async move {
+                let poll = std::time::Duration::from_millis(2);
+                let lock = acquire_pipeline_lock(store.clone(), "p", waiter, LockConflictPolicy::Queue, poll, || {})
+                    .await
+                    .unwrap();
+                order.lock().await.push(waiter);
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                lock.release().await.unwrap();
+            }));
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        store.release_lock("p", holder).await.unwrap();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(*order.lock().await, waiters);
+        assert_eq!(store.next_lock_waiter("p", Utc::now() - chrono::Duration::minutes(1)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_pipeline_lock_queue_skips_stale_waiters() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        let crashed = Uuid::new_v4();
+        store.wait_for_lock("p", crashed).await.unwrap();
+        store.lock_waiters.lock().await.get_mut("p").unwrap()[0].1 -=
+            chrono::Duration::from_std(LOCK_STALE_AFTER).unwrap();
+
+        let mut waited = false;
+        acquire_pipeline_lock(
+            store,
+            "p",
+            Uuid::new_v4(),
+            LockConflictPolicy::Queue,
+            std::time::Duration::from_millis(5),
+            || waited = true,
+        )
+        .await
+        .unwrap();
+        assert!(!waited);
+    }
+
+    /// Move a held lock's last check-in back past `LOCK_STALE_AFTER`
+    async fn age_lock(store: &InMemoryPersistence, pipeline_name: &str) {
+        store.locks.lock().await.get_mut(pipeline_name).unwrap().1 -=
+            chrono::Duration::from_std(LOCK_STALE_AFTER).unwrap() + chrono::Duration::seconds(1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_does_not_keep_the_lock() {
+        let store = std::sync::Arc::new(InMemoryPersistence::new());
+        let poll = std::time::Duration::from_millis(5);
+        let acquire = |policy| acquire_pipeline_lock(store.clone(), "p", Uuid::new_v4(), policy, poll, || {});
+
+        // A run that fails without releasing its lock stops checking in, so
+        // the lock expires instead of blocking the pipeline for good
+        let failed: Result<()> = async {
+            let _lock = acquire(LockConflictPolicy::Fail).await?;
+            anyhow::bail!("concurrency group unavailable")
+        }
+        .await;
+        assert!(failed.is_err());
+        assert!(acquire(LockConflictPolicy::Fail).await.is_err());
+        age_lock(&store, "p").await;
+        acquire(LockConflictPolicy::Fail).await.unwrap().release().await.unwrap();
+
+        // A live holder keeps checking in and keeps its lock
+        let held = acquire(LockConflictPolicy::Fail).await.unwrap();
+        age_lock(&store, "p").await;
+        tokio::time::sleep(poll * 4).await;
+        assert!(acquire(LockConflictPolicy::Fail).await.is_err());
+
+        held.release().await.unwrap();
+        acquire(LockConflictPolicy::Fail).await.unwrap();
     }
 }
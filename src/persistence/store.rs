@@ -1,11 +1,17 @@
 //! SQLite-based persistence store
 
-use crate::persistence::{PersistenceBackend, ExecutionSummary};
+use crate::core::config::Priority;
+use crate::persistence::{migrations, Artifact, DeadLetter, ExecutionStatus, HistoryMatch, HistoryQuery, PersistenceBackend, ExecutionSummary, PipelineStats, QueueItem, QueueSummary, ScheduledRun, StatsQuery, StepResult, ToolDecision};
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc, NaiveDateTime};
-use sqlx::{SqlitePool, Row};
+use sqlx::sqlite::{Sqlite, SqliteConnectOptions, SqliteRow};
+use sqlx::{QueryBuilder, SqlitePool, Row};
+use std::collections::HashMap;
 use uuid::Uuid;
 
+/// Step outputs at least this large are stored zstd-compressed
+const COMPRESS_OUTPUT_BYTES: usize = 16 * 1024;
+
 /// SQLite execution store
 pub struct SqliteExecutionStore {
     pool: SqlitePool,
@@ -14,7 +20,12 @@ pub struct SqliteExecutionStore {
 impl SqliteExecutionStore {
     /// Create a new SQLite store
     pub async fn new(db_path: &str) -> Result<Self> {
-        let pool = SqlitePool::connect(&format!("sqlite:{}", db_path))
+        let options = format!("sqlite:{}", db_path)
+            .parse::<SqliteConnectOptions>()
+            .context("Invalid database path")?
+            // A fresh data directory has no database yet
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options)
             .await
             .context("Failed to connect to database")?;
 
@@ -26,9 +37,7 @@ impl SqliteExecutionStore {
 
     /// Create store with default path
     pub async fn with_default_path() -> Result<Self> {
-        let data_dir = dirs::data_local_dir()
-            .unwrap_or_else(|| std::path::PathBuf::from("."));
-        let db_dir = data_dir.join("pipeline");
+        let db_dir = crate::persistence::data_dir();
         std::fs::create_dir_all(&db_dir)?;
 
         let db_path = db_dir.join("executions.db");
@@ -37,28 +46,7 @@ impl SqliteExecutionStore {
 
     /// Initialize database schema
     async fn init(&self) -> Result<()> {
-        sqlx::query(
-            r#"
-            CREATE TABLE IF NOT EXISTS executions (
-                id TEXT PRIMARY KEY,
-                pipeline_name TEXT NOT NULL,
-                status TEXT NOT NULL,
-                started_at TEXT NOT NULL,
-                completed_at TEXT,
-                progress REAL NOT NULL DEFAULT 0.0,
-                completed_steps INTEGER NOT NULL DEFAULT 0,
-                total_steps INTEGER NOT NULL DEFAULT 0,
-                created_at TEXT NOT NULL DEFAULT (datetime('now'))
-            );
-
-            CREATE INDEX IF NOT EXISTS idx_pipeline_name ON executions(pipeline_name);
-            CREATE INDEX IF NOT EXISTS idx_status ON executions(status);
-            CREATE INDEX IF NOT EXISTS idx_started_at ON executions(started_at);
-            "#,
-        )
-        .execute(&self.pool)
-        .await?;
-
+        migrations::migrate(&self.pool).await?;
         Ok(())
     }
 
@@ -71,6 +59,49 @@ impl SqliteExecutionStore {
     fn from_naive(dt: NaiveDateTime) -> DateTime<Utc> {
         DateTime::from_naive_utc_and_offset(dt, Utc)
     }
+
+    /// Step result from a row of the `step_results` table
+    fn step_result_from_row(row: &SqliteRow) -> Result<StepResult> {
+        let output = match row.get::<Option<Vec<u8>>, _>("output_zstd") {
+            Some(compressed) => {
+                let bytes = zstd::decode_all(compressed.as_slice()).context("Failed to decompress step output")?;
+                Some(String::from_utf8(bytes).context("Step output is not UTF-8")?)
+            }
+            None => row.get("output"),
+        };
+        Ok(StepResult {
+            step_id: row.get("step_id"),
+            status: row.get("status"),
+            output,
+            error: row.get("error"),
+            attempts: row.get::<i64, _>("attempts") as usize,
+            started_at: row.get::<Option<NaiveDateTime>, _>("started_at").map(Self::from_naive),
+            finished_at: row.get::<Option<NaiveDateTime>, _>("finished_at").map(Self::from_naive),
+            tokens: row.get::<i64, _>("tokens") as u64,
+            cost_usd: row.get("cost_usd"),
+            input_digest: row.get("input_digest"),
+        })
+    }
+
+    /// Execution summary from a row of the `executions` table
+    fn summary_from_row(row: &SqliteRow) -> Result<ExecutionSummary> {
+        Ok(ExecutionSummary {
+            execution_id: Uuid::parse_str(&row.get::<String, _>("id"))?,
+            pipeline_name: row.get("pipeline_name"),
+            status: row.get::<String, _>("status").parse().unwrap_or(ExecutionStatus::Pending),
+            started_at: Self::from_naive(row.get("started_at")),
+            completed_at: row.get::<Option<NaiveDateTime>, _>("completed_at").map(Self::from_naive),
+            progress: row.get("progress"),
+            completed_steps: row.get::<i64, _>("completed_steps") as usize,
+            total_steps: row.get::<i64, _>("total_steps") as usize,
+            bytes_written: row.get::<i64, _>("bytes_written") as u64,
+            outputs: serde_json::from_str(&row.get::<String, _>("outputs")).unwrap_or_default(),
+            seed: row.get::<Option<i64>, _>("seed").map(|seed| seed as u32),
+            parent_execution_id: row
+                .get::<Option<String>, _>("parent_execution_id")
+                .and_then(|id| Uuid::parse_str(&id).ok()),
+        })
+    }
 }
 
 #[async_trait::async_trait]
@@ -79,8 +110,8 @@ impl PersistenceBackend for SqliteExecutionStore {
         sqlx::query(
             r#"
             INSERT OR REPLACE INTO executions
-            (id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps)
-            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            (id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps, bytes_written, outputs, heartbeat_at, seed, parent_execution_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
             "#,
         )
         .bind(execution.execution_id.to_string())
@@ -91,6 +122,11 @@ impl PersistenceBackend for SqliteExecutionStore {
         .bind(execution.progress)
         .bind(execution.completed_steps as i64)
         .bind(execution.total_steps as i64)
+        .bind(execution.bytes_written as i64)
+        .bind(serde_json::to_string(&execution.outputs)?)
+        .bind(Self::to_naive(Utc::now()))
+        .bind(execution.seed.map(i64::from))
+        .bind(execution.parent_execution_id.map(|id| id.to_string()))
         .execute(&self.pool)
         .await
         .context("Failed to save execution")?;
@@ -101,7 +137,7 @@ impl PersistenceBackend for SqliteExecutionStore {
     async fn load_execution(&self, execution_id: Uuid) -> Result<Option<ExecutionSummary>> {
         let row = sqlx::query(
             r#"
-            SELECT id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps
+            SELECT id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps, bytes_written, outputs, seed, parent_execution_id
             FROM executions
             WHERE id = ?1
             "#,
@@ -111,28 +147,7 @@ impl PersistenceBackend for SqliteExecutionStore {
         .await
         .context("Failed to load execution")?;
 
-        if let Some(row) = row {
-            Ok(Some(ExecutionSummary {
-                execution_id: Uuid::parse_str(&row.get::<String, _>("id"))?,
-                pipeline_name: row.get("pipeline_name"),
-                status: match row.get::<String, _>("status").as_str() {
-                    "Pending" => crate::core::ExecutionStatus::Pending,
-                    "Running" => crate::core::ExecutionStatus::Running,
-                    "Completed" => crate::core::ExecutionStatus::Completed,
-                    "Failed" => crate::core::ExecutionStatus::Failed,
-                    "Cancelled" => crate::core::ExecutionStatus::Cancelled,
-                    "Paused" => crate::core::ExecutionStatus::Paused,
-                    _ => crate::core::ExecutionStatus::Pending,
-                },
-                started_at: Self::from_naive(row.get("started_at")),
-                completed_at: row.get::<Option<NaiveDateTime>, _>("completed_at").map(Self::from_naive),
-                progress: row.get("progress"),
-                completed_steps: row.get::<i64, _>("completed_steps") as usize,
-                total_steps: row.get::<i64, _>("total_steps") as usize,
-            }))
-        } else {
-            Ok(None)
-        }
+        row.as_ref().map(Self::summary_from_row).transpose()
     }
 
     async fn list_executions(
@@ -141,7 +156,7 @@ impl PersistenceBackend for SqliteExecutionStore {
     ) -> Result<Vec<ExecutionSummary>> {
         let rows = sqlx::query(
             r#"
-            SELECT id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps
+            SELECT id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps, bytes_written, outputs, seed, parent_execution_id
             FROM executions
             WHERE pipeline_name = ?1
             ORDER BY started_at DESC
@@ -152,43 +167,804 @@ impl PersistenceBackend for SqliteExecutionStore {
         .await
         .context("Failed to list executions")?;
 
+        rows.iter().map(Self::summary_from_row).collect()
+    }
+
+    async fn list_pipelines(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT DISTINCT pipeline_name
+            FROM executions
+            ORDER BY pipeline_name ASC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list pipelines")?;
+
+        Ok(rows.iter().map(|row| row.get("pipeline_name")).collect())
+    }
+
+    async fn try_acquire_lock(&self, pipeline_name: &str, owner: Uuid, seen_after: DateTime<Utc>) -> Result<bool> {
+        // Locks taken before check-ins were recorded count from when they
+        // were acquired
+        sqlx::query(
+            r#"
+            INSERT INTO pipeline_locks (pipeline_name, execution_id, seen_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (pipeline_name) DO UPDATE SET
+                acquired_at = CASE
+                    WHEN execution_id = excluded.execution_id THEN acquired_at
+                    ELSE datetime('now')
+                END,
+                execution_id = excluded.execution_id,
+                seen_at = excluded.seen_at
+            WHERE execution_id = excluded.execution_id OR COALESCE(seen_at, acquired_at) < ?
+            "#,
+        )
+        .bind(pipeline_name)
+        .bind(owner.to_string())
+        .bind(Self::to_naive(Utc::now()))
+        .bind(Self::to_naive(seen_after))
+        .execute(&self.pool)
+        .await
+        .context("Failed to acquire pipeline lock")?;
+
+        let holder: Option<String> = sqlx::query_scalar(
+            "SELECT execution_id FROM pipeline_locks WHERE pipeline_name = ?",
+        )
+        .bind(pipeline_name)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read pipeline lock")?;
+
+        Ok(holder.as_deref() == Some(owner.to_string().as_str()))
+    }
+
+    async fn release_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM pipeline_locks WHERE pipeline_name = ? AND execution_id = ?")
+            .bind(pipeline_name)
+            .bind(owner.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to release pipeline lock")?;
+
+        Ok(())
+    }
+
+    async fn clear_lock(&self, pipeline_name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM pipeline_locks WHERE pipeline_name = ?")
+            .bind(pipeline_name)
+            .execute(&self.pool)
+            .await
+            .context("Failed to clear pipeline lock")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn wait_for_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO lock_waiters (pipeline_name, execution_id, seen_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (pipeline_name, execution_id)
+            DO UPDATE SET seen_at = excluded.seen_at
+            "#,
+        )
+        .bind(pipeline_name)
+        .bind(owner.to_string())
+        .bind(Self::to_naive(Utc::now()))
+        .execute(&self.pool)
+        .await
+        .context("Failed to join the lock waiting line")?;
+
+        Ok(())
+    }
+
+    async fn next_lock_waiter(&self, pipeline_name: &str, seen_after: DateTime<Utc>) -> Result<Option<Uuid>> {
+        sqlx::query("DELETE FROM lock_waiters WHERE pipeline_name = ? AND seen_at < ?")
+            .bind(pipeline_name)
+            .bind(Self::to_naive(seen_after))
+            .execute(&self.pool)
+            .await
+            .context("Failed to drop stale lock waiters")?;
+
+        let next: Option<String> = sqlx::query_scalar(
+            "SELECT execution_id FROM lock_waiters WHERE pipeline_name = ? ORDER BY id LIMIT 1",
+        )
+        .bind(pipeline_name)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read the lock waiting line")?;
+
+        next.map(|id| Uuid::parse_str(&id).context("Invalid execution ID in lock waiting line"))
+            .transpose()
+    }
+
+    async fn stop_waiting_for_lock(&self, pipeline_name: &str, owner: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM lock_waiters WHERE pipeline_name = ? AND execution_id = ?")
+            .bind(pipeline_name)
+            .bind(owner.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to leave the lock waiting line")?;
+
+        Ok(())
+    }
+
+    async fn heartbeat(&self, execution_id: Uuid) -> Result<()> {
+        sqlx::query("UPDATE executions SET heartbeat_at = ? WHERE id = ?")
+            .bind(Self::to_naive(Utc::now()))
+            .bind(execution_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to record heartbeat")?;
+
+        Ok(())
+    }
+
+    async fn mark_orphaned(&self, stale_before: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        let rows = sqlx::query(
+            "SELECT id, COALESCE(heartbeat_at, started_at) AS last_seen FROM executions WHERE status = 'Running'",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list running executions")?;
+
+        let mut orphaned = Vec::new();
+        for row in rows {
+            let last_seen = Self::from_naive(row.get("last_seen"));
+            if last_seen >= stale_before {
+                continue;
+            }
+            let id: String = row.get("id");
+            sqlx::query("UPDATE executions SET status = 'Orphaned', completed_at = ? WHERE id = ? AND status = 'Running'")
+                .bind(Self::to_naive(last_seen))
+                .bind(&id)
+                .execute(&self.pool)
+                .await
+                .context("Failed to mark execution orphaned")?;
+            orphaned.push(Uuid::parse_str(&id)?);
+        }
+
+        Ok(orphaned)
+    }
+
+    async fn load_memory(&self, pipeline_name: &str) -> Result<HashMap<String, String>> {
+        let rows = sqlx::query("SELECT key, value FROM pipeline_memory WHERE pipeline_name = ?")
+            .bind(pipeline_name)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to load pipeline memory")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("key"), row.get("value")))
+            .collect())
+    }
+
+    async fn save_memory(&self, pipeline_name: &str, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO pipeline_memory (pipeline_name, key, value, updated_at)
+            VALUES (?, ?, ?, datetime('now'))
+            ON CONFLICT (pipeline_name, key)
+            DO UPDATE SET value = excluded.value, updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(pipeline_name)
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await
+        .context("Failed to save pipeline memory")?;
+
+        Ok(())
+    }
+
+    async fn try_join_group(&self, group: &str, owner: Uuid) -> Result<bool> {
+        sqlx::query("INSERT OR IGNORE INTO concurrency_groups (group_name, execution_id) VALUES (?, ?)")
+            .bind(group)
+            .bind(owner.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to join concurrency group")?;
+
+        Ok(self.group_holder(group).await? == Some(owner))
+    }
+
+    async fn take_over_group(&self, group: &str, owner: Uuid) -> Result<Option<Uuid>> {
+        let mut tx = self.pool.begin().await?;
+
+        let previous: Option<String> =
+            sqlx::query_scalar("SELECT execution_id FROM concurrency_groups WHERE group_name = ?")
+                .bind(group)
+                .fetch_optional(&mut *tx)
+                .await
+                .context("Failed to read concurrency group")?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO concurrency_groups (group_name, execution_id, joined_at)
+            VALUES (?, ?, datetime('now'))
+            ON CONFLICT (group_name)
+            DO UPDATE SET execution_id = excluded.execution_id, joined_at = excluded.joined_at
+            "#,
+        )
+        .bind(group)
+        .bind(owner.to_string())
+        .execute(&mut *tx)
+        .await
+        .context("Failed to take over concurrency group")?;
+
+        tx.commit().await?;
+
+        Ok(previous
+            .and_then(|id| Uuid::parse_str(&id).ok())
+            .filter(|previous| *previous != owner))
+    }
+
+    async fn group_holder(&self, group: &str) -> Result<Option<Uuid>> {
+        let holder: Option<String> =
+            sqlx::query_scalar("SELECT execution_id FROM concurrency_groups WHERE group_name = ?")
+                .bind(group)
+                .fetch_optional(&self.pool)
+                .await
+                .context("Failed to read concurrency group")?;
+
+        Ok(holder.and_then(|id| Uuid::parse_str(&id).ok()))
+    }
+
+    async fn leave_group(&self, group: &str, owner: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM concurrency_groups WHERE group_name = ? AND execution_id = ?")
+            .bind(group)
+            .bind(owner.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to leave concurrency group")?;
+
+        Ok(())
+    }
+
+    async fn announce_priority(&self, owner: Uuid, priority: Priority) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO run_priorities (execution_id, priority, seen_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (execution_id)
+            DO UPDATE SET priority = excluded.priority, seen_at = excluded.seen_at
+            "#,
+        )
+        .bind(owner.to_string())
+        .bind(priority.rank())
+        .bind(Self::to_naive(Utc::now()))
+        .execute(&self.pool)
+        .await
+        .context("Failed to announce run priority")?;
+
+        Ok(())
+    }
+
+    async fn withdraw_priority(&self, owner: Uuid) -> Result<()> {
+        sqlx::query("DELETE FROM run_priorities WHERE execution_id = ?")
+            .bind(owner.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to withdraw run priority")?;
+
+        Ok(())
+    }
+
+    async fn higher_priority_runs(&self, priority: Priority, seen_after: DateTime<Utc>) -> Result<usize> {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM run_priorities WHERE priority > ? AND seen_at >= ?")
+            .bind(priority.rank())
+            .bind(Self::to_naive(seen_after))
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count higher-priority runs")?;
+
+        Ok(count as usize)
+    }
+
+    async fn save_step_result(&self, execution_id: Uuid, result: &StepResult) -> Result<()> {
+        let (output, output_zstd) = match &result.output {
+            Some(output) if output.len() >= COMPRESS_OUTPUT_BYTES => {
+                let compressed = zstd::encode_all(output.as_bytes(), 0).context("Failed to compress step output")?;
+                (None, Some(compressed))
+            }
+            output => (output.clone(), None),
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let rowid: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO step_results (execution_id, step_id, status, output, output_zstd, error, attempts, started_at, finished_at, tokens, cost_usd, input_digest)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (execution_id, step_id)
+            DO UPDATE SET status = excluded.status, output = excluded.output, output_zstd = excluded.output_zstd,
+                error = excluded.error, attempts = excluded.attempts, started_at = excluded.started_at,
+                finished_at = excluded.finished_at, tokens = excluded.tokens, cost_usd = excluded.cost_usd,
+                input_digest = excluded.input_digest
+            RETURNING rowid
+            "#,
+        )
+        .bind(execution_id.to_string())
+        .bind(&result.step_id)
+        .bind(&result.status)
+        .bind(output)
+        .bind(output_zstd)
+        .bind(&result.error)
+        .bind(result.attempts as i64)
+        .bind(result.started_at.map(Self::to_naive))
+        .bind(result.finished_at.map(Self::to_naive))
+        .bind(result.tokens as i64)
+        .bind(result.cost_usd)
+        .bind(&result.input_digest)
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to save step result")?;
+
+        // The search index gets the text whether or not the output was compressed
+        sqlx::query("DELETE FROM step_results_fts WHERE rowid = ?")
+            .bind(rowid)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to index step result")?;
+        sqlx::query("INSERT INTO step_results_fts (rowid, step_id, output, error) VALUES (?, ?, ?, ?)")
+            .bind(rowid)
+            .bind(&result.step_id)
+            .bind(&result.output)
+            .bind(&result.error)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to index step result")?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn load_step_results(&self, execution_id: Uuid) -> Result<Vec<StepResult>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT step_id, status, output, output_zstd, error, attempts, started_at, finished_at, tokens, cost_usd,
+                input_digest
+            FROM step_results
+            WHERE execution_id = ?
+            ORDER BY rowid
+            "#,
+        )
+        .bind(execution_id.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load step results")?;
+
+        rows.iter().map(Self::step_result_from_row).collect()
+    }
+
+    async fn last_completed_step(&self, pipeline_name: &str, step_id: &str) -> Result<Option<(Uuid, StepResult)>> {
+        let row = sqlx::query(
+            r#"
+            SELECT step_results.execution_id, step_id, step_results.status, output, output_zstd, error, attempts,
+                step_results.started_at, finished_at, tokens, cost_usd, input_digest
+            FROM step_results JOIN executions ON executions.id = step_results.execution_id
+            WHERE pipeline_name = ? AND step_id = ? AND step_results.status = 'completed'
+            ORDER BY finished_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(pipeline_name)
+        .bind(step_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to load step result")?;
+
+        row.map(|row| {
+            let execution_id = Uuid::parse_str(&row.get::<String, _>("execution_id"))?;
+            Ok((execution_id, Self::step_result_from_row(&row)?))
+        })
+        .transpose()
+    }
+
+    async fn search_executions(&self, query: &HistoryQuery) -> Result<Vec<HistoryMatch>> {
+        let mut sql = QueryBuilder::<Sqlite>::new("");
+        if let Some(text) = &query.contains {
+            // Step results containing the text, found through the trigram
+            // index when the text is long enough for it (3 characters);
+            // shorter text is matched in the table, where compressed outputs
+            // can't be read
+            sql.push("WITH matches AS (SELECT execution_id, step_id, rowid AS position FROM step_results WHERE ");
+            if text.chars().count() >= 3 {
+                sql.push("rowid IN (SELECT rowid FROM step_results_fts WHERE step_results_fts MATCH ")
+                    .push_bind(format!("\"{}\"", text.replace('"', "\"\"")))
+                    .push(")");
+            } else {
+                let pattern = format!(
+                    "%{}%",
+                    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+                );
+                for (index, column) in ["step_id", "output", "error"].iter().enumerate() {
+                    sql.push(if index == 0 { "(" } else { " OR " })
+                        .push(column)
+                        .push(" LIKE ")
+                        .push_bind(pattern.clone())
+                        .push(" ESCAPE '\\'");
+                }
+                sql.push(")");
+            }
+            sql.push(") ");
+        }
+
+        sql.push(
+            "SELECT id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps, bytes_written, outputs, seed, parent_execution_id",
+        );
+        if query.contains.is_some() {
+            sql.push(
+                ", (SELECT group_concat(step_id, char(10)) FROM \
+                 (SELECT step_id FROM matches WHERE execution_id = executions.id ORDER BY position)) AS matched_steps",
+            );
+        }
+        sql.push(" FROM executions WHERE 1 = 1");
+        if let Some(pipeline_name) = &query.pipeline {
+            sql.push(" AND pipeline_name = ").push_bind(pipeline_name.clone());
+        }
+        if !query.statuses.is_empty() {
+            sql.push(" AND status IN (");
+            let mut statuses = sql.separated(", ");
+            for status in &query.statuses {
+                statuses.push_bind(format!("{:?}", status));
+            }
+            sql.push(")");
+        }
+        if let Some(since) = query.since {
+            sql.push(" AND started_at >= ").push_bind(Self::to_naive(since));
+        }
+        if query.contains.is_some() {
+            sql.push(" AND id IN (SELECT execution_id FROM matches)");
+        }
+        sql.push(" ORDER BY started_at DESC LIMIT ").push_bind(query.limit as i64);
+
+        let rows = sql
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to search executions")?;
+
         rows.iter()
             .map(|row| {
-                Ok(ExecutionSummary {
-                    execution_id: Uuid::parse_str(&row.get::<String, _>("id"))?,
+                let matched_steps = match query.contains {
+                    Some(_) => row
+                        .get::<Option<String>, _>("matched_steps")
+                        .map(|steps| steps.lines().map(str::to_string).collect())
+                        .unwrap_or_default(),
+                    None => Vec::new(),
+                };
+                Ok(HistoryMatch {
+                    summary: Self::summary_from_row(row)?,
+                    matched_steps,
+                })
+            })
+            .collect()
+    }
+
+    async fn pipeline_stats(&self, query: &StatsQuery) -> Result<Vec<PipelineStats>> {
+        // Step usage is summed per run first, so runs without step results
+        // still count towards the run averages
+        let mut sql = QueryBuilder::<Sqlite>::new(
+            "SELECT pipeline_name, COUNT(*) AS runs, SUM(status = ",
+        );
+        sql.push_bind(format!("{:?}", ExecutionStatus::Completed))
+            .push(
+                ") AS succeeded, \
+                 AVG((julianday(completed_at) - julianday(started_at)) * 86400.0) AS avg_duration_secs, \
+                 AVG(COALESCE(usage.retries, 0)) AS avg_retries, \
+                 SUM(COALESCE(usage.tokens, 0)) AS tokens, \
+                 TOTAL(usage.cost_usd) AS cost_usd \
+                 FROM executions LEFT JOIN \
+                 (SELECT execution_id, SUM(MAX(attempts - 1, 0)) AS retries, SUM(tokens) AS tokens, \
+                 TOTAL(cost_usd) AS cost_usd FROM step_results GROUP BY execution_id) AS usage \
+                 ON usage.execution_id = executions.id \
+                 WHERE status NOT IN (",
+            );
+        let mut unfinished = sql.separated(", ");
+        for status in [ExecutionStatus::Pending, ExecutionStatus::Running, ExecutionStatus::Paused] {
+            unfinished.push_bind(format!("{:?}", status));
+        }
+        sql.push(")");
+        if let Some(pipeline_name) = &query.pipeline {
+            sql.push(" AND pipeline_name = ").push_bind(pipeline_name.clone());
+        }
+        if let Some(since) = query.since {
+            sql.push(" AND started_at >= ").push_bind(Self::to_naive(since));
+        }
+        sql.push(" GROUP BY pipeline_name ORDER BY pipeline_name");
+
+        let rows = sql
+            .build()
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to compute pipeline stats")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let runs = row.get::<i64, _>("runs") as usize;
+                let succeeded = row.get::<i64, _>("succeeded") as usize;
+                PipelineStats {
                     pipeline_name: row.get("pipeline_name"),
-                    status: match row.get::<String, _>("status").as_str() {
-                        "Pending" => crate::core::ExecutionStatus::Pending,
-                        "Running" => crate::core::ExecutionStatus::Running,
-                        "Completed" => crate::core::ExecutionStatus::Completed,
-                        "Failed" => crate::core::ExecutionStatus::Failed,
-                        "Cancelled" => crate::core::ExecutionStatus::Cancelled,
-                        "Paused" => crate::core::ExecutionStatus::Paused,
-                        _ => crate::core::ExecutionStatus::Pending,
-                    },
+                    runs,
+                    succeeded,
+                    success_rate: succeeded as f64 / runs as f64,
+                    avg_duration_secs: row.get("avg_duration_secs"),
+                    avg_retries: row.get("avg_retries"),
+                    tokens: row.get::<i64, _>("tokens") as u64,
+                    cost_usd: row.get("cost_usd"),
+                }
+            })
+            .collect())
+    }
+
+    async fn save_dead_letter(&self, letter: &DeadLetter) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO dead_letters (execution_id, url, payload, error, attempts, failed_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(letter.execution_id.to_string())
+        .bind(&letter.url)
+        .bind(&letter.payload)
+        .bind(&letter.error)
+        .bind(letter.attempts as i64)
+        .bind(Self::to_naive(letter.failed_at))
+        .execute(&self.pool)
+        .await
+        .context("Failed to save dead letter")?;
+
+        Ok(())
+    }
+
+    async fn load_dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT execution_id, url, payload, error, attempts, failed_at
+            FROM dead_letters
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load dead letters")?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(DeadLetter {
+                    execution_id: Uuid::parse_str(&row.get::<String, _>("execution_id"))?,
+                    url: row.get("url"),
+                    payload: row.get("payload"),
+                    error: row.get("error"),
+                    attempts: row.get::<i64, _>("attempts") as u32,
+                    failed_at: Self::from_naive(row.get("failed_at")),
+                })
+            })
+            .collect()
+    }
+
+    async fn save_scheduled_run(&self, run: &ScheduledRun) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO scheduled_runs (schedule, execution_id, due_at, started_at)
+            VALUES (?, ?, ?, ?)
+            "#,
+        )
+        .bind(&run.schedule)
+        .bind(run.execution_id.to_string())
+        .bind(Self::to_naive(run.due_at))
+        .bind(Self::to_naive(run.started_at))
+        .execute(&self.pool)
+        .await
+        .context("Failed to save scheduled run")?;
+
+        Ok(())
+    }
+
+    async fn load_scheduled_runs(&self, schedule: &str, limit: usize) -> Result<Vec<ScheduledRun>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT schedule, execution_id, due_at, started_at
+            FROM scheduled_runs
+            WHERE schedule = ?
+            ORDER BY id DESC
+            LIMIT ?
+            "#,
+        )
+        .bind(schedule)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load scheduled runs")?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(ScheduledRun {
+                    schedule: row.get("schedule"),
+                    execution_id: Uuid::parse_str(&row.get::<String, _>("execution_id"))?,
+                    due_at: Self::from_naive(row.get("due_at")),
                     started_at: Self::from_naive(row.get("started_at")),
-                    completed_at: row.get::<Option<NaiveDateTime>, _>("completed_at").map(Self::from_naive),
-                    progress: row.get("progress"),
-                    completed_steps: row.get::<i64, _>("completed_steps") as usize,
-                    total_steps: row.get::<i64, _>("total_steps") as usize,
                 })
             })
             .collect()
     }
 
-    async fn list_pipelines(&self) -> Result<Vec<String>> {
+    async fn save_artifact(&self, execution_id: Uuid, artifact: &Artifact) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO artifacts (execution_id, path, step_id, source, size, sha256, collected_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (execution_id, path)
+            DO UPDATE SET step_id = excluded.step_id, source = excluded.source, size = excluded.size,
+                sha256 = excluded.sha256, collected_at = excluded.collected_at
+            "#,
+        )
+        .bind(execution_id.to_string())
+        .bind(&artifact.path)
+        .bind(&artifact.step_id)
+        .bind(&artifact.source)
+        .bind(artifact.size as i64)
+        .bind(&artifact.sha256)
+        .bind(Self::to_naive(artifact.collected_at))
+        .execute(&self.pool)
+        .await
+        .context("Failed to save artifact")?;
+
+        Ok(())
+    }
+
+    async fn load_artifacts(&self, execution_id: Uuid) -> Result<Vec<Artifact>> {
         let rows = sqlx::query(
             r#"
-            SELECT DISTINCT pipeline_name
-            FROM executions
-            ORDER BY pipeline_name ASC
+            SELECT path, step_id, source, size, sha256, collected_at
+            FROM artifacts
+            WHERE execution_id = ?
+            ORDER BY rowid
             "#,
         )
+        .bind(execution_id.to_string())
         .fetch_all(&self.pool)
         .await
-        .context("Failed to list pipelines")?;
+        .context("Failed to load artifacts")?;
 
-        Ok(rows.iter().map(|row| row.get("pipeline_name")).collect())
+        Ok(rows
+            .iter()
+            .map(|row| Artifact {
+                step_id: row.get("step_id"),
+                path: row.get("path"),
+                source: row.get("source"),
+                size: row.get::<i64, _>("size") as u64,
+                sha256: row.get("sha256"),
+                collected_at: Self::from_naive(row.get("collected_at")),
+            })
+            .collect())
+    }
+
+    async fn save_tool_decision(&self, execution_id: Uuid, decision: &ToolDecision) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO tool_decisions
+                (execution_id, step_id, tool_name, arguments_sha256, approved, approver, decided_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            "#,
+        )
+        .bind(execution_id.to_string())
+        .bind(&decision.step_id)
+        .bind(&decision.tool_name)
+        .bind(&decision.arguments_sha256)
+        .bind(decision.approved)
+        .bind(&decision.approver)
+        .bind(Self::to_naive(decision.decided_at))
+        .execute(&self.pool)
+        .await
+        .context("Failed to save tool decision")?;
+
+        Ok(())
+    }
+
+    async fn load_tool_decisions(&self, execution_id: Uuid) -> Result<Vec<ToolDecision>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT step_id, tool_name, arguments_sha256, approved, approver, decided_at
+            FROM tool_decisions
+            WHERE execution_id = ?
+            ORDER BY id
+            "#,
+        )
+        .bind(execution_id.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load tool decisions")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| ToolDecision {
+                step_id: row.get("step_id"),
+                tool_name: row.get("tool_name"),
+                arguments_sha256: row.get("arguments_sha256"),
+                approved: row.get("approved"),
+                approver: row.get("approver"),
+                decided_at: Self::from_naive(row.get("decided_at")),
+            })
+            .collect())
+    }
+
+    async fn push_queue_items(&self, queue: &str, items: &[String], pushed_by: Option<Uuid>) -> Result<()> {
+        let pushed_at = Self::to_naive(Utc::now());
+        let mut tx = self.pool.begin().await?;
+        for payload in items {
+            sqlx::query("INSERT INTO queue_items (queue, payload, pushed_by, pushed_at) VALUES (?, ?, ?, ?)")
+                .bind(queue)
+                .bind(payload)
+                .bind(pushed_by.map(|id| id.to_string()))
+                .bind(pushed_at)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to push queue item")?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn pop_queue_items(&self, queue: &str, limit: usize) -> Result<Vec<QueueItem>> {
+        // One statement, so concurrent runs never take the same item
+        let mut rows = sqlx::query(
+            r#"
+            DELETE FROM queue_items
+            WHERE id IN (SELECT id FROM queue_items WHERE queue = ? ORDER BY id LIMIT ?)
+            RETURNING id, queue, payload, pushed_by, pushed_at
+            "#,
+        )
+        .bind(queue)
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to pop queue items")?;
+        rows.sort_by_key(|row| row.get::<i64, _>("id"));
+
+        Ok(rows
+            .iter()
+            .map(|row| QueueItem {
+                queue: row.get("queue"),
+                payload: row.get("payload"),
+                pushed_by: row
+                    .get::<Option<String>, _>("pushed_by")
+                    .and_then(|id| Uuid::parse_str(&id).ok()),
+                pushed_at: Self::from_naive(row.get("pushed_at")),
+            })
+            .collect())
+    }
+
+    async fn list_queues(&self) -> Result<Vec<QueueSummary>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT queue, COUNT(*) AS items, MIN(pushed_at) AS oldest_at
+            FROM queue_items
+            GROUP BY queue
+            ORDER BY queue
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list queues")?;
+
+        Ok(rows
+            .iter()
+            .map(|row| QueueSummary {
+                queue: row.get("queue"),
+                items: row.get::<i64, _>("items") as usize,
+                oldest_at: Self::from_naive(row.get("oldest_at")),
+            })
+            .collect())
     }
 }
 
@@ -210,6 +986,10 @@ mod tests {
             progress: 1.0,
             completed_steps: 3,
             total_steps: 3,
+            bytes_written: 42,
+            outputs: [("pr_url".to_string(), "https://example.com/pr/1".to_string())].into(),
+            seed: Some(1234),
+            parent_execution_id: Some(Uuid::new_v4()),
         };
 
         store.save_execution(&summary).await.unwrap();
@@ -222,5 +1002,650 @@ mod tests {
 
         assert_eq!(loaded.pipeline_name, summary.pipeline_name);
         assert_eq!(loaded.status, summary.status);
+        assert_eq!(loaded.bytes_written, 42);
+        assert_eq!(loaded.outputs, summary.outputs);
+        assert_eq!(loaded.seed, Some(1234));
+        assert_eq!(loaded.parent_execution_id, summary.parent_execution_id);
+    }
+
+    async fn check_history_search(store: &dyn PersistenceBackend) {
+        let run = |pipeline: &str, status, days_ago| ExecutionSummary {
+            execution_id: Uuid::new_v4(),
+            pipeline_name: pipeline.to_string(),
+            status,
+            started_at: Utc::now() - chrono::Duration::days(days_ago),
+            completed_at: None,
+            progress: 0.5,
+            completed_steps: 1,
+            total_steps: 2,
+            bytes_written: 0,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        };
+        let step = |step_id: &str, status: &str, output: Option<&str>, error: Option<&str>| StepResult {
+            step_id: step_id.to_string(),
+            status: status.to_string(),
+            output: output.map(str::to_string),
+            error: error.map(str::to_string),
+            attempts: 1,
+            started_at: None,
+            finished_at: None,
+            tokens: 0,
+            cost_usd: 0.0,
+            input_digest: None,
+        };
+
+        let old = run("Release", ExecutionStatus::Failed, 10);
+        let recent = run("Release", ExecutionStatus::Failed, 1);
+        let docs = run("Docs", ExecutionStatus::Completed, 0);
+        for summary in [&old, &recent, &docs] {
+            store.save_execution(summary).await.unwrap();
+        }
+        let saves = [
+            (&old, step("review", "failed", None, Some("Agent Timeout after 300s"))),
+            (&recent, step("review", "completed", Some("LGTM"), None)),
+            (&recent, step("fix", "running", None, None)),
+            // Updated results are searched by their new text
+            (&recent, step("fix", "failed", None, Some("TIMEOUT again"))),
+            (&docs, step("write", "completed", Some("no problems"), None)),
+        ];
+        for (summary, result) in &saves {
+            store.save_step_result(summary.execution_id, result).await.unwrap();
+        }
+
+        let search = |query: HistoryQuery| async move {
+            store
+                .search_executions(&HistoryQuery { limit: 10, ..query })
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|found| (found.summary.execution_id, found.matched_steps))
+                .collect::<Vec<_>>()
+        };
+        let steps = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+
+        let failed_this_week = HistoryQuery {
+            statuses: vec![ExecutionStatus::Failed],
+            since: Some(Utc::now() - chrono::Duration::days(7)),
+            ..Default::default()
+        };
+        assert_eq!(search(failed_this_week).await, vec![(recent.execution_id, vec![])]);
+
+        let timeouts = HistoryQuery {
+            contains: Some("timeout".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            search(timeouts).await,
+            vec![(recent.execution_id, steps(&["fix"])), (old.execution_id, steps(&["review"]))]
+        );
+
+        // Text too short for the trigram index
+        let short = HistoryQuery {
+            contains: Some("Fi".to_string()),
+            pipeline: Some("Release".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(search(short).await, vec![(recent.execution_id, steps(&["fix"]))]);
+
+        let no_match = HistoryQuery {
+            contains: Some("segfault".to_string()),
+            ..Default::default()
+        };
+        assert!(search(no_match).await.is_empty());
+
+        let latest = store
+            .search_executions(&HistoryQuery { limit: 1, ..Default::default() })
+            .await
+            .unwrap();
+        assert_eq!(latest[0].summary.execution_id, docs.execution_id);
+        assert_eq!(latest.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_history_search() {
+        check_history_search(&SqliteExecutionStore::new(":memory:").await.unwrap()).await;
+        check_history_search(&crate::persistence::InMemoryPersistence::new()).await;
+    }
+
+    async fn check_pipeline_stats(store: &dyn PersistenceBackend) {
+        let now = Utc::now();
+        let run = |pipeline: &str, status, days_ago, minutes| {
+            let started_at = now - chrono::Duration::days(days_ago);
+            ExecutionSummary {
+                execution_id: Uuid::new_v4(),
+                pipeline_name: pipeline.to_string(),
+                status,
+                started_at,
+                completed_at: Some(started_at + chrono::Duration::minutes(minutes)),
+                progress: 1.0,
+                completed_steps: 1,
+                total_steps: 1,
+                bytes_written: 0,
+                outputs: Default::default(),
+                seed: None,
+                parent_execution_id: None,
+            }
+        };
+        let step = |step_id: &str, attempts, tokens, cost_usd| StepResult {
+            step_id: step_id.to_string(),
+            status: "completed".to_string(),
+            output: None,
+            error: None,
+            attempts,
+            started_at: None,
+            finished_at: None,
+            tokens,
+            cost_usd,
+            input_digest: None,
+        };
+
+        let old = run("Release", ExecutionStatus::Completed, 30, 60);
+        let passed = run("Release", ExecutionStatus::Completed, 1, 10);
+        let failed = run("Release", ExecutionStatus::Failed, 2, 20);
+        let running = ExecutionSummary {
+            completed_at: None,
+            ..run("Release", ExecutionStatus::Running, 0, 0)
+        };
+        let docs = run("Docs", ExecutionStatus::Cancelled, 3, 1);
+        for summary in [&old, &passed, &failed, &running, &docs] {
+            store.save_execution(summary).await.unwrap();
+        }
+        let saves = [
+            (&old, step("build", 1, 9000, 9.0)),
+            (&passed, step("build", 1, 1000, 0.25)),
+            (&passed, step("test", 3, 500, 0.5)),
+            (&failed, step("build", 2, 1500, 0.25)),
+            (&running, step("build", 4, 100, 1.0)),
+        ];
+        for (summary, result) in &saves {
+            store.save_step_result(summary.execution_id, result).await.unwrap();
+        }
+
+        let last_week = StatsQuery {
+            since: Some(now - chrono::Duration::days(7)),
+            ..Default::default()
+        };
+        let stats = store.pipeline_stats(&last_week).await.unwrap();
+        let names: Vec<_> = stats.iter().map(|s| s.pipeline_name.as_str()).collect();
+        assert_eq!(names, ["Docs", "Release"]);
+
+        // The running run and the one from a month ago are left out
+        let release = &stats[1];
+        assert_eq!((release.runs, release.succeeded), (2, 1));
+        assert_eq!(release.success_rate, 0.5);
+        assert!((release.avg_duration_secs.unwrap() - 900.0).abs() < 0.01, "{:?}", release.avg_duration_secs);
+        assert_eq!(release.avg_retries, 1.5);
+        assert_eq!((release.tokens, release.cost_usd), (3000, 1.0));
+
+        // A run without step results still counts
+        let docs_stats = &stats[0];
+        assert_eq!((docs_stats.runs, docs_stats.succeeded, docs_stats.success_rate), (1, 0, 0.0));
+        assert_eq!((docs_stats.avg_retries, docs_stats.tokens, docs_stats.cost_usd), (0.0, 0, 0.0));
+
+        let all_release = StatsQuery {
+            pipeline: Some("Release".to_string()),
+            ..Default::default()
+        };
+        let stats = store.pipeline_stats(&all_release).await.unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].runs, stats[0].tokens), (3, 12000));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_stats() {
+        check_pipeline_stats(&SqliteExecutionStore::new(":memory:").await.unwrap()).await;
+        check_pipeline_stats(&crate::persistence::InMemoryPersistence::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_pipeline_memory() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        store.save_memory("p", "last_issue", "12").await.unwrap();
+        store.save_memory("p", "last_issue", "15").await.unwrap();
+        store.save_memory("q", "last_issue", "3").await.unwrap();
+
+        let memory = store.load_memory("p").await.unwrap();
+        assert_eq!(memory.len(), 1);
+        assert_eq!(memory["last_issue"], "15");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_marks_silent_runs_orphaned() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let mut running = ExecutionSummary {
+            execution_id: Uuid::new_v4(),
+            pipeline_name: "p".to_string(),
+            status: ExecutionStatus::Running,
+            started_at: Utc::now(),
+            completed_at: None,
+            progress: 0.0,
+            completed_steps: 0,
+            total_steps: 2,
+            bytes_written: 0,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        };
+        store.save_execution(&running).await.unwrap();
+        running.execution_id = Uuid::new_v4();
+        running.status = ExecutionStatus::Completed;
+        store.save_execution(&running).await.unwrap();
+
+        // Heard from within the window
+        let an_hour_ago = Utc::now() - chrono::Duration::hours(1);
+        assert!(store.mark_orphaned(an_hour_ago).await.unwrap().is_empty());
+
+        let in_a_minute = Utc::now() + chrono::Duration::minutes(1);
+        let orphaned = store.mark_orphaned(in_a_minute).await.unwrap();
+        assert_eq!(orphaned.len(), 1);
+        let loaded = store.load_execution(orphaned[0]).await.unwrap().unwrap();
+        assert_eq!(loaded.status, ExecutionStatus::Orphaned);
+        assert!(loaded.completed_at.is_some());
+        assert!(store.mark_orphaned(in_a_minute).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_counts_higher_priority_runs() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let interactive = Uuid::new_v4();
+        let a_minute_ago = Utc::now() - chrono::Duration::minutes(1);
+
+        store.announce_priority(Uuid::new_v4(), Priority::Low).await.unwrap();
+        store.announce_priority(interactive, Priority::High).await.unwrap();
+        assert_eq!(store.higher_priority_runs(Priority::Low, a_minute_ago).await.unwrap(), 1);
+        assert_eq!(store.higher_priority_runs(Priority::High, a_minute_ago).await.unwrap(), 0);
+
+        // Announcements not refreshed since `seen_after` are ignored
+        let in_a_minute = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(store.higher_priority_runs(Priority::Low, in_a_minute).await.unwrap(), 0);
+
+        store.withdraw_priority(interactive).await.unwrap();
+        assert_eq!(store.higher_priority_runs(Priority::Normal, a_minute_ago).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_large_outputs_are_compressed() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let run = Uuid::new_v4();
+        let summary = ExecutionSummary {
+            execution_id: run,
+            pipeline_name: "p".to_string(),
+            status: ExecutionStatus::Completed,
+            started_at: Utc::now(),
+            completed_at: Some(Utc::now()),
+            progress: 1.0,
+            completed_steps: 1,
+            total_steps: 1,
+            bytes_written: 0,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        };
+        store.save_execution(&summary).await.unwrap();
+        let output: String = (0..5000).map(|i| format!("compiling crate {}\n", i)).collect();
+        let mut build = StepResult {
+            step_id: "build".to_string(),
+            status: "completed".to_string(),
+            output: Some(format!("{}needle in the log\n", output)),
+            error: None,
+            attempts: 1,
+            started_at: None,
+            finished_at: None,
+            tokens: 0,
+            cost_usd: 0.0,
+            input_digest: None,
+        };
+        store.save_step_result(run, &build).await.unwrap();
+
+        let (stored, compressed): (i64, i64) = sqlx::query_as(
+            "SELECT length(CAST(output_zstd AS BLOB)), output IS NULL FROM step_results WHERE step_id = 'build'",
+        )
+        .fetch_one(&store.pool)
+        .await
+        .unwrap();
+        assert!(compressed == 1 && (stored as usize) < output.len() / 10, "{} bytes stored", stored);
+        assert_eq!(store.load_step_results(run).await.unwrap(), vec![build.clone()]);
+
+        // Compressed text is still found by search, and rewriting a result
+        // replaces what is found
+        let search = |text: &str| HistoryQuery {
+            contains: Some(text.to_string()),
+            limit: 10,
+            ..Default::default()
+        };
+        assert_eq!(store.search_executions(&search("needle")).await.unwrap().len(), 1);
+        build.output = Some("short".to_string());
+        store.save_step_result(run, &build).await.unwrap();
+        assert!(store.search_executions(&search("needle")).await.unwrap().is_empty());
+        assert_eq!(store.search_executions(&search("short")).await.unwrap().len(), 1);
+        assert_eq!(store.load_step_results(run).await.unwrap(), vec![build]);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_step_results() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let run = Uuid::new_v4();
+        let started = Utc::now();
+        let mut plan = StepResult {
+            step_id: "plan".to_string(),
+            status: "running".to_string(),
+            output: None,
+            error: None,
+            attempts: 1,
+            started_at: Some(started),
+            finished_at: None,
+            tokens: 0,
+            cost_usd: 0.0,
+            input_digest: None,
+        };
+        let review = StepResult {
+            step_id: "review".to_string(),
+            status: "failed".to_string(),
+            output: None,
+            error: Some("boom".to_string()),
+            attempts: 3,
+            started_at: Some(started),
+            finished_at: Some(started),
+            tokens: 5400,
+            cost_usd: 0.42,
+            input_digest: Some("abc123".to_string()),
+        };
+        store.save_step_result(run, &plan).await.unwrap();
+        store.save_step_result(run, &review).await.unwrap();
+
+        // Saving again updates the step in place
+        plan.status = "completed".to_string();
+        plan.output = Some("the plan\nin two lines".to_string());
+        plan.finished_at = Some(started);
+        store.save_step_result(run, &plan).await.unwrap();
+
+        let results = store.load_step_results(run).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].step_id, "plan");
+        assert_eq!(results[0].output.as_deref(), Some("the plan\nin two lines"));
+        assert_eq!(results[0].status, "completed");
+        assert!(results[0].finished_at.is_some());
+        assert_eq!(results[1].error.as_deref(), Some("boom"));
+        assert_eq!(results[1].attempts, 3);
+        assert_eq!((results[1].tokens, results[1].cost_usd), (5400, 0.42));
+        assert_eq!(results[1].input_digest.as_deref(), Some("abc123"));
+
+        assert!(store.load_step_results(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    async fn check_last_completed_step(store: &dyn PersistenceBackend) {
+        let now = Utc::now();
+        let run = |pipeline: &str| ExecutionSummary {
+            execution_id: Uuid::new_v4(),
+            pipeline_name: pipeline.to_string(),
+            status: ExecutionStatus::Completed,
+            started_at: now,
+            completed_at: None,
+            progress: 1.0,
+            completed_steps: 1,
+            total_steps: 1,
+            bytes_written: 0,
+            outputs: Default::default(),
+            seed: None,
+            parent_execution_id: None,
+        };
+        let build = |status: &str, output: &str, minutes_ago| StepResult {
+            step_id: "build".to_string(),
+            status: status.to_string(),
+            output: Some(output.to_string()),
+            error: None,
+            attempts: 1,
+            started_at: None,
+            finished_at: Some(now - chrono::Duration::minutes(minutes_ago)),
+            tokens: 0,
+            cost_usd: 0.0,
+            input_digest: Some(format!("digest of {}", output)),
+        };
+
+        let (older, newer, failed, other) = (run("Release"), run("Release"), run("Release"), run("Docs"));
+        for (summary, result) in [
+            (&older, build("completed", "v1", 30)),
+            (&newer, build("completed", "v2", 20)),
+            (&failed, build("failed", "v3", 10)),
+            (&other, build("completed", "docs", 0)),
+        ] {
+            store.save_execution(summary).await.unwrap();
+            store.save_step_result(summary.execution_id, &result).await.unwrap();
+        }
+
+        let (execution_id, result) = store.last_completed_step("Release", "build").await.unwrap().unwrap();
+        assert_eq!(execution_id, newer.execution_id);
+        assert_eq!(result.output.as_deref(), Some("v2"));
+        assert_eq!(result.input_digest.as_deref(), Some("digest of v2"));
+        assert!(store.last_completed_step("Release", "test").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_last_completed_step() {
+        check_last_completed_step(&SqliteExecutionStore::new(":memory:").await.unwrap()).await;
+        check_last_completed_step(&crate::persistence::InMemoryPersistence::new()).await;
+    }
+
+    async fn check_scheduled_runs(store: &dyn PersistenceBackend) {
+        let started_at = Utc::now();
+        for (schedule, minute) in [("nightly", 0), ("hourly", 0), ("nightly", 1), ("nightly", 2)] {
+            let due_at = started_at - chrono::Duration::minutes(10 - minute);
+            store
+                .save_scheduled_run(&ScheduledRun {
+                    schedule: schedule.to_string(),
+                    execution_id: Uuid::new_v4(),
+                    due_at,
+                    started_at,
+                })
+                .await
+                .unwrap();
+        }
+
+        let runs = store.load_scheduled_runs("nightly", 2).await.unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(runs[0].due_at > runs[1].due_at, "newest first");
+        assert_eq!(store.load_scheduled_runs("hourly", 10).await.unwrap().len(), 1);
+        assert!(store.load_scheduled_runs("weekly", 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_runs() {
+        check_scheduled_runs(&SqliteExecutionStore::new(":memory:").await.unwrap()).await;
+        check_scheduled_runs(&crate::persistence::InMemoryPersistence::new()).await;
+    }
+
+    async fn check_tool_decisions(store: &dyn PersistenceBackend) {
+        let run = Uuid::new_v4();
+        let decision = |tool_name: &str, approved: bool| ToolDecision {
+            step_id: "build".to_string(),
+            tool_name: tool_name.to_string(),
+            arguments_sha256: "ab".repeat(32),
+            approved,
+            approver: "terminal:alice".to_string(),
+            decided_at: Utc::now(),
+        };
+        let decisions = [decision("bash", true), decision("write", false)];
+        for decision in &decisions {
+            store.save_tool_decision(run, decision).await.unwrap();
+        }
+
+        assert_eq!(store.load_tool_decisions(run).await.unwrap(), decisions);
+        assert!(store.load_tool_decisions(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tool_decisions() {
+        check_tool_decisions(&SqliteExecutionStore::new(":memory:").await.unwrap()).await;
+        check_tool_decisions(&crate::persistence::InMemoryPersistence::new()).await;
+    }
+
+    async fn check_queues(store: &dyn PersistenceBackend) {
+        let producer = Uuid::new_v4();
+        let files: Vec<String> = ["a.rs", "b.rs", "c.rs"].iter().map(|f| f.to_string()).collect();
+        store.push_queue_items("migrate", &files, Some(producer)).await.unwrap();
+        store.push_queue_items("review", &["pr-7".to_string()], None).await.unwrap();
+
+        let queues = store.list_queues().await.unwrap();
+        let lengths: Vec<_> = queues.iter().map(|q| (q.queue.as_str(), q.items)).collect();
+        assert_eq!(lengths, vec![("migrate", 3), ("review", 1)]);
+
+        let batch = store.pop_queue_items("migrate", 2).await.unwrap();
+        let payloads: Vec<_> = batch.iter().map(|item| item.payload.as_str()).collect();
+        assert_eq!(payloads, vec!["a.rs", "b.rs"], "oldest first");
+        assert_eq!(batch[0].pushed_by, Some(producer));
+
+        let rest = store.pop_queue_items("migrate", 10).await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert!(store.pop_queue_items("migrate", 10).await.unwrap().is_empty());
+        assert_eq!(store.pop_queue_items("review", 1).await.unwrap()[0].pushed_by, None);
+        assert!(store.list_queues().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queues() {
+        check_queues(&SqliteExecutionStore::new(":memory:").await.unwrap()).await;
+        check_queues(&crate::persistence::InMemoryPersistence::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_artifacts_overwrite_by_path() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let run = Uuid::new_v4();
+        let artifact = |path: &str, size: u64| Artifact {
+            step_id: "build".to_string(),
+            path: path.to_string(),
+            source: format!("/repo/{}", path),
+            size,
+            sha256: "ab".repeat(32),
+            collected_at: Utc::now(),
+        };
+        for (path, size) in [("build/app", 10), ("build/app.sig", 1), ("build/app", 12)] {
+            store.save_artifact(run, &artifact(path, size)).await.unwrap();
+        }
+
+        let artifacts = store.load_artifacts(run).await.unwrap();
+        let saved: Vec<_> = artifacts.iter().map(|a| (a.path.as_str(), a.size)).collect();
+        assert_eq!(saved, vec![("build/app", 12), ("build/app.sig", 1)]);
+        assert!(store.load_artifacts(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_dead_letters_newest_first() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let run = Uuid::new_v4();
+        for (url, error) in [("https://a.example", "HTTP 503"), ("https://b.example", "HTTP 404")] {
+            let letter = DeadLetter {
+                execution_id: run,
+                url: url.to_string(),
+                payload: "{}".to_string(),
+                error: error.to_string(),
+                attempts: 4,
+                failed_at: Utc::now(),
+            };
+            store.save_dead_letter(&letter).await.unwrap();
+        }
+
+        let letters = store.load_dead_letters(10).await.unwrap();
+        let urls: Vec<_> = letters.iter().map(|l| l.url.as_str()).collect();
+        assert_eq!(urls, vec!["https://b.example", "https://a.example"]);
+        assert_eq!(letters[1].execution_id, run);
+        assert_eq!(letters[1].attempts, 4);
+        assert_eq!(store.load_dead_letters(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_concurrency_group() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+
+        assert!(store.try_join_group("pr-1", first).await.unwrap());
+        assert!(!store.try_join_group("pr-1", second).await.unwrap());
+
+        assert_eq!(store.take_over_group("pr-1", second).await.unwrap(), Some(first));
+        assert_eq!(store.group_holder("pr-1").await.unwrap(), Some(second));
+
+        store.leave_group("pr-1", first).await.unwrap();
+        assert_eq!(store.group_holder("pr-1").await.unwrap(), Some(second));
+        store.leave_group("pr-1", second).await.unwrap();
+        assert_eq!(store.group_holder("pr-1").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_pipeline_lock() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+        let a_minute_ago = Utc::now() - chrono::Duration::minutes(1);
+
+        assert!(store.try_acquire_lock("p", first, a_minute_ago).await.unwrap());
+        assert!(!store.try_acquire_lock("p", second, a_minute_ago).await.unwrap());
+
+        store.release_lock("p", first).await.unwrap();
+        assert!(store.try_acquire_lock("p", second, a_minute_ago).await.unwrap());
+
+        // A holder that stopped checking in loses the lock
+        let in_a_minute = Utc::now() + chrono::Duration::minutes(1);
+        assert!(store.try_acquire_lock("p", first, in_a_minute).await.unwrap());
+        assert!(!store.try_acquire_lock("p", second, a_minute_ago).await.unwrap());
+
+        assert!(store.clear_lock("p").await.unwrap());
+        assert!(!store.clear_lock("p").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_lock_waiting_line() {
+        let store = SqliteExecutionStore::new(":memory:").await.unwrap();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let a_minute_ago = Utc::now() - chrono::Duration::minutes(1);
+
+        store.wait_for_lock("p", first).await.unwrap();
+        store.wait_for_lock("p", second).await.unwrap();
+        // Checking in again keeps a waiter's place
+        store.wait_for_lock("p", first).await.unwrap();
+        assert_eq!(store.next_lock_waiter("p", a_minute_ago).await.unwrap(), Some(first));
+        assert_eq!(store.next_lock_waiter("other", a_minute_ago).await.unwrap(), None);
+
+        store.stop_waiting_for_lock("p", first).await.unwrap();
+        assert_eq!(store.next_lock_waiter("p", a_minute_ago).await.unwrap(), Some(second));
+
+        // Waiters that stopped checking in are dropped
+        let in_a_minute = Utc::now() + chrono::Duration::minutes(1);
+        assert_eq!(store.next_lock_waiter("p", in_a_minute).await.unwrap(), None);
+        assert_eq!(store.next_lock_waiter("p", a_minute_ago).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_from_v1_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("executions.db");
+
+        // Build the v1 fixture database
+        let options = sqlx::sqlite::SqliteConnectOptions::new()
+            .filename(&db_path)
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await.unwrap();
+        sqlx::query(include_str!("../../tests/fixtures/executions_v1.sql"))
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool.close().await;
+
+        let store = SqliteExecutionStore::new(db_path.to_str().unwrap()).await.unwrap();
+        assert_eq!(
+            migrations::current_version(&store.pool).await.unwrap(),
+            migrations::latest_version()
+        );
+
+        // Old rows survive and get defaults for new columns
+        let executions = store.list_executions("legacy-pipeline").await.unwrap();
+        assert_eq!(executions.len(), 1);
+        assert_eq!(executions[0].status, ExecutionStatus::Completed);
+        assert_eq!(executions[0].bytes_written, 0);
+
+        // Tables added by later migrations are usable
+        assert!(store.try_acquire_lock("legacy-pipeline", Uuid::new_v4(), Utc::now()).await.unwrap());
     }
 }
@@ -0,0 +1,21 @@
+-- Execution history database as created by the first release (schema v1,
+-- before versioned migrations). Used to test upgrades.
+CREATE TABLE IF NOT EXISTS executions (
+    id TEXT PRIMARY KEY,
+    pipeline_name TEXT NOT NULL,
+    status TEXT NOT NULL,
+    started_at TEXT NOT NULL,
+    completed_at TEXT,
+    progress REAL NOT NULL DEFAULT 0.0,
+    completed_steps INTEGER NOT NULL DEFAULT 0,
+    total_steps INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_pipeline_name ON executions(pipeline_name);
+CREATE INDEX IF NOT EXISTS idx_status ON executions(status);
+CREATE INDEX IF NOT EXISTS idx_started_at ON executions(started_at);
+
+INSERT INTO executions (id, pipeline_name, status, started_at, completed_at, progress, completed_steps, total_steps)
+VALUES ('7f9c2ba4-e88f-4a3f-9b0c-1d2e3f405162', 'legacy-pipeline', 'Completed',
+        '2024-01-15 10:00:00', '2024-01-15 10:05:00', 1.0, 2, 2);
@@ -8,7 +8,7 @@ use pipeline::core::{
 use pipeline::execution::{ExecutionEngine, SchedulingStrategy};
 use pipeline::agent::{AgentExecutor, AgentError, AgentResponse, ProgressCallback, PiJsonEvent};
 use pipeline::agent::pi_events::AssistantMessageEvent;
-use pipeline::core::step::ConditionPattern;
+use pipeline::core::condition::Matcher;
 
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
@@ -116,7 +116,7 @@ pub async fn run_pipeline_with_agent<A: AgentExecutor + Send + Sync + 'static>(
     agent: A,
 ) -> Result<PipelineTestResult, String> {
     let start = std::time::Instant::now();
-    let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential, false);
+    let engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
     engine.execute(pipeline).await?;
     let duration = start.elapsed();
 
@@ -172,10 +172,10 @@ impl PipelineTestResult {
     /// Count how many times a step was executed (based on attempt count)
     pub fn count_step_attempts(&self, step_id: &str) -> usize {
         self.pipeline.step(step_id)
-            .and_then(|s| match &s.state {
-                StepState::Completed { attempts, .. } => Some(*attempts),
-                StepState::Failed { attempts, .. } => Some(*attempts),
-                _ => Some(0),
+            .map(|s| match &s.state {
+                StepState::Completed { attempts, .. } => *attempts,
+                StepState::Failed { attempts, .. } => *attempts,
+                _ => 0,
             })
             .unwrap_or(0)
     }
@@ -328,7 +328,7 @@ pub fn count_step_executions(result: &PipelineTestResult, step_id: &str) -> usiz
 /// Create a continuation condition for retry
 pub fn retry_continuation(pattern: &str) -> pipeline::core::step::ContinuationCondition {
     pipeline::core::step::ContinuationCondition {
-        pattern: ConditionPattern::Simple(pattern.to_string()),
+        pattern: Matcher::Contains(pattern.to_string()),
         action: pipeline::core::config::ContinuationAction::Retry,
         target: None,
     }
@@ -337,7 +337,7 @@ pub fn retry_continuation(pattern: &str) -> pipeline::core::step::ContinuationCo
 /// Create a continuation condition for routing
 pub fn route_continuation(pattern: &str, target: &str) -> pipeline::core::step::ContinuationCondition {
     pipeline::core::step::ContinuationCondition {
-        pattern: ConditionPattern::Simple(pattern.to_string()),
+        pattern: Matcher::Contains(pattern.to_string()),
         action: pipeline::core::config::ContinuationAction::Route,
         target: Some(target.to_string()),
     }
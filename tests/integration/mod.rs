@@ -21,7 +21,7 @@ async fn run_pipeline_with_real_pi(yaml: &str) -> Result<IntegrationTestResult,
     let pi_client = PiAgentClient::new(pipeline::agent::AgentClientConfig::default());
 
     let start = std::time::Instant::now();
-    let engine = ExecutionEngine::new(pi_client, SchedulingStrategy::Sequential, false);
+    let engine = ExecutionEngine::new(pi_client, SchedulingStrategy::Sequential);
     engine.execute(&mut pipeline).await?;
     let duration = start.elapsed();
 
@@ -153,7 +153,7 @@ steps:
     let pi_client = PiAgentClient::new(pipeline::agent::AgentClientConfig::default());
 
     let start = std::time::Instant::now();
-    let engine = ExecutionEngine::new(pi_client, SchedulingStrategy::Sequential, false);
+    let engine = ExecutionEngine::new(pi_client, SchedulingStrategy::Sequential);
     let result = tokio::time::timeout(Duration::from_secs(60), engine.execute(&mut pipeline)).await;
     let elapsed = start.elapsed();
 
@@ -193,7 +193,7 @@ steps:
     let mut pipeline = config.to_pipeline();
 
     let pi_client = PiAgentClient::new(pipeline::agent::AgentClientConfig::default());
-    let engine = ExecutionEngine::new(pi_client, SchedulingStrategy::Sequential, false);
+    let engine = ExecutionEngine::new(pi_client, SchedulingStrategy::Sequential);
 
     let _ = engine.execute(&mut pipeline).await;
 
@@ -282,7 +282,7 @@ steps:
     // All steps should have output
     for step_id in ["design", "implement", "review"] {
         let output = result.get_step_output(step_id)
-            .expect(&format!("Step '{}' should have output", step_id));
+            .unwrap_or_else(|| panic!("Step '{}' should have output", step_id));
         assert!(!output.is_empty(), "Step '{}' output should not be empty", step_id);
         println!("{} output: {} chars", step_id, output.len());
     }
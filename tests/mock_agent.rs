@@ -58,6 +58,7 @@ impl MockAgent {
     pub fn mock_toolcall_start_event(tool_name: &str, args: serde_json::Value) -> PiJsonEvent {
         let partial = Message {
             role: "assistant".to_string(),
+            usage: None,
             content: vec![json!({
                 "type": "toolCall",
                 "id": "call_123",
@@ -86,6 +87,7 @@ impl MockAgent {
 
         let partial = Message {
             role: "assistant".to_string(),
+            usage: None,
             content: vec![json!({
                 "type": "toolCall",
                 "id": "call_123",
@@ -504,7 +506,7 @@ mod tests {
         match event {
             PiJsonEvent::ToolExecutionEnd { tool_name, is_error, .. } => {
                 assert_eq!(tool_name, "bash");
-                assert_eq!(is_error, false);
+                assert!(!is_error);
             }
             _ => panic!("Expected ToolExecutionEnd"),
         }
@@ -4,9 +4,12 @@
 //! with the execution engine.
 
 use pipeline::agent::{AgentExecutor, AgentResponse, AgentError, ProgressCallback, PiJsonEvent};
-use pipeline::agent::pi_events::{AssistantMessageEvent, Message};
+use pipeline::agent::pi_events::AssistantMessageEvent;
+#[cfg(feature = "cli")]
+use pipeline::agent::pi_events::Message;
 use pipeline::core::config::PipelineConfig;
-use pipeline::execution::{ExecutionEngine, SchedulingStrategy};
+use pipeline::execution::{EngineConfig, ExecutionEngine, SchedulingStrategy};
+#[cfg(feature = "cli")]
 use serde_json::json;
 
 // Mock agent that generates test events
@@ -103,6 +106,18 @@ impl AgentExecutor for TestAgent {
     }
 }
 
+#[tokio::test]
+async fn test_test_agent_streams_events_to_callback() {
+    let agent = TestAgent::new(vec!["DONE".to_string()]);
+    let callback = TestCallback::new();
+
+    agent.execute_streaming("Do task 1", Some(&callback)).await.unwrap();
+
+    let events = callback.get_events();
+    assert!(matches!(events.first(), Some(PiJsonEvent::AgentStart)));
+    assert!(matches!(events.last(), Some(PiJsonEvent::AgentEnd)));
+}
+
 #[tokio::test]
 async fn test_streaming_with_engine_uses_callback() {
     let yaml = r#"
@@ -119,7 +134,7 @@ steps:
     let mut pipeline = config.to_pipeline();
 
     let agent = TestAgent::new(vec!["DONE".to_string()]);
-    let engine = ExecutionEngine::new(agent.clone(), SchedulingStrategy::Sequential, false);
+    let engine = ExecutionEngine::new(agent.clone(), SchedulingStrategy::Sequential);
 
     let result = engine.execute(&mut pipeline).await;
 
@@ -131,24 +146,26 @@ steps:
 #[tokio::test]
 async fn test_streaming_with_show_thinking_true() {
     let agent = TestAgent::new(vec!["DONE".to_string()]);
-    let _engine = ExecutionEngine::new(agent.clone(), SchedulingStrategy::Sequential, true);
+    let _engine = ExecutionEngine::with_config(
+        agent.clone(),
+        EngineConfig::default().with_show_thinking(true),
+    );
 
     // The show_thinking flag is stored in the engine
     // The callback is created internally during step execution
-    assert!(true);
 }
 
 #[tokio::test]
 async fn test_streaming_with_show_thinking_false() {
     let agent = TestAgent::new(vec!["DONE".to_string()]);
-    let _engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential, false);
+    let _engine = ExecutionEngine::new(agent, SchedulingStrategy::Sequential);
 
-    // Verify engine created successfully with show_thinking=false
-    assert!(true);
+    // Creating the engine with show_thinking=false must not panic
 }
 
 // Phase 3: Tool call display integration tests
 
+#[cfg(feature = "cli")]
 #[tokio::test]
 async fn test_tool_call_display() {
     use pipeline::cli::terminal_output::TerminalOutputCallback;
@@ -160,6 +177,7 @@ async fn test_tool_call_display() {
             content_index: 1,
             partial: Message {
                 role: "assistant".to_string(),
+                usage: None,
                 content: vec![json!({
                     "type": "toolCall",
                     "id": "call_123",
@@ -183,6 +201,7 @@ async fn test_tool_call_display() {
     callback.on_event(&tool_execution_end_event);
 }
 
+#[cfg(feature = "cli")]
 #[tokio::test]
 async fn test_error_tool_call_display() {
     use pipeline::cli::terminal_output::TerminalOutputCallback;
@@ -194,6 +213,7 @@ async fn test_error_tool_call_display() {
             content_index: 1,
             partial: Message {
                 role: "assistant".to_string(),
+                usage: None,
                 content: vec![json!({
                     "type": "toolCall",
                     "id": "call_456",
@@ -217,6 +237,7 @@ async fn test_error_tool_call_display() {
     callback.on_event(&tool_execution_end_event);
 }
 
+#[cfg(feature = "cli")]
 #[tokio::test]
 async fn test_multiple_tool_calls() {
     use pipeline::cli::terminal_output::TerminalOutputCallback;
@@ -228,6 +249,7 @@ async fn test_multiple_tool_calls() {
             content_index: 1,
             partial: Message {
                 role: "assistant".to_string(),
+                usage: None,
                 content: vec![json!({
                     "type": "toolCall",
                     "id": "call_1",
@@ -244,6 +266,7 @@ async fn test_multiple_tool_calls() {
             content_index: 1,
             partial: Message {
                 role: "assistant".to_string(),
+                usage: None,
                 content: vec![json!({
                     "type": "toolCall",
                     "id": "call_2",
@@ -260,6 +283,7 @@ async fn test_multiple_tool_calls() {
             content_index: 1,
             partial: Message {
                 role: "assistant".to_string(),
+                usage: None,
                 content: vec![json!({
                     "type": "toolCall",
                     "id": "call_3",